@@ -0,0 +1,56 @@
+// Check a database for breached, expired and overlong entries.
+//
+// cargo run --example audit -- <database> <password> <pwned-passwords-sha1.txt>
+
+extern crate chrono;
+extern crate keepass;
+
+use std::env;
+use std::process;
+
+use chrono::Local;
+
+use keepass::kpdb::breach::{BreachHash, BreachList};
+use keepass::kpdb::v1kpdb::V1Kpdb;
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    if args.len() != 4 {
+        println!("Usage: {} <database> <password> <breach list>", args[0]);
+        process::exit(2);
+    }
+
+    let mut db = V1Kpdb::new(args[1].clone(), Some(args[2].clone()), None).unwrap();
+    if let Err(e) = db.load_detailed() {
+        println!("Couldn't open {}: {}", args[1], e);
+        process::exit(1);
+    }
+    let mut list = match BreachList::open(&args[3], BreachHash::Sha1) {
+        Ok(list) => list,
+        Err(e) => {
+            println!("Couldn't open {}: {}", args[3], e);
+            process::exit(1);
+        }
+    };
+
+    match db.breached_entries(&mut list) {
+        Ok(breached) => {
+            for (entry, count) in breached {
+                println!("breached: {} (seen {} times)", entry.borrow().title, count);
+            }
+        }
+        Err(e) => println!("Couldn't check passwords: {}", e),
+    }
+
+    let now = Local::now();
+    for entry in db.iter_entries() {
+        let entry = entry.borrow();
+        if entry.expire < now {
+            println!("expired:  {}", entry.title);
+        }
+    }
+
+    if let Err(e) = db.check_field_limits() {
+        println!("Some fields can't be saved: {}", e);
+    }
+}
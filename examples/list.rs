@@ -0,0 +1,42 @@
+// Print the group tree and the entries of a database.
+//
+// cargo run --example list -- test/test_password.kdb test
+
+extern crate keepass;
+
+use std::env;
+use std::process;
+
+use keepass::kpdb::v1kpdb::V1Kpdb;
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    if args.len() != 3 {
+        println!("Usage: {} <database> <password>", args[0]);
+        process::exit(2);
+    }
+
+    let mut db = match V1Kpdb::new(args[1].clone(), Some(args[2].clone()), None) {
+        Ok(db) => db,
+        Err(e) => {
+            println!("{}", e);
+            process::exit(1);
+        }
+    };
+    if let Err(e) = db.load_detailed() {
+        println!("Couldn't open {}: {}", args[1], e);
+        process::exit(1);
+    }
+
+    for group in db.iter_groups() {
+        let group = group.borrow();
+        let indent = "  ".repeat(group.level as usize);
+        println!("{}{}/", indent, group.title);
+        for entry in group.entries.iter().filter_map(|e| e.upgrade()) {
+            let mut entry = entry.borrow_mut();
+            let title = entry.title.clone();
+            let username = entry.username().map(|u| u.to_string()).unwrap_or_default();
+            println!("{}  {} ({})", indent, title, username);
+        }
+    }
+}
@@ -0,0 +1,102 @@
+/*
+ * C API of the keepass crate, see src/ffi.rs for the conventions.
+ *
+ * Build the library with the ffi feature:
+ *
+ *     cargo rustc --release --features ffi -- --crate-type staticlib
+ *
+ * Functions returning int give KP_OK or an error code. Positive codes
+ * are the V1KpdbError variants in declaration order starting at 1,
+ * kp_last_error describes them. Strings are NUL-terminated UTF-8.
+ */
+
+#ifndef KEEPASS_H
+#define KEEPASS_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+#define KP_OK 0
+/* A required pointer was null or an argument is invalid */
+#define KP_ERR_ARG (-1)
+/* A string wasn't valid UTF-8 or contained a NUL byte */
+#define KP_ERR_UTF8 (-2)
+/* The library panicked, the database should be freed */
+#define KP_ERR_PANIC (-3)
+
+/* Fields for kp_entry_get */
+#define KP_FIELD_TITLE 0
+#define KP_FIELD_USERNAME 1
+#define KP_FIELD_PASSWORD 2
+#define KP_FIELD_URL 3
+#define KP_FIELD_NOTES 4
+#define KP_FIELD_UUID 5
+
+/* An open database, only to be used on the thread which opened it */
+typedef struct KpDb kp_db;
+/* An entry of a kp_db, stays valid when the entry is removed */
+typedef struct KpEntry kp_entry;
+/* Entries found by kp_db_search */
+typedef struct KpEntryList kp_entry_list;
+
+/* Open and load the database at path. password and keyfile may be NULL,
+ * but not both. The caller should zero out its copy of password. */
+int kp_db_open(const char *path,
+               const char *password,
+               const char *keyfile,
+               kp_db **out);
+
+/* Save the database to path, or where it was loaded from if path is
+ * NULL */
+int kp_db_save(kp_db *db, const char *path);
+
+/* Close the database. Its secrets are zeroed out. */
+void kp_db_free(kp_db *db);
+
+/* Find the entries whose title, username, URL or notes contain text,
+ * case ignored, except for the Backup group */
+int kp_db_search(const kp_db *db, const char *text, kp_entry_list **out);
+
+/* Create an entry in the group with id group_id. All strings but title
+ * may be NULL. out may be NULL if the entry isn't needed. */
+int kp_db_create_entry(kp_db *db,
+                       uint32_t group_id,
+                       const char *title,
+                       const char *username,
+                       const char *password,
+                       const char *url,
+                       const char *notes,
+                       kp_entry **out);
+
+/* Number of entries of list */
+size_t kp_entry_list_len(const kp_entry_list *list);
+
+/* The index-th entry of list, NULL if it's out of range. It's owned by
+ * the list. */
+const kp_entry *kp_entry_list_get(const kp_entry_list *list, size_t index);
+
+void kp_entry_list_free(kp_entry_list *list);
+
+/* Copy field, one of the KP_FIELD_ constants, of entry into a new
+ * string, to be freed with kp_string_free. *out is NULL if the field
+ * isn't set. */
+int kp_entry_get(const kp_db *db, const kp_entry *entry, int field, char **out);
+
+void kp_entry_free(kp_entry *entry);
+
+/* Zero out and free a string returned by the library */
+void kp_string_free(char *s);
+
+/* Description of the last error on the calling thread. It's valid until
+ * the next call on this thread and must not be freed. */
+const char *kp_last_error(void);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
@@ -0,0 +1,585 @@
+//! C API to embed the crate in applications written in other languages
+//!
+//! include/keepass.h declares everything here, test_header checks that
+//! its prototypes match. Build the library with
+//! `cargo rustc --release --features ffi -- --crate-type staticlib` (or
+//! cdylib) and link it together with OpenSSL.
+//!
+//! * Functions which can fail return KP_OK (0) or an error code. Positive
+//!   codes are the V1KpdbError variants in declaration order starting at
+//!   1, new variants are only ever appended. kp_last_error describes the
+//!   last error of the calling thread.
+//! * Strings are NUL-terminated UTF-8. Strings returned by the library
+//!   are owned by the caller and freed with kp_string_free, which zeroes
+//!   them out first.
+//! * kp_db is an open database, kp_entry refers to one of its entries
+//!   without holding it, see EntryHandle, and kp_entry_list is a list of
+//!   such references. Each has a free function and null pointers may be
+//!   passed to it.
+//! * A kp_db and its entries must only be used on the thread which
+//!   opened it.
+//! * Panics don't cross the API, they're reported as KP_ERR_PANIC.
+
+use libc::{c_char, c_int, size_t};
+use std::cell::RefCell;
+use std::ffi::{CStr, CString};
+use std::panic::{self, AssertUnwindSafe};
+use std::ptr;
+
+use kpdb::handle::EntryHandle;
+use kpdb::search::SearchQuery;
+use kpdb::v1entry::V1Entry;
+use kpdb::v1error::V1KpdbError;
+use kpdb::v1kpdb::V1Kpdb;
+use mem_protect;
+
+pub const KP_OK: c_int = 0;
+/// A required pointer was null or an argument is invalid
+pub const KP_ERR_ARG: c_int = -1;
+/// A string wasn't valid UTF-8 or contained a NUL byte
+pub const KP_ERR_UTF8: c_int = -2;
+/// The library panicked, the database should be freed
+pub const KP_ERR_PANIC: c_int = -3;
+
+/// Fields for kp_entry_get
+pub const KP_FIELD_TITLE: c_int = 0;
+pub const KP_FIELD_USERNAME: c_int = 1;
+pub const KP_FIELD_PASSWORD: c_int = 2;
+pub const KP_FIELD_URL: c_int = 3;
+pub const KP_FIELD_NOTES: c_int = 4;
+pub const KP_FIELD_UUID: c_int = 5;
+
+thread_local!(static LAST_ERROR: RefCell<CString> = RefCell::new(CString::new("").unwrap()));
+
+/// An open database
+pub struct KpDb {
+    db: V1Kpdb,
+}
+
+/// An entry of a KpDb
+pub struct KpEntry {
+    handle: EntryHandle,
+}
+
+/// Entries found by kp_db_search
+pub struct KpEntryList {
+    entries: Vec<KpEntry>,
+}
+
+// The code and description of an error, see the KP_ERR_ constants
+struct FfiError(c_int, String);
+
+impl From<V1KpdbError> for FfiError {
+    fn from(e: V1KpdbError) -> FfiError {
+        FfiError(e as c_int + 1, e.to_string())
+    }
+}
+
+fn arg_error(message: &str) -> FfiError {
+    FfiError(KP_ERR_ARG, message.to_string())
+}
+
+// Run f, catch panics and turn its result into a code for C
+fn call<F>(f: F) -> c_int
+    where F: FnOnce() -> Result<(), FfiError>
+{
+    let result = match panic::catch_unwind(AssertUnwindSafe(f)) {
+        Ok(result) => result,
+        Err(_) => Err(FfiError(KP_ERR_PANIC, "The library panicked".to_string())),
+    };
+    match result {
+        Ok(()) => KP_OK,
+        Err(FfiError(code, message)) => {
+            let message = CString::new(message).unwrap_or(CString::new("").unwrap());
+            LAST_ERROR.with(|e| *e.borrow_mut() = message);
+            code
+        }
+    }
+}
+
+// The string at s, None if s is null
+unsafe fn opt_string(s: *const c_char) -> Result<Option<String>, FfiError> {
+    if s.is_null() {
+        return Ok(None);
+    }
+    match CStr::from_ptr(s).to_str() {
+        Ok(s) => Ok(Some(s.to_string())),
+        Err(_) => Err(FfiError(KP_ERR_UTF8, "String isn't valid UTF-8".to_string())),
+    }
+}
+
+unsafe fn string(s: *const c_char) -> Result<String, FfiError> {
+    match try!(opt_string(s)) {
+        Some(s) => Ok(s),
+        None => Err(arg_error("Required pointer is null")),
+    }
+}
+
+unsafe fn reference<'a, T>(p: *const T) -> Result<&'a T, FfiError> {
+    if p.is_null() {
+        return Err(arg_error("Required pointer is null"));
+    }
+    Ok(&*p)
+}
+
+unsafe fn reference_mut<'a, T>(p: *mut T) -> Result<&'a mut T, FfiError> {
+    if p.is_null() {
+        return Err(arg_error("Required pointer is null"));
+    }
+    Ok(&mut *p)
+}
+
+// Hand s to C. s is zeroed out, so the plain text only exists in the
+// returned buffer.
+fn into_c_string(s: String) -> Result<*mut c_char, FfiError> {
+    // Room for the NUL, so CString doesn't move the bytes
+    let mut bytes = Vec::with_capacity(s.len() + 1);
+    bytes.extend_from_slice(s.as_bytes());
+    unsafe {
+        mem_protect::zero(s.as_bytes());
+    }
+    match CString::new(bytes) {
+        Ok(s) => Ok(s.into_raw()),
+        Err(e) => {
+            let bytes = e.into_vec();
+            unsafe {
+                mem_protect::zero(&bytes);
+            }
+            Err(FfiError(KP_ERR_UTF8, "String contains a NUL byte".to_string()))
+        }
+    }
+}
+
+/// Open and load the database at path. password and keyfile may be
+/// null, but not both. The caller should zero out its copy of password.
+///
+/// # Safety
+///
+/// path, and password and keyfile unless they're null, must point to
+/// NUL-terminated strings which stay valid for the call. out must point
+/// to writable memory for a pointer. The kp_db stored there belongs to
+/// the calling thread and must be freed with kp_db_free.
+#[no_mangle]
+pub unsafe extern "C" fn kp_db_open(path: *const c_char,
+                                    password: *const c_char,
+                                    keyfile: *const c_char,
+                                    out: *mut *mut KpDb)
+                                    -> c_int {
+    call(|| {
+        let out = try!(reference_mut(out));
+        *out = ptr::null_mut();
+        let mut db = try!(V1Kpdb::new(try!(string(path)),
+                                      try!(opt_string(password)),
+                                      try!(opt_string(keyfile))));
+        try!(db.load());
+        *out = Box::into_raw(Box::new(KpDb { db: db }));
+        Ok(())
+    })
+}
+
+/// Save the database to path, or where it was loaded from if path is
+/// null
+///
+/// # Safety
+///
+/// db must be a kp_db from kp_db_open which wasn't freed yet, used on
+/// the thread which opened it and by no other call meanwhile. path must
+/// be null or point to a NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn kp_db_save(db: *mut KpDb, path: *const c_char) -> c_int {
+    call(|| {
+        let db = try!(reference_mut(db));
+        let path = try!(opt_string(path));
+        try!(db.db.save(path, None, None));
+        Ok(())
+    })
+}
+
+/// Close the database. Its secrets are zeroed out.
+///
+/// # Safety
+///
+/// db must be null or a kp_db from kp_db_open, freed on the thread which
+/// opened it and only once. Neither db nor the kp_entry and
+/// kp_entry_list of it may be used afterwards, except to free them.
+#[no_mangle]
+pub unsafe extern "C" fn kp_db_free(db: *mut KpDb) {
+    if !db.is_null() {
+        let _ = panic::catch_unwind(AssertUnwindSafe(|| drop(Box::from_raw(db))));
+    }
+}
+
+/// Find the entries whose title, username, URL or notes contain text,
+/// case ignored, except for the Backup group, see SearchQuery::new
+///
+/// # Safety
+///
+/// db must be a live kp_db used on the thread which opened it, text a
+/// NUL-terminated string and out writable memory for a pointer. The
+/// list stored there must be freed with kp_entry_list_free.
+#[no_mangle]
+pub unsafe extern "C" fn kp_db_search(db: *const KpDb,
+                                      text: *const c_char,
+                                      out: *mut *mut KpEntryList)
+                                      -> c_int {
+    call(|| {
+        let out = try!(reference_mut(out));
+        *out = ptr::null_mut();
+        let db = try!(reference(db));
+        let query = SearchQuery::new(try!(string(text)));
+        let handles = try!(db.db.search_handles(&query));
+        let entries = handles.into_iter().map(|h| KpEntry { handle: h }).collect();
+        *out = Box::into_raw(Box::new(KpEntryList { entries: entries }));
+        Ok(())
+    })
+}
+
+/// Create an entry in the group with id group_id. All strings but title
+/// may be null. out may be null if the entry isn't needed.
+///
+/// # Safety
+///
+/// db must be a live kp_db used on the thread which opened it and by no
+/// other call meanwhile. Every string which isn't null must be
+/// NUL-terminated. Unless it's null, out must be writable memory for a
+/// pointer, the kp_entry stored there is freed with kp_entry_free.
+#[no_mangle]
+pub unsafe extern "C" fn kp_db_create_entry(db: *mut KpDb,
+                                            group_id: u32,
+                                            title: *const c_char,
+                                            username: *const c_char,
+                                            password: *const c_char,
+                                            url: *const c_char,
+                                            notes: *const c_char,
+                                            out: *mut *mut KpEntry)
+                                            -> c_int {
+    call(|| {
+        if !out.is_null() {
+            *out = ptr::null_mut();
+        }
+        let db = try!(reference_mut(db));
+        let group = try!(db.db.group_by_id(group_id).ok_or(V1KpdbError::IndexErr));
+        let entry = db.db.create_entry(group,
+                                       try!(string(title)),
+                                       None,
+                                       None,
+                                       try!(opt_string(url)),
+                                       try!(opt_string(notes)),
+                                       try!(opt_string(username)),
+                                       try!(opt_string(password)));
+        if !out.is_null() {
+            let handle = db.db.entry_handle(&entry.borrow());
+            *out = Box::into_raw(Box::new(KpEntry { handle: handle }));
+        }
+        Ok(())
+    })
+}
+
+/// Number of entries of list
+///
+/// # Safety
+///
+/// list must be null or a kp_entry_list from kp_db_search which wasn't
+/// freed yet.
+#[no_mangle]
+pub unsafe extern "C" fn kp_entry_list_len(list: *const KpEntryList) -> size_t {
+    match reference(list) {
+        Ok(list) => list.entries.len() as size_t,
+        Err(_) => 0,
+    }
+}
+
+/// The index-th entry of list, null if it's out of range. It's owned by
+/// the list.
+///
+/// # Safety
+///
+/// list must be null or a kp_entry_list which wasn't freed yet. The
+/// returned entry is only valid as long as the list and must not be
+/// freed on its own.
+#[no_mangle]
+pub unsafe extern "C" fn kp_entry_list_get(list: *const KpEntryList,
+                                           index: size_t)
+                                           -> *const KpEntry {
+    match reference(list).ok().and_then(|list| list.entries.get(index as usize)) {
+        Some(entry) => entry,
+        None => ptr::null(),
+    }
+}
+
+/// Free a list of kp_db_search
+///
+/// # Safety
+///
+/// list must be null or a kp_entry_list which wasn't freed yet. Entries
+/// taken from it with kp_entry_list_get are freed along with it.
+#[no_mangle]
+pub unsafe extern "C" fn kp_entry_list_free(list: *mut KpEntryList) {
+    if !list.is_null() {
+        drop(Box::from_raw(list));
+    }
+}
+
+/// Copy field, one of the KP_FIELD_ constants, of entry into a new
+/// string. *out is null if the field isn't set. HandleErr if the entry
+/// was removed meanwhile.
+///
+/// # Safety
+///
+/// db must be a live kp_db used on the thread which opened it, and entry
+/// a live kp_entry of the same database. out must be writable memory
+/// for a pointer, the string stored there is freed with kp_string_free.
+#[no_mangle]
+pub unsafe extern "C" fn kp_entry_get(db: *const KpDb,
+                                      entry: *const KpEntry,
+                                      field: c_int,
+                                      out: *mut *mut c_char)
+                                      -> c_int {
+    call(|| {
+        let out = try!(reference_mut(out));
+        *out = ptr::null_mut();
+        let db = try!(reference(db));
+        let entry = try!(reference(entry));
+        let value = try!(try!(db.db.with_entry(&entry.handle, |e| entry_field(e, field))));
+        if let Some(value) = value {
+            *out = try!(into_c_string(value));
+        }
+        Ok(())
+    })
+}
+
+fn entry_field(entry: &mut V1Entry, field: c_int) -> Result<Option<String>, FfiError> {
+    Ok(match field {
+        KP_FIELD_TITLE => Some(entry.title.clone()),
+        KP_FIELD_USERNAME => entry.username().map(|u| u.to_string()),
+        KP_FIELD_PASSWORD => entry.password().map(|p| p.to_string()),
+        KP_FIELD_URL => entry.url.clone(),
+        KP_FIELD_NOTES => entry.comment.clone(),
+        KP_FIELD_UUID => Some(entry.uuid.to_simple_string().to_uppercase()),
+        _ => return Err(arg_error("Unknown field")),
+    })
+}
+
+/// Free an entry of kp_db_create_entry
+///
+/// # Safety
+///
+/// entry must be null or a kp_entry from kp_db_create_entry which wasn't
+/// freed yet. Entries of a kp_entry_list are freed with the list.
+#[no_mangle]
+pub unsafe extern "C" fn kp_entry_free(entry: *mut KpEntry) {
+    if !entry.is_null() {
+        drop(Box::from_raw(entry));
+    }
+}
+
+/// Zero out and free a string returned by the library
+///
+/// # Safety
+///
+/// s must be null or a string returned by the library which wasn't
+/// freed yet. Its length must not have been changed, e.g. by writing a
+/// NUL into it, or the allocation is freed with the wrong size.
+#[no_mangle]
+pub unsafe extern "C" fn kp_string_free(s: *mut c_char) {
+    if s.is_null() {
+        return;
+    }
+    let s = CString::from_raw(s).into_bytes_with_nul();
+    mem_protect::zero(&s);
+}
+
+/// Description of the last error on the calling thread. It's valid
+/// until the next call on this thread and must not be freed.
+#[no_mangle]
+pub extern "C" fn kp_last_error() -> *const c_char {
+    LAST_ERROR.with(|e| e.borrow().as_ptr())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ffi::{CStr, CString};
+    use std::fs::File;
+    use std::io::Read;
+    use std::ptr;
+
+    use super::*;
+
+    fn c(s: &str) -> CString {
+        CString::new(s).unwrap()
+    }
+
+    #[test]
+    fn test_open_search_read() {
+        unsafe {
+            let mut db = ptr::null_mut();
+            let path = c("test/test_parsing.kdb");
+            assert_eq!(kp_db_open(path.as_ptr(), c("wrong").as_ptr(), ptr::null(), &mut db),
+                       V1KpdbError::DecryptErr as c_int + 1);
+            assert!(db.is_null());
+            assert!(!CStr::from_ptr(kp_last_error()).to_bytes().is_empty());
+            assert_eq!(kp_db_open(path.as_ptr(), c("test").as_ptr(), ptr::null(), &mut db),
+                       KP_OK);
+
+            let mut list = ptr::null_mut();
+            assert_eq!(kp_db_search(db, c("").as_ptr(), &mut list), KP_OK);
+            assert!(kp_entry_list_len(list) > 0);
+            assert!(kp_entry_list_get(list, kp_entry_list_len(list)).is_null());
+            let mut title = ptr::null_mut();
+            assert_eq!(kp_entry_get(db, kp_entry_list_get(list, 0), KP_FIELD_TITLE, &mut title),
+                       KP_OK);
+            assert!(!title.is_null());
+            kp_string_free(title);
+            kp_entry_list_free(list);
+
+            assert_eq!(kp_db_search(db, ptr::null(), &mut list), KP_ERR_ARG);
+            assert_eq!(kp_entry_get(db, ptr::null(), KP_FIELD_TITLE, &mut title), KP_ERR_ARG);
+            kp_db_free(db);
+        }
+    }
+
+    #[test]
+    fn test_create_entry() {
+        unsafe {
+            let mut db = ptr::null_mut();
+            let path = c("test/test_parsing.kdb");
+            assert_eq!(kp_db_open(path.as_ptr(), c("test").as_ptr(), ptr::null(), &mut db),
+                       KP_OK);
+            let group_id = (&*db).db.groups[0].borrow().id;
+            let mut entry = ptr::null_mut();
+            assert_eq!(kp_db_create_entry(db,
+                                          group_id,
+                                          c("ffi").as_ptr(),
+                                          c("user").as_ptr(),
+                                          c("secret").as_ptr(),
+                                          ptr::null(),
+                                          ptr::null(),
+                                          &mut entry),
+                       KP_OK);
+            let mut password = ptr::null_mut();
+            assert_eq!(kp_entry_get(db, entry, KP_FIELD_PASSWORD, &mut password), KP_OK);
+            assert_eq!(CStr::from_ptr(password).to_str(), Ok("secret"));
+            kp_string_free(password);
+            let mut url = ptr::null_mut();
+            assert_eq!(kp_entry_get(db, entry, KP_FIELD_URL, &mut url), KP_OK);
+            assert!(url.is_null());
+            kp_entry_free(entry);
+
+            assert_eq!(kp_db_create_entry(db,
+                                          u32::max_value(),
+                                          c("ffi").as_ptr(),
+                                          ptr::null(),
+                                          ptr::null(),
+                                          ptr::null(),
+                                          ptr::null(),
+                                          ptr::null_mut()),
+                       V1KpdbError::IndexErr as c_int + 1);
+            kp_db_free(db);
+        }
+    }
+
+    // C type of a Rust parameter or return type, spaces removed
+    fn c_type(rust: &str) -> String {
+        let mut base = rust.trim();
+        let mut pointers = 0;
+        let mut pointee_const = false;
+        loop {
+            if base.starts_with("*const ") {
+                pointee_const = true;
+                base = &base[7..];
+            } else if base.starts_with("*mut ") {
+                pointee_const = false;
+                base = &base[5..];
+            } else {
+                break;
+            }
+            pointers += 1;
+        }
+        let base = match base.trim() {
+            "c_char" => "char",
+            "c_int" => "int",
+            "u32" => "uint32_t",
+            "size_t" => "size_t",
+            "KpDb" => "kp_db",
+            "KpEntry" => "kp_entry",
+            "KpEntryList" => "kp_entry_list",
+            other => panic!("no C type for {}", other),
+        };
+        let constness = if pointee_const { "const" } else { "" };
+        format!("{}{}{}", constness, base, "*".repeat(pointers))
+    }
+
+    fn strip_spaces(s: &str) -> String {
+        s.chars().filter(|c| !c.is_whitespace()).collect()
+    }
+
+    // Name, return type and parameter types of every prototype in the
+    // header, spaces removed
+    fn header_prototypes(header: &str) -> Vec<(String, String, Vec<String>)> {
+        let mut code = String::new();
+        let mut rest = header;
+        while let Some(start) = rest.find("/*") {
+            code.push_str(&rest[..start]);
+            rest = &rest[start..];
+            rest = &rest[rest.find("*/").unwrap() + 2..];
+        }
+        code.push_str(rest);
+        let code: Vec<&str> = code.lines().filter(|l| !l.starts_with('#')).collect();
+        let code = code.join(" ");
+        let is_ident = |c: char| c.is_alphanumeric() || c == '_';
+        code.split(';')
+            .filter(|s| s.contains('(') && !s.contains("typedef"))
+            .map(|s| {
+                let open = s.find('(').unwrap();
+                let close = s.rfind(')').unwrap();
+                let before = s[..open].trim();
+                let name_start = before.rfind(|c| !is_ident(c)).map_or(0, |i| i + 1);
+                let name = &before[name_start..];
+                let ret = strip_spaces(&before[..name_start]);
+                let params = s[open + 1..close]
+                                 .split(',')
+                                 .map(|p| p.trim())
+                                 .filter(|p| *p != "void")
+                                 .map(|p| strip_spaces(p.trim_right_matches(is_ident)))
+                                 .collect();
+                (name.to_string(), ret, params)
+            })
+            .collect()
+    }
+
+    // The header is written by hand, so check it declares every function
+    // with the same signature
+    #[test]
+    fn test_header() {
+        let mut header = String::new();
+        File::open("include/keepass.h").unwrap().read_to_string(&mut header).unwrap();
+        let mut source = String::new();
+        File::open("src/ffi.rs").unwrap().read_to_string(&mut source).unwrap();
+        let prototypes = header_prototypes(&header);
+        let mut functions = 0;
+        let marker = "extern \"C\" fn ";
+        let mut rest = &source[..source.find("#[cfg(test)]").unwrap()];
+        while let Some(start) = rest.find(marker) {
+            rest = &rest[start + marker.len()..];
+            let signature = &rest[..rest.find('{').unwrap()];
+            let name = &signature[..signature.find('(').unwrap()];
+            let close = signature.find(')').unwrap();
+            let params: Vec<String> = signature[name.len() + 1..close]
+                                          .split(',')
+                                          .filter(|p| !p.trim().is_empty())
+                                          .map(|p| c_type(p.splitn(2, ':').nth(1).unwrap()))
+                                          .collect();
+            let ret = match signature[close..].find("->") {
+                Some(arrow) => c_type(&signature[close + arrow + 2..]),
+                None => "void".to_string(),
+            };
+            let prototype = prototypes.iter().find(|p| p.0 == name);
+            assert!(prototype.is_some(), "{} is missing", name);
+            let prototype = prototype.unwrap();
+            assert_eq!(prototype.1, ret, "return type of {}", name);
+            assert_eq!(prototype.2, params, "parameters of {}", name);
+            functions += 1;
+        }
+        assert_eq!(prototypes.len(), functions);
+    }
+}
@@ -0,0 +1,97 @@
+use uuid::Uuid;
+
+/// Passwords with a lower estimated strength are reported as weak
+pub const WEAK_PASSWORD_BITS: u32 = 50;
+
+/// How urgent a recommendation is. Sorting puts High first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Priority {
+    High,
+    Medium,
+    Low,
+}
+
+/// What V1Kpdb::security_advisor suggests to do
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Advice {
+    /// The key transformation is much faster than the target time.
+    /// Apply suggested with V1Header::set_key_transf_rounds.
+    IncreaseRounds {
+        current: u32,
+        suggested: u32,
+    },
+    /// The key consists of a single factor. Add a keyfile or
+    /// challenge-response component with set_credentials or set_key.
+    AddKeyFactor,
+    /// UUIDs of entries whose password appears in the breach list
+    ChangeBreachedPasswords(Vec<Uuid>),
+    /// UUIDs of entries whose password is weaker than
+    /// WEAK_PASSWORD_BITS
+    ChangeWeakPasswords(Vec<Uuid>),
+    /// KeePass 1.x only knows AES-KDF, which doesn't need much memory and
+    /// is therefore cheap to attack with GPUs. KDBX 4 with Argon2 isn't
+    /// supported by this library, so this has to be done in another
+    /// client.
+    MigrateToKdbx4,
+}
+
+impl Advice {
+    /// A sentence to show the user
+    pub fn description(&self) -> String {
+        match *self {
+            Advice::IncreaseRounds { suggested, .. } => {
+                format!("Increase the key transformation rounds to {}", suggested)
+            }
+            Advice::AddKeyFactor => "Add a keyfile or a hardware token to the key".to_string(),
+            Advice::ChangeBreachedPasswords(ref uuids) => {
+                format!("Change {} passwords which appeared in data breaches", uuids.len())
+            }
+            Advice::ChangeWeakPasswords(ref uuids) => {
+                format!("Change {} weak passwords", uuids.len())
+            }
+            Advice::MigrateToKdbx4 => "Migrate the database to KDBX 4 with Argon2".to_string(),
+        }
+    }
+}
+
+#[doc = "
+Recommendation is one result of V1Kpdb::security_advisor. The advisor
+returns them ordered by priority, most urgent first.
+"]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Recommendation {
+    pub priority: Priority,
+    pub advice: Advice,
+}
+
+impl Recommendation {
+    pub fn new(priority: Priority, advice: Advice) -> Recommendation {
+        Recommendation {
+            priority: priority,
+            advice: advice,
+        }
+    }
+}
+
+/// Rough strength of password in bits: length times the bits per
+/// character of the character classes it uses. This overrates words and
+/// patterns, so it only finds passwords which are weak for sure.
+pub fn password_strength(password: &str) -> u32 {
+    let mut pool = 0;
+    if password.chars().any(|c| c.is_lowercase()) {
+        pool += 26;
+    }
+    if password.chars().any(|c| c.is_uppercase()) {
+        pool += 26;
+    }
+    if password.chars().any(|c| c.is_numeric()) {
+        pool += 10;
+    }
+    if password.chars().any(|c| !c.is_alphanumeric()) {
+        pool += 33;
+    }
+    if pool == 0 {
+        return 0;
+    }
+    ((pool as f64).log2() * password.chars().count() as f64) as u32
+}
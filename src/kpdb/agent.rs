@@ -0,0 +1,177 @@
+use std::collections::HashMap;
+use std::fs;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use kpdb::crypter::CompositeKey;
+use kpdb::shared::SharedKpdb;
+use kpdb::v1error::V1KpdbError;
+use kpdb::v1kpdb::V1Kpdb;
+use sec_str::SecureString;
+
+#[doc = "
+Agent holds the unlocked databases of a session, keyed by their path,
+e.g. for a daemon which hands out passwords to other programs or serves
+the Secret Service API. Each database lives on a thread of its own like
+a SharedKpdb, so calls to the same database run one after another while
+different databases don't wait for each other. Every database is
+locked once it wasn't used for timeout and unlocked again on request.
+A database with unsaved changes isn't locked until they're saved, see
+AutoLock, so save them within the call which makes them. Agent is Send,
+Sync and cheap to clone.
+
+Paths are compared after resolving them, so \"./a.kdb\" and \"a.kdb\"
+are the same database. Calls for a path the agent doesn't hold are a
+NotOpenErr.
+
+```ignore
+let agent = Agent::new(Some(Duration::from_secs(300)), true);
+try!(agent.unlock(\"passwords.kdb\", Some(password), None));
+let secret = try!(agent.password(\"passwords.kdb\", \"Internet/Email/Gmail\"));
+```
+"]
+#[derive(Clone)]
+pub struct Agent {
+    databases: Arc<Mutex<HashMap<String, SharedKpdb>>>,
+    timeout: Option<Duration>,
+    forget_key: bool,
+}
+
+impl Agent {
+    /// Create an agent which locks a database after timeout without use,
+    /// None to never lock. Locking drops the key as well if forget_key
+    /// is true, unlock needs the password or keyfile then.
+    pub fn new(timeout: Option<Duration>, forget_key: bool) -> Agent {
+        Agent {
+            databases: Arc::new(Mutex::new(HashMap::new())),
+            timeout: timeout,
+            forget_key: forget_key,
+        }
+    }
+
+    /// Load the database at path with password and keyfile, or unlock it
+    /// if the agent holds it already. A locked database which kept its
+    /// key is unlocked without password and keyfile. See V1Kpdb::new and
+    /// V1Kpdb::load for the errors.
+    pub fn unlock(&self,
+                  path: &str,
+                  password: Option<String>,
+                  keyfile: Option<String>)
+                  -> Result<(), V1KpdbError> {
+        match self.get(path) {
+            Ok(shared) => {
+                if password.is_none() && keyfile.is_none() {
+                    return shared.unlock();
+                }
+                shared.unlock_with(move || {
+                    CompositeKey::from_credentials(password.map(SecureString::new),
+                                                   keyfile.map(SecureString::new))
+                })
+            }
+            Err(_) => {
+                let owned_path = path.to_string();
+                self.open(path, move || {
+                    let mut db = try!(V1Kpdb::new(owned_path, password, keyfile));
+                    try!(db.load());
+                    Ok(db)
+                })
+            }
+        }
+    }
+
+    /// Hold the database open creates for path, e.g. one loaded with a
+    /// key provider. Like SharedKpdb::spawn, open runs on the thread of
+    /// the database. A database the agent held for path before is
+    /// closed.
+    pub fn open<F>(&self, path: &str, open: F) -> Result<(), V1KpdbError>
+        where F: FnOnce() -> Result<V1Kpdb, V1KpdbError> + Send + 'static
+    {
+        // Loading takes a while, the other databases stay usable
+        let shared = try!(SharedKpdb::spawn(open));
+        try!(shared.auto_lock_after(self.timeout, self.forget_key));
+        let mut databases = try!(self.databases.lock().map_err(|_| V1KpdbError::ThreadErr));
+        databases.insert(key(path), shared);
+        Ok(())
+    }
+
+    /// Stop holding the database at path, its thread drops it once the
+    /// calls in progress are done. Changes which weren't saved are lost.
+    /// Returns false if the agent doesn't hold it.
+    pub fn close(&self, path: &str) -> bool {
+        match self.databases.lock() {
+            Ok(mut databases) => databases.remove(&key(path)).is_some(),
+            Err(_) => false,
+        }
+    }
+
+    /// The resolved paths of the databases the agent holds, sorted
+    pub fn paths(&self) -> Vec<String> {
+        let mut paths = match self.databases.lock() {
+            Ok(databases) => databases.keys().cloned().collect::<Vec<_>>(),
+            Err(_) => vec![],
+        };
+        paths.sort();
+        paths
+    }
+
+    pub fn is_open(&self, path: &str) -> bool {
+        self.get(path).is_ok()
+    }
+
+    pub fn is_locked(&self, path: &str) -> Result<bool, V1KpdbError> {
+        try!(self.get(path)).is_locked()
+    }
+
+    /// Run f with the database at path to query or modify it, see
+    /// SharedKpdb::with. LockedErr if it's locked.
+    pub fn with<F, R>(&self, path: &str, f: F) -> Result<R, V1KpdbError>
+        where F: FnOnce(&mut V1Kpdb) -> R + Send + 'static,
+              R: Send + 'static
+    {
+        try!(self.get(path)).with(f)
+    }
+
+    /// The password of the entry at entry_path in the database at path,
+    /// see V1Kpdb::entry_by_path. PathErr if there's no such entry.
+    pub fn password(&self, path: &str, entry_path: &str) -> Result<SecureString, V1KpdbError> {
+        let entry_path = entry_path.to_string();
+        try!(self.with(path, move |db| {
+            let entry = try!(db.entry_by_path(&entry_path).ok_or(V1KpdbError::PathErr));
+            let mut entry = entry.borrow_mut();
+            let password = entry.password().map_or(String::new(), |p| p.to_string());
+            Ok(SecureString::new(password))
+        }))
+    }
+
+    /// Lock the database at path now. UnsavedErr if it has unsaved
+    /// changes, it stays unlocked then.
+    pub fn lock(&self, path: &str) -> Result<(), V1KpdbError> {
+        try!(self.get(path)).lock()
+    }
+
+    /// Lock all databases now, e.g. when the screen is locked. Databases
+    /// whose thread is gone or which have unsaved changes are skipped.
+    pub fn lock_all(&self) {
+        let databases = match self.databases.lock() {
+            Ok(databases) => databases.values().cloned().collect::<Vec<_>>(),
+            Err(_) => return,
+        };
+        for shared in databases {
+            let _ = shared.lock();
+        }
+    }
+
+    // The database at path, the map isn't locked while it's used
+    fn get(&self, path: &str) -> Result<SharedKpdb, V1KpdbError> {
+        let databases = try!(self.databases.lock().map_err(|_| V1KpdbError::ThreadErr));
+        databases.get(&key(path)).cloned().ok_or(V1KpdbError::NotOpenErr)
+    }
+}
+
+// path resolved, or as it is if it can't be
+fn key(path: &str) -> String {
+    fs::canonicalize(path)
+        .ok()
+        .and_then(|p| p.to_str().map(|p| p.to_string()))
+        .unwrap_or(path.to_string())
+}
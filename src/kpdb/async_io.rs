@@ -0,0 +1,196 @@
+//! Loading and saving without blocking an async runtime
+//!
+//! V1Kpdb::load_async reads the file with tokio::fs and runs the key
+//! transformation and the decryption on tokio's blocking pool, so a
+//! high number of key transformation rounds doesn't stall other tasks.
+//! save_async does the same for the encryption and the write. Parsing
+//! and serializing the tree stay on the task, V1Kpdb isn't Send.
+//!
+//! The futures must be polled inside a tokio runtime. Unlike load and
+//! save they neither apply mem_lock_policy nor report progress, the
+//! usage_sink is still notified.
+
+use std::future::Future;
+use std::io;
+use std::mem;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Instant;
+
+use tokio::fs;
+use tokio::task::{self, JoinHandle};
+
+use kpdb::lockfile::FileLock;
+use kpdb::usage::UsageKind;
+use kpdb::v1error::V1KpdbError;
+use kpdb::v1kpdb::{LoadJob, SaveJob, V1Kpdb};
+use mem_protect;
+
+enum LoadState {
+    Start,
+    Reading(Pin<Box<Future<Output = io::Result<Vec<u8>>> + Send>>),
+    Decrypting(JoinHandle<(LoadJob, Result<(), V1KpdbError>)>),
+    Done,
+}
+
+#[doc = "
+LoadFuture is the future of V1Kpdb::load_async. Dropping it before it
+completed leaves the database as load would after an error.
+"]
+pub struct LoadFuture<'a> {
+    db: &'a mut V1Kpdb,
+    start: Instant,
+    state: LoadState,
+}
+
+impl<'a> LoadFuture<'a> {
+    fn finish(&mut self, result: Result<(), V1KpdbError>) -> Poll<Result<(), V1KpdbError>> {
+        self.db.report_usage(UsageKind::Open, self.start, result.is_ok(), 0);
+        Poll::Ready(result)
+    }
+}
+
+impl<'a> Future for LoadFuture<'a> {
+    type Output = Result<(), V1KpdbError>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), V1KpdbError>> {
+        let this = self.get_mut();
+        loop {
+            match mem::replace(&mut this.state, LoadState::Done) {
+                LoadState::Start => {
+                    if this.db.harden_process {
+                        mem_protect::harden_process();
+                    }
+                    this.state = LoadState::Reading(Box::pin(fs::read(this.db.path.clone())));
+                }
+                LoadState::Reading(mut read) => {
+                    let data = match read.as_mut().poll(cx) {
+                        Poll::Pending => {
+                            this.state = LoadState::Reading(read);
+                            return Poll::Pending;
+                        }
+                        Poll::Ready(Ok(data)) => data,
+                        Poll::Ready(Err(e)) => return this.finish(Err(read_error(e))),
+                    };
+                    let mut job = match this.db.load_job(data) {
+                        Ok(job) => job,
+                        Err(e) => return this.finish(Err(e)),
+                    };
+                    this.state = LoadState::Decrypting(task::spawn_blocking(move || {
+                        let result = job.decrypt();
+                        (job, result)
+                    }));
+                }
+                LoadState::Decrypting(mut handle) => {
+                    let result = match Pin::new(&mut handle).poll(cx) {
+                        Poll::Pending => {
+                            this.state = LoadState::Decrypting(handle);
+                            return Poll::Pending;
+                        }
+                        Poll::Ready(Ok((job, Ok(())))) => this.db.finish_load(job),
+                        Poll::Ready(Ok((_, Err(e)))) => Err(e),
+                        Poll::Ready(Err(_)) => Err(V1KpdbError::ThreadErr),
+                    };
+                    return this.finish(result);
+                }
+                LoadState::Done => panic!("LoadFuture polled after completion"),
+            }
+        }
+    }
+}
+
+enum SaveState {
+    Start(Option<String>),
+    Writing(JoinHandle<(SaveJob, Result<(), V1KpdbError>)>, Option<FileLock>),
+    Done,
+}
+
+#[doc = "
+SaveFuture is the future of V1Kpdb::save_async. Once the write started,
+dropping the future doesn't stop it, but the database doesn't take over
+the new path then.
+"]
+pub struct SaveFuture<'a> {
+    db: &'a mut V1Kpdb,
+    start: Instant,
+    state: SaveState,
+}
+
+impl<'a> SaveFuture<'a> {
+    fn finish(&mut self, result: Result<(), V1KpdbError>) -> Poll<Result<(), V1KpdbError>> {
+        self.db.report_usage(UsageKind::Save, self.start, result.is_ok(), 0);
+        Poll::Ready(result)
+    }
+}
+
+impl<'a> Future for SaveFuture<'a> {
+    type Output = Result<(), V1KpdbError>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), V1KpdbError>> {
+        let this = self.get_mut();
+        loop {
+            match mem::replace(&mut this.state, SaveState::Done) {
+                SaveState::Start(path) => {
+                    let (mut job, new_lock) = match this.db.save_job(path) {
+                        Ok(job) => job,
+                        Err(e) => return this.finish(Err(e)),
+                    };
+                    // The write is blocking too, tokio::fs would only
+                    // move it to the same pool
+                    let handle = task::spawn_blocking(move || {
+                        let result = job.encrypt().and_then(|_| job.write());
+                        (job, result)
+                    });
+                    this.state = SaveState::Writing(handle, new_lock);
+                }
+                SaveState::Writing(mut handle, new_lock) => {
+                    let result = match Pin::new(&mut handle).poll(cx) {
+                        Poll::Pending => {
+                            this.state = SaveState::Writing(handle, new_lock);
+                            return Poll::Pending;
+                        }
+                        Poll::Ready(Ok((job, Ok(())))) => {
+                            this.db.finish_save(job, new_lock);
+                            Ok(())
+                        }
+                        Poll::Ready(Ok((_, Err(e)))) => Err(e),
+                        Poll::Ready(Err(_)) => Err(V1KpdbError::ThreadErr),
+                    };
+                    return this.finish(result);
+                }
+                SaveState::Done => panic!("SaveFuture polled after completion"),
+            }
+        }
+    }
+}
+
+// fs::read doesn't tell opening and reading apart, load gives FileErr
+// if the file can't be opened
+fn read_error(e: io::Error) -> V1KpdbError {
+    match e.kind() {
+        io::ErrorKind::NotFound | io::ErrorKind::PermissionDenied => V1KpdbError::FileErr,
+        _ => V1KpdbError::ReadErr,
+    }
+}
+
+impl V1Kpdb {
+    /// Like load, but the file is read asynchronously and the key
+    /// transformation runs on tokio's blocking pool
+    pub fn load_async(&mut self) -> LoadFuture {
+        LoadFuture {
+            db: self,
+            start: Instant::now(),
+            state: LoadState::Start,
+        }
+    }
+
+    /// Like save, but the key transformation, the encryption and the
+    /// write run on tokio's blocking pool
+    pub fn save_async(&mut self, path: Option<String>) -> SaveFuture {
+        SaveFuture {
+            db: self,
+            start: Instant::now(),
+            state: SaveState::Start(path),
+        }
+    }
+}
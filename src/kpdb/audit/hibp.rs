@@ -0,0 +1,129 @@
+use std::io::Write;
+
+use openssl::crypto::hash::{Hasher, Type};
+
+use kpdb::crypter::constant_time_eq;
+use kpdb::v1error::V1KpdbError;
+use mem_protect;
+use sec_str::SecureString;
+
+/// Number of hex digits of the SHA-1 hash which are sent to the API
+pub const PREFIX_LEN: usize = 5;
+
+/// The range endpoint of the Pwned Passwords API, the prefix is
+/// appended
+pub const RANGE_URL: &'static str = "https://api.pwnedpasswords.com/range/";
+
+const HEX_DIGITS: &'static [u8; 16] = b"0123456789ABCDEF";
+
+#[doc = "
+HibpQuery prepares a k-anonymity lookup of a password in Have I Been
+Pwned. Only prefix is meant to leave the application: fetch
+RANGE_URL + prefix and pass the response to count. The crate itself
+doesn't do any networking.
+
+The rest of the hash is kept encrypted like a SecureString and is only
+decrypted while count compares it, so the full hash is never handed
+out.
+"]
+pub struct HibpQuery {
+    prefix: String,
+    suffix: SecureString,
+}
+
+impl HibpQuery {
+    /// Hash password with SHA-1. The plain text is deleted afterwards.
+    pub fn new(password: &mut SecureString) -> Result<HibpQuery, V1KpdbError> {
+        let (prefix, suffix) = try!(hash_password(password));
+        Ok(HibpQuery {
+            prefix: prefix,
+            suffix: suffix,
+        })
+    }
+
+    /// The first PREFIX_LEN hex digits of the hash, in upper case
+    pub fn prefix(&self) -> &str {
+        &self.prefix
+    }
+
+    /// The URL to fetch, i.e. RANGE_URL followed by prefix
+    pub fn range_url(&self) -> String {
+        format!("{}{}", RANGE_URL, self.prefix)
+    }
+
+    /// The verifier: how often the password appears in response, the
+    /// body of the range API. Lines are SUFFIX:COUNT, padding lines with
+    /// a count of 0 and malformed lines don't match.
+    pub fn count(&mut self, response: &str) -> u64 {
+        let suffix = self.suffix.unlocked();
+        let mut count = 0;
+        // Every line is compared, so the time taken doesn't tell which
+        // one matched
+        for line in response.lines() {
+            let mut parts = line.trim().splitn(2, ':');
+            let line_suffix = parts.next().unwrap_or("").to_uppercase();
+            let line_count = parts.next().and_then(|c| c.trim().parse::<u64>().ok());
+            if let Some(line_count) = line_count {
+                if constant_time_eq(line_suffix.as_bytes(), suffix.as_bytes()) {
+                    count = line_count;
+                }
+            }
+        }
+        count
+    }
+}
+
+/// Look password up in Have I Been Pwned. fetch is given the URL of the
+/// range to query and returns the body of the response, e.g. ReadErr if
+/// the request failed. Returns how often the password appears in
+/// breaches, 0 if it doesn't.
+pub fn check<F>(password: &mut SecureString, fetch: F) -> Result<u64, V1KpdbError>
+    where F: FnOnce(&str) -> Result<String, V1KpdbError>
+{
+    let mut query = try!(HibpQuery::new(password));
+    let response = try!(fetch(&query.range_url()));
+    Ok(query.count(&response))
+}
+
+// Sensitive data in this function:
+// * password (locked: SecureString)
+// * digest (locked)
+// * suffix (locked, then moved into a SecureString)
+//
+// At the end of this function:
+// * password is deleted
+// * digest is zeroed out
+fn hash_password(password: &mut SecureString) -> Result<(String, SecureString), V1KpdbError> {
+    password.unlock();
+    let mut hasher = Hasher::new(Type::SHA1);
+    let result = hasher.write_all(password.string.as_bytes());
+    password.delete();
+    try!(result.map_err(|_| V1KpdbError::ReadErr));
+    let digest = hasher.finish();
+    mem_protect::lock(&digest, "digest");
+
+    // The digits are written in place, so the suffix is never moved
+    let mut prefix = String::with_capacity(PREFIX_LEN);
+    let mut suffix = vec![0u8; digest.len() * 2 - PREFIX_LEN];
+    mem_protect::lock(&suffix, "suffix");
+    for (i, byte) in digest.iter().enumerate() {
+        for (j, &nibble) in [byte >> 4, byte & 0xf].iter().enumerate() {
+            let digit = HEX_DIGITS[nibble as usize];
+            let pos = i * 2 + j;
+            if pos < PREFIX_LEN {
+                prefix.push(digit as char);
+            } else {
+                suffix[pos - PREFIX_LEN] = digit;
+            }
+        }
+    }
+    unsafe {
+        mem_protect::zero(&digest);
+    }
+    mem_protect::unlock(&digest);
+
+    // SecureString locks, encrypts and zeroes the suffix itself
+    mem_protect::disown(&suffix);
+    let suffix = String::from_utf8(suffix).map_err(|_| V1KpdbError::ConvertErr);
+    Ok((prefix, SecureString::new(try!(suffix))))
+}
@@ -0,0 +1,42 @@
+use uuid::Uuid;
+
+pub mod hibp;
+
+#[doc = "
+AuditReport is the result of V1Kpdb::audit, e.g. for a database health
+screen. All lists hold entry UUIDs in the order of the entries.
+"]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AuditReport {
+    /// Number of entries which were checked
+    pub num_entries: usize,
+    /// Entries whose password is weaker than WEAK_PASSWORD_BITS,
+    /// together with the estimated strength in bits
+    pub weak: Vec<(Uuid, u32)>,
+    /// Sets of entries which share a password, ordered by their first
+    /// entry
+    pub reused: Vec<Vec<Uuid>>,
+    /// Entries without a password
+    pub empty: Vec<Uuid>,
+    /// Entries which weren't modified for longer than the given age
+    pub stale: Vec<Uuid>,
+}
+
+impl AuditReport {
+    /// Create an empty report
+    pub fn new() -> AuditReport {
+        AuditReport {
+            num_entries: 0,
+            weak: vec![],
+            reused: vec![],
+            empty: vec![],
+            stale: vec![],
+        }
+    }
+
+    /// True if no problem was found
+    pub fn is_clean(&self) -> bool {
+        self.weak.is_empty() && self.reused.is_empty() && self.empty.is_empty() &&
+        self.stale.is_empty()
+    }
+}
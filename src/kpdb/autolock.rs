@@ -0,0 +1,213 @@
+use std::time::{Duration, Instant};
+
+use kpdb::crypter::CompositeKey;
+use kpdb::v1error::V1KpdbError;
+use kpdb::v1header::V1Header;
+use kpdb::v1kpdb::V1Kpdb;
+
+#[doc = "
+LockedKpdb is a database whose groups and entries were dropped and
+zeroed out, see V1Kpdb::close. Path, header and settings are kept, and
+unless it was locked with forget_key the key as well, re-encrypted in
+memory, so unlock only needs to load the file again.
+
+Changes which weren't saved before are lost. Groups and entries which
+are still referenced elsewhere stay alive until these references are
+dropped.
+"]
+pub struct LockedKpdb {
+    db: V1Kpdb,
+    has_key: bool,
+}
+
+impl LockedKpdb {
+    /// Lock db. With forget_key the key is dropped as well and unlock_with
+    /// is needed to open the database again.
+    pub fn new(mut db: V1Kpdb, forget_key: bool) -> LockedKpdb {
+        db.close(forget_key);
+        LockedKpdb {
+            db: db,
+            has_key: !forget_key,
+        }
+    }
+
+    pub fn path(&self) -> &str {
+        &self.db.path
+    }
+
+    /// The header of the database as it was loaded
+    pub fn header(&self) -> &V1Header {
+        &self.db.header
+    }
+
+    /// True if the key was kept, i.e. unlock can be used
+    pub fn has_key(&self) -> bool {
+        self.has_key
+    }
+
+    /// Load the database again with the kept key. On failure, e.g. if
+    /// the file was changed meanwhile, the database stays locked and is
+    /// returned with the error. PassErr if the key wasn't kept.
+    pub fn unlock(mut self) -> Result<V1Kpdb, (LockedKpdb, V1KpdbError)> {
+        if !self.has_key {
+            return Err((self, V1KpdbError::PassErr));
+        }
+        match self.db.load() {
+            Ok(()) => Ok(self.db),
+            Err(e) => {
+                self.db.close(false);
+                Err((self, e))
+            }
+        }
+    }
+
+    /// Like unlock but with key, which is kept from now on
+    pub fn unlock_with(mut self, key: CompositeKey) -> Result<V1Kpdb, (LockedKpdb, V1KpdbError)> {
+        if let Err(e) = self.db.set_key(key) {
+            return Err((self, e));
+        }
+        self.has_key = true;
+        self.unlock()
+    }
+}
+
+// Either state of the database behind an AutoLock
+enum State {
+    Unlocked(V1Kpdb),
+    Locked(LockedKpdb),
+}
+
+#[doc = "
+AutoLock locks a database which wasn't used for timeout, so a
+long-running application doesn't keep it decrypted in memory all the
+time. Access the database through with, which counts as use. Call tick
+regularly, e.g. from the event loop, to lock once the timeout has
+passed, or use SharedKpdb::auto_lock_after which does so on its own.
+
+A database with unsaved changes, see V1Kpdb::dirty, isn't locked, as
+locking would lose them. It's locked once it was saved and the timeout
+has passed again.
+"]
+pub struct AutoLock {
+    /// Time without use after which tick locks, None to never lock
+    pub timeout: Option<Duration>,
+    /// If true, locking drops the key as well, see LockedKpdb::new
+    pub forget_key: bool,
+    // Always Some, None only while the state changes
+    state: Option<State>,
+    last_use: Instant,
+}
+
+impl AutoLock {
+    pub fn new(db: V1Kpdb, timeout: Option<Duration>) -> AutoLock {
+        AutoLock {
+            timeout: timeout,
+            forget_key: false,
+            state: Some(State::Unlocked(db)),
+            last_use: Instant::now(),
+        }
+    }
+
+    pub fn is_locked(&self) -> bool {
+        match self.state {
+            Some(State::Unlocked(_)) => false,
+            _ => true,
+        }
+    }
+
+    /// Run f with the database and restart the timeout. LockedErr while
+    /// the database is locked.
+    pub fn with<F, R>(&mut self, f: F) -> Result<R, V1KpdbError>
+        where F: FnOnce(&mut V1Kpdb) -> R
+    {
+        match self.state {
+            Some(State::Unlocked(ref mut db)) => {
+                self.last_use = Instant::now();
+                Ok(f(db))
+            }
+            _ => Err(V1KpdbError::LockedErr),
+        }
+    }
+
+    /// Time until tick locks the database, None if it's locked already,
+    /// has unsaved changes or there's no timeout
+    pub fn time_left(&self) -> Option<Duration> {
+        match self.timeout {
+            Some(_) if self.is_locked() || self.is_dirty() => None,
+            Some(timeout) => {
+                let idle = self.last_use.elapsed();
+                Some(if idle >= timeout {
+                    Duration::new(0, 0)
+                } else {
+                    timeout - idle
+                })
+            }
+            None => None,
+        }
+    }
+
+    /// Lock the database if the timeout has passed and it has no unsaved
+    /// changes. Returns true if it was locked now.
+    pub fn tick(&mut self) -> bool {
+        match self.time_left() {
+            Some(left) if left == Duration::new(0, 0) => self.lock().is_ok(),
+            _ => false,
+        }
+    }
+
+    /// Lock the database now. UnsavedErr if it has unsaved changes, it
+    /// stays unlocked then, see discard_and_lock.
+    pub fn lock(&mut self) -> Result<(), V1KpdbError> {
+        if self.is_dirty() {
+            return Err(V1KpdbError::UnsavedErr);
+        }
+        self.discard_and_lock();
+        Ok(())
+    }
+
+    /// Lock the database now, even if unsaved changes are lost
+    pub fn discard_and_lock(&mut self) {
+        let forget_key = self.forget_key;
+        self.state = match self.state.take() {
+            Some(State::Unlocked(db)) => Some(State::Locked(LockedKpdb::new(db, forget_key))),
+            state => state,
+        };
+    }
+
+    // True if the database is unlocked and has unsaved changes
+    fn is_dirty(&self) -> bool {
+        match self.state {
+            Some(State::Unlocked(ref db)) => db.dirty(),
+            _ => false,
+        }
+    }
+
+    /// Unlock the database with the kept key, see LockedKpdb::unlock.
+    /// Nothing happens if it's unlocked.
+    pub fn unlock(&mut self) -> Result<(), V1KpdbError> {
+        self.unlock_by(|locked| locked.unlock())
+    }
+
+    /// Unlock the database with key, see LockedKpdb::unlock_with
+    pub fn unlock_with(&mut self, key: CompositeKey) -> Result<(), V1KpdbError> {
+        self.unlock_by(|locked| locked.unlock_with(key))
+    }
+
+    fn unlock_by<F>(&mut self, unlock: F) -> Result<(), V1KpdbError>
+        where F: FnOnce(LockedKpdb) -> Result<V1Kpdb, (LockedKpdb, V1KpdbError)>
+    {
+        let (state, result) = match self.state.take() {
+            Some(State::Locked(locked)) => {
+                match unlock(locked) {
+                    Ok(db) => (State::Unlocked(db), Ok(())),
+                    Err((locked, e)) => (State::Locked(locked), Err(e)),
+                }
+            }
+            Some(state) => (state, Ok(())),
+            None => return Err(V1KpdbError::LockedErr),
+        };
+        self.state = Some(state);
+        self.last_use = Instant::now();
+        result
+    }
+}
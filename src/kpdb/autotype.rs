@@ -0,0 +1,288 @@
+use kpdb::v1entry::V1Entry;
+use kpdb::v1error::V1KpdbError;
+use sec_str::SecureString;
+
+/// The sequence KeePass types if an entry doesn't define one
+pub const DEFAULT_SEQUENCE: &'static str = "{USERNAME}{TAB}{PASSWORD}{ENTER}";
+
+// KeePass 1.x keeps auto-type settings as lines of the notes:
+//
+//   Auto-Type: {USERNAME}{TAB}{PASSWORD}{ENTER}
+//   Auto-Type-Window: Login - Mozilla Firefox
+//   Auto-Type-1: {PASSWORD}{ENTER}
+//   Auto-Type-Window-1: *Remote Desktop*
+//
+// A window line uses the sequence of the same number, the default
+// sequence if there's none.
+const SEQUENCE_PREFIX: &'static str = "Auto-Type";
+const WINDOW_PREFIX: &'static str = "Auto-Type-Window";
+
+// Keys a placeholder may name, with aliases KeePass accepts
+const SPECIAL_KEYS: &'static [(&'static str, &'static str)] = &[("TAB", "TAB"),
+                                                               ("ENTER", "ENTER"),
+                                                               ("SPACE", "SPACE"),
+                                                               ("BACKSPACE", "BACKSPACE"),
+                                                               ("BS", "BACKSPACE"),
+                                                               ("BKSP", "BACKSPACE"),
+                                                               ("DELETE", "DELETE"),
+                                                               ("DEL", "DELETE"),
+                                                               ("INSERT", "INSERT"),
+                                                               ("INS", "INSERT"),
+                                                               ("UP", "UP"),
+                                                               ("DOWN", "DOWN"),
+                                                               ("LEFT", "LEFT"),
+                                                               ("RIGHT", "RIGHT"),
+                                                               ("HOME", "HOME"),
+                                                               ("END", "END"),
+                                                               ("PGUP", "PGUP"),
+                                                               ("PGDN", "PGDN"),
+                                                               ("ESC", "ESC")];
+
+/// A window filter and the sequence typed into matching windows
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AutoTypeWindow {
+    /// Title of the window, * matches any text. Case is ignored.
+    pub window: String,
+    /// None for the default sequence of the entry
+    pub sequence: Option<String>,
+}
+
+#[doc = "
+AutoTypeConfig holds the auto-type settings of an entry, which KeePass
+1.x keeps as Auto-Type and Auto-Type-Window lines of the notes. Read it
+with V1Entry::auto_type and store changes with V1Entry::set_auto_type.
+"]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AutoTypeConfig {
+    /// The default sequence of the entry, None for DEFAULT_SEQUENCE
+    pub sequence: Option<String>,
+    pub windows: Vec<AutoTypeWindow>,
+}
+
+impl AutoTypeConfig {
+    pub fn new() -> AutoTypeConfig {
+        AutoTypeConfig {
+            sequence: None,
+            windows: vec![],
+        }
+    }
+
+    /// Read the Auto-Type lines of notes, other lines are ignored
+    pub fn parse(notes: &str) -> AutoTypeConfig {
+        let mut config = AutoTypeConfig::new();
+        // Sequences and windows by their number, "" if they have none
+        let mut sequences = vec![];
+        let mut windows = vec![];
+        for line in notes.lines() {
+            if let Some((number, window)) = parse_line(line, WINDOW_PREFIX) {
+                windows.push((number, window));
+            } else if let Some((number, sequence)) = parse_line(line, SEQUENCE_PREFIX) {
+                if number.is_empty() {
+                    config.sequence = Some(sequence.to_string());
+                } else {
+                    sequences.push((number, sequence));
+                }
+            }
+        }
+        for (number, window) in windows {
+            let sequence = sequences.iter()
+                                    .find(|&&(n, _)| n == number)
+                                    .map(|&(_, s)| s.to_string());
+            config.windows.push(AutoTypeWindow {
+                window: window.to_string(),
+                sequence: sequence,
+            });
+        }
+        config
+    }
+
+    /// The config as Auto-Type lines. Windows with a sequence of their
+    /// own are numbered from 1.
+    pub fn to_lines(&self) -> Vec<String> {
+        let mut lines = vec![];
+        if let Some(ref sequence) = self.sequence {
+            lines.push(format!("{}: {}", SEQUENCE_PREFIX, sequence));
+        }
+        let mut number = 0;
+        for window in self.windows.iter() {
+            match window.sequence {
+                Some(ref sequence) => {
+                    number += 1;
+                    lines.push(format!("{}-{}: {}", SEQUENCE_PREFIX, number, sequence));
+                    lines.push(format!("{}-{}: {}", WINDOW_PREFIX, number, window.window));
+                }
+                None => lines.push(format!("{}: {}", WINDOW_PREFIX, window.window)),
+            }
+        }
+        lines
+    }
+
+    /// The sequence to type into the window with title title: the one
+    /// of the first matching window filter, the default sequence if no
+    /// filter matches
+    pub fn sequence_for(&self, title: &str) -> &str {
+        let window = self.windows.iter().find(|w| matches_window(&w.window, title));
+        match window.and_then(|w| w.sequence.as_ref()).or(self.sequence.as_ref()) {
+            Some(sequence) => sequence,
+            None => DEFAULT_SEQUENCE,
+        }
+    }
+
+    /// True if one of the window filters matches title
+    pub fn matches(&self, title: &str) -> bool {
+        self.windows.iter().any(|w| matches_window(&w.window, title))
+    }
+
+    /// True if the line is one of the Auto-Type lines
+    pub fn is_auto_type_line(line: &str) -> bool {
+        parse_line(line, SEQUENCE_PREFIX).is_some() || parse_line(line, WINDOW_PREFIX).is_some()
+    }
+}
+
+/// One step of an expanded sequence
+pub enum AutoTypeAction {
+    /// Text to type. It holds field values like the password, so it's
+    /// kept in a SecureString.
+    Text(SecureString),
+    /// A key to press, e.g. "TAB" or "ENTER"
+    Key(&'static str),
+    /// Wait for the milliseconds
+    Delay(u32),
+}
+
+/// Expand the placeholders of sequence with the fields of entry. Field
+/// placeholders are {TITLE}, {USERNAME}, {PASSWORD}, {URL}, {NOTES} and
+/// {TOTP}, {DELAY n} waits and special keys like {TAB} or {ENTER} may be
+/// followed by a count, e.g. {TAB 2}. {{} and {}} type braces. Other
+/// characters are typed as they are, KeePass' key modifiers aren't
+/// supported.
+///
+/// Unknown or unclosed placeholders are an AutoTypeErr, so is {TOTP}
+/// if the entry has no TOTP secret.
+pub fn expand(sequence: &str, entry: &mut V1Entry) -> Result<Vec<AutoTypeAction>, V1KpdbError> {
+    let mut actions = vec![];
+    let mut text = String::new();
+    let mut rest = sequence;
+    while !rest.is_empty() {
+        if !rest.starts_with('{') {
+            let end = rest.find('{').unwrap_or(rest.len());
+            text.push_str(&rest[..end]);
+            rest = &rest[end..];
+            continue;
+        }
+        // {}} is the only placeholder which contains a closing brace
+        let end = if rest.starts_with("{}}") {
+            3
+        } else {
+            try!(rest.find('}').ok_or(V1KpdbError::AutoTypeErr)) + 1
+        };
+        let placeholder = &rest[1..end - 1];
+        rest = &rest[end..];
+
+        let mut parts = placeholder.splitn(2, ' ');
+        let name = parts.next().unwrap_or("").to_uppercase();
+        let argument = parts.next().map(|a| a.trim());
+        let field = match &name[..] {
+            "{" | "}" if argument.is_none() => {
+                text.push_str(&name);
+                continue;
+            }
+            "TITLE" => Some(SecureString::new(entry.title.clone())),
+            "URL" => Some(SecureString::new(entry.url.clone().unwrap_or(String::new()))),
+            "NOTES" => Some(SecureString::new(entry.markdown_notes().unwrap_or(String::new()))),
+            "USERNAME" => {
+                let username = entry.username().map(|u| u.to_string());
+                Some(SecureString::new(username.unwrap_or(String::new())))
+            }
+            "PASSWORD" => {
+                let password = entry.password().map(|p| p.to_string());
+                Some(SecureString::new(password.unwrap_or(String::new())))
+            }
+            "TOTP" => Some(try!(try!(entry.totp_code()).ok_or(V1KpdbError::AutoTypeErr))),
+            _ => None,
+        };
+        if let Some(value) = field {
+            if argument.is_some() {
+                return Err(V1KpdbError::AutoTypeErr);
+            }
+            push_text(&mut actions, &mut text);
+            actions.push(AutoTypeAction::Text(value));
+            continue;
+        }
+
+        push_text(&mut actions, &mut text);
+        if name == "DELAY" {
+            let delay = argument.and_then(|a| a.parse().ok());
+            actions.push(AutoTypeAction::Delay(try!(delay.ok_or(V1KpdbError::AutoTypeErr))));
+            continue;
+        }
+        let key = try!(SPECIAL_KEYS.iter()
+                                   .find(|&&(alias, _)| alias == name)
+                                   .map(|&(_, key)| key)
+                                   .ok_or(V1KpdbError::AutoTypeErr));
+        let count = match argument {
+            Some(count) => try!(count.parse::<u32>().map_err(|_| V1KpdbError::AutoTypeErr)),
+            None => 1,
+        };
+        for _ in 0..count {
+            actions.push(AutoTypeAction::Key(key));
+        }
+    }
+    push_text(&mut actions, &mut text);
+    Ok(actions)
+}
+
+// Move the literal text typed so far into an action
+fn push_text(actions: &mut Vec<AutoTypeAction>, text: &mut String) {
+    if !text.is_empty() {
+        let literal = SecureString::new(text.clone());
+        text.clear();
+        actions.push(AutoTypeAction::Text(literal));
+    }
+}
+
+// Split "<prefix>[-<number>]: <value>" into number and value
+fn parse_line<'a>(line: &'a str, prefix: &str) -> Option<(&'a str, &'a str)> {
+    if !line.starts_with(prefix) {
+        return None;
+    }
+    let rest = &line[prefix.len()..];
+    let colon = match rest.find(':') {
+        Some(i) => i,
+        None => return None,
+    };
+    let number = &rest[..colon];
+    let number = if number.is_empty() {
+        number
+    } else if number.starts_with('-') && number.len() > 1 &&
+       number[1..].chars().all(|c| c.is_digit(10)) {
+        &number[1..]
+    } else {
+        return None;
+    };
+    Some((number, rest[colon + 1..].trim()))
+}
+
+// Match title against a filter with * wildcards, ignoring case
+fn matches_window(filter: &str, title: &str) -> bool {
+    let filter = filter.to_lowercase();
+    let title = title.to_lowercase();
+    let parts: Vec<&str> = filter.split('*').collect();
+    if parts.len() == 1 {
+        return filter == title;
+    }
+    let first = parts[0];
+    let last = parts[parts.len() - 1];
+    if !title.starts_with(first) || title.len() < first.len() + last.len() ||
+       !title.ends_with(last) {
+        return false;
+    }
+    let mut rest = &title[first.len()..title.len() - last.len()];
+    for part in parts[1..parts.len() - 1].iter() {
+        match rest.find(part) {
+            Some(i) => rest = &rest[i + part.len()..],
+            None => return false,
+        }
+    }
+    true
+}
@@ -0,0 +1,73 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use kpdb::v1error::V1KpdbError;
+
+#[doc = "
+SaveOptions sets the backups V1Kpdb::save keeps of the previous
+database file. With backups set to N every save rotates db.kdb.1, the
+newest copy, up to db.kdb.N, the oldest one, before db.kdb is
+replaced. The copies are placed next to the database unless backup_dir
+is set.
+"]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SaveOptions {
+    /// Number of copies to keep, 0 (the default) keeps none
+    pub backups: usize,
+    /// Directory of the copies, None for the directory of the database.
+    /// It's created on the first save if it doesn't exist.
+    pub backup_dir: Option<PathBuf>,
+}
+
+impl SaveOptions {
+    pub fn new() -> SaveOptions {
+        SaveOptions {
+            backups: 0,
+            backup_dir: None,
+        }
+    }
+
+    /// Path of the n-th copy of the database at path, 1 is the newest
+    pub fn backup_path(&self, path: &str, n: usize) -> PathBuf {
+        let path = Path::new(path);
+        let name = match path.file_name() {
+            Some(name) => format!("{}.{}", name.to_string_lossy(), n),
+            None => n.to_string(),
+        };
+        match self.backup_dir {
+            Some(ref dir) => dir.join(name),
+            None => path.with_file_name(name),
+        }
+    }
+
+    /// Paths of the existing copies of the database at path, newest first
+    pub fn backups_of(&self, path: &str) -> Vec<PathBuf> {
+        (1..self.backups + 1)
+            .map(|n| self.backup_path(path, n))
+            .filter(|p| fs::metadata(p).is_ok())
+            .collect()
+    }
+
+    /// Shift the copies of the database at path by one, dropping the
+    /// oldest, and copy path as the newest one. Nothing happens if path
+    /// doesn't exist yet. WriteErr if a copy fails.
+    pub fn rotate(&self, path: &str) -> Result<(), V1KpdbError> {
+        if self.backups == 0 || fs::metadata(path).is_err() {
+            return Ok(());
+        }
+        if let Some(ref dir) = self.backup_dir {
+            try!(fs::create_dir_all(dir).map_err(|_| V1KpdbError::WriteErr));
+        }
+        // rename doesn't replace files on every platform
+        let _ = fs::remove_file(self.backup_path(path, self.backups));
+        for n in (1..self.backups).rev() {
+            let from = self.backup_path(path, n);
+            if fs::metadata(&from).is_ok() {
+                try!(fs::rename(&from, self.backup_path(path, n + 1))
+                         .map_err(|_| V1KpdbError::WriteErr));
+            }
+        }
+        try!(fs::copy(path, self.backup_path(path, 1)).map_err(|_| V1KpdbError::WriteErr));
+        Ok(())
+    }
+}
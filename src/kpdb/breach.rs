@@ -0,0 +1,252 @@
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom, Write};
+
+use openssl::crypto::hash::{Hasher, Type};
+use rustc_serialize::hex::ToHex;
+
+use kpdb::v1error::V1KpdbError;
+use super::super::mem_protect;
+use super::super::sec_str::SecureString;
+
+/// The hash function a breach list was made with
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BreachHash {
+    /// SHA-1 of the UTF-8 password
+    Sha1,
+    /// NTLM, i.e. MD4 of the UTF-16LE password
+    Ntlm,
+}
+
+// Once the search range is smaller than this the lines are scanned
+const SCAN_SIZE: u64 = 4096;
+
+#[doc = "
+BreachList checks passwords against a local copy of the Have I Been Pwned
+password list, for machines which can't use the online API. The file has
+to be one of the dumps ordered by hash with lines like
+
+```text
+5BAA61E4C9B93F3F0682250B6CF8331B7EE68FD8:9545824
+```
+
+The file is binary searched, so it's never loaded as a whole.
+"]
+pub struct BreachList {
+    file: File,
+    len: u64,
+    hash: BreachHash,
+}
+
+impl BreachList {
+    /// Open the breach list at path which was made with hash
+    pub fn open(path: &str, hash: BreachHash) -> Result<BreachList, V1KpdbError> {
+        let file = try!(File::open(path).map_err(|_| V1KpdbError::FileErr));
+        let len = try!(file.metadata().map_err(|_| V1KpdbError::FileErr)).len();
+        Ok(BreachList {
+            file: file,
+            len: len,
+            hash: hash,
+        })
+    }
+
+    /// How often password appears in the breach list, 0 if it doesn't
+    /// appear at all
+    pub fn lookup(&mut self, password: &mut SecureString) -> Result<u64, V1KpdbError> {
+        let hash = try!(self.hash_password(password));
+        self.find(&hash)
+    }
+
+    // Sensitive data in this function:
+    // * password (locked: SecureString)
+    // * utf16 (only for NTLM)
+    //
+    // At the end of this function:
+    // * password is deleted
+    // * utf16 is zeroed out
+    //
+    // The hash itself isn't treated as sensitive as it's looked up in a
+    // list of known hashes.
+    fn hash_password(&self, password: &mut SecureString) -> Result<String, V1KpdbError> {
+        password.unlock();
+        let digest = match self.hash {
+            BreachHash::Sha1 => {
+                let mut hasher = Hasher::new(Type::SHA1);
+                let result = hasher.write_all(password.string.as_bytes());
+                password.delete();
+                try!(result.map_err(|_| V1KpdbError::ReadErr));
+                hasher.finish()
+            }
+            BreachHash::Ntlm => {
+                // Reserve enough space up front, growing would leave copies
+                let mut utf16 = Vec::with_capacity(password.string.len() * 4);
+                for c in password.string.encode_utf16() {
+                    utf16.push(c as u8);
+                    utf16.push((c >> 8) as u8);
+                }
+                password.delete();
+                mem_protect::lock(&utf16, "utf16");
+                let digest = md4(&utf16);
+                unsafe {
+                    mem_protect::zero(&utf16);
+                }
+                mem_protect::unlock(&utf16);
+                digest
+            }
+        };
+        Ok(digest.to_hex().to_uppercase())
+    }
+
+    // Binary search for the line of hash. lo is always the start of a
+    // line and the line of hash, if any, starts before hi.
+    fn find(&mut self, hash: &str) -> Result<u64, V1KpdbError> {
+        let mut lo = 0;
+        let mut hi = self.len;
+        while hi - lo > SCAN_SIZE {
+            let mid = lo + (hi - lo) / 2;
+            let start = try!(self.next_line_start(mid));
+            if start >= hi {
+                break;
+            }
+            let (line_hash, count, next) = try!(self.read_line(start));
+            if line_hash == hash {
+                return Ok(count);
+            } else if &line_hash[..] < hash {
+                lo = next;
+            } else {
+                hi = start;
+            }
+        }
+
+        let mut start = lo;
+        while start < hi {
+            let (line_hash, count, next) = try!(self.read_line(start));
+            if line_hash == hash {
+                return Ok(count);
+            }
+            start = next;
+        }
+        Ok(0)
+    }
+
+    // Offset of the first line which starts at pos or later
+    fn next_line_start(&mut self, pos: u64) -> Result<u64, V1KpdbError> {
+        if pos == 0 {
+            return Ok(0);
+        }
+        let mut offset = pos - 1;
+        let mut buf = [0u8; 128];
+        loop {
+            let n = try!(self.read_at(offset, &mut buf));
+            if n == 0 {
+                return Ok(self.len);
+            }
+            if let Some(i) = buf[..n].iter().position(|b| *b == b'\n') {
+                return Ok(offset + i as u64 + 1);
+            }
+            offset += n as u64;
+        }
+    }
+
+    // Parse the line at start into hash and count. Also returns the start
+    // of the next line.
+    fn read_line(&mut self, start: u64) -> Result<(String, u64, u64), V1KpdbError> {
+        let mut buf = [0u8; 128];
+        let n = try!(self.read_at(start, &mut buf));
+        let (line, next) = match buf[..n].iter().position(|b| *b == b'\n') {
+            Some(i) => (&buf[..i], start + i as u64 + 1),
+            None if start + n as u64 == self.len => (&buf[..n], self.len),
+            None => return Err(V1KpdbError::ReadErr),
+        };
+        let line = try!(String::from_utf8(line.to_vec()).map_err(|_| V1KpdbError::ReadErr));
+        let mut parts = line.trim().splitn(2, ':');
+        let hash = parts.next().unwrap_or("").to_uppercase();
+        let count = match parts.next() {
+            Some(count) => try!(count.parse::<u64>().map_err(|_| V1KpdbError::ReadErr)),
+            None => return Err(V1KpdbError::ReadErr),
+        };
+        Ok((hash, count, next))
+    }
+
+    fn read_at(&mut self, pos: u64, buf: &mut [u8]) -> Result<usize, V1KpdbError> {
+        try!(self.file.seek(SeekFrom::Start(pos)).map_err(|_| V1KpdbError::ReadErr));
+        let mut n = 0;
+        while n < buf.len() {
+            match self.file.read(&mut buf[n..]) {
+                Ok(0) => break,
+                Ok(m) => n += m,
+                Err(_) => return Err(V1KpdbError::ReadErr),
+            }
+        }
+        Ok(n)
+    }
+}
+
+// MD4 (RFC 1320), needed for NTLM hashes. Not part of the crypto
+// library.
+fn md4(data: &[u8]) -> Vec<u8> {
+    fn f(x: u32, y: u32, z: u32) -> u32 {
+        (x & y) | (!x & z)
+    }
+    fn g(x: u32, y: u32, z: u32) -> u32 {
+        (x & y) | (x & z) | (y & z)
+    }
+    fn h(x: u32, y: u32, z: u32) -> u32 {
+        x ^ y ^ z
+    }
+
+    // Allocate the padded size at once, so pushing doesn't leave copies
+    let mut msg = Vec::with_capacity(data.len() + 72);
+    msg.extend_from_slice(data);
+    mem_protect::lock(&msg, "msg");
+    let bit_len = (data.len() as u64).wrapping_mul(8);
+    msg.push(0x80);
+    while msg.len() % 64 != 56 {
+        msg.push(0);
+    }
+    for i in 0..8 {
+        msg.push((bit_len >> (8 * i)) as u8);
+    }
+
+    let mut state: [u32; 4] = [0x67452301, 0xefcdab89, 0x98badcfe, 0x10325476];
+    for block in msg.chunks(64) {
+        let mut x = [0u32; 16];
+        for (i, word) in block.chunks(4).enumerate() {
+            x[i] = (word[0] as u32) | (word[1] as u32) << 8 | (word[2] as u32) << 16 |
+                   (word[3] as u32) << 24;
+        }
+        let (mut a, mut b, mut c, mut d) = (state[0], state[1], state[2], state[3]);
+
+        for &i in &[0, 4, 8, 12] {
+            a = a.wrapping_add(f(b, c, d)).wrapping_add(x[i]).rotate_left(3);
+            d = d.wrapping_add(f(a, b, c)).wrapping_add(x[i + 1]).rotate_left(7);
+            c = c.wrapping_add(f(d, a, b)).wrapping_add(x[i + 2]).rotate_left(11);
+            b = b.wrapping_add(f(c, d, a)).wrapping_add(x[i + 3]).rotate_left(19);
+        }
+        for &i in &[0, 1, 2, 3] {
+            a = a.wrapping_add(g(b, c, d)).wrapping_add(x[i]).wrapping_add(0x5a827999).rotate_left(3);
+            d = d.wrapping_add(g(a, b, c)).wrapping_add(x[i + 4]).wrapping_add(0x5a827999).rotate_left(5);
+            c = c.wrapping_add(g(d, a, b)).wrapping_add(x[i + 8]).wrapping_add(0x5a827999).rotate_left(9);
+            b = b.wrapping_add(g(c, d, a)).wrapping_add(x[i + 12]).wrapping_add(0x5a827999).rotate_left(13);
+        }
+        for &i in &[0, 2, 1, 3] {
+            a = a.wrapping_add(h(b, c, d)).wrapping_add(x[i]).wrapping_add(0x6ed9eba1).rotate_left(3);
+            d = d.wrapping_add(h(a, b, c)).wrapping_add(x[i + 8]).wrapping_add(0x6ed9eba1).rotate_left(9);
+            c = c.wrapping_add(h(d, a, b)).wrapping_add(x[i + 4]).wrapping_add(0x6ed9eba1).rotate_left(11);
+            b = b.wrapping_add(h(c, d, a)).wrapping_add(x[i + 12]).wrapping_add(0x6ed9eba1).rotate_left(15);
+        }
+
+        state[0] = state[0].wrapping_add(a);
+        state[1] = state[1].wrapping_add(b);
+        state[2] = state[2].wrapping_add(c);
+        state[3] = state[3].wrapping_add(d);
+    }
+
+    // The padded copy holds the password as well
+    unsafe {
+        mem_protect::zero(&msg);
+    }
+    mem_protect::unlock(&msg);
+    state.iter()
+         .flat_map(|s| vec![*s as u8, (s >> 8) as u8, (s >> 16) as u8, (s >> 24) as u8])
+         .collect()
+}
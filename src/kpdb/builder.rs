@@ -0,0 +1,178 @@
+use std::time::Duration;
+
+use kpdb::crypter::{CompositeKey, Crypter};
+use kpdb::v1error::V1KpdbError;
+use kpdb::v1kpdb::V1Kpdb;
+use super::super::sec_str::SecureString;
+
+/// Rounds of the key transformation if neither rounds nor calibrate
+/// are set
+pub const DEFAULT_ROUNDS: u32 = 60000;
+
+/// Title of the first group if none is set
+pub const DEFAULT_GROUP_TITLE: &'static str = "General";
+
+/// Cipher of the database content
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Cipher {
+    Aes,
+    /// Defined by the format but not supported yet, build gives
+    /// EncFlagErr
+    Twofish,
+}
+
+impl Cipher {
+    /// The flags of the header for cipher, SHA-2 included
+    pub fn enc_flag(&self) -> u32 {
+        match *self {
+            Cipher::Aes => 3,
+            Cipher::Twofish => 9,
+        }
+    }
+}
+
+#[doc = "
+ReencryptOptions sets what V1Kpdb::reencrypt changes. Fields which are
+None keep their current value.
+"]
+#[derive(Debug, Clone, Copy)]
+pub struct ReencryptOptions {
+    /// Cipher of the content. Twofish gives EncFlagErr as long as it
+    /// isn't supported
+    pub cipher: Option<Cipher>,
+    /// Rounds of the key transformation
+    pub rounds: Option<u32>,
+    /// Use as many rounds as this machine manages in this time instead,
+    /// see Crypter::benchmark_rounds. Ignored if rounds is set
+    pub calibrate: Option<Duration>,
+}
+
+impl ReencryptOptions {
+    pub fn new() -> ReencryptOptions {
+        ReencryptOptions {
+            cipher: None,
+            rounds: None,
+            calibrate: None,
+        }
+    }
+}
+
+// How the number of rounds is chosen
+#[derive(Debug, Clone, Copy)]
+enum Rounds {
+    Fixed(u32),
+    Calibrate(Duration),
+}
+
+#[doc = "
+V1KpdbBuilder creates a new database in memory. Nothing is written until
+save is called on the result:
+
+```ignore
+let mut db = V1KpdbBuilder::new(\"new.kdb\".to_string())
+                 .password(\"secret\".to_string())
+                 .calibrate(Duration::from_secs(1))
+                 .build()
+                 .unwrap();
+db.save(None, None, None).unwrap();
+```
+"]
+pub struct V1KpdbBuilder {
+    path: String,
+    key: Option<CompositeKey>,
+    password: Option<SecureString>,
+    keyfile: Option<SecureString>,
+    cipher: Cipher,
+    rounds: Rounds,
+    group_title: String,
+}
+
+impl V1KpdbBuilder {
+    /// Start a database which will be saved to path
+    pub fn new(path: String) -> V1KpdbBuilder {
+        V1KpdbBuilder {
+            path: path,
+            key: None,
+            password: None,
+            keyfile: None,
+            cipher: Cipher::Aes,
+            rounds: Rounds::Fixed(DEFAULT_ROUNDS),
+            group_title: DEFAULT_GROUP_TITLE.to_string(),
+        }
+    }
+
+    /// Protect the database with password. Like for V1Kpdb::new it
+    /// should be a String on the heap, not a copy of a &str.
+    pub fn password(mut self, password: String) -> V1KpdbBuilder {
+        self.password = Some(SecureString::new(password));
+        self
+    }
+
+    /// Protect the database with the keyfile at path
+    pub fn keyfile(mut self, path: String) -> V1KpdbBuilder {
+        self.keyfile = Some(SecureString::new(path));
+        self
+    }
+
+    /// Use key instead of password and keyfile, e.g. to add a
+    /// challenge-response component
+    pub fn key(mut self, key: CompositeKey) -> V1KpdbBuilder {
+        self.key = Some(key);
+        self
+    }
+
+    /// Encrypt the content with cipher, AES by default
+    pub fn cipher(mut self, cipher: Cipher) -> V1KpdbBuilder {
+        self.cipher = cipher;
+        self
+    }
+
+    /// Use rounds rounds of the key transformation, DEFAULT_ROUNDS by
+    /// default
+    pub fn rounds(mut self, rounds: u32) -> V1KpdbBuilder {
+        self.rounds = Rounds::Fixed(rounds);
+        self
+    }
+
+    /// Use as many rounds of the key transformation as this machine
+    /// manages in target, see Crypter::benchmark_rounds. build takes
+    /// at least target then.
+    pub fn calibrate(mut self, target: Duration) -> V1KpdbBuilder {
+        self.rounds = Rounds::Calibrate(target);
+        self
+    }
+
+    /// Title of the first top-level group, DEFAULT_GROUP_TITLE by
+    /// default. KeePass 1.x has no visible root group and entries need
+    /// a group, so a new database gets this one.
+    pub fn group_title(mut self, title: String) -> V1KpdbBuilder {
+        self.group_title = title;
+        self
+    }
+
+    /// Create the database. PassErr if neither a password, a keyfile
+    /// nor a key was given, RoundsErr for 0 rounds.
+    pub fn build(self) -> Result<V1Kpdb, V1KpdbError> {
+        if self.cipher != Cipher::Aes {
+            return Err(V1KpdbError::EncFlagErr);
+        }
+        let key = match self.key {
+            Some(key) => key,
+            None => CompositeKey::from_credentials(self.password, self.keyfile),
+        };
+        let rounds = match self.rounds {
+            Rounds::Fixed(rounds) => rounds,
+            Rounds::Calibrate(target) => Crypter::benchmark_rounds(target),
+        };
+
+        let mut db = try!(V1Kpdb::with_key(self.path, key));
+        db.header.signature1 = 0x9AA2D903;
+        db.header.signature2 = 0xB54BFB65;
+        db.header.enc_flag = self.cipher.enc_flag();
+        db.header.version = 0x00030002;
+        try!(db.header.set_key_transf_rounds(rounds));
+        try!(db.header.regenerate_seeds());
+        try!(db.create_group(self.group_title, None, None, None));
+        Ok(db)
+    }
+}
@@ -35,3 +35,30 @@ pub fn u32_to_vec_u8(value: u32) -> Vec<u8> {
     ret[3] |= ((value & (0xFF << 24)) >> 24) as u8;
     ret
 }
+
+// Extract the lowercased host part of an URL, e.g.
+// "https://User@Example.com:8080/login" => "example.com"
+pub fn url_host(url: &str) -> Option<String> {
+    let url = url.trim();
+    let rest = match url.find("://") {
+        Some(index) => &url[index + 3..],
+        None => url,
+    };
+    let rest = match rest.find(|c: char| c == '/' || c == '?' || c == '#') {
+        Some(index) => &rest[..index],
+        None => rest,
+    };
+    let rest = match rest.rfind('@') {
+        Some(index) => &rest[index + 1..],
+        None => rest,
+    };
+    let host = match rest.rfind(':') {
+        Some(index) if !rest.ends_with(']') => &rest[..index],
+        _ => rest,
+    };
+    if host.is_empty() {
+        None
+    } else {
+        Some(host.to_lowercase())
+    }
+}
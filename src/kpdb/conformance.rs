@@ -0,0 +1,344 @@
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashSet};
+use std::fs::File;
+use std::io::Read;
+use std::rc::Rc;
+
+use rustc_serialize::json::{Json, ToJson};
+use uuid::Uuid;
+
+use kpdb::crypter::CompositeKey;
+use kpdb::error::KpdbError;
+use kpdb::parser::HeaderLoadParser;
+use kpdb::v1error::V1KpdbError;
+use kpdb::v1group::V1Group;
+use kpdb::v1header::V1Header;
+use kpdb::v1kpdb::V1Kpdb;
+
+// Size of the KeePass 1.x header
+const HEADER_SIZE: usize = 124;
+// Signatures of KeePass 1.x and of KeePass 2.x files and their
+// pre-releases
+const SIGNATURE1: u32 = 0x9AA2D903;
+const SIGNATURE2: u32 = 0xB54BFB65;
+const KDBX_SIGNATURES2: [u32; 2] = [0xB54BFB66, 0xB54BFB67];
+const VERSION: u32 = 0x00030002;
+// Cipher bits of the encryption flags. Bit 1 (SHA-2) is always set.
+const FLAG_AES: u32 = 2;
+const FLAG_ARC4: u32 = 4;
+const FLAG_TWOFISH: u32 = 8;
+// Every group and entry takes at least its 6 byte end marker
+const MIN_RECORD_SIZE: u64 = 6;
+
+/// A deviation of a file from the KeePass 1.x format
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Violation {
+    /// The file is smaller than the header. Holds the file size.
+    TooSmall(usize),
+    /// The file is a KeePass 2.x (KDBX) database, which this library
+    /// doesn't read
+    Kdbx,
+    /// The signatures are no KeePass signatures
+    Signature,
+    /// The version isn't 3.2. Holds the version of the file.
+    Version(u32),
+    /// The encryption flags name no cipher, several ciphers or RC4,
+    /// which KeePass 1.x never used. Holds the flags.
+    EncFlag(u32),
+    /// The key transformation has no rounds
+    NoRounds,
+    /// The encrypted content is empty or not made of whole 16 byte
+    /// blocks. Holds its length.
+    BlockSize(usize),
+    /// The header declares more groups and entries than the content
+    /// can hold
+    Counts,
+    /// The key doesn't open the database
+    WrongKey,
+    /// The content is damaged. offset is the byte offset in the
+    /// decrypted content, if known.
+    Corrupt {
+        offset: Option<usize>,
+        error: V1KpdbError,
+    },
+    /// Several groups have this id
+    DuplicateGroupId(u32),
+    /// Several entries have this UUID
+    DuplicateUuid(Uuid),
+    /// The entry with this UUID refers to a group which doesn't exist or
+    /// isn't linked to it
+    OrphanedEntry(Uuid),
+    /// The level of the group with this id doesn't fit its parent or its
+    /// position in the groups, so the tree would change on saving
+    LevelMismatch(u32),
+    /// The group with this id, 0 for the root group, refers to a child
+    /// or entry which was dropped or doesn't refer back to it
+    DanglingReference(u32),
+}
+
+impl Violation {
+    /// A stable name for reports, e.g. "block_size"
+    pub fn code(&self) -> &'static str {
+        match *self {
+            Violation::TooSmall(_) => "too_small",
+            Violation::Kdbx => "kdbx",
+            Violation::Signature => "signature",
+            Violation::Version(_) => "version",
+            Violation::EncFlag(_) => "enc_flag",
+            Violation::NoRounds => "no_rounds",
+            Violation::BlockSize(_) => "block_size",
+            Violation::Counts => "counts",
+            Violation::WrongKey => "wrong_key",
+            Violation::Corrupt { .. } => "corrupt",
+            Violation::DuplicateGroupId(_) => "duplicate_group_id",
+            Violation::DuplicateUuid(_) => "duplicate_uuid",
+            Violation::OrphanedEntry(_) => "orphaned_entry",
+            Violation::LevelMismatch(_) => "level_mismatch",
+            Violation::DanglingReference(_) => "dangling_reference",
+        }
+    }
+}
+
+impl ToJson for Violation {
+    fn to_json(&self) -> Json {
+        let mut object = BTreeMap::new();
+        object.insert("code".to_string(), self.code().to_json());
+        match *self {
+            Violation::TooSmall(size) |
+            Violation::BlockSize(size) => {
+                object.insert("size".to_string(), size.to_json());
+            }
+            Violation::Version(value) |
+            Violation::EncFlag(value) => {
+                object.insert("value".to_string(), format!("0x{:08X}", value).to_json());
+            }
+            Violation::Corrupt { offset, error } => {
+                object.insert("offset".to_string(), offset.to_json());
+                object.insert("error".to_string(), format!("{:?}", error).to_json());
+            }
+            Violation::DuplicateGroupId(id) |
+            Violation::LevelMismatch(id) |
+            Violation::DanglingReference(id) => {
+                object.insert("group".to_string(), id.to_json());
+            }
+            Violation::DuplicateUuid(uuid) |
+            Violation::OrphanedEntry(uuid) => {
+                object.insert("entry".to_string(), uuid.to_simple_string().to_json());
+            }
+            _ => {}
+        }
+        Json::Object(object)
+    }
+}
+
+#[doc = "
+ConformanceReport is the result of check_file and check_file_with_key,
+e.g. to find out what a third-party tool got wrong. to_json gives a
+machine-readable form.
+
+inner_checked is false if no key was given or the outer structure is
+already broken, the content wasn't looked at then.
+"]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConformanceReport {
+    pub inner_checked: bool,
+    pub violations: Vec<Violation>,
+}
+
+impl ConformanceReport {
+    /// True if no violation was found
+    pub fn is_conformant(&self) -> bool {
+        self.violations.is_empty()
+    }
+}
+
+impl ToJson for ConformanceReport {
+    fn to_json(&self) -> Json {
+        let mut object = BTreeMap::new();
+        object.insert("format".to_string(), "KeePass 1.x".to_json());
+        object.insert("conformant".to_string(), self.is_conformant().to_json());
+        object.insert("inner_checked".to_string(), self.inner_checked.to_json());
+        object.insert("violations".to_string(), self.violations.to_json());
+        Json::Object(object)
+    }
+}
+
+/// Check the outer structure of the file at path without a key: size,
+/// signatures, version, encryption flags, key transformation rounds and
+/// the framing of the encrypted content. Unlike KDBX, KeePass 1.x has a
+/// fixed header, the fields of groups and entries are part of the
+/// encrypted content.
+///
+/// Only a file which can't be read is an error.
+pub fn check_file(path: &str) -> Result<ConformanceReport, V1KpdbError> {
+    let violations = try!(check_outer(path));
+    Ok(ConformanceReport {
+        inner_checked: false,
+        violations: violations,
+    })
+}
+
+/// Like check_file but decrypts the content with key if the outer
+/// structure is fine, parses all groups and entries and checks them with
+/// check_tree.
+///
+/// Errors of the key, e.g. a failing key provider, are returned as such.
+pub fn check_file_with_key(path: &str, key: CompositeKey) -> Result<ConformanceReport, V1KpdbError> {
+    let mut violations = try!(check_outer(path));
+    if !violations.is_empty() {
+        return Ok(ConformanceReport {
+            inner_checked: false,
+            violations: violations,
+        });
+    }
+
+    let mut db = try!(V1Kpdb::with_key(path.to_string(), key));
+    match db.load_detailed() {
+        Ok(()) => violations.extend(check_tree(&db)),
+        Err(KpdbError::WrongKey) => violations.push(Violation::WrongKey),
+        Err(KpdbError::Corrupt { offset, error }) => {
+            violations.push(Violation::Corrupt {
+                offset: offset,
+                error: error,
+            })
+        }
+        Err(e) => return Err(e.coarse()),
+    }
+    Ok(ConformanceReport {
+        inner_checked: true,
+        violations: violations,
+    })
+}
+
+fn check_outer(path: &str) -> Result<Vec<Violation>, V1KpdbError> {
+    let mut file = try!(File::open(path).map_err(|_| V1KpdbError::FileErr));
+    let len = try!(file.metadata().map_err(|_| V1KpdbError::ReadErr)).len() as usize;
+    let mut header = vec![0u8; HEADER_SIZE];
+    let read = try!(file.read(&mut header).map_err(|_| V1KpdbError::ReadErr));
+    // Even a short file tells KDBX by its signatures
+    if read >= 8 && is_kdbx(&header) {
+        return Ok(vec![Violation::Kdbx]);
+    }
+    if len < HEADER_SIZE {
+        return Ok(vec![Violation::TooSmall(len)]);
+    }
+    if read < HEADER_SIZE {
+        try!(file.read_exact(&mut header[read..]).map_err(|_| V1KpdbError::ReadErr));
+    }
+    let header = try!(HeaderLoadParser::new(header).parse_header());
+    Ok(check_header(&header, len - HEADER_SIZE))
+}
+
+fn is_kdbx(header: &[u8]) -> bool {
+    let signature = |i: usize| {
+        header[i] as u32 | (header[i + 1] as u32) << 8 | (header[i + 2] as u32) << 16 |
+        (header[i + 3] as u32) << 24
+    };
+    signature(0) == SIGNATURE1 && KDBX_SIGNATURES2.contains(&signature(4))
+}
+
+fn check_header(header: &V1Header, content_len: usize) -> Vec<Violation> {
+    let mut violations = vec![];
+    if header.signature1 != SIGNATURE1 || header.signature2 != SIGNATURE2 {
+        // Nothing else means anything then
+        violations.push(Violation::Signature);
+        return violations;
+    }
+    if header.version != VERSION {
+        violations.push(Violation::Version(header.version));
+    }
+    // Twofish is valid KeePass 1.x even though this crate can't read it
+    let ciphers = header.enc_flag & (FLAG_AES | FLAG_ARC4 | FLAG_TWOFISH);
+    if ciphers != FLAG_AES && ciphers != FLAG_TWOFISH {
+        violations.push(Violation::EncFlag(header.enc_flag));
+    }
+    if header.key_transf_rounds == 0 {
+        violations.push(Violation::NoRounds);
+    }
+    if content_len == 0 || content_len % 16 != 0 {
+        violations.push(Violation::BlockSize(content_len));
+    }
+    let needed = (header.num_groups as u64 + header.num_entries as u64) * MIN_RECORD_SIZE;
+    if needed > content_len as u64 {
+        violations.push(Violation::Counts);
+    }
+    violations
+}
+
+/// Check the invariants of the group tree of db which saving and loading
+/// rely on: unique group ids and entry UUIDs, entries linked to existing
+/// groups, levels which fit the parents and the order of the groups and
+/// children and entries which refer back to their group. See
+/// V1Kpdb::validate.
+pub fn check_tree(db: &V1Kpdb) -> Vec<Violation> {
+    let mut violations = vec![];
+    let mut ids = HashSet::new();
+    for group in db.groups.iter() {
+        let id = group.borrow().id;
+        if !ids.insert(id) {
+            violations.push(Violation::DuplicateGroupId(id));
+        }
+    }
+    for (i, group) in db.groups.iter().enumerate() {
+        let g = group.borrow();
+        // The parser makes the closest preceding group with a lower level
+        // the parent
+        let found = db.groups[..i].iter().rev().find(|p| p.borrow().level < g.level);
+        let fits = match (&g.parent, found) {
+            (&Some(ref parent), None) => g.level == 0 && Rc::ptr_eq(parent, &db.root_group),
+            (&Some(ref parent), Some(found)) => {
+                g.level > 0 && Rc::ptr_eq(parent, found) && parent.borrow().level + 1 == g.level
+            }
+            (&None, _) => false,
+        };
+        if !fits {
+            violations.push(Violation::LevelMismatch(g.id));
+        }
+    }
+    for group in Some(&db.root_group).into_iter().chain(db.groups.iter()) {
+        if has_dangling_reference(group) {
+            violations.push(Violation::DanglingReference(group.borrow().id));
+        }
+    }
+    let mut uuids = HashSet::new();
+    for entry in db.entries.iter() {
+        let entry = entry.borrow();
+        if !uuids.insert(entry.uuid) {
+            violations.push(Violation::DuplicateUuid(entry.uuid));
+        }
+        let linked = match entry.group {
+            Some(ref group) => {
+                group.borrow().id == entry.group_id &&
+                db.groups.iter().any(|g| Rc::ptr_eq(g, group))
+            }
+            None => false,
+        };
+        if !ids.contains(&entry.group_id) || !linked {
+            violations.push(Violation::OrphanedEntry(entry.uuid));
+        }
+    }
+    violations
+}
+
+// Check if one of the children or entries of group is gone or has
+// another parent or group
+fn has_dangling_reference(group: &Rc<RefCell<V1Group>>) -> bool {
+    let g = group.borrow();
+    let child_dangles = g.children.iter().any(|child| {
+        match child.upgrade() {
+            Some(child) => {
+                !child.borrow().parent.as_ref().map_or(false, |p| Rc::ptr_eq(p, group))
+            }
+            None => true,
+        }
+    });
+    let entry_dangles = g.entries.iter().any(|entry| {
+        match entry.upgrade() {
+            Some(entry) => {
+                !entry.borrow().group.as_ref().map_or(false, |g| Rc::ptr_eq(g, group))
+            }
+            None => true,
+        }
+    });
+    child_dangles || entry_dangles
+}
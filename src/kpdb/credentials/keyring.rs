@@ -0,0 +1,62 @@
+//! The keychain of the OS as a CredentialStore
+//!
+//! Uses the Secret Service on Linux and the BSDs, the Keychain on macOS
+//! and the Credential Manager on Windows, through the keyring crate.
+
+use keyring::{Entry, Error};
+
+use kpdb::credentials::{CredentialStore, SERVICE};
+use kpdb::v1error::V1KpdbError;
+use super::super::super::sec_str::SecureBytes;
+
+#[doc = "
+OsKeyring stores keys in the keychain of the OS under the service
+credentials::SERVICE. The keychain may ask the user before it hands out
+a key, so don't call it from a thread which mustn't block.
+"]
+pub struct OsKeyring {
+    target: Option<String>,
+}
+
+impl OsKeyring {
+    /// Use the default keychain, e.g. the login collection of the Secret
+    /// Service, which lasts across logins
+    pub fn new() -> OsKeyring {
+        OsKeyring { target: None }
+    }
+
+    /// Use the keychain named target. With the Secret Service "session"
+    /// is the collection which is cleared on logout.
+    pub fn with_target(target: &str) -> OsKeyring {
+        OsKeyring { target: Some(target.to_string()) }
+    }
+
+    fn entry(&self, id: &str) -> Result<Entry, V1KpdbError> {
+        let entry = match self.target {
+            Some(ref target) => Entry::new_with_target(target, SERVICE, id),
+            None => Entry::new(SERVICE, id),
+        };
+        entry.map_err(|_| V1KpdbError::KeyringErr)
+    }
+}
+
+impl CredentialStore for OsKeyring {
+    fn store(&mut self, id: &str, secret: &[u8]) -> Result<(), V1KpdbError> {
+        try!(self.entry(id)).set_secret(secret).map_err(|_| V1KpdbError::KeyringErr)
+    }
+
+    fn retrieve(&mut self, id: &str) -> Result<Option<SecureBytes>, V1KpdbError> {
+        match try!(self.entry(id)).get_secret() {
+            Ok(secret) => Ok(Some(SecureBytes::new(secret))),
+            Err(Error::NoEntry) => Ok(None),
+            Err(_) => Err(V1KpdbError::KeyringErr),
+        }
+    }
+
+    fn remove(&mut self, id: &str) -> Result<(), V1KpdbError> {
+        match try!(self.entry(id)).delete_credential() {
+            Ok(()) | Err(Error::NoEntry) => Ok(()),
+            Err(_) => Err(V1KpdbError::KeyringErr),
+        }
+    }
+}
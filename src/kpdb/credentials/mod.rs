@@ -0,0 +1,123 @@
+//! Caching database keys in a credential store
+//!
+//! KeyCache keeps the key of a database in a CredentialStore, so an
+//! application can offer to remember a database, e.g. until logout,
+//! without rolling its own cache. The keychain of the OS is such a
+//! store, see the keyring module behind the keyring feature.
+//!
+//! Challenge-response components are never cached, their responses
+//! change on every save. They have to be added to a recalled key again.
+
+use std::fs;
+
+use kpdb::crypter::CompositeKey;
+use kpdb::v1error::V1KpdbError;
+use kpdb::v1kpdb::V1Kpdb;
+use super::super::sec_str::SecureBytes;
+
+#[cfg(feature = "keyring")]
+pub mod keyring;
+
+/// Service name under which KeyCache stores keys
+pub const SERVICE: &'static str = "rust-keepass";
+
+/// What KeyCache stores of a key
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CachePolicy {
+    /// The password and the keyfile path or content as they were
+    /// given. A keyfile path has to stay valid.
+    CompositeKey,
+    /// The hash of password and keyfile, as the key transformation
+    /// gets it. The password, which may be used elsewhere, never leaves
+    /// the process and the keyfile isn't needed again. The hash opens
+    /// the database as well as the password does though.
+    MasterKey,
+}
+
+#[doc = "
+Implement this to keep cached keys somewhere, e.g. in a keychain. id
+identifies the database, the secret is opaque binary data.
+"]
+pub trait CredentialStore {
+    /// Store secret under id, replacing the previous one
+    fn store(&mut self, id: &str, secret: &[u8]) -> Result<(), V1KpdbError>;
+
+    /// The secret stored under id, None if there's none
+    fn retrieve(&mut self, id: &str) -> Result<Option<SecureBytes>, V1KpdbError>;
+
+    /// Remove the secret stored under id. Removing a missing one isn't
+    /// an error.
+    fn remove(&mut self, id: &str) -> Result<(), V1KpdbError>;
+}
+
+#[doc = "
+KeyCache remembers the keys of databases in a CredentialStore. Keys are
+stored under the canonical path of the database, so every path of the
+same file finds the key.
+"]
+pub struct KeyCache<S: CredentialStore> {
+    store: S,
+    /// What remember stores, MasterKey by default
+    pub policy: CachePolicy,
+}
+
+impl<S: CredentialStore> KeyCache<S> {
+    pub fn new(store: S) -> KeyCache<S> {
+        KeyCache {
+            store: store,
+            policy: CachePolicy::MasterKey,
+        }
+    }
+
+    /// Store the key of db, e.g. after it was loaded successfully
+    pub fn remember(&mut self, db: &mut V1Kpdb) -> Result<(), V1KpdbError> {
+        let cache = try!(db.cache_key(self.policy == CachePolicy::MasterKey));
+        let id = KeyCache::<S>::id(&db.path);
+        self.store.store(&id, cache.bytes())
+    }
+
+    /// The key stored for the database at path, None if there's none.
+    /// ConvertErr if the stored secret isn't a key.
+    pub fn recall(&mut self, path: &str) -> Result<Option<CompositeKey>, V1KpdbError> {
+        match try!(self.store.retrieve(&KeyCache::<S>::id(path))) {
+            Some(cache) => CompositeKey::from_cache(cache.bytes()).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// Remove the key stored for the database at path
+    pub fn forget(&mut self, path: &str) -> Result<(), V1KpdbError> {
+        self.store.remove(&KeyCache::<S>::id(path))
+    }
+
+    /// Load the database at path with its stored key. None if there's
+    /// no key or it doesn't open the database any more, e.g. because
+    /// the password was changed. Such a key is forgotten.
+    pub fn open(&mut self, path: &str) -> Result<Option<V1Kpdb>, V1KpdbError> {
+        let key = match try!(self.recall(path)) {
+            Some(key) => key,
+            None => return Ok(None),
+        };
+        let mut db = try!(V1Kpdb::with_key(path.to_string(), key));
+        match db.load_detailed() {
+            Ok(()) => Ok(Some(db)),
+            Err(ref e) if e.is_wrong_key() => {
+                try!(self.forget(path));
+                Ok(None)
+            }
+            Err(e) => Err(e.coarse()),
+        }
+    }
+
+    /// The store, e.g. to clear it
+    pub fn store(&mut self) -> &mut S {
+        &mut self.store
+    }
+
+    fn id(path: &str) -> String {
+        match fs::canonicalize(path) {
+            Ok(path) => path.to_string_lossy().into_owned(),
+            Err(_) => path.to_string(),
+        }
+    }
+}
@@ -1,21 +1,441 @@
-use libc::{c_void, size_t};
-use libc::funcs::posix88::mman;
-use std::intrinsics;
 use std::io::{Seek, SeekFrom, Read, Write};
 use std::fs::File;
+use std::cmp;
+use std::mem;
+use std::ptr;
+use std::str;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
 
 use openssl::crypto::hash::{Hasher, Type};
 use openssl::crypto::symm;
+use rand;
+use rustc_serialize::base64::FromBase64;
 use rustc_serialize::hex::FromHex;
 
+use super::policy::KeyFactor;
+use super::stream::DecryptReader;
 use super::v1header::V1Header;
 use super::v1error::V1KpdbError;
-use super::super::sec_str::SecureString;
+use super::super::mem_protect::{self, MemLockPolicy, MemLockScope};
+use super::super::sec_str::{SecureBytes, SecureString};
+
+/// Compare a and b in constant time, i.e. the time doesn't depend on
+/// where they differ. Use this for hashes and keys. Only the lengths are
+/// compared the usual way as they aren't secret.
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    let mut diff = 0u8;
+    for (x, y) in a.iter().zip(b.iter()) {
+        diff |= x ^ y;
+    }
+    // Keep the compiler from cutting the loop short
+    unsafe { ptr::read_volatile(&diff) == 0 }
+}
+
+/// Implement this to add another factor (e.g. a hardware token) to the
+/// master key. The key is hashed together with the password and/or
+/// keyfile key. challenge is the final random seed of the header, which
+/// changes on every save.
+pub trait KeyProvider {
+    fn get_key(&mut self, challenge: &[u8]) -> Result<Vec<u8>, V1KpdbError>;
+}
+
+/// One part of a CompositeKey
+pub enum KeyComponent {
+    /// The database password
+    Password(SecureString),
+    /// The database password as raw bytes, for passwords which aren't
+    /// valid UTF-8, e.g. Latin-1 passwords set by older clients. The bytes
+    /// are hashed as they are.
+    PasswordBytes(SecureBytes),
+    /// Filepath of a keyfile
+    Keyfile(SecureString),
+    /// Content of a keyfile, e.g. read by fdkey::keyfile_from_fd
+    KeyfileData(SecureBytes),
+    /// Challenge-response, e.g. a YubiKey in HMAC-SHA1 mode or a
+    /// Fido2KeyProvider
+    ChallengeResponse(Box<KeyProvider>),
+}
+
+#[doc = "
+CompositeKey holds everything the master key is derived from. At least
+a password or a keyfile is needed. Both are combined as KeePass 1.x does,
+so databases stay readable by other clients as long as no challenge-
+response component is used. The responses are hashed into the key in the
+order the components were added.
+"]
+pub struct CompositeKey {
+    password: Option<Password>,
+    keyfile: Option<Keyfile>,
+    providers: Vec<Box<KeyProvider>>,
+    // The hash of password and keyfile restored by from_cache, it
+    // replaces both
+    cached: Option<(SecureBytes, Vec<KeyFactor>)>,
+}
+
+impl CompositeKey {
+    /// Create an empty key, use add to fill it
+    pub fn new() -> CompositeKey {
+        CompositeKey {
+            password: None,
+            keyfile: None,
+            providers: vec![],
+            cached: None,
+        }
+    }
+
+    /// Create a key from the classic password and keyfile pair
+    pub fn from_credentials(password: Option<SecureString>,
+                            keyfile: Option<SecureString>)
+                            -> CompositeKey {
+        CompositeKey {
+            password: password.map(Password::Text),
+            keyfile: keyfile.map(Keyfile::Path),
+            providers: vec![],
+            cached: None,
+        }
+    }
+
+    /// Add a component. A database has only one password and one
+    /// keyfile, so these replace the previous one.
+    pub fn add(&mut self, component: KeyComponent) {
+        match component {
+            KeyComponent::ChallengeResponse(_) => {}
+            _ => self.cached = None,
+        }
+        match component {
+            KeyComponent::Password(p) => self.password = Some(Password::Text(p)),
+            KeyComponent::PasswordBytes(p) => self.password = Some(Password::Bytes(p)),
+            KeyComponent::Keyfile(k) => self.keyfile = Some(Keyfile::Path(k)),
+            KeyComponent::KeyfileData(d) => self.keyfile = Some(Keyfile::Data(d)),
+            KeyComponent::ChallengeResponse(p) => self.providers.push(p),
+        }
+    }
+
+    /// True if the key holds a password or a keyfile
+    pub fn is_valid(&self) -> bool {
+        self.password.is_some() || self.keyfile.is_some() || self.cached.is_some()
+    }
+
+    /// The factors the key is made of, in the order password, keyfile
+    /// and the challenge-response providers
+    pub fn factors(&self) -> Vec<KeyFactor> {
+        let mut factors = vec![];
+        if let Some((_, ref cached)) = self.cached {
+            factors.extend(cached.iter().cloned());
+        }
+        if self.password.is_some() {
+            factors.push(KeyFactor::Password);
+        }
+        if self.keyfile.is_some() {
+            factors.push(KeyFactor::Keyfile);
+        }
+        for _ in self.providers.iter() {
+            factors.push(KeyFactor::ChallengeResponse);
+        }
+        factors
+    }
+
+    // The key in the form kpdb::credentials stores it: the password and
+    // the keyfile, or the hash of both if master_key is set or the key
+    // was restored from a hash. The challenge-response components are
+    // left out, their responses change on every save.
+    //
+    // Sensitive data in this function:
+    // * masterkey (locked: base_key)
+    // * cache (locked: SecureBytes)
+    //
+    // At the end of this function:
+    // * masterkey is zeroed out
+    // * cache has moved out of function
+    #[doc(hidden)]
+    pub fn to_cache(&mut self, master_key: bool) -> Result<SecureBytes, V1KpdbError> {
+        if master_key || self.cached.is_some() {
+            let factors = self.factors();
+            let tag = match (factors.contains(&KeyFactor::Password),
+                             factors.contains(&KeyFactor::Keyfile)) {
+                (true, false) => CACHE_MASTER_KEY_PASSWORD,
+                (false, true) => CACHE_MASTER_KEY_KEYFILE,
+                _ => CACHE_MASTER_KEY_BOTH,
+            };
+            let masterkey = try!(self.base_key());
+            let cache = CompositeKey::cache_records(&[(tag, &masterkey)]);
+            unsafe {
+                mem_protect::zero(&masterkey);
+            }
+            mem_protect::unlock(&masterkey);
+            return Ok(cache);
+        }
+
+        let mut password = None;
+        let mut keyfile = None;
+        let cache = {
+            let mut records = vec![];
+            match self.password {
+                Some(Password::Text(ref mut text)) => {
+                    password = Some(text.unlocked());
+                    records.push((CACHE_PASSWORD, password.as_ref().unwrap().as_bytes()));
+                }
+                Some(Password::Bytes(ref bytes)) => {
+                    records.push((CACHE_PASSWORD_BYTES, bytes.bytes()))
+                }
+                None => {}
+            }
+            match self.keyfile {
+                Some(Keyfile::Path(ref mut path)) => {
+                    keyfile = Some(path.unlocked());
+                    records.push((CACHE_KEYFILE, keyfile.as_ref().unwrap().as_bytes()));
+                }
+                Some(Keyfile::Data(ref data)) => records.push((CACHE_KEYFILE_DATA, data.bytes())),
+                None => {}
+            }
+            if records.is_empty() {
+                return Err(V1KpdbError::PassErr);
+            }
+            CompositeKey::cache_records(&records)
+        };
+        // Deletes the plain text again
+        drop(password);
+        drop(keyfile);
+        Ok(cache)
+    }
+
+    /// Restore a key stored by to_cache
+    #[doc(hidden)]
+    pub fn from_cache(mut cache: &[u8]) -> Result<CompositeKey, V1KpdbError> {
+        let mut key = CompositeKey::new();
+        while cache.len() > 0 {
+            if cache.len() < 5 {
+                return Err(V1KpdbError::ConvertErr);
+            }
+            let len = cache[1] as usize | (cache[2] as usize) << 8 | (cache[3] as usize) << 16 |
+                      (cache[4] as usize) << 24;
+            if cache.len() - 5 < len {
+                return Err(V1KpdbError::ConvertErr);
+            }
+            let data = cache[5..5 + len].to_vec();
+            match cache[0] {
+                CACHE_PASSWORD | CACHE_KEYFILE => {
+                    let text = try!(String::from_utf8(data).map_err(|e| {
+                        unsafe {
+                            mem_protect::zero(e.as_bytes());
+                        }
+                        V1KpdbError::ConvertErr
+                    }));
+                    key.add(match cache[0] {
+                        CACHE_PASSWORD => KeyComponent::Password(SecureString::new(text)),
+                        _ => KeyComponent::Keyfile(SecureString::new(text)),
+                    });
+                }
+                CACHE_PASSWORD_BYTES => {
+                    key.add(KeyComponent::PasswordBytes(SecureBytes::new(data)))
+                }
+                CACHE_KEYFILE_DATA => key.add(KeyComponent::KeyfileData(SecureBytes::new(data))),
+                tag @ CACHE_MASTER_KEY_PASSWORD...CACHE_MASTER_KEY_BOTH => {
+                    let factors = match tag {
+                        CACHE_MASTER_KEY_PASSWORD => vec![KeyFactor::Password],
+                        CACHE_MASTER_KEY_KEYFILE => vec![KeyFactor::Keyfile],
+                        _ => vec![KeyFactor::Password, KeyFactor::Keyfile],
+                    };
+                    key.password = None;
+                    key.keyfile = None;
+                    key.cached = Some((SecureBytes::new(data), factors));
+                }
+                _ => {
+                    unsafe {
+                        mem_protect::zero(&data);
+                    }
+                    return Err(V1KpdbError::ConvertErr);
+                }
+            }
+            cache = &cache[5 + len..];
+        }
+        if !key.is_valid() {
+            return Err(V1KpdbError::PassErr);
+        }
+        Ok(key)
+    }
+
+    // Encode records as tag, little-endian u32 length and data. The
+    // buffer is allocated at its final size, so it never leaves a copy
+    // behind by growing.
+    fn cache_records(records: &[(u8, &[u8])]) -> SecureBytes {
+        let len: usize = records.iter().map(|&(_, data)| 5 + data.len()).sum();
+        let mut cache = Vec::with_capacity(len);
+        for &(tag, data) in records {
+            let data_len = data.len() as u32;
+            cache.push(tag);
+            cache.extend_from_slice(&[data_len as u8,
+                                      (data_len >> 8) as u8,
+                                      (data_len >> 16) as u8,
+                                      (data_len >> 24) as u8]);
+            cache.extend_from_slice(data);
+        }
+        SecureBytes::new(cache)
+    }
+
+    // The hash of password and keyfile, the master key without the
+    // challenge-response components
+    //
+    // Sensitive data in this function:
+    // * passwordkey
+    // * keyfilekey
+    // * masterkey_tmp
+    //
+    // At the end of this function:
+    // * passwordkey and keyfilekey are zeroed out
+    // * the key is locked and moved out of function
+    fn base_key(&mut self) -> Result<Vec<u8>, V1KpdbError> {
+        if let Some((ref cached, _)) = self.cached {
+            let masterkey = cached.bytes().to_vec();
+            mem_protect::lock(&masterkey, "masterkey");
+            return Ok(masterkey);
+        }
+        match (&mut self.password, &mut self.keyfile) {
+            // Only password provided
+            (&mut Some(ref mut p), &mut None) => Crypter::get_passwordsourcekey(p),
+            // Only keyfile provided
+            (&mut None, &mut Some(ref mut k)) => Crypter::get_keyfilesourcekey(k),
+            // Both provided
+            (&mut Some(ref mut p), &mut Some(ref mut k)) => {
+                // Get hashed keys...
+                let passwordkey = try!(Crypter::get_passwordsourcekey(p));
+
+                let keyfilekey = try!(Crypter::get_keyfilesourcekey(k));
+
+                // ...and hash them together
+                let mut hasher = Hasher::new(Type::SHA256);
+                try!(hasher.write_all(&passwordkey)
+                           .map_err(|_| V1KpdbError::DecryptErr));
+                try!(hasher.write_all(&keyfilekey)
+                           .map_err(|_| V1KpdbError::DecryptErr));
+
+                let masterkey_tmp = hasher.finish();
+                // Zero out unneeded keys and lock masterkey
+                unsafe {
+                    mem_protect::zero(&passwordkey);
+                    mem_protect::zero(&keyfilekey);
+                    mem_protect::unlock(&passwordkey);
+                    mem_protect::unlock(&keyfilekey);
+                    mem_protect::lock(&masterkey_tmp, "masterkey_tmp");
+                }
+                Ok(masterkey_tmp)
+            }
+            (&mut None, &mut None) => Err(V1KpdbError::PassErr),
+        }
+    }
+
+    // Re-encrypt the password and keyfile path in memory, see
+    // SecureString::rekey
+    fn rekey(&mut self) {
+        if let Some(Password::Text(ref mut password)) = self.password {
+            password.rekey();
+        }
+        if let Some(Keyfile::Path(ref mut path)) = self.keyfile {
+            path.rekey();
+        }
+    }
+}
+
+// Record tags of CompositeKey::to_cache. The master key tags tell
+// which factors it was derived from.
+const CACHE_PASSWORD: u8 = 1;
+const CACHE_PASSWORD_BYTES: u8 = 2;
+const CACHE_KEYFILE: u8 = 3;
+const CACHE_KEYFILE_DATA: u8 = 4;
+const CACHE_MASTER_KEY_PASSWORD: u8 = 5;
+const CACHE_MASTER_KEY_KEYFILE: u8 = 6;
+const CACHE_MASTER_KEY_BOTH: u8 = 7;
+
+// The password as text or as raw bytes
+enum Password {
+    Text(SecureString),
+    Bytes(SecureBytes),
+}
+
+// Where the keyfile key comes from
+enum Keyfile {
+    Path(SecureString),
+    Data(SecureBytes),
+}
+
+/// Aborts a running key transformation, see V1Kpdb::set_cancel_token.
+/// Clones share the same state, so keep one and hand another to the
+/// database.
+#[derive(Clone)]
+pub struct CancelToken {
+    cancelled: Arc<AtomicBool>,
+}
+
+impl CancelToken {
+    pub fn new() -> CancelToken {
+        CancelToken { cancelled: Arc::new(AtomicBool::new(false)) }
+    }
+
+    /// Request cancellation. Can be called from any thread
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::SeqCst)
+    }
+}
+
+#[doc = "
+KeyJob is the expensive part of deriving the key of a database, the key
+transformation, split off so it can run on another thread, e.g. by
+V1Kpdb::load_async. Password, keyfile and key providers are read when
+it's created, on the thread of the database. Progress isn't reported,
+a CancelToken of the database still aborts it.
+"]
+pub struct KeyJob {
+    // Locked, empty once it moved to transform_key
+    masterkey: Vec<u8>,
+    header: V1Header,
+    cancel_token: Option<CancelToken>,
+}
+
+impl KeyJob {
+    /// Run the key transformation and return the final key, which is
+    /// locked
+    pub fn finalkey(mut self) -> Result<Vec<u8>, V1KpdbError> {
+        let masterkey = mem::replace(&mut self.masterkey, vec![]);
+        Crypter::transform_key(masterkey, &self.header, &mut None, &self.cancel_token)
+    }
+}
+
+impl Drop for KeyJob {
+    fn drop(&mut self) {
+        unsafe {
+            mem_protect::zero(&self.masterkey);
+        }
+        mem_protect::unlock(&self.masterkey);
+    }
+}
+
+// Larger files are never treated as XML keyfiles
+const XML_KEYFILE_MAX_SIZE: u64 = 16384;
+
+// Rounds between two progress reports and looks at the CancelToken
+const PROGRESS_INTERVAL: u32 = 10000;
+
+// Chunk size of the in-place en- and decryption of the low-memory
+// profile, the only part of the profile so far, see Cargo.toml, and of
+// decrypt_locked
+const CHUNK_SIZE: usize = 64 * 1024;
 
 // implements a crypter to de- and encrypt a KeePass DB
 pub struct Crypter {
-    password: Option<SecureString>,
-    keyfile: Option<SecureString>,
+    key: CompositeKey,
+    progress: Option<Box<FnMut(u32, u32)>>,
+    cancel_token: Option<CancelToken>,
+    mem_lock_policy: Option<MemLockPolicy>,
 }
 
 // Sensitive data in Crypter overall
@@ -39,30 +459,262 @@ impl Crypter {
     pub fn new(password: Option<SecureString>,
                keyfile: Option<SecureString>)
                -> Crypter {
+        Crypter::with_key(CompositeKey::from_credentials(password, keyfile))
+    }
+
+    pub fn with_key(key: CompositeKey) -> Crypter {
         Crypter {
-            password: password,
-            keyfile: keyfile,
+            key: key,
+            progress: None,
+            cancel_token: None,
+            mem_lock_policy: None,
         }
     }
 
+    // Replace the whole key
+    pub fn set_key(&mut self, key: CompositeKey) {
+        self.key = key;
+    }
+
+    // Replace the whole key and return the old one
+    pub fn replace_key(&mut self, key: CompositeKey) -> CompositeKey {
+        mem::replace(&mut self.key, key)
+    }
+
+    // Set or remove the callback which gets the done and total
+    // rounds of the key transformation
+    pub fn set_progress(&mut self, progress: Option<Box<FnMut(u32, u32)>>) {
+        self.progress = progress;
+    }
+
+    // Set or remove the token checked during the key transformation
+    pub fn set_cancel_token(&mut self, cancel_token: Option<CancelToken>) {
+        self.cancel_token = cancel_token;
+    }
+
+    // Set the MemLockPolicy of the en- and decryption, which also raises
+    // the limit of locked memory for it, see MemLockScope. Under Require
+    // they fail with MemLockErr if a buffer couldn't be locked. None
+    // keeps the policy of the thread, e.g. the one of V1Kpdb.
+    pub fn set_mem_lock_policy(&mut self, mem_lock_policy: Option<MemLockPolicy>) {
+        self.mem_lock_policy = mem_lock_policy;
+    }
+
+    // Replace the challenge-response components by key_provider
+    pub fn set_key_provider(&mut self, key_provider: Option<Box<KeyProvider>>) {
+        self.key.providers.clear();
+        if let Some(provider) = key_provider {
+            self.key.providers.push(provider);
+        }
+    }
+
+    // The factors of the current key
+    pub fn key_factors(&self) -> Vec<KeyFactor> {
+        self.key.factors()
+    }
+
+    // The current key as kpdb::credentials stores it, see
+    // CompositeKey::to_cache
+    pub fn cache_key(&mut self, master_key: bool) -> Result<SecureBytes, V1KpdbError> {
+        self.key.to_cache(master_key)
+    }
+
+    // Re-encrypt the credentials held in memory with fresh keys
+    pub fn rekey(&mut self) {
+        self.key.rekey();
+    }
+
+    // Replace password and keyfile. The old SecureStrings are dropped
+    // and therefore zeroed out
+    pub fn set_credentials(&mut self,
+                           password: Option<SecureString>,
+                           keyfile: Option<SecureString>) {
+        self.key.cached = None;
+        self.key.password = password.map(Password::Text);
+        self.key.keyfile = keyfile.map(Keyfile::Path);
+    }
+
     // Sensitive data in this function:
     // * finalkey (locked: transform_key)
     // * decrypted_database (locked: decrypt_raw)
     //
     // At the end of this function:
-    // * decrypted database moved out of function
+    // * decrypted database moved out of function or is zeroed out if
+    //   the checks fail
     // * finalkey has moved to decrypt_raw
     //
     // decrypted database is locked through decrypt_raw
     pub fn decrypt_database(&mut self, header: &V1Header, encrypted_database: Vec<u8>) -> Result<Vec<u8>, V1KpdbError> {
+        let budget = encrypted_database.len();
+        self.with_mem_lock_policy(budget, |c| c.decrypt_checked(header, encrypted_database), |d| {
+            unsafe {
+                mem_protect::zero(d);
+                mem_protect::unlock(d);
+            }
+        })
+    }
+
+    fn decrypt_checked(&mut self,
+                       header: &V1Header,
+                       encrypted_database: Vec<u8>)
+                       -> Result<Vec<u8>, V1KpdbError> {
         let finalkey = try!(self.get_finalkey(header));
-        let decrypted_database = Crypter::decrypt_raw(header, encrypted_database, finalkey);
-        try!(Crypter::check_decryption_success(header, &decrypted_database));
-        try!(Crypter::check_content_hash(header, &decrypted_database));
+        let decrypted_database = try!(Crypter::decrypt_raw(header, encrypted_database, finalkey));
+        let check = Crypter::check_decryption_success(header, &decrypted_database)
+                        .and_then(|_| Crypter::check_content_hash(header, &decrypted_database));
+        log_trace!("decrypted {} bytes, checks: {:?}", decrypted_database.len(), check);
+        if let Err(e) = check {
+            // Don't leave the (possibly partially correct) content behind
+            unsafe {
+                mem_protect::zero(&decrypted_database);
+                mem_protect::unlock(&decrypted_database);
+            }
+            return Err(e);
+        }
 
         Ok(decrypted_database)
     }
 
+    // Like decrypt_database but for damaged files: an incomplete last
+    // block, a broken padding and a wrong content hash aren't errors.
+    // Returns the content and whether it passed all checks. If it didn't,
+    // the content may be garbage, e.g. because the key is wrong.
+    //
+    // Sensitive data in this function:
+    // * finalkey (locked: transform_key)
+    // * decrypted_database (locked: decrypt_blocks)
+    //
+    // At the end of this function:
+    // * decrypted database moved out of function
+    // * finalkey has moved to decrypt_blocks
+    pub fn decrypt_damaged_database(&mut self,
+                                    header: &V1Header,
+                                    encrypted_database: Vec<u8>)
+                                    -> Result<(Vec<u8>, bool), V1KpdbError> {
+        let budget = encrypted_database.len();
+        self.with_mem_lock_policy(budget,
+                                  |c| c.decrypt_damaged(header, encrypted_database),
+                                  |&(ref d, _)| unsafe {
+                                      mem_protect::zero(d);
+                                      mem_protect::unlock(d);
+                                  })
+    }
+
+    fn decrypt_damaged(&mut self,
+                       header: &V1Header,
+                       mut encrypted_database: Vec<u8>)
+                       -> Result<(Vec<u8>, bool), V1KpdbError> {
+        let finalkey = try!(self.get_finalkey(header));
+        let complete = encrypted_database.len() / 16 * 16;
+        let truncated = complete != encrypted_database.len();
+        encrypted_database.truncate(complete);
+        let mut decrypted_database = Crypter::decrypt_blocks(header,
+                                                             encrypted_database,
+                                                             finalkey);
+        // The padding of a truncated file is gone, the parser ignores the
+        // trailing bytes anyway
+        let intact = !truncated && Crypter::strip_padding(&mut decrypted_database).is_ok() &&
+                     Crypter::check_decryption_success(header, &decrypted_database).is_ok() &&
+                     Crypter::check_content_hash(header, &decrypted_database).is_ok();
+        log_trace!("decrypted {} bytes, intact: {}", decrypted_database.len(), intact);
+        Ok((decrypted_database, intact))
+    }
+
+    // Like decrypt_damaged_database but the ciphertext is borrowed, e.g.
+    // from a MappedFile or V1Kpdb::load_from_bytes, so it isn't copied
+    // into the heap. With the
+    // low-memory feature it's copied anyway to decrypt it in place.
+    //
+    // Sensitive data in this function:
+    // * finalkey (locked: transform_key)
+    // * decrypted_database (locked: decrypt_slice)
+    //
+    // At the end of this function:
+    // * decrypted database moved out of function
+    // * finalkey has moved to decrypt_slice
+    pub fn decrypt_mapped_database(&mut self,
+                                   header: &V1Header,
+                                   encrypted_database: &[u8])
+                                   -> Result<(Vec<u8>, bool), V1KpdbError> {
+        self.with_mem_lock_policy(encrypted_database.len(),
+                                  |c| {
+                                      let finalkey = try!(c.get_finalkey(header));
+                                      Ok(Crypter::decrypt_damaged_slice(header,
+                                                                        encrypted_database,
+                                                                        finalkey))
+                                  },
+                                  |&(ref d, _)| unsafe {
+                                      mem_protect::zero(d);
+                                      mem_protect::unlock(d);
+                                  })
+    }
+
+    // The part of decrypt_mapped_database after the key transformation,
+    // e.g. with the final key of a KeyJob
+    //
+    // Sensitive data in this function:
+    // * finalkey (locked: transform_key)
+    // * decrypted_database (locked: decrypt_slice)
+    //
+    // At the end of this function:
+    // * decrypted database moved out of function
+    // * finalkey has moved to decrypt_slice
+    #[doc(hidden)]
+    pub fn decrypt_damaged_slice(header: &V1Header,
+                                 encrypted_database: &[u8],
+                                 finalkey: Vec<u8>)
+                                 -> (Vec<u8>, bool) {
+        let complete = encrypted_database.len() / 16 * 16;
+        let truncated = complete != encrypted_database.len();
+        let mut decrypted_database = Crypter::decrypt_slice(header,
+                                                            &encrypted_database[..complete],
+                                                            finalkey);
+        let intact = !truncated && Crypter::strip_padding(&mut decrypted_database).is_ok() &&
+                     Crypter::check_decryption_success(header, &decrypted_database).is_ok() &&
+                     Crypter::check_content_hash(header, &decrypted_database).is_ok();
+        log_trace!("decrypted {} bytes, intact: {}", decrypted_database.len(), intact);
+        (decrypted_database, intact)
+    }
+
+    // Decrypt the content of length len from source while it's read,
+    // see DecryptReader
+    //
+    // Sensitive data in this function:
+    // * finalkey (locked: transform_key)
+    //
+    // At the end of this function:
+    // * finalkey has moved to DecryptReader::new
+    pub fn decrypt_stream<R: Read>(&mut self,
+                                   header: &V1Header,
+                                   source: R,
+                                   len: u64)
+                                   -> Result<DecryptReader<R>, V1KpdbError> {
+        let finalkey = try!(self.get_finalkey(header));
+        DecryptReader::new(header, source, len, finalkey)
+    }
+
+    // Check whether the credentials decrypt the database without
+    // handing out the content. Wrong credentials give Ok(false)
+    //
+    // Sensitive data in this function:
+    // * decrypted_database (locked: decrypt_raw)
+    //
+    // At the end of this function:
+    // * decrypted_database is zeroed out
+    pub fn verify_key(&mut self, header: &V1Header, encrypted_database: Vec<u8>) -> Result<bool, V1KpdbError> {
+        match self.decrypt_database(header, encrypted_database) {
+            Ok(decrypted_database) => {
+                unsafe {
+                    mem_protect::zero(&decrypted_database);
+                    mem_protect::unlock(&decrypted_database);
+                }
+                Ok(true)
+            }
+            Err(V1KpdbError::HashErr) | Err(V1KpdbError::DecryptErr) => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
     // Sensitive data in this function:
     // * finalkey (locked: transform_key)
     // * decrypted_database
@@ -71,8 +723,36 @@ impl Crypter {
     // * decrypted database has moved to encrypt_raw
     // * finalkey has moved to encrypt_raw
     pub fn encrypt_database(&mut self, header: &V1Header, decrypted_database: Vec<u8>) -> Result<Vec<u8>, V1KpdbError> {
-        let finalkey = try!(self.get_finalkey(header));
-        Ok(Crypter::encrypt_raw(header, decrypted_database, finalkey))
+        let budget = decrypted_database.len();
+        self.with_mem_lock_policy(budget,
+                                  |c| {
+                                      let finalkey = try!(c.get_finalkey(header));
+                                      Ok(Crypter::encrypt_raw(header, decrypted_database, finalkey))
+                                  },
+                                  |_| {})
+    }
+
+    // Run f under mem_lock_policy, if it's set, with a budget for twice
+    // len bytes. Under Require a buffer which failed to lock gives
+    // MemLockErr, the result of f is wiped then.
+    fn with_mem_lock_policy<T, F, W>(&mut self, len: usize, f: F, wipe: W) -> Result<T, V1KpdbError>
+        where F: FnOnce(&mut Crypter) -> Result<T, V1KpdbError>,
+              W: FnOnce(&T)
+    {
+        let policy = match self.mem_lock_policy {
+            Some(policy) => policy,
+            None => return f(self),
+        };
+        let scope = MemLockScope::enter(policy, (len as u64).saturating_mul(2));
+        let result = f(self);
+        let lock_failures = scope.leave();
+        if lock_failures > 0 && policy == MemLockPolicy::Require {
+            if let Ok(ref value) = result {
+                wipe(value);
+            }
+            return Err(V1KpdbError::MemLockErr);
+        }
+        result
     }
 
     // Sensitive data in this function:
@@ -92,50 +772,75 @@ impl Crypter {
     // passwordkey and keyfilekey are locked until procession
     // p and k are locked through SecureString
     fn get_finalkey(&mut self, header: &V1Header) -> Result<Vec<u8>, V1KpdbError> {
-        let masterkey = match (&mut self.password, &mut self.keyfile) {
-            // Only password provided
-            (&mut Some(ref mut p), &mut None) => try!(Crypter::get_passwordkey(p)),
-            // Only keyfile provided
-            (&mut None, &mut Some(ref mut k)) => try!(Crypter::get_keyfilekey(k)),
-            // Both provided
-            (&mut Some(ref mut p), &mut Some(ref mut k)) => {
-                // Get hashed keys...
-                let passwordkey = try!(Crypter::get_passwordkey(p));
-
-                let keyfilekey = try!(Crypter::get_keyfilekey(k));
+        let masterkey = try!(self.get_masterkey(header));
+        let finalkey = try!(Crypter::transform_key(masterkey,
+                                                   header,
+                                                   &mut self.progress,
+                                                   &self.cancel_token));
 
-                // ...and hash them together
-                let mut hasher = Hasher::new(Type::SHA256);
-                try!(hasher.write_all(&passwordkey)
-                           .map_err(|_| V1KpdbError::DecryptErr));
-                try!(hasher.write_all(&keyfilekey)
-                           .map_err(|_| V1KpdbError::DecryptErr));
+        Ok(finalkey)
+    }
 
-                let masterkey_tmp = hasher.finish();
-                // Zero out unneeded keys and lock masterkey
-                unsafe {
-                    intrinsics::volatile_set_memory(passwordkey.as_ptr() as *mut c_void,
-                                                    0u8,
-                                                    passwordkey.len());
-                    intrinsics::volatile_set_memory(keyfilekey.as_ptr() as *mut c_void,
-                                                    0u8,
-                                                    keyfilekey.len());
-                    mman::munlock(passwordkey.as_ptr() as *const c_void,
-                                  passwordkey.len() as size_t);
-                    mman::munlock(keyfilekey.as_ptr() as *const c_void,
-                                  keyfilekey.len() as size_t);
-                    mman::mlock(masterkey_tmp.as_ptr() as *const c_void,
-                                masterkey_tmp.len() as size_t);
-                }
-                masterkey_tmp
-            }
-            (&mut None, &mut None) => return Err(V1KpdbError::PassErr),
-        };
-        let finalkey = try!(Crypter::transform_key(masterkey, header));
+    // Everything of get_finalkey but the key transformation, which a
+    // KeyJob runs elsewhere
+    pub fn key_job(&mut self, header: &V1Header) -> Result<KeyJob, V1KpdbError> {
+        let masterkey = try!(self.get_masterkey(header));
+        Ok(KeyJob {
+            masterkey: masterkey,
+            header: header.clone(),
+            cancel_token: self.cancel_token.clone(),
+        })
+    }
 
-        Ok(finalkey)
+    // The key before the transformation, see get_finalkey
+    fn get_masterkey(&mut self, header: &V1Header) -> Result<Vec<u8>, V1KpdbError> {
+        let mut masterkey = try!(self.key.base_key());
+        for provider in self.key.providers.iter_mut() {
+            masterkey = try!(Crypter::add_provider_key(masterkey, provider, header));
+        }
+        Ok(masterkey)
     }
     
+    // Hash the key of the additional provider into the masterkey
+    // Sensitive data in this function:
+    // * masterkey (locked: get_finalkey)
+    // * providerkey
+    // * new_masterkey
+    //
+    // At the end of this function:
+    // * masterkey is zeroed out
+    // * providerkey is zeroed out
+    // * new_masterkey is locked and moved out of function, or zeroed
+    //   out if the provider failed
+    fn add_provider_key(masterkey: Vec<u8>,
+                        provider: &mut Box<KeyProvider>,
+                        header: &V1Header)
+                        -> Result<Vec<u8>, V1KpdbError> {
+        let providerkey = provider.get_key(&header.final_randomseed);
+        let mut hasher = Hasher::new(Type::SHA256);
+        let hashed = match providerkey {
+            Ok(ref key) => hasher.write_all(&masterkey).and_then(|_| hasher.write_all(key)),
+            Err(_) => Ok(()),
+        };
+        let new_masterkey = hasher.finish();
+        unsafe {
+            mem_protect::zero(&masterkey);
+            mem_protect::unlock(&masterkey);
+            if let Ok(ref key) = providerkey {
+                mem_protect::zero(&key);
+                mem_protect::unlock(&key);
+            }
+        }
+        if let Err(e) = providerkey.and(hashed.map_err(|_| V1KpdbError::DecryptErr)) {
+            unsafe {
+                mem_protect::zero(&new_masterkey);
+            }
+            return Err(e);
+        }
+        mem_protect::lock(&new_masterkey, "new_masterkey");
+        Ok(new_masterkey)
+    }
+
     // Hash the password string to create a decryption key from that
     // Sensitive data in this function:
     // * password
@@ -146,7 +851,10 @@ impl Crypter {
     // * password is zeroed out
     // * password_string is deleted (is a reference to password.string)
     // * passwordkey is moved out of function and locked
-    fn get_passwordkey(password: &mut SecureString) -> Result<Vec<u8>, V1KpdbError> {
+    //
+    // Public for the known-answer tests of testvectors
+    #[doc(hidden)]
+    pub fn get_passwordkey(password: &mut SecureString) -> Result<Vec<u8>, V1KpdbError> {
         password.unlock();
         let password_string = password.string.as_bytes();
 
@@ -157,10 +865,33 @@ impl Crypter {
 
         // hasher.finish() is a move and therefore secure
         let passwordkey = hasher.finish();
-        unsafe {
-            mman::mlock(passwordkey.as_ptr() as *const c_void,
-                        passwordkey.len() as size_t);
+        mem_protect::lock(&passwordkey, "passwordkey");
+        Ok(passwordkey)
+    }
+
+    // Get key from the password, given either as text or as bytes
+    fn get_passwordsourcekey(password: &mut Password) -> Result<Vec<u8>, V1KpdbError> {
+        match *password {
+            Password::Text(ref mut text) => Crypter::get_passwordkey(text),
+            Password::Bytes(ref bytes) => Crypter::get_passwordbyteskey(bytes.bytes()),
         }
+    }
+
+    // Same as get_passwordkey but for a password given as bytes
+    //
+    // Sensitive data in this function:
+    // * password (locked: SecureBytes)
+    // * passwordkey
+    //
+    // At the end of this function:
+    // * passwordkey is moved out of function and locked
+    fn get_passwordbyteskey(password: &[u8]) -> Result<Vec<u8>, V1KpdbError> {
+        let mut hasher = Hasher::new(Type::SHA256);
+        try!(hasher.write_all(password)
+                   .map_err(|_| V1KpdbError::DecryptErr));
+
+        let passwordkey = hasher.finish();
+        mem_protect::lock(&passwordkey, "passwordkey");
         Ok(passwordkey)
     }
 
@@ -196,25 +927,26 @@ impl Crypter {
         try!(file.seek(SeekFrom::Start(0u64))
                  .map_err(|_| V1KpdbError::FileErr));
 
+        if file_size <= XML_KEYFILE_MAX_SIZE {
+            if let Some(key) = try!(Crypter::get_xml_keyfilekey(&mut file)) {
+                return Ok(key);
+            }
+            try!(file.seek(SeekFrom::Start(0u64))
+                     .map_err(|_| V1KpdbError::FileErr));
+        }
+
         if file_size == 32 {
             let mut key: Vec<u8> = vec![];
             try!(file.read_to_end(&mut key).map_err(|_| V1KpdbError::ReadErr));
-            unsafe {
-                mman::mlock(key.as_ptr() as *const c_void,
-                            key.len() as size_t);
-                // intrinsics::volatile_set_memory(&file as *mut c_void,
-                //                                 0u8,
-                //                                 mem::size_of::<File>());
-
-            }
+            mem_protect::lock(&key, "key");
+            // intrinsics::volatile_set_memory(&file as *mut c_void,
+            //                                 0u8,
+            //                                 mem::size_of::<File>());
             return Ok(key);
         } else if file_size == 64 {
             // interpret characters as encoded hex if possible (e.g. "FF" => 0xff)
             let mut key: String = "".to_string();
-            unsafe {
-                mman::mlock(key.as_ptr() as *const c_void,
-                            key.len() as size_t);
-            }
+            mem_protect::lock(&key, "key");
             match file.read_to_string(&mut key) {
                 Ok(_) => {
                     match (&key[..]).from_hex() {
@@ -223,13 +955,9 @@ impl Crypter {
                                 // intrinsics::volatile_set_memory(&file as *mut c_void,
                                 //                                 0u8,
                                 //                                 mem::size_of::<File>());
-                                mman::mlock(decoded_key.as_ptr() as *const c_void,
-                                            decoded_key.len() as size_t);
-                                intrinsics::volatile_set_memory(key.as_ptr() as *mut c_void,
-                                                                0u8,
-                                                                key.len());
-                                mman::munlock(key.as_ptr() as *const c_void,
-                                              key.len() as size_t);
+                                mem_protect::lock(&decoded_key, "decoded_key");
+                                mem_protect::zero(&key);
+                                mem_protect::unlock(&key);
 
                             }
                             return Ok(decoded_key)
@@ -239,11 +967,8 @@ impl Crypter {
                 }
                 Err(_) => {
                     unsafe {
-                        intrinsics::volatile_set_memory(key.as_ptr() as *mut c_void,
-                                                        0u8,
-                                                        key.len());
-                        mman::munlock(key.as_ptr() as *const c_void,
-                                      key.len() as size_t);
+                        mem_protect::zero(&key);
+                        mem_protect::unlock(&key);
                         
                     }
                     try!(file.seek(SeekFrom::Start(0u64))
@@ -255,10 +980,7 @@ impl Crypter {
         // Read up to 2048 bytes and hash them
         let mut hasher = Hasher::new(Type::SHA256);
         let mut buf: Vec<u8> = vec![];
-        unsafe {
-            mman::mlock(buf.as_ptr() as *const c_void,
-                        buf.len() as size_t);
-        }
+        mem_protect::lock(&buf, "buf");
 
         loop {
             buf = vec![0; 2048];
@@ -271,7 +993,7 @@ impl Crypter {
                     try!(hasher.write_all(&buf[..])
                                .map_err(|_| V1KpdbError::DecryptErr));
                     unsafe {
-                        intrinsics::volatile_set_memory(buf.as_ptr() as *mut c_void, 0u8, buf.len())
+                        mem_protect::zero(&buf)
                     };
 
                 }
@@ -282,61 +1004,346 @@ impl Crypter {
         }
 
         let key = hasher.finish();
+        // intrinsics::volatile_set_memory(&file as *mut c_void,
+        //                                 0u8,
+        //                                 mem::size_of::<File>());
+        mem_protect::unlock(&buf);
+        mem_protect::lock(&key, "key");
+
+        Ok(key)
+    }
+
+    // Run rounds of AES_ECB over one 16 byte half of the masterkey
+    //
+    // Sensitive data in this function:
+    // * half
+    // * seed
+    //
+    // At the end of this function:
+    // * seed and the intermediate halves are zeroed out
+    // * the transformed half is moved out of function or zeroed out
+    //   on cancellation
+    fn transform_half(mut half: Vec<u8>,
+                      seed: Vec<u8>,
+                      rounds: u32,
+                      mut progress: Option<&mut Box<FnMut(u32, u32)>>,
+                      cancel_token: Option<CancelToken>)
+                      -> Result<Vec<u8>, V1KpdbError> {
+        let crypter = symm::Crypter::new(symm::Type::AES_256_ECB);
+        crypter.init(symm::Mode::Encrypt, &seed, vec![]);
         unsafe {
-            // intrinsics::volatile_set_memory(&file as *mut c_void,
-            //                                 0u8,
-            //                                 mem::size_of::<File>());
-            mman::munlock(buf.as_ptr() as *const c_void,
-                          buf.len() as size_t);
-            mman::mlock(key.as_ptr() as *const c_void,
-                        key.len() as size_t);
-            
+            mem_protect::zero(&seed);
+        }
+
+        let mut done: u32 = 0;
+        while done < rounds {
+            let batch = cmp::min(PROGRESS_INTERVAL, rounds - done);
+            for _ in 0..batch {
+                let next = crypter.update(&half);
+                unsafe {
+                    mem_protect::zero(&half);
+                }
+                half = next;
+            }
+            done += batch;
+            if let Some(ref mut progress) = progress {
+                (**progress)(done, rounds);
+            }
+            if let Some(ref cancel_token) = cancel_token {
+                if cancel_token.is_cancelled() {
+                    unsafe {
+                        mem_protect::zero(&half);
+                    }
+                    return Err(V1KpdbError::CancelledErr);
+                }
+            }
+        }
+        Ok(half)
+    }
+
+    // Get key from a KeePass 2 XML keyfile. Version 1.0 holds the key
+    // base64 encoded, version 2.0 as hex together with the first four
+    // bytes of its SHA256 as checksum. Returns None if the file isn't an
+    // XML keyfile at all. A key of other size than 32 bytes is hashed
+    // like KeePass 2 does.
+    //
+    // Sensitive data in this function:
+    // * content
+    // * data
+    // * key
+    //
+    // At the end of this function:
+    // * content and data are zeroed out
+    // * key has moved out of function and is locked
+    fn get_xml_keyfilekey(file: &mut File) -> Result<Option<Vec<u8>>, V1KpdbError> {
+        let mut content: Vec<u8> = vec![];
+        try!(file.read_to_end(&mut content).map_err(|_| V1KpdbError::ReadErr));
+        mem_protect::lock(&content, "content");
+        let result = Crypter::parse_xml_keyfile(&content);
+        unsafe {
+            mem_protect::zero(&content);
+            mem_protect::unlock(&content);
+        }
+        match try!(result) {
+            Some(data) => Crypter::get_xml_datakey(data).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    // Turn the key data of an XML keyfile into the keyfile key
+    //
+    // Sensitive data in this function:
+    // * data
+    // * key
+    //
+    // At the end of this function:
+    // * data is zeroed out
+    // * key has moved out of function and is locked
+    fn get_xml_datakey(data: Vec<u8>) -> Result<Vec<u8>, V1KpdbError> {
+        let key = if data.len() == 32 {
+            data.clone()
+        } else {
+            let mut hasher = Hasher::new(Type::SHA256);
+            try!(hasher.write_all(&data)
+                       .map_err(|_| V1KpdbError::DecryptErr));
+            hasher.finish()
+        };
+        unsafe {
+            mem_protect::zero(&data);
+        }
+        mem_protect::lock(&key, "key");
+        Ok(key)
+    }
+
+    // Get key from the keyfile, given either by path or by content
+    fn get_keyfilesourcekey(keyfile: &mut Keyfile) -> Result<Vec<u8>, V1KpdbError> {
+        match *keyfile {
+            Keyfile::Path(ref mut path) => Crypter::get_keyfilekey(path),
+            Keyfile::Data(ref data) => Crypter::get_keyfiledatakey(data.bytes()),
         }
+    }
 
+    // Same as get_keyfilekey but for the content of a keyfile
+    //
+    // Sensitive data in this function:
+    // * data (locked: SecureBytes)
+    // * key
+    //
+    // At the end of this function:
+    // * key has moved out of function and is locked
+    fn get_keyfiledatakey(data: &[u8]) -> Result<Vec<u8>, V1KpdbError> {
+        if data.len() as u64 <= XML_KEYFILE_MAX_SIZE {
+            if let Some(xml_data) = try!(Crypter::parse_xml_keyfile(data)) {
+                return Crypter::get_xml_datakey(xml_data);
+            }
+        }
+
+        if data.len() == 32 {
+            let key = data.to_vec();
+            mem_protect::lock(&key, "key");
+            return Ok(key);
+        }
+        if data.len() == 64 {
+            // interpret characters as encoded hex if possible (e.g. "FF" => 0xff)
+            if let Some(key) = str::from_utf8(data).ok().and_then(|k| k.from_hex().ok()) {
+                mem_protect::lock(&key, "key");
+                return Ok(key);
+            }
+        }
+
+        let mut hasher = Hasher::new(Type::SHA256);
+        try!(hasher.write_all(data)
+                   .map_err(|_| V1KpdbError::DecryptErr));
+        let key = hasher.finish();
+        mem_protect::lock(&key, "key");
         Ok(key)
     }
 
+    fn parse_xml_keyfile(content: &[u8]) -> Result<Option<Vec<u8>>, V1KpdbError> {
+        let text = match str::from_utf8(content) {
+            Ok(t) => t,
+            Err(_) => return Ok(None),
+        };
+        if !text.contains("<KeyFile>") {
+            return Ok(None);
+        }
+        let version = Crypter::xml_element(text, "Version").unwrap_or("1.0").trim();
+        let data_start = try!(text.find("<Data").ok_or(V1KpdbError::KeyfileErr));
+        let data_tag_end = try!(text[data_start..].find('>').ok_or(V1KpdbError::KeyfileErr)) +
+                           data_start;
+        let data_tag = &text[data_start..data_tag_end];
+        let data = try!(Crypter::xml_element(text, "Data").ok_or(V1KpdbError::KeyfileErr));
+
+        if version.starts_with("1.") {
+            data.trim().from_base64().map(Some).map_err(|_| V1KpdbError::KeyfileErr)
+        } else if version.starts_with("2.") {
+            let hex: String = data.chars().filter(|c| !c.is_whitespace()).collect();
+            let key = try!(hex.from_hex().map_err(|_| V1KpdbError::KeyfileErr));
+            if let Some(hash_start) = data_tag.find("Hash=\"") {
+                let hash = &data_tag[hash_start + 6..];
+                let hash = &hash[..hash.find('"').unwrap_or(hash.len())];
+                let mut hasher = Hasher::new(Type::SHA256);
+                try!(hasher.write_all(&key)
+                           .map_err(|_| V1KpdbError::DecryptErr));
+                let expected = try!(hash.from_hex().map_err(|_| V1KpdbError::KeyfileErr));
+                if !constant_time_eq(&expected, &hasher.finish()[..4]) {
+                    return Err(V1KpdbError::KeyfileErr);
+                }
+            }
+            Ok(Some(key))
+        } else {
+            Err(V1KpdbError::KeyfileErr)
+        }
+    }
+
+    // Text between <name ...> and </name>
+    fn xml_element<'a>(text: &'a str, name: &str) -> Option<&'a str> {
+        let open = format!("<{}", name);
+        let close = format!("</{}>", name);
+        let start = match text.find(&open) {
+            Some(s) => s,
+            None => return None,
+        };
+        let content_start = match text[start..].find('>') {
+            Some(e) => start + e + 1,
+            None => return None,
+        };
+        text[content_start..].find(&close).map(|end| &text[content_start..content_start + end])
+    }
+
+    /// Count how many rounds of the key transformation this machine
+    /// manages in target, e.g. one second as KeePass does. Use the result
+    /// with V1Header::set_key_transf_rounds to harden a database.
+    pub fn benchmark_rounds(target: Duration) -> u32 {
+        // Rounds between two looks at the clock
+        const BATCH: u32 = 1000;
+
+        let seed: Vec<u8> = (0..32).map(|_| rand::random::<u8>()).collect();
+        let crypter = symm::Crypter::new(symm::Type::AES_256_ECB);
+        crypter.init(symm::Mode::Encrypt, &seed, vec![]);
+        // Both halves of the key are transformed in parallel, so one
+        // 16 byte block per round is what an unlock costs
+        let mut key: Vec<u8> = vec![0; 16];
+
+        let mut rounds: u32 = 0;
+        let start = Instant::now();
+        while start.elapsed() < target {
+            for _ in 0..BATCH {
+                key = crypter.update(&key);
+            }
+            rounds = match rounds.checked_add(BATCH) {
+                Some(r) => r,
+                None => return u32::max_value(),
+            };
+        }
+        if rounds == 0 {
+            1
+        } else {
+            rounds
+        }
+    }
+
     // Create the finalkey from the masterkey by encrypting it with some
     // random seeds from the database header and AES_ECB
     // 
     // Sensitive data in this function:
     // * masterkey (locked: get_finalkey)
+    // * first_half, second_half
+    // * transformedkey
     // * finalkey
     //
     // At the end of this function:
-    // * masterkey is zeroed out
+    // * masterkey, first_half, second_half and transformedkey are zeroed out
     // * finalkey is locked and moved out of function
-    fn transform_key(mut masterkey: Vec<u8>, header: &V1Header) -> Result<Vec<u8>, V1KpdbError> {
-        let crypter = symm::Crypter::new(symm::Type::AES_256_ECB);
-        crypter.init(symm::Mode::Encrypt, &header.transf_randomseed, vec![]);
-        for _ in 0..header.key_transf_rounds {
-            masterkey = crypter.update(&masterkey);
-        }
-        let mut hasher = Hasher::new(Type::SHA256);
-        try!(hasher.write_all(&masterkey)
-                   .map_err(|_| V1KpdbError::DecryptErr));
-        masterkey = hasher.finish();
+    #[doc(hidden)]
+    pub fn transform_key(masterkey: Vec<u8>,
+                         header: &V1Header,
+                         progress: &mut Option<Box<FnMut(u32, u32)>>,
+                         cancel_token: &Option<CancelToken>)
+                         -> Result<Vec<u8>, V1KpdbError> {
+        let transformedkey = try!(Crypter::transformed_key(masterkey,
+                                                           header,
+                                                           progress,
+                                                           cancel_token));
+
         let mut hasher = Hasher::new(Type::SHA256);
         try!(hasher.write_all(&header.final_randomseed)
                    .map_err(|_| V1KpdbError::DecryptErr));
-        try!(hasher.write_all(&masterkey)
+        try!(hasher.write_all(&transformedkey)
                    .map_err(|_| V1KpdbError::DecryptErr));
         let finalkey = hasher.finish();
 
-        // Zero out masterkey as it is not needed anymore
+        // Zero out transformedkey as it is not needed anymore
         unsafe {
-            intrinsics::volatile_set_memory(masterkey.as_ptr() as *mut c_void,
-                                            0u8,
-                                            masterkey.len());
-            mman::munlock(masterkey.as_ptr() as *const c_void,
-                          masterkey.len() as size_t);
-            mman::mlock(finalkey.as_ptr() as *const c_void, finalkey.len() as size_t);
-
+            mem_protect::zero(&transformedkey);
+            mem_protect::lock(&finalkey, "finalkey");
         }
 
         Ok(finalkey)
     }
 
+    // The AES-KDF part of transform_key: SHA256 of the masterkey after
+    // the rounds of AES_ECB. The result isn't locked.
+    //
+    // AES_ECB works on 16 byte blocks so both halves of the masterkey are
+    // transformed independently, the second one in its own thread
+    //
+    // Progress is reported from this thread for the first half only,
+    // the second half runs in lockstep
+    #[doc(hidden)]
+    pub fn transformed_key(masterkey: Vec<u8>,
+                           header: &V1Header,
+                           progress: &mut Option<Box<FnMut(u32, u32)>>,
+                           cancel_token: &Option<CancelToken>)
+                           -> Result<Vec<u8>, V1KpdbError> {
+        let first_half = masterkey[..16].to_vec();
+        let second_half = masterkey[16..].to_vec();
+        // Zero out masterkey as only the halves are needed from now on
+        unsafe {
+            mem_protect::zero(&masterkey);
+            mem_protect::unlock(&masterkey);
+        }
+
+        let seed = header.transf_randomseed.clone();
+        let rounds = header.key_transf_rounds;
+        log_debug!("transforming the key with {} rounds", rounds);
+        let start = Instant::now();
+        let worker_token = cancel_token.clone();
+        let worker = thread::spawn(move || {
+            Crypter::transform_half(second_half, seed, rounds, None, worker_token)
+        });
+
+        let first_half = Crypter::transform_half(first_half,
+                                                 header.transf_randomseed.clone(),
+                                                 rounds,
+                                                 progress.as_mut(),
+                                                 cancel_token.clone());
+        let second_half = try!(worker.join().map_err(|_| V1KpdbError::DecryptErr));
+        let (first_half, second_half) = match (first_half, second_half) {
+            (Ok(first), Ok(second)) => (first, second),
+            (Ok(half), Err(e)) | (Err(e), Ok(half)) => {
+                unsafe {
+                    mem_protect::zero(&half);
+                }
+                return Err(e);
+            }
+            (Err(e), Err(_)) => return Err(e),
+        };
+        log_trace!("key transformation took {:?}", start.elapsed());
+
+        let mut hasher = Hasher::new(Type::SHA256);
+        try!(hasher.write_all(&first_half)
+                   .map_err(|_| V1KpdbError::DecryptErr));
+        try!(hasher.write_all(&second_half)
+                   .map_err(|_| V1KpdbError::DecryptErr));
+        let transformedkey = hasher.finish();
+        unsafe {
+            mem_protect::zero(&first_half);
+            mem_protect::zero(&second_half);
+        }
+        Ok(transformedkey)
+    }
+
     // Decrypt the raw data and return it
     //
     // Sensitive data in this function:
@@ -348,31 +1355,208 @@ impl Crypter {
     // * decrypted_database is locked and moved out of function
     //
     // finalkey is locked through transform_key
-    fn decrypt_raw(header: &V1Header, encrypted_database: Vec<u8>, finalkey: Vec<u8>) -> Vec<u8> {
-        let mut decrypted_database = symm::decrypt(symm::Type::AES_256_CBC,
-                                     &finalkey,
-                                     header.iv.clone(),
-                                     &encrypted_database);
+    fn decrypt_raw(header: &V1Header,
+                   encrypted_database: Vec<u8>,
+                   finalkey: Vec<u8>)
+                   -> Result<Vec<u8>, V1KpdbError> {
+        let aligned = encrypted_database.len() % 16 == 0;
+        let mut decrypted_database = Crypter::decrypt_blocks(header, encrypted_database, finalkey);
+        if !aligned ||
+           Crypter::strip_padding(&mut decrypted_database).is_err() {
+            unsafe {
+                mem_protect::zero(&decrypted_database);
+            }
+            mem_protect::unlock(&decrypted_database);
+            return Err(V1KpdbError::DecryptErr);
+        }
+        Ok(decrypted_database)
+    }
+
+    // Decrypt without removing the padding
+    //
+    // Sensitive data in this function:
+    // * finalkey (locked: transform_key)
+    // * decrypted_database
+    //
+    // At the end of this function:
+    // * finalkey is zeroed out
+    // * decrypted_database is moved out of the function and locked
+    #[cfg(not(feature = "low-memory"))]
+    fn decrypt_blocks(header: &V1Header, encrypted_database: Vec<u8>, finalkey: Vec<u8>) -> Vec<u8> {
+        Crypter::decrypt_slice(header, &encrypted_database, finalkey)
+    }
+
+    // decrypt_blocks of borrowed ciphertext
+    //
+    // Sensitive data in this function:
+    // * finalkey (locked: transform_key)
+    // * decrypted_database
+    //
+    // At the end of this function:
+    // * finalkey is zeroed out
+    // * decrypted_database is moved out of the function and locked
+    #[cfg(not(feature = "low-memory"))]
+    fn decrypt_slice(header: &V1Header, encrypted_database: &[u8], finalkey: Vec<u8>) -> Vec<u8> {
+        // The padding is checked by the callers, OpenSSL would just drop
+        // the last block if it's wrong
+        let crypter = symm::Crypter::new(symm::Type::AES_256_CBC);
+        crypter.pad(false);
+        crypter.init(symm::Mode::Decrypt, &finalkey, header.iv.clone());
+        let mut decrypted_database = crypter.update(&encrypted_database);
+        decrypted_database.extend(crypter.finalize());
 
         // Zero out finalkey as it is not needed anymore
         unsafe {
-            intrinsics::volatile_set_memory(finalkey.as_ptr() as *mut c_void, 0u8, finalkey.len());
-            mman::munlock(finalkey.as_ptr() as *const c_void, finalkey.len() as size_t);
+            mem_protect::zero(&finalkey);
+            mem_protect::unlock(&finalkey);
         }
 
-        // Delete padding from decrypted data
-        let padding = decrypted_database[decrypted_database.len() - 1] as usize;
-        let length = decrypted_database.len();
+        mem_protect::lock(&decrypted_database, "decrypted_database");
+        decrypted_database
+    }
+
+    // Like decrypt_blocks but the plaintext replaces the ciphertext chunk
+    // by chunk, so there's never a second copy of the whole database.
+    // An incomplete last block is dropped.
+    //
+    // Sensitive data in this function:
+    // * finalkey (locked: transform_key)
+    // * data (locked before the first chunk is decrypted)
+    // * chunk
+    //
+    // At the end of this function:
+    // * finalkey is zeroed out
+    // * every chunk is zeroed out after it's copied into data
+    // * data is moved out of the function and locked
+    #[cfg(feature = "low-memory")]
+    fn decrypt_blocks(header: &V1Header, mut data: Vec<u8>, finalkey: Vec<u8>) -> Vec<u8> {
+        let crypter = symm::Crypter::new(symm::Type::AES_256_CBC);
+        crypter.pad(false);
+        crypter.init(symm::Mode::Decrypt, &finalkey, header.iv.clone());
+
+        let complete = data.len() / 16 * 16;
+        data.truncate(complete);
+        mem_protect::lock(&data, "decrypted_database");
+        let mut written = 0;
+        let mut read = 0;
+        // The output never gets ahead of the input, so the ciphertext
+        // which is still needed isn't overwritten
+        while read < complete {
+            let end = cmp::min(read + CHUNK_SIZE, complete);
+            let chunk = crypter.update(&data[read..end]);
+            read = end;
+            written = Crypter::copy_chunk(&mut data, written, chunk);
+        }
+        let chunk = crypter.finalize();
+        written = Crypter::copy_chunk(&mut data, written, chunk);
 
-        // resize() is safe as just padding is dropped
-        decrypted_database.resize(length - padding, 0);
         unsafe {
-            mman::mlock(decrypted_database.as_ptr() as *const c_void, decrypted_database.len() as size_t);
+            mem_protect::zero(&finalkey);
+            mem_protect::unlock(&finalkey);
+            mem_protect::zero(&data[written..]);
         }
-        decrypted_database
+        data.truncate(written);
+        data
+    }
+
+    // decrypt_blocks of borrowed ciphertext, which is copied to decrypt
+    // it in place
+    #[cfg(feature = "low-memory")]
+    fn decrypt_slice(header: &V1Header, encrypted_database: &[u8], finalkey: Vec<u8>) -> Vec<u8> {
+        Crypter::decrypt_blocks(header, encrypted_database.to_vec(), finalkey)
+    }
+
+    // Copy chunk into data at pos and zero it out. Returns the position
+    // after the chunk.
+    fn copy_chunk(data: &mut Vec<u8>, pos: usize, chunk: Vec<u8>) -> usize {
+        mem_protect::lock(&chunk, "chunk");
+        let end = pos + chunk.len();
+        data[pos..end].copy_from_slice(&chunk);
+        unsafe {
+            mem_protect::zero(&chunk);
+        }
+        mem_protect::unlock(&chunk);
+        end
+    }
+
+    // Decrypt AES-256-CBC ciphertext with PKCS#7 padding, e.g. of the undo
+    // log or of an archive. The plaintext goes to a buffer of its final
+    // size which is locked before the first chunk is copied into it, so
+    // it's never reallocated and no unlocked copy is left behind.
+    // DecryptErr if the padding is broken. Public for the archives.
+    //
+    // Sensitive data in this function:
+    // * key (borrowed, the caller zeroes it out)
+    // * plaintext (locked before the first chunk is decrypted)
+    // * chunk
+    //
+    // At the end of this function:
+    // * every chunk is zeroed out after it's copied into plaintext
+    // * plaintext is moved out of the function and locked, the caller
+    //   has to zero it out
+    #[doc(hidden)]
+    pub fn decrypt_locked(key: &[u8],
+                          iv: Vec<u8>,
+                          ciphertext: &[u8])
+                          -> Result<Vec<u8>, V1KpdbError> {
+        if ciphertext.len() % 16 != 0 {
+            return Err(V1KpdbError::DecryptErr);
+        }
+        let mut plaintext = vec![0u8; ciphertext.len()];
+        mem_protect::lock(&plaintext, "plaintext");
+        // The padding is checked by strip_padding, OpenSSL would just
+        // drop a broken one
+        let crypter = symm::Crypter::new(symm::Type::AES_256_CBC);
+        crypter.pad(false);
+        crypter.init(symm::Mode::Decrypt, key, iv);
+        let mut written = 0;
+        for chunk in ciphertext.chunks(CHUNK_SIZE) {
+            written = Crypter::copy_chunk(&mut plaintext, written, crypter.update(chunk));
+        }
+        Crypter::copy_chunk(&mut plaintext, written, crypter.finalize());
+        if Crypter::strip_padding(&mut plaintext).is_err() {
+            unsafe {
+                mem_protect::zero(&plaintext);
+            }
+            mem_protect::unlock(&plaintext);
+            return Err(V1KpdbError::DecryptErr);
+        }
+        Ok(plaintext)
     }
 
-    fn encrypt_raw(header: &V1Header, decrypted_database: Vec<u8>, finalkey: Vec<u8>) -> Vec<u8> {
+    // Remove the PKCS#7 padding from data. Every byte of the padding has
+    // to hold its length, which is 1 to 16. Public for the tests.
+    //
+    // Sensitive data in this function:
+    // * data (locked: decrypt_raw)
+    //
+    // At the end of this function:
+    // * data is shortened, the padding is zeroed out
+    #[doc(hidden)]
+    pub fn strip_padding(data: &mut Vec<u8>) -> Result<(), V1KpdbError> {
+        let length = data.len();
+        let padding = match data.last() {
+            Some(&p) => p as usize,
+            None => return Err(V1KpdbError::DecryptErr),
+        };
+        if padding == 0 || padding > 16 || padding > length ||
+           data[length - padding..].iter().any(|b| *b as usize != padding) {
+            return Err(V1KpdbError::DecryptErr);
+        }
+        // Zero the padding out as well, the locked buffer keeps its size
+        unsafe {
+            mem_protect::zero(&data[length - padding..]);
+        }
+        data.truncate(length - padding);
+        Ok(())
+    }
+
+    #[cfg(not(feature = "low-memory"))]
+    #[doc(hidden)]
+    pub fn encrypt_raw(header: &V1Header,
+                       decrypted_database: Vec<u8>,
+                       finalkey: Vec<u8>)
+                       -> Vec<u8> {
         let encrypted_database = symm::encrypt(symm::Type::AES_256_CBC,
                                              &finalkey,
                                              header.iv.clone(),
@@ -380,15 +1564,66 @@ impl Crypter {
         
         // Zero out finalkey as it is not needed anymore
         unsafe {
-            intrinsics::volatile_set_memory(finalkey.as_ptr() as *mut c_void, 0u8, finalkey.len());
-            mman::munlock(finalkey.as_ptr() as *const c_void, finalkey.len() as size_t);
-            intrinsics::volatile_set_memory(decrypted_database.as_ptr() as *mut c_void, 0u8, decrypted_database.len());
-            mman::munlock(decrypted_database.as_ptr() as *const c_void, decrypted_database.len() as size_t);
+            mem_protect::zero(&finalkey);
+            mem_protect::unlock(&finalkey);
+            mem_protect::zero(&decrypted_database);
+            mem_protect::unlock(&decrypted_database);
         }
 
         encrypted_database
     }
 
+    // Like encrypt_raw but the ciphertext replaces the plaintext chunk by
+    // chunk, see decrypt_blocks
+    //
+    // Sensitive data in this function:
+    // * finalkey (locked: transform_key)
+    // * data (locked)
+    // * chunk (ciphertext)
+    //
+    // At the end of this function:
+    // * finalkey is zeroed out
+    // * data holds the ciphertext and is moved out of the function
+    #[cfg(feature = "low-memory")]
+    #[doc(hidden)]
+    pub fn encrypt_raw(header: &V1Header, mut data: Vec<u8>, finalkey: Vec<u8>) -> Vec<u8> {
+        // Pad in place. If the buffer would have to grow, it's moved into
+        // one of the right size first so no copy is left behind.
+        let padding = 16 - data.len() % 16;
+        if data.capacity() < data.len() + padding {
+            let mut grown = Vec::with_capacity(data.len() + padding);
+            grown.extend_from_slice(&data);
+            unsafe {
+                mem_protect::zero(&data);
+            }
+            mem_protect::unlock(&data);
+            data = grown;
+        }
+        data.extend((0..padding).map(|_| padding as u8));
+        mem_protect::lock(&data, "decrypted_database");
+
+        let crypter = symm::Crypter::new(symm::Type::AES_256_CBC);
+        crypter.pad(false);
+        crypter.init(symm::Mode::Encrypt, &finalkey, header.iv.clone());
+        let mut pos = 0;
+        while pos < data.len() {
+            let end = cmp::min(pos + CHUNK_SIZE, data.len());
+            let chunk = crypter.update(&data[pos..end]);
+            data[pos..pos + chunk.len()].copy_from_slice(&chunk);
+            pos += chunk.len();
+        }
+        let chunk = crypter.finalize();
+        data[pos..pos + chunk.len()].copy_from_slice(&chunk);
+
+        unsafe {
+            mem_protect::zero(&finalkey);
+            mem_protect::unlock(&finalkey);
+        }
+        // Only ciphertext is left
+        mem_protect::disown(&data);
+        data
+    }
+
     // Check some conditions
     // Sensitive data in this function
     // * decrypted_content (locked: decrypt_raw)
@@ -427,7 +1662,7 @@ impl Crypter {
                           decrypted_content: &Vec<u8>)
                           -> Result<(), V1KpdbError> {
         let content_hash = try!(Crypter::get_content_hash(decrypted_content));
-        if content_hash != header.content_hash {
+        if !constant_time_eq(&content_hash, &header.content_hash) {
             return Err(V1KpdbError::HashErr);
         }
         Ok(())
@@ -0,0 +1,127 @@
+//! Custom string fields of entries
+//!
+//! KeePass 1.x has no custom fields, so they're kept as a block at the
+//! end of the comment, one "<name>: <value>" line per field:
+//!
+//!   KPEX_CUSTOM_FIELDS
+//!   Account number: 12345
+//!   Security question: First pet
+//!   KPEX_CUSTOM_FIELDS_END
+//!
+//! Other clients show the block as part of the notes. Backslashes and
+//! line breaks in values are escaped with a backslash, e.g. a line break
+//! as \n. The names are those of custom strings in KeePass 2.x, so they
+//! can't be the names of its standard fields. See
+//! V1Entry::set_custom_field.
+
+/// Line which opens the block of custom fields in the comment
+pub const CUSTOM_FIELDS_HEADER: &'static str = "KPEX_CUSTOM_FIELDS";
+
+/// Line which closes the block of custom fields
+pub const CUSTOM_FIELDS_FOOTER: &'static str = "KPEX_CUSTOM_FIELDS_END";
+
+/// Names of the standard fields of KeePass 2.x, which custom fields
+/// can't have
+pub const RESERVED_NAMES: [&'static str; 5] = ["Title", "UserName", "Password", "URL", "Notes"];
+
+/// True if name can be the name of a custom field: it isn't empty,
+/// reserved or surrounded by whitespace and has no colon or control
+/// characters
+pub fn is_valid_name(name: &str) -> bool {
+    !name.is_empty() && name.trim() == name && !RESERVED_NAMES.contains(&name) &&
+    !name.chars().any(|c| c == ':' || c.is_control())
+}
+
+/// Remove the blocks of custom fields from comment. Returns the rest of
+/// the comment and the fields in order. A header which isn't followed
+/// by a footer before the next header doesn't open a block, lines of a
+/// block without a colon are skipped.
+pub fn split_fields(comment: &str) -> (String, Vec<(String, String)>) {
+    let lines = comment.lines().collect::<Vec<_>>();
+    let mut text = vec![];
+    let mut fields = vec![];
+    let mut i = 0;
+    while i < lines.len() {
+        // A block is closed before the next one opens
+        let footer = match lines[i] {
+            CUSTOM_FIELDS_HEADER => {
+                lines[i + 1..]
+                    .iter()
+                    .position(|l| *l == CUSTOM_FIELDS_HEADER || *l == CUSTOM_FIELDS_FOOTER)
+                    .map(|f| i + 1 + f)
+                    .and_then(|f| if lines[f] == CUSTOM_FIELDS_FOOTER { Some(f) } else { None })
+            }
+            _ => None,
+        };
+        match footer {
+            Some(footer) => {
+                for line in lines[i + 1..footer].iter() {
+                    if let Some(colon) = line.find(':') {
+                        let value = &line[colon + 1..];
+                        let value = if value.starts_with(' ') {
+                            &value[1..]
+                        } else {
+                            value
+                        };
+                        fields.push((line[..colon].trim().to_string(), unescape(value)));
+                    }
+                }
+                i = footer + 1;
+            }
+            None => {
+                text.push(lines[i]);
+                i += 1;
+            }
+        }
+    }
+    (text.join("\n"), fields)
+}
+
+/// Append fields as block to text, the reverse of split_fields. text is
+/// returned as it is if there are no fields.
+pub fn join_fields(text: &str, fields: &[(String, String)]) -> String {
+    if fields.is_empty() {
+        return text.to_string();
+    }
+    let mut lines = vec![];
+    if !text.is_empty() {
+        lines.push(text.to_string());
+    }
+    lines.push(CUSTOM_FIELDS_HEADER.to_string());
+    for &(ref name, ref value) in fields {
+        lines.push(format!("{}: {}", name, escape(value)));
+    }
+    lines.push(CUSTOM_FIELDS_FOOTER.to_string());
+    lines.join("\n")
+}
+
+fn escape(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+fn unescape(escaped: &str) -> String {
+    let mut value = String::with_capacity(escaped.len());
+    let mut chars = escaped.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            value.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => value.push('\n'),
+            Some('r') => value.push('\r'),
+            Some(c) => value.push(c),
+            None => value.push('\\'),
+        }
+    }
+    value
+}
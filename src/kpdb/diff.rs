@@ -0,0 +1,185 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use uuid::Uuid;
+
+use kpdb::v1group::V1Group;
+use kpdb::v1kpdb::V1Kpdb;
+
+/// A field of an entry which may differ between two versions
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EntryField {
+    Title,
+    Url,
+    Username,
+    Password,
+    Comment,
+    /// The ::secret:: sections of the notes
+    ProtectedNotes,
+    Image,
+    Expire,
+    /// Name or content of the attachment
+    Attachment,
+    /// The entry is in another group
+    Group,
+}
+
+/// A field of a group which may differ between two versions
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GroupField {
+    Title,
+    Image,
+    Expire,
+    Flags,
+    Notes,
+    CustomData,
+    /// The group has another parent
+    Parent,
+}
+
+/// An entry which is in both databases but differs
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EntryChange {
+    pub uuid: Uuid,
+    /// Title in the second database
+    pub title: String,
+    pub fields: Vec<EntryField>,
+}
+
+/// A group which is in both databases but differs
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GroupChange {
+    pub id: u32,
+    /// Title in the second database
+    pub title: String,
+    pub fields: Vec<GroupField>,
+}
+
+#[doc = "
+DbDiff describes how the second database of diff differs from the first
+one, e.g. what a sync would change. Entries are compared by UUID and
+groups by id, not by their position. Only the names of changed fields
+are listed, so a diff can be logged without leaking secrets. Meta
+entries and times of access and modification aren't compared.
+"]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DbDiff {
+    /// Ids of the groups which are only in the second database
+    pub added_groups: Vec<u32>,
+    /// Ids of the groups which are only in the first database
+    pub removed_groups: Vec<u32>,
+    pub modified_groups: Vec<GroupChange>,
+    /// Entries which are only in the second database
+    pub added_entries: Vec<Uuid>,
+    /// Entries which are only in the first database
+    pub removed_entries: Vec<Uuid>,
+    pub modified_entries: Vec<EntryChange>,
+}
+
+impl DbDiff {
+    /// True if both databases hold the same groups and entries
+    pub fn is_empty(&self) -> bool {
+        self.added_groups.is_empty() && self.removed_groups.is_empty() &&
+        self.modified_groups.is_empty() && self.added_entries.is_empty() &&
+        self.removed_entries.is_empty() && self.modified_entries.is_empty()
+    }
+}
+
+/// Compare a with b, see DbDiff
+pub fn diff(a: &V1Kpdb, b: &V1Kpdb) -> DbDiff {
+    let mut diff = DbDiff {
+        added_groups: vec![],
+        removed_groups: vec![],
+        modified_groups: vec![],
+        added_entries: vec![],
+        removed_entries: vec![],
+        modified_entries: vec![],
+    };
+
+    for group in a.groups.iter() {
+        let id = group.borrow().id;
+        if b.group_by_id(id).is_none() {
+            diff.removed_groups.push(id);
+        }
+    }
+    for new in b.groups.iter() {
+        let id = new.borrow().id;
+        match a.group_by_id(id) {
+            Some(old) => {
+                let fields = group_changes(&old.borrow(), &new.borrow());
+                if !fields.is_empty() {
+                    diff.modified_groups.push(GroupChange {
+                        id: id,
+                        title: new.borrow().title.clone(),
+                        fields: fields,
+                    });
+                }
+            }
+            None => diff.added_groups.push(id),
+        }
+    }
+
+    for entry in a.entries.iter() {
+        let uuid = entry.borrow().uuid;
+        if b.find_by_uuid(&uuid).is_none() {
+            diff.removed_entries.push(uuid);
+        }
+    }
+    for new in b.entries.iter() {
+        let uuid = new.borrow().uuid;
+        let old = match a.find_by_uuid(&uuid) {
+            Some(old) => old,
+            None => {
+                diff.added_entries.push(uuid);
+                continue;
+            }
+        };
+        // Comparing an entry with itself would borrow it twice
+        if Rc::ptr_eq(&old, new) {
+            continue;
+        }
+        let mut fields = old.borrow_mut().changed_fields(&mut new.borrow_mut());
+        if old.borrow().group_id != new.borrow().group_id {
+            fields.push(EntryField::Group);
+        }
+        if !fields.is_empty() {
+            diff.modified_entries.push(EntryChange {
+                uuid: uuid,
+                title: new.borrow().title.clone(),
+                fields: fields,
+            });
+        }
+    }
+    diff
+}
+
+fn group_changes(old: &V1Group, new: &V1Group) -> Vec<GroupField> {
+    let mut fields = vec![];
+    if old.title != new.title {
+        fields.push(GroupField::Title);
+    }
+    if old.image != new.image {
+        fields.push(GroupField::Image);
+    }
+    if old.expire != new.expire {
+        fields.push(GroupField::Expire);
+    }
+    if old.flags != new.flags {
+        fields.push(GroupField::Flags);
+    }
+    if old.notes != new.notes {
+        fields.push(GroupField::Notes);
+    }
+    if old.custom_data != new.custom_data {
+        fields.push(GroupField::CustomData);
+    }
+    if parent_id(&old.parent) != parent_id(&new.parent) {
+        fields.push(GroupField::Parent);
+    }
+    fields
+}
+
+// The root group has id 0
+fn parent_id(parent: &Option<Rc<RefCell<V1Group>>>) -> u32 {
+    parent.as_ref().map_or(0, |p| p.borrow().id)
+}
@@ -0,0 +1,139 @@
+use std::str;
+
+use kpdb::common::url_host;
+use kpdb::v1error::V1KpdbError;
+
+/// Name of the meta stream which holds the equivalent domains
+pub const EQUIVALENT_DOMAINS_STREAM: &'static str = "RKP_EQUIVALENT_DOMAINS";
+
+// Public suffixes which consist of more than one label. This is only the
+// commonly used part of the public suffix list (https://publicsuffix.org),
+// every other suffix is assumed to be the last label of the host.
+const MULTI_LABEL_SUFFIXES: &'static [&'static str] = &["co.uk", "org.uk", "ac.uk", "gov.uk",
+                                                        "me.uk", "ltd.uk", "plc.uk", "co.jp",
+                                                        "ne.jp", "or.jp", "ac.jp", "com.au",
+                                                        "net.au", "org.au", "edu.au", "co.nz",
+                                                        "org.nz", "com.br", "com.cn", "com.mx",
+                                                        "com.tr", "com.tw", "com.hk", "com.sg",
+                                                        "co.in", "co.kr", "co.za", "co.il",
+                                                        "com.ar", "com.pl", "github.io",
+                                                        "blogspot.com", "appspot.com",
+                                                        "herokuapp.com"];
+
+/// Reduce a host to its registrable domain, i.e. the public suffix plus
+/// one label: "smile.amazon.co.uk" => "amazon.co.uk". IP addresses and
+/// single label hosts are returned unchanged.
+pub fn registrable_domain(host: &str) -> String {
+    let host = host.trim_right_matches('.').to_lowercase();
+    if host.starts_with('[') || host.chars().all(|c| c.is_digit(10) || c == '.') {
+        return host;
+    }
+
+    let labels: Vec<&str> = host.split('.').collect();
+    let mut suffix_labels = 1;
+    for suffix in MULTI_LABEL_SUFFIXES {
+        if host == *suffix || host.ends_with(&format!(".{}", suffix)) {
+            suffix_labels = suffix.split('.').count();
+            break;
+        }
+    }
+    if labels.len() <= suffix_labels {
+        return host;
+    }
+    labels[labels.len() - suffix_labels - 1..].join(".")
+}
+
+#[doc = "
+EquivalentDomains holds sets of domains which belong to the same
+service, e.g. amazon.com and amazon.de. Domains are compared by their
+registrable domain, hence www.amazon.de is equivalent to amazon.com,
+too. V1Kpdb keeps them in the meta stream EQUIVALENT_DOMAINS_STREAM.
+"]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct EquivalentDomains {
+    sets: Vec<Vec<String>>,
+}
+
+impl EquivalentDomains {
+    /// Create an empty configuration
+    pub fn new() -> EquivalentDomains {
+        EquivalentDomains { sets: vec![] }
+    }
+
+    /// Declare all given domains as equivalent. If one of the domains
+    /// is already part of a set, the sets are merged.
+    pub fn add(&mut self, domains: Vec<String>) {
+        let mut new_set: Vec<String> = vec![];
+        for domain in domains.iter() {
+            let domain = registrable_domain(domain);
+            if !new_set.contains(&domain) {
+                new_set.push(domain);
+            }
+        }
+
+        let mut index = 0;
+        while index < self.sets.len() {
+            if self.sets[index].iter().any(|d| new_set.contains(d)) {
+                for domain in self.sets.remove(index) {
+                    if !new_set.contains(&domain) {
+                        new_set.push(domain);
+                    }
+                }
+            } else {
+                index += 1;
+            }
+        }
+
+        if new_set.len() > 1 {
+            self.sets.push(new_set);
+        }
+    }
+
+    /// Remove the set domain belongs to
+    pub fn remove(&mut self, domain: &str) {
+        let domain = registrable_domain(domain);
+        self.sets.retain(|set| !set.contains(&domain));
+    }
+
+    /// All sets of equivalent domains
+    pub fn sets(&self) -> &Vec<Vec<String>> {
+        &self.sets
+    }
+
+    /// Check if the hosts of url1 and url2 belong to the same service
+    pub fn urls_match(&self, url1: &str, url2: &str) -> bool {
+        match (url_host(url1), url_host(url2)) {
+            (Some(host1), Some(host2)) => self.hosts_match(&host1, &host2),
+            _ => false,
+        }
+    }
+
+    /// Check if host1 and host2 belong to the same service
+    pub fn hosts_match(&self, host1: &str, host2: &str) -> bool {
+        let domain1 = registrable_domain(host1);
+        let domain2 = registrable_domain(host2);
+        if domain1 == domain2 {
+            return true;
+        }
+        self.sets.iter().any(|set| set.contains(&domain1) && set.contains(&domain2))
+    }
+    /// The stream is one line per set with its domains separated by
+    /// spaces
+    pub fn encode(&self) -> Vec<u8> {
+        let mut data = String::new();
+        for set in self.sets.iter() {
+            data.push_str(&set.join(" "));
+            data.push('\n');
+        }
+        data.into_bytes()
+    }
+
+    pub fn decode(data: &[u8]) -> Result<EquivalentDomains, V1KpdbError> {
+        let text = try!(str::from_utf8(data).map_err(|_| V1KpdbError::MetaErr));
+        let mut domains = EquivalentDomains::new();
+        for line in text.lines() {
+            domains.add(line.split_whitespace().map(|d| d.to_string()).collect());
+        }
+        Ok(domains)
+    }
+}
@@ -0,0 +1,191 @@
+use std::collections::BTreeMap;
+
+use chrono::{DateTime, Local};
+use rustc_serialize::hex::FromHex;
+use serde::de::Error;
+use serde::ser::{SerializeSeq, SerializeStruct};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use uuid::Uuid;
+
+use kpdb::v1entry::V1Entry;
+use kpdb::v1group::V1Group;
+use kpdb::v1kpdb::V1Kpdb;
+use sec_str::{SecureBytes, SecureString};
+
+// Times are serialized as RFC 3339 strings, UUIDs as 32 hex digits.
+// Groups don't hold their parent but their level, entries their group_id,
+// the same way the KeePass 1.x format links them.
+
+#[doc = "
+FlatTree is the serializable form of the groups and entries of a
+database. The group tree is made of Rc and Weak references, hence the
+groups are listed in tree order with their level instead, like in a
+KeePass 1.x file, and entries refer to their group by group_id.
+
+Serializing a V1Kpdb gives this form. It holds the passwords in plain
+text. Deserialize it and pass it to V1Kpdb::set_tree, e.g. to load a
+fixture in a test.
+"]
+#[derive(Deserialize)]
+pub struct FlatTree {
+    pub groups: Vec<V1Group>,
+    pub entries: Vec<V1Entry>,
+}
+
+impl Serialize for V1Kpdb {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut tree = try!(serializer.serialize_struct("FlatTree", 2));
+        try!(tree.serialize_field("groups", &Groups(self)));
+        try!(tree.serialize_field("entries", &Entries(self)));
+        tree.end()
+    }
+}
+
+struct Groups<'a>(&'a V1Kpdb);
+
+impl<'a> Serialize for Groups<'a> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut seq = try!(serializer.serialize_seq(Some(self.0.groups.len())));
+        for group in self.0.groups.iter() {
+            try!(seq.serialize_element(&*group.borrow()));
+        }
+        seq.end()
+    }
+}
+
+struct Entries<'a>(&'a V1Kpdb);
+
+impl<'a> Serialize for Entries<'a> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut seq = try!(serializer.serialize_seq(Some(self.0.entries.len())));
+        for entry in self.0.entries.iter() {
+            try!(seq.serialize_element(&*entry.borrow()));
+        }
+        seq.end()
+    }
+}
+
+impl Serialize for V1Group {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut group = try!(serializer.serialize_struct("V1Group", 11));
+        try!(group.serialize_field("id", &self.id));
+        try!(group.serialize_field("title", &self.title));
+        try!(group.serialize_field("image", &self.image));
+        try!(group.serialize_field("level", &self.level));
+        try!(group.serialize_field("creation", &self.creation.to_rfc3339()));
+        try!(group.serialize_field("last_mod", &self.last_mod.to_rfc3339()));
+        try!(group.serialize_field("last_access", &self.last_access.to_rfc3339()));
+        try!(group.serialize_field("expire", &self.expire.to_rfc3339()));
+        try!(group.serialize_field("flags", &self.flags));
+        try!(group.serialize_field("notes", &self.notes));
+        try!(group.serialize_field("custom_data", &self.custom_data));
+        group.end()
+    }
+}
+
+#[derive(Deserialize)]
+struct GroupData {
+    id: u32,
+    title: String,
+    image: u32,
+    level: u16,
+    creation: String,
+    last_mod: String,
+    last_access: String,
+    expire: String,
+    flags: u32,
+    notes: Option<String>,
+    #[serde(default)]
+    custom_data: BTreeMap<String, String>,
+}
+
+impl<'de> Deserialize<'de> for V1Group {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<V1Group, D::Error> {
+        let data = try!(GroupData::deserialize(deserializer));
+        let mut group = V1Group::new();
+        group.id = data.id;
+        group.title = data.title;
+        group.image = data.image;
+        group.level = data.level;
+        group.creation = try!(parse_time(&data.creation));
+        group.last_mod = try!(parse_time(&data.last_mod));
+        group.last_access = try!(parse_time(&data.last_access));
+        group.expire = try!(parse_time(&data.expire));
+        group.flags = data.flags;
+        group.notes = data.notes;
+        group.custom_data = data.custom_data;
+        Ok(group)
+    }
+}
+
+impl Serialize for V1Entry {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut entry = try!(serializer.serialize_struct("V1Entry", 15));
+        try!(entry.serialize_field("uuid", &self.uuid.to_simple_string()));
+        try!(entry.serialize_field("group_id", &self.group_id));
+        try!(entry.serialize_field("image", &self.image));
+        try!(entry.serialize_field("title", &self.title));
+        try!(entry.serialize_field("url", &self.url));
+        try!(entry.serialize_field("username", &self.username));
+        try!(entry.serialize_field("password", &self.raw_password()));
+        try!(entry.serialize_field("comment", &self.comment));
+        try!(entry.serialize_field("protected_notes", &self.protected_notes));
+        try!(entry.serialize_field("binary_desc", &self.binary_desc));
+        try!(entry.serialize_field("binary", &self.binary));
+        try!(entry.serialize_field("creation", &self.creation.to_rfc3339()));
+        try!(entry.serialize_field("last_mod", &self.last_mod.to_rfc3339()));
+        try!(entry.serialize_field("last_access", &self.last_access.to_rfc3339()));
+        try!(entry.serialize_field("expire", &self.expire.to_rfc3339()));
+        entry.end()
+    }
+}
+
+#[derive(Deserialize)]
+struct EntryData {
+    uuid: String,
+    group_id: u32,
+    image: u32,
+    title: String,
+    url: Option<String>,
+    username: Option<SecureString>,
+    password: Option<SecureString>,
+    comment: Option<String>,
+    #[serde(default)]
+    protected_notes: Vec<SecureString>,
+    binary_desc: Option<String>,
+    binary: Option<SecureBytes>,
+    creation: String,
+    last_mod: String,
+    last_access: String,
+    expire: String,
+}
+
+impl<'de> Deserialize<'de> for V1Entry {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<V1Entry, D::Error> {
+        let data = try!(EntryData::deserialize(deserializer));
+        let uuid = try!(data.uuid.from_hex().map_err(|_| D::Error::custom("invalid UUID")));
+        let mut entry = V1Entry::new();
+        entry.uuid = try!(Uuid::from_bytes(&uuid).ok_or(D::Error::custom("invalid UUID")));
+        entry.group_id = data.group_id;
+        entry.image = data.image;
+        entry.title = data.title;
+        entry.url = data.url;
+        entry.username = data.username;
+        entry.replace_raw_password(data.password);
+        entry.comment = data.comment;
+        entry.protected_notes = data.protected_notes;
+        entry.binary_desc = data.binary_desc;
+        entry.binary = data.binary;
+        entry.creation = try!(parse_time(&data.creation));
+        entry.last_mod = try!(parse_time(&data.last_mod));
+        entry.last_access = try!(parse_time(&data.last_access));
+        entry.expire = try!(parse_time(&data.expire));
+        Ok(entry)
+    }
+}
+
+fn parse_time<E: Error>(time: &str) -> Result<DateTime<Local>, E> {
+    DateTime::parse_from_rfc3339(time)
+        .map(|t| t.with_timezone(&Local))
+        .map_err(|_| E::custom("invalid RFC 3339 time"))
+}
@@ -0,0 +1,47 @@
+use std::io::Write;
+
+use openssl::crypto::hash::{Hasher, Type};
+
+use kpdb::v1entry::V1Entry;
+
+/// When V1Kpdb::find_duplicates considers two entries duplicates
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DuplicateCriteria {
+    /// Same title, username and URL, e.g. after importing the same CSV
+    /// file twice
+    Fields,
+    /// Same non-empty password
+    Password,
+}
+
+/// The SHA-256 hash of the fields of entry compared by criteria, None if
+/// entry can't be a duplicate, i.e. it has no password under Password.
+/// Hashes are compared instead of the fields, so no plaintext copies of
+/// usernames or passwords are kept.
+pub fn duplicate_key(entry: &mut V1Entry, criteria: DuplicateCriteria) -> Option<Vec<u8>> {
+    let mut hasher = Hasher::new(Type::SHA256);
+    match criteria {
+        DuplicateCriteria::Fields => {
+            // A separator which can't occur in the fields keeps e.g.
+            // "ab" + "c" apart from "a" + "bc"
+            let _ = hasher.write_all(entry.title.as_bytes());
+            let _ = hasher.write_all(&[0]);
+            if let Some(ref username) = entry.username() {
+                let _ = hasher.write_all(username.as_bytes());
+            }
+            let _ = hasher.write_all(&[0]);
+            if let Some(ref url) = entry.url {
+                let _ = hasher.write_all(url.as_bytes());
+            }
+        }
+        DuplicateCriteria::Password => {
+            match entry.raw_password_mut().map(|p| p.unlocked()) {
+                Some(ref password) if !password.is_empty() => {
+                    let _ = hasher.write_all(password.as_bytes());
+                }
+                _ => return None,
+            }
+        }
+    }
+    Some(hasher.finish())
+}
@@ -0,0 +1,163 @@
+use std::error;
+use std::fmt;
+use std::io;
+
+use kpdb::v1error::V1KpdbError;
+
+/// A field of the database header
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HeaderField {
+    /// The two signatures at the start of the file
+    Signature,
+    /// The flags of the encryption algorithm
+    EncFlag,
+    Version,
+    /// Number of key transformation rounds
+    KeyTransfRounds,
+}
+
+impl HeaderField {
+    fn name(&self) -> &'static str {
+        match *self {
+            HeaderField::Signature => "signature",
+            HeaderField::EncFlag => "encryption flags",
+            HeaderField::Version => "version",
+            HeaderField::KeyTransfRounds => "key transformation rounds",
+        }
+    }
+}
+
+#[doc = "
+KpdbError is returned by V1Kpdb::load_detailed. Unlike V1KpdbError it
+carries the context of the failure, so applications can show sensible
+messages: a wrong key is told apart from a damaged file, invalid header
+fields are named and I/O errors are kept.
+
+Only loading from a file reports these details so far. V1KpdbError stays
+the error of the rest of the API, including load, load_from_bytes and
+save, and coarse maps a KpdbError back to it.
+"]
+#[derive(Debug)]
+pub enum KpdbError {
+    /// The password, keyfile or another key component is wrong. The
+    /// content neither passed the checks nor looked like groups and
+    /// entries.
+    WrongKey,
+    /// The file couldn't be opened or read. error is FileErr or ReadErr.
+    Io {
+        error: V1KpdbError,
+        source: io::Error,
+    },
+    /// The file is too small to hold a header
+    TooSmall,
+    /// A header field holds an unsupported value
+    InvalidHeader {
+        field: HeaderField,
+        error: V1KpdbError,
+    },
+    /// The key was right but the content is damaged. offset is the byte
+    /// offset in the decrypted content where parsing failed, if known.
+    /// load_with_recovery may be able to salvage the rest.
+    Corrupt {
+        offset: Option<usize>,
+        error: V1KpdbError,
+    },
+    /// Anything else, e.g. a failing key provider
+    Other(V1KpdbError),
+}
+
+impl KpdbError {
+    /// The corresponding V1KpdbError. A wrong key gives DecryptErr.
+    pub fn coarse(&self) -> V1KpdbError {
+        match *self {
+            KpdbError::WrongKey => V1KpdbError::DecryptErr,
+            KpdbError::Io { error, .. } => error,
+            KpdbError::TooSmall => V1KpdbError::FileErr,
+            KpdbError::InvalidHeader { error, .. } => error,
+            KpdbError::Corrupt { error, .. } => error,
+            KpdbError::Other(error) => error,
+        }
+    }
+
+    /// True if the key is wrong, i.e. the user should try again
+    pub fn is_wrong_key(&self) -> bool {
+        match *self {
+            KpdbError::WrongKey => true,
+            _ => false,
+        }
+    }
+
+    /// True if the file is damaged or no KeePass 1.x database
+    pub fn is_corrupt(&self) -> bool {
+        match *self {
+            KpdbError::TooSmall | KpdbError::InvalidHeader { .. } | KpdbError::Corrupt { .. } => true,
+            _ => false,
+        }
+    }
+}
+
+impl From<V1KpdbError> for KpdbError {
+    fn from(error: V1KpdbError) -> KpdbError {
+        match error {
+            V1KpdbError::SignatureErr => header_error(HeaderField::Signature, error),
+            V1KpdbError::EncFlagErr => header_error(HeaderField::EncFlag, error),
+            V1KpdbError::VersionErr => header_error(HeaderField::Version, error),
+            V1KpdbError::RoundsErr => header_error(HeaderField::KeyTransfRounds, error),
+            V1KpdbError::ConvertErr | V1KpdbError::OffsetErr | V1KpdbError::TreeErr |
+            V1KpdbError::MetaErr => {
+                KpdbError::Corrupt {
+                    offset: None,
+                    error: error,
+                }
+            }
+            _ => KpdbError::Other(error),
+        }
+    }
+}
+
+fn header_error(field: HeaderField, error: V1KpdbError) -> KpdbError {
+    KpdbError::InvalidHeader {
+        field: field,
+        error: error,
+    }
+}
+
+impl fmt::Display for KpdbError {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            KpdbError::WrongKey => fmt.write_str("Wrong password or keyfile"),
+            KpdbError::Io { ref source, .. } => write!(fmt, "Couldn't read database: {}", source),
+            KpdbError::TooSmall => fmt.write_str("File is too small to be a database"),
+            KpdbError::InvalidHeader { field, .. } => {
+                write!(fmt, "Invalid {} in database header", field.name())
+            }
+            KpdbError::Corrupt { offset: Some(offset), error } => {
+                write!(fmt, "Database is damaged at byte {}: {}", offset, error)
+            }
+            KpdbError::Corrupt { offset: None, error } => {
+                write!(fmt, "Database is damaged: {}", error)
+            }
+            KpdbError::Other(error) => write!(fmt, "{}", error),
+        }
+    }
+}
+
+impl error::Error for KpdbError {
+    fn description(&self) -> &str {
+        match *self {
+            KpdbError::WrongKey => "Wrong password or keyfile",
+            KpdbError::Io { .. } => "Couldn't read database",
+            KpdbError::TooSmall => "File is too small to be a database",
+            KpdbError::InvalidHeader { .. } => "Invalid database header",
+            KpdbError::Corrupt { .. } => "Database is damaged",
+            KpdbError::Other(ref error) => error::Error::description(error),
+        }
+    }
+
+    fn cause(&self) -> Option<&error::Error> {
+        match *self {
+            KpdbError::Io { ref source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
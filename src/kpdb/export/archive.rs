@@ -0,0 +1,201 @@
+//! Passphrase-encrypted archives of a whole database
+//!
+//! An archive holds the groups and entries, meta streams and attachments
+//! included, in the plaintext format of the KDB file, but it doesn't
+//! depend on the header of the file, its key or its cipher: it's
+//! encrypted with a passphrase alone and can be restored into any
+//! database, see V1Kpdb::export_encrypted and V1Kpdb::import_encrypted.
+//!
+//! The layout, all numbers little endian:
+//!
+//! * 8 bytes MAGIC
+//! * 4 bytes number of PBKDF2 iterations
+//! * 32 bytes salt
+//! * 16 bytes IV
+//! * the content, encrypted with AES-256-CBC and PKCS#7 padding
+//! * 32 bytes HMAC-SHA256 of everything before
+//!
+//! Both keys come from PBKDF2-HMAC-SHA256 of the passphrase and the salt,
+//! the first 32 bytes encrypt, the last 32 authenticate. The tag is
+//! checked before anything is decrypted, a wrong passphrase and a
+//! changed byte both give HashErr.
+
+use std::cmp;
+use std::io::{Read, Write};
+
+use openssl::crypto::hash::Type;
+use openssl::crypto::hmac::{hmac, HMAC};
+use openssl::crypto::symm;
+use rand::{OsRng, Rng};
+
+use kpdb::crypter::{constant_time_eq, Crypter};
+use kpdb::v1error::V1KpdbError;
+use kpdb::v1parser::{parse_decrypted, Tree};
+use super::super::super::mem_protect;
+use super::super::super::sec_str::SecureBytes;
+
+/// First bytes of every archive
+pub const MAGIC: &'static [u8] = b"KPDBARC1";
+
+/// Iterations of PBKDF2 export_encrypted uses
+pub const DEFAULT_ITERATIONS: u32 = 600000;
+
+/// Archives with more iterations are rejected, so that a crafted file
+/// can't keep read busy for hours
+pub const MAX_ITERATIONS: u32 = 100000000;
+
+const SALT_LEN: usize = 32;
+const IV_LEN: usize = 16;
+const HEADER_LEN: usize = 60;
+const TAG_LEN: usize = 32;
+// Ciphertext written at once
+const CHUNK_LEN: usize = 65536;
+
+/// PBKDF2 with HMAC-SHA256 (RFC 8018) of password and salt, len bytes.
+/// The result is locked and zeroed on drop.
+pub fn pbkdf2_sha256(password: &[u8], salt: &[u8], iterations: u32, len: usize) -> SecureBytes {
+    // Locked before the first block is derived
+    let mut derived = SecureBytes::new(vec![0u8; len]);
+    let mut pos = 0;
+    let mut block = 1u32;
+    while pos < len {
+        let mut input = salt.to_vec();
+        input.extend_from_slice(&[(block >> 24) as u8, (block >> 16) as u8, (block >> 8) as u8,
+                                  block as u8]);
+        let mut u = hmac(Type::SHA256, password, &input);
+        let mut t = u.clone();
+        mem_protect::lock(&t, "pbkdf2 block");
+        for _ in 1..iterations {
+            let next = hmac(Type::SHA256, password, &u);
+            unsafe {
+                mem_protect::zero(&u);
+            }
+            u = next;
+            for (t, u) in t.iter_mut().zip(u.iter()) {
+                *t ^= *u;
+            }
+        }
+        let end = cmp::min(pos + t.len(), len);
+        derived.bytes_mut()[pos..end].copy_from_slice(&t[..end - pos]);
+        pos = end;
+        unsafe {
+            mem_protect::zero(&u);
+            mem_protect::zero(&t);
+        }
+        mem_protect::unlock(&t);
+        block += 1;
+    }
+    derived
+}
+
+/// Encrypt the plaintext format of a database with passphrase and write
+/// it to writer as an archive. content is called with a function which
+/// takes the plaintext piece by piece, e.g. SaveParser::stream, so it's
+/// never held as a whole and each piece can be zeroed out once it's
+/// encrypted. RoundsErr for 0 or more than MAX_ITERATIONS iterations,
+/// RngErr if no salt can be generated, WriteErr if writer fails.
+pub fn write<W, F>(mut writer: W,
+                   passphrase: &[u8],
+                   iterations: u32,
+                   content: F)
+                   -> Result<(), V1KpdbError>
+    where W: Write,
+          F: FnOnce(&mut FnMut(&[u8]))
+{
+    if iterations == 0 || iterations > MAX_ITERATIONS {
+        return Err(V1KpdbError::RoundsErr);
+    }
+    let mut rng = try!(OsRng::new().map_err(|_| V1KpdbError::RngErr));
+    let mut salt = vec![0u8; SALT_LEN];
+    rng.fill_bytes(&mut salt);
+    let mut iv = vec![0u8; IV_LEN];
+    rng.fill_bytes(&mut iv);
+
+    let keys = pbkdf2_sha256(passphrase, &salt, iterations, 64);
+    let (enc_key, mac_key) = keys.bytes().split_at(32);
+    let mut header = MAGIC.to_vec();
+    header.extend_from_slice(&[iterations as u8,
+                               (iterations >> 8) as u8,
+                               (iterations >> 16) as u8,
+                               (iterations >> 24) as u8]);
+    header.extend_from_slice(&salt);
+    header.extend_from_slice(&iv);
+
+    let mut mac = HMAC::new(Type::SHA256, mac_key);
+    try!(mac.write_all(&header).map_err(|_| V1KpdbError::WriteErr));
+    try!(writer.write_all(&header).map_err(|_| V1KpdbError::WriteErr));
+
+    let crypter = symm::Crypter::new(symm::Type::AES_256_CBC);
+    crypter.pad(true);
+    crypter.init(symm::Mode::Encrypt, enc_key, iv);
+    // Ciphertext is collected up to CHUNK_LEN, the pieces are small
+    let mut ciphertext = Vec::with_capacity(CHUNK_LEN + 16);
+    let mut result = Ok(());
+    {
+        let mut sink = |plaintext: &[u8]| {
+            if result.is_err() {
+                return;
+            }
+            ciphertext.extend(crypter.update(plaintext));
+            if ciphertext.len() >= CHUNK_LEN {
+                result = write_ciphertext(&mut writer, &mut mac, &ciphertext);
+                ciphertext.clear();
+            }
+        };
+        content(&mut sink);
+    }
+    try!(result);
+    ciphertext.extend(crypter.finalize());
+    try!(write_ciphertext(&mut writer, &mut mac, &ciphertext));
+    try!(writer.write_all(&mac.finish()).map_err(|_| V1KpdbError::WriteErr));
+    writer.flush().map_err(|_| V1KpdbError::WriteErr)
+}
+
+fn write_ciphertext<W: Write>(writer: &mut W,
+                              mac: &mut HMAC,
+                              ciphertext: &[u8])
+                              -> Result<(), V1KpdbError> {
+    try!(mac.write_all(ciphertext).map_err(|_| V1KpdbError::WriteErr));
+    writer.write_all(ciphertext).map_err(|_| V1KpdbError::WriteErr)
+}
+
+/// Read an archive written by write and decrypt it with passphrase. The
+/// content is locked and zeroed on drop. ReadErr if reader fails,
+/// SignatureErr if it isn't an archive, FileErr if it's cut short,
+/// RoundsErr for an invalid number of iterations, HashErr for a wrong
+/// passphrase or a changed archive.
+pub fn decrypt<R: Read>(mut reader: R, passphrase: &[u8]) -> Result<SecureBytes, V1KpdbError> {
+    let mut data = vec![];
+    try!(reader.read_to_end(&mut data).map_err(|_| V1KpdbError::ReadErr));
+    if data.len() < MAGIC.len() || &data[..MAGIC.len()] != MAGIC {
+        return Err(V1KpdbError::SignatureErr);
+    }
+    // At least one block of ciphertext as it's padded
+    if data.len() < HEADER_LEN + IV_LEN + TAG_LEN {
+        return Err(V1KpdbError::FileErr);
+    }
+    let iterations = (data[8] as u32) | (data[9] as u32) << 8 | (data[10] as u32) << 16 |
+                     (data[11] as u32) << 24;
+    if iterations == 0 || iterations > MAX_ITERATIONS {
+        return Err(V1KpdbError::RoundsErr);
+    }
+    let salt = &data[12..12 + SALT_LEN];
+    let iv = data[12 + SALT_LEN..HEADER_LEN].to_vec();
+    let (authenticated, tag) = data.split_at(data.len() - TAG_LEN);
+
+    let keys = pbkdf2_sha256(passphrase, salt, iterations, 64);
+    let (enc_key, mac_key) = keys.bytes().split_at(32);
+    if !constant_time_eq(&hmac(Type::SHA256, mac_key, authenticated), tag) {
+        return Err(V1KpdbError::HashErr);
+    }
+    // Straight into a locked buffer of the final size
+    let content = try!(Crypter::decrypt_locked(enc_key, iv, &authenticated[HEADER_LEN..]));
+    Ok(SecureBytes::from_locked(content))
+}
+
+/// Like decrypt, but parse the content into the groups and entries. See
+/// v1parser::parse_decrypted for the errors of malformed content.
+pub fn read<R: Read>(reader: R, passphrase: &[u8]) -> Result<Tree, V1KpdbError> {
+    let content = try!(decrypt(reader, passphrase));
+    parse_decrypted(content.bytes())
+}
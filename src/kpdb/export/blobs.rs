@@ -0,0 +1,166 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs::File;
+use std::io::Write;
+use std::path::Path;
+
+use rustc_serialize::json::{self, Json, ToJson};
+use uuid::Uuid;
+
+use kpdb::crypter::CompositeKey;
+use kpdb::export::group_titles;
+use kpdb::generator::PasswordGenerator;
+use kpdb::import::create_groups;
+use kpdb::v1entry::V1Entry;
+use kpdb::v1error::V1KpdbError;
+use kpdb::v1kpdb::V1Kpdb;
+use sec_str::SecureString;
+
+/// Name of the index written next to the blobs
+pub const MANIFEST_FILE: &'static str = "index.json";
+
+// Group of exported entries which aren't in a group
+const EXPORT_GROUP_TITLE: &'static str = "Export";
+
+/// Decides with which key the blob of an entry is encrypted
+pub trait PassphrasePolicy {
+    fn key_for(&mut self, entry: &V1Entry) -> Result<CompositeKey, V1KpdbError>;
+}
+
+/// Encrypts all blobs with the same passphrase
+pub struct SharedPassphrase {
+    passphrase: SecureString,
+}
+
+impl SharedPassphrase {
+    pub fn new(passphrase: SecureString) -> SharedPassphrase {
+        SharedPassphrase { passphrase: passphrase }
+    }
+}
+
+impl PassphrasePolicy for SharedPassphrase {
+    fn key_for(&mut self, _: &V1Entry) -> Result<CompositeKey, V1KpdbError> {
+        let passphrase = SecureString::new(self.passphrase.unlocked().to_string());
+        Ok(CompositeKey::from_credentials(Some(passphrase), None))
+    }
+}
+
+/// Encrypts every blob with a fresh passphrase of generator. Hand them
+/// to the recipients with take.
+pub struct GeneratedPassphrases {
+    pub generator: PasswordGenerator,
+    passphrases: HashMap<Uuid, SecureString>,
+}
+
+impl GeneratedPassphrases {
+    pub fn new(generator: PasswordGenerator) -> GeneratedPassphrases {
+        GeneratedPassphrases {
+            generator: generator,
+            passphrases: HashMap::new(),
+        }
+    }
+
+    /// Remove and return the passphrase of the blob of the entry with
+    /// UUID uuid
+    pub fn take(&mut self, uuid: &Uuid) -> Option<SecureString> {
+        self.passphrases.remove(uuid)
+    }
+}
+
+impl PassphrasePolicy for GeneratedPassphrases {
+    fn key_for(&mut self, entry: &V1Entry) -> Result<CompositeKey, V1KpdbError> {
+        let mut passphrase = try!(self.generator.generate());
+        let copy = SecureString::new(passphrase.unlocked().to_string());
+        self.passphrases.insert(entry.uuid, passphrase);
+        Ok(CompositeKey::from_credentials(Some(copy), None))
+    }
+}
+
+impl<F> PassphrasePolicy for F
+    where F: FnMut(&V1Entry) -> Result<CompositeKey, V1KpdbError>
+{
+    fn key_for(&mut self, entry: &V1Entry) -> Result<CompositeKey, V1KpdbError> {
+        self(entry)
+    }
+}
+
+/// One blob written by per_entry_blobs
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BlobRecord {
+    /// UUID of the entry, kept in the blob
+    pub uuid: Uuid,
+    /// Title of the entry
+    pub title: String,
+    /// Titles of the groups from the top level down to the group of
+    /// the entry
+    pub group_path: Vec<String>,
+    /// Name of the blob in the export directory
+    pub file_name: String,
+}
+
+impl ToJson for BlobRecord {
+    fn to_json(&self) -> Json {
+        let mut object = BTreeMap::new();
+        object.insert("uuid".to_string(), self.uuid.to_simple_string().to_json());
+        object.insert("title".to_string(), self.title.to_json());
+        object.insert("group".to_string(), self.group_path.to_json());
+        object.insert("file".to_string(), self.file_name.to_json());
+        Json::Object(object)
+    }
+}
+
+/// Write every entry of db as a KeePass 1.x database of its own into
+/// dir, e.g. to hand single credentials to different recipients. Each
+/// blob holds the entry with its UUID in a copy of its group path and is
+/// encrypted with the key policy returns for it. Any KeePass 1.x client
+/// opens them, merge takes them back into another database.
+///
+/// The blobs are named by the UUID of the entry. MANIFEST_FILE lists
+/// UUID, title, group path and file name of each blob as JSON, no
+/// secrets. Existing files of the same names are replaced.
+pub fn per_entry_blobs(db: &V1Kpdb,
+                       dir: &Path,
+                       policy: &mut PassphrasePolicy)
+                       -> Result<Vec<BlobRecord>, V1KpdbError> {
+    let mut records = vec![];
+    for entry in db.entries.iter() {
+        let mut entry = entry.borrow_mut();
+        let key = try!(policy.key_for(&entry));
+        let file_name = format!("{}.kdb", entry.uuid.to_simple_string());
+        let path = try!(dir.join(&file_name).to_str().ok_or(V1KpdbError::FileErr)).to_string();
+        let mut blob = try!(V1Kpdb::with_key(path, key));
+        // Same format and key transformation rounds as db, save gives
+        // it seeds of its own
+        blob.header = db.header.clone();
+        blob.header.num_groups = 0;
+        blob.header.num_entries = 0;
+        blob.field_limits = db.field_limits.clone();
+
+        let mut group_path = group_titles(&entry.group);
+        if group_path.is_empty() {
+            group_path.push(EXPORT_GROUP_TITLE.to_string());
+        }
+        let group = try!(create_groups(&mut blob, &group_path));
+        let copy = blob.create_entry(group, String::new(), None, None, None, None, None, None);
+        {
+            let mut copy = copy.borrow_mut();
+            copy.copy_from(&mut entry);
+            // The blob is dropped right after saving, so its UUID index
+            // needn't know about the change
+            copy.uuid = entry.uuid;
+        }
+        try!(blob.save(None, None, None));
+
+        records.push(BlobRecord {
+            uuid: entry.uuid,
+            title: entry.title.clone(),
+            group_path: group_path,
+            file_name: file_name,
+        });
+    }
+
+    let manifest = format!("{}\n", json::as_pretty_json(&records.to_json()));
+    let mut file = try!(File::create(dir.join(MANIFEST_FILE)).map_err(|_| V1KpdbError::FileErr));
+    try!(file.write_all(manifest.as_bytes()).map_err(|_| V1KpdbError::WriteErr));
+    try!(file.sync_all().map_err(|_| V1KpdbError::WriteErr));
+    Ok(records)
+}
@@ -0,0 +1,103 @@
+use std::io::{self, Write};
+
+use kpdb::export::group_titles;
+use kpdb::v1error::V1KpdbError;
+use kpdb::v1kpdb::V1Kpdb;
+use mem_protect;
+
+pub use kpdb::import::csv::{CsvColumn, CsvMapping};
+
+/// Applications should show this before they export
+pub const PLAINTEXT_WARNING: &'static str = "The exported file contains all passwords in plain \
+                                             text. Anyone who can read it has access to your \
+                                             accounts. Delete it securely after use.";
+
+/// Write the entries of db to writer as CSV, laid out as described by
+/// mapping. Returns the number of entries written.
+///
+/// The output holds usernames, passwords and protected notes in plain
+/// text, see PLAINTEXT_WARNING. They are written straight from the
+/// decrypted fields, so no other copies are made. Buffers of writer are
+/// out of reach though.
+pub fn write<W: Write>(db: &V1Kpdb,
+                       writer: &mut W,
+                       mapping: &CsvMapping)
+                       -> Result<usize, V1KpdbError> {
+    try!(mapping.check());
+    write_entries(db, writer, mapping).map_err(|_| V1KpdbError::WriteErr)
+}
+
+fn write_entries<W: Write>(db: &V1Kpdb, writer: &mut W, mapping: &CsvMapping) -> io::Result<usize> {
+    if mapping.has_header {
+        for (i, column) in mapping.columns.iter().enumerate() {
+            if i > 0 {
+                try!(writer.write_all(&[mapping.delimiter]));
+            }
+            try!(write_field(writer, column.name(), mapping.delimiter));
+        }
+        try!(writer.write_all(b"\r\n"));
+    }
+
+    for entry in db.entries.iter() {
+        let mut entry = entry.borrow_mut();
+        for (i, column) in mapping.columns.iter().enumerate() {
+            if i > 0 {
+                try!(writer.write_all(&[mapping.delimiter]));
+            }
+            match *column {
+                CsvColumn::GroupPath => {
+                    let separator = mapping.group_separator.to_string();
+                    let path = group_titles(&entry.group).join(&separator);
+                    try!(write_field(writer, &path, mapping.delimiter));
+                }
+                CsvColumn::Title => try!(write_field(writer, &entry.title, mapping.delimiter)),
+                CsvColumn::Username => {
+                    if let Some(username) = entry.username() {
+                        try!(write_field(writer, &username, mapping.delimiter));
+                    }
+                }
+                CsvColumn::Password => {
+                    if let Some(password) = entry.password() {
+                        try!(write_field(writer, &password, mapping.delimiter));
+                    }
+                }
+                CsvColumn::Url => {
+                    if let Some(ref url) = entry.url {
+                        try!(write_field(writer, url, mapping.delimiter));
+                    }
+                }
+                CsvColumn::Notes => {
+                    if let Some(notes) = entry.markdown_notes() {
+                        let result = write_field(writer, &notes, mapping.delimiter);
+                        unsafe {
+                            mem_protect::zero(&notes);
+                        }
+                        try!(result);
+                    }
+                }
+                CsvColumn::Ignore => {}
+            }
+        }
+        try!(writer.write_all(b"\r\n"));
+    }
+    try!(writer.flush());
+    Ok(db.entries.len())
+}
+
+// Quote value if needed and double the quotes inside. The value is
+// written in pieces, so no escaped copy of it is made.
+fn write_field<W: Write>(writer: &mut W, value: &str, delimiter: u8) -> io::Result<()> {
+    let needs_quotes = value.bytes().any(|b| b == delimiter || b == b'"' || b == b'\r' || b == b'\n') ||
+                       value.starts_with(' ') || value.ends_with(' ');
+    if !needs_quotes {
+        return writer.write_all(value.as_bytes());
+    }
+    try!(writer.write_all(b"\""));
+    for (i, part) in value.split('"').enumerate() {
+        if i > 0 {
+            try!(writer.write_all(b"\"\""));
+        }
+        try!(writer.write_all(part.as_bytes()));
+    }
+    writer.write_all(b"\"")
+}
@@ -0,0 +1,27 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use kpdb::v1group::V1Group;
+
+pub mod archive;
+pub mod blobs;
+pub mod csv;
+
+pub use self::blobs::per_entry_blobs;
+
+/// Titles of the groups from the top level down to group
+pub fn group_titles(group: &Option<Rc<RefCell<V1Group>>>) -> Vec<String> {
+    let mut titles = vec![];
+    let mut current = group.clone();
+    while let Some(group) = current {
+        let group = group.borrow();
+        // The root group has id 0 and no title
+        if group.id == 0 {
+            break;
+        }
+        titles.push(group.title.clone());
+        current = group.parent.clone();
+    }
+    titles.reverse();
+    titles
+}
@@ -0,0 +1,101 @@
+//! Receive credentials over an inherited file descriptor
+//!
+//! Service managers can hand a password or keyfile to a process through
+//! a pipe or socket instead of the environment or the command line. The
+//! data is read straight into locked memory and the descriptor is closed
+//! afterwards.
+
+use std::fs::File;
+use std::io::{ErrorKind, Read};
+use std::os::unix::io::{FromRawFd, RawFd};
+
+use kpdb::v1error::V1KpdbError;
+use super::super::mem_protect;
+use super::super::sec_str::{SecureBytes, SecureString};
+
+// Larger inputs are rejected
+const MAX_FD_KEY_SIZE: usize = 1024 * 1024;
+
+/// Read a password from fd until EOF. One trailing newline is removed.
+///
+/// # Safety
+///
+/// fd has to be an open descriptor which nothing else owns, e.g. one
+/// inherited from the service manager. It's closed when the password is
+/// read, so it mustn't be used or closed elsewhere afterwards.
+pub unsafe fn password_from_fd(fd: RawFd) -> Result<SecureString, V1KpdbError> {
+    let mut data = try!(read_all(File::from_raw_fd(fd)));
+    if data.ends_with(b"\r\n") {
+        let len = data.len() - 2;
+        data.truncate(len);
+    } else if data.ends_with(b"\n") {
+        let len = data.len() - 1;
+        data.truncate(len);
+    }
+    // String::from_utf8 keeps the buffer, so no copy is made
+    match String::from_utf8(data) {
+        Ok(password) => Ok(SecureString::new(password)),
+        Err(e) => {
+            let data = e.into_bytes();
+            unsafe {
+                mem_protect::zero(&data);
+            }
+            mem_protect::unlock(&data);
+            Err(V1KpdbError::ReadErr)
+        }
+    }
+}
+
+/// Read the content of a keyfile from fd until EOF. Use the result as
+/// KeyComponent::KeyfileData.
+///
+/// # Safety
+///
+/// Like for password_from_fd, fd has to be an open descriptor which
+/// nothing else owns. It's closed afterwards.
+pub unsafe fn keyfile_from_fd(fd: RawFd) -> Result<SecureBytes, V1KpdbError> {
+    let data = try!(read_all(File::from_raw_fd(fd)));
+    Ok(SecureBytes::new(data))
+}
+
+// Read everything from file into a locked buffer. The buffer grows by
+// copying into a new locked buffer and wiping the old one, as Vec's own
+// reallocation would leave copies behind. file is closed on return.
+fn read_all(mut file: File) -> Result<Vec<u8>, V1KpdbError> {
+    let mut buf: Vec<u8> = vec![0; 4096];
+    mem_protect::lock(&buf, "buf");
+    let mut len = 0;
+    loop {
+        if len == buf.len() {
+            if buf.len() >= MAX_FD_KEY_SIZE {
+                wipe(&buf);
+                return Err(V1KpdbError::ReadErr);
+            }
+            let mut bigger: Vec<u8> = vec![0; buf.len() * 2];
+            mem_protect::lock(&bigger, "buf");
+            bigger[..len].copy_from_slice(&buf[..len]);
+            wipe(&buf);
+            buf = bigger;
+        }
+        match file.read(&mut buf[len..]) {
+            Ok(0) => break,
+            Ok(n) => len += n,
+            // A signal arrived before anything was read
+            Err(ref e) if e.kind() == ErrorKind::Interrupted => continue,
+            Err(_) => {
+                wipe(&buf);
+                return Err(V1KpdbError::ReadErr);
+            }
+        }
+    }
+    // Only the zeroes after the data are dropped
+    buf.truncate(len);
+    Ok(buf)
+}
+
+fn wipe(buf: &Vec<u8>) {
+    unsafe {
+        mem_protect::zero(buf);
+    }
+    mem_protect::unlock(buf);
+}
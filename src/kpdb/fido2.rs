@@ -0,0 +1,144 @@
+use kpdb::crypter::KeyProvider;
+use kpdb::v1error::V1KpdbError;
+use super::super::mem_protect;
+use super::super::sec_str::SecureString;
+
+/// Errors an authenticator reports for an hmac-secret request
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Fido2Error {
+    /// The authenticator needs a PIN (CTAP2_ERR_PIN_REQUIRED)
+    PinRequired,
+    /// The given PIN was wrong (CTAP2_ERR_PIN_INVALID)
+    PinInvalid,
+    /// The authenticator doesn't hold the credential
+    NoCredential,
+    /// Any other error, e.g. the device was unplugged or the user
+    /// didn't touch it in time
+    Device,
+}
+
+#[doc = "
+Fido2Authenticator is the connection to a single CTAP2 device. This
+crate doesn't talk to USB/NFC itself; implement this trait on top of
+the transport library of your choice.
+"]
+pub trait Fido2Authenticator {
+    /// Name of the device to show during device selection
+    fn name(&self) -> String;
+
+    /// Run authenticatorGetAssertion for credential_id with the
+    /// hmac-secret extension and return the 32 byte output for salt.
+    /// pin is None on the first try.
+    fn hmac_secret(&mut self,
+                   rp_id: &str,
+                   credential_id: &[u8],
+                   salt: &[u8],
+                   pin: Option<&str>)
+                   -> Result<Vec<u8>, Fido2Error>;
+}
+
+// How often the PIN is asked for before giving up. Authenticators
+// block after three wrong PINs in a row until replugged.
+const MAX_PIN_TRIES: usize = 3;
+
+#[doc = "
+Fido2KeyProvider adds the output of the CTAP2 hmac-secret extension as
+another factor to the master key. The salt is derived from the final
+random seed of the header, hence every save needs the device, too.
+
+* select_device is called with the names of all devices if there is
+  more than one and returns the index of the one to use (None aborts).
+* request_pin is called if the device needs a PIN (None aborts).
+"]
+pub struct Fido2KeyProvider {
+    /// Relying party id the credential was created for
+    pub rp_id: String,
+    /// Id of the credential on the authenticator
+    pub credential_id: Vec<u8>,
+    devices: Vec<Box<Fido2Authenticator>>,
+    select_device: Box<FnMut(&[String]) -> Option<usize>>,
+    request_pin: Box<FnMut() -> Option<SecureString>>,
+}
+
+impl Fido2KeyProvider {
+    pub fn new(rp_id: String,
+               credential_id: Vec<u8>,
+               devices: Vec<Box<Fido2Authenticator>>,
+               select_device: Box<FnMut(&[String]) -> Option<usize>>,
+               request_pin: Box<FnMut() -> Option<SecureString>>)
+               -> Fido2KeyProvider {
+        Fido2KeyProvider {
+            rp_id: rp_id,
+            credential_id: credential_id,
+            devices: devices,
+            select_device: select_device,
+            request_pin: request_pin,
+        }
+    }
+
+    fn choose_device(&mut self) -> Result<usize, V1KpdbError> {
+        match self.devices.len() {
+            0 => Err(V1KpdbError::KeyProviderErr),
+            1 => Ok(0),
+            n => {
+                let names: Vec<String> = self.devices.iter().map(|d| d.name()).collect();
+                match (self.select_device)(&names) {
+                    Some(index) if index < n => Ok(index),
+                    _ => Err(V1KpdbError::KeyProviderErr),
+                }
+            }
+        }
+    }
+}
+
+impl KeyProvider for Fido2KeyProvider {
+    // Sensitive data in this function:
+    // * pin (SecureString, unlocked only for the request)
+    // * key (locked)
+    //
+    // At the end of this function:
+    // * pin is dropped and therefore zeroed out
+    // * key is moved out of the function
+    fn get_key(&mut self, challenge: &[u8]) -> Result<Vec<u8>, V1KpdbError> {
+        let index = try!(self.choose_device());
+        let salt = challenge.to_vec();
+        let mut pin: Option<SecureString> = None;
+        let mut pin_tries = 0;
+
+        loop {
+            let result = match pin {
+                Some(ref mut p) => {
+                    p.unlock();
+                    let result = self.devices[index].hmac_secret(&self.rp_id,
+                                                                 &self.credential_id,
+                                                                 &salt,
+                                                                 Some(&p.string));
+                    p.delete();
+                    result
+                }
+                None => {
+                    self.devices[index].hmac_secret(&self.rp_id,
+                                                    &self.credential_id,
+                                                    &salt,
+                                                    None)
+                }
+            };
+
+            match result {
+                Ok(key) => {
+                    mem_protect::lock(&key, "key");
+                    return Ok(key);
+                }
+                Err(Fido2Error::PinRequired) |
+                Err(Fido2Error::PinInvalid) if pin_tries < MAX_PIN_TRIES => {
+                    pin_tries += 1;
+                    pin = match (self.request_pin)() {
+                        Some(p) => Some(p),
+                        None => return Err(V1KpdbError::KeyProviderErr),
+                    };
+                }
+                Err(_) => return Err(V1KpdbError::KeyProviderErr),
+            }
+        }
+    }
+}
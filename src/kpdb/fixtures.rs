@@ -0,0 +1,230 @@
+//! Reproducible databases for tests
+//!
+//! generate writes a database after a FixtureSpec: header values, the
+//! number of groups, entries, attachments and meta streams. Everything
+//! random, the seeds and IV of the header, UUIDs and passwords, comes
+//! from a generator seeded with FixtureSpec::seed, all timestamps are
+//! FixtureSpec::time. The same spec gives the same bytes every time,
+//! so the files can be compared with golden files, see compare_golden.
+//! Changing the fixtures changes the bytes, regenerate the golden files
+//! then by setting UPDATE_GOLDEN_ENV.
+
+use std::cell::RefCell;
+use std::cmp;
+use std::env;
+use std::fs::File;
+use std::io::{Read, Write};
+use std::rc::Rc;
+
+use chrono::{DateTime, Local, TimeZone};
+use rand::{Rng, SeedableRng, XorShiftRng};
+use uuid::Uuid;
+
+use kpdb::builder::V1KpdbBuilder;
+use kpdb::meta::new_meta_entry;
+use kpdb::v1entry::V1Entry;
+use kpdb::v1error::V1KpdbError;
+use kpdb::v1kpdb::V1Kpdb;
+
+/// If this environment variable is set, compare_golden writes the
+/// golden files instead of comparing them
+pub const UPDATE_GOLDEN_ENV: &'static str = "KEEPASS_UPDATE_GOLDEN";
+
+#[doc = "
+FixtureSpec describes a database for generate. Groups are titled
+\"Group <n>\", entries \"Entry <n>\" with the username \"user<n>\" and a
+random password of 16 letters and digits. Entries are spread randomly
+over the groups, the first num_attachments get an attachment.
+"]
+#[derive(Debug, Clone)]
+pub struct FixtureSpec {
+    /// Seed of the random values
+    pub seed: u64,
+    /// Password of the database
+    pub password: String,
+    /// Header values. enc_flag and version are written as they are, e.g.
+    /// to test unsupported ones, the content is encrypted with AES
+    /// anyway. 3 (SHA-2 and AES) and 0x00030002 by default
+    pub enc_flag: u32,
+    pub version: u32,
+    /// Rounds of the key transformation, 10 by default to keep tests
+    /// fast
+    pub rounds: u32,
+    /// Number of groups, at least 1 as entries need a group
+    pub num_groups: u32,
+    /// Highest level of a group. Groups are put below a random earlier
+    /// group as long as it's above this level. 0 (the default) gives
+    /// top-level groups only
+    pub max_level: u16,
+    pub num_entries: u32,
+    /// Number of entries with an attachment and its size in bytes
+    pub num_attachments: u32,
+    pub attachment_size: usize,
+    /// Number of meta streams, named KPX_FIXTURE_<n>, with 32 random
+    /// bytes each
+    pub num_meta_streams: u32,
+    /// Creation, modification and access time of groups and entries
+    pub time: DateTime<Local>,
+}
+
+impl FixtureSpec {
+    /// A database with one group, no entries and the password "fixture"
+    pub fn new(seed: u64) -> FixtureSpec {
+        FixtureSpec {
+            seed: seed,
+            password: "fixture".to_string(),
+            enc_flag: 3,
+            version: 0x00030002,
+            rounds: 10,
+            num_groups: 1,
+            max_level: 0,
+            num_entries: 0,
+            num_attachments: 0,
+            attachment_size: 0,
+            num_meta_streams: 0,
+            time: Local.ymd(2020, 1, 1).and_hms(12, 0, 0),
+        }
+    }
+}
+
+/// The file of the database spec describes
+pub fn generate(spec: &FixtureSpec) -> Result<Vec<u8>, V1KpdbError> {
+    let mut rng = seeded_rng(spec.seed);
+    let mut db = try!(V1KpdbBuilder::new(String::new())
+                          .password(spec.password.clone())
+                          .rounds(spec.rounds)
+                          .group_title("Group 0".to_string())
+                          .build());
+    let mut header = db.header.clone();
+    header.enc_flag = spec.enc_flag;
+    header.version = spec.version;
+    header.final_randomseed = random_bytes(&mut rng, 16);
+    header.iv = random_bytes(&mut rng, 16);
+    header.transf_randomseed = random_bytes(&mut rng, 32);
+
+    for n in 1..spec.num_groups {
+        let parent = db.groups[rng.gen_range(0, db.groups.len())].clone();
+        let parent = if parent.borrow().level < spec.max_level {
+            Some(parent)
+        } else {
+            None
+        };
+        try!(db.create_group(format!("Group {}", n), None, None, parent));
+    }
+    for group in db.groups.iter() {
+        let mut group = group.borrow_mut();
+        group.creation = spec.time;
+        group.last_mod = spec.time;
+        group.last_access = spec.time;
+    }
+
+    for n in 0..spec.num_entries {
+        let group = db.groups[rng.gen_range(0, db.groups.len())].clone();
+        let password = rng.gen_ascii_chars().take(16).collect::<String>();
+        let entry = db.create_entry(group,
+                                    format!("Entry {}", n),
+                                    None,
+                                    None,
+                                    Some(format!("https://host{}.example.com/", n)),
+                                    Some(format!("Comment of entry {}", n)),
+                                    Some(format!("user{}", n)),
+                                    Some(password));
+        let mut entry = entry.borrow_mut();
+        stamp(&mut entry, &mut rng, spec.time);
+        if n < spec.num_attachments {
+            let data = random_bytes(&mut rng, spec.attachment_size);
+            try!(entry.set_attachment(format!("attachment{}.bin", n), data));
+        }
+    }
+
+    for n in 0..spec.num_meta_streams {
+        let group_id = db.groups[0].borrow().id;
+        let data = random_bytes(&mut rng, 32);
+        let stream = format!("KPX_FIXTURE_{}", n);
+        db.meta_entries.push(Rc::new(RefCell::new(new_meta_entry(&stream, data, group_id))));
+    }
+    db.encode_with(header, |entry| stamp(entry, &mut rng, spec.time))
+}
+
+/// The database spec describes, loaded from the result of generate.
+/// path is only used by save.
+pub fn open(spec: &FixtureSpec, path: String) -> Result<V1Kpdb, V1KpdbError> {
+    let data = try!(generate(spec));
+    let mut db = try!(V1Kpdb::new(path, Some(spec.password.clone()), None));
+    try!(db.load_from_bytes(&data));
+    Ok(db)
+}
+
+#[doc = "
+GoldenMismatch tells where data differs from a golden file. offset is
+the first byte which differs, or the length of the shorter one if one
+is a prefix of the other.
+"]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GoldenMismatch {
+    pub offset: usize,
+    pub expected_len: usize,
+    pub actual_len: usize,
+}
+
+/// Compare actual with the golden file at path, None if they're equal.
+/// If UPDATE_GOLDEN_ENV is set, the file is written with actual instead.
+/// FileErr if the file can't be opened, e.g. because it's not there yet.
+pub fn compare_golden(path: &str, actual: &[u8]) -> Result<Option<GoldenMismatch>, V1KpdbError> {
+    if env::var_os(UPDATE_GOLDEN_ENV).is_some() {
+        let mut file = try!(File::create(path).map_err(|_| V1KpdbError::FileErr));
+        try!(file.write_all(actual).map_err(|_| V1KpdbError::WriteErr));
+        return Ok(None);
+    }
+    let mut file = try!(File::open(path).map_err(|_| V1KpdbError::FileErr));
+    let mut expected = vec![];
+    try!(file.read_to_end(&mut expected).map_err(|_| V1KpdbError::ReadErr));
+    if expected[..] == actual[..] {
+        return Ok(None);
+    }
+    let offset = expected.iter()
+                         .zip(actual.iter())
+                         .position(|(e, a)| e != a)
+                         .unwrap_or(cmp::min(expected.len(), actual.len()));
+    Ok(Some(GoldenMismatch {
+        offset: offset,
+        expected_len: expected.len(),
+        actual_len: actual.len(),
+    }))
+}
+
+/// Like compare_golden but panics with the mismatch, for tests
+pub fn assert_golden(path: &str, actual: &[u8]) {
+    match compare_golden(path, actual) {
+        Ok(None) => {}
+        Ok(Some(mismatch)) => {
+            panic!("{} differs at byte {}, {} bytes expected, {} found",
+                   path,
+                   mismatch.offset,
+                   mismatch.expected_len,
+                   mismatch.actual_len)
+        }
+        Err(e) => panic!("{} can't be read: {:?}", path, e),
+    }
+}
+
+fn seeded_rng(seed: u64) -> XorShiftRng {
+    // The last two words keep the seed from being all zeroes, which
+    // XorShiftRng doesn't accept
+    XorShiftRng::from_seed([seed as u32, (seed >> 32) as u32, 0x9E3779B9, 0x7F4A7C15])
+}
+
+fn random_bytes(rng: &mut XorShiftRng, len: usize) -> Vec<u8> {
+    let mut bytes = vec![0u8; len];
+    rng.fill_bytes(&mut bytes);
+    bytes
+}
+
+// Replace the random UUID and the current time of a new entry. The UUID
+// index of the database isn't updated, it's only encoded.
+fn stamp(entry: &mut V1Entry, rng: &mut XorShiftRng, time: DateTime<Local>) {
+    entry.uuid = Uuid::from_bytes(&random_bytes(rng, 16)).unwrap();
+    entry.creation = time;
+    entry.last_mod = time;
+    entry.last_access = time;
+}
@@ -0,0 +1,185 @@
+use std::char;
+
+use rand::{self, Rng};
+
+use kpdb::v1error::V1KpdbError;
+use mem_protect;
+use sec_str::SecureString;
+
+/// Most classes each_class works with, entropy_bits goes through all
+/// subsets of them
+pub const MAX_EACH_CLASS: usize = 16;
+
+/// A set of characters passwords are drawn from
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CharClass {
+    /// a-z
+    Lowercase,
+    /// A-Z
+    Uppercase,
+    /// 0-9
+    Digits,
+    /// The printable ASCII characters which are neither letters nor
+    /// digits, without space
+    Symbols,
+    /// All characters from the first to the second one, both included,
+    /// e.g. a Unicode block
+    Range(char, char),
+    /// Exactly these characters
+    Chars(Vec<char>),
+}
+
+impl CharClass {
+    /// The Cyrillic letters of Russian, А-я without Ё and ё
+    pub fn cyrillic() -> CharClass {
+        CharClass::Range('\u{410}', '\u{44F}')
+    }
+
+    /// The Greek letters Α-Ω and α-ω, including the final sigma ς
+    pub fn greek() -> CharClass {
+        // U+03A2 is unassigned
+        CharClass::Chars(('\u{391}' as u32..'\u{3CA}' as u32)
+                             .filter(|c| *c != 0x3A2 && (*c <= 0x3A9 || *c >= 0x3B1))
+                             .filter_map(char::from_u32)
+                             .collect())
+    }
+
+    /// The characters of the class in ascending order, without
+    /// duplicates
+    pub fn chars(&self) -> Vec<char> {
+        let mut chars = match *self {
+            CharClass::Lowercase => char_range('a', 'z'),
+            CharClass::Uppercase => char_range('A', 'Z'),
+            CharClass::Digits => char_range('0', '9'),
+            CharClass::Symbols => {
+                char_range('!', '~').into_iter().filter(|c| !c.is_alphanumeric()).collect()
+            }
+            CharClass::Range(first, last) => char_range(first, last),
+            CharClass::Chars(ref chars) => chars.clone(),
+        };
+        chars.sort();
+        chars.dedup();
+        chars
+    }
+}
+
+// Surrogates aren't chars, so a range across them skips them
+fn char_range(first: char, last: char) -> Vec<char> {
+    (first as u32..last as u32 + 1).filter_map(char::from_u32).collect()
+}
+
+#[doc = "
+PasswordGenerator creates random passwords from a set of character
+classes. Besides the ASCII classes it takes Unicode ranges, e.g. for
+systems which accept Cyrillic or Greek passwords.
+
+The characters of all classes are merged, so a character which is in two
+classes isn't more likely than others. entropy_bits accounts for that and
+for the passwords excluded by each_class.
+"]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PasswordGenerator {
+    /// Length of the passwords in characters, not bytes
+    pub length: usize,
+    pub classes: Vec<CharClass>,
+    /// If true, every password holds at least one character of each
+    /// class
+    pub each_class: bool,
+}
+
+impl PasswordGenerator {
+    /// A generator for passwords of length characters from the ASCII
+    /// classes
+    pub fn new(length: usize) -> PasswordGenerator {
+        PasswordGenerator {
+            length: length,
+            classes: vec![CharClass::Lowercase,
+                          CharClass::Uppercase,
+                          CharClass::Digits,
+                          CharClass::Symbols],
+            each_class: false,
+        }
+    }
+
+    /// All characters passwords are drawn from, without duplicates
+    pub fn alphabet(&self) -> Vec<char> {
+        let mut alphabet: Vec<char> = self.classes.iter().flat_map(|c| c.chars()).collect();
+        alphabet.sort();
+        alphabet.dedup();
+        alphabet
+    }
+
+    /// Entropy of the generated passwords in bits, i.e. log2 of the
+    /// number of passwords the settings allow. 0 for invalid settings.
+    pub fn entropy_bits(&self) -> f64 {
+        if self.check().is_err() {
+            return 0.0;
+        }
+        let alphabet = self.alphabet();
+        let size = alphabet.len() as f64;
+        let all = self.length as f64 * size.log2();
+        if !self.each_class {
+            return all;
+        }
+
+        // Inclusion-exclusion over the classes which are missing from a
+        // password gives the share of passwords which hold all classes.
+        // Classes may overlap, so the size of their union is counted.
+        let classes: Vec<Vec<char>> = self.classes.iter().map(|c| c.chars()).collect();
+        let mut share = 0.0;
+        for subset in 0..(1u32 << classes.len()) {
+            let mut missing: Vec<char> = vec![];
+            for (i, class) in classes.iter().enumerate() {
+                if subset & (1 << i) != 0 {
+                    missing.extend(class.iter().cloned());
+                }
+            }
+            missing.sort();
+            missing.dedup();
+            let term = (1.0 - missing.len() as f64 / size).powi(self.length as i32);
+            if subset.count_ones() % 2 == 0 {
+                share += term;
+            } else {
+                share -= term;
+            }
+        }
+        all + share.log2()
+    }
+
+    /// Generate a password. GeneratorErr if the length is 0, a class is
+    /// empty or each_class is set and the password is shorter than the
+    /// number of classes or there are more than MAX_EACH_CLASS classes.
+    pub fn generate(&self) -> Result<SecureString, V1KpdbError> {
+        try!(self.check());
+        let alphabet = self.alphabet();
+        let classes: Vec<Vec<char>> = self.classes.iter().map(|c| c.chars()).collect();
+        let mut rng = rand::thread_rng();
+        loop {
+            // Room for the longest UTF-8 characters, so the string is
+            // never moved while it's built
+            let mut password = String::with_capacity(self.length * 4);
+            for _ in 0..self.length {
+                password.push(alphabet[rng.gen_range(0, alphabet.len())]);
+            }
+            // Passwords without all classes are drawn again, so the
+            // accepted ones stay uniformly distributed
+            if !self.each_class ||
+               classes.iter().all(|class| password.chars().any(|c| class.binary_search(&c).is_ok())) {
+                return Ok(SecureString::new(password));
+            }
+            unsafe {
+                mem_protect::zero(&password);
+            }
+        }
+    }
+
+    fn check(&self) -> Result<(), V1KpdbError> {
+        if self.length == 0 || self.classes.is_empty() ||
+           self.classes.iter().any(|c| c.chars().is_empty()) ||
+           (self.each_class &&
+            (self.length < self.classes.len() || self.classes.len() > MAX_EACH_CLASS)) {
+            return Err(V1KpdbError::GeneratorErr);
+        }
+        Ok(())
+    }
+}
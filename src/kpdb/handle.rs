@@ -0,0 +1,129 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::{Rc, Weak};
+
+use uuid::Uuid;
+
+use kpdb::v1group::V1Group;
+
+#[doc = "
+EntryHandle refers to an entry of a V1Kpdb by its UUID without holding
+it. Handles are plain values, so they can be kept across mutations of
+the database. Resolve them with V1Kpdb::with_entry or resolve_entry.
+
+A handle becomes stale when its entry is removed. It doesn't resolve to
+another entry which gets the same UUID later on, e.g. by an import.
+"]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct EntryHandle {
+    uuid: Uuid,
+    generation: u64,
+}
+
+impl EntryHandle {
+    /// UUID of the entry
+    pub fn uuid(&self) -> Uuid {
+        self.uuid
+    }
+}
+
+#[doc = "
+GroupHandle is the EntryHandle of groups. Group ids are reused after a
+group is removed, a handle of the removed group stays stale though.
+"]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct GroupHandle {
+    id: u32,
+    generation: u64,
+}
+
+impl GroupHandle {
+    /// Id of the group
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+}
+
+#[doc = "
+HandleTable keeps the generation counters behind the handles of a
+V1Kpdb and a cache of the groups resolved so far. The database updates
+it, applications don't need it.
+"]
+pub struct HandleTable {
+    // Generation of the next removal
+    next_generation: u64,
+    // Generation of the UUIDs and ids which were removed before
+    entries: HashMap<Uuid, u64>,
+    groups: HashMap<u32, u64>,
+    group_cache: RefCell<HashMap<u32, Weak<RefCell<V1Group>>>>,
+}
+
+impl HandleTable {
+    pub fn new() -> HandleTable {
+        HandleTable {
+            next_generation: 1,
+            entries: HashMap::new(),
+            groups: HashMap::new(),
+            group_cache: RefCell::new(HashMap::new()),
+        }
+    }
+
+    /// Handle of the current entry with UUID uuid
+    pub fn entry_handle(&self, uuid: Uuid) -> EntryHandle {
+        EntryHandle {
+            uuid: uuid,
+            generation: self.entries.get(&uuid).cloned().unwrap_or(0),
+        }
+    }
+
+    /// Handle of the current group with id id
+    pub fn group_handle(&self, id: u32) -> GroupHandle {
+        GroupHandle {
+            id: id,
+            generation: self.groups.get(&id).cloned().unwrap_or(0),
+        }
+    }
+
+    /// False if the entry of handle was removed since
+    pub fn is_current_entry(&self, handle: &EntryHandle) -> bool {
+        self.entry_handle(handle.uuid) == *handle
+    }
+
+    /// False if the group of handle was removed since
+    pub fn is_current_group(&self, handle: &GroupHandle) -> bool {
+        self.group_handle(handle.id) == *handle
+    }
+
+    /// Make the handles of the entry with UUID uuid stale
+    pub fn entry_removed(&mut self, uuid: Uuid) {
+        self.entries.insert(uuid, self.next_generation);
+        self.next_generation += 1;
+    }
+
+    /// Make the handles of the group with id id stale
+    pub fn group_removed(&mut self, id: u32) {
+        self.groups.insert(id, self.next_generation);
+        self.next_generation += 1;
+        self.group_cache.borrow_mut().remove(&id);
+    }
+
+    /// The cached group with id id, if it's still alive and has the id.
+    /// A borrowed group can't be checked and is returned as it is.
+    pub fn cached_group(&self, id: u32) -> Option<Rc<RefCell<V1Group>>> {
+        let group = self.group_cache.borrow().get(&id).and_then(|g| g.upgrade());
+        match group {
+            Some(ref g) if g.try_borrow().map(|g| g.id == id).unwrap_or(true) => group.clone(),
+            _ => None,
+        }
+    }
+
+    /// Remember group as the group with id id
+    pub fn cache_group(&self, id: u32, group: &Rc<RefCell<V1Group>>) {
+        self.group_cache.borrow_mut().insert(id, Rc::downgrade(group));
+    }
+
+    /// Forget all cached groups, e.g. when the groups were replaced
+    pub fn clear_cache(&self) {
+        self.group_cache.borrow_mut().clear();
+    }
+}
@@ -0,0 +1,96 @@
+use kpdb::common::url_host;
+use kpdb::import::csv;
+use kpdb::import::{push_note, push_otp, push_url, ImportEntry};
+use kpdb::v1error::V1KpdbError;
+
+/// Parse the password export of a web browser. The layout is told by
+/// the names in the header row:
+///
+/// * Chrome, Edge and other Chromium based browsers:
+///   name,url,username,password,note
+/// * Firefox: url,username,password,httpRealm,formActionOrigin,guid,
+///   timeCreated,timePasswordChanged,timeLastUsed
+/// * Safari: Title,URL,Username,Password,Notes,OTPAuth
+///
+/// All logins go into a group named after the browser. Without a name
+/// the host of the URL becomes the title. The HTTP realm of Firefox is
+/// added to the notes, OTP secrets of Safari as otpauth:// lines, which
+/// validate checks. Other columns are skipped and overwritten with
+/// zeroes. ImportErr if the url or password column is missing.
+pub fn parse_csv(data: &[u8]) -> Result<Vec<ImportEntry>, V1KpdbError> {
+    let mut rows = try!(csv::fields(data, b',')).into_iter();
+    let header: Vec<String> = match rows.next() {
+        Some(header) => header.iter().map(|h| h.trim().to_lowercase()).collect(),
+        None => return Ok(vec![]),
+    };
+    if !header.iter().any(|h| h == "url") || !header.iter().any(|h| h == "password") {
+        for row in rows {
+            csv::wipe_fields(&row);
+        }
+        return Err(V1KpdbError::ImportErr);
+    }
+    let browser = if header.iter().any(|h| h == "guid" || h == "httprealm") {
+        "Firefox"
+    } else if header.iter().any(|h| h == "title" || h == "otpauth") {
+        "Safari"
+    } else {
+        "Chrome"
+    };
+
+    let mut records = vec![];
+    for row in rows {
+        let mut record = ImportEntry::new();
+        record.group_path = vec![browser.to_string()];
+        let mut otp = None;
+        for (i, value) in row.into_iter().enumerate() {
+            let unused = match header.get(i).map(|h| h.as_str()) {
+                Some(_) if value.is_empty() => Some(value),
+                Some("name") | Some("title") => {
+                    record.title = value;
+                    None
+                }
+                Some("username") => {
+                    record.username = Some(value);
+                    None
+                }
+                Some("password") => {
+                    record.password = Some(value);
+                    None
+                }
+                Some("url") => {
+                    push_url(&mut record, value);
+                    None
+                }
+                Some("note") | Some("notes") => {
+                    push_note(&mut record, &value);
+                    None
+                }
+                Some("httprealm") => {
+                    push_note(&mut record, &format!("HTTP realm: {}", value));
+                    None
+                }
+                // Labeled with the title, which may come later
+                Some("otpauth") => {
+                    otp = Some(value);
+                    None
+                }
+                _ => Some(value),
+            };
+            if let Some(unused) = unused {
+                csv::wipe_fields(&[unused]);
+            }
+        }
+        if record.title.is_empty() {
+            record.title = record.url
+                                 .as_ref()
+                                 .and_then(|url| url_host(url))
+                                 .unwrap_or(String::new());
+        }
+        if let Some(otp) = otp {
+            push_otp(&mut record, &otp);
+            csv::wipe_fields(&[otp]);
+        }
+        records.push(record);
+    }
+    Ok(records)
+}
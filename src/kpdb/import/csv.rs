@@ -0,0 +1,305 @@
+use std::io::Read;
+use std::mem;
+
+use kpdb::import::ImportEntry;
+use kpdb::v1error::V1KpdbError;
+use mem_protect;
+
+/// The field of ImportEntry a CSV column maps to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CsvColumn {
+    /// Titles of the groups from the top level down, separated by
+    /// CsvMapping::group_separator
+    GroupPath,
+    Title,
+    Username,
+    Password,
+    Url,
+    Notes,
+    /// The column is skipped on import and left empty on export
+    Ignore,
+}
+
+impl CsvColumn {
+    /// Name of the column in the header row of an export
+    pub fn name(&self) -> &'static str {
+        match *self {
+            CsvColumn::GroupPath => "Group",
+            CsvColumn::Title => "Title",
+            CsvColumn::Username => "Username",
+            CsvColumn::Password => "Password",
+            CsvColumn::Url => "URL",
+            CsvColumn::Notes => "Notes",
+            CsvColumn::Ignore => "",
+        }
+    }
+}
+
+#[doc = "
+CsvMapping describes the layout of a CSV file, i.e. which column holds
+which field. Other password managers order their columns differently, so
+columns holds one CsvColumn per column of the file. Records with fewer
+columns leave the missing fields empty, additional columns are ignored.
+
+Fields are quoted and escaped as in RFC 4180: fields which contain the
+delimiter, quotes or line breaks are enclosed in double quotes and quotes
+inside are doubled.
+"]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CsvMapping {
+    pub columns: Vec<CsvColumn>,
+    /// If true, the first record holds the column names. It's skipped on
+    /// import and written on export.
+    pub has_header: bool,
+    /// Separates the fields of a record, e.g. b',' or b';'. Must be
+    /// ASCII and neither a quote nor a line break.
+    pub delimiter: u8,
+    /// Separates the group titles in a GroupPath column
+    pub group_separator: char,
+}
+
+impl CsvMapping {
+    /// Group path, title, username, password, URL and notes separated
+    /// by commas with a header row
+    pub fn new() -> CsvMapping {
+        CsvMapping {
+            columns: vec![CsvColumn::GroupPath,
+                          CsvColumn::Title,
+                          CsvColumn::Username,
+                          CsvColumn::Password,
+                          CsvColumn::Url,
+                          CsvColumn::Notes],
+            has_header: true,
+            delimiter: b',',
+            group_separator: '/',
+        }
+    }
+
+    /// CsvErr if the delimiter can't be used
+    pub fn check(&self) -> Result<(), V1KpdbError> {
+        check_delimiter(self.delimiter)
+    }
+}
+
+fn check_delimiter(delimiter: u8) -> Result<(), V1KpdbError> {
+    match delimiter {
+        b'"' | b'\r' | b'\n' | 0x80...0xFF => Err(V1KpdbError::CsvErr),
+        _ => Ok(()),
+    }
+}
+
+/// Read CSV records from reader. See parse, the data read is overwritten
+/// with zeroes afterwards.
+pub fn read<R: Read>(mut reader: R, mapping: &CsvMapping) -> Result<Vec<ImportEntry>, V1KpdbError> {
+    // read_to_end may move the buffer while it grows. Readers which know
+    // their size, e.g. files, mostly fill it in one go.
+    let mut data = vec![];
+    let result = match reader.read_to_end(&mut data) {
+        Ok(_) => {
+            mem_protect::lock(&data, "CSV import");
+            parse(&data, mapping)
+        }
+        Err(_) => Err(V1KpdbError::ReadErr),
+    };
+    unsafe {
+        mem_protect::zero(&data);
+    }
+    mem_protect::unlock(&data);
+    result
+}
+
+/// Parse the CSV records in data into ImportEntries which can be checked
+/// with preview and validate and imported with apply. Fields which aren't
+/// imported are overwritten with zeroes. See fields for errors.
+pub fn parse(data: &[u8], mapping: &CsvMapping) -> Result<Vec<ImportEntry>, V1KpdbError> {
+    let mut records = try!(fields(data, mapping.delimiter)).into_iter();
+    if mapping.has_header {
+        if let Some(header) = records.next() {
+            wipe_fields(&header);
+        }
+    }
+    Ok(records.map(|fields| to_record(fields, mapping)).collect())
+}
+
+/// The fields of the CSV records in data, e.g. for exports whose columns
+/// are found by their header. Each field is copied once into a buffer of
+/// its final size, so the passwords are moved into the database without
+/// further copies. CsvErr if a quoted field isn't closed, the data isn't
+/// UTF-8 or delimiter can't be used, see CsvMapping::delimiter.
+pub fn fields(data: &[u8], delimiter: u8) -> Result<Vec<Vec<String>>, V1KpdbError> {
+    try!(check_delimiter(delimiter));
+    // Byte order mark of UTF-8, e.g. written by Excel
+    let data = if data.starts_with(b"\xEF\xBB\xBF") {
+        &data[3..]
+    } else {
+        data
+    };
+    let mut reader = Reader {
+        data: data,
+        pos: 0,
+        delimiter: delimiter,
+    };
+    let mut records = vec![];
+    loop {
+        match reader.record() {
+            Ok(Some(fields)) => records.push(fields),
+            Ok(None) => return Ok(records),
+            Err(e) => {
+                for fields in records.iter() {
+                    wipe_fields(fields);
+                }
+                return Err(e);
+            }
+        }
+    }
+}
+
+fn to_record(fields: Vec<String>, mapping: &CsvMapping) -> ImportEntry {
+    let mut record = ImportEntry::new();
+    for (i, field) in fields.into_iter().enumerate() {
+        let value = if field.is_empty() {
+            None
+        } else {
+            Some(field)
+        };
+        let column = mapping.columns.get(i).cloned().unwrap_or(CsvColumn::Ignore);
+        // Values which aren't imported, e.g. if two columns map to the
+        // same field
+        let unused = match column {
+            CsvColumn::GroupPath => {
+                if let Some(ref path) = value {
+                    record.group_path = path.split(mapping.group_separator)
+                                            .map(|t| t.trim())
+                                            .filter(|t| !t.is_empty())
+                                            .map(|t| t.to_string())
+                                            .collect();
+                }
+                None
+            }
+            CsvColumn::Title => value.map(|title| mem::replace(&mut record.title, title)),
+            CsvColumn::Username => replace(&mut record.username, value),
+            CsvColumn::Password => replace(&mut record.password, value),
+            CsvColumn::Url => replace(&mut record.url, value),
+            CsvColumn::Notes => replace(&mut record.notes, value),
+            CsvColumn::Ignore => value,
+        };
+        if let Some(unused) = unused {
+            wipe(&unused);
+        }
+    }
+    record
+}
+
+// Set field to value if there is one, returns the previous value
+fn replace(field: &mut Option<String>, value: Option<String>) -> Option<String> {
+    match value {
+        Some(value) => mem::replace(field, Some(value)),
+        None => None,
+    }
+}
+
+fn wipe(s: &str) {
+    unsafe {
+        mem_protect::zero(s);
+    }
+}
+
+/// Overwrite fields with zeroes, e.g. the ones which aren't imported
+pub fn wipe_fields(fields: &[String]) {
+    for field in fields {
+        wipe(field);
+    }
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+    delimiter: u8,
+}
+
+impl<'a> Reader<'a> {
+    // The fields of the next record, None at the end of data. Empty lines
+    // are skipped.
+    fn record(&mut self) -> Result<Option<Vec<String>>, V1KpdbError> {
+        loop {
+            match self.data.get(self.pos) {
+                Some(&b'\r') | Some(&b'\n') => self.pos += 1,
+                Some(_) => break,
+                None => return Ok(None),
+            }
+        }
+        let mut fields = vec![];
+        loop {
+            match self.field() {
+                Ok(field) => fields.push(field),
+                Err(e) => {
+                    wipe_fields(&fields);
+                    return Err(e);
+                }
+            }
+            match self.data.get(self.pos) {
+                Some(&c) if c == self.delimiter => self.pos += 1,
+                _ => return Ok(Some(fields)),
+            }
+        }
+    }
+
+    // The field at pos. pos is left at the delimiter or line break after
+    // it.
+    fn field(&mut self) -> Result<String, V1KpdbError> {
+        let start = self.pos;
+        let quoted = self.data.get(start) == Some(&b'"');
+        let mut end = start;
+        if quoted {
+            end += 1;
+            loop {
+                match self.data.get(end) {
+                    Some(&b'"') if self.data.get(end + 1) == Some(&b'"') => end += 2,
+                    Some(&b'"') => {
+                        end += 1;
+                        break;
+                    }
+                    Some(_) => end += 1,
+                    None => return Err(V1KpdbError::CsvErr),
+                }
+            }
+            match self.data.get(end) {
+                Some(&b'\r') | Some(&b'\n') | None => {}
+                Some(&c) if c == self.delimiter => {}
+                Some(_) => return Err(V1KpdbError::CsvErr),
+            }
+        } else {
+            while let Some(&c) = self.data.get(end) {
+                if c == self.delimiter || c == b'\r' || c == b'\n' {
+                    break;
+                }
+                end += 1;
+            }
+        }
+        self.pos = end;
+
+        // The raw field is never shorter than the unescaped one
+        let raw = &self.data[start..end];
+        let mut bytes = Vec::with_capacity(raw.len());
+        if quoted {
+            let inner = &raw[1..raw.len() - 1];
+            let mut i = 0;
+            while i < inner.len() {
+                bytes.push(inner[i]);
+                // Skip the second quote of ""
+                if inner[i] == b'"' {
+                    i += 1;
+                }
+                i += 1;
+            }
+        } else {
+            bytes.extend_from_slice(raw);
+        }
+        String::from_utf8(bytes).map_err(|e| {
+            unsafe {
+                mem_protect::zero(&e.into_bytes());
+            }
+            V1KpdbError::CsvErr
+        })
+    }
+}
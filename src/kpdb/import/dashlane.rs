@@ -0,0 +1,146 @@
+use rustc_serialize::json::Json;
+
+use kpdb::import::csv;
+use kpdb::import::{push_note, push_otp, push_url, take_string, ImportEntry};
+use kpdb::import::zip;
+use kpdb::v1error::V1KpdbError;
+use mem_protect;
+
+// The files of a zip export which are imported, other kinds of items
+// are skipped
+const ZIP_FILES: [&'static str; 2] = ["credentials.csv", "securenotes.csv"];
+
+// The keys of items in a JSON export which are imported, in the order
+// they are added to the record
+const JSON_KEYS: [&'static str; 12] = ["title",
+                                       "login",
+                                       "email",
+                                       "secondaryLogin",
+                                       "password",
+                                       "domain",
+                                       "url",
+                                       "note",
+                                       "content",
+                                       "category",
+                                       "otpSecret",
+                                       "otpUrl"];
+
+/// Parse the zip file Dashlane exports: credentials.csv and
+/// securenotes.csv are unpacked in memory, parsed like in parse_csv and
+/// overwritten with zeroes afterwards. ImportErr if it holds neither.
+pub fn parse_zip(data: &[u8]) -> Result<Vec<ImportEntry>, V1KpdbError> {
+    let files = try!(zip::files(data));
+    let mut found = false;
+    let mut records = vec![];
+    for name in ZIP_FILES.iter() {
+        if let Some(file) = files.iter().find(|f| f.base_name() == *name) {
+            let content = try!(zip::extract(data, file));
+            let result = parse_csv(&content);
+            unsafe {
+                mem_protect::zero(&content);
+            }
+            records.extend(try!(result));
+            found = true;
+        }
+    }
+    if !found {
+        return Err(V1KpdbError::ImportErr);
+    }
+    Ok(records)
+}
+
+/// Parse a CSV file of a Dashlane export, e.g. credentials.csv or
+/// securenotes.csv of the zip file it writes, see parse_zip. The columns
+/// are found by the names in the header row, unknown columns are skipped
+/// and overwritten with zeroes.
+///
+/// The category becomes the group. Additional usernames go into the
+/// notes, OTP secrets are added as otpauth:// lines, which validate
+/// checks.
+pub fn parse_csv(data: &[u8]) -> Result<Vec<ImportEntry>, V1KpdbError> {
+    let mut rows = try!(csv::fields(data, b',')).into_iter();
+    let header: Vec<String> = match rows.next() {
+        Some(header) => header.iter().map(|h| h.trim().to_lowercase()).collect(),
+        None => return Ok(vec![]),
+    };
+    if !header.iter().any(|h| h == "title") {
+        for row in rows {
+            csv::wipe_fields(&row);
+        }
+        return Err(V1KpdbError::ImportErr);
+    }
+
+    let mut records = vec![];
+    for row in rows {
+        let mut record = ImportEntry::new();
+        for (i, value) in row.into_iter().enumerate() {
+            let unused = match header.get(i) {
+                Some(name) if !value.is_empty() => set_field(&mut record, name, value),
+                _ => Some(value),
+            };
+            if let Some(unused) = unused {
+                csv::wipe_fields(&[unused]);
+            }
+        }
+        records.push(record);
+    }
+    Ok(records)
+}
+
+/// Parse the JSON export of Dashlane. Credentials (AUTHENTIFIANT) and
+/// secure notes (SECUREDNOTE) are imported like in parse_csv, other
+/// kinds of items are skipped.
+pub fn parse_json(export: &str) -> Result<Vec<ImportEntry>, V1KpdbError> {
+    let mut root = match Json::from_str(export) {
+        Ok(Json::Object(root)) => root,
+        _ => return Err(V1KpdbError::ImportErr),
+    };
+    let credentials = root.remove("AUTHENTIFIANT");
+    let notes = root.remove("SECUREDNOTE");
+    if credentials.is_none() && notes.is_none() {
+        return Err(V1KpdbError::ImportErr);
+    }
+
+    let mut records = vec![];
+    for items in credentials.into_iter().chain(notes) {
+        let items = match items {
+            Json::Array(items) => items,
+            _ => return Err(V1KpdbError::ImportErr),
+        };
+        for item in items {
+            let mut item = match item {
+                Json::Object(item) => item,
+                _ => return Err(V1KpdbError::ImportErr),
+            };
+            let mut record = ImportEntry::new();
+            for key in JSON_KEYS.iter() {
+                if let Some(value) = take_string(&mut item, key) {
+                    set_field(&mut record, &key.to_lowercase(), value);
+                }
+            }
+            records.push(record);
+        }
+    }
+    Ok(records)
+}
+
+// Store value in the field of record named name. Returns value if the
+// name is unknown.
+fn set_field(record: &mut ImportEntry, name: &str, value: String) -> Option<String> {
+    match name {
+        "title" => record.title = value,
+        "username" | "login" | "email" if record.username.is_none() => {
+            record.username = Some(value)
+        }
+        "username" | "login" | "email" | "username2" | "username3" | "secondarylogin" => {
+            push_note(record, &format!("Alternative username: {}", value))
+        }
+        "password" => record.password = Some(value),
+        "note" | "content" => push_note(record, &value),
+        "url" | "domain" => push_url(record, value),
+        "category" => record.group_path = vec![value],
+        "otpsecret" | "otpurl" => push_otp(record, &value),
+        _ => return Some(value),
+    }
+    None
+}
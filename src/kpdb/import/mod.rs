@@ -0,0 +1,296 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use chrono::{DateTime, Local};
+use rustc_serialize::json::{self, Json};
+
+use kpdb::v1entry::{V1Entry, ADDITIONAL_URL_PREFIX};
+use kpdb::v1error::V1KpdbError;
+use kpdb::notes::SECRET_FENCE;
+use kpdb::v1group::V1Group;
+use kpdb::v1kpdb::V1Kpdb;
+
+pub mod browser;
+pub mod csv;
+pub mod dashlane;
+pub mod proton;
+pub mod psafe3;
+pub mod validate;
+pub mod zip;
+
+#[doc = "
+ImportEntry is the format independent result of reading a single
+record of a foreign export. All importers produce these, so that
+preview and apply work the same for every format.
+
+username and password are of type String to move them into
+V1Kpdb::create_entry without copying (see there).
+"]
+pub struct ImportEntry {
+    /// Titles of the groups from the top level down to the group
+    /// which should hold the entry. Empty means the first top level
+    /// group.
+    pub group_path: Vec<String>,
+    /// Title of the entry
+    pub title: String,
+    /// Username for the login
+    pub username: Option<String>,
+    /// Password for the login
+    pub password: Option<String>,
+    /// URL for the login
+    pub url: Option<String>,
+    /// Some comment about the entry
+    pub notes: Option<String>,
+    /// Expiration date. None means that the entry never expires
+    pub expire: Option<DateTime<Local>>,
+}
+
+impl ImportEntry {
+    /// Create an empty record
+    pub fn new() -> ImportEntry {
+        ImportEntry {
+            group_path: vec![],
+            title: "".to_string(),
+            username: None,
+            password: None,
+            url: None,
+            notes: None,
+            expire: None,
+        }
+    }
+}
+
+/// An imported record which would collide with an existing entry
+pub struct ImportConflict {
+    /// Index of the record in the imported records
+    pub index: usize,
+    /// The existing entry with the same group, title and username
+    pub existing: Rc<RefCell<V1Entry>>,
+}
+
+/// An entry which would be created by an import
+pub struct PreviewEntry {
+    /// Index of the record in the imported records
+    pub index: usize,
+    /// Path of the group which would hold the entry
+    pub group_path: Vec<String>,
+    /// Title of the entry
+    pub title: String,
+}
+
+#[doc = "
+PreviewTree describes what importing a list of records would do to a
+database without changing it, so that applications can show a
+confirmation screen first.
+"]
+pub struct PreviewTree {
+    /// Groups which don't exist yet and would be created
+    pub new_groups: Vec<Vec<String>>,
+    /// Entries which would be created
+    pub new_entries: Vec<PreviewEntry>,
+    /// Records which collide with existing entries. They are
+    /// imported anyway, conflicts are only reported.
+    pub conflicts: Vec<ImportConflict>,
+}
+
+/// Calculate what importing records into db would do
+pub fn preview(db: &V1Kpdb, records: &[ImportEntry]) -> PreviewTree {
+    let mut tree = PreviewTree {
+        new_groups: vec![],
+        new_entries: vec![],
+        conflicts: vec![],
+    };
+
+    for (index, record) in records.iter().enumerate() {
+        let group_path = effective_group_path(db, record);
+        for depth in 1..group_path.len() + 1 {
+            let path = group_path[..depth].to_vec();
+            if find_group(db, &path).is_none() && !tree.new_groups.contains(&path) {
+                tree.new_groups.push(path);
+            }
+        }
+
+        if let Some(group) = find_group(db, &group_path) {
+            for entry in group.borrow().child_entries() {
+                if is_same_entry(&mut entry.borrow_mut(), record) {
+                    tree.conflicts.push(ImportConflict {
+                        index: index,
+                        existing: entry.clone(),
+                    });
+                }
+            }
+        }
+
+        tree.new_entries.push(PreviewEntry {
+            index: index,
+            group_path: group_path,
+            title: record.title.clone(),
+        });
+    }
+    tree
+}
+
+/// Import records into db, creating missing groups on the way. The
+/// import is a single change for V1Kpdb::undo, nothing is imported on
+/// error.
+pub fn apply(db: &mut V1Kpdb, records: Vec<ImportEntry>) -> Result<(), V1KpdbError> {
+    db.transaction(move |db| {
+        for record in records {
+            let group_path = effective_group_path(db, &record);
+            let group = try!(create_groups(db, &group_path));
+            db.create_entry(group,
+                            record.title,
+                            record.expire,
+                            None,
+                            record.url,
+                            record.notes,
+                            record.username,
+                            record.password);
+        }
+        Ok(())
+    })
+}
+
+// Records without a group go into the first top level group or into
+// a new "Import" group if the database is empty
+fn effective_group_path(db: &V1Kpdb, record: &ImportEntry) -> Vec<String> {
+    if !record.group_path.is_empty() {
+        return record.group_path.clone();
+    }
+    match db.root_group.borrow().child_groups().first() {
+        Some(child) => vec![child.borrow().title.clone()],
+        None => vec!["Import".to_string()],
+    }
+}
+
+// Find a group by the titles on the way from the root group
+pub fn find_group(db: &V1Kpdb, path: &[String]) -> Option<Rc<RefCell<V1Group>>> {
+    let mut current = db.root_group.clone();
+    for title in path {
+        let next = current.borrow()
+                          .child_groups()
+                          .into_iter()
+                          .find(|c| c.borrow().title == *title);
+        current = match next {
+            Some(g) => g,
+            None => return None,
+        };
+    }
+    if path.is_empty() {
+        None
+    } else {
+        Some(current)
+    }
+}
+
+/// Find the group at path or create it and the groups on the way
+pub fn create_groups(db: &mut V1Kpdb, path: &[String]) -> Result<Rc<RefCell<V1Group>>, V1KpdbError> {
+    let mut parent: Option<Rc<RefCell<V1Group>>> = None;
+    for depth in 1..path.len() + 1 {
+        parent = match find_group(db, &path[..depth]) {
+            Some(g) => Some(g),
+            None => {
+                Some(try!(db.create_group(path[depth - 1].clone(), None, None, parent.clone())))
+            }
+        };
+    }
+    match parent {
+        Some(g) => Ok(g),
+        None => Err(V1KpdbError::TreeErr),
+    }
+}
+
+// Entries are the same if title and username are equal
+fn is_same_entry(entry: &mut V1Entry, record: &ImportEntry) -> bool {
+    if entry.title != record.title {
+        return false;
+    }
+    match (&mut entry.username, &record.username) {
+        (&mut Some(ref mut username), &Some(ref other)) => {
+            username.unlock();
+            let is_same = username.string == *other;
+            username.delete();
+            is_same
+        }
+        (&mut None, &None) => true,
+        _ => false,
+    }
+}
+
+// Helpers of the importers of other password managers
+
+// Append line to the notes of record
+fn push_note(record: &mut ImportEntry, line: &str) {
+    record.notes = Some(match record.notes.take() {
+        Some(notes) => format!("{}\n{}", notes, line),
+        None => line.to_string(),
+    });
+}
+
+// Append line to the notes of record as protected section, see
+// V1Entry::protected_notes. It's moved into protected memory when the
+// database is loaded the next time.
+fn push_secret(record: &mut ImportEntry, line: &str) {
+    push_note(record, &format!("{}\n{}\n{}", SECRET_FENCE, line, SECRET_FENCE));
+}
+
+// Put url into the URL field or, if that's taken, add it as KP2A_URL line
+// to the notes, see V1Entry::additional_urls
+fn push_url(record: &mut ImportEntry, url: String) {
+    if url.is_empty() {
+        return;
+    }
+    if record.url.is_none() {
+        record.url = Some(url);
+        return;
+    }
+    let count = record.notes.as_ref().map_or(0, |n| {
+        n.lines().filter(|l| l.starts_with(ADDITIONAL_URL_PREFIX)).count()
+    });
+    push_note(record,
+              &format!("{}_{}: {}", ADDITIONAL_URL_PREFIX, count + 1, url));
+}
+
+// Add a TOTP secret or otpauth:// URI as otpauth line to the notes. A
+// bare secret is turned into a URI labeled with the title.
+fn push_otp(record: &mut ImportEntry, otp: &str) {
+    let otp = otp.trim();
+    if otp.is_empty() {
+        return;
+    }
+    let uri = if otp.to_lowercase().starts_with("otpauth://") {
+        otp.to_string()
+    } else {
+        let mut label: String = record.title
+                                      .bytes()
+                                      .map(|b| {
+                                          if b < 0x80 && (b as char).is_alphanumeric() {
+                                              (b as char).to_string()
+                                          } else {
+                                              format!("%{:02X}", b)
+                                          }
+                                      })
+                                      .collect();
+        if label.is_empty() {
+            label = "Imported".to_string();
+        }
+        let secret: String = otp.chars().filter(|c| !c.is_whitespace()).collect();
+        format!("otpauth://totp/{}?secret={}", label, secret.to_uppercase())
+    };
+    push_note(record, &uri);
+}
+
+// Move the string at key out of object. None if it's missing, empty or
+// no string.
+fn take_string(object: &mut json::Object, key: &str) -> Option<String> {
+    match object.remove(key) {
+        Some(Json::String(s)) => {
+            if s.is_empty() {
+                None
+            } else {
+                Some(s)
+            }
+        }
+        _ => None,
+    }
+}
@@ -0,0 +1,183 @@
+use std::io::Read;
+use std::str;
+
+use rustc_serialize::json::{self, Json};
+
+use kpdb::import::{push_note, push_otp, push_secret, push_url, take_string, ImportEntry};
+use kpdb::import::zip;
+use kpdb::v1error::V1KpdbError;
+use mem_protect;
+
+// Items in the trash have state 2
+const TRASHED: i64 = 2;
+
+/// Read a Proton Pass export from reader, see parse. The data read is
+/// overwritten with zeroes afterwards.
+pub fn read<R: Read>(mut reader: R) -> Result<Vec<ImportEntry>, V1KpdbError> {
+    let mut export = String::new();
+    let result = match reader.read_to_string(&mut export) {
+        Ok(_) => parse(&export),
+        Err(_) => Err(V1KpdbError::ReadErr),
+    };
+    unsafe {
+        mem_protect::zero(&export);
+    }
+    result
+}
+
+/// Parse the zip file Proton Pass exports, see parse for its data.json.
+/// The unpacked data.json is overwritten with zeroes afterwards. PGP
+/// encrypted exports hold no data.json and give ImportErr.
+pub fn parse_zip(data: &[u8]) -> Result<Vec<ImportEntry>, V1KpdbError> {
+    let files = try!(zip::files(data));
+    let file = try!(files.iter()
+                         .find(|f| f.base_name() == "data.json")
+                         .ok_or(V1KpdbError::ImportErr));
+    let export = try!(zip::extract(data, file));
+    let result = match str::from_utf8(&export) {
+        Ok(export) => parse(export),
+        Err(_) => Err(V1KpdbError::ImportErr),
+    };
+    unsafe {
+        mem_protect::zero(&export);
+    }
+    result
+}
+
+/// Parse the JSON export of Proton Pass, i.e. the data.json of the zip
+/// file it writes, see parse_zip. PGP encrypted exports have to be
+/// decrypted first, otherwise this returns ImportErr.
+///
+/// Each vault becomes a top level group. Logins keep username, password
+/// and URLs, additional URLs become KP2A_URL lines of the notes. TOTP
+/// URIs are added to the notes as otpauth:// lines, which validate
+/// checks. Hidden fields and card details go into protected sections of
+/// the notes. Items in the trash are skipped.
+pub fn parse(export: &str) -> Result<Vec<ImportEntry>, V1KpdbError> {
+    let mut root = match Json::from_str(export) {
+        Ok(Json::Object(root)) => root,
+        _ => return Err(V1KpdbError::ImportErr),
+    };
+    if root.get("encrypted") == Some(&Json::Boolean(true)) {
+        return Err(V1KpdbError::ImportErr);
+    }
+    let vaults = match root.remove("vaults") {
+        Some(Json::Object(vaults)) => vaults,
+        _ => return Err(V1KpdbError::ImportErr),
+    };
+
+    let mut records = vec![];
+    for (_, vault) in vaults {
+        let mut vault = try!(into_object(vault));
+        let name = take_string(&mut vault, "name").unwrap_or("Proton Pass".to_string());
+        let items = match vault.remove("items") {
+            Some(Json::Array(items)) => items,
+            _ => return Err(V1KpdbError::ImportErr),
+        };
+        for item in items {
+            let mut item = try!(into_object(item));
+            if item.get("state").and_then(|s| s.as_i64()) == Some(TRASHED) {
+                continue;
+            }
+            let data = match item.remove("data") {
+                Some(data) => try!(into_object(data)),
+                None => return Err(V1KpdbError::ImportErr),
+            };
+            let mut record = try!(parse_item(data));
+            record.group_path = vec![name.clone()];
+            // The address of an alias is part of the item, not its data
+            if let Some(alias) = take_string(&mut item, "aliasEmail") {
+                if record.username.is_none() {
+                    record.username = Some(alias);
+                }
+            }
+            records.push(record);
+        }
+    }
+    Ok(records)
+}
+
+fn parse_item(mut data: json::Object) -> Result<ImportEntry, V1KpdbError> {
+    let mut record = ImportEntry::new();
+    let mut metadata = match data.remove("metadata") {
+        Some(metadata) => try!(into_object(metadata)),
+        None => return Err(V1KpdbError::ImportErr),
+    };
+    record.title = take_string(&mut metadata, "name").unwrap_or(String::new());
+    record.notes = take_string(&mut metadata, "note");
+
+    let kind = take_string(&mut data, "type").unwrap_or(String::new());
+    let mut content = match data.remove("content") {
+        Some(Json::Object(content)) => content,
+        _ => json::Object::new(),
+    };
+    match &kind[..] {
+        "login" => {
+            // Older exports only have username
+            let username = take_string(&mut content, "itemUsername")
+                               .or_else(|| take_string(&mut content, "username"));
+            let email = take_string(&mut content, "itemEmail");
+            match (username, email) {
+                (Some(username), Some(email)) => {
+                    record.username = Some(username);
+                    push_note(&mut record, &format!("Email: {}", email));
+                }
+                (username, email) => record.username = username.or(email),
+            }
+            record.password = take_string(&mut content, "password");
+            if let Some(Json::Array(urls)) = content.remove("urls") {
+                for url in urls {
+                    if let Json::String(url) = url {
+                        push_url(&mut record, url);
+                    }
+                }
+            }
+            if let Some(totp) = take_string(&mut content, "totpUri") {
+                push_otp(&mut record, &totp);
+            }
+        }
+        "creditCard" => {
+            record.username = take_string(&mut content, "cardholderName");
+            record.password = take_string(&mut content, "number");
+            if let Some(expires) = take_string(&mut content, "expirationDate") {
+                push_note(&mut record, &format!("Expires: {}", expires));
+            }
+            for &(key, label) in [("verificationNumber", "CVV"), ("pin", "PIN")].iter() {
+                if let Some(value) = take_string(&mut content, key) {
+                    push_secret(&mut record, &format!("{}: {}", label, value));
+                }
+            }
+        }
+        // Notes, aliases and identities keep title and note
+        _ => {}
+    }
+
+    if let Some(Json::Array(fields)) = data.remove("extraFields") {
+        for field in fields {
+            let mut field = try!(into_object(field));
+            let name = take_string(&mut field, "fieldName").unwrap_or(String::new());
+            let kind = take_string(&mut field, "type").unwrap_or(String::new());
+            let value = match field.remove("data") {
+                Some(Json::Object(mut data)) => take_string(&mut data, "content"),
+                _ => None,
+            };
+            let value = match value {
+                Some(value) => value,
+                None => continue,
+            };
+            match &kind[..] {
+                "totp" => push_otp(&mut record, &value),
+                "hidden" => push_secret(&mut record, &format!("{}: {}", name, value)),
+                _ => push_note(&mut record, &format!("{}: {}", name, value)),
+            }
+        }
+    }
+    Ok(record)
+}
+
+fn into_object(value: Json) -> Result<json::Object, V1KpdbError> {
+    match value {
+        Json::Object(object) => Ok(object),
+        _ => Err(V1KpdbError::ImportErr),
+    }
+}
@@ -0,0 +1,270 @@
+//! Password Safe v3 files (.psafe3)
+//!
+//! The layout, all numbers little endian, see formatV3.txt of Password
+//! Safe:
+//!
+//! * 4 bytes TAG
+//! * 32 bytes salt
+//! * 4 bytes number of iterations
+//! * 32 bytes SHA256 of the stretched passphrase P'
+//! * 32 bytes record key K, encrypted with Twofish-ECB under P'
+//! * 32 bytes HMAC key L, encrypted with Twofish-ECB under P'
+//! * 16 bytes IV
+//! * the fields of the header and the records, encrypted with
+//!   Twofish-CBC under K
+//! * 16 bytes EOF_MARKER, not encrypted
+//! * 32 bytes HMAC-SHA256 under L of the data of all fields
+//!
+//! P' is SHA256 of the passphrase and the salt, hashed again once per
+//! iteration. A field is 4 bytes length, 1 byte type and the data,
+//! padded to whole blocks of 16 bytes. A field of type END closes the
+//! header and every record.
+
+use std::io::{Read, Write};
+
+use chrono::{DateTime, Local, TimeZone};
+use openssl::crypto::hash::{hash, Hasher, Type};
+use openssl::crypto::hmac::HMAC;
+
+use kpdb::crypter::constant_time_eq;
+use kpdb::import::{push_note, push_url, ImportEntry};
+use kpdb::twofish::{Twofish, BLOCK_LEN};
+use kpdb::v1error::V1KpdbError;
+use mem_protect;
+use sec_str::SecureBytes;
+
+/// First bytes of every Password Safe v3 file
+pub const TAG: &'static [u8] = b"PWS3";
+
+/// Marks the end of the encrypted fields
+pub const EOF_MARKER: &'static [u8] = b"PWS3-EOFPWS3-EOF";
+
+/// Files which stretch the passphrase more often are rejected, so that
+/// a crafted file can't keep parse busy for hours
+pub const MAX_ITERATIONS: u32 = 1 << 24;
+
+const SALT_LEN: usize = 32;
+const HEADER_LEN: usize = 152;
+const HMAC_LEN: usize = 32;
+
+// Types of record fields
+const GROUP: u8 = 0x02;
+const TITLE: u8 = 0x03;
+const USERNAME: u8 = 0x04;
+const NOTES: u8 = 0x05;
+const PASSWORD: u8 = 0x06;
+const EXPIRE: u8 = 0x0a;
+const URL: u8 = 0x0d;
+const EMAIL: u8 = 0x14;
+// Closes the header and a record
+const END: u8 = 0xff;
+
+/// Read a Password Safe v3 file from reader, see parse
+pub fn read<R: Read>(mut reader: R, passphrase: &[u8]) -> Result<Vec<ImportEntry>, V1KpdbError> {
+    let mut data = vec![];
+    try!(reader.read_to_end(&mut data).map_err(|_| V1KpdbError::ReadErr));
+    parse(&data, passphrase)
+}
+
+/// Decrypt a Password Safe v3 file with passphrase and parse its
+/// records. ImportErr if it isn't such a file or it's malformed, HashErr
+/// for a wrong passphrase or a changed file, RoundsErr for more than
+/// MAX_ITERATIONS iterations.
+///
+/// Groups are nested by the dots in their names like in Password Safe.
+/// Notes keep their text with Unix line breaks, an email address becomes
+/// the username if there's none and a note otherwise. The password
+/// history, autotype and the other fields are skipped.
+pub fn parse(data: &[u8], passphrase: &[u8]) -> Result<Vec<ImportEntry>, V1KpdbError> {
+    if data.len() < HEADER_LEN + EOF_MARKER.len() + HMAC_LEN || &data[..TAG.len()] != TAG {
+        return Err(V1KpdbError::ImportErr);
+    }
+    let salt = &data[4..4 + SALT_LEN];
+    let iterations = (data[36] as u32) | (data[37] as u32) << 8 | (data[38] as u32) << 16 |
+                     (data[39] as u32) << 24;
+    if iterations > MAX_ITERATIONS {
+        return Err(V1KpdbError::RoundsErr);
+    }
+    let stretched = stretch(passphrase, salt, iterations);
+    if !constant_time_eq(&hash(Type::SHA256, stretched.bytes()), &data[40..72]) {
+        return Err(V1KpdbError::HashErr);
+    }
+    let twofish = Twofish::new(stretched.bytes());
+    let record_key = decrypt_ecb(&twofish, &data[72..104]);
+    let hmac_key = decrypt_ecb(&twofish, &data[104..136]);
+    let iv = &data[136..HEADER_LEN];
+
+    // The marker is a whole block after the last encrypted one
+    let encrypted = &data[HEADER_LEN..];
+    let mut end = None;
+    for (i, block) in encrypted.chunks(BLOCK_LEN).enumerate() {
+        if block == EOF_MARKER {
+            end = Some(i * BLOCK_LEN);
+            break;
+        }
+    }
+    let end = match end {
+        Some(end) if encrypted.len() - end - EOF_MARKER.len() == HMAC_LEN => end,
+        _ => return Err(V1KpdbError::ImportErr),
+    };
+    let plaintext = decrypt_cbc(&Twofish::new(record_key.bytes()), iv, &encrypted[..end]);
+    let fields = try!(split_fields(plaintext.bytes()));
+
+    let mut mac = HMAC::new(Type::SHA256, hmac_key.bytes());
+    for &(_, data) in fields.iter() {
+        try!(mac.write_all(data).map_err(|_| V1KpdbError::HashErr));
+    }
+    if !constant_time_eq(&mac.finish(), &encrypted[end + EOF_MARKER.len()..]) {
+        return Err(V1KpdbError::HashErr);
+    }
+
+    // The header only describes the file
+    let header_end = match fields.iter().position(|&(kind, _)| kind == END) {
+        Some(index) => index,
+        None => return Err(V1KpdbError::ImportErr),
+    };
+    let mut records = vec![];
+    let mut record = ImportEntry::new();
+    let mut complete = true;
+    for &(kind, data) in fields[header_end + 1..].iter() {
+        complete = kind == END;
+        if complete {
+            records.push(record);
+            record = ImportEntry::new();
+        } else {
+            try!(set_field(&mut record, kind, data));
+        }
+    }
+    if !complete {
+        return Err(V1KpdbError::ImportErr);
+    }
+    Ok(records)
+}
+
+// SHA256 of passphrase and salt, hashed iterations times more
+fn stretch(passphrase: &[u8], salt: &[u8], iterations: u32) -> SecureBytes {
+    let mut hasher = Hasher::new(Type::SHA256);
+    let _ = hasher.write_all(passphrase);
+    let _ = hasher.write_all(salt);
+    let mut key = hasher.finish();
+    for _ in 0..iterations {
+        let next = hash(Type::SHA256, &key);
+        unsafe {
+            mem_protect::zero(&key);
+        }
+        key = next;
+    }
+    SecureBytes::new(key)
+}
+
+fn decrypt_ecb(twofish: &Twofish, ciphertext: &[u8]) -> SecureBytes {
+    let mut plaintext = ciphertext.to_vec();
+    for block in plaintext.chunks_mut(BLOCK_LEN) {
+        twofish.decrypt_block(block);
+    }
+    SecureBytes::new(plaintext)
+}
+
+fn decrypt_cbc(twofish: &Twofish, iv: &[u8], ciphertext: &[u8]) -> SecureBytes {
+    let mut plaintext = ciphertext.to_vec();
+    let mut previous = iv;
+    for (block, encrypted) in plaintext.chunks_mut(BLOCK_LEN).zip(ciphertext.chunks(BLOCK_LEN)) {
+        twofish.decrypt_block(block);
+        for (b, p) in block.iter_mut().zip(previous.iter()) {
+            *b ^= *p;
+        }
+        previous = encrypted;
+    }
+    SecureBytes::new(plaintext)
+}
+
+// The type and data of every field in plaintext
+fn split_fields(plaintext: &[u8]) -> Result<Vec<(u8, &[u8])>, V1KpdbError> {
+    let mut fields = vec![];
+    let mut pos = 0;
+    while pos < plaintext.len() {
+        let rest = &plaintext[pos..];
+        if rest.len() < 5 {
+            return Err(V1KpdbError::ImportErr);
+        }
+        let len = ((rest[0] as u32) | (rest[1] as u32) << 8 | (rest[2] as u32) << 16 |
+                   (rest[3] as u32) << 24) as usize;
+        if len > rest.len() - 5 {
+            return Err(V1KpdbError::ImportErr);
+        }
+        fields.push((rest[4], &rest[5..5 + len]));
+        // The padding fills the last block of the field
+        pos += (5 + len + BLOCK_LEN - 1) / BLOCK_LEN * BLOCK_LEN;
+    }
+    Ok(fields)
+}
+
+fn set_field(record: &mut ImportEntry, kind: u8, data: &[u8]) -> Result<(), V1KpdbError> {
+    match kind {
+        GROUP => record.group_path = split_group(&try!(text(data))),
+        TITLE => record.title = try!(text(data)),
+        USERNAME => record.username = Some(try!(text(data))),
+        NOTES => {
+            let notes = try!(text(data));
+            push_note(record, &notes.replace("\r\n", "\n"));
+        }
+        PASSWORD => record.password = Some(try!(text(data))),
+        EXPIRE => record.expire = time(data),
+        URL => push_url(record, try!(text(data))),
+        EMAIL => {
+            let email = try!(text(data));
+            if record.username.is_none() {
+                record.username = Some(email);
+            } else {
+                push_note(record, &format!("Email: {}", email));
+            }
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+// The UTF-8 text of a field. Invalid data is overwritten with zeroes.
+fn text(data: &[u8]) -> Result<String, V1KpdbError> {
+    match String::from_utf8(data.to_vec()) {
+        Ok(text) => Ok(text),
+        Err(err) => {
+            unsafe {
+                mem_protect::zero(&err.into_bytes());
+            }
+            Err(V1KpdbError::ImportErr)
+        }
+    }
+}
+
+// A time_t of 4 or 8 bytes. None for 0, which means no time.
+fn time(data: &[u8]) -> Option<DateTime<Local>> {
+    if data.len() != 4 && data.len() != 8 {
+        return None;
+    }
+    let secs = data.iter().rev().fold(0i64, |secs, b| secs << 8 | *b as i64);
+    if secs == 0 {
+        return None;
+    }
+    Local.timestamp_opt(secs, 0).single()
+}
+
+// The levels of a group name like "Internet.Email", a dot in a title is
+// escaped as "\."
+fn split_group(name: &str) -> Vec<String> {
+    let mut titles = vec![];
+    let mut title = String::new();
+    let mut chars = name.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' if chars.peek() == Some(&'.') => {
+                title.push('.');
+                chars.next();
+            }
+            '.' => titles.push(title.split_off(0)),
+            c => title.push(c),
+        }
+    }
+    titles.push(title);
+    titles.into_iter().filter(|t| !t.is_empty()).collect()
+}
@@ -0,0 +1,235 @@
+use std::mem;
+
+use kpdb::import::ImportEntry;
+
+/// A problem of an imported record
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum IssueKind {
+    /// The URL isn't normalized, e.g. lacks a scheme. Holds the
+    /// normalized URL.
+    UrlNotNormalized(String),
+    /// An otpauth:// URI in the URL or the notes is malformed. The URI
+    /// isn't part of the issue as it holds the secret.
+    InvalidOtpAuth,
+    /// Username and password seem to be swapped, e.g. because the
+    /// columns of a CSV export were mapped the wrong way round
+    SwappedCredentials,
+}
+
+impl IssueKind {
+    /// True if fix can correct the issue
+    pub fn is_fixable(&self) -> bool {
+        match *self {
+            IssueKind::UrlNotNormalized(_) | IssueKind::SwappedCredentials => true,
+            IssueKind::InvalidOtpAuth => false,
+        }
+    }
+}
+
+/// An issue of a single record
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ImportIssue {
+    /// Index of the record in the imported records
+    pub index: usize,
+    pub kind: IssueKind,
+}
+
+#[doc = "
+ImportReport lists the issues validate found in imported records, so
+that applications can show them and let the user decide before the
+records are applied. Fixable issues are corrected by fix.
+"]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ImportReport {
+    pub issues: Vec<ImportIssue>,
+}
+
+impl ImportReport {
+    pub fn is_clean(&self) -> bool {
+        self.issues.is_empty()
+    }
+
+    /// The issues fix can correct
+    pub fn fixable(&self) -> Vec<&ImportIssue> {
+        self.issues.iter().filter(|i| i.kind.is_fixable()).collect()
+    }
+}
+
+/// Check records for issues without changing them
+pub fn validate(records: &[ImportEntry]) -> ImportReport {
+    let mut issues = vec![];
+    for (index, record) in records.iter().enumerate() {
+        let mut push = |kind| {
+            issues.push(ImportIssue {
+                index: index,
+                kind: kind,
+            })
+        };
+
+        if let Some(ref url) = record.url {
+            if is_otpauth(url) {
+                if !is_valid_otpauth(url.trim()) {
+                    push(IssueKind::InvalidOtpAuth);
+                }
+            } else if let Some(normalized) = normalize_url(url) {
+                push(IssueKind::UrlNotNormalized(normalized));
+            }
+        }
+        if let Some(ref notes) = record.notes {
+            for line in notes.lines().map(|l| l.trim()).filter(|l| is_otpauth(l)) {
+                if !is_valid_otpauth(line) {
+                    push(IssueKind::InvalidOtpAuth);
+                }
+            }
+        }
+        if let (&Some(ref username), &Some(ref password)) = (&record.username, &record.password) {
+            if looks_swapped(username, password) {
+                push(IssueKind::SwappedCredentials);
+            }
+        }
+    }
+    ImportReport { issues: issues }
+}
+
+/// Correct the fixable issues of report in records. records must be the
+/// ones report was made for.
+pub fn fix(records: &mut [ImportEntry], report: &ImportReport) {
+    for issue in report.issues.iter() {
+        let record = match records.get_mut(issue.index) {
+            Some(record) => record,
+            None => continue,
+        };
+        match issue.kind {
+            IssueKind::UrlNotNormalized(ref url) => record.url = Some(url.clone()),
+            // Swapping moves the strings, so no copies of the password
+            // are left behind
+            IssueKind::SwappedCredentials => {
+                mem::swap(&mut record.username, &mut record.password)
+            }
+            IssueKind::InvalidOtpAuth => {}
+        }
+    }
+}
+
+// Normalized form of url: without surrounding whitespace, with a scheme
+// and with lowercase scheme and host. None if url is normalized already
+// or isn't recognizable as URL.
+fn normalize_url(url: &str) -> Option<String> {
+    let trimmed = url.trim();
+    if trimmed.is_empty() || trimmed.contains(char::is_whitespace) {
+        return None;
+    }
+    let (scheme, rest) = match trimmed.find("://") {
+        Some(i) => (trimmed[..i].to_lowercase(), &trimmed[i + 3..]),
+        // Something like example.com/login
+        None if trimmed.contains('.') && !trimmed.starts_with('.') => {
+            ("https".to_string(), trimmed)
+        }
+        None => return None,
+    };
+    let host_end = rest.find(|c| c == '/' || c == '?' || c == '#').unwrap_or(rest.len());
+    let (authority, path) = rest.split_at(host_end);
+    // Only the host is case insensitive, not the user info
+    let authority = match authority.rfind('@') {
+        Some(i) => format!("{}{}", &authority[..i + 1], authority[i + 1..].to_lowercase()),
+        None => authority.to_lowercase(),
+    };
+    let normalized = format!("{}://{}{}", scheme, authority, path);
+    if normalized == url {
+        None
+    } else {
+        Some(normalized)
+    }
+}
+
+fn is_otpauth(s: &str) -> bool {
+    s.trim().to_lowercase().starts_with("otpauth://")
+}
+
+// Check an otpauth URI as specified by the Key Uri Format:
+// otpauth://TYPE/LABEL?PARAMETERS with a base32 secret
+fn is_valid_otpauth(uri: &str) -> bool {
+    let rest = &uri["otpauth://".len()..];
+    let (path, query) = match rest.find('?') {
+        Some(i) => (&rest[..i], &rest[i + 1..]),
+        None => return false,
+    };
+    let otp_type = match path.find('/') {
+        Some(i) if i + 1 < path.len() => path[..i].to_lowercase(),
+        _ => return false,
+    };
+    if otp_type != "totp" && otp_type != "hotp" {
+        return false;
+    }
+
+    let mut secret = false;
+    let mut counter = false;
+    for param in query.split('&') {
+        let mut parts = param.splitn(2, '=');
+        let (key, value) = match (parts.next(), parts.next()) {
+            (Some(key), Some(value)) => (key, value),
+            _ => return false,
+        };
+        let valid = match key {
+            "secret" => {
+                secret = true;
+                is_base32(value)
+            }
+            "counter" => {
+                counter = true;
+                value.parse::<u64>().is_ok()
+            }
+            "digits" => value == "6" || value == "7" || value == "8",
+            "period" => value.parse::<u32>().map_or(false, |p| p > 0),
+            "algorithm" => {
+                let value = value.to_uppercase();
+                value == "SHA1" || value == "SHA256" || value == "SHA512"
+            }
+            _ => true,
+        };
+        if !valid {
+            return false;
+        }
+    }
+    secret && (otp_type == "totp" || counter)
+}
+
+fn is_base32(s: &str) -> bool {
+    let s = s.trim_right_matches('=');
+    !s.is_empty() &&
+    s.chars().all(|c| {
+        match c {
+            'A'...'Z' | 'a'...'z' | '2'...'7' => true,
+            _ => false,
+        }
+    })
+}
+
+// Heuristic: the username field holds something which looks like a
+// generated password and the password field holds something which looks
+// like a username or an email address
+fn looks_swapped(username: &str, password: &str) -> bool {
+    looks_like_password(username) && !looks_like_password(password) &&
+    looks_like_username(password)
+}
+
+fn looks_like_password(s: &str) -> bool {
+    if s.chars().count() < 8 || s.contains(char::is_whitespace) || s.contains('@') {
+        return false;
+    }
+    let classes = [s.chars().any(|c| c.is_lowercase()),
+                   s.chars().any(|c| c.is_uppercase()),
+                   s.chars().any(|c| c.is_numeric()),
+                   s.chars().any(|c| !c.is_alphanumeric())];
+    classes.iter().filter(|c| **c).count() >= 3
+}
+
+fn looks_like_username(s: &str) -> bool {
+    if s.is_empty() || s.len() > 64 || s.contains(char::is_whitespace) {
+        return false;
+    }
+    if let Some(at) = s.find('@') {
+        return at > 0 && s[at + 1..].contains('.');
+    }
+    s.chars().all(|c| c.is_lowercase() || c.is_numeric() || c == '.' || c == '_' || c == '-')
+}
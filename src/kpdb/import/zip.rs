@@ -0,0 +1,421 @@
+//! Zip files as written by the exporters of Proton Pass and Dashlane
+//!
+//! Only what these need is supported, see APPNOTE.TXT of PKWARE: a
+//! single disk, no Zip64, no encryption and the methods stored (0) and
+//! deflate (8, RFC 1951). Everything else gives ImportErr. The files are
+//! found through the central directory at the end of the zip:
+//!
+//! * end of central directory: 4 bytes signature, 12 bytes disk numbers
+//!   and counts, 2 bytes total number of files, 4 bytes size and 4 bytes
+//!   offset of the central directory, 2 bytes comment length
+//! * central directory header per file: 4 bytes signature, 4 bytes
+//!   versions, 2 bytes flags, 2 bytes method, 4 bytes time, 4 bytes
+//!   CRC-32, 4 bytes compressed and 4 bytes uncompressed size, 2 bytes
+//!   name length, 2 bytes extra length, 2 bytes comment length, 8 bytes
+//!   disk and attributes, 4 bytes offset of the local header, the name
+//! * local header: 26 bytes like the central one, 2 bytes name length,
+//!   2 bytes extra length, the name, the extra field and the data
+//!
+//! All numbers are little endian. The unpacked files hold the secrets of
+//! the export, callers overwrite them with zeroes when they're done.
+
+use kpdb::common::{slice_to_u16, slice_to_u32};
+use kpdb::v1error::V1KpdbError;
+use mem_protect;
+
+/// Larger files are rejected, so that a crafted zip can't exhaust the
+/// memory
+pub const MAX_FILE_SIZE: usize = 1 << 26;
+
+const END_SIGNATURE: u32 = 0x06054b50;
+const CENTRAL_SIGNATURE: u32 = 0x02014b50;
+const LOCAL_SIGNATURE: u32 = 0x04034b50;
+const END_LEN: usize = 22;
+const CENTRAL_LEN: usize = 46;
+const LOCAL_LEN: usize = 30;
+// The comment at the end is at most this long
+const MAX_COMMENT_LEN: usize = 0xFFFF;
+
+const STORED: u16 = 0;
+const DEFLATE: u16 = 8;
+const FLAG_ENCRYPTED: u16 = 1;
+
+/// A file in a zip, see files
+pub struct ZipFile {
+    /// Path of the file inside the zip, with / as separator
+    pub name: String,
+    method: u16,
+    flags: u16,
+    crc: u32,
+    compressed_size: usize,
+    size: usize,
+    offset: usize,
+}
+
+impl ZipFile {
+    /// The name without the directories
+    pub fn base_name(&self) -> &str {
+        self.name.rsplit('/').next().unwrap_or("")
+    }
+}
+
+/// The files in data, directories excluded. ImportErr if data isn't a
+/// zip file this module can read.
+pub fn files(data: &[u8]) -> Result<Vec<ZipFile>, V1KpdbError> {
+    let end = try!(find_end(data));
+    let count = try!(u16_at(data, end + 10)) as usize;
+    let mut pos = try!(u32_at(data, end + 16)) as usize;
+    let mut files = vec![];
+    for _ in 0..count {
+        if try!(u32_at(data, pos)) != CENTRAL_SIGNATURE {
+            return Err(V1KpdbError::ImportErr);
+        }
+        let name_len = try!(u16_at(data, pos + 28)) as usize;
+        let extra_len = try!(u16_at(data, pos + 30)) as usize;
+        let comment_len = try!(u16_at(data, pos + 32)) as usize;
+        let name = try!(data.get(pos + CENTRAL_LEN..pos + CENTRAL_LEN + name_len)
+                            .ok_or(V1KpdbError::ImportErr));
+        let file = ZipFile {
+            name: String::from_utf8_lossy(name).into_owned(),
+            flags: try!(u16_at(data, pos + 8)),
+            method: try!(u16_at(data, pos + 10)),
+            crc: try!(u32_at(data, pos + 16)),
+            compressed_size: try!(u32_at(data, pos + 20)) as usize,
+            size: try!(u32_at(data, pos + 24)) as usize,
+            offset: try!(u32_at(data, pos + 42)) as usize,
+        };
+        if !file.name.ends_with('/') {
+            files.push(file);
+        }
+        pos += CENTRAL_LEN + name_len + extra_len + comment_len;
+    }
+    Ok(files)
+}
+
+/// Unpack file, which is one of the files of data. ImportErr if the
+/// method isn't supported, the file is encrypted or larger than
+/// MAX_FILE_SIZE or its data doesn't match its CRC-32.
+pub fn extract(data: &[u8], file: &ZipFile) -> Result<Vec<u8>, V1KpdbError> {
+    if file.flags & FLAG_ENCRYPTED != 0 || file.size > MAX_FILE_SIZE {
+        return Err(V1KpdbError::ImportErr);
+    }
+    if try!(u32_at(data, file.offset)) != LOCAL_SIGNATURE {
+        return Err(V1KpdbError::ImportErr);
+    }
+    let name_len = try!(u16_at(data, file.offset + 26)) as usize;
+    let extra_len = try!(u16_at(data, file.offset + 28)) as usize;
+    let start = file.offset + LOCAL_LEN + name_len + extra_len;
+    let compressed = try!(data.get(start..start + file.compressed_size)
+                              .ok_or(V1KpdbError::ImportErr));
+
+    // The capacity is never exceeded, so no copy of the content is left
+    // behind by a reallocation
+    let mut content = Vec::with_capacity(file.size);
+    let result = match file.method {
+        STORED => {
+            content.extend_from_slice(compressed);
+            Ok(())
+        }
+        DEFLATE => inflate(compressed, &mut content, file.size),
+        _ => Err(V1KpdbError::ImportErr),
+    };
+    match result {
+        Ok(()) if content.len() == file.size && crc32(&content) == file.crc => Ok(content),
+        _ => {
+            unsafe {
+                mem_protect::zero(&content);
+            }
+            Err(V1KpdbError::ImportErr)
+        }
+    }
+}
+
+// Position of the end of central directory record. It's searched
+// backwards as the comment may contain anything.
+fn find_end(data: &[u8]) -> Result<usize, V1KpdbError> {
+    if data.len() < END_LEN {
+        return Err(V1KpdbError::ImportErr);
+    }
+    let last = data.len() - END_LEN;
+    let first = last.saturating_sub(MAX_COMMENT_LEN);
+    for pos in (first..last + 1).rev() {
+        if try!(u32_at(data, pos)) == END_SIGNATURE {
+            return Ok(pos);
+        }
+    }
+    Err(V1KpdbError::ImportErr)
+}
+
+fn u16_at(data: &[u8], pos: usize) -> Result<u16, V1KpdbError> {
+    data.get(pos..).map_or(Err(V1KpdbError::ImportErr), |d| {
+        slice_to_u16(d).map_err(|_| V1KpdbError::ImportErr)
+    })
+}
+
+fn u32_at(data: &[u8], pos: usize) -> Result<u32, V1KpdbError> {
+    data.get(pos..).map_or(Err(V1KpdbError::ImportErr), |d| {
+        slice_to_u32(d).map_err(|_| V1KpdbError::ImportErr)
+    })
+}
+
+/// CRC-32 as used by zip (polynomial 0xEDB88320)
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xFFFFFFFFu32;
+    for byte in data {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xEDB88320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
+// Base lengths and extra bits of the length symbols 257 to 285
+const LENGTH_BASE: [u16; 29] = [3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43,
+                                51, 59, 67, 83, 99, 115, 131, 163, 195, 227, 258];
+const LENGTH_EXTRA: [u8; 29] = [0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4,
+                                4, 4, 5, 5, 5, 5, 0];
+// Base distances and extra bits of the distance symbols 0 to 29
+const DIST_BASE: [u16; 30] = [1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257,
+                              385, 513, 769, 1025, 1537, 2049, 3073, 4097, 6145, 8193, 12289,
+                              16385, 24577];
+const DIST_EXTRA: [u8; 30] = [0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9,
+                              10, 10, 11, 11, 12, 12, 13, 13];
+// Order of the code length code lengths of a dynamic block
+const CODE_LENGTH_ORDER: [usize; 19] = [16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2,
+                                        14, 1, 15];
+const MAX_BITS: usize = 15;
+
+// Reads the bits of a deflate stream, least significant bit first
+struct BitReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+    buffer: u32,
+    count: u32,
+}
+
+impl<'a> BitReader<'a> {
+    fn new(data: &'a [u8]) -> BitReader<'a> {
+        BitReader {
+            data: data,
+            pos: 0,
+            buffer: 0,
+            count: 0,
+        }
+    }
+
+    fn bits(&mut self, n: u32) -> Result<u32, V1KpdbError> {
+        while self.count < n {
+            let byte = try!(self.data.get(self.pos).ok_or(V1KpdbError::ImportErr));
+            self.buffer |= (*byte as u32) << self.count;
+            self.pos += 1;
+            self.count += 8;
+        }
+        let value = self.buffer & ((1u32 << n) - 1);
+        self.buffer >>= n;
+        self.count -= n;
+        Ok(value)
+    }
+
+    // Drop the bits left of the current byte, stored blocks start at a
+    // byte boundary
+    fn align(&mut self) {
+        self.buffer = 0;
+        self.count = 0;
+    }
+}
+
+// A canonical Huffman code: the number of codes of each length and the
+// symbols ordered by code
+struct Huffman {
+    counts: [u16; MAX_BITS + 1],
+    symbols: Vec<u16>,
+}
+
+impl Huffman {
+    // The code given by the code length of each symbol, 0 for unused
+    // symbols. Over-subscribed lengths give ImportErr.
+    fn new(lengths: &[u8]) -> Result<Huffman, V1KpdbError> {
+        let mut counts = [0u16; MAX_BITS + 1];
+        for length in lengths {
+            counts[*length as usize] += 1;
+        }
+        let mut left = 1i32;
+        for count in counts.iter().skip(1) {
+            left = (left << 1) - *count as i32;
+            if left < 0 {
+                return Err(V1KpdbError::ImportErr);
+            }
+        }
+        let mut offsets = [0u16; MAX_BITS + 1];
+        for len in 1..MAX_BITS {
+            offsets[len + 1] = offsets[len] + counts[len];
+        }
+        let mut symbols = vec![0; lengths.len()];
+        for (symbol, length) in lengths.iter().enumerate() {
+            if *length != 0 {
+                symbols[offsets[*length as usize] as usize] = symbol as u16;
+                offsets[*length as usize] += 1;
+            }
+        }
+        Ok(Huffman {
+            counts: counts,
+            symbols: symbols,
+        })
+    }
+
+    fn decode(&self, reader: &mut BitReader) -> Result<u16, V1KpdbError> {
+        let mut code = 0i32;
+        let mut first = 0i32;
+        let mut index = 0i32;
+        for len in 1..MAX_BITS + 1 {
+            code |= try!(reader.bits(1)) as i32;
+            let count = self.counts[len] as i32;
+            if code - first < count {
+                return Ok(self.symbols[(index + code - first) as usize]);
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+        Err(V1KpdbError::ImportErr)
+    }
+}
+
+// Decompress the deflate stream data into out, which must not grow
+// beyond size
+fn inflate(data: &[u8], out: &mut Vec<u8>, size: usize) -> Result<(), V1KpdbError> {
+    let mut reader = BitReader::new(data);
+    loop {
+        let last = try!(reader.bits(1)) == 1;
+        match try!(reader.bits(2)) {
+            0 => try!(inflate_stored(&mut reader, out, size)),
+            1 => {
+                let (lengths, distances) = try!(fixed_codes());
+                try!(inflate_codes(&mut reader, out, size, &lengths, &distances));
+            }
+            2 => {
+                let (lengths, distances) = try!(dynamic_codes(&mut reader));
+                try!(inflate_codes(&mut reader, out, size, &lengths, &distances));
+            }
+            _ => return Err(V1KpdbError::ImportErr),
+        }
+        if last {
+            return Ok(());
+        }
+    }
+}
+
+fn inflate_stored(reader: &mut BitReader, out: &mut Vec<u8>, size: usize)
+                  -> Result<(), V1KpdbError> {
+    reader.align();
+    let len = try!(reader.bits(16)) as usize;
+    let complement = try!(reader.bits(16)) as usize;
+    if len != !complement & 0xFFFF || out.len() + len > size {
+        return Err(V1KpdbError::ImportErr);
+    }
+    let start = reader.pos;
+    let stored = try!(reader.data.get(start..start + len).ok_or(V1KpdbError::ImportErr));
+    out.extend_from_slice(stored);
+    reader.pos += len;
+    Ok(())
+}
+
+fn fixed_codes() -> Result<(Huffman, Huffman), V1KpdbError> {
+    let mut lengths = [0u8; 288];
+    for (symbol, length) in lengths.iter_mut().enumerate() {
+        *length = match symbol {
+            0...143 => 8,
+            144...255 => 9,
+            256...279 => 7,
+            _ => 8,
+        };
+    }
+    Ok((try!(Huffman::new(&lengths)), try!(Huffman::new(&[5u8; 30]))))
+}
+
+fn dynamic_codes(reader: &mut BitReader) -> Result<(Huffman, Huffman), V1KpdbError> {
+    let num_lengths = try!(reader.bits(5)) as usize + 257;
+    let num_distances = try!(reader.bits(5)) as usize + 1;
+    let num_code_lengths = try!(reader.bits(4)) as usize + 4;
+    if num_lengths > 286 || num_distances > 30 {
+        return Err(V1KpdbError::ImportErr);
+    }
+    let mut code_lengths = [0u8; 19];
+    for i in 0..num_code_lengths {
+        code_lengths[CODE_LENGTH_ORDER[i]] = try!(reader.bits(3)) as u8;
+    }
+    let code_lengths = try!(Huffman::new(&code_lengths));
+
+    let mut lengths = vec![];
+    while lengths.len() < num_lengths + num_distances {
+        let symbol = try!(code_lengths.decode(reader));
+        let (length, repeat) = match symbol {
+            0...15 => (symbol as u8, 1),
+            16 => {
+                let previous = try!(lengths.last().cloned().ok_or(V1KpdbError::ImportErr));
+                (previous, 3 + try!(reader.bits(2)))
+            }
+            17 => (0, 3 + try!(reader.bits(3))),
+            _ => (0, 11 + try!(reader.bits(7))),
+        };
+        if lengths.len() + repeat as usize > num_lengths + num_distances {
+            return Err(V1KpdbError::ImportErr);
+        }
+        for _ in 0..repeat {
+            lengths.push(length);
+        }
+    }
+    // Without an end of block code the block can't end
+    if lengths[256] == 0 {
+        return Err(V1KpdbError::ImportErr);
+    }
+    Ok((try!(Huffman::new(&lengths[..num_lengths])),
+        try!(Huffman::new(&lengths[num_lengths..]))))
+}
+
+fn inflate_codes(reader: &mut BitReader,
+                 out: &mut Vec<u8>,
+                 size: usize,
+                 lengths: &Huffman,
+                 distances: &Huffman)
+                 -> Result<(), V1KpdbError> {
+    loop {
+        let symbol = try!(lengths.decode(reader)) as usize;
+        if symbol < 256 {
+            if out.len() == size {
+                return Err(V1KpdbError::ImportErr);
+            }
+            out.push(symbol as u8);
+            continue;
+        }
+        if symbol == 256 {
+            return Ok(());
+        }
+        let symbol = symbol - 257;
+        if symbol >= LENGTH_BASE.len() {
+            return Err(V1KpdbError::ImportErr);
+        }
+        let len = LENGTH_BASE[symbol] as usize +
+                  try!(reader.bits(LENGTH_EXTRA[symbol] as u32)) as usize;
+        let symbol = try!(distances.decode(reader)) as usize;
+        if symbol >= DIST_BASE.len() {
+            return Err(V1KpdbError::ImportErr);
+        }
+        let distance = DIST_BASE[symbol] as usize +
+                       try!(reader.bits(DIST_EXTRA[symbol] as u32)) as usize;
+        if distance > out.len() || out.len() + len > size {
+            return Err(V1KpdbError::ImportErr);
+        }
+        // The copy may overlap what it appends, hence byte by byte
+        let start = out.len() - distance;
+        for i in 0..len {
+            let byte = out[start + i];
+            out.push(byte);
+        }
+    }
+}
@@ -0,0 +1,101 @@
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::rc::Rc;
+
+use kpdb::v1entry::V1Entry;
+use kpdb::v1group::V1Group;
+
+/// Order in which the group tree is traversed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Traversal {
+    /// A group is followed by all its subgroups before its siblings
+    DepthFirst,
+    /// All groups of a level come before the groups of the next level
+    BreadthFirst,
+}
+
+#[doc = "
+Iterator over the groups of a group tree. It yields strong references,
+weak references to already dropped groups are skipped. The root group
+itself is not yielded.
+"]
+pub struct GroupIter {
+    pending: VecDeque<Rc<RefCell<V1Group>>>,
+    traversal: Traversal,
+}
+
+impl GroupIter {
+    /// Iterate over all subgroups of root
+    pub fn new(root: &Rc<RefCell<V1Group>>, traversal: Traversal) -> GroupIter {
+        let mut iter = GroupIter {
+            pending: VecDeque::new(),
+            traversal: Traversal::BreadthFirst,
+        };
+        iter.push_children(root);
+        iter.traversal = traversal;
+        iter
+    }
+
+    fn push_children(&mut self, group: &Rc<RefCell<V1Group>>) {
+        let children = group.borrow().child_groups();
+        match self.traversal {
+            Traversal::BreadthFirst => self.pending.extend(children),
+            Traversal::DepthFirst => {
+                for child in children.into_iter().rev() {
+                    self.pending.push_front(child);
+                }
+            }
+        }
+    }
+}
+
+impl Iterator for GroupIter {
+    type Item = Rc<RefCell<V1Group>>;
+
+    fn next(&mut self) -> Option<Rc<RefCell<V1Group>>> {
+        match self.pending.pop_front() {
+            Some(group) => {
+                self.push_children(&group);
+                Some(group)
+            }
+            None => None,
+        }
+    }
+}
+
+#[doc = "
+Iterator over the entries of a group tree in the order of their groups
+(see GroupIter). It yields strong references.
+"]
+pub struct EntryIter {
+    groups: GroupIter,
+    pending: VecDeque<Rc<RefCell<V1Entry>>>,
+}
+
+impl EntryIter {
+    /// Iterate over all entries in the subgroups of root
+    pub fn new(root: &Rc<RefCell<V1Group>>, traversal: Traversal) -> EntryIter {
+        EntryIter {
+            groups: GroupIter::new(root, traversal),
+            pending: VecDeque::new(),
+        }
+    }
+}
+
+impl Iterator for EntryIter {
+    type Item = Rc<RefCell<V1Entry>>;
+
+    fn next(&mut self) -> Option<Rc<RefCell<V1Entry>>> {
+        loop {
+            if let Some(entry) = self.pending.pop_front() {
+                return Some(entry);
+            }
+            match self.groups.next() {
+                Some(group) => {
+                    self.pending.extend(group.borrow().child_entries());
+                }
+                None => return None,
+            }
+        }
+    }
+}
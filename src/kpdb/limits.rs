@@ -0,0 +1,102 @@
+use kpdb::notes::SECRET_FENCE;
+use kpdb::v1entry::V1Entry;
+use kpdb::v1error::V1KpdbError;
+use kpdb::v1group::V1Group;
+
+/// Limits of the KeePass 1.x format
+pub mod v1 {
+    /// Every field is stored with its size as u32
+    pub const MAX_FIELD_SIZE: usize = 0xFFFF_FFFF;
+    /// Strings are stored with a terminating null byte
+    pub const MAX_STRING_LEN: usize = MAX_FIELD_SIZE - 1;
+    /// Not a limit of the format itself but longer titles are cut off
+    /// or rejected by other clients
+    pub const MAX_TITLE_LEN: usize = 1024;
+    /// Same as MAX_TITLE_LEN for usernames
+    pub const MAX_USERNAME_LEN: usize = 1024;
+}
+
+#[doc = "
+FieldLimits holds the maximal lengths in bytes of the entry and group
+fields. V1Kpdb checks them before saving, the setters of V1Entry and
+V1Group check them when a field is set. The title and username limits
+aren't limits of the format, so save only checks them as for_save
+tells. The defaults are the limits of the KeePass 1.x format, see the
+v1 module. Applications can lower them, raising them above the format
+limits makes the database unsaveable.
+"]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FieldLimits {
+    /// Title of groups and entries
+    pub title: usize,
+    pub url: usize,
+    pub username: usize,
+    pub password: usize,
+    /// Comment of entries and notes of groups
+    pub comment: usize,
+    pub binary_desc: usize,
+    pub binary: usize,
+}
+
+impl FieldLimits {
+    /// The limits of the KeePass 1.x format
+    pub fn v1() -> FieldLimits {
+        FieldLimits {
+            title: v1::MAX_TITLE_LEN,
+            url: v1::MAX_STRING_LEN,
+            username: v1::MAX_USERNAME_LEN,
+            password: v1::MAX_STRING_LEN,
+            comment: v1::MAX_STRING_LEN,
+            binary_desc: v1::MAX_STRING_LEN,
+            binary: v1::MAX_FIELD_SIZE,
+        }
+    }
+
+    /// The limits save enforces: title and username only need to fit
+    /// into the format, so databases with longer ones still save
+    pub fn for_save(&self) -> FieldLimits {
+        FieldLimits {
+            title: v1::MAX_STRING_LEN,
+            username: v1::MAX_STRING_LEN,
+            ..self.clone()
+        }
+    }
+
+    /// Check all fields of entry. Returns FieldLengthErr if one is too long
+    pub fn check_entry(&self, entry: &V1Entry) -> Result<(), V1KpdbError> {
+        // The lengths of the SecureStrings stay the same after delete
+        try!(check(entry.title.len(), self.title));
+        try!(check_option(entry.url.as_ref().map(|u| u.len()), self.url));
+        try!(check_option(entry.username.as_ref().map(|u| u.string.len()), self.username));
+        try!(check_option(entry.raw_password().map(|p| p.string.len()), self.password));
+        // The protected sections are saved as part of the comment
+        let notes_len = entry.protected_notes
+                             .iter()
+                             .fold(0, |n, s| n + s.string.len() + 2 * SECRET_FENCE.len() + 2);
+        try!(check(entry.comment.as_ref().map_or(0, |c| c.len()) + notes_len, self.comment));
+        try!(check_option(entry.binary_desc.as_ref().map(|d| d.len()), self.binary_desc));
+        check_option(entry.binary.as_ref().map(|b| b.len()), self.binary)
+    }
+
+    /// Check the title and the notes of group
+    pub fn check_group(&self, group: &V1Group) -> Result<(), V1KpdbError> {
+        try!(check(group.title.len(), self.title));
+        check_option(group.notes.as_ref().map(|n| n.len()), self.comment)
+    }
+}
+
+/// Check a single length against limit
+pub fn check(len: usize, limit: usize) -> Result<(), V1KpdbError> {
+    if len > limit {
+        Err(V1KpdbError::FieldLengthErr)
+    } else {
+        Ok(())
+    }
+}
+
+fn check_option(len: Option<usize>, limit: usize) -> Result<(), V1KpdbError> {
+    match len {
+        Some(len) => check(len, limit),
+        None => Ok(()),
+    }
+}
@@ -0,0 +1,232 @@
+use std::env;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::process;
+
+use chrono::{DateTime, Duration, Local};
+
+use kpdb::v1error::V1KpdbError;
+
+#[doc = "
+LockInfo describes who holds the lock of a database. It is stored in
+<database path>.lock as simple key=value lines:
+
+```text
+user=alice
+host=laptop
+pid=4242
+time=2015-08-01T10:00:00+02:00
+```
+"]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LockInfo {
+    /// Login name of the user holding the lock
+    pub user: String,
+    /// Hostname of the machine holding the lock
+    pub host: String,
+    /// Process id of the process holding the lock
+    pub pid: u32,
+    /// When the lock was taken
+    pub time: DateTime<Local>,
+}
+
+impl LockInfo {
+    /// Describe the current process
+    pub fn current() -> LockInfo {
+        LockInfo {
+            user: current_user(),
+            host: current_host(),
+            pid: process::id(),
+            time: Local::now(),
+        }
+    }
+
+    /// How long the lock has been held
+    pub fn age(&self) -> Duration {
+        Local::now() - self.time
+    }
+
+    /// Check if the lock belongs to the current process
+    pub fn is_own(&self) -> bool {
+        self.pid == process::id() && self.host == current_host()
+    }
+
+    /// Parse the content of a lock file. Unknown keys are ignored,
+    /// missing keys make the lock file invalid.
+    pub fn parse(content: &str) -> Result<LockInfo, V1KpdbError> {
+        let mut user = None;
+        let mut host = None;
+        let mut pid = None;
+        let mut time = None;
+        for line in content.lines() {
+            let mut parts = line.splitn(2, '=');
+            let key = parts.next().unwrap_or("").trim();
+            let value = match parts.next() {
+                Some(v) => v.trim(),
+                None => continue,
+            };
+            match key {
+                "user" => user = Some(value.to_string()),
+                "host" => host = Some(value.to_string()),
+                "pid" => pid = value.parse::<u32>().ok(),
+                "time" => {
+                    time = DateTime::parse_from_rfc3339(value)
+                               .ok()
+                               .map(|t| t.with_timezone(&Local))
+                }
+                _ => {}
+            }
+        }
+        match (user, host, pid, time) {
+            (Some(user), Some(host), Some(pid), Some(time)) => {
+                Ok(LockInfo {
+                    user: user,
+                    host: host,
+                    pid: pid,
+                    time: time,
+                })
+            }
+            _ => Err(V1KpdbError::LockErr),
+        }
+    }
+
+    /// Serialize into the lock file format
+    pub fn to_lock_string(&self) -> String {
+        format!("user={}\nhost={}\npid={}\ntime={}\n",
+                self.user,
+                self.host,
+                self.pid,
+                self.time.to_rfc3339())
+    }
+}
+
+/// Path of the lock file belonging to the database at path
+pub fn lock_path(path: &str) -> String {
+    format!("{}.lock", path)
+}
+
+/// Read the lock file of the database at path
+///
+/// Returns None if the database isn't locked and LockErr if a lock file
+/// exists but can't be parsed (e.g. an empty lock of another client).
+pub fn inspect(path: &str) -> Result<Option<LockInfo>, V1KpdbError> {
+    let mut file = match File::open(lock_path(path)) {
+        Ok(f) => f,
+        Err(_) => return Ok(None),
+    };
+    let mut content = String::new();
+    try!(file.read_to_string(&mut content).map_err(|_| V1KpdbError::ReadErr));
+    LockInfo::parse(&content).map(Some)
+}
+
+// Write info as lock file of the database at path
+pub fn write_lock(path: &str, info: &LockInfo) -> Result<(), V1KpdbError> {
+    let mut file = try!(File::create(lock_path(path)).map_err(|_| V1KpdbError::FileErr));
+    try!(file.write_all(info.to_lock_string().as_bytes()).map_err(|_| V1KpdbError::WriteErr));
+    Ok(())
+}
+
+#[doc = "
+FileLock holds the lock file of a database while it's open, so other
+processes using this crate or KeePassX see that it's in use. V1Kpdb
+takes one with acquire_file_lock, and save refuses to overwrite a
+database locked by someone else.
+
+The lock file is removed when the FileLock is dropped, unless another
+process took the lock over with force meanwhile.
+"]
+#[derive(Debug)]
+pub struct FileLock {
+    path: String,
+    info: LockInfo,
+}
+
+impl FileLock {
+    /// Take the lock of the database at path. FileLockedErr if there's
+    /// a lock file already, inspect tells who holds it.
+    pub fn acquire(path: &str) -> Result<FileLock, V1KpdbError> {
+        let info = LockInfo::current();
+        // create_new fails if the file exists, so two processes can't
+        // both take the lock
+        let mut file = match OpenOptions::new()
+                                 .write(true)
+                                 .create_new(true)
+                                 .open(lock_path(path)) {
+            Ok(file) => file,
+            Err(ref e) if e.kind() == io::ErrorKind::AlreadyExists => {
+                return Err(V1KpdbError::FileLockedErr)
+            }
+            Err(_) => return Err(V1KpdbError::FileErr),
+        };
+        if file.write_all(info.to_lock_string().as_bytes()).is_err() {
+            let _ = fs::remove_file(lock_path(path));
+            return Err(V1KpdbError::WriteErr);
+        }
+        Ok(FileLock {
+            path: path.to_string(),
+            info: info,
+        })
+    }
+
+    /// Take the lock of the database at path even if someone else holds
+    /// it, e.g. after the user confirmed that it's stale
+    pub fn force(path: &str) -> Result<FileLock, V1KpdbError> {
+        let info = LockInfo::current();
+        try!(write_lock(path, &info));
+        Ok(FileLock {
+            path: path.to_string(),
+            info: info,
+        })
+    }
+
+    /// Path of the locked database
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    pub fn info(&self) -> &LockInfo {
+        &self.info
+    }
+
+    /// Check that the lock file is still ours, i.e. no other process
+    /// took it over with force
+    pub fn is_held(&self) -> bool {
+        match inspect(&self.path) {
+            Ok(Some(ref info)) => is_same_lock(info, &self.info),
+            _ => false,
+        }
+    }
+}
+
+impl Drop for FileLock {
+    fn drop(&mut self) {
+        if self.is_held() {
+            let _ = fs::remove_file(lock_path(&self.path));
+        }
+    }
+}
+
+// The lock file keeps the time in seconds only
+fn is_same_lock(a: &LockInfo, b: &LockInfo) -> bool {
+    a.user == b.user && a.host == b.host && a.pid == b.pid &&
+    a.time.timestamp() == b.time.timestamp()
+}
+
+fn current_user() -> String {
+    env::var("USER")
+        .or(env::var("USERNAME"))
+        .unwrap_or("unknown".to_string())
+}
+
+fn current_host() -> String {
+    if let Ok(host) = env::var("HOSTNAME").or(env::var("COMPUTERNAME")) {
+        return host;
+    }
+    let mut host = String::new();
+    if let Ok(mut file) = File::open("/etc/hostname") {
+        if file.read_to_string(&mut host).is_ok() && !host.trim().is_empty() {
+            return host.trim().to_string();
+        }
+    }
+    "unknown".to_string()
+}
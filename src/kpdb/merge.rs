@@ -0,0 +1,132 @@
+use uuid::Uuid;
+
+use kpdb::v1entry::V1Entry;
+
+#[doc = "
+The outcome of a merge conflict, i.e. an entry which was changed in
+both databases.
+"]
+pub enum Resolution {
+    /// Keep the local entry, drop the remote changes
+    Local,
+    /// Replace the local entry with the remote one
+    Remote,
+    /// Keep the local entry and add the remote one as a new entry
+    Both,
+    /// Replace the local entry with a new one, e.g. a field-wise
+    /// combination of both. The UUID of the local entry is kept.
+    Custom(V1Entry),
+}
+
+#[doc = "
+Implement this to decide how merge handles entries which exist in both
+databases but differ. local and remote have the same UUID.
+"]
+pub trait ConflictResolver {
+    fn resolve(&mut self, local: &V1Entry, remote: &V1Entry) -> Resolution;
+}
+
+/// Keeps the entry with the newer modification date. On equal dates
+/// the local entry wins. This is what KeePass' synchronize does.
+pub struct NewestWins;
+
+impl ConflictResolver for NewestWins {
+    fn resolve(&mut self, local: &V1Entry, remote: &V1Entry) -> Resolution {
+        if remote.last_mod > local.last_mod {
+            Resolution::Remote
+        } else {
+            Resolution::Local
+        }
+    }
+}
+
+/// Never loses data: keeps the local entry and adds the remote one as
+/// a copy, so the user can clean up by hand
+pub struct DuplicateOnConflict;
+
+impl ConflictResolver for DuplicateOnConflict {
+    fn resolve(&mut self, _: &V1Entry, _: &V1Entry) -> Resolution {
+        Resolution::Both
+    }
+}
+
+impl<F> ConflictResolver for F
+    where F: FnMut(&V1Entry, &V1Entry) -> Resolution
+{
+    fn resolve(&mut self, local: &V1Entry, remote: &V1Entry) -> Resolution {
+        self(local, remote)
+    }
+}
+
+/// How a conflict was resolved, see Resolution
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Decision {
+    KeptLocal,
+    TookRemote,
+    KeptBoth,
+    Custom,
+}
+
+impl<'a> From<&'a Resolution> for Decision {
+    fn from(resolution: &'a Resolution) -> Decision {
+        match *resolution {
+            Resolution::Local => Decision::KeptLocal,
+            Resolution::Remote => Decision::TookRemote,
+            Resolution::Both => Decision::KeptBoth,
+            Resolution::Custom(_) => Decision::Custom,
+        }
+    }
+}
+
+/// A conflicting entry and what was done about it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MergeConflict {
+    /// UUID of the entry in both databases
+    pub uuid: Uuid,
+    /// Title of the local entry
+    pub title: String,
+    /// How the conflict was resolved
+    pub decision: Decision,
+}
+
+#[doc = "
+MergeReport lists what a merge did to the local database, entry by
+entry, so that applications can show the user what happened.
+
+Note that KeePass 1.x databases don't record deleted entries. Hence an
+entry deleted in one copy reappears after merging with a copy which
+still has it; there are no deletions to report.
+"]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MergeReport {
+    /// Ids of the groups which were added from the remote database
+    pub added_groups: Vec<u32>,
+    /// Entries which only existed in the remote database
+    pub added: Vec<Uuid>,
+    /// Entries which were replaced by a newer remote version without
+    /// local changes
+    pub updated: Vec<Uuid>,
+    /// Entries which were identical or older in the remote database
+    pub skipped: Vec<Uuid>,
+    /// Entries which were changed in both databases
+    pub conflicts: Vec<MergeConflict>,
+}
+
+impl MergeReport {
+    /// Create an empty report
+    pub fn new() -> MergeReport {
+        MergeReport {
+            added_groups: vec![],
+            added: vec![],
+            updated: vec![],
+            skipped: vec![],
+            conflicts: vec![],
+        }
+    }
+
+    /// True if the merge didn't change the local database
+    pub fn is_unchanged(&self) -> bool {
+        self.added_groups.is_empty() && self.added.is_empty() && self.updated.is_empty() &&
+        self.conflicts.iter().all(|c| c.decision == Decision::KeptLocal)
+    }
+}
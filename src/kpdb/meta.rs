@@ -0,0 +1,347 @@
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::rc::Rc;
+use std::str;
+
+use uuid::Uuid;
+
+use kpdb::common::{slice_to_u32, u32_to_vec_u8};
+use kpdb::v1entry::V1Entry;
+use kpdb::v1error::V1KpdbError;
+use kpdb::v1group::V1Group;
+use super::super::sec_str::{SecureBytes, SecureString};
+
+// Fields which mark an entry as meta entry. KeePass 1.x stores
+// additional data of its own and of other clients this way.
+pub const META_TITLE: &'static str = "Meta-Info";
+pub const META_USERNAME: &'static str = "SYSTEM";
+pub const META_URL: &'static str = "$";
+pub const META_BINARY_DESC: &'static str = "bin-stream";
+
+/// Name of the meta stream which holds group notes and custom data
+pub const GROUP_META_STREAM: &'static str = "RKP_GROUP_META";
+
+// Meta streams of KeePassX and KeePass which MetaInfo understands
+pub const CUSTOM_ICONS_STREAM: &'static str = "KPX_CUSTOM_ICONS_4";
+pub const GROUP_TREE_STATE_STREAM: &'static str = "KPX_GROUP_TREE_STATE";
+pub const DEFAULT_USERNAME_STREAM: &'static str = "Default User Name";
+
+const PNG_SIGNATURE: [u8; 8] = [0x89, 0x50, 0x4e, 0x47, 0x0d, 0x0a, 0x1a, 0x0a];
+
+#[doc = "
+MetaInfo holds the UI state KeePassX and KeePass keep in meta entries.
+V1Kpdb reads it on load and writes it back on save, so other clients
+find their data again. Meta streams which MetaInfo doesn't understand
+stay in V1Kpdb::meta_entries as they are.
+"]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MetaInfo {
+    /// PNG images of the custom icons (KPX_CUSTOM_ICONS_4)
+    pub custom_icons: Vec<Vec<u8>>,
+    /// Index into custom_icons by entry UUID
+    pub entry_icons: BTreeMap<Uuid, u32>,
+    /// Index into custom_icons by group id
+    pub group_icons: BTreeMap<u32, u32>,
+    /// Whether a group is expanded in the tree view, by group id
+    /// (KPX_GROUP_TREE_STATE)
+    pub expanded_groups: BTreeMap<u32, bool>,
+    /// Username for new entries (Default User Name of KeePass)
+    pub default_username: Option<String>,
+}
+
+impl MetaInfo {
+    pub fn new() -> MetaInfo {
+        MetaInfo {
+            custom_icons: vec![],
+            entry_icons: BTreeMap::new(),
+            group_icons: BTreeMap::new(),
+            expanded_groups: BTreeMap::new(),
+            default_username: None,
+        }
+    }
+
+    /// Add a custom icon and return its index. An icon which is there
+    /// already is reused. IconErr if png isn't a PNG image.
+    pub fn add_icon(&mut self, png: Vec<u8>) -> Result<u32, V1KpdbError> {
+        if !png.starts_with(&PNG_SIGNATURE) {
+            return Err(V1KpdbError::IconErr);
+        }
+        if let Some(index) = self.custom_icons.iter().position(|i| *i == png) {
+            return Ok(index as u32);
+        }
+        self.custom_icons.push(png);
+        Ok((self.custom_icons.len() - 1) as u32)
+    }
+
+    /// Remove the custom icon at index. Groups and entries which used it
+    /// lose their custom icon, the indices of the following icons shift
+    /// down by one.
+    pub fn remove_icon(&mut self, index: u32) -> Result<Vec<u8>, V1KpdbError> {
+        if index as usize >= self.custom_icons.len() {
+            return Err(V1KpdbError::IndexErr);
+        }
+        let icon = self.custom_icons.remove(index as usize);
+        self.entry_icons = shift_icons(&self.entry_icons, index);
+        self.group_icons = shift_icons(&self.group_icons, index);
+        Ok(icon)
+    }
+
+    /// True if name is one of the streams MetaInfo reads and writes
+    pub fn is_known_stream(name: &str) -> bool {
+        name == CUSTOM_ICONS_STREAM || name == GROUP_TREE_STATE_STREAM ||
+        name == DEFAULT_USERNAME_STREAM
+    }
+
+    /// Read the meta stream name. Unknown streams are ignored.
+    pub fn decode_stream(&mut self, name: &str, data: &[u8]) -> Result<(), V1KpdbError> {
+        match name {
+            CUSTOM_ICONS_STREAM => self.decode_custom_icons(data),
+            GROUP_TREE_STATE_STREAM => self.decode_group_tree_state(data),
+            DEFAULT_USERNAME_STREAM => {
+                // Saved with a terminating null byte
+                let end = data.iter().position(|b| *b == 0).unwrap_or(data.len());
+                let name = try!(str::from_utf8(&data[..end]).map_err(|_| V1KpdbError::MetaErr));
+                self.default_username = Some(name.to_string());
+                Ok(())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// The meta streams to save. Streams without data are left out.
+    pub fn encode(&self) -> Vec<(&'static str, Vec<u8>)> {
+        let mut streams = vec![];
+        if !self.custom_icons.is_empty() {
+            streams.push((CUSTOM_ICONS_STREAM, self.encode_custom_icons()));
+        }
+        if !self.expanded_groups.is_empty() {
+            let mut data = u32_to_vec_u8(self.expanded_groups.len() as u32);
+            for (id, expanded) in &self.expanded_groups {
+                data.append(&mut u32_to_vec_u8(*id));
+                data.push(*expanded as u8);
+            }
+            streams.push((GROUP_TREE_STATE_STREAM, data));
+        }
+        if let Some(ref username) = self.default_username {
+            let mut data = username.clone().into_bytes();
+            data.push(0);
+            streams.push((DEFAULT_USERNAME_STREAM, data));
+        }
+        streams
+    }
+
+    // The stream is:
+    // number of icons, entries and groups (u32 each)
+    // per icon: size (u32), PNG data
+    // per entry: UUID (16 bytes), icon index (u32)
+    // per group: group id (u32), icon index (u32)
+    fn decode_custom_icons(&mut self, data: &[u8]) -> Result<(), V1KpdbError> {
+        let mut reader = StreamReader::new(data);
+        let num_icons = try!(reader.read_u32());
+        let num_entries = try!(reader.read_u32());
+        let num_groups = try!(reader.read_u32());
+        let mut icons = vec![];
+        for _ in 0..num_icons {
+            let size = try!(reader.read_u32()) as usize;
+            icons.push(try!(reader.read(size)).to_vec());
+        }
+        let mut entry_icons = BTreeMap::new();
+        for _ in 0..num_entries {
+            let uuid = try!(Uuid::from_bytes(try!(reader.read(16))).ok_or(V1KpdbError::MetaErr));
+            let icon = try!(reader.read_u32());
+            if icon < num_icons {
+                entry_icons.insert(uuid, icon);
+            }
+        }
+        let mut group_icons = BTreeMap::new();
+        for _ in 0..num_groups {
+            let id = try!(reader.read_u32());
+            let icon = try!(reader.read_u32());
+            if icon < num_icons {
+                group_icons.insert(id, icon);
+            }
+        }
+        self.custom_icons = icons;
+        self.entry_icons = entry_icons;
+        self.group_icons = group_icons;
+        Ok(())
+    }
+
+    fn encode_custom_icons(&self) -> Vec<u8> {
+        let mut data = u32_to_vec_u8(self.custom_icons.len() as u32);
+        data.append(&mut u32_to_vec_u8(self.entry_icons.len() as u32));
+        data.append(&mut u32_to_vec_u8(self.group_icons.len() as u32));
+        for icon in &self.custom_icons {
+            data.append(&mut u32_to_vec_u8(icon.len() as u32));
+            data.extend_from_slice(icon);
+        }
+        for (uuid, icon) in &self.entry_icons {
+            data.extend_from_slice(uuid.as_bytes());
+            data.append(&mut u32_to_vec_u8(*icon));
+        }
+        for (id, icon) in &self.group_icons {
+            data.append(&mut u32_to_vec_u8(*id));
+            data.append(&mut u32_to_vec_u8(*icon));
+        }
+        data
+    }
+
+    // The stream is the number of groups (u32) followed by group id
+    // (u32) and expanded flag (u8) per group
+    fn decode_group_tree_state(&mut self, data: &[u8]) -> Result<(), V1KpdbError> {
+        let mut reader = StreamReader::new(data);
+        let num_groups = try!(reader.read_u32());
+        let mut expanded_groups = BTreeMap::new();
+        for _ in 0..num_groups {
+            let id = try!(reader.read_u32());
+            let expanded = try!(reader.read(1))[0] != 0;
+            expanded_groups.insert(id, expanded);
+        }
+        self.expanded_groups = expanded_groups;
+        Ok(())
+    }
+}
+
+// Icon indices of map after the icon at removed was deleted
+fn shift_icons<K: Ord + Copy>(map: &BTreeMap<K, u32>, removed: u32) -> BTreeMap<K, u32> {
+    let mut shifted = BTreeMap::new();
+    for (key, icon) in map {
+        if *icon > removed {
+            shifted.insert(*key, *icon - 1);
+        } else if *icon < removed {
+            shifted.insert(*key, *icon);
+        }
+    }
+    shifted
+}
+
+// Bounds checked reading of binary meta streams
+struct StreamReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> StreamReader<'a> {
+    fn new(data: &'a [u8]) -> StreamReader<'a> {
+        StreamReader {
+            data: data,
+            pos: 0,
+        }
+    }
+
+    fn read(&mut self, len: usize) -> Result<&'a [u8], V1KpdbError> {
+        if len > self.data.len() - self.pos {
+            return Err(V1KpdbError::MetaErr);
+        }
+        let slice = &self.data[self.pos..self.pos + len];
+        self.pos += len;
+        Ok(slice)
+    }
+
+    fn read_u32(&mut self) -> Result<u32, V1KpdbError> {
+        let bytes = try!(self.read(4));
+        slice_to_u32(bytes).map_err(|_| V1KpdbError::MetaErr)
+    }
+}
+
+/// Check whether entry is a meta entry rather than real credentials.
+/// The name of the meta stream is in comment, the data in binary.
+pub fn is_meta_entry(entry: &mut V1Entry) -> bool {
+    if entry.title != META_TITLE || entry.url.as_ref().map(|u| &u[..]) != Some(META_URL) ||
+       entry.binary_desc.as_ref().map(|d| &d[..]) != Some(META_BINARY_DESC) ||
+       entry.comment.is_none() || entry.binary.as_ref().map_or(true, |b| b.len() == 0) {
+        return false;
+    }
+    match entry.username {
+        Some(ref mut username) => {
+            username.unlock();
+            let is_system = username.string == META_USERNAME;
+            username.delete();
+            is_system
+        }
+        None => false,
+    }
+}
+
+/// Create a meta entry holding data as meta stream name in the group
+/// group_id
+pub fn new_meta_entry(name: &str, data: Vec<u8>, group_id: u32) -> V1Entry {
+    let mut entry = V1Entry::new();
+    entry.title = META_TITLE.to_string();
+    entry.username = Some(SecureString::new(META_USERNAME.to_string()));
+    entry.url = Some(META_URL.to_string());
+    entry.comment = Some(name.to_string());
+    entry.binary_desc = Some(META_BINARY_DESC.to_string());
+    entry.binary = Some(SecureBytes::new(data));
+    entry.group_id = group_id;
+    entry
+}
+
+// The stream is UTF-8 text with one line per item:
+// N<TAB>group id<TAB>notes
+// D<TAB>group id<TAB>key<TAB>value
+// Backslash, tab and newline are escaped in notes, keys and values.
+pub fn encode_group_meta(groups: &[Rc<RefCell<V1Group>>]) -> Vec<u8> {
+    let mut out = String::new();
+    for group in groups {
+        let group = group.borrow();
+        if let Some(ref notes) = group.notes {
+            out.push_str(&format!("N\t{}\t{}\n", group.id, escape(notes)));
+        }
+        for (key, value) in &group.custom_data {
+            out.push_str(&format!("D\t{}\t{}\t{}\n", group.id, escape(key), escape(value)));
+        }
+    }
+    out.into_bytes()
+}
+
+/// Read group notes and custom data from data into groups. Items of
+/// groups which don't exist anymore are dropped.
+pub fn decode_group_meta(data: &[u8],
+                         groups: &[Rc<RefCell<V1Group>>])
+                         -> Result<(), V1KpdbError> {
+    let text = try!(str::from_utf8(data).map_err(|_| V1KpdbError::MetaErr));
+    for line in text.lines() {
+        let fields: Vec<&str> = line.split('\t').collect();
+        let id = match fields.get(1).map(|id| id.parse::<u32>()) {
+            Some(Ok(id)) => id,
+            _ => return Err(V1KpdbError::MetaErr),
+        };
+        let group = match groups.iter().find(|g| g.borrow().id == id) {
+            Some(g) => g,
+            None => continue,
+        };
+        match (fields[0], fields.len()) {
+            ("N", 3) => group.borrow_mut().notes = Some(try!(unescape(fields[2]))),
+            ("D", 4) => {
+                let key = try!(unescape(fields[2]));
+                let value = try!(unescape(fields[3]));
+                group.borrow_mut().custom_data.insert(key, value);
+            }
+            _ => return Err(V1KpdbError::MetaErr),
+        }
+    }
+    Ok(())
+}
+
+fn escape(s: &str) -> String {
+    s.replace("\\", "\\\\").replace("\t", "\\t").replace("\n", "\\n")
+}
+
+fn unescape(s: &str) -> Result<String, V1KpdbError> {
+    let mut out = String::new();
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('\\') => out.push('\\'),
+            Some('t') => out.push('\t'),
+            Some('n') => out.push('\n'),
+            _ => return Err(V1KpdbError::MetaErr),
+        }
+    }
+    Ok(out)
+}
@@ -0,0 +1,122 @@
+//! Read-only memory mapping of database files
+//!
+//! V1Kpdb::load decrypts a mapped file straight from the page cache, so
+//! the ciphertext isn't copied into the heap first. On Linux the mapping
+//! is excluded from core dumps and isn't inherited by child processes.
+//! It's only used if V1Kpdb::use_mmap is set, as a file changed in place
+//! meanwhile can make the load fail or crash the process, see
+//! MappedFile.
+
+use std::fs::File;
+use std::io;
+use std::ops::Deref;
+use std::slice;
+
+#[doc = "
+MappedFile is a file mapped read-only into memory.
+
+The mapping is private, which only means that it's never written back.
+It still shows the current content of the file: if another process
+writes to the file in place, the change can show up at any time, even
+halfway through a read. If the file is truncated, reading beyond its
+new end raises SIGBUS, which kills the process. Replacing the file by a
+rename, as save does, is safe, the mapping keeps the old file.
+"]
+pub struct MappedFile {
+    ptr: *const u8,
+    len: usize,
+}
+
+impl MappedFile {
+    /// Map the file at path. Fails if it can't be opened, is empty or
+    /// the platform doesn't support mapping, load falls back to reading
+    /// the file then.
+    pub fn open(path: &str) -> io::Result<MappedFile> {
+        let file = try!(File::open(path));
+        let len = try!(file.metadata()).len() as usize;
+        if len == 0 {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "empty file"));
+        }
+        // The mapping stays valid after the file is closed
+        let ptr = try!(sys::map(&file, len));
+        Ok(MappedFile {
+            ptr: ptr,
+            len: len,
+        })
+    }
+}
+
+impl Deref for MappedFile {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        unsafe { slice::from_raw_parts(self.ptr, self.len) }
+    }
+}
+
+impl Drop for MappedFile {
+    fn drop(&mut self) {
+        unsafe {
+            sys::unmap(self.ptr, self.len);
+        }
+    }
+}
+
+#[cfg(unix)]
+mod sys {
+    use std::fs::File;
+    use std::io;
+    use std::os::unix::io::AsRawFd;
+    use std::ptr;
+
+    use libc::{self, c_int, c_void, size_t};
+
+    // Not in this version of libc
+    #[cfg(target_os = "linux")]
+    const MADV_DONTDUMP: c_int = 16;
+
+    pub fn map(file: &File, len: usize) -> io::Result<*const u8> {
+        let ptr = unsafe {
+            libc::mmap(ptr::null_mut(),
+                       len as size_t,
+                       libc::PROT_READ,
+                       libc::MAP_PRIVATE,
+                       file.as_raw_fd(),
+                       0)
+        };
+        if ptr == libc::MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+        advise(ptr, len);
+        Ok(ptr as *const u8)
+    }
+
+    pub unsafe fn unmap(ptr: *const u8, len: usize) {
+        libc::munmap(ptr as *mut c_void, len as size_t);
+    }
+
+    // Only hygiene, errors are ignored, e.g. of kernels older than 3.4
+    #[cfg(target_os = "linux")]
+    fn advise(ptr: *mut c_void, len: usize) {
+        use libc::funcs::bsd44::madvise;
+        unsafe {
+            madvise(ptr, len as size_t, MADV_DONTDUMP);
+            madvise(ptr, len as size_t, libc::MADV_DONTFORK);
+        }
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    fn advise(_: *mut c_void, _: usize) {}
+}
+
+#[cfg(not(unix))]
+mod sys {
+    use std::fs::File;
+    use std::io;
+
+    pub fn map(_: &File, _: usize) -> io::Result<*const u8> {
+        Err(io::Error::new(io::ErrorKind::Other, "mapping not supported"))
+    }
+
+    pub unsafe fn unmap(_: *const u8, _: usize) {}
+}
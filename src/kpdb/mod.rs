@@ -3,14 +3,91 @@ pub mod v1error;
 pub mod v1group;
 pub mod v1entry;
 pub mod v1header;
+pub mod v1parser;
+pub mod advisor;
+pub mod agent;
+#[cfg(feature = "tokio")]
+pub mod async_io;
+pub mod audit;
+pub mod autolock;
+pub mod autotype;
+pub mod backup;
+pub mod breach;
+pub mod builder;
+pub mod conformance;
+pub mod credentials;
+pub mod custom_fields;
+pub mod search;
+pub mod secret_access;
+pub mod shared;
+pub mod stream;
+pub mod diff;
+#[cfg(feature = "serde")]
+pub mod dump;
+pub mod domains;
+pub mod duplicates;
+pub mod error;
+pub mod export;
+#[cfg(any(test, feature = "fixtures"))]
+pub mod fixtures;
+pub mod generator;
+pub mod handle;
+pub mod import;
+pub mod iter;
+pub mod limits;
+pub mod lockfile;
+pub mod merge;
+pub mod meta;
+pub mod mmap;
+pub mod notes;
+pub mod order;
+pub mod otp;
+pub mod passkey;
+pub mod password_history;
+pub mod path;
+pub mod placeholders;
+pub mod patch;
+pub mod policy;
+pub mod recovery;
+pub mod recovery_codes;
+pub mod reveal;
+pub mod stats;
+pub mod time;
+pub mod timeline;
+pub mod twofish;
+pub mod crypter;
+pub mod fido2;
+#[cfg(unix)]
+pub mod fdkey;
+pub mod undo;
+pub mod usage;
+#[cfg(any(test, feature = "testvectors"))]
+pub mod testvectors;
 
 mod common;
-mod crypter;
 mod parser;
+mod xml;
+
+pub use self::diff::diff;
 
 #[cfg(test)]
 mod tests_v1kpdb;
+#[cfg(test)]
+mod tests_v1entry;
+#[cfg(test)]
+mod tests_import;
+#[cfg(test)]
+mod tests_lockfile;
+#[cfg(test)]
+mod tests_fido2;
+#[cfg(test)]
+mod tests_credentials;
+#[cfg(test)]
+mod tests_breach;
+#[cfg(test)]
+mod tests_generator;
 mod tests_parser;
+#[cfg(test)]
 mod tests_crypter;
 
 use std::rc::Weak;
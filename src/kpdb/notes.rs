@@ -0,0 +1,179 @@
+use mem_protect;
+use sec_str::SecureString;
+
+/// Line which opens and closes a protected section of the notes
+pub const SECRET_FENCE: &'static str = "::secret::";
+
+// Notes are Markdown with protected sections like
+//
+//   Server setup
+//   ::secret::
+//   root password: ...
+//   ::secret::
+//
+// In memory the comment of an entry keeps an empty section as
+// placeholder, the content is held in V1Entry::protected_notes. On save
+// the sections are put back in the order of the placeholders.
+
+// Byte ranges of the lines of text without their line break
+fn line_ranges(text: &str) -> Vec<(usize, usize)> {
+    let mut ranges = vec![];
+    let mut start = 0;
+    for (i, b) in text.bytes().enumerate() {
+        if b == b'\n' {
+            ranges.push((start, i));
+            start = i + 1;
+        }
+    }
+    ranges.push((start, text.len()));
+    ranges
+}
+
+fn is_fence(line: &str) -> bool {
+    line.trim_right_matches('\r') == SECRET_FENCE
+}
+
+/// Move the protected sections of notes into SecureStrings. Returns the
+/// notes with an empty section in place of each protected one and the
+/// sections in order. A section which isn't closed lasts until the end.
+/// notes is zeroed out.
+pub fn split_secrets(notes: String) -> (String, Vec<SecureString>) {
+    let mut secrets = vec![];
+    let text = {
+        let lines = line_ranges(&notes);
+        let mut pieces: Vec<&str> = vec![];
+        // Index of the first line inside the open section
+        let mut open: Option<usize> = None;
+        for (n, &(start, end)) in lines.iter().enumerate() {
+            let line = &notes[start..end];
+            match open {
+                None if is_fence(line) => open = Some(n + 1),
+                None => pieces.push(line),
+                Some(first) if is_fence(line) => {
+                    secrets.push(section(&notes, &lines[first..n]));
+                    pieces.push(SECRET_FENCE);
+                    pieces.push(SECRET_FENCE);
+                    open = None;
+                }
+                Some(_) => {}
+            }
+        }
+        if let Some(first) = open {
+            secrets.push(section(&notes, &lines[first..]));
+            pieces.push(SECRET_FENCE);
+            pieces.push(SECRET_FENCE);
+        }
+        pieces.join("\n")
+    };
+    unsafe {
+        mem_protect::zero(&notes);
+    }
+    (text, secrets)
+}
+
+/// Like split_secrets for notes as they were saved. Notes which
+/// join_secrets wouldn't write back the same, because a section isn't
+/// closed or a fence line ends with "\r", are returned as they are
+/// without sections, so other clients' notes keep their text.
+pub fn split_saved_secrets(notes: String) -> (String, Vec<SecureString>) {
+    let mut fences = 0;
+    for line in notes.split('\n').filter(|l| is_fence(l)) {
+        if line != SECRET_FENCE {
+            return (notes, vec![]);
+        }
+        fences += 1;
+    }
+    if fences % 2 != 0 {
+        return (notes, vec![]);
+    }
+    split_secrets(notes)
+}
+
+fn section(notes: &str, lines: &[(usize, usize)]) -> SecureString {
+    match (lines.first(), lines.last()) {
+        (Some(first), Some(last)) => SecureString::new(notes[first.0..last.1].to_string()),
+        _ => SecureString::new(String::new()),
+    }
+}
+
+/// Put the protected sections back into text, the reverse of
+/// split_secrets. Each empty section gets the next one of secrets,
+/// sections which are left over are appended. The result holds the
+/// secrets in plain text.
+pub fn join_secrets(text: &str, secrets: &mut [SecureString]) -> String {
+    // Allocated once, so no partial copies of the secrets are left behind
+    let capacity = secrets.iter()
+                          .fold(text.len(), |n, s| n + s.string.len() + 2 * SECRET_FENCE.len() + 4);
+    let mut notes = String::with_capacity(capacity);
+    let lines = line_ranges(text);
+    let mut secrets = secrets.iter_mut();
+    let mut n = 0;
+    while n < lines.len() {
+        if n > 0 {
+            notes.push('\n');
+        }
+        let line = &text[lines[n].0..lines[n].1];
+        let placeholder = is_fence(line) && n + 1 < lines.len() &&
+                          is_fence(&text[lines[n + 1].0..lines[n + 1].1]);
+        let secret = if placeholder {
+            secrets.next()
+        } else {
+            None
+        };
+        match secret {
+            Some(secret) => {
+                push_section(&mut notes, secret);
+                n += 2;
+            }
+            None => {
+                notes.push_str(line);
+                n += 1;
+            }
+        }
+    }
+    for secret in secrets {
+        if !notes.is_empty() {
+            notes.push('\n');
+        }
+        push_section(&mut notes, secret);
+    }
+    notes
+}
+
+/// Remove the empty section at index from text, counting from 0, e.g.
+/// because the protected section it stands for was dropped. text is
+/// returned as it is if it has less sections.
+pub fn remove_placeholder(text: &str, index: usize) -> String {
+    let lines = line_ranges(text);
+    let mut pieces: Vec<&str> = vec![];
+    let mut found = 0;
+    let mut n = 0;
+    while n < lines.len() {
+        let line = &text[lines[n].0..lines[n].1];
+        let placeholder = is_fence(line) && n + 1 < lines.len() &&
+                          is_fence(&text[lines[n + 1].0..lines[n + 1].1]);
+        if placeholder {
+            if found != index {
+                pieces.push(line);
+                pieces.push(&text[lines[n + 1].0..lines[n + 1].1]);
+            }
+            found += 1;
+            n += 2;
+        } else {
+            pieces.push(line);
+            n += 1;
+        }
+    }
+    pieces.join("\n")
+}
+
+fn push_section(notes: &mut String, secret: &mut SecureString) {
+    notes.push_str(SECRET_FENCE);
+    notes.push('\n');
+    let content = secret.unlocked();
+    if !content.is_empty() {
+        notes.push_str(&content);
+        notes.push('\n');
+    }
+    notes.push_str(SECRET_FENCE);
+}
@@ -0,0 +1,84 @@
+//! Sorting of groups and entries, see V1Kpdb::sort_groups and
+//! V1Kpdb::sort_entries
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use chrono::{DateTime, Local};
+
+use kpdb::v1entry::V1Entry;
+use kpdb::v1group::V1Group;
+use mem_protect;
+
+/// What groups and entries are sorted by
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SortKey {
+    /// The title, ignoring case
+    Title,
+    /// The username, ignoring case, entries without one first. Groups
+    /// have no username, they're sorted by title instead
+    Username,
+    /// The time of the last modification, the oldest first
+    LastMod,
+}
+
+// Only one kind is compared for a key
+#[derive(PartialEq, Eq, PartialOrd, Ord)]
+enum SortValue {
+    Text(String),
+    Time(DateTime<Local>),
+}
+
+/// The indices of entries in the order of key. Entries with the same
+/// value keep their order.
+pub fn entry_order(entries: &[Rc<RefCell<V1Entry>>], key: SortKey) -> Vec<usize> {
+    let values = entries.iter()
+                        .map(|entry| {
+                            let mut entry = entry.borrow_mut();
+                            match key {
+                                SortKey::Title => SortValue::Text(entry.title.to_lowercase()),
+                                SortKey::Username => {
+                                    let username = entry.username()
+                                                        .map(|u| u.to_lowercase())
+                                                        .unwrap_or(String::new());
+                                    SortValue::Text(username)
+                                }
+                                SortKey::LastMod => SortValue::Time(entry.last_mod),
+                            }
+                        })
+                        .collect::<Vec<_>>();
+    let order = sorted_indices(&values);
+    // The usernames are copies
+    for value in values.iter() {
+        if let SortValue::Text(ref text) = *value {
+            unsafe {
+                mem_protect::zero(text);
+            }
+        }
+    }
+    order
+}
+
+/// The indices of groups in the order of key. Groups with the same value
+/// keep their order.
+pub fn group_order(groups: &[Rc<RefCell<V1Group>>], key: SortKey) -> Vec<usize> {
+    let values = groups.iter()
+                       .map(|group| {
+                           let group = group.borrow();
+                           match key {
+                               SortKey::Title | SortKey::Username => {
+                                   SortValue::Text(group.title.to_lowercase())
+                               }
+                               SortKey::LastMod => SortValue::Time(group.last_mod),
+                           }
+                       })
+                       .collect::<Vec<_>>();
+    sorted_indices(&values)
+}
+
+fn sorted_indices(values: &[SortValue]) -> Vec<usize> {
+    let mut indices = (0..values.len()).collect::<Vec<_>>();
+    // sort_by is stable
+    indices.sort_by(|a, b| values[*a].cmp(&values[*b]));
+    indices
+}
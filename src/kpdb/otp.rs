@@ -0,0 +1,330 @@
+use std::str;
+
+use chrono::{DateTime, Local};
+use openssl::crypto::hash::Type;
+use openssl::crypto::hmac::hmac;
+
+use kpdb::v1error::V1KpdbError;
+use mem_protect;
+use sec_str::SecureString;
+
+/// Scheme of the Key Uri Format used by authenticator apps
+pub const OTPAUTH_SCHEME: &'static str = "otpauth://";
+
+// KeeTrayTOTP keeps a bare base32 secret and its settings as
+// "TOTP Seed: <secret>" and "TOTP Settings: <period>;<digits>"
+const TOTP_SEED_PREFIX: &'static str = "TOTP Seed:";
+const TOTP_SETTINGS_PREFIX: &'static str = "TOTP Settings:";
+
+const DEFAULT_DIGITS: u32 = 6;
+const DEFAULT_PERIOD: u64 = 30;
+
+/// Counter based (RFC 4226) or time based (RFC 6238) one-time passwords
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OtpKind {
+    Hotp,
+    Totp,
+}
+
+/// The hash function of the HMAC
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OtpAlgorithm {
+    Sha1,
+    Sha256,
+    Sha512,
+}
+
+impl OtpAlgorithm {
+    fn hash_type(&self) -> Type {
+        match *self {
+            OtpAlgorithm::Sha1 => Type::SHA1,
+            OtpAlgorithm::Sha256 => Type::SHA256,
+            OtpAlgorithm::Sha512 => Type::SHA512,
+        }
+    }
+}
+
+#[doc = "
+Otp generates one-time passwords as authenticator apps do. It's read
+from an otpauth:// URI or a bare base32 secret, see V1Entry::otp. The
+secret is held in a SecureString and only decoded while a code is
+generated. Codes are returned as SecureStrings as well.
+"]
+pub struct Otp {
+    pub kind: OtpKind,
+    pub algorithm: OtpAlgorithm,
+    /// Number of digits of a code, 6 to 8
+    pub digits: u32,
+    /// Seconds a TOTP code is valid
+    pub period: u64,
+    /// Counter of the next HOTP code. Otp doesn't advance it, store the
+    /// new value in the entry after a code was used.
+    pub counter: u64,
+    /// The label of the URI without the issuer, e.g. the account name
+    pub label: String,
+    pub issuer: Option<String>,
+    // The secret in base32, upper case without padding
+    secret: SecureString,
+}
+
+impl Otp {
+    /// Parse an otpauth://TYPE/LABEL?PARAMETERS URI. The parameters
+    /// secret and for HOTP counter are required, algorithm, digits and
+    /// period default to SHA1, 6 and 30.
+    pub fn from_uri(uri: &str) -> Result<Otp, V1KpdbError> {
+        let uri = uri.trim();
+        if !uri.to_lowercase().starts_with(OTPAUTH_SCHEME) {
+            return Err(V1KpdbError::OtpErr);
+        }
+        let rest = &uri[OTPAUTH_SCHEME.len()..];
+        let (path, query) = match rest.find('?') {
+            Some(i) => (&rest[..i], &rest[i + 1..]),
+            None => return Err(V1KpdbError::OtpErr),
+        };
+        let (kind, label) = match path.find('/') {
+            Some(i) => (path[..i].to_lowercase(), try!(percent_decode(&path[i + 1..]))),
+            None => return Err(V1KpdbError::OtpErr),
+        };
+        let kind = match &kind[..] {
+            "hotp" => OtpKind::Hotp,
+            "totp" => OtpKind::Totp,
+            _ => return Err(V1KpdbError::OtpErr),
+        };
+        // The label is "Issuer:account" or just the account
+        let (mut issuer, label) = match label.find(':') {
+            Some(i) => (Some(label[..i].trim().to_string()), label[i + 1..].trim().to_string()),
+            None => (None, label),
+        };
+
+        let mut otp = Otp::new(kind, label);
+        let mut secret = None;
+        let mut counter = false;
+        for param in query.split('&').filter(|p| !p.is_empty()) {
+            let mut parts = param.splitn(2, '=');
+            let (key, value) = match (parts.next(), parts.next()) {
+                (Some(key), Some(value)) => (key, value),
+                _ => return Err(V1KpdbError::OtpErr),
+            };
+            match key {
+                "secret" => secret = Some(try!(normalize_secret(value))),
+                "issuer" => issuer = Some(try!(percent_decode(value))),
+                "algorithm" => {
+                    otp.algorithm = match &value.to_uppercase()[..] {
+                        "SHA1" => OtpAlgorithm::Sha1,
+                        "SHA256" => OtpAlgorithm::Sha256,
+                        "SHA512" => OtpAlgorithm::Sha512,
+                        _ => return Err(V1KpdbError::OtpErr),
+                    }
+                }
+                "digits" => otp.digits = try!(parse_digits(value)),
+                "period" => otp.period = try!(parse_period(value)),
+                "counter" => {
+                    otp.counter = try!(value.parse().map_err(|_| V1KpdbError::OtpErr));
+                    counter = true;
+                }
+                _ => {}
+            }
+        }
+        if kind == OtpKind::Hotp && !counter {
+            return Err(V1KpdbError::OtpErr);
+        }
+        otp.secret = try!(secret.ok_or(V1KpdbError::OtpErr));
+        otp.issuer = issuer.and_then(|i| if i.is_empty() { None } else { Some(i) });
+        Ok(otp)
+    }
+
+    /// A TOTP with default settings for a bare base32 secret, which may
+    /// contain spaces and padding
+    pub fn from_secret(secret: &str) -> Result<Otp, V1KpdbError> {
+        let mut otp = Otp::new(OtpKind::Totp, String::new());
+        otp.secret = try!(normalize_secret(secret));
+        Ok(otp)
+    }
+
+    /// Find the first secret in text: a line which is an otpauth:// URI
+    /// or a "TOTP Seed:" line of KeeTrayTOTP, with its "TOTP Settings:"
+    /// line if there is one. Ok(None) if there's no secret.
+    pub fn find(text: &str) -> Result<Option<Otp>, V1KpdbError> {
+        let mut seed = None;
+        let mut settings = None;
+        for line in text.lines().map(|l| l.trim()) {
+            if line.to_lowercase().starts_with(OTPAUTH_SCHEME) {
+                return Otp::from_uri(line).map(Some);
+            }
+            if line.starts_with(TOTP_SEED_PREFIX) && seed.is_none() {
+                seed = Some(&line[TOTP_SEED_PREFIX.len()..]);
+            } else if line.starts_with(TOTP_SETTINGS_PREFIX) && settings.is_none() {
+                settings = Some(&line[TOTP_SETTINGS_PREFIX.len()..]);
+            }
+        }
+        let mut otp = match seed {
+            Some(seed) => try!(Otp::from_secret(seed)),
+            None => return Ok(None),
+        };
+        if let Some(settings) = settings {
+            // "<period>;<digits>", a third part is the URL of a time
+            // server
+            let mut parts = settings.split(';').map(|s| s.trim());
+            otp.period = try!(parse_period(parts.next().unwrap_or("")));
+            if let Some(digits) = parts.next() {
+                otp.digits = try!(parse_digits(digits));
+            }
+        }
+        Ok(Some(otp))
+    }
+
+    /// The HOTP code for counter (RFC 4226)
+    pub fn hotp(&mut self, counter: u64) -> Result<SecureString, V1KpdbError> {
+        self.generate(counter)
+    }
+
+    /// The TOTP code at time (RFC 6238), whatever kind the Otp is
+    pub fn totp(&mut self, time: DateTime<Local>) -> Result<SecureString, V1KpdbError> {
+        let seconds = time.timestamp();
+        if seconds < 0 || self.period == 0 {
+            return Err(V1KpdbError::OtpErr);
+        }
+        let counter = seconds as u64 / self.period;
+        self.generate(counter)
+    }
+
+    /// Seconds until the TOTP code of time expires
+    pub fn remaining(&self, time: DateTime<Local>) -> u64 {
+        if self.period == 0 || time.timestamp() < 0 {
+            return 0;
+        }
+        self.period - time.timestamp() as u64 % self.period
+    }
+
+    fn new(kind: OtpKind, label: String) -> Otp {
+        Otp {
+            kind: kind,
+            algorithm: OtpAlgorithm::Sha1,
+            digits: DEFAULT_DIGITS,
+            period: DEFAULT_PERIOD,
+            counter: 0,
+            label: label,
+            issuer: None,
+            secret: SecureString::new(String::new()),
+        }
+    }
+
+    // Sensitive data in this function:
+    // * secret (locked: SecureString)
+    // * key (locked)
+    // * mac (locked)
+    //
+    // At the end of this function:
+    // * secret is deleted
+    // * key and mac are zeroed out
+    fn generate(&mut self, counter: u64) -> Result<SecureString, V1KpdbError> {
+        if self.digits < 6 || self.digits > 8 {
+            return Err(V1KpdbError::OtpErr);
+        }
+        let key = {
+            let secret = self.secret.unlocked();
+            try!(decode_base32(&secret))
+        };
+        let message: Vec<u8> = (0..8).map(|i| (counter >> (56 - i * 8)) as u8).collect();
+        let mac = hmac(self.algorithm.hash_type(), &key, &message);
+        mem_protect::lock(&mac, "mac");
+        unsafe {
+            mem_protect::zero(&key);
+        }
+        mem_protect::unlock(&key);
+
+        // Dynamic truncation
+        let offset = (mac[mac.len() - 1] & 0xf) as usize;
+        let binary = (mac[offset] as u32 & 0x7f) << 24 | (mac[offset + 1] as u32) << 16 |
+                     (mac[offset + 2] as u32) << 8 | mac[offset + 3] as u32;
+        unsafe {
+            mem_protect::zero(&mac);
+        }
+        mem_protect::unlock(&mac);
+
+        let code = binary as u64 % 10u64.pow(self.digits);
+        Ok(SecureString::new(format!("{:0width$}", code, width = self.digits as usize)))
+    }
+}
+
+fn parse_digits(value: &str) -> Result<u32, V1KpdbError> {
+    match value.trim().parse() {
+        Ok(digits @ 6...8) => Ok(digits),
+        _ => Err(V1KpdbError::OtpErr),
+    }
+}
+
+fn parse_period(value: &str) -> Result<u64, V1KpdbError> {
+    match value.trim().parse() {
+        Ok(0) | Err(_) => Err(V1KpdbError::OtpErr),
+        Ok(period) => Ok(period),
+    }
+}
+
+// Upper case base32 without spaces and padding. The result is written
+// in place, so no copy of the secret is left behind.
+fn normalize_secret(value: &str) -> Result<SecureString, V1KpdbError> {
+    let mut secret = String::with_capacity(value.len());
+    for c in value.chars().filter(|c| !c.is_whitespace() && *c != '=') {
+        match c {
+            'A'...'Z' | '2'...'7' => secret.push(c),
+            'a'...'z' => secret.push((c as u8 - b'a' + b'A') as char),
+            _ => return Err(V1KpdbError::OtpErr),
+        }
+    }
+    if secret.is_empty() {
+        return Err(V1KpdbError::OtpErr);
+    }
+    Ok(SecureString::new(secret))
+}
+
+// Decode a normalized secret into a locked buffer, the caller zeroes it
+// out
+fn decode_base32(secret: &str) -> Result<Vec<u8>, V1KpdbError> {
+    let mut key = vec![0u8; secret.len() * 5 / 8];
+    mem_protect::lock(&key, "key");
+    let mut buffer = 0u32;
+    let mut bits = 0;
+    let mut len = 0;
+    for c in secret.bytes() {
+        let value = match c {
+            b'A'...b'Z' => c - b'A',
+            b'2'...b'7' => c - b'2' + 26,
+            _ => {
+                unsafe {
+                    mem_protect::zero(&key);
+                }
+                mem_protect::unlock(&key);
+                return Err(V1KpdbError::OtpErr);
+            }
+        };
+        buffer = (buffer << 5 | value as u32) & 0xfff;
+        bits += 5;
+        if bits >= 8 {
+            bits -= 8;
+            key[len] = (buffer >> bits) as u8;
+            len += 1;
+        }
+    }
+    Ok(key)
+}
+
+fn percent_decode(s: &str) -> Result<String, V1KpdbError> {
+    let bytes = s.as_bytes();
+    let mut out = vec![];
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            if i + 3 > bytes.len() {
+                return Err(V1KpdbError::OtpErr);
+            }
+            let hex = try!(str::from_utf8(&bytes[i + 1..i + 3]).map_err(|_| V1KpdbError::OtpErr));
+            out.push(try!(u8::from_str_radix(hex, 16).map_err(|_| V1KpdbError::OtpErr)));
+            i += 3;
+        } else {
+            out.push(bytes[i]);
+            i += 1;
+        }
+    }
+    String::from_utf8(out).map_err(|_| V1KpdbError::OtpErr)
+}
@@ -1,20 +1,23 @@
-use libc::{c_void, size_t};
-use libc::funcs::posix88::mman;
 use std::cell::{RefCell, RefMut};
-use std::intrinsics;
+use std::io::Read;
 use std::rc::Rc;
 use std::str;
 
-use chrono::{DateTime, Local, TimeZone, Datelike, Timelike};
 use rustc_serialize::hex::FromHex;
 use uuid::Uuid;
 
 use kpdb::common::{slice_to_u16, slice_to_u32, u16_to_vec_u8, u32_to_vec_u8};
+use kpdb::error::KpdbError;
+use kpdb::notes::split_saved_secrets;
+use kpdb::recovery::{DroppedRecord, RecordKind, RecoveryReport};
+use kpdb::stream::read_error;
+use kpdb::time;
 use kpdb::v1error::V1KpdbError;
 use kpdb::v1kpdb::V1Kpdb;
 use kpdb::v1entry::V1Entry;
 use kpdb::v1group::V1Group;
-use sec_str::SecureString;
+use mem_protect;
+use sec_str::{SecureBytes, SecureString};
 use kpdb::v1header::V1Header;
 
 pub struct HeaderLoadParser {
@@ -46,7 +49,14 @@ impl HeaderLoadParser {
         content_hash.extend(&self.header[56..88]);
         transf_randomseed.extend(&self.header[88..120]);
         let key_transf_rounds = try!(slice_to_u32(&self.header[120..124]));
-
+        // Seeds, IV and content hash stay out of the log
+        log_debug!("header: version {:#010x}, encryption flags {:#x}, {} groups, {} entries, \
+                    {} key transformation rounds",
+                   version,
+                   enc_flag,
+                   num_groups,
+                   num_entries,
+                   key_transf_rounds);
 
         Ok(V1Header {
             signature1: signature1,
@@ -114,30 +124,16 @@ impl LoadParser {
     
     // Parse the groups and put them into a vector
     pub fn parse_groups(&mut self) -> Result<(Vec<Rc<RefCell<V1Group>>>, Vec<u16>), V1KpdbError> {
+        try!(self.check_counts());
+
         let mut group_number: u32 = 0;
         let mut levels: Vec<u16> = vec![];
         let mut cur_group = Rc::new(RefCell::new(V1Group::new()));
         let mut groups: Vec<Rc<RefCell<V1Group>>> = vec![];
 
-        let mut field_type: u16;
-        let mut field_size: u32;
-
         while group_number < self.num_groups {
-            field_type = try!(slice_to_u16(&self.decrypted_database[self.pos..self.pos + 2]));
-            self.pos += 2;
-
-            if self.pos > self.decrypted_database.len() {
-                return Err(V1KpdbError::OffsetErr);
-            }
-
-            field_size = try!(slice_to_u32(&self.decrypted_database[self.pos..self.pos + 4]));
-            self.pos += 4;
-
-            if self.pos > self.decrypted_database.len() {
-                return Err(V1KpdbError::OffsetErr);
-            }
-
-            let _ = self.read_group_field(cur_group.borrow_mut(), field_type, field_size);
+            let (field_type, field_size) = try!(self.read_field_header());
+            try!(self.read_group_field(cur_group.borrow_mut(), field_type, field_size));
 
             if field_type == 0x0008 {
                 levels.push(cur_group.borrow().level);
@@ -145,16 +141,14 @@ impl LoadParser {
                 groups.push(cur_group);
                 group_number += 1;
                 if group_number == self.num_groups {
+                    // Skip the data of the end marker as well
+                    self.pos += field_size;
                     break;
                 };
                 cur_group = Rc::new(RefCell::new(V1Group::new()));
             }
 
-            self.pos += field_size as usize;
-
-            if self.pos > self.decrypted_database.len() {
-                return Err(V1KpdbError::OffsetErr);
-            }
+            self.pos += field_size;
         }
 
         Ok((groups, levels))
@@ -162,72 +156,254 @@ impl LoadParser {
 
     // Parse the entries and put them into a vector
     pub fn parse_entries(&mut self) -> Result<Vec<Rc<RefCell<V1Entry>>>, V1KpdbError> {
+        try!(self.check_counts());
+
         let mut entry_number: u32 = 0;
         let mut cur_entry = Rc::new(RefCell::new(V1Entry::new()));
         let mut entries: Vec<Rc<RefCell<V1Entry>>> = vec![];
 
-        let mut field_type: u16;
-        let mut field_size: u32;
-
         while entry_number < self.num_entries {
-            field_type = try!(slice_to_u16(&self.decrypted_database[self.pos..self.pos + 2]));
-            self.pos += 2;
+            let (field_type, field_size) = try!(self.read_field_header());
+            try!(self.read_entry_field(cur_entry.borrow_mut(), field_type, field_size));
 
-            if self.pos > self.decrypted_database.len() {
-                return Err(V1KpdbError::OffsetErr);
+            if field_type == 0xFFFF {
+                entries.push(cur_entry);
+                entry_number += 1;
+                if entry_number == self.num_entries {
+                    self.pos += field_size;
+                    break;
+                };
+                cur_entry = Rc::new(RefCell::new(V1Entry::new()));
             }
 
-            field_size = try!(slice_to_u32(&self.decrypted_database[self.pos..self.pos + 4]));
-            self.pos += 4;
+            self.pos += field_size;
+        }
 
-            if self.pos > self.decrypted_database.len() {
-                return Err(V1KpdbError::OffsetErr);
+        Ok(entries)
+    }
+
+    // Like parse_groups but groups with damaged fields are dropped and
+    // parsing stops at the first field which doesn't fit into the data.
+    // Everything is written to report.
+    pub fn salvage_groups(&mut self,
+                          report: &mut RecoveryReport)
+                          -> (Vec<Rc<RefCell<V1Group>>>, Vec<u16>) {
+        let mut levels: Vec<u16> = vec![];
+        let mut groups: Vec<Rc<RefCell<V1Group>>> = vec![];
+
+        for index in 0..self.num_groups {
+            let offset = self.pos;
+            let group = Rc::new(RefCell::new(V1Group::new()));
+            let salvaged = self.salvage_record(|parser, field_type, field_size| {
+                parser.read_group_field(group.borrow_mut(), field_type, field_size)
+            });
+            match salvaged {
+                Salvaged::Intact => {
+                    levels.push(group.borrow().level);
+                    groups.push(group);
+                }
+                Salvaged::Damaged(error) => {
+                    report.dropped.push(DroppedRecord {
+                        kind: RecordKind::Group,
+                        index: index,
+                        offset: offset,
+                        error: error,
+                    })
+                }
+                Salvaged::Truncated => {
+                    report.truncated = true;
+                    report.missing_groups = self.num_groups - index;
+                    break;
+                }
             }
+        }
 
-            let _ = self.read_entry_field(cur_entry.borrow_mut(), field_type, field_size);
+        (groups, levels)
+    }
 
-            if field_type == 0xFFFF {
-                entries.push(cur_entry);
-                entry_number += 1;
-                if entry_number == self.num_entries {
+    // Like parse_entries, see salvage_groups
+    pub fn salvage_entries(&mut self, report: &mut RecoveryReport) -> Vec<Rc<RefCell<V1Entry>>> {
+        let mut entries: Vec<Rc<RefCell<V1Entry>>> = vec![];
+
+        // Nothing is left if the groups were cut off already
+        if report.truncated {
+            report.missing_entries = self.num_entries;
+            return entries;
+        }
+
+        for index in 0..self.num_entries {
+            let offset = self.pos;
+            let entry = Rc::new(RefCell::new(V1Entry::new()));
+            let salvaged = self.salvage_record(|parser, field_type, field_size| {
+                parser.read_entry_field(entry.borrow_mut(), field_type, field_size)
+            });
+            match salvaged {
+                Salvaged::Intact => entries.push(entry),
+                Salvaged::Damaged(error) => {
+                    report.dropped.push(DroppedRecord {
+                        kind: RecordKind::Entry,
+                        index: index,
+                        offset: offset,
+                        error: error,
+                    })
+                }
+                Salvaged::Truncated => {
+                    report.truncated = true;
+                    report.missing_entries = self.num_entries - index;
                     break;
+                }
+            }
+        }
+
+        entries
+    }
+
+    // Read the fields of one record up to its end marker. A field which
+    // can't be converted damages the record but as its size is known
+    // parsing goes on with the next field.
+    fn salvage_record<F>(&mut self, mut read_field: F) -> Salvaged
+        where F: FnMut(&mut LoadParser, u16, usize) -> Result<(), V1KpdbError>
+    {
+        let mut damage = None;
+        loop {
+            let (field_type, field_size) = match self.read_field_header() {
+                Ok(header) => header,
+                Err(_) => return Salvaged::Truncated,
+            };
+            if let Err(e) = read_field(self, field_type, field_size) {
+                damage = damage.or(Some(e));
+            }
+            self.pos += field_size;
+            if field_type == 0xFFFF {
+                return match damage {
+                    Some(e) => Salvaged::Damaged(e),
+                    None => Salvaged::Intact,
                 };
-                cur_entry = Rc::new(RefCell::new(V1Entry::new()));
             }
+        }
+    }
 
-            self.pos += field_size as usize;
+    // Error for a parse failure at the current position
+    pub fn corrupt(&self, error: V1KpdbError) -> KpdbError {
+        KpdbError::Corrupt {
+            offset: Some(self.pos),
+            error: error,
+        }
+    }
 
-            if self.pos > self.decrypted_database.len() {
-                return Err(V1KpdbError::OffsetErr);
+    // Tell a wrong key from a damaged file after the content failed the
+    // checks: garbage from a wrong key doesn't parse into a single
+    // record, a damaged file still holds intact ones
+    pub fn diagnose(&mut self) -> KpdbError {
+        let mut report = RecoveryReport::new();
+        let (groups, _) = self.salvage_groups(&mut report);
+        let entries = self.salvage_entries(&mut report);
+        if groups.is_empty() && entries.is_empty() {
+            return KpdbError::WrongKey;
+        }
+        let offset = match report.dropped.first() {
+            Some(dropped) => dropped.offset,
+            None => self.pos,
+        };
+        KpdbError::Corrupt {
+            offset: Some(offset),
+            error: V1KpdbError::HashErr,
+        }
+    }
+
+    // Set num_groups and num_entries from the records of the content,
+    // which holds no counts. A record is a group if its field 0x0001,
+    // the id, has 4 bytes and an entry if it has 16, the UUID. All groups
+    // come before the entries. Only the field headers are read, nothing
+    // is allocated.
+    pub fn infer_counts(&mut self) -> Result<(), V1KpdbError> {
+        let mut num_groups: u32 = 0;
+        let mut num_entries: u32 = 0;
+        let mut id_size = None;
+        let mut in_record = false;
+        self.pos = 0;
+        while self.pos < self.decrypted_database.len() {
+            let (field_type, field_size) = try!(self.read_field_header());
+            self.pos += field_size;
+            in_record = true;
+            match field_type {
+                0x0001 => id_size = Some(field_size),
+                0xFFFF => {
+                    match id_size.take() {
+                        Some(4) if num_entries == 0 => num_groups += 1,
+                        Some(16) => num_entries += 1,
+                        _ => return Err(V1KpdbError::ConvertErr),
+                    }
+                    in_record = false;
+                }
+                _ => (),
             }
         }
+        // The last record has no end marker
+        if in_record {
+            return Err(V1KpdbError::OffsetErr);
+        }
+        self.pos = 0;
+        self.num_groups = num_groups;
+        self.num_entries = num_entries;
+        Ok(())
+    }
 
-        Ok(entries)
+    // Every group and entry takes at least the 6 bytes of its end
+    // marker. Counts which can't fit into the data are rejected before
+    // anything is allocated for them.
+    fn check_counts(&self) -> Result<(), V1KpdbError> {
+        let needed = (self.num_groups as u64 + self.num_entries as u64) * 6;
+        if needed > self.decrypted_database.len() as u64 {
+            return Err(V1KpdbError::OffsetErr);
+        }
+        Ok(())
+    }
+
+    // Read type and size of the next field. Returns OffsetErr if the
+    // field doesn't fit into the remaining data. Afterwards pos points to
+    // the data of the field.
+    fn read_field_header(&mut self) -> Result<(u16, usize), V1KpdbError> {
+        let remaining = self.decrypted_database.len() - self.pos;
+        if remaining < 6 {
+            return Err(V1KpdbError::OffsetErr);
+        }
+        let field_type = try!(slice_to_u16(&self.decrypted_database[self.pos..self.pos + 2]));
+        let field_size = try!(slice_to_u32(&self.decrypted_database[self.pos + 2..self.pos + 6]));
+        self.pos += 6;
+        if field_size as u64 > (remaining - 6) as u64 {
+            return Err(V1KpdbError::OffsetErr);
+        }
+        Ok((field_type, field_size as usize))
     }
 
     // Read a group field from the raw data by it's field type
     fn read_group_field(&mut self,
-                        mut group: RefMut<V1Group>,
+                        group: RefMut<V1Group>,
                         field_type: u16,
-                        field_size: u32)
+                        field_size: usize)
                         -> Result<(), V1KpdbError> {
-        let db_slice = if field_type == 0x0002 {
-            &self.decrypted_database[self.pos..self.pos + (field_size - 1) as usize]
-        } else {
-            &self.decrypted_database[self.pos..self.pos + field_size as usize]
-        };
+        // read_field_header made sure that the field fits
+        let db_slice = &self.decrypted_database[self.pos..self.pos + field_size];
+        LoadParser::group_field(group, field_type, db_slice)
+    }
 
+    // Set the field of group with type field_type from db_slice
+    fn group_field(mut group: RefMut<V1Group>,
+                   field_type: u16,
+                   db_slice: &[u8])
+                   -> Result<(), V1KpdbError> {
         match field_type {
             0x0001 => group.id = try!(slice_to_u32(db_slice)),
             0x0002 => {
-                group.title = str::from_utf8(db_slice)
+                group.title = str::from_utf8(strip_null(db_slice))
                                   .unwrap_or("")
                                   .to_string()
             }
-            0x0003 => group.creation = LoadParser::get_date(db_slice),
-            0x0004 => group.last_mod = LoadParser::get_date(db_slice),
-            0x0005 => group.last_access = LoadParser::get_date(db_slice),
-            0x0006 => group.expire = LoadParser::get_date(db_slice),
+            0x0003 => group.creation = try!(time::unpack(db_slice)),
+            0x0004 => group.last_mod = try!(time::unpack(db_slice)),
+            0x0005 => group.last_access = try!(time::unpack(db_slice)),
+            0x0006 => group.expire = try!(time::unpack_expiry(db_slice)),
             0x0007 => group.image = try!(slice_to_u32(db_slice)),
             0x0008 => group.level = try!(slice_to_u16(db_slice)),
             0x0009 => group.flags = try!(slice_to_u32(db_slice)),
@@ -239,95 +415,183 @@ impl LoadParser {
 
     // Read an entry field from the raw data by it's field type
     fn read_entry_field(&mut self,
-                        mut entry: RefMut<V1Entry>,
+                        entry: RefMut<V1Entry>,
                         field_type: u16,
-                        field_size: u32)
+                        field_size: usize)
                         -> Result<(), V1KpdbError> {
-        let db_slice = match field_type {
-            0x0004...0x0008 | 0x000D => {
-                &self.decrypted_database[self.pos..self.pos + (field_size - 1) as usize]
-            }
-            _ => &self.decrypted_database[self.pos..self.pos + field_size as usize],
-        };
+        // read_field_header made sure that the field fits
+        let db_slice = &self.decrypted_database[self.pos..self.pos + field_size];
+        LoadParser::entry_field(entry, field_type, db_slice)
+    }
 
+    // Set the field of entry with type field_type from db_slice
+    fn entry_field(mut entry: RefMut<V1Entry>,
+                   field_type: u16,
+                   db_slice: &[u8])
+                   -> Result<(), V1KpdbError> {
         match field_type {
-            0x0001 => entry.uuid = Uuid::from_bytes(db_slice).unwrap(),
+            0x0001 => {
+                entry.uuid = try!(Uuid::from_bytes(db_slice).ok_or(V1KpdbError::ConvertErr))
+            }
             0x0002 => entry.group_id = try!(slice_to_u32(db_slice)),
             0x0003 => entry.image = try!(slice_to_u32(db_slice)),
             0x0004 => {
-                entry.title = str::from_utf8(db_slice)
+                entry.title = str::from_utf8(strip_null(db_slice))
                                   .unwrap_or("")
                                   .to_string()
             }
             0x0005 => {
-                entry.url = Some(str::from_utf8(db_slice)
+                entry.url = Some(str::from_utf8(strip_null(db_slice))
                                      .unwrap_or("")
                                      .to_string())
             }
             0x0006 => {
-                entry.username = Some(SecureString::new(str::from_utf8(db_slice)
-                                                            .unwrap()
-                                                            .to_string()))
+                let username = try!(str::from_utf8(strip_null(db_slice))
+                                        .map_err(|_| V1KpdbError::ConvertErr));
+                entry.username = Some(SecureString::new(username.to_string()))
             }
             0x0007 => {
-                entry.password = Some(SecureString::new(str::from_utf8(db_slice)
-                                                            .unwrap()
-                                                            .to_string()))
+                let password = try!(str::from_utf8(strip_null(db_slice))
+                                        .map_err(|_| V1KpdbError::ConvertErr));
+                entry.replace_raw_password(Some(SecureString::new(password.to_string())));
             }
-            0x0008 => entry.comment = Some(str::from_utf8(db_slice).unwrap_or("").to_string()),
-            0x0009 => entry.creation = LoadParser::get_date(db_slice),
-            0x000A => entry.last_mod = LoadParser::get_date(db_slice),
-            0x000B => entry.last_access = LoadParser::get_date(db_slice),
-            0x000C => entry.expire = LoadParser::get_date(db_slice),
+            0x0008 => {
+                let comment = str::from_utf8(strip_null(db_slice)).unwrap_or("").to_string();
+                let (comment, secrets) = split_saved_secrets(comment);
+                entry.comment = Some(comment);
+                entry.protected_notes = secrets;
+            }
+            0x0009 => entry.creation = try!(time::unpack(db_slice)),
+            0x000A => entry.last_mod = try!(time::unpack(db_slice)),
+            0x000B => entry.last_access = try!(time::unpack(db_slice)),
+            0x000C => entry.expire = try!(time::unpack_expiry(db_slice)),
             0x000D => {
-                entry.binary_desc = Some(str::from_utf8(db_slice)
+                entry.binary_desc = Some(str::from_utf8(strip_null(db_slice))
                                              .unwrap_or("")
                                              .to_string())
             }
-            0x000E => {
-                entry.binary = Some((0..field_size as usize)
-                                        .map(|i| db_slice[i])
-                                        .collect())
-            }
+            0x000E => entry.binary = Some(SecureBytes::new(db_slice.to_vec())),
             _ => (),
         }
 
         Ok(())
     }
 
-    // Parse a date. Taken from original KeePass-code
-    fn get_date(date_bytes: &[u8]) -> DateTime<Local> {
-        let dw1 = date_bytes[0] as i32;
-        let dw2 = date_bytes[1] as i32;
-        let dw3 = date_bytes[2] as i32;
-        let dw4 = date_bytes[3] as i32;
-        let dw5 = date_bytes[4] as i32;
+    // Like parse_groups and parse_entries but the content is read field
+    // by field from reader, e.g. a DecryptReader, so it's never held as a
+    // whole. len is the size of the content, sizes of garbage fields
+    // beyond it are rejected before anything is allocated for them.
+    pub fn parse_stream<R: Read>(reader: &mut R,
+                                 num_groups: u32,
+                                 num_entries: u32,
+                                 len: u64)
+                                 -> Result<(Vec<Rc<RefCell<V1Group>>>,
+                                            Vec<u16>,
+                                            Vec<Rc<RefCell<V1Entry>>>),
+                                           V1KpdbError> {
+        if (num_groups as u64 + num_entries as u64) * 6 > len {
+            return Err(V1KpdbError::OffsetErr);
+        }
+        let mut remaining = len;
+        let mut levels: Vec<u16> = vec![];
+        let mut groups: Vec<Rc<RefCell<V1Group>>> = vec![];
+        for _ in 0..num_groups {
+            let group = Rc::new(RefCell::new(V1Group::new()));
+            try!(LoadParser::read_stream_record(reader, &mut remaining, |field_type, data| {
+                try!(LoadParser::group_field(group.borrow_mut(), field_type, data));
+                if field_type == 0x0008 {
+                    levels.push(group.borrow().level);
+                }
+                Ok(())
+            }));
+            groups.push(group);
+        }
 
-        let year = (dw1 << 6) | (dw2 >> 2);
-        let month = (((dw2 & 0x03) << 2) | (dw3 >> 6)) as u32;
-        let day = ((dw3 >> 1) & 0x1F) as u32;
-        let hour = (((dw3 & 0x01) << 4) | (dw4 >> 4)) as u32;
-        let minute = (((dw4 & 0x0F) << 2) | (dw5 >> 6)) as u32;
-        let second = (dw5 & 0x3F) as u32;
+        let mut entries: Vec<Rc<RefCell<V1Entry>>> = vec![];
+        for _ in 0..num_entries {
+            let entry = Rc::new(RefCell::new(V1Entry::new()));
+            try!(LoadParser::read_stream_record(reader, &mut remaining, |field_type, data| {
+                LoadParser::entry_field(entry.borrow_mut(), field_type, data)
+            }));
+            entries.push(entry);
+        }
+
+        Ok((groups, levels, entries))
+    }
 
-        Local.ymd(year, month, day).and_hms(hour, minute, second)
+    // Read the fields of one record up to its end marker from reader
+    //
+    // Sensitive data in this function:
+    // * data (locked)
+    //
+    // At the end of this function:
+    // * data is zeroed out after each field
+    fn read_stream_record<R, F>(reader: &mut R,
+                                remaining: &mut u64,
+                                mut read_field: F)
+                                -> Result<(), V1KpdbError>
+        where R: Read,
+              F: FnMut(u16, &[u8]) -> Result<(), V1KpdbError>
+    {
+        loop {
+            let mut field_header = [0u8; 6];
+            if *remaining < 6 {
+                return Err(V1KpdbError::OffsetErr);
+            }
+            try!(reader.read_exact(&mut field_header).map_err(read_error));
+            *remaining -= 6;
+            let field_type = try!(slice_to_u16(&field_header[0..2]));
+            let field_size = try!(slice_to_u32(&field_header[2..6])) as u64;
+            if field_size > *remaining {
+                return Err(V1KpdbError::OffsetErr);
+            }
+            *remaining -= field_size;
+
+            let mut data = vec![0u8; field_size as usize];
+            mem_protect::lock(&data, "field");
+            let result = reader.read_exact(&mut data)
+                               .map_err(read_error)
+                               .and_then(|_| read_field(field_type, &data));
+            unsafe {
+                mem_protect::zero(&data);
+            }
+            mem_protect::unlock(&data);
+            try!(result);
+            if field_type == 0xFFFF {
+                return Ok(());
+            }
+        }
     }
 
     // Create the group tree from the level data
     pub fn create_group_tree(db: &mut V1Kpdb, levels: Vec<u16>) -> Result<(), V1KpdbError> {
-        if levels[0] != 0 {
+        LoadParser::link_group_tree(&db.root_group, &db.groups, &db.entries, levels)
+    }
+
+    // create_group_tree for groups and entries outside of a database,
+    // see v1parser::parse_decrypted
+    pub fn link_group_tree(root_group: &Rc<RefCell<V1Group>>,
+                           groups: &[Rc<RefCell<V1Group>>],
+                           entries: &[Rc<RefCell<V1Entry>>],
+                           levels: Vec<u16>)
+                           -> Result<(), V1KpdbError> {
+        // Every group needs exactly one level
+        if levels.len() != groups.len() {
+            return Err(V1KpdbError::TreeErr);
+        }
+        if levels.first().map_or(false, |l| *l != 0) {
             return Err(V1KpdbError::TreeErr);
         }
 
-        for i in 0..db.groups.len() {
+        for i in 0..groups.len() {
             // level 0 means that the group is not a sub group. Hence add it as a children
             // of the root
             if levels[i] == 0 {
-                db.groups[i].borrow_mut().parent = Some(db.root_group.clone());
-                db.root_group
+                groups[i].borrow_mut().parent = Some(root_group.clone());
+                root_group
                   .borrow_mut()
                   .children
-                  .push(Rc::downgrade(&(db.groups[i].clone())));
+                  .push(Rc::downgrade(&(groups[i].clone())));
                 continue;
             }
 
@@ -339,11 +603,11 @@ impl LoadParser {
                     if levels[i] - levels[j] != 1 {
                         return Err(V1KpdbError::TreeErr);
                     }
-                    db.groups[i].borrow_mut().parent = Some(db.groups[j].clone());
-                    db.groups[j]
+                    groups[i].borrow_mut().parent = Some(groups[j].clone());
+                    groups[j]
                         .borrow_mut()
                         .children
-                        .push(Rc::downgrade(&(db.groups[i].clone())));
+                        .push(Rc::downgrade(&(groups[i].clone())));
                     break;
                 }
                 // It's not possible that a group which comes after another
@@ -359,8 +623,8 @@ impl LoadParser {
         // Sort entries to their groups
         // iter is secure as it is just obfuscated
         // pointer arithmetic to the entries vector
-        for e in db.entries.iter() {
-            for g in db.groups.iter() {
+        for e in entries.iter() {
+            for g in groups.iter() {
                 if e.borrow().group_id == g.borrow().id {
                     g.borrow_mut().entries.push(Rc::downgrade(&e.clone()));
                     e.borrow_mut().group = Some(g.clone());
@@ -371,18 +635,49 @@ impl LoadParser {
         Ok(())
     }
 
+    // Lower the levels which don't fit into the tree, e.g. because the
+    // parent group was dropped. Returns the number of changed levels.
+    pub fn repair_levels(groups: &Vec<Rc<RefCell<V1Group>>>, levels: &mut Vec<u16>) -> usize {
+        let mut repaired = 0;
+        for i in 0..levels.len() {
+            let max = if i == 0 {
+                0
+            } else {
+                levels[i - 1] + 1
+            };
+            if levels[i] > max {
+                levels[i] = max;
+                groups[i].borrow_mut().level = max;
+                repaired += 1;
+            }
+        }
+        repaired
+    }
+
     pub fn delete_decrypted_content(&mut self) {
         // Zero out raw data as it's not needed anymore
         unsafe {
-            intrinsics::volatile_set_memory(self.decrypted_database.as_ptr() as *mut c_void,
-                                            0u8,
-                                            self.decrypted_database.len());
-            mman::munlock(self.decrypted_database.as_ptr() as *const c_void,
-                          self.decrypted_database.len() as size_t);
+            mem_protect::zero(&self.decrypted_database);
+            mem_protect::unlock(&self.decrypted_database);
         }
     }
 }
 
+// Outcome of reading a record in recovery mode
+enum Salvaged {
+    Intact,
+    Damaged(V1KpdbError),
+    Truncated,
+}
+
+// Strings are saved with a terminating null byte
+fn strip_null(slice: &[u8]) -> &[u8] {
+    match slice.last() {
+        Some(&0) => &slice[..slice.len() - 1],
+        _ => slice,
+    }
+}
+
 impl Drop for LoadParser {
     fn drop(&mut self) {
         self.delete_decrypted_content();
@@ -402,12 +697,24 @@ impl SaveParser {
     }
 
     pub fn prepare(&mut self, database: &V1Kpdb) {
-        self.save_groups(database);
-        self.save_entries(database);
+        let content = &mut self.database;
+        SaveParser::stream(database, |bytes| content.extend_from_slice(bytes));
     }
-    
-    fn save_groups(&mut self,
-                   database: &V1Kpdb) {
+
+    /// Serialize the groups and entries of database field by field into
+    /// sink instead of a single buffer, e.g. to encrypt them on the fly.
+    /// The copies of secret fields handed to sink are locked and zeroed
+    /// out as soon as it returns.
+    pub fn stream<F>(database: &V1Kpdb, mut sink: F)
+        where F: FnMut(&[u8])
+    {
+        SaveParser::save_groups(database, &mut sink);
+        SaveParser::save_entries(database, &mut sink);
+    }
+
+    fn save_groups<F>(database: &V1Kpdb, sink: &mut F)
+        where F: FnMut(&[u8])
+    {
         let mut ret: Vec<u8>;
         let mut ret_len: u32;
         for group in &database.groups {
@@ -415,33 +722,39 @@ impl SaveParser {
                 ret = SaveParser::save_group_field(group.clone(), field_type);
                 ret_len = ret.len() as u32;
                 if ret_len > 0 {
-                    self.database.append(&mut u16_to_vec_u8(field_type));
-                    self.database.append(&mut u32_to_vec_u8(ret_len));
-                    self.database.append(&mut ret);
+                    sink(&u16_to_vec_u8(field_type));
+                    sink(&u32_to_vec_u8(ret_len));
+                    sink(&ret);
                 }
             }
-            self.database.append(&mut vec![0xFFu8, 0xFFu8]);
-            self.database.append(&mut vec![0u8, 0u8, 0u8, 0u8]);
+            sink(&[0xFFu8, 0xFFu8, 0u8, 0u8, 0u8, 0u8]);
         }
     }
 
-    fn save_entries(&mut self,
-                    database: &V1Kpdb) {
+    fn save_entries<F>(database: &V1Kpdb, sink: &mut F)
+        where F: FnMut(&[u8])
+    {
         let mut ret: Vec<u8>;
         let mut ret_len: u32;
-        for entry in &database.entries {
+        for entry in database.entries.iter().chain(database.meta_entries.iter()) {
             for field_type in 1..15 as u16 {
                 ret = SaveParser::save_entry_field(entry.clone(), field_type);
                 ret_len = ret.len() as u32;
                 if ret_len > 0 {
-                    self.database.append(&mut u16_to_vec_u8(field_type));
-                    self.database.append(&mut u32_to_vec_u8(ret_len));
-                    self.database.append(&mut ret);
+                    sink(&u16_to_vec_u8(field_type));
+                    sink(&u32_to_vec_u8(ret_len));
+                    sink(&ret);
                 }
-            }            
-            self.database.append(&mut vec![0xFFu8, 0xFFu8]);
-            self.database.append(&mut vec![0u8, 0u8, 0u8, 0u8]);
-        }        
+                // Copies of secrets are locked, see save_entry_field
+                unsafe {
+                    mem_protect::zero(&ret);
+                }
+                if is_secret_field(field_type) {
+                    mem_protect::unlock(&ret);
+                }
+            }
+            sink(&[0xFFu8, 0xFFu8, 0u8, 0u8, 0u8, 0u8]);
+        }
     }
     
     fn save_group_field(group: Rc<RefCell<V1Group>>,
@@ -453,10 +766,10 @@ impl SaveParser {
                 title.push(0);
                 return title;
             },
-            0x0003 => return SaveParser::pack_date(&group.borrow().creation),
-            0x0004 => return SaveParser::pack_date(&group.borrow().last_mod),
-            0x0005 => return SaveParser::pack_date(&group.borrow().last_access),
-            0x0006 => return SaveParser::pack_date(&group.borrow().expire),
+            0x0003 => return time::pack(&group.borrow().creation),
+            0x0004 => return time::pack(&group.borrow().last_mod),
+            0x0005 => return time::pack(&group.borrow().last_access),
+            0x0006 => return time::pack(&group.borrow().expire),
             0x0007 => return u32_to_vec_u8(group.borrow().image),
             0x0008 => return u16_to_vec_u8(group.borrow().level),
             0x0009 => return u32_to_vec_u8(group.borrow().flags),
@@ -486,31 +799,27 @@ impl SaveParser {
             },
             0x0006 => {
                 if let Some(ref mut username) = entry.borrow_mut().username {
-                    username.unlock();
-                    let mut ret = username.string.clone().into_bytes();
-                    ret.push(0);
-                    return ret;
+                    return secret_field(&username.unlocked());
                 }
             },
             0x0007 => {
-                if let Some(ref mut password) = entry.borrow_mut().password {
-                    password.unlock();
-                    let mut ret = password.string.clone().into_bytes();
-                    ret.push(0);
-                    return ret;
+                if let Some(password) = entry.borrow_mut().raw_password_mut() {
+                    return secret_field(&password.unlocked());
                 }
             },
             0x0008 => {
-                if let Some(ref comment) = entry.borrow().comment {
-                    let mut ret = comment.clone().into_bytes();
-                    ret.push(0);
-                    return ret;                    
+                if let Some(notes) = entry.borrow_mut().markdown_notes() {
+                    let ret = secret_field(&notes);
+                    unsafe {
+                        mem_protect::zero(&notes);
+                    }
+                    return ret;
                 }
             },
-            0x0009 => return SaveParser::pack_date(&entry.borrow().creation),
-            0x000A => return SaveParser::pack_date(&entry.borrow().last_mod),
-            0x000B => return SaveParser::pack_date(&entry.borrow().last_access),
-            0x000C => return SaveParser::pack_date(&entry.borrow().expire),
+            0x0009 => return time::pack(&entry.borrow().creation),
+            0x000A => return time::pack(&entry.borrow().last_mod),
+            0x000B => return time::pack(&entry.borrow().last_access),
+            0x000C => return time::pack(&entry.borrow().expire),
             0x000D => {
                 if let Some(ref binary_desc) = entry.borrow().binary_desc {
                     let mut ret = binary_desc.clone().into_bytes();
@@ -520,7 +829,9 @@ impl SaveParser {
             },
             0x000E => {
                 if let Some(ref binary) = entry.borrow().binary {
-                    return binary.clone();
+                    let ret = binary.bytes().to_vec();
+                    mem_protect::lock(&ret, "binary");
+                    return ret;
                 }
             },
             _ => (),
@@ -529,21 +840,23 @@ impl SaveParser {
         return vec![];        
     }
     
-    fn pack_date(date: &DateTime<Local>) -> Vec<u8> {
-        let year = date.year() as i32;
-        let month = date.month() as i32;
-        let day = date.day() as i32;
-        let hour = date.hour() as i32;
-        let minute = date.minute() as i32;
-        let second = date.second() as i32;
-        
-        let dw1 = (0x0000FFFF & ((year>>6) & 0x0000003F)) as u8;
-        let dw2 = (0x0000FFFF & ((year & 0x0000003F)<<2 | ((month>>2) & 0x00000003))) as u8;
-        let dw3 = (0x0000FFFF & (((month & 0x0000003)<<6) | ((day & 0x0000001F)<<1) | ((hour>>4) & 0x00000001))) as u8;
-        let dw4 = (0x0000FFFF & (((hour & 0x0000000F)<<4) | ((minute>>2) & 0x0000000F))) as u8;
-        let dw5 = (0x0000FFFF & (((minute & 0x00000003)<<6) | (second & 0x0000003F))) as u8;
-
-        vec![dw1, dw2, dw3, dw4, dw5]
+}
+
+
+// Username, password, notes and attachment of an entry
+fn is_secret_field(field_type: u16) -> bool {
+    match field_type {
+        0x0006 | 0x0007 | 0x0008 | 0x000E => true,
+        _ => false,
     }
 }
 
+// A locked, null terminated copy of a secret field, allocated once so no
+// partial copies are left behind. save_entries zeroes it out.
+fn secret_field(plain: &str) -> Vec<u8> {
+    let mut ret = Vec::with_capacity(plain.len() + 1);
+    ret.extend_from_slice(plain.as_bytes());
+    ret.push(0);
+    mem_protect::lock(&ret, "entry field");
+    ret
+}
@@ -0,0 +1,133 @@
+use chrono::{DateTime, Local};
+
+use sec_str::SecureString;
+
+/// First line of the protected notes section which holds the previous
+/// passwords of an entry, followed by the number of passwords to keep
+pub const PASSWORD_HISTORY_HEADER: &'static str = "KPEX_PASSWORD_HISTORY";
+
+#[doc = "
+The previous passwords of an entry, kept in a protected section of its
+notes, one per line as \"<RFC 3339 time> <password>\". Backslashes and
+line breaks in passwords are escaped with a backslash, e.g. a line
+break as \\n. See V1Entry::enable_password_history.
+"]
+pub struct PasswordHistory {
+    /// Number of passwords kept, the oldest are dropped first. 0 keeps
+    /// all of them
+    pub limit: usize,
+    /// The oldest password first
+    pub passwords: Vec<OldPassword>,
+}
+
+/// A password of an entry before it was changed
+pub struct OldPassword {
+    pub password: SecureString,
+    /// When the password was replaced
+    pub replaced: DateTime<Local>,
+}
+
+impl PasswordHistory {
+    pub fn new(limit: usize) -> PasswordHistory {
+        PasswordHistory {
+            limit: limit,
+            passwords: vec![],
+        }
+    }
+
+    /// Add password as replaced at time and drop the oldest passwords
+    /// which exceed the limit
+    pub fn push(&mut self, password: SecureString, replaced: DateTime<Local>) {
+        self.passwords.push(OldPassword {
+            password: password,
+            replaced: replaced,
+        });
+        let limit = self.limit;
+        self.set_limit(limit);
+    }
+
+    /// Change the limit, dropping the oldest passwords which exceed it
+    pub fn set_limit(&mut self, limit: usize) {
+        self.limit = limit;
+        if limit > 0 && self.passwords.len() > limit {
+            let excess = self.passwords.len() - limit;
+            self.passwords.drain(..excess);
+        }
+    }
+}
+
+/// Read the history of a protected notes section. None if section
+/// doesn't hold a password history. Lines which can't be read are
+/// skipped.
+pub fn decode_history(section: &mut SecureString) -> Option<PasswordHistory> {
+    let content = section.unlocked();
+    let mut lines = content.lines();
+    let limit = match lines.next().map(|l| l.split_whitespace().collect::<Vec<_>>()) {
+        Some(ref words) if words.first() == Some(&PASSWORD_HISTORY_HEADER) => {
+            words.get(1).and_then(|l| l.parse().ok()).unwrap_or(0)
+        }
+        _ => return None,
+    };
+    let mut history = PasswordHistory::new(limit);
+    for line in lines {
+        let space = match line.find(' ') {
+            Some(i) => i,
+            None => continue,
+        };
+        let replaced = match DateTime::parse_from_rfc3339(&line[..space]) {
+            Ok(time) => time.with_timezone(&Local),
+            Err(_) => continue,
+        };
+        history.passwords.push(OldPassword {
+            password: unescape(&line[space + 1..]),
+            replaced: replaced,
+        });
+    }
+    Some(history)
+}
+
+/// The protected notes section holding history
+pub fn encode_history(history: &mut PasswordHistory) -> SecureString {
+    // Allocated once, so no partial copies of the passwords are left
+    // behind. Escaping at most doubles a password.
+    let capacity = history.passwords
+                          .iter()
+                          .fold(PASSWORD_HISTORY_HEADER.len() + 21,
+                                |n, p| n + 2 * p.password.string.len() + 40);
+    let mut section = String::with_capacity(capacity);
+    section.push_str(PASSWORD_HISTORY_HEADER);
+    section.push(' ');
+    section.push_str(&history.limit.to_string());
+    for old in history.passwords.iter_mut() {
+        section.push('\n');
+        section.push_str(&old.replaced.to_rfc3339());
+        section.push(' ');
+        for c in old.password.unlocked().chars() {
+            match c {
+                '\\' => section.push_str("\\\\"),
+                '\n' => section.push_str("\\n"),
+                '\r' => section.push_str("\\r"),
+                c => section.push(c),
+            }
+        }
+    }
+    SecureString::new(section)
+}
+
+fn unescape(escaped: &str) -> SecureString {
+    let mut password = String::with_capacity(escaped.len());
+    let mut chars = escaped.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            password.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => password.push('\n'),
+            Some('r') => password.push('\r'),
+            Some(c) => password.push(c),
+            None => password.push('\\'),
+        }
+    }
+    SecureString::new(password)
+}
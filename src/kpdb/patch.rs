@@ -0,0 +1,170 @@
+use chrono::{DateTime, Local};
+use rustc_serialize::json::{self, Json};
+
+use kpdb::limits::{check, FieldLimits};
+use kpdb::v1entry::{never_expires, V1Entry};
+use kpdb::v1error::V1KpdbError;
+use super::super::sec_str::SecureString;
+
+#[doc = "
+A single change of an entry, see V1Entry::apply_patch. In JSON a patch
+is an array of operations:
+
+```text
+[
+    {\"op\": \"set\", \"field\": \"title\", \"value\": \"Mail\"},
+    {\"op\": \"set\", \"field\": \"password\", \"value\": \"secret\"},
+    {\"op\": \"set\", \"field\": \"url\", \"value\": null},
+    {\"op\": \"add_tag\", \"value\": \"work\"},
+    {\"op\": \"set_expiry\", \"value\": \"2030-01-01T00:00:00+01:00\"}
+]
+```
+
+Settable fields are title, url, username, password, comment and image.
+null removes a field, for title it's not allowed and for set_expiry it
+means that the entry never expires.
+"]
+pub enum PatchOp {
+    /// "set" of title
+    SetTitle(String),
+    /// "set" of url
+    SetUrl(Option<String>),
+    /// "set" of username
+    SetUsername(Option<SecureString>),
+    /// "set" of password
+    SetPassword(Option<SecureString>),
+    /// "set" of comment
+    SetComment(Option<String>),
+    /// "set" of image
+    SetImage(u32),
+    /// "add_tag", see V1Entry::add_tag
+    AddTag(String),
+    /// "set_expiry"
+    SetExpiry(DateTime<Local>),
+}
+
+/// Parse and validate a patch. Any invalid operation makes the whole
+/// patch invalid.
+pub fn parse_patch(patch: &str) -> Result<Vec<PatchOp>, V1KpdbError> {
+    let ops = match try!(Json::from_str(patch).map_err(|_| V1KpdbError::PatchErr)) {
+        Json::Array(ops) => ops,
+        _ => return Err(V1KpdbError::PatchErr),
+    };
+    let mut result = vec![];
+    for op in ops {
+        match op {
+            Json::Object(op) => result.push(try!(parse_op(op))),
+            _ => return Err(V1KpdbError::PatchErr),
+        }
+    }
+    Ok(result)
+}
+
+// The value is moved out of the object, so protected values go into a
+// SecureString without another copy
+fn parse_op(mut op: json::Object) -> Result<PatchOp, V1KpdbError> {
+    let value = try!(op.remove("value").ok_or(V1KpdbError::PatchErr));
+    let name = match op.get("op") {
+        Some(&Json::String(ref name)) => name.clone(),
+        _ => return Err(V1KpdbError::PatchErr),
+    };
+    match &name[..] {
+        "set" => {
+            let field = match op.get("field") {
+                Some(&Json::String(ref field)) => field.clone(),
+                _ => return Err(V1KpdbError::PatchErr),
+            };
+            parse_set(&field, value)
+        }
+        "add_tag" => {
+            let tag = try!(into_string(value));
+            if tag.trim().is_empty() || tag.contains(',') || tag.contains('\n') {
+                return Err(V1KpdbError::PatchErr);
+            }
+            Ok(PatchOp::AddTag(tag.trim().to_string()))
+        }
+        "set_expiry" => {
+            match value {
+                Json::Null => Ok(PatchOp::SetExpiry(never_expires())),
+                Json::String(ref time) => {
+                    DateTime::parse_from_rfc3339(time)
+                        .map(|t| PatchOp::SetExpiry(t.with_timezone(&Local)))
+                        .map_err(|_| V1KpdbError::PatchErr)
+                }
+                _ => Err(V1KpdbError::PatchErr),
+            }
+        }
+        _ => Err(V1KpdbError::PatchErr),
+    }
+}
+
+// New values have to fit into the limits of the format
+fn parse_set(field: &str, value: Json) -> Result<PatchOp, V1KpdbError> {
+    let limits = FieldLimits::v1();
+    match field {
+        "title" => {
+            let title = try!(into_string(value));
+            try!(check(title.len(), limits.title));
+            Ok(PatchOp::SetTitle(title))
+        }
+        "url" => Ok(PatchOp::SetUrl(try!(into_checked_string(value, limits.url)))),
+        "comment" => Ok(PatchOp::SetComment(try!(into_checked_string(value, limits.comment)))),
+        "username" => {
+            Ok(PatchOp::SetUsername(try!(into_checked_string(value, limits.username))
+                                        .map(SecureString::new)))
+        }
+        "password" => {
+            Ok(PatchOp::SetPassword(try!(into_checked_string(value, limits.password))
+                                        .map(SecureString::new)))
+        }
+        "image" => {
+            match value.as_u64() {
+                Some(image) if image <= u32::max_value() as u64 => {
+                    Ok(PatchOp::SetImage(image as u32))
+                }
+                _ => Err(V1KpdbError::PatchErr),
+            }
+        }
+        _ => Err(V1KpdbError::PatchErr),
+    }
+}
+
+fn into_string(value: Json) -> Result<String, V1KpdbError> {
+    match value {
+        Json::String(string) => Ok(string),
+        _ => Err(V1KpdbError::PatchErr),
+    }
+}
+
+fn into_checked_string(value: Json, limit: usize) -> Result<Option<String>, V1KpdbError> {
+    match value {
+        Json::Null => Ok(None),
+        value => {
+            let string = try!(into_string(value));
+            try!(check(string.len(), limit));
+            Ok(Some(string))
+        }
+    }
+}
+
+/// Apply already validated operations to entry
+pub fn apply_ops(entry: &mut V1Entry, ops: Vec<PatchOp>) {
+    for op in ops {
+        match op {
+            PatchOp::SetTitle(title) => entry.title = title,
+            PatchOp::SetUrl(url) => entry.url = url,
+            // The old SecureStrings are dropped and therefore zeroed out
+            PatchOp::SetUsername(username) => entry.username = username,
+            PatchOp::SetPassword(password) => {
+                entry.replace_raw_password(password);
+            }
+            PatchOp::SetComment(comment) => entry.comment = comment,
+            PatchOp::SetImage(image) => entry.image = image,
+            PatchOp::AddTag(tag) => {
+                entry.add_tag(&tag);
+            }
+            PatchOp::SetExpiry(expire) => entry.expire = expire,
+        }
+    }
+    entry.last_mod = Local::now();
+}
@@ -0,0 +1,49 @@
+//! Slash-separated paths of groups and entries
+//!
+//! A path lists the titles from a top-level group down, separated by
+//! "/", e.g. "Internet/Email" for a group or "Internet/Email/Gmail" for
+//! the entry Gmail in it. A "/" in a title is written as "\/", a
+//! backslash as "\\". See V1Kpdb::group_by_path, V1Kpdb::entry_by_path
+//! and V1Kpdb::create_path.
+
+use kpdb::v1error::V1KpdbError;
+
+/// Separates the titles of a path
+pub const SEPARATOR: char = '/';
+
+// Escapes SEPARATOR and itself in a title
+const ESCAPE: char = '\\';
+
+/// The titles of path from the top level down. PathErr if path or one
+/// of its titles is empty, e.g. "Internet//Email" or "Internet/", or a
+/// backslash doesn't escape "/" or "\".
+pub fn split(path: &str) -> Result<Vec<String>, V1KpdbError> {
+    let mut titles = vec![];
+    let mut title = String::new();
+    let mut chars = path.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            SEPARATOR => titles.push(title.split_off(0)),
+            ESCAPE => {
+                match chars.next() {
+                    Some(c) if c == SEPARATOR || c == ESCAPE => title.push(c),
+                    _ => return Err(V1KpdbError::PathErr),
+                }
+            }
+            c => title.push(c),
+        }
+    }
+    titles.push(title);
+    if titles.iter().any(|t| t.is_empty()) {
+        return Err(V1KpdbError::PathErr);
+    }
+    Ok(titles)
+}
+
+/// The path of titles, the reverse of split
+pub fn join(titles: &[String]) -> String {
+    titles.iter()
+          .map(|t| t.replace(ESCAPE, "\\\\").replace(SEPARATOR, "\\/"))
+          .collect::<Vec<_>>()
+          .join("/")
+}
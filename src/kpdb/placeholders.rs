@@ -0,0 +1,284 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use chrono::{DateTime, Datelike, Local, Timelike, UTC};
+use uuid::Uuid;
+
+use kpdb::export::group_titles;
+use kpdb::v1entry::V1Entry;
+use kpdb::v1kpdb::V1Kpdb;
+use sec_str::SecureString;
+
+/// How deep placeholders in field values are expanded, e.g. a password
+/// which is a {REF:...} to another entry. Deeper placeholders and
+/// reference cycles are kept as they are.
+pub const MAX_DEPTH: usize = 12;
+
+// Field codes of {REF:<wanted>@<search in>:<text>}
+const REF_PREFIX: &'static str = "REF:";
+
+// Parts of the URL for {URL:<part>}
+const URL_PREFIX: &'static str = "URL:";
+
+/// Expand the placeholders of text for entry the way KeePass does:
+///
+/// * {TITLE}, {USERNAME}, {PASSWORD}, {URL}, {NOTES} and {UUID}
+/// * {URL:RMVSCM}, {URL:SCM}, {URL:HOST}, {URL:PORT}, {URL:PATH},
+///   {URL:QUERY}, {URL:USERINFO}, {URL:USERNAME} and {URL:PASSWORD}
+/// * {GROUP} and {GROUP_PATH}, the titles joined by dots
+/// * {REF:<wanted>@<search in>:<text>}, a field of another entry of db.
+///   Fields are T(itle), U(sername), P(assword), A (URL), N(otes) and
+///   I (UUID). I finds the entry by its UUID in hex, the others by a
+///   field containing text, case ignored.
+/// * {DT_SIMPLE}, {DT_YEAR}, {DT_MONTH}, {DT_DAY}, {DT_HOUR},
+///   {DT_MINUTE}, {DT_SECOND} and the same with DT_UTC_
+///
+/// Placeholders are case-insensitive. Field values are expanded as well,
+/// up to MAX_DEPTH. Unknown placeholders, references to missing entries
+/// and fields of entries which are borrowed elsewhere are kept as they
+/// are, so entry shouldn't be borrowed meanwhile.
+pub fn expand(db: &V1Kpdb, entry: &Rc<RefCell<V1Entry>>, text: &str) -> SecureString {
+    expand_at(db, entry, text, Local::now())
+}
+
+/// expand with now as the time of the DT_ placeholders
+pub fn expand_at(db: &V1Kpdb,
+                 entry: &Rc<RefCell<V1Entry>>,
+                 text: &str,
+                 now: DateTime<Local>)
+                 -> SecureString {
+    let expander = Expander { db: db, now: now };
+    expander.expand(entry, text, 0)
+}
+
+struct Expander<'a> {
+    db: &'a V1Kpdb,
+    now: DateTime<Local>,
+}
+
+impl<'a> Expander<'a> {
+    // The result is moved into a SecureString, field values are only
+    // unlocked while they're copied into it
+    fn expand(&self, entry: &Rc<RefCell<V1Entry>>, text: &str, depth: usize) -> SecureString {
+        let mut out = String::with_capacity(text.len());
+        let mut rest = text;
+        while let Some(start) = rest.find('{') {
+            out.push_str(&rest[..start]);
+            rest = &rest[start..];
+            let end = match rest.find('}') {
+                Some(end) => end,
+                None => break,
+            };
+            let placeholder = &rest[1..end];
+            match self.value(entry, placeholder, depth) {
+                Some(mut value) => out.push_str(&value.unlocked()),
+                None => out.push_str(&rest[..end + 1]),
+            }
+            rest = &rest[end + 1..];
+        }
+        out.push_str(rest);
+        SecureString::new(out)
+    }
+
+    fn value(&self,
+             entry: &Rc<RefCell<V1Entry>>,
+             placeholder: &str,
+             depth: usize)
+             -> Option<SecureString> {
+        let name = placeholder.to_uppercase();
+        if name.starts_with("DT_") {
+            return self.date_time(&name[3..]).map(SecureString::new);
+        }
+        if depth >= MAX_DEPTH {
+            return None;
+        }
+        if name.starts_with(REF_PREFIX) {
+            let (wanted, target) = match self.reference(&placeholder[REF_PREFIX.len()..]) {
+                Some(reference) => reference,
+                None => return None,
+            };
+            return field(&target, wanted)
+                       .map(|mut v| self.expand(&target, &v.unlocked(), depth + 1));
+        }
+        if name.starts_with(URL_PREFIX) {
+            let mut url = match field(entry, 'A') {
+                Some(mut url) => self.expand(entry, &url.unlocked(), depth + 1),
+                None => return None,
+            };
+            let part = url_part(&url.unlocked(), &name[URL_PREFIX.len()..]);
+            return part.map(SecureString::new);
+        }
+        let code = match &name[..] {
+            "TITLE" => 'T',
+            "USERNAME" => 'U',
+            "PASSWORD" => 'P',
+            "URL" => 'A',
+            "NOTES" => 'N',
+            "UUID" => 'I',
+            "GROUP" | "GROUP_PATH" => {
+                let titles = match entry.try_borrow() {
+                    Ok(entry) => group_titles(&entry.group),
+                    Err(_) => return None,
+                };
+                return Some(SecureString::new(if name == "GROUP" {
+                    titles.last().cloned().unwrap_or(String::new())
+                } else {
+                    titles.join(".")
+                }));
+            }
+            _ => return None,
+        };
+        field(entry, code).map(|mut v| self.expand(entry, &v.unlocked(), depth + 1))
+    }
+
+    // The wanted field code and the entry of "<wanted>@<search in>:<text>"
+    fn reference(&self, reference: &str) -> Option<(char, Rc<RefCell<V1Entry>>)> {
+        let reference = reference.trim();
+        let code = |b: u8| {
+            match b {
+                b'T' | b'U' | b'P' | b'A' | b'N' | b'I' => Some(b as char),
+                b't' | b'u' | b'p' | b'a' | b'n' | b'i' => Some((b - b'a' + b'A') as char),
+                _ => None,
+            }
+        };
+        let bytes = reference.as_bytes();
+        let (wanted, search_in) = match bytes.len() {
+            len if len >= 4 && bytes[1] == b'@' && bytes[3] == b':' => {
+                match (code(bytes[0]), code(bytes[2])) {
+                    (Some(wanted), Some(search_in)) => (wanted, search_in),
+                    _ => return None,
+                }
+            }
+            _ => return None,
+        };
+        // The codes are ASCII, so this is a char boundary
+        let text = &reference[4..];
+        let target = if search_in == 'I' {
+            let uuid = match Uuid::parse_str(text) {
+                Ok(uuid) => uuid,
+                Err(_) => return None,
+            };
+            self.db
+                .entries
+                .iter()
+                .find(|e| e.try_borrow().map(|e| e.uuid == uuid).unwrap_or(false))
+                .cloned()
+        } else {
+            let text = text.to_lowercase();
+            self.db
+                .entries
+                .iter()
+                .find(|e| {
+                    field(e, search_in)
+                        .map(|mut v| v.unlocked().to_lowercase().contains(&text))
+                        .unwrap_or(false)
+                })
+                .cloned()
+        };
+        target.map(|target| (wanted, target))
+    }
+
+    fn date_time(&self, name: &str) -> Option<String> {
+        if name.starts_with("UTC_") {
+            format_date_time(&self.now.with_timezone(&UTC), &name[4..])
+        } else {
+            format_date_time(&self.now, name)
+        }
+    }
+}
+
+// The field of entry with code code, see expand. Fields which aren't
+// set are empty. None if code names no field or the entry is borrowed.
+fn field(entry: &Rc<RefCell<V1Entry>>, code: char) -> Option<SecureString> {
+    let mut entry = match entry.try_borrow_mut() {
+        Ok(entry) => entry,
+        Err(_) => return None,
+    };
+    let value = match code {
+        'T' => Some(entry.title.clone()),
+        'U' => Some(entry.username().map(|u| u.to_string()).unwrap_or(String::new())),
+        'P' => Some(entry.password().map(|p| p.to_string()).unwrap_or(String::new())),
+        'A' => Some(entry.url.clone().unwrap_or(String::new())),
+        'N' => Some(entry.markdown_notes().unwrap_or(String::new())),
+        'I' => Some(entry.uuid.to_simple_string().to_uppercase()),
+        _ => None,
+    };
+    value.map(SecureString::new)
+}
+
+fn format_date_time<T: Datelike + Timelike>(time: &T, name: &str) -> Option<String> {
+    Some(match name {
+        "SIMPLE" => {
+            format!("{:04}{:02}{:02}{:02}{:02}{:02}",
+                    time.year(),
+                    time.month(),
+                    time.day(),
+                    time.hour(),
+                    time.minute(),
+                    time.second())
+        }
+        "YEAR" => format!("{:04}", time.year()),
+        "MONTH" => format!("{:02}", time.month()),
+        "DAY" => format!("{:02}", time.day()),
+        "HOUR" => format!("{:02}", time.hour()),
+        "MINUTE" => format!("{:02}", time.minute()),
+        "SECOND" => format!("{:02}", time.second()),
+        _ => return None,
+    })
+}
+
+// A part of url for {URL:<part>}. Query includes the "?", a port which
+// isn't given is the default port of http, https and ftp.
+fn url_part(url: &str, part: &str) -> Option<String> {
+    let url = url.trim();
+    let (scheme, rest) = match url.find("://") {
+        Some(index) => (&url[..index], &url[index + 3..]),
+        None => ("", url),
+    };
+    if part == "RMVSCM" {
+        return Some(rest.to_string());
+    }
+    if part == "SCM" {
+        return Some(scheme.to_string());
+    }
+
+    let authority_end = rest.find(|c: char| c == '/' || c == '?' || c == '#').unwrap_or(rest.len());
+    let (authority, location) = rest.split_at(authority_end);
+    let (userinfo, host_port) = match authority.rfind('@') {
+        Some(index) => (&authority[..index], &authority[index + 1..]),
+        None => ("", authority),
+    };
+    let (host, port) = match host_port.rfind(':') {
+        Some(index) if !host_port.ends_with(']') => (&host_port[..index], &host_port[index + 1..]),
+        _ => (host_port, ""),
+    };
+    let location = match location.find('#') {
+        Some(index) => &location[..index],
+        None => location,
+    };
+    let (path, query) = match location.find('?') {
+        Some(index) => location.split_at(index),
+        None => (location, ""),
+    };
+    let mut credentials = userinfo.splitn(2, ':');
+
+    Some(match part {
+        "HOST" => host.to_string(),
+        "PORT" if port.is_empty() => {
+            match &scheme.to_lowercase()[..] {
+                "http" => "80",
+                "https" => "443",
+                "ftp" => "21",
+                _ => "",
+            }
+            .to_string()
+        }
+        "PORT" => port.to_string(),
+        "PATH" => path.to_string(),
+        "QUERY" => query.to_string(),
+        "USERINFO" => userinfo.to_string(),
+        "USERNAME" => credentials.next().unwrap_or("").to_string(),
+        "PASSWORD" => credentials.nth(1).unwrap_or("").to_string(),
+        _ => return None,
+    })
+}
@@ -0,0 +1,124 @@
+use std::str;
+
+use kpdb::v1error::V1KpdbError;
+
+/// Name of the meta stream which holds the unlock policy
+pub const UNLOCK_POLICY_STREAM: &'static str = "RKP_UNLOCK_POLICY";
+
+/// A kind of component of a CompositeKey
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum KeyFactor {
+    Password,
+    Keyfile,
+    /// Each challenge-response provider counts as one factor
+    ChallengeResponse,
+}
+
+impl KeyFactor {
+    fn name(&self) -> &'static str {
+        match *self {
+            KeyFactor::Password => "password",
+            KeyFactor::Keyfile => "keyfile",
+            KeyFactor::ChallengeResponse => "challenge-response",
+        }
+    }
+
+    fn from_name(name: &str) -> Result<KeyFactor, V1KpdbError> {
+        match name {
+            "password" => Ok(KeyFactor::Password),
+            "keyfile" => Ok(KeyFactor::Keyfile),
+            "challenge-response" => Ok(KeyFactor::ChallengeResponse),
+            _ => Err(V1KpdbError::MetaErr),
+        }
+    }
+}
+
+#[doc = "
+UnlockPolicy describes which factors are needed to open a database, e.g.
+password and keyfile and a hardware token. It's saved with the database
+as meta entry, so clients can show the user what to provide next time.
+
+required of factors have to be present. As KeePass 1.x derives the key
+from all components together, only policies which require all factors
+can be enforced. N-of-M policies are rejected by V1Kpdb::set_unlock_policy
+with PolicyErr as they would need secret sharing.
+"]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnlockPolicy {
+    /// Number of factors needed
+    pub required: usize,
+    /// The factors of the key
+    pub factors: Vec<KeyFactor>,
+}
+
+impl UnlockPolicy {
+    /// Require all of factors
+    pub fn all(factors: Vec<KeyFactor>) -> UnlockPolicy {
+        UnlockPolicy {
+            required: factors.len(),
+            factors: factors,
+        }
+    }
+
+    /// True if the policy requires all of its factors
+    pub fn is_enforceable(&self) -> bool {
+        !self.factors.is_empty() && self.required == self.factors.len()
+    }
+
+    /// Check that a key made of factors satisfies the policy. Returns
+    /// PolicyErr otherwise.
+    pub fn check(&self, factors: &[KeyFactor]) -> Result<(), V1KpdbError> {
+        if !self.is_enforceable() {
+            return Err(V1KpdbError::PolicyErr);
+        }
+        let mut wanted = self.factors.clone();
+        let mut given = factors.to_vec();
+        wanted.sort();
+        given.sort();
+        if wanted == given {
+            Ok(())
+        } else {
+            Err(V1KpdbError::PolicyErr)
+        }
+    }
+
+    // The stream is UTF-8 text:
+    // required=<n>
+    // factors=<name>,<name>,...
+    pub fn encode(&self) -> Vec<u8> {
+        let names: Vec<&str> = self.factors.iter().map(|f| f.name()).collect();
+        format!("required={}\nfactors={}\n", self.required, names.join(","))
+            .into_bytes()
+    }
+
+    pub fn decode(data: &[u8]) -> Result<UnlockPolicy, V1KpdbError> {
+        let text = try!(str::from_utf8(data).map_err(|_| V1KpdbError::MetaErr));
+        let mut required = None;
+        let mut factors = None;
+        for line in text.lines() {
+            let mut parts = line.splitn(2, '=');
+            match (parts.next(), parts.next()) {
+                (Some("required"), Some(n)) => {
+                    required = Some(try!(n.parse::<usize>().map_err(|_| V1KpdbError::MetaErr)))
+                }
+                (Some("factors"), Some(names)) => {
+                    let mut list = vec![];
+                    for name in names.split(',').filter(|n| !n.is_empty()) {
+                        list.push(try!(KeyFactor::from_name(name)));
+                    }
+                    factors = Some(list);
+                }
+                _ => return Err(V1KpdbError::MetaErr),
+            }
+        }
+        match (required, factors) {
+            (Some(required), Some(factors)) => {
+                Ok(UnlockPolicy {
+                    required: required,
+                    factors: factors,
+                })
+            }
+            _ => Err(V1KpdbError::MetaErr),
+        }
+    }
+}
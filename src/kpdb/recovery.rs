@@ -0,0 +1,72 @@
+use kpdb::v1error::V1KpdbError;
+
+/// Title of the group which gets the entries whose group was lost
+pub const RECOVERED_GROUP_TITLE: &'static str = "Recovered";
+
+/// Kind of a record in the database
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RecordKind {
+    Group,
+    Entry,
+}
+
+/// A group or entry which was dropped because one of its fields couldn't
+/// be read
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DroppedRecord {
+    pub kind: RecordKind,
+    /// Position among the groups or entries respectively
+    pub index: u32,
+    /// Byte offset of the record in the decrypted content
+    pub offset: usize,
+    /// Error of the first damaged field
+    pub error: V1KpdbError,
+}
+
+#[doc = "
+RecoveryReport describes what V1Kpdb::load_with_recovery had to drop or
+change to open a damaged database. Save the database under a new path
+after checking the salvaged data, the original file shouldn't be
+overwritten.
+"]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RecoveryReport {
+    /// The content hash and the padding were correct, i.e. the file
+    /// wasn't damaged on disk
+    pub content_intact: bool,
+    /// The content ended in the middle of a record
+    pub truncated: bool,
+    /// Records with damaged fields
+    pub dropped: Vec<DroppedRecord>,
+    /// Groups which were never reached because the content ended before
+    pub missing_groups: u32,
+    /// Entries which were never reached because the content ended before
+    pub missing_entries: u32,
+    /// Meta entries whose stream couldn't be decoded
+    pub dropped_meta_entries: usize,
+    /// Groups whose level didn't fit into the tree and was lowered
+    pub repaired_levels: usize,
+    /// Entries whose group was lost and which were moved to the group
+    /// RECOVERED_GROUP_TITLE
+    pub orphaned_entries: usize,
+}
+
+impl RecoveryReport {
+    pub fn new() -> RecoveryReport {
+        RecoveryReport {
+            content_intact: true,
+            truncated: false,
+            dropped: vec![],
+            missing_groups: 0,
+            missing_entries: 0,
+            dropped_meta_entries: 0,
+            repaired_levels: 0,
+            orphaned_entries: 0,
+        }
+    }
+
+    /// True if nothing had to be dropped or changed
+    pub fn is_clean(&self) -> bool {
+        *self == RecoveryReport::new()
+    }
+}
@@ -0,0 +1,80 @@
+use chrono::{DateTime, Local};
+
+use sec_str::SecureString;
+
+/// First line of the protected notes section which holds the recovery
+/// codes of an entry
+pub const RECOVERY_CODES_HEADER: &'static str = "KPEX_RECOVERY_CODES";
+
+// Used codes are kept as "<code> used <RFC 3339 time>"
+const USED_MARKER: &'static str = " used ";
+
+#[doc = "
+A one-time recovery code, e.g. a backup code for two-factor
+authentication. The codes of an entry are kept in a protected section of
+its notes, one per line, see V1Entry::recovery_codes.
+"]
+pub struct RecoveryCode {
+    pub code: SecureString,
+    /// When the code was used, None if it's still valid
+    pub used: Option<DateTime<Local>>,
+}
+
+impl RecoveryCode {
+    /// An unused code
+    pub fn new(code: SecureString) -> RecoveryCode {
+        RecoveryCode {
+            code: code,
+            used: None,
+        }
+    }
+
+    pub fn is_used(&self) -> bool {
+        self.used.is_some()
+    }
+}
+
+/// Read the codes of a protected notes section. None if section doesn't
+/// hold recovery codes.
+pub fn decode_codes(section: &mut SecureString) -> Option<Vec<RecoveryCode>> {
+    let content = section.unlocked();
+    let mut lines = content.lines();
+    if lines.next().map(|l| l.trim()) != Some(RECOVERY_CODES_HEADER) {
+        return None;
+    }
+    let mut codes = vec![];
+    for line in lines.map(|l| l.trim()).filter(|l| !l.is_empty()) {
+        let (code, used) = match line.find(USED_MARKER) {
+            Some(i) => {
+                let time = DateTime::parse_from_rfc3339(line[i + USED_MARKER.len()..].trim())
+                               .ok()
+                               .map(|t| t.with_timezone(&Local));
+                (&line[..i], time)
+            }
+            None => (line, None),
+        };
+        codes.push(RecoveryCode {
+            code: SecureString::new(code.to_string()),
+            used: used,
+        });
+    }
+    Some(codes)
+}
+
+/// The protected notes section holding codes
+pub fn encode_codes(codes: &mut [RecoveryCode]) -> SecureString {
+    // Allocated once, so no partial copies of the codes are left behind
+    let capacity = codes.iter().fold(RECOVERY_CODES_HEADER.len(),
+                                     |n, c| n + c.code.string.len() + USED_MARKER.len() + 40);
+    let mut section = String::with_capacity(capacity);
+    section.push_str(RECOVERY_CODES_HEADER);
+    for code in codes.iter_mut() {
+        section.push('\n');
+        section.push_str(&code.code.unlocked());
+        if let Some(used) = code.used {
+            section.push_str(USED_MARKER);
+            section.push_str(&used.to_rfc3339());
+        }
+    }
+    SecureString::new(section)
+}
@@ -0,0 +1,154 @@
+use std::cell::RefCell;
+use std::rc::{Rc, Weak};
+use std::time::{Duration, Instant};
+
+use mem_protect;
+
+/// A protected field of an entry which can be revealed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProtectedField {
+    Username,
+    Password,
+    /// The protected notes section with this index, see
+    /// V1Entry::protected_notes
+    Notes(usize),
+}
+
+// The plain text shared by a guard and the tracker of its database. None
+// once it's wiped.
+type Plaintext = Rc<RefCell<Option<String>>>;
+
+fn wipe(plaintext: &Plaintext) {
+    if let Some(text) = plaintext.borrow_mut().take() {
+        unsafe {
+            mem_protect::zero(&text);
+        }
+        mem_protect::unlock(&text);
+    }
+}
+
+#[doc = "
+Revealed holds the plain text of a protected field for a limited time, see
+V1Entry::reveal. The plain text is a locked copy which is overwritten with
+zeroes when the time to live has passed, when the guard is dropped or when
+V1Kpdb::wipe_reveals is called, whatever comes first. The protected field
+itself stays encrypted.
+
+There's no timer thread, so an expired guard is wiped the next time it's
+accessed. Applications which need the bound to hold without accessing the
+guard call V1Kpdb::wipe_expired_reveals regularly.
+"]
+pub struct Revealed {
+    plaintext: Plaintext,
+    expires: Instant,
+}
+
+impl Revealed {
+    /// Take ownership of plaintext and lock it. It's wiped after ttl.
+    pub fn new(plaintext: String, ttl: Duration) -> Revealed {
+        mem_protect::lock(&plaintext, "Revealed");
+        Revealed {
+            plaintext: Rc::new(RefCell::new(Some(plaintext))),
+            expires: Instant::now() + ttl,
+        }
+    }
+
+    /// Call f with the plain text. None if the guard expired or was
+    /// wiped, the plain text is gone then.
+    pub fn with<F, T>(&self, f: F) -> Option<T>
+        where F: FnOnce(&str) -> T
+    {
+        if self.is_expired() {
+            wipe(&self.plaintext);
+        }
+        self.plaintext.borrow().as_ref().map(|text| f(text))
+    }
+
+    pub fn is_expired(&self) -> bool {
+        Instant::now() >= self.expires
+    }
+
+    /// True as long as the plain text is still available
+    pub fn is_revealed(&self) -> bool {
+        !self.is_expired() && self.plaintext.borrow().is_some()
+    }
+
+    /// Time left until the plain text is wiped
+    pub fn remaining(&self) -> Duration {
+        let now = Instant::now();
+        if now >= self.expires {
+            Duration::new(0, 0)
+        } else {
+            self.expires - now
+        }
+    }
+
+    /// Wipe the plain text now
+    pub fn wipe(&self) {
+        wipe(&self.plaintext);
+    }
+}
+
+impl Drop for Revealed {
+    fn drop(&mut self) {
+        wipe(&self.plaintext);
+    }
+}
+
+/// Guards handed out by a database which may still hold plain text
+pub struct RevealTracker {
+    guards: Vec<(Weak<RefCell<Option<String>>>, Instant)>,
+}
+
+impl RevealTracker {
+    pub fn new() -> RevealTracker {
+        RevealTracker { guards: vec![] }
+    }
+
+    pub fn track(&mut self, revealed: &Revealed) {
+        self.forget_wiped();
+        self.guards.push((Rc::downgrade(&revealed.plaintext), revealed.expires));
+    }
+
+    /// Number of guards which still hold plain text, expired guards
+    /// which weren't accessed since included
+    pub fn outstanding(&mut self) -> usize {
+        self.forget_wiped();
+        self.guards.len()
+    }
+
+    /// Wipe the guards which expired, returns how many
+    pub fn wipe_expired(&mut self) -> usize {
+        let now = Instant::now();
+        let mut wiped = 0;
+        for &(ref guard, expires) in self.guards.iter() {
+            if expires <= now {
+                if let Some(plaintext) = guard.upgrade() {
+                    wipe(&plaintext);
+                    wiped += 1;
+                }
+            }
+        }
+        self.forget_wiped();
+        wiped
+    }
+
+    /// Wipe all guards, returns how many held plain text
+    pub fn wipe_all(&mut self) -> usize {
+        let wiped = self.outstanding();
+        for &(ref guard, _) in self.guards.iter() {
+            if let Some(plaintext) = guard.upgrade() {
+                wipe(&plaintext);
+            }
+        }
+        self.guards.clear();
+        wiped
+    }
+
+    // Dropped and wiped guards don't hold plain text anymore
+    fn forget_wiped(&mut self) {
+        self.guards.retain(|&(ref guard, _)| {
+            guard.upgrade().map_or(false, |plaintext| plaintext.borrow().is_some())
+        });
+    }
+}
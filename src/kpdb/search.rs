@@ -0,0 +1,218 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use regex::Regex;
+
+use kpdb::v1entry::V1Entry;
+use kpdb::v1error::V1KpdbError;
+use kpdb::v1group::V1Group;
+
+// Title of the group KeePass 1.x uses as a trash can
+pub const BACKUP_GROUP_TITLE: &'static str = "Backup";
+
+// Title of the group V1Kpdb::archive_entry moves entries into
+pub const ARCHIVE_GROUP_TITLE: &'static str = "Archive";
+
+/// Key in the custom data of a group which excludes the group and its
+/// subgroups from search if set to "true"
+pub const EXCLUDE_FROM_SEARCH: &'static str = "ExcludeFromSearch";
+
+#[doc = "
+SearchQuery describes which entries V1Kpdb::search should return.
+Create one with SearchQuery::new and adjust the public fields, e.g.
+to search only in titles with a case sensitive regex:
+
+```ignore
+let mut query = SearchQuery::new(\"^Mail.*\".to_string());
+query.username = false;
+query.url = false;
+query.notes = false;
+query.regex = true;
+query.case_sensitive = true;
+let entries = try!(db.search(&query));
+```
+"]
+pub struct SearchQuery {
+    /// The text to search for. Interpreted as a regular expression
+    /// if regex is set.
+    pub text: String,
+    /// Match on the title of the entry
+    pub title: bool,
+    /// Match on the username of the entry
+    pub username: bool,
+    /// Match on the URL and the additional URLs of the entry
+    pub url: bool,
+    /// Match on the comment of the entry
+    pub notes: bool,
+    /// Match on the name of the attachment of the entry
+    pub attachment_names: bool,
+    /// Match case sensitive
+    pub case_sensitive: bool,
+    /// Interpret text as a regular expression instead of a substring
+    pub regex: bool,
+    /// Also return entries inside the Backup group. KeePass 1.x keeps
+    /// the old revisions of entries there, so this searches the history
+    /// as well.
+    pub include_backup: bool,
+    /// Also return entries inside groups excluded from search, e.g.
+    /// the Archive group
+    pub include_excluded: bool,
+    /// Ids of further groups to leave out, including their subgroups
+    pub excluded_groups: Vec<u32>,
+}
+
+impl SearchQuery {
+    /// Create a case insensitive substring search over title, username,
+    /// URL and comment which excludes the Backup group (as KeePass does)
+    /// and groups excluded from search.
+    pub fn new(text: String) -> SearchQuery {
+        SearchQuery {
+            text: text,
+            title: true,
+            username: true,
+            url: true,
+            notes: true,
+            attachment_names: false,
+            case_sensitive: false,
+            regex: false,
+            include_backup: false,
+            include_excluded: false,
+            excluded_groups: vec![],
+        }
+    }
+
+    // Build the matcher once per search instead of once per field
+    pub fn matcher(&self) -> Result<Matcher, V1KpdbError> {
+        if self.regex {
+            let pattern = if self.case_sensitive {
+                self.text.clone()
+            } else {
+                format!("(?i){}", self.text)
+            };
+            let regex = try!(Regex::new(&pattern).map_err(|_| V1KpdbError::RegexErr));
+            Ok(Matcher::Pattern(regex))
+        } else if self.case_sensitive {
+            Ok(Matcher::Substring(self.text.clone(), true))
+        } else {
+            Ok(Matcher::Substring(self.text.to_lowercase(), false))
+        }
+    }
+
+    // Check whether entry matches the query. entry is mutable as the
+    // username has to be unlocked for matching. It's deleted right after.
+    pub fn matches_entry(&self, matcher: &Matcher, entry: &mut V1Entry) -> bool {
+        if !self.include_backup && is_in_backup_group(entry) {
+            return false;
+        }
+        if !self.include_excluded && is_in_excluded_group(entry) {
+            return false;
+        }
+        if !self.excluded_groups.is_empty() && self.is_in_excluded_groups(entry) {
+            return false;
+        }
+
+        if self.title && matcher.is_match(&entry.title) {
+            return true;
+        }
+        if self.url && entry.urls().iter().any(|u| matcher.is_match(u)) {
+            return true;
+        }
+        if self.notes {
+            if let Some(ref comment) = entry.comment {
+                if matcher.is_match(comment) {
+                    return true;
+                }
+            }
+        }
+        if self.attachment_names {
+            if let Some(ref binary_desc) = entry.binary_desc {
+                if matcher.is_match(binary_desc) {
+                    return true;
+                }
+            }
+        }
+        if self.username {
+            if let Some(ref mut username) = entry.username {
+                username.unlock();
+                let is_match = matcher.is_match(&username.string);
+                username.delete();
+                if is_match {
+                    return true;
+                }
+            }
+        }
+        false
+    }
+
+    fn is_in_excluded_groups(&self, entry: &V1Entry) -> bool {
+        let mut current = entry.group.clone();
+        while let Some(group) = current {
+            if self.excluded_groups.contains(&group.borrow().id) {
+                return true;
+            }
+            current = group.borrow().parent.clone();
+        }
+        false
+    }
+}
+
+// Compiled form of a SearchQuery's text
+pub enum Matcher {
+    // needle, case_sensitive. needle is already lowercased if
+    // case_sensitive is false
+    Substring(String, bool),
+    Pattern(Regex),
+}
+
+impl Matcher {
+    pub fn is_match(&self, haystack: &str) -> bool {
+        match *self {
+            Matcher::Substring(ref needle, true) => haystack.contains(&needle[..]),
+            Matcher::Substring(ref needle, false) => {
+                haystack.to_lowercase().contains(&needle[..])
+            }
+            Matcher::Pattern(ref regex) => regex.is_match(haystack),
+        }
+    }
+}
+
+/// Check if group is the Backup group or one of its subgroups
+pub fn is_backup_group(group: &Rc<RefCell<V1Group>>) -> bool {
+    let mut current = Some(group.clone());
+    while let Some(g) = current {
+        if g.borrow().level == 0 && g.borrow().title == BACKUP_GROUP_TITLE &&
+           g.borrow().parent.is_some() {
+            return true;
+        }
+        current = g.borrow().parent.clone();
+    }
+    false
+}
+
+/// Check if entry lies inside the Backup group
+pub fn is_in_backup_group(entry: &V1Entry) -> bool {
+    match entry.group {
+        Some(ref group) => is_backup_group(group),
+        None => false,
+    }
+}
+
+/// Check if group or one of its parents is excluded from search
+pub fn is_excluded_from_search(group: &Rc<RefCell<V1Group>>) -> bool {
+    let mut current = Some(group.clone());
+    while let Some(g) = current {
+        if g.borrow().custom_data.get(EXCLUDE_FROM_SEARCH).map(|v| &v[..]) == Some("true") {
+            return true;
+        }
+        current = g.borrow().parent.clone();
+    }
+    false
+}
+
+/// Check if entry lies inside a group excluded from search
+pub fn is_in_excluded_group(entry: &V1Entry) -> bool {
+    match entry.group {
+        Some(ref group) => is_excluded_from_search(group),
+        None => false,
+    }
+}
@@ -0,0 +1,35 @@
+use std::cell::RefCell;
+
+use chrono::{DateTime, Local};
+use uuid::Uuid;
+
+#[doc = "
+SecretAccess tells a callback whenever the password of an entry is read,
+see V1Kpdb::on_secret_access. A database shares one SecretAccess with
+all its entries, so the callback can be set or replaced at any time.
+"]
+pub struct SecretAccess {
+    callback: RefCell<Option<Box<FnMut(&Uuid, DateTime<Local>)>>>,
+}
+
+impl SecretAccess {
+    /// Create one without a callback
+    pub fn new() -> SecretAccess {
+        SecretAccess { callback: RefCell::new(None) }
+    }
+
+    /// Replace the callback, None to stop reporting
+    pub fn set_callback(&self, callback: Option<Box<FnMut(&Uuid, DateTime<Local>)>>) {
+        *self.callback.borrow_mut() = callback;
+    }
+
+    /// Report a read of the password of the entry with uuid now. Reads
+    /// by the callback itself aren't reported.
+    pub fn notify(&self, uuid: &Uuid) {
+        if let Ok(mut callback) = self.callback.try_borrow_mut() {
+            if let Some(ref mut callback) = *callback {
+                callback(uuid, Local::now());
+            }
+        }
+    }
+}
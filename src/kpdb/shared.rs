@@ -0,0 +1,175 @@
+use std::sync::{Arc, Mutex};
+use std::sync::mpsc::{channel, RecvTimeoutError, Sender};
+use std::thread;
+use std::time::Duration;
+
+use kpdb::autolock::AutoLock;
+use kpdb::crypter::CompositeKey;
+use kpdb::v1error::V1KpdbError;
+use kpdb::v1kpdb::V1Kpdb;
+
+// A closure run on the thread of the database. FnOnce can't be called
+// through a Box, so it's wrapped in an Option and taken on the call.
+type Job = Box<FnMut(&mut AutoLock) + Send>;
+
+#[doc = "
+SharedKpdb makes a V1Kpdb usable from several threads, e.g. for a
+background auto-save or a daemon serving requests. Groups and entries
+are linked by Rc<RefCell<...>>, so a V1Kpdb can't leave the thread it
+was created on. Instead the database lives on a thread of its own and
+SharedKpdb sends closures there, which run one after another with
+exclusive access. SharedKpdb is Send, Sync and cheap to clone.
+
+Neither groups nor entries can be passed out of a closure, return the
+data needed or use EntryHandle and GroupHandle to refer to them.
+
+The thread ends when the last SharedKpdb is dropped. If a closure
+panics the database is dropped with the thread and every later call is
+a ThreadErr.
+
+With auto_lock_after the thread locks the database once it wasn't used
+for a while and has no unsaved changes, see AutoLock. Calls are a
+LockedErr then until unlock.
+
+```ignore
+let shared = try!(SharedKpdb::spawn(|| {
+    let mut db = try!(V1Kpdb::new(path, Some(password), None));
+    try!(db.load());
+    Ok(db)
+}));
+let saver = shared.clone();
+thread::spawn(move || saver.with(|db| db.save(None, None, None)));
+let titles = try!(shared.with(|db| {
+    db.entries.iter().map(|e| e.borrow().title.clone()).collect::<Vec<_>>()
+}));
+```
+"]
+#[derive(Clone)]
+pub struct SharedKpdb {
+    // Sender isn't Sync
+    sender: Arc<Mutex<Sender<Job>>>,
+}
+
+impl SharedKpdb {
+    /// Start the thread of the database. open creates the database on
+    /// that thread, e.g. by loading it. Its error is returned.
+    pub fn spawn<F>(open: F) -> Result<SharedKpdb, V1KpdbError>
+        where F: FnOnce() -> Result<V1Kpdb, V1KpdbError> + Send + 'static
+    {
+        let (sender, receiver) = channel::<Job>();
+        let (opened, open_result) = channel();
+        try!(thread::Builder::new()
+                 .name("kpdb".to_string())
+                 .spawn(move || {
+                     let mut lock = match open() {
+                         Ok(db) => {
+                             let _ = opened.send(Ok(()));
+                             AutoLock::new(db, None)
+                         }
+                         Err(e) => {
+                             let _ = opened.send(Err(e));
+                             return;
+                         }
+                     };
+                     loop {
+                         // Wake up to lock the database when it's due
+                         let job = match lock.time_left() {
+                             Some(left) => receiver.recv_timeout(left),
+                             None => {
+                                 receiver.recv().map_err(|_| RecvTimeoutError::Disconnected)
+                             }
+                         };
+                         match job {
+                             Ok(mut job) => job(&mut lock),
+                             Err(RecvTimeoutError::Timeout) => {
+                                 lock.tick();
+                             }
+                             Err(RecvTimeoutError::Disconnected) => break,
+                         }
+                     }
+                 })
+                 .map_err(|_| V1KpdbError::ThreadErr));
+        try!(try!(open_result.recv().map_err(|_| V1KpdbError::ThreadErr)));
+        Ok(SharedKpdb { sender: Arc::new(Mutex::new(sender)) })
+    }
+
+    /// Run f with the database and return its result. Blocks until the
+    /// closures sent before are done. ThreadErr if the thread of the
+    /// database is gone, LockedErr if the database is locked.
+    pub fn with<F, R>(&self, f: F) -> Result<R, V1KpdbError>
+        where F: FnOnce(&mut V1Kpdb) -> R + Send + 'static,
+              R: Send + 'static
+    {
+        try!(self.run(move |lock| lock.with(f)))
+    }
+
+    /// Like with but doesn't wait for f, e.g. to save in the background.
+    /// Errors of f are lost then, so is f if the database is locked.
+    pub fn post<F>(&self, f: F) -> Result<(), V1KpdbError>
+        where F: FnOnce(&mut V1Kpdb) + Send + 'static
+    {
+        let mut f = Some(f);
+        self.send(Box::new(move |lock: &mut AutoLock| {
+            if let Some(f) = f.take() {
+                let _ = lock.with(f);
+            }
+        }))
+    }
+
+    /// Lock the database after timeout without a call of with or post,
+    /// None to never lock it. Locking drops the key as well if
+    /// forget_key is true, see AutoLock.
+    pub fn auto_lock_after(&self,
+                           timeout: Option<Duration>,
+                           forget_key: bool)
+                           -> Result<(), V1KpdbError> {
+        self.run(move |lock| {
+            lock.timeout = timeout;
+            lock.forget_key = forget_key;
+        })
+    }
+
+    /// Lock the database now. UnsavedErr if it has unsaved changes, see
+    /// AutoLock::lock.
+    pub fn lock(&self) -> Result<(), V1KpdbError> {
+        try!(self.run(|lock| lock.lock()))
+    }
+
+    /// Unlock the database with the kept key, see AutoLock::unlock. If
+    /// the key was dropped use unlock_with.
+    pub fn unlock(&self) -> Result<(), V1KpdbError> {
+        try!(self.run(|lock| lock.unlock()))
+    }
+
+    /// Unlock the database with the key key creates. It's called on the
+    /// thread of the database, as keys can't be sent between threads.
+    pub fn unlock_with<F>(&self, key: F) -> Result<(), V1KpdbError>
+        where F: FnOnce() -> CompositeKey + Send + 'static
+    {
+        try!(self.run(move |lock| lock.unlock_with(key())))
+    }
+
+    pub fn is_locked(&self) -> Result<bool, V1KpdbError> {
+        self.run(|lock| lock.is_locked())
+    }
+
+    // Run f on the thread of the database and wait for its result
+    fn run<F, R>(&self, f: F) -> Result<R, V1KpdbError>
+        where F: FnOnce(&mut AutoLock) -> R + Send + 'static,
+              R: Send + 'static
+    {
+        let (result_sender, result) = channel();
+        let mut f = Some(f);
+        try!(self.send(Box::new(move |lock: &mut AutoLock| {
+            if let Some(f) = f.take() {
+                let _ = result_sender.send(f(lock));
+            }
+        })));
+        result.recv().map_err(|_| V1KpdbError::ThreadErr)
+    }
+
+    fn send(&self, job: Job) -> Result<(), V1KpdbError> {
+        let sender = try!(self.sender.lock().map_err(|_| V1KpdbError::ThreadErr));
+        sender.send(job).map_err(|_| V1KpdbError::ThreadErr)
+    }
+}
@@ -0,0 +1,54 @@
+use chrono::{DateTime, Local};
+
+#[doc = "
+DbStats is the result of V1Kpdb::stats, e.g. for a dashboard or to check
+a database after an import or a merge. Groups and entries in the Backup
+group are counted as well, except for the expired entries.
+"]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DbStats {
+    /// Number of groups
+    pub num_groups: usize,
+    /// Number of entries
+    pub num_entries: usize,
+    /// Number of levels of the group tree, 0 if there are no groups
+    pub depth: usize,
+    /// Number of entries with an attachment and the size of their
+    /// content in bytes
+    pub num_attachments: usize,
+    pub attachment_size: usize,
+    /// Earliest and latest modification time of groups and entries,
+    /// None if there are neither
+    pub oldest_mod: Option<DateTime<Local>>,
+    pub newest_mod: Option<DateTime<Local>>,
+    /// Number of entries which have expired, as in
+    /// V1Kpdb::expired_entries
+    pub num_expired: usize,
+}
+
+impl DbStats {
+    /// Create the statistics of an empty database
+    pub fn new() -> DbStats {
+        DbStats {
+            num_groups: 0,
+            num_entries: 0,
+            depth: 0,
+            num_attachments: 0,
+            attachment_size: 0,
+            oldest_mod: None,
+            newest_mod: None,
+            num_expired: 0,
+        }
+    }
+
+    // Widen the range of modification times to include last_mod
+    #[doc(hidden)]
+    pub fn add_mod(&mut self, last_mod: DateTime<Local>) {
+        if self.oldest_mod.map_or(true, |oldest| last_mod < oldest) {
+            self.oldest_mod = Some(last_mod);
+        }
+        if self.newest_mod.map_or(true, |newest| last_mod > newest) {
+            self.newest_mod = Some(last_mod);
+        }
+    }
+}
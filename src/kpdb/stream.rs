@@ -0,0 +1,200 @@
+use std::cmp;
+use std::io::{self, Read, Write};
+
+use openssl::crypto::hash::{Hasher, Type};
+use openssl::crypto::symm;
+
+use kpdb::crypter::constant_time_eq;
+use kpdb::v1error::V1KpdbError;
+use kpdb::v1header::V1Header;
+use mem_protect;
+
+// Ciphertext decrypted at once, a multiple of the AES block size
+const CHUNK_SIZE: usize = 64 * 1024;
+
+#[doc = "
+DecryptReader decrypts the content of a database while it's read, see
+Crypter::decrypt_stream and V1Kpdb::load_streaming. Only one chunk of
+the plaintext is held at a time, in locked memory, instead of the whole
+content. The padding is removed from the last block and the content
+hash is computed on the way.
+
+Nothing read is trustworthy before finish returned Ok. A wrong key or
+a damaged file show up there or as read errors, which carry the
+V1KpdbError, e.g. DecryptErr for an invalid padding.
+"]
+pub struct DecryptReader<R: Read> {
+    source: R,
+    crypter: symm::Crypter,
+    hasher: Hasher,
+    content_hash: Vec<u8>,
+    // Ciphertext which wasn't read yet
+    remaining: u64,
+    // The decrypted chunk, buffer[pos..end] wasn't read yet
+    buffer: Vec<u8>,
+    pos: usize,
+    end: usize,
+    // The first error, later reads fail with it as well
+    error: Option<V1KpdbError>,
+}
+
+impl<R: Read> DecryptReader<R> {
+    // Read len bytes of ciphertext from source
+    //
+    // Sensitive data in this function:
+    // * finalkey (locked: transform_key)
+    // * buffer (locked)
+    //
+    // At the end of this function:
+    // * finalkey is zeroed out, the cipher keeps its own copy
+    pub fn new(header: &V1Header,
+               source: R,
+               len: u64,
+               finalkey: Vec<u8>)
+               -> Result<DecryptReader<R>, V1KpdbError> {
+        // The padding is checked in refill, OpenSSL would just drop the
+        // last block if it's wrong
+        let crypter = symm::Crypter::new(symm::Type::AES_256_CBC);
+        crypter.pad(false);
+        crypter.init(symm::Mode::Decrypt, &finalkey, header.iv.clone());
+        unsafe {
+            mem_protect::zero(&finalkey);
+            mem_protect::unlock(&finalkey);
+        }
+        // At least the padding is needed
+        if len == 0 || len % 16 != 0 {
+            return Err(V1KpdbError::DecryptErr);
+        }
+
+        let buffer = vec![0u8; CHUNK_SIZE];
+        mem_protect::lock(&buffer, "decrypted_chunk");
+        Ok(DecryptReader {
+            source: source,
+            crypter: crypter,
+            hasher: Hasher::new(Type::SHA256),
+            content_hash: header.content_hash.clone(),
+            remaining: len,
+            buffer: buffer,
+            pos: 0,
+            end: 0,
+            error: None,
+        })
+    }
+
+    /// Read the rest of the content and check the content hash. HashErr
+    /// if it doesn't match, i.e. the key is wrong or the file damaged.
+    pub fn finish(mut self) -> Result<(), V1KpdbError> {
+        if let Some(e) = self.error {
+            return Err(e);
+        }
+        while self.remaining > 0 {
+            try!(self.refill());
+        }
+        let content_hash = self.hasher.finish();
+        if !constant_time_eq(&content_hash, &self.content_hash) {
+            return Err(V1KpdbError::HashErr);
+        }
+        Ok(())
+    }
+
+    // Decrypt the next chunk into buffer
+    //
+    // Sensitive data in this function:
+    // * chunk (locked)
+    //
+    // At the end of this function:
+    // * chunk is copied into buffer and zeroed out
+    fn refill(&mut self) -> Result<(), V1KpdbError> {
+        let size = cmp::min(CHUNK_SIZE as u64, self.remaining) as usize;
+        let mut ciphertext = vec![0u8; size];
+        try!(self.source.read_exact(&mut ciphertext).map_err(|_| V1KpdbError::ReadErr));
+        self.remaining -= size as u64;
+
+        let mut chunk = self.crypter.update(&ciphertext);
+        if self.remaining == 0 {
+            chunk.extend(self.crypter.finalize());
+        }
+        mem_protect::lock(&chunk, "chunk");
+        let fits = chunk.len() <= self.buffer.len();
+        if fits {
+            self.buffer[..chunk.len()].copy_from_slice(&chunk);
+        }
+        self.pos = 0;
+        self.end = if fits {
+            chunk.len()
+        } else {
+            0
+        };
+        unsafe {
+            mem_protect::zero(&chunk);
+        }
+        mem_protect::unlock(&chunk);
+        if !fits {
+            return Err(V1KpdbError::DecryptErr);
+        }
+
+        if self.remaining == 0 {
+            try!(self.strip_padding());
+        }
+        self.hasher
+            .write_all(&self.buffer[..self.end])
+            .map_err(|_| V1KpdbError::DecryptErr)
+    }
+
+    // Remove the PKCS#7 padding from the last chunk, see
+    // Crypter::strip_padding
+    fn strip_padding(&mut self) -> Result<(), V1KpdbError> {
+        let padding = match self.end {
+            0 => return Err(V1KpdbError::DecryptErr),
+            end => self.buffer[end - 1] as usize,
+        };
+        if padding == 0 || padding > 16 || padding > self.end ||
+           self.buffer[self.end - padding..self.end].iter().any(|b| *b as usize != padding) {
+            return Err(V1KpdbError::DecryptErr);
+        }
+        self.end -= padding;
+        Ok(())
+    }
+}
+
+impl<R: Read> Read for DecryptReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if let Some(e) = self.error {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, e));
+        }
+        while self.pos == self.end {
+            if self.remaining == 0 {
+                return Ok(0);
+            }
+            if let Err(e) = self.refill() {
+                self.error = Some(e);
+                return Err(io::Error::new(io::ErrorKind::InvalidData, e));
+            }
+        }
+        let len = cmp::min(buf.len(), self.end - self.pos);
+        buf[..len].copy_from_slice(&self.buffer[self.pos..self.pos + len]);
+        self.pos += len;
+        Ok(len)
+    }
+}
+
+impl<R: Read> Drop for DecryptReader<R> {
+    fn drop(&mut self) {
+        unsafe {
+            mem_protect::zero(&self.buffer);
+        }
+        mem_protect::unlock(&self.buffer);
+    }
+}
+
+/// The V1KpdbError of a read error of a DecryptReader. Other errors are
+/// ReadErr, the end of the content is OffsetErr.
+pub fn read_error(e: io::Error) -> V1KpdbError {
+    if let Some(e) = e.get_ref().and_then(|e| e.downcast_ref::<V1KpdbError>()) {
+        return *e;
+    }
+    match e.kind() {
+        io::ErrorKind::UnexpectedEof => V1KpdbError::OffsetErr,
+        _ => V1KpdbError::ReadErr,
+    }
+}
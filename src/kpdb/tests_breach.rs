@@ -0,0 +1,123 @@
+use std::env;
+use std::fs::{self, File};
+use std::io::Write;
+
+use openssl::crypto::hash::{Hasher, Type};
+use rustc_serialize::hex::ToHex;
+
+use kpdb::audit::hibp::{self, HibpQuery};
+use kpdb::breach::{BreachHash, BreachList};
+use kpdb::v1error::V1KpdbError;
+use kpdb::v1kpdb::V1Kpdb;
+use sec_str::SecureString;
+
+fn tmp_path(name: &str) -> String {
+    let mut path = env::temp_dir();
+    path.push(name);
+    path.to_str().unwrap().to_string()
+}
+
+fn sha1_hex(data: &str) -> String {
+    let mut hasher = Hasher::new(Type::SHA1);
+    let _ = hasher.write_all(data.as_bytes());
+    hasher.finish().to_hex().to_uppercase()
+}
+
+// Write a list ordered by hash as in the real dumps. password appears
+// 42 times, the others once.
+fn write_sha1_list(path: &str) {
+    let mut lines: Vec<String> = (0..2000).map(|i| format!("{}:1", sha1_hex(&i.to_string())))
+                                          .collect();
+    lines.push(format!("{}:42", sha1_hex("password")));
+    lines.sort();
+    let mut file = File::create(path).unwrap();
+    for line in lines {
+        let _ = write!(file, "{}\r\n", line);
+    }
+}
+
+fn lookup(list: &mut BreachList, password: &str) -> u64 {
+    list.lookup(&mut SecureString::new(password.to_string())).ok().unwrap()
+}
+
+#[test]
+fn test_sha1_lookup() {
+    let path = tmp_path("rust_keepass_test_breach_sha1.txt");
+    write_sha1_list(&path);
+    let mut list = BreachList::open(&path, BreachHash::Sha1).ok().unwrap();
+    assert_eq!(lookup(&mut list, "password"), 42);
+    for i in &[0, 1, 999, 1999] {
+        assert_eq!(lookup(&mut list, &i.to_string()), 1);
+    }
+    assert_eq!(lookup(&mut list, "2000"), 0);
+    assert_eq!(lookup(&mut list, "correct horse battery staple"), 0);
+    let _ = fs::remove_file(&path);
+}
+
+#[test]
+fn test_ntlm_lookup() {
+    let path = tmp_path("rust_keepass_test_breach_ntlm.txt");
+    {
+        let mut file = File::create(&path).unwrap();
+        let _ = write!(file,
+                       "31D6CFE0D16AE931B73C59D7E0C089C0:7\r\n\
+                        8846F7EAEE8FB117AD06BDD830B7586C:3");
+    }
+    let mut list = BreachList::open(&path, BreachHash::Ntlm).ok().unwrap();
+    assert_eq!(lookup(&mut list, "password"), 3);
+    assert_eq!(lookup(&mut list, "test"), 0);
+    let _ = fs::remove_file(&path);
+}
+
+#[test]
+fn test_breached_entries() {
+    let path = tmp_path("rust_keepass_test_breach_db.txt");
+    write_sha1_list(&path);
+    let mut db = V1Kpdb::new("test/test_password.kdb".to_string(),
+                             Some("test".to_string()),
+                             None)
+                     .ok()
+                     .unwrap();
+    assert!(db.load().is_ok());
+    let group = db.groups[0].clone();
+    let entry = db.create_entry(group,
+                                "Weak".to_string(),
+                                None,
+                                None,
+                                None,
+                                None,
+                                None,
+                                Some("password".to_string()));
+
+    let mut list = BreachList::open(&path, BreachHash::Sha1).ok().unwrap();
+    let breached = db.breached_entries(&mut list).ok().unwrap();
+    assert_eq!(breached.len(), 1);
+    assert!(breached[0].0 == entry);
+    assert_eq!(breached[0].1, 42);
+    let _ = fs::remove_file(&path);
+}
+
+#[test]
+fn test_hibp() {
+    let mut password = SecureString::new("password".to_string());
+    let mut query = HibpQuery::new(&mut password).unwrap();
+    assert_eq!(query.prefix(), "5BAA6");
+    assert_eq!(query.range_url(), "https://api.pwnedpasswords.com/range/5BAA6");
+    assert_eq!(password.string, "\0\0\0\0\0\0\0\0");
+    let response = "003D68EB55068C33ACE09247EE4C639306B:3\r\n\
+                    1E4C9B93F3F0682250B6CF8331B7EE68FD8:9545824\r\n\
+                    1E4C9B93F3F0682250B6CF8331B7EE68FD9:0\r\n";
+    assert_eq!(query.count(response), 9545824);
+    assert_eq!(query.count(&response.to_lowercase()), 9545824);
+    assert_eq!(query.count("1E4C9B93F3F0682250B6CF8331B7EE68FD8\r\n"), 0);
+
+    let mut password = SecureString::new("Xk9#mP2$42".to_string());
+    let prefix = sha1_hex("Xk9#mP2$42")[..5].to_string();
+    let count = hibp::check(&mut password, |url| {
+        assert!(url.ends_with(&prefix));
+        Ok(response.to_string())
+    });
+    assert_eq!(count, Ok(0));
+    assert_eq!(hibp::check(&mut password, |_| Err(V1KpdbError::ReadErr)),
+               Err(V1KpdbError::ReadErr));
+}
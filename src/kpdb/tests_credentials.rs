@@ -0,0 +1,111 @@
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+
+use kpdb::credentials::{CachePolicy, CredentialStore, KeyCache};
+use kpdb::crypter::CompositeKey;
+use kpdb::policy::KeyFactor;
+use kpdb::v1error::V1KpdbError;
+use kpdb::v1kpdb::V1Kpdb;
+use sec_str::{SecureBytes, SecureString};
+
+// Store which keeps the secrets in memory
+struct MemoryStore {
+    secrets: HashMap<String, Vec<u8>>,
+}
+
+impl CredentialStore for MemoryStore {
+    fn store(&mut self, id: &str, secret: &[u8]) -> Result<(), V1KpdbError> {
+        self.secrets.insert(id.to_string(), secret.to_vec());
+        Ok(())
+    }
+
+    fn retrieve(&mut self, id: &str) -> Result<Option<SecureBytes>, V1KpdbError> {
+        Ok(self.secrets.get(id).map(|s| SecureBytes::new(s.clone())))
+    }
+
+    fn remove(&mut self, id: &str) -> Result<(), V1KpdbError> {
+        self.secrets.remove(id);
+        Ok(())
+    }
+}
+
+fn new_cache(policy: CachePolicy) -> KeyCache<MemoryStore> {
+    let mut cache = KeyCache::new(MemoryStore { secrets: HashMap::new() });
+    cache.policy = policy;
+    cache
+}
+
+fn open_both() -> V1Kpdb {
+    let mut db = V1Kpdb::new("test/test_both.kdb".to_string(),
+                             Some("test".to_string()),
+                             Some("test/test_key".to_string()))
+                     .ok()
+                     .unwrap();
+    assert!(db.load().is_ok());
+    db
+}
+
+#[test]
+fn test_remember_composite_key() {
+    let mut cache = new_cache(CachePolicy::CompositeKey);
+    assert!(cache.recall("test/test_both.kdb").unwrap().is_none());
+    let mut db = open_both();
+    assert!(cache.remember(&mut db).is_ok());
+
+    let key = cache.recall("test/test_both.kdb").unwrap().unwrap();
+    assert_eq!(key.factors(), vec![KeyFactor::Password, KeyFactor::Keyfile]);
+    // Found under another path of the same file
+    let opened = cache.open("test/../test/test_both.kdb").unwrap().unwrap();
+    assert_eq!(opened.entries.len(), db.entries.len());
+
+    assert!(cache.forget("test/test_both.kdb").is_ok());
+    assert!(cache.open("test/test_both.kdb").unwrap().is_none());
+}
+
+#[test]
+fn test_remember_master_key() {
+    let mut cache = new_cache(CachePolicy::MasterKey);
+    let mut db = open_both();
+    assert!(cache.remember(&mut db).is_ok());
+    {
+        let secret = cache.store().secrets.values().next().unwrap();
+        // Tag, length and the hash, no trace of the password
+        assert_eq!(secret.len(), 5 + 32);
+        assert!(!secret.windows(4).any(|w| w == b"test"));
+    }
+    let mut opened = cache.open("test/test_both.kdb").unwrap().unwrap();
+    assert_eq!(opened.entries.len(), db.entries.len());
+    // A restored master key can be cached again
+    let mut again = new_cache(CachePolicy::CompositeKey);
+    assert!(again.remember(&mut opened).is_ok());
+    assert!(again.open("test/test_both.kdb").unwrap().is_some());
+}
+
+#[test]
+fn test_forget_stale_key() {
+    let mut path = env::temp_dir();
+    path.push("rust_keepass_test_stale_key.kdb");
+    let path = path.to_str().unwrap().to_string();
+    assert!(fs::copy("test/test_password.kdb", &path).is_ok());
+
+    let mut cache = new_cache(CachePolicy::MasterKey);
+    let mut db = V1Kpdb::new(path.clone(), Some("test".to_string()), None).ok().unwrap();
+    assert!(db.load().is_ok());
+    assert!(cache.remember(&mut db).is_ok());
+    assert!(db.set_credentials(Some(SecureString::new("changed".to_string())), None).is_ok());
+    assert!(db.save(None, None, None).is_ok());
+
+    assert!(cache.open(&path).unwrap().is_none());
+    assert!(cache.recall(&path).unwrap().is_none());
+    let _ = fs::remove_file(&path);
+}
+
+#[test]
+fn test_invalid_cache() {
+    assert_eq!(CompositeKey::from_cache(&[1, 5, 0, 0, 0, b'a']).err(),
+               Some(V1KpdbError::ConvertErr));
+    assert_eq!(CompositeKey::from_cache(&[9, 0, 0, 0, 0]).err(),
+               Some(V1KpdbError::ConvertErr));
+    assert_eq!(CompositeKey::from_cache(&[]).err(), Some(V1KpdbError::PassErr));
+}
@@ -1,10 +1,18 @@
 #![allow(dead_code)]
-use std::fs::File;
-use std::io::Read;
+use std::env;
+use std::fs::{self, File};
+use std::io::{Read, Write};
+use std::time::Duration;
+
+use rustc_serialize::hex::FromHex;
 
 use kpdb::parser::HeaderLoadParser;
-use kpdb::crypter::Crypter;
+use kpdb::crypter::{constant_time_eq, Crypter};
+use kpdb::testvectors::{CONTENT_HASH_VECTORS, KDF_VECTORS};
+use kpdb::twofish::Twofish;
+use kpdb::v1error::V1KpdbError;
 use kpdb::v1header::V1Header;
+use super::super::mem_protect::{self, MemLockPolicy};
 use super::super::sec_str::SecureString;
 
 fn setup(path: String,
@@ -222,3 +230,199 @@ fn test_decrypt_it_w_both() {
     assert_eq!(test_content1, test1);
     assert_eq!(test_content2, test2);
 }
+
+#[test]
+fn test_benchmark_rounds() {
+    let short = Crypter::benchmark_rounds(Duration::from_millis(20));
+    let long = Crypter::benchmark_rounds(Duration::from_millis(200));
+    // The counts depend on the load of the machine, so they aren't compared
+    assert!(short >= 1000);
+    assert!(long >= 1000);
+
+    let mut header = V1Header::new();
+    assert!(header.set_key_transf_rounds(short).is_ok());
+    assert_eq!(header.key_transf_rounds, short);
+    assert!(header.set_key_transf_rounds(long).is_ok());
+    assert_eq!(header.key_transf_rounds, long);
+    assert_eq!(header.set_key_transf_rounds(0), Err(V1KpdbError::RoundsErr));
+    assert_eq!(header.key_transf_rounds, long);
+}
+
+#[test]
+fn test_crypter_mem_lock_policy() {
+    let (mut crypter, header, encrypted_database) =
+        setup("test/test_password.kdb".to_string(),
+              Some(SecureString::new("test".to_string())),
+              None);
+    crypter.set_mem_lock_policy(Some(MemLockPolicy::Disabled));
+    let failures = mem_protect::lock_failures();
+    let decrypted = crypter.decrypt_database(&header, encrypted_database.clone()).unwrap();
+    assert_eq!(mem_protect::lock_failures(), failures);
+    assert_eq!(mem_protect::policy(), MemLockPolicy::BestEffort);
+    unsafe {
+        mem_protect::zero(&decrypted);
+    }
+
+    // Succeeds unless RLIMIT_MEMLOCK can't be raised enough
+    crypter.set_mem_lock_policy(Some(MemLockPolicy::Require));
+    match crypter.decrypt_database(&header, encrypted_database) {
+        Ok(decrypted) => unsafe {
+            mem_protect::zero(&decrypted);
+            mem_protect::unlock(&decrypted);
+        },
+        Err(e) => assert_eq!(e, V1KpdbError::MemLockErr),
+    }
+    assert_eq!(mem_protect::policy(), MemLockPolicy::BestEffort);
+}
+
+#[test]
+fn test_kdf_vectors() {
+    for vector in KDF_VECTORS {
+        let mut password = SecureString::new(vector.password.to_string());
+        let password_key = Crypter::get_passwordkey(&mut password).ok().unwrap();
+        assert_eq!(password_key, vector.password_key.from_hex().unwrap());
+
+        let mut header = V1Header::new();
+        header.transf_randomseed = vector.transf_randomseed.from_hex().unwrap();
+        header.key_transf_rounds = vector.rounds;
+        header.final_randomseed = vector.final_randomseed.from_hex().unwrap();
+        let transformed_key = Crypter::transformed_key(password_key.clone(),
+                                                       &header,
+                                                       &mut None,
+                                                       &None)
+                                  .ok()
+                                  .unwrap();
+        assert_eq!(transformed_key, vector.transformed_key.from_hex().unwrap());
+        let final_key = Crypter::transform_key(password_key, &header, &mut None, &None)
+                            .ok()
+                            .unwrap();
+        assert_eq!(final_key, vector.final_key.from_hex().unwrap());
+    }
+}
+
+#[test]
+fn test_content_hash_vectors() {
+    for vector in CONTENT_HASH_VECTORS {
+        let (mut crypter, header, encrypted_database) =
+            setup(vector.path.to_string(),
+                  Some(SecureString::new(vector.password.to_string())),
+                  None);
+        let content_hash = vector.content_hash.from_hex().unwrap();
+        assert_eq!(header.content_hash, content_hash);
+
+        let decrypted_database = crypter.decrypt_database(&header, encrypted_database)
+                                        .ok()
+                                        .unwrap();
+        assert_eq!(Crypter::get_content_hash(&decrypted_database).ok().unwrap(),
+                   content_hash);
+    }
+}
+
+#[test]
+fn test_decrypt_it_w_xml_key() {
+    for keyfile in &["test/32Bkey.xml", "test/32Bkey.keyx"] {
+        let (mut crypter, header, encrypted_database) =
+            setup("test/test_32B_key.kdb".to_string(),
+                  None,
+                  Some(SecureString::new(keyfile.to_string())));
+        assert!(crypter.decrypt_database(&header, encrypted_database).is_ok());
+    }
+
+    // Wrong checksum of a version 2.0 keyfile
+    let mut content = String::new();
+    let _ = File::open("test/32Bkey.keyx").unwrap().read_to_string(&mut content);
+    let mut path = env::temp_dir();
+    path.push("rust_keepass_test_bad_hash.keyx");
+    let path = path.to_str().unwrap().to_string();
+    let _ = File::create(&path)
+                .unwrap()
+                .write_all(content.replace("456AC06E", "00000000").as_bytes());
+    let (mut crypter, header, encrypted_database) =
+        setup("test/test_32B_key.kdb".to_string(),
+              None,
+              Some(SecureString::new(path.clone())));
+    match crypter.decrypt_database(&header, encrypted_database) {
+        Ok(_) => assert!(false),
+        Err(e) => assert_eq!(e, V1KpdbError::KeyfileErr),
+    };
+    let _ = fs::remove_file(&path);
+}
+
+#[test]
+fn test_constant_time_eq() {
+    assert!(constant_time_eq(&[], &[]));
+    assert!(constant_time_eq(&[1, 2, 3], &[1, 2, 3]));
+    assert!(!constant_time_eq(&[1, 2, 3], &[1, 2, 4]));
+    assert!(!constant_time_eq(&[0, 2, 3], &[1, 2, 3]));
+    assert!(!constant_time_eq(&[1, 2, 3], &[1, 2]));
+}
+
+#[test]
+fn test_strip_padding() {
+    let mut data = vec![1, 2, 3, 4, 4, 4, 4];
+    assert!(Crypter::strip_padding(&mut data).is_ok());
+    assert_eq!(data, vec![1, 2, 3]);
+    let mut data = vec![16; 16];
+    assert!(Crypter::strip_padding(&mut data).is_ok());
+    assert!(data.is_empty());
+
+    for data in &[vec![], vec![1, 2, 0], vec![1, 2, 17], vec![3, 3], vec![1, 2, 3, 3]] {
+        let mut data = data.clone();
+        assert_eq!(Crypter::strip_padding(&mut data), Err(V1KpdbError::DecryptErr));
+    }
+}
+
+#[test]
+fn test_decrypt_corrupt_ciphertext() {
+    // Garbled last block, i.e. garbled padding
+    let (mut crypter, header, mut encrypted_database) =
+        setup("test/test_password.kdb".to_string(),
+              Some(SecureString::new("test".to_string())),
+              None);
+    let len = encrypted_database.len();
+    for b in encrypted_database[len - 16..].iter_mut() {
+        *b ^= 0x5a;
+    }
+    match crypter.decrypt_database(&header, encrypted_database) {
+        Err(V1KpdbError::DecryptErr) | Err(V1KpdbError::HashErr) => {}
+        _ => assert!(false),
+    }
+
+    // Not a multiple of the block size
+    let (mut crypter, header, mut encrypted_database) =
+        setup("test/test_password.kdb".to_string(),
+              Some(SecureString::new("test".to_string())),
+              None);
+    encrypted_database.pop();
+    assert_eq!(crypter.decrypt_database(&header, encrypted_database),
+               Err(V1KpdbError::DecryptErr));
+
+    // Nothing at all
+    let (mut crypter, header, _) = setup("test/test_password.kdb".to_string(),
+                                         Some(SecureString::new("test".to_string())),
+                                         None);
+    assert_eq!(crypter.decrypt_database(&header, vec![]),
+               Err(V1KpdbError::DecryptErr));
+}
+
+#[test]
+fn test_twofish() {
+    // Known answers of the Twofish specification, all with a zero block
+    let vectors = [("00000000000000000000000000000000", "9F589F5CF6122C32B6BFEC2F2AE8C35A"),
+                   ("000000000000000000000000000000000000000000000000",
+                    "EFA71F788965BD4453F860178FC19101"),
+                   ("0000000000000000000000000000000000000000000000000000000000000000",
+                    "57FF739D4DC92C1BD7FC01700CC8216F"),
+                   ("0123456789ABCDEFFEDCBA98765432100011223344556677",
+                    "CFD1D2E5A9BE9CDF501F13B892BD2248"),
+                   ("0123456789ABCDEFFEDCBA987654321000112233445566778899AABBCCDDEEFF",
+                    "37527BE0052334B89F0CFCCAE87CFA20")];
+    for &(key, ciphertext) in vectors.iter() {
+        let twofish = Twofish::new(&key.from_hex().unwrap());
+        let mut block = vec![0u8; 16];
+        twofish.encrypt_block(&mut block);
+        assert_eq!(block, ciphertext.from_hex().unwrap());
+        twofish.decrypt_block(&mut block);
+        assert_eq!(block, vec![0u8; 16]);
+    }
+}
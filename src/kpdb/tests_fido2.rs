@@ -0,0 +1,110 @@
+use std::cell::Cell;
+use std::env;
+use std::fs;
+use std::io::Write;
+use std::rc::Rc;
+
+use openssl::crypto::hash::{Hasher, Type};
+
+use kpdb::crypter::KeyProvider;
+use kpdb::fido2::{Fido2Authenticator, Fido2Error, Fido2KeyProvider};
+use kpdb::v1error::V1KpdbError;
+use kpdb::v1kpdb::V1Kpdb;
+use sec_str::SecureString;
+
+// Fake authenticator which "hashes" the salt with its secret and needs
+// the PIN 1234
+struct MockAuthenticator {
+    secret: Vec<u8>,
+}
+
+impl Fido2Authenticator for MockAuthenticator {
+    fn name(&self) -> String {
+        format!("mock {}", self.secret[0])
+    }
+
+    fn hmac_secret(&mut self,
+                   rp_id: &str,
+                   credential_id: &[u8],
+                   salt: &[u8],
+                   pin: Option<&str>)
+                   -> Result<Vec<u8>, Fido2Error> {
+        assert_eq!(rp_id, "rust-keepass");
+        if credential_id != b"cred" {
+            return Err(Fido2Error::NoCredential);
+        }
+        match pin {
+            None => return Err(Fido2Error::PinRequired),
+            Some("1234") => {}
+            Some(_) => return Err(Fido2Error::PinInvalid),
+        }
+        let mut hasher = Hasher::new(Type::SHA256);
+        let _ = hasher.write_all(&self.secret);
+        let _ = hasher.write_all(salt);
+        Ok(hasher.finish())
+    }
+}
+
+fn provider(pin: &'static str, pin_requests: Rc<Cell<usize>>) -> Fido2KeyProvider {
+    let devices: Vec<Box<Fido2Authenticator>> = vec![Box::new(MockAuthenticator {
+                                                         secret: vec![1; 32],
+                                                     }),
+                                                     Box::new(MockAuthenticator {
+                                                         secret: vec![2; 32],
+                                                     })];
+    Fido2KeyProvider::new("rust-keepass".to_string(),
+                          b"cred".to_vec(),
+                          devices,
+                          Box::new(|names: &[String]| {
+                              assert_eq!(names[1], "mock 2");
+                              Some(1)
+                          }),
+                          Box::new(move || {
+                              pin_requests.set(pin_requests.get() + 1);
+                              Some(SecureString::new(pin.to_string()))
+                          }))
+}
+
+#[test]
+fn test_fido2_get_key() {
+    let pin_requests = Rc::new(Cell::new(0));
+    let mut good = provider("1234", pin_requests.clone());
+    let key = good.get_key(&[0; 16]).ok().unwrap();
+    assert_eq!(key.len(), 32);
+    assert_eq!(pin_requests.get(), 1);
+    assert!(good.get_key(&[1; 16]).ok().unwrap() != key);
+
+    let pin_requests = Rc::new(Cell::new(0));
+    let mut bad = provider("0000", pin_requests.clone());
+    match bad.get_key(&[0; 16]) {
+        Ok(_) => assert!(false),
+        Err(e) => assert_eq!(e, V1KpdbError::KeyProviderErr),
+    };
+    assert_eq!(pin_requests.get(), 3);
+}
+
+#[test]
+fn test_fido2_unlock() {
+    let mut path = env::temp_dir();
+    path.push("rust_keepass_test_fido2.kdb");
+    let path = path.to_str().unwrap().to_string();
+    assert!(fs::copy("test/test_password.kdb", &path).is_ok());
+
+    let mut db = V1Kpdb::new(path.clone(), Some("test".to_string()), None).ok().unwrap();
+    assert!(db.load().is_ok());
+    db.set_key_provider(Some(Box::new(provider("1234", Rc::new(Cell::new(0))))));
+    assert!(db.save(None, None, None).is_ok());
+
+    let mut without = V1Kpdb::new(path.clone(), Some("test".to_string()), None).ok().unwrap();
+    // Depending on the random IV a wrong key shows up as invalid padding
+    // or as wrong hash
+    match without.load() {
+        Err(V1KpdbError::DecryptErr) | Err(V1KpdbError::HashErr) => {}
+        _ => assert!(false),
+    };
+
+    let mut with = V1Kpdb::new(path.clone(), Some("test".to_string()), None).ok().unwrap();
+    with.set_key_provider(Some(Box::new(provider("1234", Rc::new(Cell::new(0))))));
+    assert!(with.load().is_ok());
+    let _ = fs::remove_file(&path);
+}
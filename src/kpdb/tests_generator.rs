@@ -0,0 +1,57 @@
+use kpdb::generator::{CharClass, PasswordGenerator};
+use kpdb::v1error::V1KpdbError;
+
+fn close(a: f64, b: f64) -> bool {
+    (a - b).abs() < 0.001
+}
+
+#[test]
+fn test_char_classes() {
+    assert_eq!(CharClass::Digits.chars().len(), 10);
+    assert_eq!(CharClass::Symbols.chars().len(), 32);
+    assert_eq!(CharClass::cyrillic().chars().len(), 64);
+    assert_eq!(CharClass::greek().chars().len(), 49);
+    assert!(CharClass::greek().chars().contains(&'ς'));
+    // Surrogates are skipped
+    assert_eq!(CharClass::Range('\u{D7FF}', '\u{E000}').chars().len(), 2);
+    assert!(CharClass::Range('z', 'a').chars().is_empty());
+}
+
+#[test]
+fn test_generate() {
+    let mut generator = PasswordGenerator::new(20);
+    generator.classes = vec![CharClass::cyrillic(), CharClass::Digits];
+    generator.each_class = true;
+    for _ in 0..20 {
+        let mut password = generator.generate().ok().unwrap();
+        password.unlock();
+        assert_eq!(password.string.chars().count(), 20);
+        assert!(password.string.chars().any(|c| c >= 'А' && c <= 'я'));
+        assert!(password.string.chars().any(|c| c.is_digit(10)));
+        assert!(password.string.chars().all(|c| (c >= 'А' && c <= 'я') || c.is_digit(10)));
+    }
+
+    generator.length = 1;
+    assert_eq!(generator.generate().err(), Some(V1KpdbError::GeneratorErr));
+    generator.length = 8;
+    generator.classes.push(CharClass::Range('b', 'a'));
+    assert_eq!(generator.generate().err(), Some(V1KpdbError::GeneratorErr));
+    assert_eq!(generator.entropy_bits(), 0.0);
+}
+
+#[test]
+fn test_entropy_bits() {
+    let mut generator = PasswordGenerator::new(10);
+    assert!(close(generator.entropy_bits(), 10.0 * 94f64.log2()));
+
+    // Overlapping classes don't count twice
+    generator.classes = vec![CharClass::Lowercase, CharClass::Range('a', 'f')];
+    assert!(close(generator.entropy_bits(), 10.0 * 26f64.log2()));
+
+    // Two classes of two characters and length 2: ab, ba, ... 8 of the
+    // 16 passwords hold both classes
+    generator.length = 2;
+    generator.classes = vec![CharClass::Chars(vec!['a', 'b']), CharClass::Chars(vec!['1', '2'])];
+    generator.each_class = true;
+    assert!(close(generator.entropy_bits(), 3.0));
+}
@@ -0,0 +1,515 @@
+use std::env;
+use std::fs::{self, File};
+use std::io::Read;
+
+use chrono::{Local, TimeZone};
+use openssl::crypto::hash::{hash, Type};
+use openssl::crypto::hmac::hmac;
+use rustc_serialize::json::Json;
+
+use kpdb::export;
+use kpdb::export::blobs::{GeneratedPassphrases, SharedPassphrase, MANIFEST_FILE};
+use kpdb::generator::PasswordGenerator;
+use kpdb::import::{apply, browser, csv, dashlane, preview, proton, psafe3, zip, ImportEntry};
+use kpdb::import::csv::{CsvColumn, CsvMapping};
+use kpdb::import::validate::{fix, validate, IssueKind};
+use kpdb::twofish::Twofish;
+use kpdb::v1error::V1KpdbError;
+use kpdb::v1kpdb::V1Kpdb;
+use sec_str::SecureString;
+
+fn setup() -> V1Kpdb {
+    let mut db = V1Kpdb::new("test/test_password.kdb".to_string(),
+                             Some("test".to_string()),
+                             None)
+                     .ok()
+                     .unwrap();
+    assert!(db.load().is_ok());
+    db
+}
+
+fn record(group_path: Vec<&str>, title: &str, username: &str) -> ImportEntry {
+    let mut record = ImportEntry::new();
+    record.group_path = group_path.iter().map(|s| s.to_string()).collect();
+    record.title = title.to_string();
+    record.username = Some(username.to_string());
+    record.password = Some("secret".to_string());
+    record
+}
+
+#[test]
+fn test_preview() {
+    let db = setup();
+    let num_groups = db.groups.len();
+    let num_entries = db.entries.len();
+    let records = vec![record(vec!["Internet"], "foo", "foo"),
+                       record(vec!["Internet", "Mail"], "Mailbox", "alice"),
+                       record(vec!["Internet", "Mail", "Work"], "Work", "alice"),
+                       record(vec![], "No group", "bob")];
+
+    let tree = preview(&db, &records);
+    assert_eq!(tree.new_groups,
+               vec![vec!["Internet".to_string(), "Mail".to_string()],
+                    vec!["Internet".to_string(), "Mail".to_string(), "Work".to_string()]]);
+    assert_eq!(tree.new_entries.len(), 4);
+    assert_eq!(tree.new_entries[3].group_path, vec!["Internet".to_string()]);
+    assert_eq!(tree.conflicts.len(), 1);
+    assert_eq!(tree.conflicts[0].index, 0);
+    assert_eq!(tree.conflicts[0].existing.borrow().title, "foo");
+
+    // Nothing changed
+    assert_eq!(db.groups.len(), num_groups);
+    assert_eq!(db.entries.len(), num_entries);
+}
+
+#[test]
+fn test_apply() {
+    let mut db = setup();
+    let num_groups = db.header.num_groups;
+    let num_entries = db.header.num_entries;
+    let records = vec![record(vec!["Internet", "Mail"], "Mailbox", "alice"),
+                       record(vec!["Internet", "Mail"], "Other", "bob")];
+
+    assert!(apply(&mut db, records).is_ok());
+    assert_eq!(db.header.num_groups, num_groups + 1);
+    assert_eq!(db.header.num_entries, num_entries + 2);
+    let mail = db.groups[1].clone();
+    assert_eq!(mail.borrow().title, "Mail");
+    assert_eq!(mail.borrow().level, 1);
+    assert_eq!(mail.borrow().entries.len(), 2);
+
+    // A second preview finds the imported entries as conflicts
+    let tree = preview(&db, &[record(vec!["Internet", "Mail"], "Mailbox", "alice")]);
+    assert_eq!(tree.new_groups.len(), 0);
+    assert_eq!(tree.conflicts.len(), 1);
+}
+
+#[test]
+fn test_validate() {
+    let mut records = vec![record(vec![], "Normalized", "alice"),
+                           record(vec![], "No scheme", "alice"),
+                           record(vec![], "Swapped", "Xk9#mP2$vL"),
+                           record(vec![], "OTP", "bob"),
+                           record(vec![], "Bad OTP", "bob")];
+    records[0].url = Some("https://example.com/Login".to_string());
+    records[1].url = Some(" Example.COM/Login ".to_string());
+    records[2].password = Some("alice@example.com".to_string());
+    records[3].notes = Some("otpauth://totp/Example:bob?secret=JBSWY3DPEHPK3PXP&digits=6"
+                                .to_string());
+    records[4].url = Some("otpauth://hotp/Example:bob?secret=JBSWY3DPEHPK3PXP".to_string());
+    records[4].notes = Some("foo\notpauth://totp/Example?secret=not-base32".to_string());
+
+    let report = validate(&records);
+    let kinds: Vec<(usize, IssueKind)> = report.issues
+                                                .iter()
+                                                .map(|i| (i.index, i.kind.clone()))
+                                                .collect();
+    assert_eq!(kinds,
+               vec![(1, IssueKind::UrlNotNormalized("https://example.com/Login".to_string())),
+                    (2, IssueKind::SwappedCredentials),
+                    (4, IssueKind::InvalidOtpAuth),
+                    (4, IssueKind::InvalidOtpAuth)]);
+    assert_eq!(report.fixable().len(), 2);
+
+    fix(&mut records, &report);
+    assert_eq!(records[1].url.as_ref().unwrap(), "https://example.com/Login");
+    assert_eq!(records[2].username.as_ref().unwrap(), "alice@example.com");
+    assert_eq!(records[2].password.as_ref().unwrap(), "Xk9#mP2$vL");
+    let report = validate(&records);
+    assert_eq!(report.fixable().len(), 0);
+    assert!(!report.is_clean());
+}
+
+#[test]
+fn test_csv_import() {
+    let data = "\u{FEFF}Group,Title,Username,Password,URL,Notes\r\n\
+                Internet/Mail,Mailbox,alice,\"pa,ss\"\"word\",https://example.com,\"line 1\r\nline 2\"\r\n\
+                \r\n\
+                ,No group,bob\n";
+    let records = csv::parse(data.as_bytes(), &CsvMapping::new()).unwrap();
+    assert_eq!(records.len(), 2);
+    assert_eq!(records[0].group_path, vec!["Internet".to_string(), "Mail".to_string()]);
+    assert_eq!(records[0].title, "Mailbox");
+    assert_eq!(records[0].username.as_ref().unwrap(), "alice");
+    assert_eq!(records[0].password.as_ref().unwrap(), "pa,ss\"word");
+    assert_eq!(records[0].url.as_ref().unwrap(), "https://example.com");
+    assert_eq!(records[0].notes.as_ref().unwrap(), "line 1\r\nline 2");
+    assert!(records[1].group_path.is_empty());
+    assert_eq!(records[1].title, "No group");
+    assert!(records[1].password.is_none());
+
+    let mut mapping = CsvMapping::new();
+    mapping.columns = vec![CsvColumn::Title, CsvColumn::Ignore, CsvColumn::Password];
+    mapping.has_header = false;
+    mapping.delimiter = b';';
+    let records = csv::read("foo;bar;\"secret\";baz".as_bytes(), &mapping).unwrap();
+    assert_eq!(records.len(), 1);
+    assert_eq!(records[0].title, "foo");
+    assert!(records[0].username.is_none());
+    assert_eq!(records[0].password.as_ref().unwrap(), "secret");
+
+    assert_eq!(csv::parse(b"foo;\"bar", &mapping).err(), Some(V1KpdbError::CsvErr));
+    assert_eq!(csv::parse(b"foo;\"bar\"baz", &mapping).err(), Some(V1KpdbError::CsvErr));
+    assert_eq!(csv::parse(b"foo;\xFF", &mapping).err(), Some(V1KpdbError::CsvErr));
+    mapping.delimiter = b'"';
+    assert_eq!(csv::parse(b"foo", &mapping).err(), Some(V1KpdbError::CsvErr));
+}
+
+#[test]
+fn test_csv_export() {
+    let mut db = setup();
+    let group = db.groups[0].clone();
+    db.create_entry(group,
+                    "Quotes, \"commas\"".to_string(),
+                    None,
+                    None,
+                    None,
+                    Some("first\nsecond".to_string()),
+                    Some(" alice ".to_string()),
+                    Some("pa\"ss;word".to_string()));
+
+    let mut output = vec![];
+    let written = export::csv::write(&db, &mut output, &CsvMapping::new()).unwrap();
+    assert_eq!(written, db.entries.len());
+    assert!(String::from_utf8_lossy(&output)
+                .starts_with("Group,Title,Username,Password,URL,Notes\r\n"));
+
+    let records = csv::parse(&output, &CsvMapping::new()).unwrap();
+    assert_eq!(records.len(), db.entries.len());
+    let record = records.last().unwrap();
+    assert_eq!(record.group_path, vec![db.groups[0].borrow().title.clone()]);
+    assert_eq!(record.title, "Quotes, \"commas\"");
+    assert_eq!(record.username.as_ref().unwrap(), " alice ");
+    assert_eq!(record.password.as_ref().unwrap(), "pa\"ss;word");
+    assert!(record.url.is_none());
+    assert_eq!(record.notes.as_ref().unwrap(), "first\nsecond");
+}
+
+#[test]
+fn test_per_entry_blobs() {
+    let mut db = setup();
+    let group = db.groups[0].clone();
+    db.create_entry(group,
+                    "second".to_string(),
+                    None,
+                    None,
+                    None,
+                    None,
+                    Some("bob".to_string()),
+                    Some("hunter2".to_string()));
+    let mut dir = env::temp_dir();
+    dir.push("rust_keepass_test_per_entry_blobs");
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir(&dir).unwrap();
+
+    let mut policy = GeneratedPassphrases::new(PasswordGenerator::new(20));
+    let records = export::per_entry_blobs(&db, &dir, &mut policy).unwrap();
+    assert_eq!(records.len(), db.entries.len());
+    let mut manifest = String::new();
+    File::open(dir.join(MANIFEST_FILE)).unwrap().read_to_string(&mut manifest).unwrap();
+    assert!(!manifest.contains("hunter2"));
+    match Json::from_str(&manifest).unwrap() {
+        Json::Array(items) => assert_eq!(items.len(), records.len()),
+        _ => panic!("manifest is no array"),
+    }
+
+    let record = records.last().unwrap();
+    assert_eq!(record.title, "second");
+    assert_eq!(record.group_path, vec![db.groups[0].borrow().title.clone()]);
+    let path = dir.join(&record.file_name).to_str().unwrap().to_string();
+    let mut passphrase = policy.take(&record.uuid).unwrap();
+    let mut blob = V1Kpdb::new(path.clone(), Some(passphrase.unlocked().to_string()), None).unwrap();
+    assert!(blob.load().is_ok());
+    assert_eq!(blob.entries.len(), 1);
+    assert_eq!(blob.groups.len(), 1);
+    let mut entry = blob.entries[0].borrow_mut();
+    assert_eq!(entry.uuid, record.uuid);
+    assert_eq!(entry.title, "second");
+    assert_eq!(&*entry.password().unwrap(), "hunter2");
+    assert!(V1Kpdb::new(path, Some("test".to_string()), None).unwrap().load().is_err());
+
+    let mut policy = SharedPassphrase::new(SecureString::new("shared".to_string()));
+    let records = export::per_entry_blobs(&db, &dir, &mut policy).unwrap();
+    for record in records.iter() {
+        let path = dir.join(&record.file_name).to_str().unwrap().to_string();
+        let mut blob = V1Kpdb::new(path, Some("shared".to_string()), None).unwrap();
+        assert!(blob.load().is_ok());
+    }
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn test_proton_import() {
+    let export = r#"{
+        "version": "1.21.2",
+        "encrypted": false,
+        "vaults": {
+            "share1": {
+                "name": "Work",
+                "items": [{
+                    "itemId": "a",
+                    "state": 1,
+                    "aliasEmail": null,
+                    "data": {
+                        "metadata": {"name": "Mail", "note": "Company mail", "itemUuid": "1"},
+                        "extraFields": [
+                            {"fieldName": "PIN", "type": "hidden", "data": {"content": "1234"}},
+                            {"fieldName": "2FA", "type": "totp",
+                             "data": {"content": "jbsw y3dp ehpk 3pxp"}}
+                        ],
+                        "type": "login",
+                        "content": {
+                            "itemEmail": "alice@example.com",
+                            "itemUsername": "alice",
+                            "password": "secret",
+                            "urls": ["https://mail.example.com", "https://example.com"],
+                            "totpUri": "otpauth://totp/Mail?secret=JBSWY3DPEHPK3PXP"
+                        }
+                    }
+                }, {
+                    "itemId": "b",
+                    "state": 2,
+                    "data": {"metadata": {"name": "Trashed", "note": ""}, "type": "note"}
+                }, {
+                    "itemId": "c",
+                    "state": 1,
+                    "aliasEmail": "alias@passmail.net",
+                    "data": {"metadata": {"name": "Alias", "note": ""}, "type": "alias",
+                             "content": {}}
+                }]
+            }
+        }
+    }"#;
+    let records = proton::parse(export).unwrap();
+    assert_eq!(records.len(), 2);
+    assert_eq!(records[0].group_path, vec!["Work".to_string()]);
+    assert_eq!(records[0].title, "Mail");
+    assert_eq!(records[0].username.as_ref().unwrap(), "alice");
+    assert_eq!(records[0].password.as_ref().unwrap(), "secret");
+    assert_eq!(records[0].url.as_ref().unwrap(), "https://mail.example.com");
+    assert_eq!(records[0].notes.as_ref().unwrap(),
+               "Company mail\nEmail: alice@example.com\nKP2A_URL_1: https://example.com\n\
+                otpauth://totp/Mail?secret=JBSWY3DPEHPK3PXP\n::secret::\nPIN: 1234\n::secret::\n\
+                otpauth://totp/Mail?secret=JBSWY3DPEHPK3PXP");
+    assert_eq!(records[1].title, "Alias");
+    assert_eq!(records[1].username.as_ref().unwrap(), "alias@passmail.net");
+    assert!(validate(&records).is_clean());
+
+    assert_eq!(proton::parse(r#"{"encrypted": true, "vaults": {}}"#).err(),
+               Some(V1KpdbError::ImportErr));
+    assert_eq!(proton::parse("[]").err(), Some(V1KpdbError::ImportErr));
+}
+
+#[test]
+fn test_dashlane_import() {
+    let data = "username,username2,username3,title,password,note,url,category,otpSecret\n\
+                alice,alice2,,Mail,secret,Company mail,https://mail.example.com,Work,\
+                JBSWY3DPEHPK3PXP\n\
+                bob,,,Shop,\"pa,ss\",,https://shop.example.com,,\n";
+    let records = dashlane::parse_csv(data.as_bytes()).unwrap();
+    assert_eq!(records.len(), 2);
+    assert_eq!(records[0].group_path, vec!["Work".to_string()]);
+    assert_eq!(records[0].title, "Mail");
+    assert_eq!(records[0].username.as_ref().unwrap(), "alice");
+    assert_eq!(records[0].password.as_ref().unwrap(), "secret");
+    assert_eq!(records[0].notes.as_ref().unwrap(),
+               "Alternative username: alice2\nCompany mail\n\
+                otpauth://totp/Mail?secret=JBSWY3DPEHPK3PXP");
+    assert!(records[1].group_path.is_empty());
+    assert_eq!(records[1].password.as_ref().unwrap(), "pa,ss");
+    assert!(validate(&records).is_clean());
+    assert_eq!(dashlane::parse_csv(b"foo,bar\n1,2").err(), Some(V1KpdbError::ImportErr));
+
+    let export = r#"{
+        "AUTHENTIFIANT": [{"title": "Mail", "email": "alice@example.com", "login": "alice",
+                           "password": "secret", "domain": "https://mail.example.com",
+                           "note": "", "category": "Work"}],
+        "SECUREDNOTE": [{"title": "Server", "content": "root access", "category": ""}]
+    }"#;
+    let records = dashlane::parse_json(export).unwrap();
+    assert_eq!(records.len(), 2);
+    assert_eq!(records[0].username.as_ref().unwrap(), "alice");
+    assert_eq!(records[0].notes.as_ref().unwrap(),
+               "Alternative username: alice@example.com");
+    assert_eq!(records[0].url.as_ref().unwrap(), "https://mail.example.com");
+    assert_eq!(records[1].title, "Server");
+    assert_eq!(records[1].notes.as_ref().unwrap(), "root access");
+    assert!(records[1].group_path.is_empty());
+    assert_eq!(dashlane::parse_json("{}").err(), Some(V1KpdbError::ImportErr));
+}
+
+#[test]
+fn test_zip_import() {
+    let mut data = vec![];
+    File::open("test/dashlane_export.zip").unwrap().read_to_end(&mut data).unwrap();
+    // Dynamic, stored and fixed deflate blocks and a stored file
+    let files = zip::files(&data).unwrap();
+    let names: Vec<&str> = files.iter().map(|f| &f.name[..]).collect();
+    assert_eq!(names, vec!["credentials.csv", "securenotes.csv", "ids.csv", "payments.csv"]);
+    assert!(zip::extract(&data, &files[0]).unwrap().starts_with(b"username,username2"));
+    assert_eq!(zip::extract(&data, &files[1]).unwrap(),
+               b"title,note,category\nServer,root access,\n".to_vec());
+    assert_eq!(zip::extract(&data, &files[2]).unwrap(), b"type,number,name\n".to_vec());
+    assert_eq!(zip::extract(&data, &files[3]).unwrap(), b"type,account_name\n".to_vec());
+    assert_eq!(zip::crc32(b"123456789"), 0xCBF43926);
+
+    let records = dashlane::parse_zip(&data).unwrap();
+    assert_eq!(records.len(), 3);
+    assert_eq!(records[0].title, "Mail");
+    assert_eq!(records[1].password.as_ref().unwrap(), "pa,ss");
+    assert_eq!(records[2].title, "Server");
+    assert_eq!(records[2].notes.as_ref().unwrap(), "root access");
+    assert_eq!(proton::parse_zip(&data).err(), Some(V1KpdbError::ImportErr));
+
+    let mut damaged = data.clone();
+    damaged[60] ^= 0xff;
+    assert_eq!(dashlane::parse_zip(&damaged).err(), Some(V1KpdbError::ImportErr));
+    assert_eq!(zip::files(b"credentials.csv").err(), Some(V1KpdbError::ImportErr));
+
+    let mut data = vec![];
+    File::open("test/proton_export.zip").unwrap().read_to_end(&mut data).unwrap();
+    assert_eq!(zip::files(&data).unwrap()[0].base_name(), "data.json");
+    let records = proton::parse_zip(&data).unwrap();
+    assert_eq!(records.len(), 2);
+    assert_eq!(records[0].group_path, vec!["Work".to_string()]);
+    assert_eq!(records[0].password.as_ref().unwrap(), "secret");
+    assert_eq!(records[1].title, "Alias");
+    assert_eq!(dashlane::parse_zip(&data).err(), Some(V1KpdbError::ImportErr));
+}
+
+#[test]
+fn test_browser_import() {
+    let data = "name,url,username,password,note\n\
+                Mail,https://mail.example.com/login,alice,secret,Work account\n\
+                ,https://shop.example.com/,bob,hunter2,\n";
+    let records = browser::parse_csv(data.as_bytes()).unwrap();
+    assert_eq!(records.len(), 2);
+    assert_eq!(records[0].group_path, vec!["Chrome".to_string()]);
+    assert_eq!(records[0].title, "Mail");
+    assert_eq!(records[0].username.as_ref().unwrap(), "alice");
+    assert_eq!(records[0].password.as_ref().unwrap(), "secret");
+    assert_eq!(records[0].url.as_ref().unwrap(), "https://mail.example.com/login");
+    assert_eq!(records[0].notes.as_ref().unwrap(), "Work account");
+    assert_eq!(records[1].title, "shop.example.com");
+    assert!(records[1].notes.is_none());
+
+    let data = "\"url\",\"username\",\"password\",\"httpRealm\",\"formActionOrigin\",\"guid\",\
+                \"timeCreated\",\"timePasswordChanged\",\"timeLastUsed\"\n\
+                \"https://router.example.com\",\"admin\",\"pa,ss\",\"Router\",\"\",\
+                \"{0b4a}\",\"1700000000000\",\"1700000000000\",\"1700000000000\"\n";
+    let records = browser::parse_csv(data.as_bytes()).unwrap();
+    assert_eq!(records.len(), 1);
+    assert_eq!(records[0].group_path, vec!["Firefox".to_string()]);
+    assert_eq!(records[0].title, "router.example.com");
+    assert_eq!(records[0].password.as_ref().unwrap(), "pa,ss");
+    assert_eq!(records[0].notes.as_ref().unwrap(), "HTTP realm: Router");
+
+    let data = "Title,URL,Username,Password,Notes,OTPAuth\n\
+                Bank,https://bank.example.com,carol,secret,,JBSWY3DPEHPK3PXP\n";
+    let records = browser::parse_csv(data.as_bytes()).unwrap();
+    assert_eq!(records[0].group_path, vec!["Safari".to_string()]);
+    assert_eq!(records[0].notes.as_ref().unwrap(),
+               "otpauth://totp/Bank?secret=JBSWY3DPEHPK3PXP");
+    assert!(validate(&records).is_clean());
+    assert_eq!(browser::parse_csv(b"name,username\nMail,alice").err(),
+               Some(V1KpdbError::ImportErr));
+}
+
+// A Password Safe v3 file of records, each a list of field types and
+// texts, written like Password Safe does
+fn psafe3_file(passphrase: &[u8], records: &[Vec<(u8, &[u8])>]) -> Vec<u8> {
+    let salt = [1u8; 32];
+    let iterations = 2048u32;
+    let mut stretched = hash(Type::SHA256, &[passphrase, &salt[..]].concat());
+    for _ in 0..iterations {
+        stretched = hash(Type::SHA256, &stretched);
+    }
+    let (record_key, hmac_key, iv) = ([2u8; 32], [3u8; 32], [4u8; 16]);
+
+    let mut file = b"PWS3".to_vec();
+    file.extend_from_slice(&salt);
+    file.extend_from_slice(&[0x00, 0x08, 0x00, 0x00]);
+    file.extend(hash(Type::SHA256, &stretched));
+    let twofish = Twofish::new(&stretched);
+    for key in [&record_key, &hmac_key].iter() {
+        let mut blocks = key.to_vec();
+        for block in blocks.chunks_mut(16) {
+            twofish.encrypt_block(block);
+        }
+        file.extend(blocks);
+    }
+    file.extend_from_slice(&iv);
+
+    // The version of the format and the end of the header first
+    let mut fields: Vec<(u8, &[u8])> = vec![(0x00, &[0x0d, 0x03]), (0xff, &[])];
+    for record in records {
+        fields.extend(record.iter().cloned());
+        fields.push((0xff, &[]));
+    }
+    let mut plaintext = vec![];
+    let mut data = vec![];
+    for &(kind, value) in fields.iter() {
+        let len = value.len() as u32;
+        plaintext.extend_from_slice(&[len as u8, (len >> 8) as u8, (len >> 16) as u8,
+                                      (len >> 24) as u8, kind]);
+        plaintext.extend_from_slice(value);
+        while plaintext.len() % 16 != 0 {
+            plaintext.push(0x5a);
+        }
+        data.extend_from_slice(value);
+    }
+    let twofish = Twofish::new(&record_key);
+    let mut previous = iv.to_vec();
+    for block in plaintext.chunks_mut(16) {
+        for (b, p) in block.iter_mut().zip(previous.iter()) {
+            *b ^= *p;
+        }
+        twofish.encrypt_block(block);
+        previous = block.to_vec();
+    }
+    file.extend(plaintext);
+    file.extend_from_slice(psafe3::EOF_MARKER);
+    file.extend(hmac(Type::SHA256, &hmac_key, &data));
+    file
+}
+
+#[test]
+fn test_psafe3_import() {
+    let expire = [0x00, 0xf1, 0x53, 0x65];
+    let records = vec![vec![(0x02, &b"Internet.Mail\\.Server"[..]),
+                            (0x03, b"Mail"),
+                            (0x04, b"alice"),
+                            (0x06, b"a password longer than a single block"),
+                            (0x05, b"first\r\nsecond"),
+                            (0x0d, b"https://mail.example.com"),
+                            (0x0a, &expire[..])],
+                       vec![(0x03, b"Shop"), (0x14, b"bob@example.com"), (0x06, b"secret")]];
+    let file = psafe3_file(b"passphrase", &records);
+    let imported = psafe3::read(&file[..], b"passphrase").unwrap();
+    assert_eq!(imported.len(), 2);
+    assert_eq!(imported[0].group_path,
+               vec!["Internet".to_string(), "Mail.Server".to_string()]);
+    assert_eq!(imported[0].title, "Mail");
+    assert_eq!(imported[0].username.as_ref().unwrap(), "alice");
+    assert_eq!(imported[0].password.as_ref().unwrap(),
+               "a password longer than a single block");
+    assert_eq!(imported[0].notes.as_ref().unwrap(), "first\nsecond");
+    assert_eq!(imported[0].url.as_ref().unwrap(), "https://mail.example.com");
+    assert_eq!(imported[0].expire, Some(Local.timestamp(1700000000, 0)));
+    assert!(imported[1].group_path.is_empty());
+    assert_eq!(imported[1].username.as_ref().unwrap(), "bob@example.com");
+    assert!(validate(&imported).is_clean());
+
+    assert_eq!(psafe3::parse(&file, b"wrong").err(), Some(V1KpdbError::HashErr));
+    let mut changed = file.clone();
+    let len = changed.len();
+    changed[len - 1] ^= 1;
+    assert_eq!(psafe3::parse(&changed, b"passphrase").err(),
+               Some(V1KpdbError::HashErr));
+    let mut slow = file.clone();
+    slow[36..40].copy_from_slice(&[0xff, 0xff, 0xff, 0xff]);
+    assert_eq!(psafe3::parse(&slow, b"passphrase").err(),
+               Some(V1KpdbError::RoundsErr));
+    assert_eq!(psafe3::parse(b"PWS2", b"passphrase").err(),
+               Some(V1KpdbError::ImportErr));
+}
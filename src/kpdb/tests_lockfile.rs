@@ -0,0 +1,113 @@
+use std::env;
+use std::fs::{self, File};
+use std::io::Write;
+
+use chrono::{Duration, Local};
+
+use kpdb::lockfile::{inspect, lock_path, write_lock, FileLock, LockInfo};
+use kpdb::v1error::V1KpdbError;
+use kpdb::v1kpdb::V1Kpdb;
+
+fn tmp_path(name: &str) -> String {
+    let mut path = env::temp_dir();
+    path.push(name);
+    path.to_str().unwrap().to_string()
+}
+
+#[test]
+fn test_inspect() {
+    let path = tmp_path("rust_keepass_test_inspect.kdb");
+    let _ = fs::remove_file(lock_path(&path));
+    assert_eq!(inspect(&path), Ok(None));
+
+    let mut info = LockInfo::current();
+    info.user = "alice".to_string();
+    info.host = "laptop".to_string();
+    info.time = info.time - Duration::hours(2);
+    assert!(write_lock(&path, &info).is_ok());
+
+    let read = inspect(&path).ok().unwrap().unwrap();
+    assert_eq!(read.user, "alice");
+    assert_eq!(read.host, "laptop");
+    assert_eq!(read.pid, info.pid);
+    assert_eq!(read.time.timestamp(), info.time.timestamp());
+    assert!(read.age() >= Duration::hours(2));
+    assert!(!read.is_own());
+
+    let mut file = File::create(lock_path(&path)).unwrap();
+    let _ = file.write_all(b"");
+    assert_eq!(inspect(&path), Err(V1KpdbError::LockErr));
+    let _ = fs::remove_file(lock_path(&path));
+}
+
+#[test]
+fn test_parse() {
+    let info = LockInfo::parse("host=box\nfoo=bar\nuser=bob\npid=12\ntime=2015-08-01T10:00:00+02:00")
+                   .ok()
+                   .unwrap();
+    assert_eq!(info.host, "box");
+    assert_eq!(info.pid, 12);
+    assert!(info.time < Local::now());
+    assert!(LockInfo::parse("user=bob\npid=x").is_err());
+    assert!(LockInfo::current().is_own());
+}
+
+#[test]
+fn test_file_lock() {
+    let path = tmp_path("rust_keepass_test_file_lock.kdb");
+    let _ = fs::remove_file(lock_path(&path));
+    let lock = FileLock::acquire(&path).ok().unwrap();
+    assert!(lock.is_held());
+    assert!(inspect(&path).ok().unwrap().unwrap().is_own());
+    assert_eq!(FileLock::acquire(&path).err(), Some(V1KpdbError::FileLockedErr));
+    drop(lock);
+    assert_eq!(inspect(&path), Ok(None));
+
+    // A lock taken over by someone else isn't removed
+    let lock = FileLock::acquire(&path).ok().unwrap();
+    let mut info = LockInfo::current();
+    info.user = "alice".to_string();
+    info.pid += 1;
+    assert!(write_lock(&path, &info).is_ok());
+    assert!(!lock.is_held());
+    drop(lock);
+    assert_eq!(inspect(&path).ok().unwrap().unwrap().user, "alice");
+
+    let lock = FileLock::force(&path).ok().unwrap();
+    assert!(lock.is_held());
+    drop(lock);
+    assert_eq!(inspect(&path), Ok(None));
+}
+
+#[test]
+fn test_save_locked() {
+    let path = tmp_path("rust_keepass_test_save_locked.kdb");
+    let _ = fs::remove_file(lock_path(&path));
+    fs::copy("test/test_parsing.kdb", &path).unwrap();
+    let mut db = V1Kpdb::new(path.clone(), Some("test".to_string()), None).ok().unwrap();
+    assert!(db.load().is_ok());
+
+    let mut info = LockInfo::current();
+    info.host = "laptop".to_string();
+    assert!(write_lock(&path, &info).is_ok());
+    // The meta entries aren't updated for a save which can't happen
+    db.equivalent_domains.add(vec!["amazon.com".to_string(), "amazon.de".to_string()]);
+    let num_meta_entries = db.meta_entries.len();
+    assert_eq!(db.save(None, None, None), Err(V1KpdbError::FileLockedErr));
+    assert_eq!(db.meta_entries.len(), num_meta_entries);
+    assert_eq!(db.acquire_file_lock(false), Err(V1KpdbError::FileLockedErr));
+    assert!(db.acquire_file_lock(true).is_ok());
+    assert!(db.save(None, None, None).is_ok());
+
+    // The lock moves with the database
+    let moved = tmp_path("rust_keepass_test_save_locked_moved.kdb");
+    let _ = fs::remove_file(lock_path(&moved));
+    assert!(db.save(Some(moved.clone()), None, None).is_ok());
+    assert_eq!(inspect(&path), Ok(None));
+    assert!(inspect(&moved).ok().unwrap().unwrap().is_own());
+    db.release_file_lock();
+    assert_eq!(inspect(&moved), Ok(None));
+    assert!(db.file_lock().is_none());
+    let _ = fs::remove_file(&path);
+    let _ = fs::remove_file(&moved);
+}
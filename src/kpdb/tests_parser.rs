@@ -2,13 +2,19 @@
 use std::io::{Seek, SeekFrom, Read, Write};
 use std::fs::File;
 
-use chrono::Datelike;
+use chrono::{Datelike, Local, TimeZone};
 use uuid::Uuid;
 
 use kpdb::crypter::Crypter;
 use kpdb::parser::{HeaderLoadParser, LoadParser,SaveParser};
+use kpdb::recovery::{RecordKind, RecoveryReport};
+use kpdb::time;
+use kpdb::v1entry::never_expires;
+use kpdb::v1error::V1KpdbError;
 use kpdb::v1header::V1Header;
 use kpdb::v1kpdb::V1Kpdb;
+use kpdb::v1parser::parse_decrypted;
+use mem_protect;
 use super::super::sec_str::SecureString;
 
 fn setup(path: String, password: Option<SecureString>, keyfile: Option<SecureString>) -> LoadParser {
@@ -84,13 +90,13 @@ fn test_parse_entries() {
     }
 
     entries[0].borrow_mut().username.as_mut().unwrap().unlock();
-    entries[0].borrow_mut().password.as_mut().unwrap().unlock();
+    entries[0].borrow_mut().raw_password_mut().unwrap().unlock();
 
     assert_eq!(entries[0].borrow().uuid, uuid);
     assert_eq!(entries[0].borrow().title, "foo");
     assert_eq!(entries[0].borrow().url, Some("foo".to_string()));
     assert_eq!(entries[0].borrow().username.as_ref().unwrap().string, "foo");
-    assert_eq!(entries[0].borrow().password.as_ref().unwrap().string,
+    assert_eq!(entries[0].borrow().raw_password().unwrap().string,
                "DLE\"H<JZ|E");
     assert_eq!(entries[0].borrow().image, 1);
     assert_eq!(entries[0].borrow().group_id, 1);
@@ -238,3 +244,199 @@ fn test_prepare_save() {
     assert_eq!(test_4[..], parser.database[216..252]);
 }
 
+
+// Raw field: type, size and data
+fn field(field_type: u16, data: &[u8]) -> Vec<u8> {
+    let mut raw = vec![field_type as u8, (field_type >> 8) as u8];
+    let size = data.len() as u32;
+    raw.extend(&[size as u8, (size >> 8) as u8, (size >> 16) as u8, (size >> 24) as u8]);
+    raw.extend(data);
+    raw
+}
+
+#[test]
+fn test_parse_corrupt_data() {
+    let end = field(0xFFFF, &[]);
+
+    // More groups than the data can hold
+    let mut parser = LoadParser::new(end.clone(), 0xFFFFFFFF, 0);
+    assert_eq!(parser.parse_groups().err(), Some(V1KpdbError::OffsetErr));
+
+    // Field larger than the remaining data
+    let mut raw = field(0x0002, b"title\0");
+    raw[2] = 0xFF;
+    raw.extend(&end);
+    let mut parser = LoadParser::new(raw, 1, 0);
+    assert_eq!(parser.parse_groups().err(), Some(V1KpdbError::OffsetErr));
+
+    // Truncated field header
+    let mut parser = LoadParser::new(vec![0x01, 0x00, 0x04], 1, 0);
+    assert_eq!(parser.parse_groups().err(), Some(V1KpdbError::OffsetErr));
+
+    // Invalid date (month 0) and too short date
+    let mut raw = field(0x0003, &[0x1F, 0x40, 0x00, 0x00, 0x00]);
+    raw.extend(&end);
+    let mut parser = LoadParser::new(raw, 1, 0);
+    assert_eq!(parser.parse_groups().err(), Some(V1KpdbError::ConvertErr));
+    let mut raw = field(0x0009, &[0x1F]);
+    raw.extend(&end);
+    let mut parser = LoadParser::new(raw, 0, 1);
+    assert_eq!(parser.parse_entries().err(), Some(V1KpdbError::ConvertErr));
+
+    // Invalid UUID and password which isn't UTF-8
+    let mut raw = field(0x0001, &[1, 2, 3]);
+    raw.extend(&end);
+    let mut parser = LoadParser::new(raw, 0, 1);
+    assert_eq!(parser.parse_entries().err(), Some(V1KpdbError::ConvertErr));
+    let mut raw = field(0x0007, &[0xFF, 0xFE, 0x00]);
+    raw.extend(&end);
+    let mut parser = LoadParser::new(raw, 0, 1);
+    assert_eq!(parser.parse_entries().err(), Some(V1KpdbError::ConvertErr));
+
+    // Empty strings without null byte are fine
+    let mut raw = field(0x0004, &[]);
+    raw.extend(field(0x0007, &[]));
+    raw.extend(&end);
+    let mut parser = LoadParser::new(raw, 0, 1);
+    let entries = parser.parse_entries().ok().unwrap();
+    assert_eq!(entries[0].borrow().title, "");
+}
+
+#[test]
+fn test_create_group_tree_without_levels() {
+    let mut db = V1Kpdb::new("test/test_password.kdb".to_string(),
+                             Some("test".to_string()),
+                             None)
+                     .ok()
+                     .unwrap();
+    let mut raw = field(0x0001, &[1, 0, 0, 0]);
+    raw.extend(field(0xFFFF, &[]));
+    let mut parser = LoadParser::new(raw, 1, 0);
+    let (groups, levels) = parser.parse_groups().ok().unwrap();
+    db.groups = groups;
+    assert_eq!(LoadParser::create_group_tree(&mut db, levels).err(),
+               Some(V1KpdbError::TreeErr));
+}
+
+#[test]
+fn test_salvage_records() {
+    let end = field(0xFFFF, &[]);
+
+    // Group with level 1 after a damaged group, the second field of
+    // which is invalid
+    let mut raw = field(0x0002, b"first\0");
+    raw.extend(&end);
+    let damaged = raw.len();
+    raw.extend(field(0x0002, b"damaged\0"));
+    raw.extend(field(0x0003, &[0x1F, 0x40, 0x00, 0x00, 0x00]));
+    raw.extend(&end);
+    raw.extend(field(0x0002, b"third\0"));
+    raw.extend(field(0x0008, &[0x02, 0x00]));
+    raw.extend(&end);
+    // One intact entry, then the data ends in the middle of the second
+    raw.extend(field(0x0004, b"entry\0"));
+    raw.extend(&end);
+    raw.extend(field(0x0004, b"cut off\0"));
+    raw.extend(&[0x05, 0x00]);
+
+    let mut report = RecoveryReport::new();
+    let mut parser = LoadParser::new(raw, 3, 3);
+    let (groups, mut levels) = parser.salvage_groups(&mut report);
+    let entries = parser.salvage_entries(&mut report);
+    assert_eq!(groups.len(), 2);
+    assert_eq!(groups[1].borrow().title, "third");
+    assert_eq!(entries.len(), 1);
+    assert_eq!(entries[0].borrow().title, "entry");
+    assert_eq!(report.dropped.len(), 1);
+    assert_eq!(report.dropped[0].kind, RecordKind::Group);
+    assert_eq!(report.dropped[0].index, 1);
+    assert_eq!(report.dropped[0].offset, damaged);
+    assert_eq!(report.dropped[0].error, V1KpdbError::ConvertErr);
+    assert!(report.truncated);
+    assert_eq!(report.missing_groups, 0);
+    assert_eq!(report.missing_entries, 2);
+
+    assert_eq!(LoadParser::repair_levels(&groups, &mut levels), 1);
+    assert_eq!(levels, vec![0, 1]);
+    assert_eq!(groups[1].borrow().level, 1);
+}
+
+#[test]
+fn test_packed_dates() {
+    let date = Local.ymd(2015, 2, 28).and_hms(13, 45, 59);
+    assert_eq!(time::unpack(&time::pack(&date)).ok(), Some(date));
+    // Fractions of a second are dropped
+    let fraction = Local.ymd(2015, 2, 28).and_hms_nano(13, 45, 59, 500);
+    assert_eq!(time::unpack(&time::pack(&fraction)).ok(), Some(date));
+
+    assert_eq!(time::pack(&Local.ymd(3500, 1, 1).and_hms(0, 0, 0)),
+               time::pack(&never_expires()));
+    assert_eq!(time::unpack(&[0, 0, 0, 0, 0]).err(), Some(V1KpdbError::ConvertErr));
+    assert_eq!(time::unpack_expiry(&[0, 0, 0, 0, 0]).ok(), Some(never_expires()));
+    assert_eq!(time::unpack(&[0x1F, 0x7C]).err(), Some(V1KpdbError::ConvertErr));
+}
+
+#[test]
+fn test_system_time() {
+    let date = Local.ymd(2015, 2, 28).and_hms_nano(13, 45, 59, 123_456_789);
+    assert_eq!(time::from_system_time(time::to_system_time(&date)), date);
+    let before_epoch = Local.ymd(1960, 7, 1).and_hms_nano(0, 0, 1, 999_999_999);
+    assert_eq!(time::from_system_time(time::to_system_time(&before_epoch)), before_epoch);
+    assert!(time::to_system_time(&before_epoch) < time::to_system_time(&date));
+}
+
+#[test]
+fn test_parse_decrypted() {
+    let mut raw = vec![];
+    let _ = File::open("test/test_password.kdb").unwrap().read_to_end(&mut raw);
+    let header = V1Header::parse(&raw).unwrap();
+    assert_eq!(header.num_groups, 2);
+    assert_eq!(V1Header::parse(&raw[..123]).err(), Some(V1KpdbError::FileErr));
+    let mut crypter = Crypter::new(Some(SecureString::new("test".to_string())), None);
+    let content = crypter.decrypt_database(&header, raw[124..].to_vec()).unwrap();
+
+    let tree = parse_decrypted(&content).unwrap();
+    assert_eq!(tree.groups.len(), 2);
+    assert_eq!(tree.entries.len() + tree.meta_entries.len(), header.num_entries as usize);
+    assert_eq!(tree.root_group.borrow().children.len(), 2);
+    assert_eq!(tree.entries[0].borrow().group.as_ref().unwrap().borrow().title, "Internet");
+
+    // Every prefix and damaged bytes give an error or a tree, no panic
+    for len in 0..content.len() {
+        let _ = parse_decrypted(&content[..len]);
+    }
+    let mut damaged = content.clone();
+    mem_protect::lock(&damaged, "damaged");
+    for index in 0..damaged.len() {
+        damaged[index] ^= 0xA5;
+        let _ = parse_decrypted(&damaged);
+        damaged[index] ^= 0xA5;
+    }
+    unsafe {
+        mem_protect::zero(&damaged);
+        mem_protect::zero(&content);
+    }
+    mem_protect::unlock(&damaged);
+    mem_protect::unlock(&content);
+
+    let group = |id: &[u8]| {
+        let mut raw = field(0x0001, id);
+        raw.extend(field(0x0008, &[0, 0]));
+        raw.extend(field(0xFFFF, &[]));
+        raw
+    };
+    assert_eq!(parse_decrypted(&[]).unwrap().groups.len(), 0);
+    // A record without end marker, without an id and a group after an
+    // entry
+    assert_eq!(parse_decrypted(&field(0x0001, &[1, 0, 0, 0])).err(),
+               Some(V1KpdbError::OffsetErr));
+    assert_eq!(parse_decrypted(&field(0xFFFF, &[])).err(), Some(V1KpdbError::ConvertErr));
+    let mut raw = group(&[1, 0, 0, 0]);
+    raw.extend(group(&[7; 16]));
+    raw.extend(group(&[2, 0, 0, 0]));
+    assert_eq!(parse_decrypted(&raw).err(), Some(V1KpdbError::ConvertErr));
+    // A field size beyond the data isn't allocated
+    let mut raw = group(&[1, 0, 0, 0]);
+    raw.extend(&[0x02, 0x00, 0xFF, 0xFF, 0xFF, 0x7F]);
+    assert_eq!(parse_decrypted(&raw).err(), Some(V1KpdbError::OffsetErr));
+}
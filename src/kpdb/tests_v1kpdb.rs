@@ -1,7 +1,71 @@
+use std::cell::RefCell;
+use std::env;
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+#[cfg(feature = "secret-audit")]
+use std::panic;
+use std::path::PathBuf;
+use std::rc::Rc;
+#[cfg(feature = "log")]
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
+
 use chrono::{Timelike, Local, TimeZone, Datelike};
+#[cfg(unix)]
+use libc;
+#[cfg(feature = "log")]
+use log::{self, LevelFilter, Log, Metadata, Record};
+use rustc_serialize::hex::FromHex;
+use rustc_serialize::json::ToJson;
 
+use kpdb::advisor::{password_strength, Advice, Priority};
+use kpdb::agent::Agent;
+use kpdb::autolock::LockedKpdb;
+use kpdb::breach::{BreachHash, BreachList};
+use kpdb::builder::{Cipher, ReencryptOptions, V1KpdbBuilder, DEFAULT_GROUP_TITLE};
+use kpdb::conformance::{self, Violation};
+use kpdb::diff;
+use kpdb::diff::{EntryField, GroupField};
+use kpdb::domains::EquivalentDomains;
+use kpdb::duplicates::DuplicateCriteria;
+#[cfg(feature = "serde")]
+use kpdb::dump::FlatTree;
+use kpdb::crypter::{CancelToken, CompositeKey, KeyComponent, KeyProvider};
+use kpdb::error::{HeaderField, KpdbError};
+use kpdb::export::archive;
+use kpdb::fixtures::{self, FixtureSpec};
+#[cfg(unix)]
+use kpdb::fdkey;
+use kpdb::handle::EntryHandle;
+use kpdb::limits::v1;
+use kpdb::merge::{Decision, DuplicateOnConflict, NewestWins};
+use kpdb::order::SortKey;
+use kpdb::meta::{new_meta_entry, MetaInfo, CUSTOM_ICONS_STREAM};
+use kpdb::parser::SaveParser;
+use kpdb::path;
+use kpdb::placeholders;
+use kpdb::policy::{KeyFactor, UnlockPolicy};
+use kpdb::recovery::RECOVERED_GROUP_TITLE;
+use kpdb::reveal::ProtectedField;
+use kpdb::search::{is_backup_group, SearchQuery};
+use kpdb::shared::SharedKpdb;
+use kpdb::timeline::TimelineKind;
+use kpdb::undo::Snapshot;
+use kpdb::usage::{UsageEvent, UsageKind};
+use kpdb::v1entry::V1Entry;
+use kpdb::v1group::V1Group;
 use kpdb::v1kpdb::V1Kpdb;
 use kpdb::v1error::V1KpdbError;
+use mem_protect::{self, MemLockPolicy};
+use sec_str::{SecureBytes, SecureString};
+use sec_str::shadow;
+#[cfg(feature = "secret-audit")]
+use security;
+#[cfg(feature = "serde")]
+use serde_json;
+#[cfg(feature = "tokio")]
+use tokio;
 
 #[test]
 fn test_new() {
@@ -27,9 +91,10 @@ fn test_new() {
                          None);
     assert!(result.is_ok());
     db = result.ok().unwrap();
+    // The wrong key garbles the padding of this file
     match db.load() {
         Ok(_) => assert!(false),
-        Err(e) => assert_eq!(e, V1KpdbError::HashErr),
+        Err(e) => assert_eq!(e, V1KpdbError::DecryptErr),
     };
 }
 
@@ -44,6 +109,371 @@ fn test_save() {
     assert!(db.load().is_ok());
 }
 
+// Copy a test database into the temp dir so that tests which save don't
+// touch the files under test/
+fn copy_to_tmp(src: &str, name: &str) -> String {
+    let mut path = env::temp_dir();
+    path.push(name);
+    let path = path.to_str().unwrap().to_string();
+    assert!(fs::copy(src, &path).is_ok());
+    path
+}
+
+// Depending on the random IV a wrong key shows up as invalid padding or
+// as wrong hash
+fn is_wrong_key(result: Result<(), V1KpdbError>) -> bool {
+    result == Err(V1KpdbError::DecryptErr) || result == Err(V1KpdbError::HashErr)
+}
+
+// Temporary files a save left next to path
+fn tmp_files(path: &str) -> Vec<PathBuf> {
+    let path = PathBuf::from(path);
+    let prefix = format!("{}.", path.file_name().unwrap().to_str().unwrap());
+    let entries = match fs::read_dir(path.parent().unwrap()) {
+        Ok(entries) => entries,
+        Err(_) => return vec![],
+    };
+    entries.filter_map(|e| e.ok())
+           .map(|e| e.path())
+           .filter(|p| {
+               let name = p.file_name().unwrap().to_str().unwrap_or("");
+               name.starts_with(&prefix) && name.ends_with(".tmp")
+           })
+           .collect()
+}
+
+fn read_file(path: &str) -> Vec<u8> {
+    let mut raw: Vec<u8> = vec![];
+    let _ = File::open(path).unwrap().read_to_end(&mut raw);
+    raw
+}
+
+#[test]
+fn test_save_atomic_w_backup() {
+    let path = copy_to_tmp("test/test_password.kdb", "rust_keepass_test_save_atomic.kdb");
+    let backup_path = format!("{}.bak", path);
+    let _ = fs::remove_file(&backup_path);
+    let original = read_file(&path);
+
+    let mut db = V1Kpdb::new(path.clone(), Some("test".to_string()), None).ok().unwrap();
+    assert!(db.load().is_ok());
+    db.keep_backup = true;
+    assert!(db.save(None, None, None).is_ok());
+
+    assert_eq!(read_file(&backup_path), original);
+    assert!(tmp_files(&path).is_empty());
+    assert!(read_file(&path) != original);
+    assert!(db.load().is_ok());
+
+    let _ = fs::remove_file(&backup_path);
+    let _ = fs::remove_file(&path);
+}
+
+#[test]
+fn test_save_keeps_other_files_and_permissions() {
+    let path = copy_to_tmp("test/test_password.kdb", "rust_keepass_test_save_tmp.kdb");
+    let other_path = format!("{}.tmp", path);
+    {
+        let mut other = File::create(&other_path).unwrap();
+        assert!(other.write_all(b"not a database").is_ok());
+    }
+    let original = read_file(&path);
+    let mut permissions = fs::metadata(&path).unwrap().permissions();
+    permissions.set_readonly(true);
+    assert!(fs::set_permissions(&path, permissions).is_ok());
+
+    // A read-only database isn't replaced
+    let mut db = V1Kpdb::new(path.clone(), Some("test".to_string()), None).ok().unwrap();
+    assert!(db.load().is_ok());
+    assert_eq!(db.save(None, None, None), Err(V1KpdbError::FileErr));
+    assert_eq!(read_file(&path), original);
+    assert!(fs::metadata(&path).unwrap().permissions().readonly());
+    assert_eq!(tmp_files(&path), vec![PathBuf::from(&other_path)]);
+
+    let mut permissions = fs::metadata(&path).unwrap().permissions();
+    permissions.set_readonly(false);
+    assert!(fs::set_permissions(&path, permissions).is_ok());
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        assert!(fs::set_permissions(&path, fs::Permissions::from_mode(0o640)).is_ok());
+    }
+    assert!(db.save(None, None, None).is_ok());
+    assert!(db.save(None, None, None).is_ok());
+    assert_eq!(read_file(&other_path), b"not a database".to_vec());
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        assert_eq!(fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o640);
+    }
+    assert_eq!(tmp_files(&path), vec![PathBuf::from(&other_path)]);
+    assert!(db.load().is_ok());
+
+    let _ = fs::remove_file(&other_path);
+    let _ = fs::remove_file(&path);
+}
+
+#[cfg(unix)]
+#[test]
+fn test_save_through_symlink() {
+    use std::os::unix::fs::{symlink, PermissionsExt};
+
+    let target = copy_to_tmp("test/test_password.kdb", "rust_keepass_test_symlink_target.kdb");
+    let link = format!("{}.link", target);
+    let _ = fs::remove_file(&link);
+    assert!(symlink(&target, &link).is_ok());
+    let mut db = V1Kpdb::new(link.clone(), Some("test".to_string()), None).ok().unwrap();
+    assert!(db.load().is_ok());
+    db.entries[0].borrow_mut().title = "Through the link".to_string();
+    assert!(db.save(None, None, None).is_ok());
+    assert!(fs::symlink_metadata(&link).unwrap().file_type().is_symlink());
+    assert_eq!(read_file(&link), read_file(&target));
+    assert!(tmp_files(&target).is_empty());
+
+    // A new database is only readable by its owner
+    let mut new_path = env::temp_dir();
+    new_path.push("rust_keepass_test_new_mode.kdb");
+    let new_path = new_path.to_str().unwrap().to_string();
+    let _ = fs::remove_file(&new_path);
+    assert!(db.save(Some(new_path.clone()), None, None).is_ok());
+    assert_eq!(fs::metadata(&new_path).unwrap().permissions().mode() & 0o777, 0o600);
+
+    let _ = fs::remove_file(&link);
+    let _ = fs::remove_file(&target);
+    let _ = fs::remove_file(&new_path);
+}
+
+#[test]
+fn test_save_rolling_backups() {
+    let path = copy_to_tmp("test/test_password.kdb", "rust_keepass_test_rolling.kdb");
+    let mut backup_dir = env::temp_dir();
+    backup_dir.push("rust_keepass_test_rolling_backups");
+    let _ = fs::remove_dir_all(&backup_dir);
+
+    let mut db = V1Kpdb::new(path.clone(), Some("test".to_string()), None).ok().unwrap();
+    assert!(db.load().is_ok());
+    db.save_options.backups = 2;
+    db.save_options.backup_dir = Some(backup_dir.clone());
+    let mut versions = vec![read_file(&path)];
+    for _ in 0..3 {
+        assert!(db.save(None, None, None).is_ok());
+        versions.push(read_file(&path));
+    }
+
+    let backups = db.save_options.backups_of(&path);
+    assert_eq!(backups.len(), 2);
+    assert_eq!(backups[0], backup_dir.join("rust_keepass_test_rolling.kdb.1"));
+    assert_eq!(read_file(backups[0].to_str().unwrap()), versions[2]);
+    assert_eq!(read_file(backups[1].to_str().unwrap()), versions[1]);
+    assert!(fs::metadata(db.save_options.backup_path(&path, 3)).is_err());
+
+    db.save_options.backup_dir = None;
+    assert_eq!(db.save_options.backup_path(&path, 1),
+               PathBuf::from(format!("{}.1", path)));
+
+    let _ = fs::remove_dir_all(&backup_dir);
+    let _ = fs::remove_file(&path);
+}
+
+#[test]
+fn test_save_regenerates_seeds() {
+    let path = copy_to_tmp("test/test_password.kdb", "rust_keepass_test_seeds.kdb");
+    let mut db = V1Kpdb::new(path.clone(), Some("test".to_string()), None).ok().unwrap();
+    assert!(db.load().is_ok());
+    assert!(db.header.set_key_transf_rounds(100).is_ok());
+    assert_eq!(db.header.set_key_transf_rounds(0), Err(V1KpdbError::RoundsErr));
+
+    let mut seeds = vec![];
+    for _ in 0..2 {
+        let old = db.header.clone();
+        assert!(db.save(None, None, None).is_ok());
+        assert_eq!(db.header.final_randomseed.len(), 16);
+        assert_eq!(db.header.iv.len(), 16);
+        assert_eq!(db.header.transf_randomseed.len(), 32);
+        assert!(db.header.iv != old.iv);
+        assert!(db.header.final_randomseed != old.final_randomseed);
+        assert!(db.header.transf_randomseed != old.transf_randomseed);
+        seeds.push(db.header.iv.clone());
+    }
+    assert!(seeds[0] != seeds[1]);
+
+    let mut reopened = V1Kpdb::new(path.clone(), Some("test".to_string()), None).ok().unwrap();
+    assert!(reopened.load().is_ok());
+    assert_eq!(reopened.header.iv, db.header.iv);
+    assert_eq!(reopened.header.key_transf_rounds, 100);
+    let _ = fs::remove_file(&path);
+}
+
+#[test]
+fn test_load_from_bytes() {
+    let db = open_parsing_db();
+    let raw = read_file("test/test_parsing.kdb");
+    let mut loaded = V1Kpdb::new(String::new(), Some("test".to_string()), None).ok().unwrap();
+    assert!(loaded.load_from_bytes(&raw).is_ok());
+    assert_eq!(loaded.groups.len(), db.groups.len());
+    assert_eq!(loaded.entries.len(), db.entries.len());
+
+    let mut read = V1Kpdb::new(String::new(), Some("test".to_string()), None).ok().unwrap();
+    assert!(read.load_from_reader(File::open("test/test_parsing.kdb").unwrap()).is_ok());
+    assert_eq!(read.entries.len(), db.entries.len());
+
+    let mut wrong = V1Kpdb::new(String::new(), Some("wrong".to_string()), None).ok().unwrap();
+    assert_eq!(wrong.load_from_bytes(&raw), Err(V1KpdbError::DecryptErr));
+    assert_eq!(wrong.load_from_bytes(&raw[..100]), Err(V1KpdbError::FileErr));
+}
+
+#[cfg(feature = "tokio")]
+#[test]
+fn test_load_save_async() {
+    let runtime = tokio::runtime::Builder::new_current_thread().build().unwrap();
+    let path = copy_to_tmp("test/test_parsing.kdb", "rust_keepass_test_async.kdb");
+    let db = open_parsing_db();
+    let mut loaded = V1Kpdb::new(path.clone(), Some("test".to_string()), None).ok().unwrap();
+    assert!(runtime.block_on(loaded.load_async()).is_ok());
+    assert_eq!(loaded.groups.len(), db.groups.len());
+    assert_eq!(loaded.entries.len(), db.entries.len());
+
+    loaded.entries[0].borrow_mut().title = "async".to_string();
+    assert!(runtime.block_on(loaded.save_async(None)).is_ok());
+    let mut reloaded = V1Kpdb::new(path.clone(), Some("test".to_string()), None).ok().unwrap();
+    assert!(reloaded.load().is_ok());
+    assert_eq!(reloaded.entries[0].borrow().title, "async");
+
+    let mut wrong = V1Kpdb::new(path.clone(), Some("wrong".to_string()), None).ok().unwrap();
+    assert_eq!(runtime.block_on(wrong.load_async()), Err(V1KpdbError::DecryptErr));
+    let mut missing = V1Kpdb::new(format!("{}.missing", path), Some("test".to_string()), None)
+                          .ok()
+                          .unwrap();
+    assert_eq!(runtime.block_on(missing.load_async()), Err(V1KpdbError::FileErr));
+    let _ = fs::remove_file(&path);
+}
+
+#[test]
+fn test_load_mmap() {
+    let db = open_parsing_db();
+    let mut mapped = V1Kpdb::new("test/test_parsing.kdb".to_string(),
+                                 Some("test".to_string()),
+                                 None)
+                         .ok()
+                         .unwrap();
+    mapped.use_mmap = true;
+    assert!(mapped.load().is_ok());
+    assert_eq!(mapped.groups.len(), db.groups.len());
+    assert_eq!(mapped.entries.len(), db.entries.len());
+    assert_eq!(mapped.entries[0].borrow_mut().password().map(|p| p.to_string()),
+               db.entries[0].borrow_mut().password().map(|p| p.to_string()));
+    assert!(mapped.load_with_recovery().unwrap().content_intact);
+
+    let mut wrong = V1Kpdb::new("test/test_parsing.kdb".to_string(),
+                                Some("wrong".to_string()),
+                                None)
+                        .ok()
+                        .unwrap();
+    wrong.use_mmap = true;
+    assert!(wrong.load_detailed().unwrap_err().is_wrong_key());
+
+    // Empty files can't be mapped and are read instead
+    let path = copy_to_tmp("test/test_parsing.kdb", "rust_keepass_test_mmap.kdb");
+    let raw = read_file(&path);
+    for len in vec![0, 100] {
+        assert!(File::create(&path).unwrap().write_all(&raw[..len]).is_ok());
+        let mut small = V1Kpdb::new(path.clone(), Some("test".to_string()), None).ok().unwrap();
+        small.use_mmap = true;
+        match small.load_detailed() {
+            Err(KpdbError::TooSmall) => {}
+            _ => assert!(false),
+        }
+    }
+    let _ = fs::remove_file(&path);
+}
+
+#[test]
+fn test_load_streaming() {
+    let db = open_parsing_db();
+    let mut streamed = V1Kpdb::new("test/test_parsing.kdb".to_string(),
+                                   Some("test".to_string()),
+                                   None)
+                           .ok()
+                           .unwrap();
+    assert!(streamed.load_streaming().is_ok());
+    assert_eq!(streamed.groups.len(), db.groups.len());
+    assert_eq!(streamed.entries.len(), db.entries.len());
+    for (a, b) in streamed.groups.iter().zip(db.groups.iter()) {
+        assert_eq!(a.borrow().title, b.borrow().title);
+        assert_eq!(a.borrow().level, b.borrow().level);
+    }
+    for (a, b) in streamed.entries.iter().zip(db.entries.iter()) {
+        assert!(a.borrow_mut().same_content(&mut b.borrow_mut()));
+    }
+
+    // Content over several chunks
+    let path = copy_to_tmp("test/test_password.kdb", "rust_keepass_test_streaming.kdb");
+    let mut db = V1Kpdb::new(path.clone(), Some("test".to_string()), None).ok().unwrap();
+    assert!(db.load().is_ok());
+    let binary: Vec<u8> = (0..200000).map(|i| i as u8).collect();
+    db.entries[0].borrow_mut().binary = Some(SecureBytes::new(binary.clone()));
+    assert!(db.save(None, None, None).is_ok());
+    let mut streamed = V1Kpdb::new(path.clone(), Some("test".to_string()), None).ok().unwrap();
+    assert!(streamed.load_streaming().is_ok());
+    assert_eq!(streamed.entries.len(), db.entries.len());
+    let uuid = db.entries[0].borrow().uuid;
+    let entry = streamed.find_by_uuid(&uuid).unwrap();
+    assert_eq!(entry.borrow().binary.as_ref().map(|b| b.bytes()), Some(&binary[..]));
+
+    let mut wrong = V1Kpdb::new(path.clone(), Some("wrong".to_string()), None).ok().unwrap();
+    assert!(is_wrong_key(wrong.load_streaming()));
+    assert!(wrong.entries.is_empty());
+
+    // A damaged attachment parses but fails the content hash
+    let mut raw = read_file(&path);
+    let middle = raw.len() / 2;
+    raw[middle] ^= 0xff;
+    File::create(&path).unwrap().write_all(&raw).unwrap();
+    let mut damaged = V1Kpdb::new(path.clone(), Some("test".to_string()), None).ok().unwrap();
+    assert_eq!(damaged.load_streaming(), Err(V1KpdbError::HashErr));
+    assert!(damaged.entries.is_empty());
+
+    // A broken group tree leaves the loaded database as it was
+    let _ = fs::remove_file(&path);
+    let path = copy_to_tmp("test/test_password.kdb", "rust_keepass_test_streaming.kdb");
+    let mut db = V1Kpdb::new(path.clone(), Some("test".to_string()), None).ok().unwrap();
+    assert!(db.load().is_ok());
+    let num_groups = db.groups.len();
+    let num_entries = db.entries.len();
+    db.groups[0].borrow_mut().level = 1;
+    assert!(db.save(None, None, None).is_ok());
+    db.groups[0].borrow_mut().level = 0;
+    assert_eq!(db.load_streaming(), Err(V1KpdbError::TreeErr));
+    assert_eq!(db.groups.len(), num_groups);
+    assert_eq!(db.entries.len(), num_entries);
+    assert!(db.entries[0].borrow().group.is_some());
+    assert!(db.validate().is_empty());
+    let _ = fs::remove_file(&path);
+}
+
+#[test]
+fn test_save_failure_keeps_original() {
+    let path = copy_to_tmp("test/test_password.kdb", "rust_keepass_test_save_fail.kdb");
+    let original = read_file(&path);
+
+    let mut db = V1Kpdb::new(path.clone(), Some("test".to_string()), None).ok().unwrap();
+    assert!(db.load().is_ok());
+    // Saving into a directory that doesn't exist must fail without
+    // leaving anything behind
+    let mut bad_path = env::temp_dir();
+    bad_path.push("rust_keepass_no_such_dir");
+    bad_path.push("db.kdb");
+    let bad_path = bad_path.to_str().unwrap().to_string();
+    match db.save(Some(bad_path.clone()), None, None) {
+        Ok(_) => assert!(false),
+        Err(e) => assert_eq!(e, V1KpdbError::FileErr),
+    };
+    assert!(tmp_files(&bad_path).is_empty());
+    assert_eq!(read_file(&path), original);
+
+    let _ = fs::remove_file(&path);
+}
+
 #[test]
 fn test_create_group_w_title_only() {
     let mut result = V1Kpdb::new("test/test_password.kdb".to_string(),
@@ -138,7 +568,7 @@ fn test_create_entry() {
 
     let mut new_entry = db.entries[db.entries.len() - 1].borrow_mut();
     new_entry.username.as_mut().unwrap().unlock();
-    new_entry.password.as_mut().unwrap().unlock();
+    new_entry.raw_password_mut().unwrap().unlock();
     assert_eq!(new_entry.title, "test");
     assert_eq!((new_entry.expire.year(),
                 new_entry.expire.month(),
@@ -152,11 +582,44 @@ fn test_create_entry() {
     assert_eq!(new_entry.url.as_ref().unwrap(), "http://foo");
     assert_eq!(new_entry.comment.as_ref().unwrap(), "foo");
     assert_eq!(new_entry.username.as_ref().unwrap().string, "bar");
-    assert_eq!(new_entry.password.as_ref().unwrap().string, "foobar");
+    assert_eq!(new_entry.raw_password().unwrap().string, "foobar");
 
     assert_eq!(db.header.num_entries, num_entries_before + 1);
 }
 
+#[test]
+fn test_child_groups() {
+    let mut db = open_parsing_db();
+    let parent = db.create_group("Parent".to_string(), None, None, None).unwrap();
+    let first = db.create_group("First".to_string(), None, None, Some(parent.clone())).unwrap();
+    let second = db.create_group("Second".to_string(), None, None, Some(parent.clone())).unwrap();
+    let entry = db.create_entry(first.clone(),
+                                "Entry".to_string(),
+                                None,
+                                None,
+                                None,
+                                None,
+                                None,
+                                None);
+    let titles: Vec<String> = parent.borrow()
+                                    .child_groups()
+                                    .iter()
+                                    .map(|g| g.borrow().title.clone())
+                                    .collect();
+    assert_eq!(titles, vec!["First".to_string(), "Second".to_string()]);
+    assert_eq!(first.borrow().parent_id(), Some(parent.borrow().id));
+    assert_eq!(db.root_group.borrow().parent_id(), None);
+    assert!(Rc::ptr_eq(&first.borrow().child_entries()[0], &entry));
+
+    // Removed groups and entries are gone
+    drop(entry);
+    let entry = first.borrow().child_entries()[0].clone();
+    assert!(db.remove_entry(entry).is_ok());
+    assert!(first.borrow().child_entries().is_empty());
+    assert!(db.remove_group(second).is_ok());
+    assert_eq!(parent.borrow().child_groups().len(), 1);
+}
+
 #[test]
 fn test_remove_group() {
     let mut result = V1Kpdb::new("test/test_parsing.kdb".to_string(),
@@ -230,3 +693,2681 @@ fn test_remove_entry() {
     assert_eq!(db.groups[0].borrow().entries.len(),
                num_entries_in_group - 1);
 }
+
+#[test]
+fn test_search() {
+    let mut db = V1Kpdb::new("test/test_password.kdb".to_string(),
+                             Some("test".to_string()),
+                             None)
+                     .ok()
+                     .unwrap();
+    assert!(db.load().is_ok());
+    let group = db.groups[0].clone();
+    db.create_entry(group, "Mailbox".to_string(), None, None,
+                    Some("https://mail.example.com".to_string()),
+                    Some("work account".to_string()),
+                    Some("Alice".to_string()),
+                    Some("secret".to_string()));
+    assert!(db.create_group("Backup".to_string(), None, None, None).is_ok());
+    let backup = db.groups[db.groups.len() - 1].clone();
+    db.create_entry(backup, "Old mailbox".to_string(), None, None, None, None, None, None);
+
+    // Substring, case insensitive, on all fields
+    let query = SearchQuery::new("MAIL".to_string());
+    let result = db.search(&query).ok().unwrap();
+    assert_eq!(result.len(), 1);
+    assert_eq!(result[0].borrow().title, "Mailbox");
+
+    // Username is matched and locked again afterwards
+    let result = db.search(&SearchQuery::new("alice".to_string())).ok().unwrap();
+    assert_eq!(result.len(), 1);
+    assert!(result[0].borrow().username.as_ref().unwrap().string != "Alice");
+
+    // Field selectors
+    let mut query = SearchQuery::new("work".to_string());
+    query.notes = false;
+    assert_eq!(db.search(&query).ok().unwrap().len(), 0);
+
+    // Backup group
+    let mut query = SearchQuery::new("mailbox".to_string());
+    assert_eq!(db.search(&query).ok().unwrap().len(), 1);
+    query.include_backup = true;
+    assert_eq!(db.search(&query).ok().unwrap().len(), 2);
+
+    // Regex
+    let mut query = SearchQuery::new("^m.*x$".to_string());
+    query.regex = true;
+    assert_eq!(db.search(&query).ok().unwrap().len(), 1);
+    query.case_sensitive = true;
+    assert_eq!(db.search(&query).ok().unwrap().len(), 0);
+    query.text = "(".to_string();
+    match db.search(&query) {
+        Ok(_) => assert!(false),
+        Err(e) => assert_eq!(e, V1KpdbError::RegexErr),
+    };
+}
+
+#[test]
+fn test_find_entries_for_url() {
+    let mut db = V1Kpdb::new("test/test_password.kdb".to_string(),
+                             Some("test".to_string()),
+                             None)
+                     .ok()
+                     .unwrap();
+    assert!(db.load().is_ok());
+    let group = db.groups[0].clone();
+    db.create_entry(group.clone(), "Amazon".to_string(), None, None,
+                    Some("https://www.amazon.com/login".to_string()),
+                    None, None, None);
+    db.create_entry(group, "BBC".to_string(), None, None,
+                    Some("https://account.bbc.co.uk".to_string()),
+                    None, None, None);
+
+    assert_eq!(db.find_entries_for_url("https://smile.amazon.com").len(), 1);
+    assert_eq!(db.find_entries_for_url("https://www.bbc.co.uk/news").len(), 1);
+    assert_eq!(db.find_entries_for_url("https://other.co.uk").len(), 0);
+    assert_eq!(db.find_entries_for_url("https://www.amazon.de").len(), 0);
+
+    db.equivalent_domains.add(vec!["amazon.com".to_string(), "www.amazon.de".to_string()]);
+    let result = db.find_entries_for_url("https://www.amazon.de");
+    assert_eq!(result.len(), 1);
+    assert_eq!(result[0].borrow().title, "Amazon");
+
+    db.equivalent_domains.remove("amazon.de");
+    assert_eq!(db.find_entries_for_url("https://www.amazon.de").len(), 0);
+}
+
+#[test]
+fn test_equivalent_domains_saved() {
+    let path = copy_to_tmp("test/test_password.kdb", "rust_keepass_test_equivalent_domains.kdb");
+    let mut db = V1Kpdb::new(path.clone(), Some("test".to_string()), None).ok().unwrap();
+    assert!(db.load().is_ok());
+    let num_entries = db.entries.len();
+    db.equivalent_domains.add(vec!["amazon.com".to_string(), "amazon.de".to_string()]);
+    db.equivalent_domains.add(vec!["bbc.co.uk".to_string(), "www.bbc.com".to_string()]);
+    assert!(db.save(None, None, None).is_ok());
+
+    let mut reloaded = V1Kpdb::new(path.clone(), Some("test".to_string()), None).ok().unwrap();
+    assert!(reloaded.load().is_ok());
+    assert_eq!(reloaded.equivalent_domains, db.equivalent_domains);
+    assert!(reloaded.equivalent_domains.hosts_match("smile.amazon.de", "amazon.com"));
+    assert_eq!(reloaded.entries.len(), num_entries);
+    db.equivalent_domains = EquivalentDomains::new();
+    assert!(db.reload(None).is_ok());
+    assert_eq!(db.equivalent_domains, reloaded.equivalent_domains);
+
+    // Without sets the stream is dropped
+    reloaded.equivalent_domains.remove("amazon.com");
+    reloaded.equivalent_domains.remove("bbc.com");
+    assert!(reloaded.save(None, None, None).is_ok());
+    let mut reloaded = V1Kpdb::new(path.clone(), Some("test".to_string()), None).ok().unwrap();
+    assert!(reloaded.load().is_ok());
+    assert!(reloaded.equivalent_domains.sets().is_empty());
+    assert!(!reloaded.meta_entries
+                     .iter()
+                     .any(|e| e.borrow().comment == Some("RKP_EQUIVALENT_DOMAINS".to_string())));
+    let _ = fs::remove_file(&path);
+}
+
+#[test]
+fn test_delete_group_and_entry() {
+    let mut db = V1Kpdb::new("test/test_parsing.kdb".to_string(),
+                             Some("test".to_string()),
+                             None)
+                     .ok()
+                     .unwrap();
+    assert!(db.load().is_ok());
+    let num_groups_before = db.header.num_groups;
+
+    let id = db.groups[2].borrow().id;
+    match db.delete_group(id, false) {
+        Ok(_) => assert!(false),
+        Err(e) => assert_eq!(e, V1KpdbError::NotEmptyErr),
+    };
+    let empty = db.create_group("empty".to_string(), None, None, None).ok().unwrap();
+    let empty_id = empty.borrow().id;
+    drop(empty);
+    assert!(db.delete_group(empty_id, false).is_ok());
+    assert!(db.delete_group(id, true).is_ok());
+    assert_eq!(db.header.num_groups, num_groups_before - 5);
+    assert!(db.group_by_id(id).is_none());
+    match db.delete_group(id, true) {
+        Ok(_) => assert!(false),
+        Err(e) => assert_eq!(e, V1KpdbError::IndexErr),
+    };
+
+    let num_entries_before = db.header.num_entries;
+    let uuid = db.entries[0].borrow().uuid;
+    assert!(db.delete_entry(&uuid).is_ok());
+    assert!(db.entry_by_uuid(&uuid).is_none());
+    assert_eq!(db.header.num_entries, num_entries_before - 1);
+}
+
+#[test]
+fn test_move_entry() {
+    let mut db = V1Kpdb::new("test/test_password.kdb".to_string(),
+                             Some("test".to_string()),
+                             None)
+                     .ok()
+                     .unwrap();
+    assert!(db.load().is_ok());
+    let entry = db.entries[0].clone();
+    let old_group = db.groups[0].clone();
+    let new_group = db.groups[1].clone();
+    let num_entries_in_old = old_group.borrow().entries.len();
+
+    assert!(db.move_entry(entry.clone(), new_group.clone()).is_ok());
+    assert_eq!(old_group.borrow().entries.len(), num_entries_in_old - 1);
+    assert_eq!(new_group.borrow().entries.len(), 1);
+    assert_eq!(entry.borrow().group_id, new_group.borrow().id);
+    assert_eq!(entry.borrow().group.as_ref().unwrap().borrow().title, "test");
+}
+
+#[test]
+fn test_move_group() {
+    let mut db = V1Kpdb::new("test/test_parsing.kdb".to_string(),
+                             Some("test".to_string()),
+                             None)
+                     .ok()
+                     .unwrap();
+    assert!(db.load().is_ok());
+    // groups[2] ("11") holds the subtree 22, 21, 32, 31
+    let group = db.groups[2].clone();
+    let child = db.groups[3].clone();
+    match db.move_group(group.clone(), Some(child)) {
+        Ok(_) => assert!(false),
+        Err(e) => assert_eq!(e, V1KpdbError::TreeErr),
+    };
+
+    let num_root_children = db.root_group.borrow().children.len();
+    assert!(db.move_group(group.clone(), None).is_ok());
+    assert_eq!(db.root_group.borrow().children.len(), num_root_children + 1);
+    assert_eq!(group.borrow().level, 0);
+    assert_eq!(group.borrow().parent.as_ref().unwrap().borrow().id, 0);
+    // The subtree moved to the end of the groups vector
+    let len = db.groups.len();
+    assert!(db.groups[len - 5] == group);
+    assert_eq!(db.groups[len - 4].borrow().level, 1);
+
+    // Tree survives save and load
+    let path = copy_to_tmp("test/test_parsing.kdb", "rust_keepass_test_move_group.kdb");
+    assert!(db.save(Some(path.clone()), None, None).is_ok());
+    assert!(db.load().is_ok());
+    assert_eq!(db.root_group.borrow().children.len(), num_root_children + 1);
+    assert_eq!(db.groups[db.groups.len() - 5].borrow().title, "11");
+    assert_eq!(db.groups[db.groups.len() - 5].borrow().level, 0);
+    let _ = fs::remove_file(&path);
+}
+
+#[test]
+fn test_ordering() {
+    fn group_titles(db: &V1Kpdb) -> Vec<String> {
+        db.groups.iter().map(|g| g.borrow().title.clone()).collect()
+    }
+    fn entry_titles(group: &Rc<RefCell<V1Group>>) -> Vec<String> {
+        group.borrow().child_entries().iter().map(|e| e.borrow().title.clone()).collect()
+    }
+
+    let path = copy_to_tmp("test/test_parsing.kdb", "rust_keepass_test_ordering.kdb");
+    let mut db = V1Kpdb::new(path.clone(), Some("test".to_string()), None).ok().unwrap();
+    assert!(db.load().is_ok());
+    db.undo_limit = 100;
+    let internet = db.groups[0].clone();
+    let eleven = db.groups[2].clone();
+    assert_eq!(db.move_group_up(eleven.clone()).ok(), Some(true));
+    assert_eq!(group_titles(&db), vec!["Internet", "11", "22", "21", "32", "31", "12"]);
+    assert_eq!(db.move_group_up(eleven.clone()).ok(), Some(false));
+    assert_eq!(db.move_group_down(internet.clone()).ok(), Some(false));
+    assert_eq!(db.move_group_down(eleven.clone()).ok(), Some(true));
+    assert_eq!(group_titles(&db), vec!["Internet", "12", "11", "22", "21", "32", "31"]);
+    assert_eq!(db.undo_steps(), 2);
+
+    let twenty_one = db.group_by_path("Internet/11/21").unwrap();
+    assert!(db.sort_groups(Some(twenty_one), SortKey::Title).is_ok());
+    assert!(db.sort_groups(Some(internet.clone()), SortKey::Username).is_ok());
+    assert_eq!(group_titles(&db), vec!["Internet", "11", "22", "21", "31", "32", "12"]);
+    let walked = db.iter_groups().map(|g| g.borrow().title.clone()).collect::<Vec<_>>();
+    assert_eq!(walked, group_titles(&db));
+    assert!(db.validate().is_empty());
+
+    let sorted = db.create_path("Sorted").ok().unwrap();
+    let twelve = db.group_by_path("Internet/12").unwrap();
+    let new_entry = |db: &mut V1Kpdb, group: &Rc<RefCell<V1Group>>, title: &str, user: &str| {
+        let username = if user.is_empty() {
+            None
+        } else {
+            Some(user.to_string())
+        };
+        db.create_entry(group.clone(), title.to_string(), None, None, None, None, username, None)
+    };
+    new_entry(&mut db, &sorted, "b", "Zed");
+    let other = new_entry(&mut db, &twelve, "other", "");
+    let first = new_entry(&mut db, &sorted, "A", "");
+    new_entry(&mut db, &sorted, "c", "alice");
+    let other_index = db.entries.iter().position(|e| Rc::ptr_eq(e, &other)).unwrap();
+
+    assert!(db.sort_entries(sorted.clone(), SortKey::Title).is_ok());
+    assert_eq!(entry_titles(&sorted), vec!["A", "b", "c"]);
+    assert!(db.sort_entries(sorted.clone(), SortKey::Username).is_ok());
+    assert_eq!(entry_titles(&sorted), vec!["A", "c", "b"]);
+    assert!(Rc::ptr_eq(&db.entries[other_index], &other));
+    assert_eq!(db.move_entry_up(first.clone()).ok(), Some(false));
+    assert_eq!(db.move_entry_down(first.clone()).ok(), Some(true));
+    assert_eq!(entry_titles(&sorted), vec!["c", "A", "b"]);
+    let undo_steps = db.undo_steps();
+    assert!(db.sort_entries(sorted.clone(), SortKey::LastMod).is_ok());
+    assert_eq!(db.undo_steps(), undo_steps + 1);
+    assert_eq!(db.undo().ok(), Some(true));
+    let sorted = db.group_by_path("Sorted").unwrap();
+    assert_eq!(entry_titles(&sorted), vec!["c", "A", "b"]);
+
+    // The order survives save and load
+    assert!(db.save(None, None, None).is_ok());
+    assert!(db.load().is_ok());
+    assert_eq!(group_titles(&db),
+               vec!["Internet", "11", "22", "21", "31", "32", "12", "Sorted"]);
+    let sorted = db.group_by_path("Sorted").unwrap();
+    assert_eq!(entry_titles(&sorted), vec!["c", "A", "b"]);
+    let _ = fs::remove_file(&path);
+}
+
+#[test]
+fn test_iter_groups() {
+    let mut db = V1Kpdb::new("test/test_parsing.kdb".to_string(),
+                             Some("test".to_string()),
+                             None)
+                     .ok()
+                     .unwrap();
+    assert!(db.load().is_ok());
+
+    let titles: Vec<String> = db.iter_groups().map(|g| g.borrow().title.clone()).collect();
+    assert_eq!(titles, vec!["Internet", "12", "11", "22", "21", "32", "31"]);
+
+    let levels: Vec<u16> = db.iter_groups_breadth_first().map(|g| g.borrow().level).collect();
+    assert_eq!(levels, vec![0, 1, 1, 2, 2, 3, 3]);
+
+    let num_leafs = db.iter_groups().filter(|g| g.borrow().children.is_empty()).count();
+    assert_eq!(num_leafs, 4);
+}
+
+#[test]
+fn test_iter_entries() {
+    let mut db = V1Kpdb::new("test/test_parsing.kdb".to_string(),
+                             Some("test".to_string()),
+                             None)
+                     .ok()
+                     .unwrap();
+    assert!(db.load().is_ok());
+
+    assert_eq!(db.iter_entries().count(), db.entries.len());
+    let titles: Vec<String> = db.iter_entries_breadth_first()
+                                .map(|e| e.borrow().title.clone())
+                                .collect();
+    assert_eq!(titles[0], "test1");
+    assert_eq!(titles.len(), 5);
+}
+
+#[test]
+fn test_set_credentials() {
+    let path = copy_to_tmp("test/test_password.kdb", "rust_keepass_test_set_credentials.kdb");
+    let mut db = V1Kpdb::new(path.clone(), Some("test".to_string()), None).ok().unwrap();
+    assert!(db.load().is_ok());
+    let old_seed = db.header.transf_randomseed.clone();
+
+    match db.set_credentials(None, None) {
+        Ok(_) => assert!(false),
+        Err(e) => assert_eq!(e, V1KpdbError::PassErr),
+    };
+    assert!(db.set_credentials(Some(SecureString::new("new password".to_string())),
+                               Some(SecureString::new("test/test_key".to_string())))
+              .is_ok());
+    assert!(db.header.transf_randomseed != old_seed);
+    assert!(db.save(None, None, None).is_ok());
+
+    // Depending on the random IV a wrong key shows up as invalid padding
+    // or as wrong hash
+    let mut old = V1Kpdb::new(path.clone(), Some("test".to_string()), None).ok().unwrap();
+    match old.load() {
+        Err(V1KpdbError::DecryptErr) | Err(V1KpdbError::HashErr) => {}
+        _ => assert!(false),
+    };
+    let mut new = V1Kpdb::new(path.clone(),
+                              Some("new password".to_string()),
+                              Some("test/test_key".to_string()))
+                      .ok()
+                      .unwrap();
+    assert!(new.load().is_ok());
+    assert_eq!(new.entries.len(), db.entries.len());
+    let _ = fs::remove_file(&path);
+}
+
+#[test]
+fn test_usage_sink() {
+    let path = copy_to_tmp("test/test_password.kdb", "rust_keepass_test_usage.kdb");
+    let events = Rc::new(RefCell::new(vec![]));
+    let sink_events = events.clone();
+
+    let mut db = V1Kpdb::new(path.clone(), Some("test".to_string()), None).ok().unwrap();
+    db.usage_sink = Some(Box::new(move |event: &UsageEvent| {
+        sink_events.borrow_mut().push((event.kind, event.success, event.num_entries));
+    }));
+    assert!(db.load().is_ok());
+    assert!(db.save(None, None, None).is_ok());
+    assert!(db.save(Some("/nonexistent/dir/db.kdb".to_string()), None, None).is_err());
+    let other = open_parsing_db();
+    let report = db.merge(&other, &mut NewestWins, None).unwrap();
+    let num_entries = 1 + report.added.len();
+
+    assert_eq!(*events.borrow(),
+               vec![(UsageKind::Open, true, 1),
+                    (UsageKind::Save, true, 1),
+                    (UsageKind::Save, false, 1),
+                    (UsageKind::Merge, true, num_entries)]);
+    let _ = fs::remove_file(&path);
+}
+
+#[test]
+fn test_mem_lock_policy() {
+    let events = Rc::new(RefCell::new(vec![]));
+    let sink_events = events.clone();
+    let mut db = V1Kpdb::new("test/test_password.kdb".to_string(),
+                             Some("test".to_string()),
+                             None)
+                     .ok()
+                     .unwrap();
+    db.usage_sink = Some(Box::new(move |event: &UsageEvent| {
+        sink_events.borrow_mut().push(event.lock_failures);
+    }));
+
+    // Nothing is locked, so nothing can fail
+    db.mem_lock_policy = MemLockPolicy::Disabled;
+    let failures = mem_protect::lock_failures();
+    assert!(db.load().is_ok());
+    assert_eq!(mem_protect::lock_failures(), failures);
+    assert_eq!(*events.borrow(), vec![0]);
+    // The policy only applies during load
+    assert_eq!(mem_protect::policy(), MemLockPolicy::BestEffort);
+
+    // Succeeds unless RLIMIT_MEMLOCK is too small, then nothing is kept
+    db.mem_lock_policy = MemLockPolicy::Require;
+    match db.load() {
+        Ok(()) => assert_eq!(events.borrow()[1], 0),
+        Err(e) => {
+            assert_eq!(e, V1KpdbError::MemLockErr);
+            assert!(db.entries.is_empty());
+        }
+    }
+
+    if let Some(limit) = mem_protect::lock_limit() {
+        assert_eq!(mem_protect::raise_lock_limit(limit), Some(limit));
+    }
+
+    // Loading raises a lowered soft limit again, at most to the hard one
+    #[cfg(unix)]
+    {
+        let mut limit = libc::rlimit {
+            rlim_cur: 0,
+            rlim_max: 0,
+        };
+        assert_eq!(unsafe { libc::getrlimit(libc::RLIMIT_MEMLOCK, &mut limit) }, 0);
+        let original = limit.rlim_cur;
+        let hard = limit.rlim_max as u64;
+        let lowered = mem_protect::locked_bytes() as u64;
+        if hard > lowered {
+            limit.rlim_cur = lowered as libc::rlim_t;
+            assert_eq!(unsafe { libc::setrlimit(libc::RLIMIT_MEMLOCK, &limit) }, 0);
+            db.mem_lock_policy = MemLockPolicy::BestEffort;
+            assert!(db.load().is_ok());
+            let raised = mem_protect::lock_limit().unwrap();
+            assert!(raised > lowered && raised <= hard);
+            assert_eq!(mem_protect::raise_lock_limit(u64::max_value()), Some(hard));
+            limit.rlim_cur = original;
+            assert_eq!(unsafe { libc::setrlimit(libc::RLIMIT_MEMLOCK, &limit) }, 0);
+        }
+    }
+}
+
+#[test]
+fn test_harden_process() {
+    let mut db = V1Kpdb::new("test/test_password.kdb".to_string(),
+                             Some("test".to_string()),
+                             None)
+                     .ok()
+                     .unwrap();
+    db.harden_process = true;
+    // No other test hardens the process, so this is what did it
+    assert!(db.load_from_bytes(&read_file("test/test_password.kdb")).is_ok());
+    assert!(mem_protect::is_hardened());
+    assert!(db.load().is_ok());
+    // Lowering the limits never needs privileges
+    assert!(mem_protect::harden_process());
+}
+
+#[test]
+fn test_progress_and_cancel() {
+    let reports = Rc::new(RefCell::new(vec![]));
+    let progress_reports = reports.clone();
+    let mut db = V1Kpdb::new("test/test_password.kdb".to_string(),
+                             Some("test".to_string()),
+                             None)
+                     .ok()
+                     .unwrap();
+    db.set_progress_callback(Some(Box::new(move |done, total| {
+        progress_reports.borrow_mut().push((done, total));
+    })));
+    assert!(db.load().is_ok());
+    assert_eq!(reports.borrow().len(), 15);
+    assert_eq!(reports.borrow()[0], (10000, 150000));
+    assert_eq!(*reports.borrow().last().unwrap(), (150000, 150000));
+
+    // Cancel from within the progress callback
+    let token = CancelToken::new();
+    let callback_token = token.clone();
+    let mut db = V1Kpdb::new("test/test_password.kdb".to_string(),
+                             Some("test".to_string()),
+                             None)
+                     .ok()
+                     .unwrap();
+    db.set_cancel_token(Some(token));
+    db.set_progress_callback(Some(Box::new(move |done, _| {
+        if done >= 20000 {
+            callback_token.cancel();
+        }
+    })));
+    match db.load() {
+        Ok(_) => assert!(false),
+        Err(e) => assert_eq!(e, V1KpdbError::CancelledErr),
+    };
+    assert_eq!(db.entries.len(), 0);
+}
+
+#[test]
+fn test_conformance() {
+    let report = conformance::check_file("test/test_password.kdb").unwrap();
+    assert!(report.is_conformant());
+    assert!(!report.inner_checked);
+    let key = CompositeKey::from_credentials(Some(SecureString::new("test".to_string())), None);
+    let report = conformance::check_file_with_key("test/test_password.kdb", key).unwrap();
+    assert!(report.inner_checked);
+    assert!(report.is_conformant());
+    let key = CompositeKey::from_credentials(Some(SecureString::new("wrong".to_string())), None);
+    let report = conformance::check_file_with_key("test/test_password.kdb", key).unwrap();
+    assert_eq!(report.violations, vec![Violation::WrongKey]);
+    assert_eq!(conformance::check_file("test/nonexistent.kdb").err(),
+               Some(V1KpdbError::FileErr));
+
+    let path = copy_to_tmp("test/test_password.kdb", "rust_keepass_test_conformance.kdb");
+    let mut data = vec![];
+    File::open(&path).unwrap().read_to_end(&mut data).unwrap();
+    let len = data.len();
+    File::create(&path).unwrap().write_all(&data[..len - 5]).unwrap();
+    let report = conformance::check_file(&path).unwrap();
+    assert_eq!(report.violations, vec![Violation::BlockSize(len - 5 - 124)]);
+    assert!(report.to_json().to_string().contains("\"code\":\"block_size\""));
+
+    // Twofish is fine, RC4 and several ciphers aren't
+    data[8] = 8;
+    data[120..124].copy_from_slice(&[0, 0, 0, 0]);
+    File::create(&path).unwrap().write_all(&data).unwrap();
+    let report = conformance::check_file(&path).unwrap();
+    assert_eq!(report.violations, vec![Violation::NoRounds]);
+    for flags in [4, 10].iter() {
+        data[8] = *flags;
+        File::create(&path).unwrap().write_all(&data).unwrap();
+        let report = conformance::check_file(&path).unwrap();
+        assert_eq!(report.violations,
+                   vec![Violation::EncFlag(*flags as u32), Violation::NoRounds]);
+    }
+
+    File::create(&path).unwrap().write_all(&[0x03, 0xd9, 0xa2, 0x9a, 0x67, 0xfb, 0x4b, 0xb5]).unwrap();
+    assert_eq!(conformance::check_file(&path).unwrap().violations, vec![Violation::Kdbx]);
+    data[0] = 0;
+    File::create(&path).unwrap().write_all(&data).unwrap();
+    assert_eq!(conformance::check_file(&path).unwrap().violations, vec![Violation::Signature]);
+    let _ = fs::remove_file(&path);
+}
+
+#[test]
+fn test_verify_credentials() {
+    assert_eq!(V1Kpdb::verify_credentials("test/test_password.kdb",
+                                          Some("test".to_string()),
+                                          None),
+               Ok(true));
+    assert_eq!(V1Kpdb::verify_credentials("test/test_password.kdb",
+                                          Some("tes".to_string()),
+                                          None),
+               Ok(false));
+    assert_eq!(V1Kpdb::verify_credentials("test/test_both.kdb",
+                                          Some("test".to_string()),
+                                          Some("test/test_key".to_string())),
+               Ok(true));
+    assert_eq!(V1Kpdb::verify_credentials("test/test_password.kdb", None, None),
+               Err(V1KpdbError::PassErr));
+    assert_eq!(V1Kpdb::verify_credentials("test/nonexistent.kdb",
+                                          Some("test".to_string()),
+                                          None),
+               Err(V1KpdbError::FileErr));
+}
+
+// Whether the plain text of s is deleted, i.e. it's encrypted at rest
+fn is_deleted(s: &SecureString) -> bool {
+    s.string.bytes().all(|b| b == 0)
+}
+
+#[test]
+fn test_save_deletes_secrets() {
+    let path = copy_to_tmp("test/test_password.kdb", "rust_keepass_test_save_secrets.kdb");
+    let mut db = V1Kpdb::new(path.clone(), Some("test".to_string()), None).ok().unwrap();
+    assert!(db.load().is_ok());
+    db.entries[0].borrow_mut().username = Some(SecureString::new("alice".to_string()));
+    assert!(db.entries[0]
+              .borrow_mut()
+              .set_password(Some(SecureString::new("secret".to_string())))
+              .is_ok());
+    assert!(db.save(None, None, None).is_ok());
+    {
+        let entry = db.entries[0].borrow();
+        assert!(is_deleted(entry.username.as_ref().unwrap()));
+        assert!(is_deleted(entry.raw_password().unwrap()));
+    }
+    let mut entry = db.entries[0].borrow_mut();
+    assert_eq!(&*entry.username.as_mut().unwrap().unlocked(), "alice");
+    assert_eq!(&*entry.raw_password_mut().unwrap().unlocked(), "secret");
+    let _ = fs::remove_file(&path);
+}
+
+#[test]
+fn test_transaction_keeps_secrets_deleted() {
+    let mut db = V1Kpdb::new("test/test_password.kdb".to_string(),
+                             Some("test".to_string()),
+                             None)
+                     .ok()
+                     .unwrap();
+    assert!(db.load().is_ok());
+    db.entries[0].borrow_mut().username = Some(SecureString::new("alice".to_string()));
+    assert!(db.transaction(|_| Ok(())).is_ok());
+    assert!(is_deleted(db.entries[0].borrow().username.as_ref().unwrap()));
+
+    // A rollback restores deleted secrets as well
+    let result: Result<(), V1KpdbError> = db.transaction(|_| Err(V1KpdbError::IndexErr));
+    assert_eq!(result, Err(V1KpdbError::IndexErr));
+    let mut entry = db.entries[0].borrow_mut();
+    assert!(is_deleted(entry.username.as_ref().unwrap()));
+    assert_eq!(&*entry.username.as_mut().unwrap().unlocked(), "alice");
+}
+
+#[test]
+fn test_key_material_zeroed_out() {
+    let path = copy_to_tmp("test/test_both.kdb", "rust_keepass_test_zeroed.kdb");
+    {
+        let mut db = V1Kpdb::new(path.clone(),
+                                 Some("test".to_string()),
+                                 Some("test/test_key".to_string()))
+                         .ok()
+                         .unwrap();
+        assert!(db.load().is_ok());
+        // The copy of the attachment made by save is checked as well
+        db.entries[0].borrow_mut().binary = Some(SecureBytes::new(vec![1, 2, 3]));
+        assert!(db.save(None, None, None).is_ok());
+        assert_eq!(V1Kpdb::verify_credentials(&path, Some("test".to_string()), None),
+                   Ok(false));
+    }
+    assert_eq!(shadow::outstanding(), Vec::<&str>::new());
+    let _ = fs::remove_file(&path);
+}
+
+#[cfg(feature = "secret-audit")]
+#[test]
+fn test_no_residual_secrets() {
+    assert!(security::is_tracking());
+    {
+        let token = CancelToken::new();
+        token.cancel();
+        let mut db = V1Kpdb::new("test/test_password.kdb".to_string(),
+                                 Some("test".to_string()),
+                                 None)
+                         .unwrap();
+        db.set_cancel_token(Some(token));
+        assert_eq!(db.load(), Err(V1KpdbError::CancelledErr));
+        assert!(!security::residual_secrets().is_empty());
+    }
+    security::assert_no_residual_secrets();
+
+    {
+        let mut db = open_parsing_db();
+        let mut path = env::temp_dir();
+        path.push("rust_keepass_nonexistent");
+        path.push("test.kdb");
+        db.path = path.to_str().unwrap().to_string();
+        assert!(db.save(None, None, None).is_err());
+    }
+    assert_eq!(security::check_no_residual_secrets(), Ok(()));
+
+    let result = panic::catch_unwind(|| {
+        let _db = open_parsing_db();
+        panic!("caught at the FFI boundary");
+    });
+    assert!(result.is_err());
+    security::assert_no_residual_secrets();
+}
+
+#[test]
+fn test_group_meta() {
+    let path = copy_to_tmp("test/test_password.kdb", "rust_keepass_test_group_meta.kdb");
+    let mut db = V1Kpdb::new(path.clone(), Some("test".to_string()), None).ok().unwrap();
+    assert!(db.load().is_ok());
+    db.groups[1].borrow_mut().notes = Some("line 1\nline\t2 \\".to_string());
+    db.groups[0].borrow_mut().custom_data.insert("color".to_string(), "red".to_string());
+    db.meta_entries.push(Rc::new(RefCell::new(new_meta_entry("KPX_OTHER", vec![1, 2, 3], 2))));
+    assert!(db.save(None, None, None).is_ok());
+
+    let mut db = V1Kpdb::new(path.clone(), Some("test".to_string()), None).ok().unwrap();
+    assert!(db.load().is_ok());
+    assert_eq!(db.entries.len(), 1);
+    assert_eq!(db.meta_entries.len(), 2);
+    assert_eq!(db.groups[0].borrow().entries.len(), 1);
+    assert_eq!(db.groups[1].borrow().notes,
+               Some("line 1\nline\t2 \\".to_string()));
+    assert_eq!(db.groups[0].borrow().notes, None);
+    assert_eq!(db.groups[0].borrow().custom_data.get("color"),
+               Some(&"red".to_string()));
+    assert!(db.meta_entries
+              .iter()
+              .any(|e| e.borrow().binary.as_ref().map(|b| b.bytes()) == Some(&[1, 2, 3][..])));
+
+    // Without any metadata left the group meta entry is dropped
+    db.groups[1].borrow_mut().notes = None;
+    db.groups[0].borrow_mut().custom_data.clear();
+    assert!(db.save(None, None, None).is_ok());
+    let mut db = V1Kpdb::new(path.clone(), Some("test".to_string()), None).ok().unwrap();
+    assert!(db.load().is_ok());
+    assert_eq!(db.meta_entries.len(), 1);
+    let _ = fs::remove_file(&path);
+}
+
+#[test]
+fn test_meta_info() {
+    let path = copy_to_tmp("test/test_password.kdb", "rust_keepass_test_meta_info.kdb");
+    let mut db = V1Kpdb::new(path.clone(), Some("test".to_string()), None).ok().unwrap();
+    assert!(db.load().is_ok());
+    let uuid = db.entries[0].borrow().uuid;
+    let group_id = db.groups[1].borrow().id;
+    db.meta_info.custom_icons = vec![vec![0x89, 0x50, 0x4e, 0x47], vec![1, 2]];
+    db.meta_info.entry_icons.insert(uuid, 1);
+    db.meta_info.group_icons.insert(group_id, 0);
+    db.meta_info.expanded_groups.insert(group_id, true);
+    db.meta_info.default_username = Some("alice".to_string());
+    let meta_info = db.meta_info.clone();
+    assert!(db.save(None, None, None).is_ok());
+
+    let mut db = V1Kpdb::new(path.clone(), Some("test".to_string()), None).ok().unwrap();
+    assert!(db.load().is_ok());
+    assert_eq!(db.meta_info, meta_info);
+    assert_eq!(db.meta_entries.len(), 3);
+    assert_eq!(db.entries.len(), 1);
+
+    // Saving again doesn't duplicate the streams
+    assert!(db.save(None, None, None).is_ok());
+    let mut db = V1Kpdb::new(path.clone(), Some("test".to_string()), None).ok().unwrap();
+    assert!(db.load().is_ok());
+    assert_eq!(db.meta_entries.len(), 3);
+    let _ = fs::remove_file(&path);
+
+    // Nothing is saved without data, truncated streams are rejected
+    let mut meta_info = MetaInfo::new();
+    assert!(meta_info.encode().is_empty());
+    let mut data = vec![1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 10, 0, 0, 0, 1, 2];
+    assert_eq!(meta_info.decode_stream(CUSTOM_ICONS_STREAM, &data),
+               Err(V1KpdbError::MetaErr));
+    data.truncate(6);
+    assert_eq!(meta_info.decode_stream(CUSTOM_ICONS_STREAM, &data),
+               Err(V1KpdbError::MetaErr));
+    assert_eq!(meta_info, MetaInfo::new());
+}
+
+#[test]
+fn test_custom_icons() {
+    let png = |n: u8| vec![0x89, 0x50, 0x4e, 0x47, 0x0d, 0x0a, 0x1a, 0x0a, n];
+    let path = copy_to_tmp("test/test_password.kdb", "rust_keepass_test_custom_icons.kdb");
+    let mut db = V1Kpdb::new(path.clone(), Some("test".to_string()), None).ok().unwrap();
+    assert!(db.load().is_ok());
+    assert_eq!(db.add_custom_icon(vec![1, 2, 3]), Err(V1KpdbError::IconErr));
+    assert_eq!(db.add_custom_icon(png(0)), Ok(0));
+    assert_eq!(db.add_custom_icon(png(1)), Ok(1));
+    assert_eq!(db.add_custom_icon(png(2)), Ok(2));
+    assert_eq!(db.add_custom_icon(png(1)), Ok(1));
+    let entry = db.entries[0].clone();
+    let group = db.groups[1].clone();
+    let first_group = db.groups[0].clone();
+    assert_eq!(db.set_entry_custom_icon(&entry.borrow(), Some(3)),
+               Err(V1KpdbError::IndexErr));
+    assert!(db.set_entry_custom_icon(&entry.borrow(), Some(2)).is_ok());
+    assert!(db.set_group_custom_icon(&group.borrow(), Some(1)).is_ok());
+    assert!(db.set_group_custom_icon(&first_group.borrow(), Some(0)).is_ok());
+    assert!(db.save(None, None, None).is_ok());
+
+    let mut db = V1Kpdb::new(path.clone(), Some("test".to_string()), None).ok().unwrap();
+    assert!(db.load().is_ok());
+    assert_eq!(db.custom_icons(), &[png(0), png(1), png(2)][..]);
+    let entry = db.entries[0].clone();
+    let group = db.groups[1].clone();
+    assert_eq!(db.entry_custom_icon(&entry.borrow()), Some(2));
+    assert_eq!(db.group_custom_icon(&group.borrow()), Some(1));
+
+    // Removing an icon shifts the following ones
+    assert_eq!(db.remove_custom_icon(1), Ok(png(1)));
+    assert_eq!(db.remove_custom_icon(5), Err(V1KpdbError::IndexErr));
+    assert_eq!(db.group_custom_icon(&group.borrow()), None);
+    assert_eq!(db.entry_custom_icon(&entry.borrow()), Some(1));
+    let first_group = db.groups[0].clone();
+    assert_eq!(db.group_custom_icon(&first_group.borrow()), Some(0));
+    assert!(db.set_entry_custom_icon(&entry.borrow(), None).is_ok());
+    assert_eq!(db.entry_custom_icon(&entry.borrow()), None);
+
+    // Deleted groups don't keep their icon
+    let id = first_group.borrow().id;
+    drop(first_group);
+    assert!(db.delete_group(id, true).is_ok());
+    assert!(db.meta_info.group_icons.is_empty());
+    let _ = fs::remove_file(&path);
+}
+
+#[test]
+fn test_security_advisor() {
+    assert!(password_strength("password") < 50);
+    assert!(password_strength("Xk9#mP2$42") > 60);
+    assert_eq!(password_strength(""), 0);
+
+    let mut db = V1Kpdb::new("test/test_password.kdb".to_string(),
+                             Some("test".to_string()),
+                             None)
+                     .ok()
+                     .unwrap();
+    assert!(db.load().is_ok());
+    let uuid = db.entries[0].borrow().uuid;
+    db.entries[0].borrow_mut()
+        .set_password(Some(SecureString::new("password".to_string())))
+        .unwrap();
+    assert!(db.header.set_key_transf_rounds(1).is_ok());
+
+    let list_path = env::temp_dir().join("rust_keepass_test_security_advisor.txt");
+    {
+        let mut file = File::create(&list_path).unwrap();
+        let _ = write!(file, "5BAA61E4C9B93F3F0682250B6CF8331B7EE68FD8:9545824\r\n");
+    }
+    let mut list = BreachList::open(list_path.to_str().unwrap(), BreachHash::Sha1).unwrap();
+    let advice = db.security_advisor(Duration::from_millis(20), Some(&mut list)).unwrap();
+    let _ = fs::remove_file(&list_path);
+    let kinds: Vec<(Priority, Advice)> = advice.into_iter()
+                                               .map(|r| (r.priority, r.advice))
+                                               .collect();
+    assert_eq!(kinds.len(), 5);
+    match kinds[0] {
+        (Priority::High, Advice::IncreaseRounds { current: 1, suggested }) => {
+            assert!(suggested > 10)
+        }
+        _ => assert!(false),
+    }
+    assert_eq!(kinds[1],
+               (Priority::High, Advice::ChangeBreachedPasswords(vec![uuid])));
+    assert_eq!(kinds[2],
+               (Priority::Medium, Advice::ChangeWeakPasswords(vec![uuid])));
+    assert_eq!(kinds[3], (Priority::Low, Advice::AddKeyFactor));
+    assert_eq!(kinds[4], (Priority::Low, Advice::MigrateToKdbx4));
+
+    // Enough rounds and a strong password leave only the format
+    assert!(db.header.set_key_transf_rounds(u32::max_value()).is_ok());
+    db.entries[0].borrow_mut()
+        .set_password(Some(SecureString::new("Xk9#mP2$42".to_string())))
+        .unwrap();
+    let advice = db.security_advisor(Duration::from_millis(20), None).unwrap();
+    assert_eq!(advice.len(), 2);
+    assert_eq!(advice[0].advice, Advice::AddKeyFactor);
+}
+
+#[test]
+fn test_audit() {
+    let db = open_parsing_db();
+    for entry in db.entries.iter() {
+        let mut entry = entry.borrow_mut();
+        entry.set_password(Some(SecureString::new("Xk9#mP2$42-unique".to_string()))).unwrap();
+        entry.last_mod = Local::now();
+    }
+    let report = db.audit(chrono::Duration::days(365));
+    assert_eq!(report.num_entries, db.entries.len());
+    assert_eq!(report.reused.len(), 1);
+    assert_eq!(report.reused[0].len(), db.entries.len());
+
+    let uuids: Vec<_> = db.entries.iter().map(|e| e.borrow().uuid).collect();
+    for (i, entry) in db.entries.iter().enumerate() {
+        let password = format!("Xk9#mP2$42-{}", i);
+        entry.borrow_mut().set_password(Some(SecureString::new(password))).unwrap();
+    }
+    db.entries[0].borrow_mut()
+        .set_password(Some(SecureString::new("password".to_string())))
+        .unwrap();
+    db.entries[1].borrow_mut().set_password(None).unwrap();
+    db.entries[2].borrow_mut().set_password(Some(SecureString::new("".to_string()))).unwrap();
+    db.entries[3].borrow_mut()
+        .set_password(Some(SecureString::new("Xk9#mP2$42-4".to_string())))
+        .unwrap();
+    db.entries[3].borrow_mut().last_mod = Local::now() - chrono::Duration::days(400);
+    let report = db.audit(chrono::Duration::days(365));
+    assert_eq!(report.weak, vec![(uuids[0], password_strength("password"))]);
+    assert_eq!(report.empty, vec![uuids[1], uuids[2]]);
+    assert_eq!(report.reused, vec![vec![uuids[3], uuids[4]]]);
+    assert_eq!(report.stale, vec![uuids[3]]);
+    assert!(!report.is_clean());
+}
+
+#[test]
+fn test_attachment() {
+    let path = copy_to_tmp("test/test_password.kdb", "rust_keepass_test_attachment.kdb");
+    let mut db = V1Kpdb::new(path.clone(), Some("test".to_string()), None).ok().unwrap();
+    assert!(db.load().is_ok());
+    assert!(db.entries[0].borrow().attachment().is_none());
+    let data: Vec<u8> = (0..1000).map(|i| (i % 256) as u8).collect();
+    assert!(db.entries[0].borrow_mut().set_attachment("scan.pdf".to_string(), data.clone()).is_ok());
+    assert!(db.save(None, None, None).is_ok());
+
+    let mut db = V1Kpdb::new(path.clone(), Some("test".to_string()), None).ok().unwrap();
+    assert!(db.load().is_ok());
+    assert_eq!(db.entries[0].borrow().attachment(), Some(("scan.pdf", &data[..])));
+    let (name, removed) = db.entries[0].borrow_mut().remove_attachment().unwrap();
+    assert_eq!(name, "scan.pdf");
+    assert_eq!(removed.bytes(), &data[..]);
+    assert!(db.save(None, None, None).is_ok());
+
+    let mut db = V1Kpdb::new(path.clone(), Some("test".to_string()), None).ok().unwrap();
+    assert!(db.load().is_ok());
+    assert!(db.entries[0].borrow().attachment().is_none());
+    let _ = fs::remove_file(&path);
+}
+
+#[test]
+fn test_markdown_notes() {
+    let path = copy_to_tmp("test/test_password.kdb", "rust_keepass_test_markdown_notes.kdb");
+    let mut db = V1Kpdb::new(path.clone(), Some("test".to_string()), None).ok().unwrap();
+    assert!(db.load().is_ok());
+    let notes = "Setup\n::secret::\npin: 1234\n::secret::\nDone";
+    assert!(db.entries[0].borrow_mut().set_markdown_notes(notes.to_string()).is_ok());
+    assert!(db.save(None, None, None).is_ok());
+
+    let mut db = V1Kpdb::new(path.clone(), Some("test".to_string()), None).ok().unwrap();
+    assert!(db.load().is_ok());
+    let mut entry = db.entries[0].borrow_mut();
+    assert_eq!(entry.comment,
+               Some("Setup\n::secret::\n::secret::\nDone".to_string()));
+    assert_eq!(&*entry.protected_notes[0].unlocked(), "pin: 1234");
+    assert_eq!(entry.markdown_notes(), Some(notes.to_string()));
+    // Secrets aren't found by search
+    drop(entry);
+    assert_eq!(db.search(&SearchQuery::new("1234".to_string())).unwrap().len(), 0);
+
+    // Sections are saved without a comment as well
+    let mut db = V1Kpdb::new(path.clone(), Some("test".to_string()), None).ok().unwrap();
+    assert!(db.load().is_ok());
+    {
+        let mut entry = db.entries[0].borrow_mut();
+        entry.comment = None;
+        entry.protected_notes = vec![SecureString::new("pin: 1234".to_string())];
+    }
+    assert!(db.save(None, None, None).is_ok());
+    let mut db = V1Kpdb::new(path.clone(), Some("test".to_string()), None).ok().unwrap();
+    assert!(db.load().is_ok());
+    assert_eq!(db.entries[0].borrow_mut().markdown_notes(),
+               Some("::secret::\npin: 1234\n::secret::".to_string()));
+
+    // Notes of other clients which aren't in the saved form keep their text
+    {
+        let mut entry = db.entries[0].borrow_mut();
+        entry.comment = Some("Key\n::secret::\nabc".to_string());
+        entry.protected_notes.clear();
+    }
+    assert!(db.save(None, None, None).is_ok());
+    let mut db = V1Kpdb::new(path.clone(), Some("test".to_string()), None).ok().unwrap();
+    assert!(db.load().is_ok());
+    assert_eq!(db.entries[0].borrow().comment,
+               Some("Key\n::secret::\nabc".to_string()));
+    assert!(db.entries[0].borrow().protected_notes.is_empty());
+    let _ = fs::remove_file(&path);
+}
+
+#[test]
+fn test_find_by_uuid() {
+    let mut db = V1Kpdb::new("test/test_parsing.kdb".to_string(),
+                             Some("test".to_string()),
+                             None)
+                     .ok()
+                     .unwrap();
+    assert!(db.load().is_ok());
+    for entry in db.entries.clone() {
+        let uuid = entry.borrow().uuid;
+        assert!(db.find_by_uuid(&uuid).unwrap() == entry);
+    }
+
+    let group = db.groups[0].clone();
+    let entry = db.create_entry(group, "new".to_string(), None, None, None, None, None, None);
+    let uuid = entry.borrow().uuid;
+    assert!(db.entries.iter().filter(|e| e.borrow().uuid == uuid).count() == 1);
+    assert!(db.find_by_uuid(&uuid).unwrap() == entry);
+
+    // A changed UUID is found through the fallback
+    let new_uuid = db.entries[0].borrow().uuid;
+    db.entries[0].borrow_mut().uuid = uuid;
+    entry.borrow_mut().uuid = new_uuid;
+    assert!(db.find_by_uuid(&new_uuid).unwrap() == entry);
+    entry.borrow_mut().uuid = uuid;
+    db.entries[0].borrow_mut().uuid = new_uuid;
+
+    drop(entry);
+    assert!(db.delete_entry(&uuid).is_ok());
+    assert!(db.find_by_uuid(&uuid).is_none());
+}
+
+#[test]
+fn test_paths() {
+    assert_eq!(path::split("Internet/Email").ok(),
+               Some(vec!["Internet".to_string(), "Email".to_string()]));
+    assert_eq!(path::split("TCP\\/IP/a\\\\b").ok(),
+               Some(vec!["TCP/IP".to_string(), "a\\b".to_string()]));
+    for malformed in &["", "/Internet", "Internet/", "Internet//Email", "a\\b"] {
+        assert_eq!(path::split(malformed), Err(V1KpdbError::PathErr));
+    }
+    let titles = vec!["TCP/IP".to_string(), "a\\b".to_string()];
+    assert_eq!(path::split(&path::join(&titles)).ok(), Some(titles));
+
+    let mut db = V1KpdbBuilder::new(String::new())
+                     .password("test".to_string())
+                     .build()
+                     .ok()
+                     .unwrap();
+    db.undo_limit = 1;
+    let num_groups = db.groups.len();
+    let email = db.create_path("Internet/Email").ok().unwrap();
+    assert_eq!(db.groups.len(), num_groups + 2);
+    assert_eq!(email.borrow().level, 1);
+    assert_eq!(db.undo_steps(), 1);
+    // Existing groups are reused
+    let work = db.create_path("Internet/Email/Work").ok().unwrap();
+    assert_eq!(db.groups.len(), num_groups + 3);
+    assert!(work.borrow().parent.as_ref().unwrap().clone() == email);
+    assert_eq!(db.create_path("Internet//Email").err(), Some(V1KpdbError::PathErr));
+    assert_eq!(db.groups.len(), num_groups + 3);
+    let slash = db.create_path("Internet/TCP\\/IP").ok().unwrap();
+    assert_eq!(slash.borrow().title, "TCP/IP");
+
+    assert!(db.group_by_path("Internet/Email").unwrap() == email);
+    assert!(db.group_by_path("Internet/TCP\\/IP").unwrap() == slash);
+    assert!(db.group_by_path("Internet/Chat").is_none());
+    assert!(db.group_by_path("Email").is_none());
+    assert!(db.group_by_path("Internet/").is_none());
+
+    let gmail = db.create_entry(email.clone(),
+                                "Gmail".to_string(),
+                                None,
+                                None,
+                                None,
+                                None,
+                                None,
+                                None);
+    db.create_entry(email.clone(), "Gmail".to_string(), None, None, None, None, None, None);
+    assert!(db.entry_by_path("Internet/Email/Gmail").unwrap() == gmail);
+    assert!(db.entry_by_path("Internet/Gmail").is_none());
+    assert!(db.entry_by_path("Gmail").is_none());
+    assert!(db.entry_by_path("Internet/Email").is_none());
+}
+
+fn open_parsing_db() -> V1Kpdb {
+    let mut db = V1Kpdb::new("test/test_parsing.kdb".to_string(),
+                             Some("test".to_string()),
+                             None)
+                     .ok()
+                     .unwrap();
+    assert!(db.load().is_ok());
+    db
+}
+
+#[test]
+fn test_handles() {
+    let mut db = open_parsing_db();
+    let entries = db.entry_handles();
+    assert_eq!(entries.len(), db.entries.len());
+    let groups = db.group_handles();
+    assert_eq!(groups.len(), db.groups.len());
+
+    let handle = entries[0];
+    let title = db.with_entry(&handle, |e| e.title.clone()).unwrap();
+    assert_eq!(db.with_entry(&handle, |e| {
+                     assert_eq!(db.with_entry(&handle, |_| ()), Err(V1KpdbError::BorrowErr));
+                     e.title.len()
+                 }),
+               Ok(title.len()));
+    let group = groups[1];
+    assert_eq!(db.with_group(&group, |g| g.id), Ok(group.id()));
+    assert_eq!(db.with_group(&group, |_| db.with_group(&group, |_| ())),
+               Ok(Err(V1KpdbError::BorrowErr)));
+
+    // Handles stay valid while other entries and groups change
+    let other = db.entries[1].borrow().uuid;
+    assert!(db.delete_entry(&other).is_ok());
+    let new_group = db.create_group("new".to_string(), None, None, None).unwrap();
+    assert_eq!(db.with_entry(&handle, |e| e.title.clone()), Ok(title));
+    let found = db.search_handles(&SearchQuery::new("new".to_string())).unwrap();
+    assert!(found.iter().all(|h| db.resolve_entry(h).is_some()));
+
+    // A removed group stays stale after its id is taken again
+    let id = new_group.borrow().id;
+    let new_handle = db.group_handle(&new_group.borrow());
+    assert!(db.delete_group(id, false).is_ok());
+    assert!(db.resolve_group(&new_handle).is_none());
+    let again = db.create_group("again".to_string(), None, None, None).unwrap();
+    assert_eq!(again.borrow().id, id);
+    assert_eq!(db.with_group(&new_handle, |_| ()), Err(V1KpdbError::HandleErr));
+    let again_handle = db.group_handle(&again.borrow());
+    assert_eq!(db.with_group(&again_handle, |g| g.title.clone()), Ok("again".to_string()));
+
+    let uuid = handle.uuid();
+    assert!(db.delete_entry(&uuid).is_ok());
+    assert_eq!(db.with_entry(&handle, |_| ()), Err(V1KpdbError::HandleErr));
+    assert!(db.resolve_entry(&db.entry_handles()[0]).is_some());
+}
+
+#[test]
+fn test_timeline() {
+    let mut db = open_parsing_db();
+    let group = db.groups[0].clone();
+    let entry = db.create_entry(group,
+                                "mail".to_string(),
+                                None,
+                                None,
+                                None,
+                                None,
+                                Some("alice".to_string()),
+                                Some("old".to_string()));
+    let created = Local.ymd(2020, 1, 1).and_hms(12, 0, 0);
+    {
+        let mut entry = entry.borrow_mut();
+        entry.creation = created;
+        entry.last_mod = created;
+        entry.last_access = created + chrono::Duration::days(30);
+    }
+    let events = entry.borrow().timeline();
+    assert_eq!(events.iter().map(|e| e.kind).collect::<Vec<_>>(),
+               vec![TimelineKind::Created, TimelineKind::Accessed]);
+
+    // The old revision goes to the Backup group, as KeePass does
+    let backup = db.create_group("Backup".to_string(), None, None, None).unwrap();
+    let revision = db.create_entry(backup,
+                                   "mail".to_string(),
+                                   None,
+                                   None,
+                                   None,
+                                   None,
+                                   Some("alice".to_string()),
+                                   Some("old".to_string()));
+    revision.borrow_mut().last_mod = created + chrono::Duration::days(10);
+    {
+        let mut entry = entry.borrow_mut();
+        entry.last_mod = created + chrono::Duration::days(20);
+        entry.expire = created + chrono::Duration::days(365);
+    }
+    let events = db.timeline(&entry);
+    assert_eq!(events.iter().map(|e| e.kind).collect::<Vec<_>>(),
+               vec![TimelineKind::Created,
+                    TimelineKind::Modified,
+                    TimelineKind::Modified,
+                    TimelineKind::Accessed,
+                    TimelineKind::Expires]);
+    assert_eq!(events[1].revision, Some(revision.borrow().uuid));
+    assert_eq!(events[2].time, created + chrono::Duration::days(20));
+    assert_eq!(events[2].revision, None);
+    assert!(events.windows(2).all(|w| w[0].time <= w[1].time));
+
+    // Revisions of other accounts aren't part of it
+    revision.borrow_mut().username = Some(SecureString::new("bob".to_string()));
+    assert_eq!(db.timeline(&entry).len(), 4);
+}
+
+#[test]
+fn test_placeholders() {
+    let mut db = open_parsing_db();
+    let parent = db.create_group("Work".to_string(), None, None, None).unwrap();
+    let group = db.create_group("Mail".to_string(), None, None, Some(parent)).unwrap();
+    let server = db.create_entry(group.clone(),
+                                 "Server".to_string(),
+                                 None,
+                                 None,
+                                 Some("https://bob:pw@Mail.example.com:8443/login?next=/inbox#top"
+                                          .to_string()),
+                                 None,
+                                 Some("alice".to_string()),
+                                 Some("s3cret".to_string()));
+    let uuid = server.borrow().uuid.to_simple_string().to_uppercase();
+    let client = db.create_entry(group,
+                                 "Client".to_string(),
+                                 None,
+                                 None,
+                                 Some("{REF:A@T:server}".to_string()),
+                                 Some(format!("{{REF:P@I:{}}}", uuid)),
+                                 Some("{REF:U@t:SERV}".to_string()),
+                                 None);
+    let now = Local.ymd(2024, 3, 9).and_hms(8, 5, 7);
+    let expand = |entry, text: &str| {
+        placeholders::expand_at(&db, entry, text, now).unlocked().to_string()
+    };
+
+    assert_eq!(expand(&server, "{TITLE}/{username}:{PASSWORD} in {GROUP_PATH} ({GROUP})"),
+               "Server/alice:s3cret in Work.Mail (Mail)");
+    assert_eq!(expand(&server, "{URL:SCM}|{URL:HOST}|{URL:PORT}|{URL:PATH}|{URL:QUERY}"),
+               "https|Mail.example.com|8443|/login|?next=/inbox");
+    assert_eq!(expand(&server, "{URL:USERINFO}|{URL:USERNAME}|{URL:PASSWORD}|{URL:RMVSCM}"),
+               "bob:pw|bob|pw|bob:pw@Mail.example.com:8443/login?next=/inbox#top");
+    assert_eq!(expand(&server, "{DT_SIMPLE} {DT_YEAR}-{DT_MONTH}-{DT_DAY} {DT_SECOND}"),
+               "20240309080507 2024-03-09 07");
+    assert_eq!(expand(&server, "{UUID}"), uuid);
+
+    // References and placeholders in field values
+    assert_eq!(expand(&client, "{USERNAME} {NOTES} {URL:HOST}"),
+               "alice s3cret Mail.example.com");
+    // A reference cycle ends at MAX_DEPTH
+    let cycle = format!("{{REF:P@I:{}}}", client.borrow().uuid.to_simple_string());
+    client.borrow_mut().set_password(Some(SecureString::new(cycle.clone()))).unwrap();
+    assert_eq!(expand(&client, "{PASSWORD}"), cycle);
+
+    // Unknown and broken placeholders stay as they are
+    assert_eq!(expand(&server, "{FOO} {REF:X@T:a} {REF:P@T:nothing} {TITLE"),
+               "{FOO} {REF:X@T:a} {REF:P@T:nothing} {TITLE");
+    let _guard = server.borrow();
+    assert_eq!(expand(&server, "{TITLE}"), "{TITLE}");
+}
+
+#[test]
+fn test_shared() {
+    assert_eq!(SharedKpdb::spawn(|| Err(V1KpdbError::FileErr)).err(),
+               Some(V1KpdbError::FileErr));
+
+    let shared = SharedKpdb::spawn(|| Ok(open_parsing_db())).unwrap();
+    let before = shared.with(|db| db.entries.len()).unwrap();
+    let threads: Vec<_> = (0..4)
+                              .map(|i| {
+                                  let shared = shared.clone();
+                                  thread::spawn(move || {
+                                      shared.with(move |db| {
+                                                let group = db.groups[0].clone();
+                                                db.create_entry(group,
+                                                                format!("thread {}", i),
+                                                                None,
+                                                                None,
+                                                                None,
+                                                                None,
+                                                                None,
+                                                                None);
+                                            })
+                                            .unwrap()
+                                  })
+                              })
+                              .collect();
+    for thread in threads {
+        thread.join().unwrap();
+    }
+    shared.post(|db| db.groups[0].borrow_mut().title = "Shared".to_string()).unwrap();
+    let (len, title) = shared.with(|db| (db.entries.len(), db.groups[0].borrow().title.clone()))
+                             .unwrap();
+    assert_eq!(len, before + 4);
+    assert_eq!(title, "Shared");
+
+    // A panic takes the database with it
+    assert_eq!(shared.with(|_| panic!("job failed")).err(), Some(V1KpdbError::ThreadErr));
+    assert_eq!(shared.with(|db| db.entries.len()).err(), Some(V1KpdbError::ThreadErr));
+}
+
+#[test]
+fn test_agent() {
+    let agent = Agent::new(None, true);
+    assert!(agent.paths().is_empty());
+    assert_eq!(agent.with("test/test_parsing.kdb", |db| db.entries.len()).err(),
+               Some(V1KpdbError::NotOpenErr));
+    assert!(agent.unlock("test/test_parsing.kdb", Some("wrong".to_string()), None).is_err());
+    assert!(!agent.is_open("test/test_parsing.kdb"));
+
+    agent.unlock("test/test_parsing.kdb", Some("test".to_string()), None).unwrap();
+    agent.open("test/test_password.kdb", || {
+             let mut db = try!(V1Kpdb::new("test/test_password.kdb".to_string(),
+                                           Some("test".to_string()),
+                                           None));
+             try!(db.load());
+             Ok(db)
+         })
+         .unwrap();
+    assert_eq!(agent.paths().len(), 2);
+    assert!(agent.is_open("./test/../test/test_parsing.kdb"));
+    let num_entries = open_parsing_db().entries.len();
+    assert_eq!(agent.with("test/test_parsing.kdb", |db| db.entries.len()),
+               Ok(num_entries));
+
+    // Clones share the databases
+    let other = agent.clone();
+    let thread = thread::spawn(move || {
+        other.with("test/test_parsing.kdb", |db| {
+                 let group = db.groups[0].clone();
+                 db.create_entry(group,
+                                 "Agent".to_string(),
+                                 None,
+                                 None,
+                                 None,
+                                 None,
+                                 None,
+                                 Some("secret".to_string()));
+             })
+             .unwrap()
+    });
+    thread.join().unwrap();
+    let title = agent.with("test/test_parsing.kdb", |db| db.groups[0].borrow().title.clone())
+                     .unwrap();
+    let mut password = agent.password("test/test_parsing.kdb", &format!("{}/Agent", title))
+                            .unwrap();
+    assert_eq!(&*password.unlocked(), "secret");
+    assert_eq!(agent.password("test/test_parsing.kdb", "nothing/here").err(),
+               Some(V1KpdbError::PathErr));
+
+    // The unsaved entry keeps the database unlocked
+    agent.lock_all();
+    assert_eq!(agent.is_locked("test/test_password.kdb"), Ok(true));
+    assert_eq!(agent.is_locked("test/test_parsing.kdb"), Ok(false));
+    assert_eq!(agent.lock("test/test_parsing.kdb"), Err(V1KpdbError::UnsavedErr));
+
+    // Locking dropped the key once the entry is gone, so unlocking
+    // needs the password again
+    assert_eq!(agent.with("test/test_parsing.kdb", |db| db.load()), Ok(Ok(())));
+    agent.lock_all();
+    assert_eq!(agent.with("test/test_parsing.kdb", |db| db.entries.len()).err(),
+               Some(V1KpdbError::LockedErr));
+    assert_eq!(agent.unlock("test/test_parsing.kdb", None, None).err(),
+               Some(V1KpdbError::PassErr));
+    agent.unlock("test/test_parsing.kdb", Some("test".to_string()), None).unwrap();
+    assert_eq!(agent.with("test/test_parsing.kdb", |db| db.entries.len()),
+               Ok(num_entries));
+
+    assert!(agent.close("test/test_parsing.kdb"));
+    assert!(!agent.close("test/test_parsing.kdb"));
+    assert_eq!(agent.paths().len(), 1);
+
+    // The timer locks on its own
+    let agent = Agent::new(Some(Duration::from_millis(20)), false);
+    agent.unlock("test/test_parsing.kdb", Some("test".to_string()), None).unwrap();
+    thread::sleep(Duration::from_millis(200));
+    assert_eq!(agent.is_locked("test/test_parsing.kdb"), Ok(true));
+    assert_eq!(agent.unlock("test/test_parsing.kdb", None, None), Ok(()));
+}
+
+#[test]
+fn test_secret_access() {
+    let mut db = open_parsing_db();
+    let reads = Rc::new(RefCell::new(vec![]));
+    let log = reads.clone();
+    db.on_secret_access(move |uuid, _| log.borrow_mut().push(*uuid));
+
+    let entry = db.entries.iter().find(|e| e.borrow().has_password()).unwrap().clone();
+    let uuid = entry.borrow().uuid;
+    assert!(reads.borrow().is_empty());
+    assert!(entry.borrow_mut().password().is_some());
+    assert_eq!(*reads.borrow(), vec![uuid]);
+    assert!(db.reveal(&entry, ProtectedField::Password, Duration::from_secs(1)).is_some());
+    assert_eq!(*reads.borrow(), vec![uuid, uuid]);
+
+    // Saving and auditing read the passwords internally only
+    let path = copy_to_tmp("test/test_parsing.kdb", "rust_keepass_test_secret_access.kdb");
+    assert!(db.save(Some(path.clone()), None, None).is_ok());
+    let _ = fs::remove_file(&path);
+    let _ = db.audit(chrono::Duration::days(365));
+    assert_eq!(reads.borrow().len(), 2);
+
+    // New entries report as well, entries outside a database don't
+    let group = db.groups[0].clone();
+    let created = db.create_entry(group,
+                                  "Secret".to_string(),
+                                  None,
+                                  None,
+                                  None,
+                                  None,
+                                  None,
+                                  Some("hunter2".to_string()));
+    let created_uuid = created.borrow().uuid;
+    assert_eq!(created.borrow_mut().password().map(|p| p.to_string()),
+               Some("hunter2".to_string()));
+    assert_eq!(reads.borrow()[2], created_uuid);
+    let mut loose = V1Entry::new();
+    assert!(loose.set_password(Some(SecureString::new("loose".to_string()))).is_ok());
+    assert!(loose.password().is_some());
+    assert_eq!(reads.borrow().len(), 3);
+
+    db.clear_secret_access();
+    assert!(entry.borrow_mut().password().is_some());
+    assert_eq!(reads.borrow().len(), 3);
+}
+
+#[test]
+fn test_auto_lock() {
+    let db = open_parsing_db();
+    let num_entries = db.entries.len();
+    let locked = LockedKpdb::new(db, false);
+    assert_eq!(locked.path(), "test/test_parsing.kdb");
+    assert!(locked.has_key());
+    let db = locked.unlock().ok().unwrap();
+    assert_eq!(db.entries.len(), num_entries);
+
+    // Without the key a new one is needed
+    let locked = LockedKpdb::new(db, true);
+    let (locked, e) = locked.unlock().err().unwrap();
+    assert_eq!(e, V1KpdbError::PassErr);
+    let wrong = CompositeKey::from_credentials(Some(SecureString::new("wrong".to_string())), None);
+    let (locked, _) = locked.unlock_with(wrong).err().unwrap();
+    assert!(locked.has_key());
+    let key = CompositeKey::from_credentials(Some(SecureString::new("test".to_string())), None);
+    let db = locked.unlock_with(key).ok().unwrap();
+    assert_eq!(db.entries.len(), num_entries);
+
+    let mut lock = db.auto_lock_after(Duration::from_secs(3600));
+    assert!(!lock.tick());
+    assert_eq!(lock.with(|db| db.entries.len()), Ok(num_entries));
+    lock.timeout = Some(Duration::new(0, 0));
+    assert!(lock.tick());
+    assert!(lock.is_locked());
+    assert_eq!(lock.with(|db| db.entries.len()).err(), Some(V1KpdbError::LockedErr));
+    assert!(lock.unlock().is_ok());
+    assert_eq!(lock.with(|db| db.entries.len()), Ok(num_entries));
+
+    // Unsaved changes keep the database unlocked
+    assert!(lock.with(|db| db.entries[0].borrow_mut().set_dirty(true)).is_ok());
+    assert_eq!(lock.time_left(), None);
+    assert!(!lock.tick());
+    assert_eq!(lock.lock(), Err(V1KpdbError::UnsavedErr));
+    assert!(!lock.is_locked());
+    lock.discard_and_lock();
+    assert!(lock.is_locked());
+    assert!(lock.unlock().is_ok());
+    assert_eq!(lock.with(|db| db.dirty()), Ok(false));
+    assert_eq!(lock.lock(), Ok(()));
+
+    // Closing drops the equivalent domains as well
+    let mut db = open_parsing_db();
+    db.equivalent_domains.add(vec!["amazon.com".to_string(), "amazon.de".to_string()]);
+    db.close(false);
+    assert_eq!(db.equivalent_domains, EquivalentDomains::new());
+
+    // The thread of a SharedKpdb locks on its own
+    let shared = SharedKpdb::spawn(|| Ok(open_parsing_db())).unwrap();
+    shared.auto_lock_after(Some(Duration::from_millis(20)), true).unwrap();
+    thread::sleep(Duration::from_millis(200));
+    assert_eq!(shared.is_locked(), Ok(true));
+    assert_eq!(shared.with(|db| db.entries.len()).err(), Some(V1KpdbError::LockedErr));
+    assert_eq!(shared.unlock().err(), Some(V1KpdbError::PassErr));
+    shared.auto_lock_after(None, false).unwrap();
+    shared.unlock_with(|| {
+              CompositeKey::from_credentials(Some(SecureString::new("test".to_string())), None)
+          })
+          .unwrap();
+    assert_eq!(shared.with(|db| db.entries.len()), Ok(num_entries));
+    shared.lock().unwrap();
+    assert_eq!(shared.unlock(), Ok(()));
+}
+
+#[test]
+fn test_reload() {
+    let path = copy_to_tmp("test/test_parsing.kdb", "rust_keepass_test_reload.kdb");
+    let open = || {
+        let mut db = V1Kpdb::new(path.clone(), Some("test".to_string()), None).ok().unwrap();
+        assert!(db.load().is_ok());
+        db
+    };
+    let mut db = open();
+    let mut other = open();
+    let num_entries = db.entries.len();
+    assert_eq!(db.has_changed_on_disk(), Ok(false));
+
+    // Saved elsewhere while db has unsaved changes
+    let group = other.groups[0].clone();
+    other.create_entry(group, "remote".to_string(), None, None, None, None, None, None);
+    assert!(other.save(None, None, None).is_ok());
+    let group = db.groups[0].clone();
+    db.create_entry(group, "local".to_string(), None, None, None, None, None, None);
+    assert_eq!(db.has_changed_on_disk(), Ok(true));
+
+    let wrong = CompositeKey::from_credentials(Some(SecureString::new("wrong".to_string())), None);
+    assert!(db.reload_merged(Some(wrong), &mut NewestWins).is_err());
+    assert_eq!(db.entries.len(), num_entries + 1);
+    let report = db.reload_merged(None, &mut NewestWins).ok().unwrap();
+    assert_eq!(report.added.len(), 1);
+    assert_eq!(db.entries.len(), num_entries + 2);
+    assert_eq!(db.has_changed_on_disk(), Ok(false));
+    assert!(db.save(None, None, None).is_ok());
+    assert_eq!(db.has_changed_on_disk(), Ok(false));
+    assert_eq!(other.has_changed_on_disk(), Ok(true));
+
+    // reload drops unsaved changes
+    let group = other.groups[0].clone();
+    other.create_entry(group, "unsaved".to_string(), None, None, None, None, None, None);
+    assert!(other.reload(None).is_ok());
+    assert_eq!(other.entries.len(), num_entries + 2);
+    assert!(other.entries.iter().all(|e| e.borrow().title != "unsaved"));
+    let key = CompositeKey::from_credentials(Some(SecureString::new("test".to_string())), None);
+    assert!(other.reload(Some(key)).is_ok());
+    let _ = fs::remove_file(&path);
+}
+
+#[test]
+fn test_merge() {
+    let mut local = open_parsing_db();
+    let mut remote = open_parsing_db();
+    let num_groups = local.groups.len();
+    let num_entries = local.entries.len();
+    assert!(local.merge(&remote, &mut NewestWins, None).unwrap().is_unchanged());
+
+    // Both copies get a new group, which ends up with the same id
+    let sync = Local::now() - chrono::Duration::hours(1);
+    let group = local.create_group("Local".to_string(), None, None, None).unwrap();
+    local.create_entry(group, "local".to_string(), None, None, None, None, None, None);
+    let group = remote.create_group("Remote".to_string(), None, None, None).unwrap();
+    let added = remote.create_entry(group, "remote".to_string(), None, None, None, None, None, None);
+    let added_uuid = added.borrow().uuid;
+    // Changed remotely only, changed in both and changed locally only
+    let uuids: Vec<_> = local.entries.iter().take(3).map(|e| e.borrow().uuid).collect();
+    for (i, uuid) in uuids.iter().enumerate() {
+        if i < 2 {
+            let entry = remote.find_by_uuid(uuid).unwrap();
+            entry.borrow_mut().title = format!("remote {}", i);
+            entry.borrow_mut().last_mod = Local::now();
+        }
+        if i > 0 {
+            let entry = local.find_by_uuid(uuid).unwrap();
+            entry.borrow_mut().title = format!("local {}", i);
+            entry.borrow_mut().last_mod = Local::now() - chrono::Duration::minutes(1);
+        }
+    }
+    remote.groups[0].borrow_mut().title = "renamed".to_string();
+    remote.groups[0].borrow_mut().last_mod = Local::now();
+
+    let report = local.merge(&remote, &mut DuplicateOnConflict, Some(sync)).unwrap();
+    assert_eq!(report.added, vec![added_uuid]);
+    assert_eq!(report.updated, vec![uuids[0]]);
+    assert_eq!(report.conflicts.len(), 1);
+    assert_eq!(report.conflicts[0].uuid, uuids[1]);
+    assert_eq!(report.conflicts[0].decision, Decision::KeptBoth);
+    assert!(report.skipped.contains(&uuids[2]));
+    assert_eq!(report.added_groups.len(), 1);
+
+    assert_eq!(local.groups.len(), num_groups + 2);
+    assert_eq!(local.groups[0].borrow().title, "renamed");
+    // The new entry, the copy of the conflict and the local entry
+    assert_eq!(local.entries.len(), num_entries + 3);
+    assert_eq!(local.find_by_uuid(&uuids[0]).unwrap().borrow().title, "remote 0");
+    assert_eq!(local.find_by_uuid(&uuids[1]).unwrap().borrow().title, "local 1");
+    assert_eq!(local.find_by_uuid(&uuids[2]).unwrap().borrow().title, "local 2");
+    assert!(local.entries.iter().any(|e| e.borrow().title == "remote 1"));
+    let added = local.find_by_uuid(&added_uuid).unwrap();
+    let group = added.borrow().group.clone().unwrap();
+    assert_eq!(group.borrow().title, "Remote");
+    assert!(group.borrow().id != local.groups.iter().find(|g| g.borrow().title == "Local")
+                                                 .unwrap()
+                                                 .borrow()
+                                                 .id);
+}
+
+#[test]
+fn test_diff() {
+    let a = open_parsing_db();
+    let mut b = open_parsing_db();
+    assert!(diff(&a, &b).is_empty());
+    assert!(diff(&a, &a).is_empty());
+
+    let changed = b.entries[0].clone();
+    changed.borrow_mut().set_password(Some(SecureString::new("new".to_string()))).unwrap();
+    changed.borrow_mut().comment = Some("changed".to_string());
+    let moved = b.entries[1].clone();
+    let group = b.groups[0].clone();
+    assert!(b.move_entry(moved.clone(), group).is_ok());
+    let removed = b.entries[2].borrow().uuid;
+    assert!(b.delete_entry(&removed).is_ok());
+    b.groups[1].borrow_mut().title = "renamed".to_string();
+    let group = b.create_group("new".to_string(), None, None, None).unwrap();
+    let added = b.create_entry(group.clone(), "new".to_string(), None, None, None, None, None, None);
+
+    let diff = diff(&a, &b);
+    assert_eq!(diff.added_groups, vec![group.borrow().id]);
+    assert!(diff.removed_groups.is_empty());
+    assert_eq!(diff.modified_groups.len(), 1);
+    assert_eq!(diff.modified_groups[0].fields, vec![GroupField::Title]);
+    assert_eq!(diff.added_entries, vec![added.borrow().uuid]);
+    assert_eq!(diff.removed_entries, vec![removed]);
+    assert_eq!(diff.modified_entries.len(), 2);
+    assert_eq!(diff.modified_entries[0].uuid, changed.borrow().uuid);
+    assert_eq!(diff.modified_entries[0].fields,
+               vec![EntryField::Password, EntryField::Comment]);
+    assert_eq!(diff.modified_entries[1].fields, vec![EntryField::Group]);
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn test_serde() {
+    let a = open_parsing_db();
+    let json = serde_json::to_string(&a).unwrap();
+    {
+        let mut entry = a.entries[0].borrow_mut();
+        let password = entry.password().unwrap();
+        assert!(json.contains(&format!("\"password\":\"{}\"", &*password)));
+    }
+
+    let tree: FlatTree = serde_json::from_str(&json).unwrap();
+    assert_eq!(tree.groups.len(), a.groups.len());
+    let mut b = V1Kpdb::new("test/test_parsing.kdb".to_string(),
+                            Some("test".to_string()),
+                            None)
+                    .unwrap();
+    assert!(b.set_tree(tree).is_ok());
+    assert!(diff(&a, &b).is_empty());
+    assert_eq!(b.groups[1].borrow().parent.as_ref().map(|p| p.borrow().id),
+               a.groups[1].borrow().parent.as_ref().map(|p| p.borrow().id));
+    assert_eq!(b.header.num_entries, a.header.num_entries);
+
+    let mut tree: FlatTree = serde_json::from_str(&json).unwrap();
+    tree.groups[0].level = 1;
+    assert_eq!(b.set_tree(tree), Err(V1KpdbError::TreeErr));
+}
+
+#[test]
+fn test_reveal() {
+    let mut db = open_parsing_db();
+    let entry = db.entries[0].clone();
+    let expected = entry.borrow_mut().password().unwrap().to_string();
+
+    let password = db.reveal(&entry, ProtectedField::Password, Duration::from_secs(60)).unwrap();
+    assert_eq!(password.with(|p| p.to_string()), Some(expected));
+    assert!(password.is_revealed());
+    assert_eq!(db.outstanding_reveals(), 1);
+    assert!(db.reveal(&entry, ProtectedField::Notes(0), Duration::from_secs(60)).is_none());
+    // The field itself stays encrypted
+    assert!(entry.borrow().raw_password().unwrap().string.bytes().all(|b| b == 0));
+
+    assert_eq!(db.wipe_expired_reveals(), 0);
+    assert_eq!(db.wipe_reveals(), 1);
+    assert_eq!(password.with(|p| p.len()), None);
+    assert!(!password.is_revealed());
+    assert_eq!(db.outstanding_reveals(), 0);
+
+    db.max_reveal_ttl = Some(Duration::new(0, 0));
+    let username = db.reveal(&entry, ProtectedField::Username, Duration::from_secs(60)).unwrap();
+    assert!(username.is_expired());
+    assert_eq!(username.remaining(), Duration::new(0, 0));
+    assert_eq!(db.outstanding_reveals(), 1);
+    assert_eq!(db.wipe_expired_reveals(), 1);
+    assert_eq!(username.with(|u| u.len()), None);
+
+    db.max_reveal_ttl = None;
+    let username = db.reveal(&entry, ProtectedField::Username, Duration::from_secs(60)).unwrap();
+    assert_eq!(db.outstanding_reveals(), 1);
+    drop(username);
+    assert_eq!(db.outstanding_reveals(), 0);
+}
+
+#[test]
+fn test_xml_export_import() {
+    let mut db = open_parsing_db();
+    let parent = db.groups[0].clone();
+    let group = db.create_group("<Sub & Co>".to_string(), None, None, Some(parent)).unwrap();
+    let entry = db.create_entry(group,
+                                "Quotes \"'".to_string(),
+                                Some(Local.ymd(2030, 1, 2).and_hms(3, 4, 5)),
+                                Some(7),
+                                Some("https://example.com/?a=1&b=2".to_string()),
+                                None,
+                                Some("alice".to_string()),
+                                Some("<secret>&".to_string()));
+    assert!(entry.borrow_mut()
+                 .set_markdown_notes("line 1\r\nline 2\n::secret::\nhidden\n::secret::".to_string())
+                 .is_ok());
+    assert!(entry.borrow_mut().set_attachment("file.bin".to_string(), vec![0, 1, 2]).is_ok());
+
+    let mut output = vec![];
+    assert_eq!(db.export_xml(&mut output).unwrap(), db.entries.len());
+
+    let mut copy = V1Kpdb::new("test/unused.kdb".to_string(), Some("test".to_string()), None)
+                       .unwrap();
+    assert_eq!(copy.import_xml(&output[..]).unwrap(), db.entries.len());
+    assert_eq!(copy.entries.len(), db.entries.len());
+    let uuid = entry.borrow().uuid;
+    {
+        let imported = copy.find_by_uuid(&uuid).unwrap();
+        let mut imported = imported.borrow_mut();
+        assert_eq!(imported.title, "Quotes \"'");
+        assert_eq!(&*imported.username().unwrap(), "alice");
+        assert_eq!(&*imported.password().unwrap(), "<secret>&");
+        assert_eq!(imported.url.as_ref().unwrap(), "https://example.com/?a=1&b=2");
+        assert_eq!(imported.comment.as_ref().unwrap(), "line 1\r\nline 2\n::secret::\n::secret::");
+        assert_eq!(imported.protected_notes.len(), 1);
+        assert_eq!(imported.image, 7);
+        assert_eq!(imported.expire, Local.ymd(2030, 1, 2).and_hms(3, 4, 5));
+        assert_eq!(imported.attachment(), Some(("file.bin", &[0u8, 1, 2][..])));
+        let group = imported.group.clone().unwrap();
+        assert_eq!(group.borrow().title, "<Sub & Co>");
+        assert_eq!(group.borrow().parent.as_ref().unwrap().borrow().title,
+                   db.groups[0].borrow().title);
+    }
+
+    for original in db.entries.iter() {
+        let original = original.borrow();
+        let imported = copy.find_by_uuid(&original.uuid).unwrap();
+        let imported = imported.borrow();
+        assert_eq!(imported.title, original.title);
+        // The format has no fractions of seconds
+        assert_eq!(imported.creation.timestamp(), original.creation.timestamp());
+        assert_eq!(imported.last_mod.timestamp(), original.last_mod.timestamp());
+        assert_eq!(imported.expire, original.expire);
+    }
+
+    // UUIDs which are taken already are replaced
+    assert!(copy.import_xml(&output[..]).is_ok());
+    assert_eq!(copy.entries.len(), 2 * db.entries.len());
+    assert_eq!(copy.find_by_uuid(&uuid).unwrap().borrow().title, "Quotes \"'");
+
+    assert_eq!(copy.import_xml(&b"<pwentry></pwentry>"[..]).err(), Some(V1KpdbError::XmlErr));
+    assert_eq!(copy.import_xml(&b"<pwlist><pwentry><title>&foo;</title></pwentry></pwlist>"[..])
+                   .err(),
+               Some(V1KpdbError::XmlErr));
+}
+
+// Stands in for e.g. a YubiKey in HMAC-SHA1 mode
+struct XorResponse(u8);
+
+impl KeyProvider for XorResponse {
+    fn get_key(&mut self, challenge: &[u8]) -> Result<Vec<u8>, V1KpdbError> {
+        Ok(challenge.iter().map(|b| b ^ self.0).collect())
+    }
+}
+
+// A token which was unplugged
+struct NoResponse;
+
+impl KeyProvider for NoResponse {
+    fn get_key(&mut self, _: &[u8]) -> Result<Vec<u8>, V1KpdbError> {
+        Err(V1KpdbError::KeyProviderErr)
+    }
+}
+
+fn composite_key(responses: &[u8]) -> CompositeKey {
+    let mut key = CompositeKey::new();
+    key.add(KeyComponent::Password(SecureString::new("test".to_string())));
+    key.add(KeyComponent::Keyfile(SecureString::new("test/test_key".to_string())));
+    for response in responses {
+        key.add(KeyComponent::ChallengeResponse(Box::new(XorResponse(*response))));
+    }
+    key
+}
+
+#[test]
+fn test_composite_key() {
+    match V1Kpdb::with_key("test/test_both.kdb".to_string(), CompositeKey::new()) {
+        Ok(_) => assert!(false),
+        Err(e) => assert_eq!(e, V1KpdbError::PassErr),
+    };
+
+    // Without challenge-response it's the same as new
+    let mut db = V1Kpdb::with_key("test/test_both.kdb".to_string(), composite_key(&[]))
+                     .ok()
+                     .unwrap();
+    assert!(db.load().is_ok());
+
+    let path = copy_to_tmp("test/test_both.kdb", "rust_keepass_test_composite.kdb");
+    assert!(db.set_key(composite_key(&[1, 2])).is_ok());
+    assert!(db.save(Some(path.clone()), None, None).is_ok());
+
+    let mut db = V1Kpdb::with_key(path.clone(), composite_key(&[1, 2])).ok().unwrap();
+    assert!(db.load().is_ok());
+    // Order matters
+    let mut db = V1Kpdb::with_key(path.clone(), composite_key(&[2, 1])).ok().unwrap();
+    assert!(is_wrong_key(db.load()));
+    let mut db = V1Kpdb::with_key(path.clone(), composite_key(&[1])).ok().unwrap();
+    assert!(is_wrong_key(db.load()));
+
+    // The error of a provider is passed on
+    let mut key = composite_key(&[1]);
+    key.add(KeyComponent::ChallengeResponse(Box::new(NoResponse)));
+    let mut db = V1Kpdb::with_key(path.clone(), key).ok().unwrap();
+    assert_eq!(db.load(), Err(V1KpdbError::KeyProviderErr));
+    let _ = fs::remove_file(&path);
+}
+
+#[test]
+fn test_archive_entry() {
+    let mut db = V1Kpdb::new("test/test_parsing.kdb".to_string(),
+                             Some("test".to_string()),
+                             None)
+                     .ok()
+                     .unwrap();
+    assert!(db.load().is_ok());
+    assert!(db.archive_group().is_none());
+    assert_eq!(db.archived().len(), 0);
+
+    let query = SearchQuery::new("test".to_string());
+    assert_eq!(db.search(&query).ok().unwrap().len(), 5);
+
+    let entry = db.entries[1].clone();
+    assert!(db.archive_entry(entry.clone()).is_ok());
+    let entry2 = db.entries[2].clone();
+    assert!(db.archive_entry(entry2).is_ok());
+    let archive = db.archive_group().unwrap();
+    assert_eq!(archive.borrow().level, 0);
+    assert_eq!(archive.borrow().entries.len(), 2);
+    assert_eq!(db.groups.iter().filter(|g| g.borrow().title == "Archive").count(), 1);
+    assert_eq!(db.archived().len(), 2);
+    assert!(db.archived()[0] == entry);
+
+    assert_eq!(db.search(&query).ok().unwrap().len(), 3);
+    let mut query = SearchQuery::new("test".to_string());
+    query.include_excluded = true;
+    assert_eq!(db.search(&query).ok().unwrap().len(), 5);
+}
+
+#[test]
+fn test_expiry() {
+    let db = open_parsing_db();
+    let now = Local::now();
+    for entry in db.entries.iter() {
+        entry.borrow_mut().set_expiry(None);
+    }
+    assert!(db.expired_entries(now).is_empty());
+    assert!(db.expiring_within(chrono::Duration::days(7)).is_empty());
+
+    db.entries[0].borrow_mut().set_expiry(Some(now + chrono::Duration::days(3)));
+    db.entries[1].borrow_mut().set_expiry(Some(now - chrono::Duration::days(1)));
+    db.entries[2].borrow_mut().set_expiry(Some(now + chrono::Duration::days(1)));
+    db.entries[3].borrow_mut().set_expiry(Some(now + chrono::Duration::days(30)));
+    let expired = db.expired_entries(now);
+    assert_eq!(expired.len(), 1);
+    assert!(expired[0] == db.entries[1]);
+    assert!(db.entries[1].borrow().is_expired(now));
+    let expiring = db.expiring_within(chrono::Duration::days(7));
+    assert_eq!(expiring.len(), 2);
+    assert!(expiring[0] == db.entries[2] && expiring[1] == db.entries[0]);
+
+    let mut entry = db.entries[0].borrow_mut();
+    assert_eq!(entry.expiry().unwrap().nanosecond(), 0);
+    entry.set_expiry(None);
+    assert!(entry.expiry().is_none());
+    assert_eq!(entry.expire, Local.ymd(2999, 12, 28).and_hms(23, 59, 59));
+    assert!(!entry.is_expired(Local.ymd(3000, 1, 1).and_hms(0, 0, 0)));
+}
+
+#[test]
+fn test_recycle_bin() {
+    let mut db = open_parsing_db();
+    let query = SearchQuery::new("test".to_string());
+    let found = db.search(&query).ok().unwrap().len();
+    let recycled = db.recycled().len();
+    let num_entries = db.entries.len();
+
+    let entry = db.search(&query).ok().unwrap()[0].clone();
+    assert!(db.recycle_entry(entry.clone()).is_ok());
+    let bin = db.backup_group().unwrap();
+    assert_eq!(bin.borrow().level, 0);
+    assert!(entry.borrow().group.as_ref().unwrap().borrow().id == bin.borrow().id);
+    assert_eq!(db.recycled().len(), recycled + 1);
+    assert_eq!(db.search(&query).ok().unwrap().len(), found - 1);
+    let mut with_backup = SearchQuery::new("test".to_string());
+    with_backup.include_backup = true;
+    assert!(db.search(&with_backup).ok().unwrap().contains(&entry));
+
+    // Recycling it again deletes it
+    assert!(db.recycle_entry(entry).is_ok());
+    assert_eq!(db.recycled().len(), recycled);
+    assert_eq!(db.entries.len(), num_entries - 1);
+
+    let group = db.groups.iter().find(|g| !is_backup_group(g)).unwrap().clone();
+    let group_id = group.borrow().id;
+    assert!(db.recycle_group(group).is_ok());
+    assert!(db.group_by_id(group_id).unwrap().borrow().parent.as_ref().unwrap().borrow().id ==
+            bin.borrow().id);
+
+    assert!(db.empty_recycle_bin().is_ok());
+    assert_eq!(db.recycled().len(), 0);
+    assert!(db.group_by_id(group_id).is_none());
+    assert!(db.backup_group().is_some());
+    assert_eq!(db.groups.iter().filter(|g| g.borrow().title == "Backup").count(), 1);
+}
+
+#[test]
+fn test_search_scope() {
+    let mut db = V1Kpdb::new("test/test_parsing.kdb".to_string(),
+                             Some("test".to_string()),
+                             None)
+                     .ok()
+                     .unwrap();
+    assert!(db.load().is_ok());
+    db.entries[0].borrow_mut().binary_desc = Some("scan.pdf".to_string());
+    db.entries[0].borrow_mut().binary = Some(SecureBytes::new(vec![1]));
+
+    let mut query = SearchQuery::new("scan".to_string());
+    assert_eq!(db.search(&query).ok().unwrap().len(), 0);
+    query.attachment_names = true;
+    assert_eq!(db.search(&query).ok().unwrap().len(), 1);
+
+    // Group 11 holds test2 itself and test4/test5 in its subgroups
+    let mut query = SearchQuery::new("test".to_string());
+    let id = db.groups[2].borrow().id;
+    query.excluded_groups.push(id);
+    let result = db.search(&query).ok().unwrap();
+    let titles: Vec<String> = result.iter().map(|e| e.borrow().title.clone()).collect();
+    assert_eq!(titles, vec!["test1", "test3"]);
+}
+
+#[cfg(unix)]
+fn open_fd(path: &str) -> i32 {
+    use std::os::unix::io::IntoRawFd;
+    File::open(path).unwrap().into_raw_fd()
+}
+
+#[test]
+#[cfg(unix)]
+fn test_credentials_from_fd() {
+    let mut path = env::temp_dir();
+    path.push("rust_keepass_test_fd_password");
+    let path = path.to_str().unwrap().to_string();
+    {
+        use std::io::Write;
+        let mut file = File::create(&path).unwrap();
+        assert!(file.write_all(b"test\n").is_ok());
+    }
+
+    // The descriptors of open_fd are owned by nobody else
+    let mut key = CompositeKey::new();
+    let password = unsafe { fdkey::password_from_fd(open_fd(&path)) };
+    key.add(KeyComponent::Password(password.ok().unwrap()));
+    let mut db = V1Kpdb::with_key("test/test_password.kdb".to_string(), key).ok().unwrap();
+    assert!(db.load().is_ok());
+
+    let mut key = CompositeKey::new();
+    let password = unsafe { fdkey::password_from_fd(open_fd(&path)) };
+    key.add(KeyComponent::Password(password.ok().unwrap()));
+    let keyfile = unsafe { fdkey::keyfile_from_fd(open_fd("test/test_key")) };
+    key.add(KeyComponent::KeyfileData(keyfile.ok().unwrap()));
+    let mut db = V1Kpdb::with_key("test/test_both.kdb".to_string(), key).ok().unwrap();
+    assert!(db.load().is_ok());
+    let _ = fs::remove_file(&path);
+}
+
+#[test]
+fn test_rotate_protection_keys() {
+    let mut db = V1Kpdb::new("test/test_parsing.kdb".to_string(),
+                             Some("test".to_string()),
+                             None)
+                     .ok()
+                     .unwrap();
+    assert!(db.load().is_ok());
+    assert!(!db.rotate_protection_keys_if_due());
+
+    let password = {
+        let mut entry = db.entries[0].borrow_mut();
+        let password = entry.raw_password_mut().unwrap();
+        password.unlock();
+        let plain = password.string.clone();
+        password.delete();
+        plain
+    };
+
+    db.key_rotation_interval = Some(Duration::from_secs(0));
+    assert!(db.rotate_protection_keys_if_due());
+
+    {
+        let mut entry = db.entries[0].borrow_mut();
+        let new_password = entry.raw_password_mut().unwrap();
+        new_password.unlock();
+        assert_eq!(new_password.string, password);
+        new_password.delete();
+    }
+
+    // The master password still works after the rotation
+    let path = copy_to_tmp("test/test_parsing.kdb", "rust_keepass_test_rotate.kdb");
+    assert!(db.save(Some(path.clone()), None, None).is_ok());
+    let mut db = V1Kpdb::new(path.clone(), Some("test".to_string()), None).ok().unwrap();
+    assert!(db.load().is_ok());
+    let _ = fs::remove_file(&path);
+}
+
+#[test]
+fn test_password_bytes() {
+    let mut db = V1Kpdb::new("test/test_password.kdb".to_string(),
+                             Some("test".to_string()),
+                             None)
+                     .ok()
+                     .unwrap();
+    assert!(db.load().is_ok());
+
+    // "täst" in Latin-1
+    let latin1_key = || {
+        let mut key = CompositeKey::new();
+        key.add(KeyComponent::PasswordBytes(SecureBytes::new(vec![0x74, 0xe4, 0x73, 0x74])));
+        key
+    };
+    let path = copy_to_tmp("test/test_password.kdb", "rust_keepass_test_password_bytes.kdb");
+    assert!(db.set_key(latin1_key()).is_ok());
+    assert!(db.save(Some(path.clone()), None, None).is_ok());
+
+    let mut db = V1Kpdb::with_key(path.clone(), latin1_key()).ok().unwrap();
+    assert!(db.load().is_ok());
+    // The UTF-8 encoding of the same password is a different key
+    let mut db = V1Kpdb::new(path.clone(), Some("täst".to_string()), None).ok().unwrap();
+    assert!(is_wrong_key(db.load()));
+    let _ = fs::remove_file(&path);
+}
+
+#[test]
+fn test_save_checks_field_limits() {
+    let mut db = V1Kpdb::new("test/test_password.kdb".to_string(),
+                             Some("test".to_string()),
+                             None)
+                     .ok()
+                     .unwrap();
+    assert!(db.load().is_ok());
+    let path = copy_to_tmp("test/test_password.kdb", "rust_keepass_test_field_limits.kdb");
+    let original = read_file(&path);
+
+    db.field_limits.comment = 8;
+    db.entries[0].borrow_mut().comment = Some("longer than eight".to_string());
+    assert_eq!(db.save(Some(path.clone()), None, None),
+               Err(V1KpdbError::FieldLengthErr));
+    assert_eq!(read_file(&path), original);
+
+    db.entries[0].borrow_mut().comment = Some("short".to_string());
+    assert!(db.save(Some(path.clone()), None, None).is_ok());
+
+    // Titles are checked when they're set, a long one loaded from a file
+    // still saves
+    let long = (0..v1::MAX_TITLE_LEN + 1).map(|_| 'a').collect::<String>();
+    assert_eq!(db.create_group(long.clone(), None, None, None).err(),
+               Some(V1KpdbError::FieldLengthErr));
+    assert_eq!(db.groups[0].borrow_mut().set_title(long.clone()),
+               Err(V1KpdbError::FieldLengthErr));
+    db.entries[0].borrow_mut().title = long.clone();
+    db.groups[0].borrow_mut().title = long.clone();
+    assert!(db.save(Some(path.clone()), None, None).is_ok());
+    let mut reloaded = V1Kpdb::new(path.clone(), Some("test".to_string()), None).ok().unwrap();
+    assert!(reloaded.load().is_ok());
+    assert_eq!(reloaded.entries[0].borrow().title, long);
+    assert_eq!(reloaded.groups[0].borrow().title, long);
+    let _ = fs::remove_file(&path);
+}
+
+#[test]
+fn test_unlock_policy() {
+    let mut db = V1Kpdb::new("test/test_both.kdb".to_string(),
+                             Some("test".to_string()),
+                             Some("test/test_key".to_string()))
+                     .ok()
+                     .unwrap();
+    assert!(db.load().is_ok());
+    assert!(db.unlock_policy().is_none());
+
+    // The key has no challenge-response component
+    let policy = UnlockPolicy::all(vec![KeyFactor::Password,
+                                        KeyFactor::Keyfile,
+                                        KeyFactor::ChallengeResponse]);
+    assert_eq!(db.set_unlock_policy(Some(policy)), Err(V1KpdbError::PolicyErr));
+    // 1 of 2 can't be enforced
+    let policy = UnlockPolicy {
+        required: 1,
+        factors: vec![KeyFactor::Password, KeyFactor::Keyfile],
+    };
+    assert_eq!(db.set_unlock_policy(Some(policy)), Err(V1KpdbError::PolicyErr));
+
+    let policy = UnlockPolicy::all(vec![KeyFactor::Keyfile, KeyFactor::Password]);
+    assert!(db.set_unlock_policy(Some(policy.clone())).is_ok());
+    assert_eq!(db.set_credentials(Some(SecureString::new("test".to_string())), None),
+               Err(V1KpdbError::PolicyErr));
+
+    let path = copy_to_tmp("test/test_both.kdb", "rust_keepass_test_unlock_policy.kdb");
+    assert!(db.save(Some(path.clone()), None, None).is_ok());
+    assert!(db.save(Some(path.clone()), None, None).is_ok());
+    let num_meta_entries = db.meta_entries.len();
+
+    let mut db = V1Kpdb::new(path.clone(),
+                             Some("test".to_string()),
+                             Some("test/test_key".to_string()))
+                     .ok()
+                     .unwrap();
+    assert!(db.load().is_ok());
+    assert_eq!(db.unlock_policy(), Some(&policy));
+    assert_eq!(db.meta_entries.len(), num_meta_entries);
+    let _ = fs::remove_file(&path);
+}
+
+#[test]
+fn test_load_with_recovery() {
+    // An intact database gives a clean report
+    let mut db = V1Kpdb::new("test/test_parsing.kdb".to_string(),
+                             Some("test".to_string()),
+                             None)
+                     .unwrap();
+    let report = db.load_with_recovery().ok().unwrap();
+    assert!(report.is_clean());
+    let num_groups = db.groups.len();
+    let num_entries = db.entries.len();
+
+    // Cut off the end of the file
+    let path = copy_to_tmp("test/test_parsing.kdb", "rust_keepass_test_recovery.kdb");
+    let raw = read_file(&path);
+    let file = fs::OpenOptions::new().write(true).open(&path).unwrap();
+    assert!(file.set_len((raw.len() - 100) as u64).is_ok());
+
+    let mut db = V1Kpdb::new(path.clone(), Some("test".to_string()), None).unwrap();
+    assert!(db.load().is_err());
+    let report = db.load_with_recovery().ok().unwrap();
+    assert!(!report.content_intact);
+    assert!(report.truncated);
+    assert!(report.missing_groups > 0 || report.missing_entries > 0);
+    assert!(db.groups.len() <= num_groups);
+    assert!(db.entries.len() < num_entries);
+    assert!(db.entries.iter().all(|e| e.borrow().group.is_some()));
+    if report.orphaned_entries > 0 {
+        assert!(db.groups.iter().any(|g| g.borrow().title == RECOVERED_GROUP_TITLE));
+    }
+
+    // The salvaged tree can be saved and loaded again
+    let saved = copy_to_tmp("test/test_parsing.kdb", "rust_keepass_test_recovered.kdb");
+    assert!(db.save(Some(saved.clone()), None, None).is_ok());
+    let mut db = V1Kpdb::new(saved, Some("test".to_string()), None).unwrap();
+    assert!(db.load().is_ok());
+
+    // Nothing can be salvaged with a wrong password
+    let mut db = V1Kpdb::new("test/test_parsing.kdb".to_string(),
+                             Some("tes".to_string()),
+                             None)
+                     .unwrap();
+    assert_eq!(db.load_with_recovery().err(), Some(V1KpdbError::DecryptErr));
+}
+
+#[test]
+fn test_load_detailed() {
+    let open = |path: &str, password: &str| {
+        V1Kpdb::new(path.to_string(), Some(password.to_string()), None)
+            .unwrap()
+            .load_detailed()
+    };
+
+    let err = open("test/test_password.kdb", "tes").err().unwrap();
+    assert!(err.is_wrong_key());
+    assert!(!err.is_corrupt());
+    assert_eq!(err.coarse(), V1KpdbError::DecryptErr);
+
+    match open("test/nonexistent.kdb", "test") {
+        Err(KpdbError::Io { error, ref source }) => {
+            assert_eq!(error, V1KpdbError::FileErr);
+            assert_eq!(source.kind(), io::ErrorKind::NotFound);
+        }
+        _ => assert!(false),
+    }
+
+    // Wrong signature
+    let path = copy_to_tmp("test/test_password.kdb", "rust_keepass_test_detailed_sig.kdb");
+    let mut raw = read_file(&path);
+    raw[0] ^= 0xFF;
+    assert!(File::create(&path).unwrap().write_all(&raw).is_ok());
+    match open(&path, "test") {
+        Err(KpdbError::InvalidHeader { field, error }) => {
+            assert_eq!(field, HeaderField::Signature);
+            assert_eq!(error, V1KpdbError::SignatureErr);
+        }
+        _ => assert!(false),
+    }
+
+    // Too small for the header
+    assert!(File::create(&path).unwrap().write_all(&raw[..100]).is_ok());
+    match open(&path, "test") {
+        Err(KpdbError::TooSmall) => {}
+        _ => assert!(false),
+    }
+
+    // Damaged content with the right key
+    let path = copy_to_tmp("test/test_parsing.kdb", "rust_keepass_test_detailed_cut.kdb");
+    let raw = read_file(&path);
+    let file = fs::OpenOptions::new().write(true).open(&path).unwrap();
+    assert!(file.set_len((raw.len() - 100) as u64).is_ok());
+    let err = open(&path, "test").err().unwrap();
+    assert!(err.is_corrupt());
+    match err {
+        KpdbError::Corrupt { offset: Some(_), error } => assert_eq!(error, V1KpdbError::HashErr),
+        _ => assert!(false),
+    }
+    assert!(format!("{}", err).starts_with("Database is damaged at byte"));
+}
+
+#[test]
+fn test_stats() {
+    let db = open_parsing_db();
+    let stats = db.stats();
+    assert_eq!(stats.num_groups, db.groups.len());
+    assert_eq!(stats.num_entries, db.entries.len());
+    let max_level = db.groups.iter().map(|g| g.borrow().level).max().unwrap();
+    assert_eq!(stats.depth, max_level as usize + 1);
+    assert!(stats.oldest_mod.unwrap() <= stats.newest_mod.unwrap());
+
+    let now = Local::now();
+    for entry in db.entries.iter() {
+        entry.borrow_mut().set_expiry(None);
+    }
+    db.entries[0].borrow_mut().set_expiry(Some(now - chrono::Duration::days(1)));
+    db.entries[0].borrow_mut().last_mod = Local.ymd(1999, 1, 1).and_hms(0, 0, 0);
+    let old = db.entries[1].borrow_mut().remove_attachment().map_or(0, |(_, data)| data.len());
+    assert!(db.entries[1]
+              .borrow_mut()
+              .set_attachment("a.txt".to_string(), vec![1, 2, 3])
+              .is_ok());
+
+    let after = db.stats();
+    assert_eq!(after.num_expired, 1);
+    assert_eq!(after.oldest_mod, Some(Local.ymd(1999, 1, 1).and_hms(0, 0, 0)));
+    assert_eq!(after.attachment_size, stats.attachment_size - old + 3);
+    assert!(after.num_attachments >= 1);
+}
+
+#[test]
+fn test_duplicates() {
+    let mut db = open_parsing_db();
+    let group = db.groups[0].clone();
+    let create = |db: &mut V1Kpdb, title: &str| {
+        db.create_entry(group.clone(),
+                        title.to_string(),
+                        None,
+                        None,
+                        Some("https://example.com".to_string()),
+                        None,
+                        Some("user".to_string()),
+                        Some("Dup1icate!Pass".to_string()))
+    };
+    let first = create(&mut db, "Duplicate");
+    let second = create(&mut db, "Duplicate");
+    let other = create(&mut db, "Other title");
+    second.borrow_mut().last_mod = first.borrow().last_mod + chrono::Duration::seconds(1);
+    let handles: Vec<EntryHandle> = [&first, &second, &other]
+                                        .iter()
+                                        .map(|e| db.entry_handle(&e.borrow()))
+                                        .collect();
+
+    let by_fields = db.find_duplicates(DuplicateCriteria::Fields);
+    assert!(by_fields.contains(&vec![handles[0], handles[1]]));
+    let by_password = db.find_duplicates(DuplicateCriteria::Password);
+    assert!(by_password.contains(&vec![handles[0], handles[1], handles[2]]));
+
+    let num_recycled = by_fields.iter().map(|c| c.len() - 1).sum::<usize>();
+    assert_eq!(db.deduplicate(DuplicateCriteria::Fields).ok(), Some(num_recycled));
+    assert!(is_backup_group(first.borrow().group.as_ref().unwrap()));
+    assert!(!is_backup_group(second.borrow().group.as_ref().unwrap()));
+    assert!(db.find_duplicates(DuplicateCriteria::Fields).is_empty());
+    let by_password = db.find_duplicates(DuplicateCriteria::Password);
+    assert!(by_password.contains(&vec![handles[1], handles[2]]));
+}
+
+#[test]
+fn test_validate_and_repair() {
+    let mut db = open_parsing_db();
+    assert!(db.validate().is_empty());
+    assert_eq!(db.repair().ok(), Some(vec![]));
+    let num_groups = db.groups.len();
+    let num_entries = db.entries.len();
+
+    let first_id = db.groups[0].borrow().id;
+    let second_id = db.groups[1].borrow().id;
+    db.groups[1].borrow_mut().id = first_id;
+    let uuid = db.entries[0].borrow().uuid;
+    db.entries[0].borrow_mut().group = None;
+    db.entries[0].borrow_mut().group_id = 9999;
+    let level = db.groups[2].borrow().level;
+    db.groups[2].borrow_mut().level = level + 3;
+    let third_id = db.groups[2].borrow().id;
+
+    let violations = db.validate();
+    assert!(violations.contains(&Violation::DuplicateGroupId(first_id)));
+    assert!(violations.contains(&Violation::OrphanedEntry(uuid)));
+    assert!(violations.contains(&Violation::LevelMismatch(third_id)));
+    assert!(violations.iter().any(|v| v.code() == "dangling_reference"));
+
+    assert_eq!(db.repair().ok(), Some(violations));
+    assert!(db.validate().is_empty());
+    assert!(db.groups[1].borrow().id != first_id && db.groups[1].borrow().id != second_id);
+    assert_eq!(db.groups[2].borrow().level, level);
+    assert_eq!(db.groups.len(), num_groups + 1);
+    assert_eq!(db.entries.len(), num_entries);
+    let recovered = db.entries[0].borrow().group.clone().unwrap();
+    assert_eq!(recovered.borrow().title, RECOVERED_GROUP_TITLE);
+
+    let path = copy_to_tmp("test/test_parsing.kdb", "rust_keepass_test_repair.kdb");
+    assert!(db.save(Some(path.clone()), None, None).is_ok());
+    let mut reloaded = V1Kpdb::new(path.clone(), Some("test".to_string()), None).ok().unwrap();
+    assert!(reloaded.load().is_ok());
+    assert!(reloaded.validate().is_empty());
+    assert_eq!(reloaded.groups.len(), num_groups + 1);
+    let _ = fs::remove_file(&path);
+}
+
+#[test]
+fn test_builder() {
+    let mut path = env::temp_dir();
+    path.push("rust_keepass_test_builder.kdb");
+    let path = path.to_str().unwrap().to_string();
+    let _ = fs::remove_file(&path);
+
+    let mut db = V1KpdbBuilder::new(path.clone())
+                     .password("test".to_string())
+                     .rounds(1000)
+                     .group_title("Internet".to_string())
+                     .build()
+                     .unwrap();
+    assert_eq!(db.header.key_transf_rounds, 1000);
+    let group = db.groups[0].clone();
+    db.create_entry(group, "Mail".to_string(), None, None, None, None, None, None);
+    assert!(db.validate().is_empty());
+    assert!(db.save(None, None, None).is_ok());
+
+    let mut loaded = V1Kpdb::new(path.clone(), Some("test".to_string()), None).ok().unwrap();
+    assert!(loaded.load().is_ok());
+    assert_eq!(loaded.groups.len(), 1);
+    assert_eq!(loaded.groups[0].borrow().title, "Internet");
+    assert_eq!(loaded.entries[0].borrow().title, "Mail");
+    let _ = fs::remove_file(&path);
+
+    let db = V1KpdbBuilder::new(path.clone())
+                 .keyfile("test/test_key".to_string())
+                 .calibrate(Duration::from_millis(10))
+                 .build()
+                 .unwrap();
+    assert!(db.header.key_transf_rounds > 0);
+    assert_eq!(db.groups[0].borrow().title, DEFAULT_GROUP_TITLE);
+    assert_eq!(V1KpdbBuilder::new(path.clone()).build().err(), Some(V1KpdbError::PassErr));
+    let builder = V1KpdbBuilder::new(path.clone()).password("test".to_string());
+    assert_eq!(builder.rounds(0).build().err(), Some(V1KpdbError::RoundsErr));
+    assert_eq!(V1KpdbBuilder::new(path.clone())
+                   .password("test".to_string())
+                   .cipher(Cipher::Twofish)
+                   .build()
+                   .err(),
+               Some(V1KpdbError::EncFlagErr));
+}
+
+#[test]
+fn test_reencrypt() {
+    let path = copy_to_tmp("test/test_password.kdb", "rust_keepass_test_reencrypt.kdb");
+    let mut db = V1Kpdb::new(path.clone(), Some("test".to_string()), None).ok().unwrap();
+    assert!(db.load().is_ok());
+    let seed = db.header.transf_randomseed.clone();
+
+    let mut options = ReencryptOptions::new();
+    options.cipher = Some(Cipher::Twofish);
+    assert_eq!(db.reencrypt(options), Err(V1KpdbError::EncFlagErr));
+    options.cipher = Some(Cipher::Aes);
+    options.rounds = Some(0);
+    assert_eq!(db.reencrypt(options), Err(V1KpdbError::RoundsErr));
+    assert_eq!(db.header.key_transf_rounds, 150000);
+
+    options.rounds = Some(1000);
+    assert!(db.reencrypt(options).is_ok());
+    assert!(!db.dirty());
+    let mut reopened = V1Kpdb::new(path.clone(), Some("test".to_string()), None).ok().unwrap();
+    assert!(reopened.load().is_ok());
+    assert_eq!(reopened.header.key_transf_rounds, 1000);
+    assert_eq!(reopened.header.enc_flag, 3);
+    assert!(reopened.header.transf_randomseed != seed);
+    assert_eq!(reopened.entries.len(), db.entries.len());
+    let _ = fs::remove_file(&path);
+}
+
+#[test]
+fn test_export_encrypted() {
+    // RFC 7914, 11
+    let derived = archive::pbkdf2_sha256(b"passwd", b"salt", 1, 64);
+    assert_eq!(derived.bytes(),
+               &"55ac046e56e3089fec1691c22544b605f94185216dde0465e68b9d57c20dacbc\
+                 49ca9cccf179b645991664b39d77ef317c71b845b1e30bd509112041d3a19783"
+                    .from_hex()
+                    .unwrap()[..]);
+    assert_eq!(archive::pbkdf2_sha256(b"passwd", b"salt", 1, 40).bytes(), &derived.bytes()[..40]);
+
+    // Attachments larger than a chunk of the encryption
+    let mut spec = FixtureSpec::new(1074);
+    spec.num_groups = 4;
+    spec.max_level = 2;
+    spec.num_entries = 6;
+    spec.num_attachments = 2;
+    spec.attachment_size = 100000;
+    spec.num_meta_streams = 1;
+    let mut db = fixtures::open(&spec, String::new()).ok().unwrap();
+    let mut exported = vec![];
+    assert_eq!(db.export_encrypted_with(&mut exported, SecureString::new("backup".to_string()), 0),
+               Err(V1KpdbError::RoundsErr));
+    let protected = shadow::outstanding().len();
+    assert!(db.export_encrypted_with(&mut exported,
+                                     SecureString::new("backup".to_string()),
+                                     10)
+              .is_ok());
+    assert_eq!(&exported[..archive::MAGIC.len()], archive::MAGIC);
+    // The content is streamed, no copy of it is left behind
+    assert_eq!(shadow::outstanding().len(), protected);
+    let mut parser = SaveParser::new();
+    parser.prepare(&db);
+    let content = archive::decrypt(&exported[..], b"backup").ok().unwrap();
+    assert!(content.bytes() == &parser.database[..]);
+    unsafe {
+        mem_protect::zero(&parser.database);
+    }
+
+    let tree = archive::read(&exported[..], b"backup").ok().unwrap();
+    assert_eq!(tree.groups.len(), 4);
+    assert_eq!(tree.entries.len(), 6);
+    assert!(tree.meta_entries.iter().any(|e| {
+        e.borrow().comment.as_ref().map(|c| &c[..]) == Some("KPX_FIXTURE_0")
+    }));
+
+    let mut restored = V1KpdbBuilder::new(String::new())
+                           .password("other".to_string())
+                           .build()
+                           .ok()
+                           .unwrap();
+    restored.undo_limit = 1;
+    let before = restored.entries.len();
+    let wrong = SecureString::new("wrong".to_string());
+    assert_eq!(restored.import_encrypted(&exported[..], wrong),
+               Err(V1KpdbError::HashErr));
+    let mut tampered = exported.clone();
+    tampered[100] ^= 1;
+    assert_eq!(restored.import_encrypted(&tampered[..], SecureString::new("backup".to_string())),
+               Err(V1KpdbError::HashErr));
+    assert_eq!(restored.import_encrypted(&exported[..70], SecureString::new("backup".to_string())),
+               Err(V1KpdbError::FileErr));
+    assert_eq!(restored.import_encrypted(&b"KDBARC"[..], SecureString::new("backup".to_string())),
+               Err(V1KpdbError::SignatureErr));
+    let mut iterations = exported.clone();
+    iterations[8..12].copy_from_slice(&[0, 0, 0, 0]);
+    assert_eq!(restored.import_encrypted(&iterations[..],
+                                         SecureString::new("backup".to_string())),
+               Err(V1KpdbError::RoundsErr));
+    assert_eq!(restored.entries.len(), before);
+    assert_eq!(restored.undo_steps(), 0);
+
+    assert!(restored.import_encrypted(&exported[..], SecureString::new("backup".to_string()))
+                    .is_ok());
+    assert!(restored.dirty());
+    assert_eq!(restored.groups.len(), db.groups.len());
+    assert_eq!(restored.entries.len(), db.entries.len());
+    for (restored, original) in restored.entries.iter().zip(db.entries.iter()) {
+        let mut restored = restored.borrow_mut();
+        let mut original = original.borrow_mut();
+        assert_eq!(restored.uuid, original.uuid);
+        assert_eq!(restored.password().map(|p| p.to_string()),
+                   original.password().map(|p| p.to_string()));
+        assert_eq!(restored.attachment(), original.attachment());
+    }
+    assert!(restored.validate().is_empty());
+    assert_eq!(restored.undo().ok(), Some(true));
+    assert_eq!(restored.entries.len(), before);
+}
+
+#[test]
+fn test_transaction() {
+    let mut db = open_parsing_db();
+    db.undo_limit = 5;
+    let num_groups = db.groups.len();
+    let num_entries = db.entries.len();
+    let uuid = db.entries[0].borrow().uuid;
+    let title = db.entries[0].borrow().title.clone();
+
+    let result: Result<(), V1KpdbError> = db.transaction(|db| {
+        let group = try!(db.create_group("Half".to_string(), None, None, None));
+        db.create_entry(group, "Half".to_string(), None, None, None, None, None, None);
+        db.entries[0].borrow_mut().title = "Changed".to_string();
+        try!(db.delete_group(9999, false));
+        Ok(())
+    });
+    assert_eq!(result, Err(V1KpdbError::IndexErr));
+    assert_eq!(db.groups.len(), num_groups);
+    assert_eq!(db.entries.len(), num_entries);
+    assert_eq!(db.find_by_uuid(&uuid).unwrap().borrow().title, title);
+    assert!(!db.dirty());
+    assert_eq!(db.undo_steps(), 0);
+    assert!(db.validate().is_empty());
+
+    // Inner transactions roll back on their own
+    let result = db.transaction(|db| {
+        try!(db.create_group("Kept".to_string(), None, None, None));
+        let inner: Result<(), V1KpdbError> = db.transaction(|db| {
+            try!(db.create_group("Dropped".to_string(), None, None, None));
+            Err(V1KpdbError::TreeErr)
+        });
+        assert_eq!(inner, Err(V1KpdbError::TreeErr));
+        Ok(db.groups.len())
+    });
+    assert_eq!(result, Ok(num_groups + 1));
+    assert!(db.groups.iter().any(|g| g.borrow().title == "Kept"));
+    assert!(!db.groups.iter().any(|g| g.borrow().title == "Dropped"));
+    assert!(db.dirty());
+    assert_eq!(db.undo_steps(), 1);
+    assert_eq!(db.undo().ok(), Some(true));
+    assert_eq!(db.groups.len(), num_groups);
+}
+
+#[test]
+fn test_undo_redo() {
+    let mut db = open_parsing_db();
+    let group = db.groups[0].clone();
+    db.create_entry(group.clone(), "Untracked".to_string(), None, None, None, None, None, None);
+    assert_eq!(db.undo_steps(), 0);
+    assert_eq!(db.undo().ok(), Some(false));
+
+    db.undo_limit = 2;
+    let num_entries = db.entries.len();
+    let entry = db.create_entry(group.clone(),
+                                "Undo".to_string(),
+                                None,
+                                None,
+                                None,
+                                None,
+                                Some("user".to_string()),
+                                Some("secret".to_string()));
+    let handle = db.entry_handle(&entry.borrow());
+    drop(entry);
+    let removed = db.entries[0].clone();
+    let removed_uuid = removed.borrow().uuid;
+    assert!(db.remove_entry(removed).is_ok());
+    assert!(db.edit_entry(&handle, |e| e.title = "Renamed".to_string()).is_ok());
+    assert_eq!(db.undo_steps(), 2);
+
+    assert_eq!(db.undo().ok(), Some(true));
+    assert_eq!(db.with_entry(&handle, |e| e.title.clone()).ok(), Some("Undo".to_string()));
+    assert_eq!(db.with_entry(&handle, |e| e.password().unwrap().to_string()).ok(),
+               Some("secret".to_string()));
+    assert_eq!(db.undo().ok(), Some(true));
+    assert!(db.find_by_uuid(&removed_uuid).is_some());
+    assert_eq!(db.entries.len(), num_entries + 1);
+    assert_eq!(db.undo().ok(), Some(false));
+    assert!(db.validate().is_empty());
+
+    assert_eq!(db.redo().ok(), Some(true));
+    assert!(db.find_by_uuid(&removed_uuid).is_none());
+    assert_eq!(db.redo().ok(), Some(true));
+    assert_eq!(db.with_entry(&handle, |e| e.title.clone()).ok(), Some("Renamed".to_string()));
+    assert_eq!(db.redo().ok(), Some(false));
+
+    // A batch is one step, a batch without changes none
+    db.batch(|db| {
+        let group = db.groups[0].clone();
+        db.create_entry(group.clone(), "One".to_string(), None, None, None, None, None, None);
+        db.create_entry(group, "Two".to_string(), None, None, None, None, None, None);
+    });
+    assert_eq!(db.undo_steps(), 2);
+    assert_eq!(db.redo_steps(), 0);
+    db.batch(|db| db.entries.len());
+    assert_eq!(db.undo_steps(), 2);
+    assert_eq!(db.undo().ok(), Some(true));
+    assert_eq!(db.entries.len(), num_entries);
+
+    assert!(db.load().is_ok());
+    assert_eq!(db.undo_steps(), 0);
+    assert_eq!(db.redo_steps(), 0);
+}
+
+#[test]
+fn test_undo_snapshots() {
+    let content = b"groups and entries".to_vec();
+    let snapshot = Snapshot::new(SecureBytes::new(content.clone()), 1, 2);
+    let decrypted = snapshot.decrypt().ok().unwrap();
+    assert_eq!(decrypted, content);
+    unsafe {
+        mem_protect::zero(&decrypted);
+    }
+    mem_protect::unlock(&decrypted);
+    assert_eq!(Snapshot::new(SecureBytes::new(vec![]), 0, 0).decrypt().ok(), Some(vec![]));
+
+    // Changes are recorded without comparing the whole database, but
+    // only if they're told about
+    let mut db = open_parsing_db();
+    db.undo_limit = 10;
+    let entry = db.entries[0].clone();
+    assert!(db.batch(|_| entry.borrow_mut().set_title("Renamed".to_string())).is_ok());
+    assert!(db.batch(|_| entry.borrow_mut().set_title("Again".to_string())).is_ok());
+    assert_eq!(db.undo_steps(), 2);
+    db.batch(|_| entry.borrow_mut().title = "Untold".to_string());
+    assert_eq!(db.undo_steps(), 2);
+    db.batch(|_| {
+        let mut entry = entry.borrow_mut();
+        entry.title = "Told".to_string();
+        entry.set_dirty(true);
+    });
+    assert_eq!(db.undo_steps(), 3);
+    assert_eq!(db.undo().ok(), Some(true));
+    assert_eq!(db.entries[0].borrow().title, "Untold");
+}
+
+#[test]
+fn test_dirty() {
+    let mut db = open_parsing_db();
+    assert!(!db.dirty());
+    assert!(db.changed_entries().is_empty());
+
+    assert!(db.entries[1].borrow_mut().set_title("Changed".to_string()).is_ok());
+    assert!(db.entries[1].borrow().dirty());
+    assert!(db.dirty());
+    let group = db.groups[0].clone();
+    let entry = db.create_entry(group, "New".to_string(), None, None, None, None, None, None);
+    let changed = db.changed_entries();
+    assert_eq!(changed.len(), 2);
+    assert!(changed[0] == db.entries[1] && changed[1] == entry);
+
+    let path = copy_to_tmp("test/test_parsing.kdb", "rust_keepass_test_dirty.kdb");
+    assert!(db.save(Some(path.clone()), None, None).is_ok());
+    assert!(!db.dirty());
+    assert!(!entry.borrow().dirty());
+
+    // Removals and direct writes with set_dirty count as well
+    assert!(db.remove_entry(entry).is_ok());
+    assert!(db.dirty());
+    assert!(db.changed_entries().is_empty());
+    assert!(db.save(None, None, None).is_ok());
+    db.groups[0].borrow_mut().title = "Renamed".to_string();
+    assert!(!db.dirty());
+    db.groups[0].borrow_mut().set_dirty(true);
+    assert!(db.dirty());
+    assert!(db.load().is_ok());
+    assert!(!db.dirty());
+    let handle = db.group_handle(&db.groups[1].borrow());
+    assert!(db.edit_group(&handle, |g| g.image = 3).is_ok());
+    assert!(db.groups[1].borrow().dirty());
+    let _ = fs::remove_file(&path);
+}
+
+#[test]
+fn test_fixtures() {
+    let mut spec = FixtureSpec::new(42);
+    spec.num_groups = 6;
+    spec.max_level = 2;
+    spec.num_entries = 20;
+    spec.num_attachments = 3;
+    spec.attachment_size = 100;
+    spec.num_meta_streams = 2;
+    let data = fixtures::generate(&spec).unwrap();
+    assert!(data == fixtures::generate(&spec).unwrap());
+    fixtures::assert_golden("test/test_fixture.kdb", &data);
+
+    let mut db = fixtures::open(&spec, "fixture.kdb".to_string()).unwrap();
+    assert_eq!(db.groups.len(), 6);
+    assert!(db.groups.iter().any(|g| g.borrow().level == 2));
+    assert_eq!(db.entries.len(), 20);
+    assert_eq!(db.entries.iter().filter(|e| e.borrow().binary.is_some()).count(), 3);
+    assert_eq!(db.meta_entries.len(), 2);
+    assert_eq!(db.header.key_transf_rounds, 10);
+    assert!(db.entries.iter().all(|e| e.borrow().last_mod == spec.time));
+    // Loading and encoding again gives the same file
+    let header = db.header.clone();
+    assert!(db.encode_with(header, |_| ()).unwrap() == data);
+
+    spec.seed = 43;
+    let other = fixtures::generate(&spec).unwrap();
+    assert_eq!(other.len(), data.len());
+    assert!(other != data);
+    // Other seeds and IV, the header differs right after version
+    let path = copy_to_tmp("test/test_fixture.kdb", "rust_keepass_test_fixture.kdb");
+    if env::var_os(fixtures::UPDATE_GOLDEN_ENV).is_none() {
+        assert_eq!(fixtures::compare_golden(&path, &other).unwrap().unwrap().offset, 16);
+        assert_eq!(fixtures::compare_golden("test/missing.kdb", &other),
+                   Err(V1KpdbError::FileErr));
+    }
+    let _ = fs::remove_file(&path);
+
+    spec.enc_flag = 8;
+    let mut db = V1Kpdb::new(String::new(), Some(spec.password.clone()), None).ok().unwrap();
+    assert_eq!(db.load_from_bytes(&fixtures::generate(&spec).unwrap()),
+               Err(V1KpdbError::EncFlagErr));
+}
+
+// Collects the messages of every thread, other tests log as well
+#[cfg(feature = "log")]
+struct CaptureLogger {
+    messages: Mutex<Vec<String>>,
+}
+
+#[cfg(feature = "log")]
+impl Log for CaptureLogger {
+    fn enabled(&self, _: &Metadata) -> bool {
+        true
+    }
+
+    fn log(&self, record: &Record) {
+        self.messages.lock().unwrap().push(format!("{}", record.args()));
+    }
+
+    fn flush(&self) {}
+}
+
+#[cfg(feature = "log")]
+static LOGGER: CaptureLogger = CaptureLogger { messages: Mutex::new(vec![]) };
+
+#[cfg(feature = "log")]
+#[test]
+fn test_log_without_secrets() {
+    assert!(log::set_logger(&LOGGER).is_ok());
+    log::set_max_level(LevelFilter::Trace);
+    let mut db = open_parsing_db();
+    let path = copy_to_tmp("test/test_parsing.kdb", "rust_keepass_test_log.kdb");
+    assert!(db.save(Some(path.clone()), None, None).is_ok());
+    let _ = fs::remove_file(&path);
+
+    let mut secrets = vec!["test".to_string(), path];
+    for entry in db.entries.iter() {
+        let mut entry = entry.borrow_mut();
+        secrets.push(entry.title.clone());
+        secrets.extend(entry.password().map(|p| p.to_string()));
+    }
+    let messages = LOGGER.messages.lock().unwrap();
+    assert!(messages.iter().any(|m| m.contains("key transformation rounds")));
+    assert!(messages.iter().any(|m| m.starts_with("Open succeeded")));
+    assert!(messages.iter().any(|m| m.starts_with("Save succeeded")));
+    for secret in secrets.iter().filter(|s| s.len() >= 3) {
+        assert!(!messages.iter().any(|m| m.contains(secret.as_str())), "{} logged", secret);
+    }
+}
@@ -0,0 +1,51 @@
+//! Known-answer vectors for the crypto primitives of KeePass 1.x
+//!
+//! The vectors come from test/test_password.kdb of this repository, they
+//! aren't published by KeePass. The seeds, the rounds and the content
+//! hash are read from its header, the content hash was written by the
+//! program which saved the file. The keys were computed from them with
+//! the AES and SHA256 of Python's cryptography package, following the
+//! key derivation of KeePass 1.x (CPwManager::_TransformMasterKey in
+//! PwManager.cpp), and the content decrypted with the final key matches
+//! the content hash. A different crypto backend has to reproduce every
+//! one of them. All byte values are hex strings.
+
+/// Key derivation: password key, AES-KDF transformation and final key
+pub struct KdfVector {
+    /// The database password
+    pub password: &'static str,
+    /// SHA256 of the password
+    pub password_key: &'static str,
+    /// Key for the AES-ECB rounds
+    pub transf_randomseed: &'static str,
+    pub rounds: u32,
+    /// SHA256 of the password key after all rounds
+    pub transformed_key: &'static str,
+    /// Hashed in front of the transformed key
+    pub final_randomseed: &'static str,
+    /// The AES-CBC key of the content
+    pub final_key: &'static str,
+}
+
+/// SHA256 over the decrypted content of a database file
+pub struct ContentHashVector {
+    pub path: &'static str,
+    pub password: &'static str,
+    pub content_hash: &'static str,
+}
+
+pub const KDF_VECTORS: &'static [KdfVector] = &[KdfVector {
+    password: "test",
+    password_key: "9f86d081884c7d659a2feaa0c55ad015a3bf4f1b2b0b822cd15d6c15b0f00a08",
+    transf_randomseed: "6900e587f3dd534216428f93b7cddf9f60fd6217b5506ce8cbf610383dfe2820",
+    rounds: 150000,
+    transformed_key: "8c243f5ca60eed8042545f184eff4e49d031d9241ed49869932ae9f16da51932",
+    final_randomseed: "b049ce9f87a6a96590b0489d92ef12e1",
+    final_key: "04e722f6171d5a4de9be7d3674b15f83a7d42267af382405da9aa6093e63c870",
+}];
+
+pub const CONTENT_HASH_VECTORS: &'static [ContentHashVector] = &[ContentHashVector {
+    path: "test/test_password.kdb",
+    password: "test",
+    content_hash: "cbcd022ebcda6ff5f56b5040ede6054e5091a49e45946e03d4c6790c8a0b554c",
+}];
@@ -0,0 +1,106 @@
+//! The timestamps of groups and entries
+//!
+//! Timestamps are chrono DateTime<Local>, which already orders and
+//! converts to NaiveDateTime with naive_local. This module adds the
+//! conversions to and from std::time::SystemTime and the packed 5 byte
+//! form of the KDB format: 14 bits year, 4 bits month, 5 bits day,
+//! 5 bits hour, 6 bits minute and 6 bits second, local time without a
+//! time zone.
+
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use chrono::{DateTime, Datelike, Local, TimeZone, Timelike};
+
+use kpdb::v1entry::never_expires;
+use kpdb::v1error::V1KpdbError;
+
+/// The SystemTime of time, nanoseconds included
+pub fn to_system_time(time: &DateTime<Local>) -> SystemTime {
+    let secs = time.timestamp();
+    // A leap second shows up as 1_000_000_000 and more
+    let nanos = Duration::new(0, time.nanosecond() % 1_000_000_000);
+    if secs >= 0 {
+        UNIX_EPOCH + Duration::new(secs as u64, 0) + nanos
+    } else {
+        UNIX_EPOCH - Duration::new(secs.abs() as u64, 0) + nanos
+    }
+}
+
+/// The local time of time, nanoseconds included
+pub fn from_system_time(time: SystemTime) -> DateTime<Local> {
+    match time.duration_since(UNIX_EPOCH) {
+        Ok(since) => Local.timestamp(since.as_secs() as i64, since.subsec_nanos()),
+        Err(e) => {
+            let before = e.duration();
+            let secs = -(before.as_secs() as i64);
+            match before.subsec_nanos() {
+                0 => Local.timestamp(secs, 0),
+                nanos => Local.timestamp(secs - 1, 1_000_000_000 - nanos),
+            }
+        }
+    }
+}
+
+/// Unpack a date of the KDB format. ConvertErr if there are less than 5
+/// bytes or they don't hold a valid date, e.g. month 0.
+pub fn unpack(bytes: &[u8]) -> Result<DateTime<Local>, V1KpdbError> {
+    if bytes.len() < 5 {
+        return Err(V1KpdbError::ConvertErr);
+    }
+    // Taken from the original KeePass code
+    let dw1 = bytes[0] as i32;
+    let dw2 = bytes[1] as i32;
+    let dw3 = bytes[2] as i32;
+    let dw4 = bytes[3] as i32;
+    let dw5 = bytes[4] as i32;
+
+    let year = (dw1 << 6) | (dw2 >> 2);
+    let month = (((dw2 & 0x03) << 2) | (dw3 >> 6)) as u32;
+    let day = ((dw3 >> 1) & 0x1F) as u32;
+    let hour = (((dw3 & 0x01) << 4) | (dw4 >> 4)) as u32;
+    let minute = (((dw4 & 0x0F) << 2) | (dw5 >> 6)) as u32;
+    let second = (dw5 & 0x3F) as u32;
+
+    // A corrupted file can hold e.g. month 0
+    Local.ymd_opt(year, month, day)
+         .single()
+         .and_then(|date| date.and_hms_opt(hour, minute, second))
+         .ok_or(V1KpdbError::ConvertErr)
+}
+
+/// Like unpack but for an expiry date. Some clients write zeroes for
+/// "never expires", these give never_expires instead of ConvertErr.
+pub fn unpack_expiry(bytes: &[u8]) -> Result<DateTime<Local>, V1KpdbError> {
+    if bytes.len() >= 5 && bytes[..5].iter().all(|b| *b == 0) {
+        return Ok(never_expires());
+    }
+    unpack(bytes)
+}
+
+/// Pack time into the 5 bytes of the KDB format. Fractions of a second
+/// are dropped. Times after never_expires are packed as never_expires,
+/// times before year 0 as its start, as the format can't hold them.
+pub fn pack(time: &DateTime<Local>) -> Vec<u8> {
+    let (year, month, day, hour, minute, second) = if *time > never_expires() {
+        let never = never_expires();
+        (never.year(), never.month(), never.day(), never.hour(), never.minute(), never.second())
+    } else if time.year() < 0 {
+        (0, 1, 1, 0, 0, 0)
+    } else {
+        (time.year(), time.month(), time.day(), time.hour(), time.minute(), time.second())
+    };
+    let year = year as i32;
+    let month = month as i32;
+    let day = day as i32;
+    let hour = hour as i32;
+    let minute = minute as i32;
+    let second = second as i32;
+
+    let dw1 = ((year >> 6) & 0x3F) as u8;
+    let dw2 = (((year & 0x3F) << 2) | ((month >> 2) & 0x03)) as u8;
+    let dw3 = (((month & 0x03) << 6) | ((day & 0x1F) << 1) | ((hour >> 4) & 0x01)) as u8;
+    let dw4 = (((hour & 0x0F) << 4) | ((minute >> 2) & 0x0F)) as u8;
+    let dw5 = (((minute & 0x03) << 6) | (second & 0x3F)) as u8;
+
+    vec![dw1, dw2, dw3, dw4, dw5]
+}
@@ -0,0 +1,42 @@
+use chrono::{DateTime, Local};
+use uuid::Uuid;
+
+/// What happened to an entry. Events at the same time are sorted in
+/// this order.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum TimelineKind {
+    Created,
+    Modified,
+    Accessed,
+    /// The entry expires or expired. Entries which never expire don't
+    /// have this event.
+    Expires,
+}
+
+#[doc = "
+TimelineEvent is one point of the history of an entry, see
+V1Entry::timeline and V1Kpdb::timeline.
+"]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TimelineEvent {
+    pub time: DateTime<Local>,
+    pub kind: TimelineKind,
+    /// For modifications found in the Backup group the UUID of the
+    /// revision which holds the state at that time
+    pub revision: Option<Uuid>,
+}
+
+impl TimelineEvent {
+    pub fn new(time: DateTime<Local>, kind: TimelineKind) -> TimelineEvent {
+        TimelineEvent {
+            time: time,
+            kind: kind,
+            revision: None,
+        }
+    }
+}
+
+/// Sort events by time, events of the same time by kind
+pub fn sort_events(events: &mut Vec<TimelineEvent>) {
+    events.sort_by(|a, b| (a.time, a.kind).cmp(&(b.time, b.kind)));
+}
@@ -0,0 +1,240 @@
+//! The Twofish block cipher
+//!
+//! OpenSSL doesn't implement Twofish, so this follows the specification
+//! by Schneier et al. (1998): 128-bit blocks and keys of 128, 192 or 256
+//! bits. It's used to read Password Safe files, see import::psafe3. Only
+//! single blocks are handled, modes of operation are up to the caller.
+
+use std::ptr;
+
+// The nibble tables of the permutations q0 and q1
+const Q0_T: [[u8; 16]; 4] = [[0x8, 0x1, 0x7, 0xD, 0x6, 0xF, 0x3, 0x2, 0x0, 0xB, 0x5, 0x9, 0xE,
+                              0xC, 0xA, 0x4],
+                             [0xE, 0xC, 0xB, 0x8, 0x1, 0x2, 0x3, 0x5, 0xF, 0x4, 0xA, 0x6, 0x7,
+                              0x0, 0x9, 0xD],
+                             [0xB, 0xA, 0x5, 0xE, 0x6, 0xD, 0x9, 0x0, 0xC, 0x8, 0xF, 0x3, 0x2,
+                              0x4, 0x7, 0x1],
+                             [0xD, 0x7, 0xF, 0x4, 0x1, 0x2, 0x6, 0xE, 0x9, 0xB, 0x3, 0x0, 0x8,
+                              0x5, 0xC, 0xA]];
+const Q1_T: [[u8; 16]; 4] = [[0x2, 0x8, 0xB, 0xD, 0xF, 0x7, 0x6, 0xE, 0x3, 0x1, 0x9, 0x4, 0x0,
+                              0xA, 0xC, 0x5],
+                             [0x1, 0xE, 0x2, 0xB, 0x4, 0xC, 0x3, 0x7, 0x6, 0xD, 0xA, 0x5, 0xF,
+                              0x9, 0x0, 0x8],
+                             [0x4, 0xC, 0x7, 0x5, 0x1, 0x6, 0x9, 0xA, 0x0, 0xE, 0xD, 0x8, 0x2,
+                              0xB, 0x3, 0xF],
+                             [0xB, 0x9, 0x5, 0x1, 0xC, 0x3, 0xD, 0xE, 0x6, 0x4, 0x7, 0xF, 0x2,
+                              0x0, 0x8, 0xA]];
+
+// Maximum distance separable matrix of g, over GF(2^8) modulo MDS_POLY
+const MDS: [[u8; 4]; 4] = [[0x01, 0xEF, 0x5B, 0x5B],
+                           [0x5B, 0xEF, 0xEF, 0x01],
+                           [0xEF, 0x5B, 0x01, 0xEF],
+                           [0xEF, 0x01, 0xEF, 0x5B]];
+const MDS_POLY: u16 = 0x169;
+
+// Reed-Solomon matrix deriving the S-box key, over GF(2^8) modulo RS_POLY
+const RS: [[u8; 8]; 4] = [[0x01, 0xA4, 0x55, 0x87, 0x5A, 0x58, 0xDB, 0x9E],
+                          [0xA4, 0x56, 0x82, 0xF3, 0x1E, 0xC6, 0x68, 0xE5],
+                          [0x02, 0xA1, 0xFC, 0xC1, 0x47, 0xAE, 0x3D, 0x19],
+                          [0xA4, 0x55, 0x87, 0x5A, 0x58, 0xDB, 0x9E, 0x03]];
+const RS_POLY: u16 = 0x14D;
+
+const ROUNDS: usize = 16;
+const RHO: u32 = 0x01010101;
+
+/// Size of a block in bytes
+pub const BLOCK_LEN: usize = 16;
+
+#[doc = "
+A Twofish key schedule. The round keys and the key-dependent S-boxes are
+overwritten with zeroes on drop.
+"]
+pub struct Twofish {
+    // Whitening and round keys
+    subkeys: [u32; 40],
+    // g as one lookup per input byte, the MDS multiplication included
+    sboxes: [[u32; 256]; 4],
+}
+
+impl Twofish {
+    /// Prepare key, which has to be 16, 24 or 32 bytes long. Panics
+    /// otherwise.
+    pub fn new(key: &[u8]) -> Twofish {
+        assert!(key.len() == 16 || key.len() == 24 || key.len() == 32);
+        let q0 = permutation(&Q0_T);
+        let q1 = permutation(&Q1_T);
+
+        // The words of the key with even and odd index
+        let (mut even, mut odd) = (vec![], vec![]);
+        for (i, word) in key.chunks(4).map(le_word).enumerate() {
+            if i % 2 == 0 {
+                even.push(word);
+            } else {
+                odd.push(word);
+            }
+        }
+        // The S-box key is used in reverse order
+        let mut sbox_key = key.chunks(8).map(rs_encode).collect::<Vec<_>>();
+        sbox_key.reverse();
+
+        let mut subkeys = [0u32; 40];
+        for i in 0..20 {
+            let a = h(2 * i as u32 * RHO, &even, &q0, &q1);
+            let b = h((2 * i as u32 + 1) * RHO, &odd, &q0, &q1).rotate_left(8);
+            subkeys[2 * i] = a.wrapping_add(b);
+            subkeys[2 * i + 1] = a.wrapping_add(b.wrapping_mul(2)).rotate_left(9);
+        }
+        let mut sboxes = [[0u32; 256]; 4];
+        for (j, sbox) in sboxes.iter_mut().enumerate() {
+            for x in 0..256 {
+                sbox[x] = mds_column(j, chain(j, x as u8, &sbox_key, &q0, &q1));
+            }
+        }
+
+        for word in even.iter_mut().chain(odd.iter_mut()).chain(sbox_key.iter_mut()) {
+            unsafe {
+                ptr::write_volatile(word, 0);
+            }
+        }
+        Twofish {
+            subkeys: subkeys,
+            sboxes: sboxes,
+        }
+    }
+
+    /// Encrypt a single block in place. Panics if block isn't
+    /// BLOCK_LEN bytes long.
+    pub fn encrypt_block(&self, block: &mut [u8]) {
+        assert_eq!(block.len(), BLOCK_LEN);
+        let k = &self.subkeys;
+        let mut r = [0u32; 4];
+        for i in 0..4 {
+            r[i] = le_word(&block[4 * i..4 * i + 4]) ^ k[i];
+        }
+        for round in 0..ROUNDS {
+            let (f0, f1) = self.f(r[0], r[1], round);
+            r = [(r[2] ^ f0).rotate_right(1), r[3].rotate_left(1) ^ f1, r[0], r[1]];
+        }
+        for i in 0..4 {
+            put_le_word(&mut block[4 * i..4 * i + 4], r[(i + 2) % 4] ^ k[i + 4]);
+        }
+    }
+
+    /// Decrypt a single block in place. Panics if block isn't
+    /// BLOCK_LEN bytes long.
+    pub fn decrypt_block(&self, block: &mut [u8]) {
+        assert_eq!(block.len(), BLOCK_LEN);
+        let k = &self.subkeys;
+        let mut r = [0u32; 4];
+        for i in 0..4 {
+            r[(i + 2) % 4] = le_word(&block[4 * i..4 * i + 4]) ^ k[i + 4];
+        }
+        for round in (0..ROUNDS).rev() {
+            let (f0, f1) = self.f(r[2], r[3], round);
+            r = [r[2], r[3], r[0].rotate_left(1) ^ f0, (r[1] ^ f1).rotate_right(1)];
+        }
+        for i in 0..4 {
+            put_le_word(&mut block[4 * i..4 * i + 4], r[i] ^ k[i]);
+        }
+    }
+
+    // The round function F
+    fn f(&self, r0: u32, r1: u32, round: usize) -> (u32, u32) {
+        let t0 = self.g(r0);
+        let t1 = self.g(r1.rotate_left(8));
+        (t0.wrapping_add(t1).wrapping_add(self.subkeys[2 * round + 8]),
+         t0.wrapping_add(t1.wrapping_mul(2)).wrapping_add(self.subkeys[2 * round + 9]))
+    }
+
+    fn g(&self, x: u32) -> u32 {
+        self.sboxes[0][x as u8 as usize] ^ self.sboxes[1][(x >> 8) as u8 as usize] ^
+        self.sboxes[2][(x >> 16) as u8 as usize] ^ self.sboxes[3][(x >> 24) as usize]
+    }
+}
+
+impl Drop for Twofish {
+    fn drop(&mut self) {
+        let sboxes = self.sboxes.iter_mut().flat_map(|s| s.iter_mut());
+        for word in self.subkeys.iter_mut().chain(sboxes) {
+            unsafe {
+                ptr::write_volatile(word, 0);
+            }
+        }
+    }
+}
+
+// The permutation given by the nibble tables t
+fn permutation(t: &[[u8; 16]; 4]) -> [u8; 256] {
+    let ror4 = |x: u8| ((x >> 1) | (x << 3)) & 0xF;
+    let mut q = [0u8; 256];
+    for x in 0..256 {
+        let (a0, b0) = ((x >> 4) as u8, (x & 0xF) as u8);
+        let (a1, b1) = (a0 ^ b0, a0 ^ ror4(b0) ^ ((a0 << 3) & 0xF));
+        let (a2, b2) = (t[0][a1 as usize], t[1][b1 as usize]);
+        let (a3, b3) = (a2 ^ b2, a2 ^ ror4(b2) ^ ((a2 << 3) & 0xF));
+        let (a4, b4) = (t[2][a3 as usize], t[3][b3 as usize]);
+        q[x] = (b4 << 4) | a4;
+    }
+    q
+}
+
+// The function h without the MDS matrix for the byte at position j of
+// the input x, keyed with the words l
+fn chain(j: usize, x: u8, l: &[u32], q0: &[u8; 256], q1: &[u8; 256]) -> u8 {
+    let byte = |word: u32| (word >> (8 * j)) as u8;
+    let mut y = x;
+    if l.len() == 4 {
+        y = [q1, q0, q0, q1][j][y as usize] ^ byte(l[3]);
+    }
+    if l.len() >= 3 {
+        y = [q1, q1, q0, q0][j][y as usize] ^ byte(l[2]);
+    }
+    y = [q0, q1, q0, q1][j][y as usize] ^ byte(l[1]);
+    y = [q0, q0, q1, q1][j][y as usize] ^ byte(l[0]);
+    [q1, q0, q1, q0][j][y as usize]
+}
+
+fn h(x: u32, l: &[u32], q0: &[u8; 256], q1: &[u8; 256]) -> u32 {
+    (0..4).fold(0, |z, j| z ^ mds_column(j, chain(j, (x >> (8 * j)) as u8, l, q0, q1)))
+}
+
+// Column j of the MDS matrix multiplied by y
+fn mds_column(j: usize, y: u8) -> u32 {
+    (0..4).fold(0, |z, i| z | (gf_mul(MDS[i][j], y, MDS_POLY) as u32) << (8 * i))
+}
+
+// A word of the S-box key from 8 bytes of the key
+fn rs_encode(key: &[u8]) -> u32 {
+    let mut word = 0;
+    for (i, row) in RS.iter().enumerate() {
+        let byte = row.iter().zip(key.iter()).fold(0, |s, (r, m)| s ^ gf_mul(*r, *m, RS_POLY));
+        word |= (byte as u32) << (8 * i);
+    }
+    word
+}
+
+// Multiplication in GF(2^8) modulo poly
+fn gf_mul(a: u8, b: u8, poly: u16) -> u8 {
+    let (mut a, mut b, mut product) = (a as u16, b, 0u16);
+    while b != 0 {
+        if b & 1 != 0 {
+            product ^= a;
+        }
+        a <<= 1;
+        if a & 0x100 != 0 {
+            a ^= poly;
+        }
+        b >>= 1;
+    }
+    product as u8
+}
+
+fn le_word(bytes: &[u8]) -> u32 {
+    (bytes[0] as u32) | (bytes[1] as u32) << 8 | (bytes[2] as u32) << 16 | (bytes[3] as u32) << 24
+}
+
+fn put_le_word(bytes: &mut [u8], word: u32) {
+    for i in 0..4 {
+        bytes[i] = (word >> (8 * i)) as u8;
+    }
+}
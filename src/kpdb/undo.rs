@@ -0,0 +1,141 @@
+use std::collections::VecDeque;
+
+use openssl::crypto::symm;
+use rand;
+
+use kpdb::crypter::Crypter;
+use kpdb::v1error::V1KpdbError;
+use super::super::mem_protect;
+use super::super::sec_str::SecureBytes;
+
+#[doc = "
+Snapshot is the state of a database in an UndoLog: its groups and
+entries, meta entries included, in the plaintext format of the file.
+Like a SecureString the content is kept encrypted with a random key,
+it's only decrypted to restore it.
+"]
+pub struct Snapshot {
+    encrypted: Vec<u8>,
+    key: Vec<u8>,
+    iv: Vec<u8>,
+    num_groups: u32,
+    num_entries: u32,
+}
+
+impl Snapshot {
+    /// Encrypt content, which is zeroed out on drop as every SecureBytes
+    pub fn new(content: SecureBytes, num_groups: u32, num_entries: u32) -> Snapshot {
+        let key: Vec<u8> = (0..32).map(|_| rand::random::<u8>()).collect();
+        mem_protect::lock(&key, "snapshot key");
+        let iv: Vec<u8> = (0..16).map(|_| rand::random::<u8>()).collect();
+        Snapshot {
+            encrypted: symm::encrypt(symm::Type::AES_256_CBC, &key, iv.clone(), content.bytes()),
+            key: key,
+            iv: iv,
+            num_groups: num_groups,
+            num_entries: num_entries,
+        }
+    }
+
+    /// The serialized groups and entries, decrypted into a locked buffer.
+    /// The caller has to zero it out. DecryptErr only if the memory of
+    /// the snapshot is corrupted.
+    pub fn decrypt(&self) -> Result<Vec<u8>, V1KpdbError> {
+        Crypter::decrypt_locked(&self.key, self.iv.clone(), &self.encrypted)
+    }
+
+    /// Number of groups and entries in content, as the header holds them
+    pub fn num_groups(&self) -> u32 {
+        self.num_groups
+    }
+
+    pub fn num_entries(&self) -> u32 {
+        self.num_entries
+    }
+}
+
+impl Drop for Snapshot {
+    fn drop(&mut self) {
+        unsafe {
+            mem_protect::zero(&self.key);
+        }
+        mem_protect::unlock(&self.key);
+    }
+}
+
+#[doc = "
+UndoLog holds the states of a database before its last changes and the
+states undone since, see V1Kpdb::undo. The database updates it,
+applications don't need it.
+"]
+pub struct UndoLog {
+    undo: VecDeque<Snapshot>,
+    redo: Vec<Snapshot>,
+    // Number of nested V1Kpdb::batch calls running
+    depth: usize,
+}
+
+impl UndoLog {
+    pub fn new() -> UndoLog {
+        UndoLog {
+            undo: VecDeque::new(),
+            redo: vec![],
+            depth: 0,
+        }
+    }
+
+    /// Enter a batch of changes, true if it's the outermost one, whose
+    /// state is recorded
+    pub fn begin(&mut self) -> bool {
+        self.depth += 1;
+        self.depth == 1
+    }
+
+    /// Leave the batch entered last
+    pub fn end(&mut self) {
+        self.depth -= 1;
+    }
+
+    /// Record the state before a change. The states undone so far can't
+    /// be redone any more. Only the last limit states are kept.
+    pub fn record(&mut self, before: Snapshot, limit: usize) {
+        self.redo.clear();
+        self.undo.push_back(before);
+        while self.undo.len() > limit {
+            self.undo.pop_front();
+        }
+    }
+
+    /// Take the state to go back to and keep current for redo
+    pub fn undo(&mut self, current: Snapshot) -> Option<Snapshot> {
+        let before = self.undo.pop_back();
+        if before.is_some() {
+            self.redo.push(current);
+        }
+        before
+    }
+
+    /// Take the state to go forward to and keep current for undo
+    pub fn redo(&mut self, current: Snapshot) -> Option<Snapshot> {
+        let after = self.redo.pop();
+        if after.is_some() {
+            self.undo.push_back(current);
+        }
+        after
+    }
+
+    /// Number of changes which can be undone and redone respectively
+    pub fn undo_steps(&self) -> usize {
+        self.undo.len()
+    }
+
+    pub fn redo_steps(&self) -> usize {
+        self.redo.len()
+    }
+
+    /// Drop all states, e.g. because another database was loaded
+    pub fn clear(&mut self) {
+        self.undo.clear();
+        self.redo.clear();
+    }
+}
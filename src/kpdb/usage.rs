@@ -0,0 +1,50 @@
+use std::time::Duration;
+
+/// The operation a UsageEvent reports
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum UsageKind {
+    /// V1Kpdb::load
+    Open,
+    /// V1Kpdb::save
+    Save,
+    /// V1Kpdb::merge
+    Merge,
+}
+
+#[doc = "
+A coarse usage event. It never carries any content of the database,
+not even titles or the path.
+"]
+#[derive(Clone, Debug)]
+pub struct UsageEvent {
+    /// What was done
+    pub kind: UsageKind,
+    /// How long it took, including the key transformation
+    pub duration: Duration,
+    /// False if the operation returned an error
+    pub success: bool,
+    /// Number of groups and entries afterwards
+    pub num_groups: usize,
+    pub num_entries: usize,
+    /// Number of buffers which couldn't be locked against swapping, see
+    /// MemLockPolicy. Under BestEffort this is the only warning
+    pub lock_failures: usize,
+}
+
+#[doc = "
+Implement this to receive usage events from a database, e.g. to feed
+local metrics. Nothing is reported unless a sink is set in
+V1Kpdb::usage_sink and the crate itself never sends anything anywhere.
+Closures taking a &UsageEvent can be used as a sink directly.
+"]
+pub trait UsageSink {
+    fn record(&mut self, event: &UsageEvent);
+}
+
+impl<F> UsageSink for F
+    where F: FnMut(&UsageEvent)
+{
+    fn record(&mut self, event: &UsageEvent) {
+        self(event)
+    }
+}
@@ -1,11 +1,43 @@
 use std::cell::RefCell;
+use std::mem;
 use std::rc::Rc;
+use std::time::Duration;
 
-use chrono::{DateTime, Local, TimeZone};
+use chrono::{DateTime, Local, TimeZone, Timelike};
 use uuid::Uuid;
 
+use super::autotype::{expand, AutoTypeAction, AutoTypeConfig};
+use super::common::url_host;
+use super::custom_fields::{is_valid_name, join_fields, split_fields};
+use super::diff::EntryField;
+use super::limits::{check, FieldLimits};
+use super::notes::{join_secrets, remove_placeholder, split_secrets, SECRET_FENCE};
+use super::otp::{Otp, OtpKind};
+use super::passkey::Passkey;
+use super::password_history::{decode_history, encode_history, OldPassword, PasswordHistory};
+use super::reveal::{ProtectedField, Revealed};
+use super::secret_access::SecretAccess;
+use super::recovery_codes::{decode_codes, encode_codes, RecoveryCode};
+use super::timeline::{sort_events, TimelineEvent, TimelineKind};
+use super::patch::{apply_ops, parse_patch};
+use super::v1error::V1KpdbError;
 use super::v1group::V1Group;
-use super::super::sec_str::SecureString;
+use super::super::mem_protect;
+use super::super::sec_str::{SecureBytes, SecureString, Unlocked};
+
+// KeePassXC stores additional URLs as custom fields named KP2A_URL,
+// KP2A_URL_1, ... As KeePass 1.x has no custom fields they are kept as
+// "KP2A_URL_1: <url>" lines in the comment of the entry.
+pub const ADDITIONAL_URL_PREFIX: &'static str = "KP2A_URL";
+
+// Tags are kept the same way as a "Tags: a, b" line in the comment
+const TAGS_PREFIX: &'static str = "Tags:";
+
+/// The expiry date KeePass 1.x stores for entries and groups which never
+/// expire, 2999-12-28 23:59:59
+pub fn never_expires() -> DateTime<Local> {
+    Local.ymd(2999, 12, 28).and_hms(23, 59, 59)
+}
 
 #[doc = "
 Implements an entry in a KeePass v1.x database.
@@ -25,14 +57,19 @@ pub struct V1Entry {
     pub url: Option<String>,
     /// Username for the login
     pub username: Option<SecureString>,
-    /// Password for the login
-    pub password: Option<SecureString>,
+    // Password for the login, read through password so that reads can
+    // be reported, see V1Kpdb::on_secret_access
+    password: Option<SecureString>,
     /// Some comment about the entry
     pub comment: Option<String>,
-    /// Descripton of the binary content
+    /// The ::secret:: sections of the comment, see set_markdown_notes.
+    /// The comment holds an empty section in place of each.
+    pub protected_notes: Vec<SecureString>,
+    /// Descripton of the binary content, i.e. the file name of the
+    /// attachment
     pub binary_desc: Option<String>,
-    /// ???
-    pub binary: Option<Vec<u8>>,
+    /// Content of the attachment
+    pub binary: Option<SecureBytes>,
     /// Date of creation
     pub creation: DateTime<Local>,
     /// Date of last modification
@@ -41,6 +78,12 @@ pub struct V1Entry {
     pub last_access: DateTime<Local>,
     /// Expiration date
     pub expire: DateTime<Local>,
+    // Changed since the last load or save, see dirty
+    dirty: bool,
+    // Counts the changes, see V1Kpdb::batch
+    revision: u64,
+    // Shared with the database holding the entry
+    secret_access: Option<Rc<SecretAccess>>,
 }
 
 impl V1Entry {
@@ -58,12 +101,826 @@ impl V1Entry {
             username: None,
             password: None,
             comment: None,
+            protected_notes: vec![],
             binary_desc: None,
             binary: None,
             creation: Local::now(),
             last_mod: Local::now(),
             last_access: Local::now(),
-            expire: Local.ymd(2999, 12, 28).and_hms(23, 59, 59),
+            expire: never_expires(),
+            dirty: false,
+            revision: 0,
+            secret_access: None,
+        }
+    }
+
+    /// True if the entry was changed since the database was loaded or
+    /// saved. The setters and the methods of V1Kpdb set it, writing to a
+    /// field directly doesn't, use set_dirty then.
+    pub fn dirty(&self) -> bool {
+        self.dirty
+    }
+
+    /// Mark the entry as changed or unchanged
+    pub fn set_dirty(&mut self, dirty: bool) {
+        self.dirty = dirty;
+        if dirty {
+            self.revision = self.revision.wrapping_add(1);
+        }
+    }
+
+    // Number of changes so far, every setter and set_dirty(true) counts
+    pub(crate) fn revision(&self) -> u64 {
+        self.revision
+    }
+
+    /// Set the title. Returns FieldLengthErr if it's longer than
+    /// FieldLimits::v1 allows, the entry stays unchanged then. The
+    /// other setters work the same.
+    pub fn set_title(&mut self, title: String) -> Result<(), V1KpdbError> {
+        try!(check(title.len(), FieldLimits::v1().title));
+        self.title = title;
+        self.set_dirty(true);
+        Ok(())
+    }
+
+    pub fn set_url(&mut self, url: Option<String>) -> Result<(), V1KpdbError> {
+        try!(check(url.as_ref().map_or(0, |u| u.len()), FieldLimits::v1().url));
+        self.url = url;
+        self.set_dirty(true);
+        Ok(())
+    }
+
+    pub fn set_username(&mut self, username: Option<SecureString>) -> Result<(), V1KpdbError> {
+        try!(check(username.as_ref().map_or(0, |u| u.string.len()),
+                   FieldLimits::v1().username));
+        self.username = username;
+        self.set_dirty(true);
+        Ok(())
+    }
+
+    /// Set the password. If a password history is kept, the previous
+    /// password goes into it, see enable_password_history.
+    pub fn set_password(&mut self, password: Option<SecureString>) -> Result<(), V1KpdbError> {
+        try!(check(password.as_ref().map_or(0, |p| p.string.len()),
+                   FieldLimits::v1().password));
+        let previous = mem::replace(&mut self.password, password);
+        if let (Some(mut previous), Some(mut history)) = (previous, self.stored_history()) {
+            let changed = match self.password {
+                Some(ref mut current) => *current.unlocked() != *previous.unlocked(),
+                None => true,
+            };
+            if changed {
+                history.push(previous, Local::now());
+                self.store_section(encode_history(&mut history), |s| decode_history(s).is_some());
+            }
+        }
+        self.set_dirty(true);
+        Ok(())
+    }
+
+    pub fn set_comment(&mut self, comment: Option<String>) -> Result<(), V1KpdbError> {
+        try!(check(comment.as_ref().map_or(0, |c| c.len()), FieldLimits::v1().comment));
+        self.comment = comment;
+        self.set_dirty(true);
+        Ok(())
+    }
+
+    /// Set when the entry expires, None if it never does. The time is
+    /// stored with second precision, so it's truncated to seconds.
+    pub fn set_expiry(&mut self, expiry: Option<DateTime<Local>>) {
+        self.expire = match expiry {
+            Some(expiry) => expiry.with_nanosecond(0).unwrap_or(expiry),
+            None => never_expires(),
+        };
+        self.set_dirty(true);
+    }
+
+    /// When the entry expires, None if it never does
+    pub fn expiry(&self) -> Option<DateTime<Local>> {
+        if self.expire == never_expires() {
+            None
+        } else {
+            Some(self.expire)
+        }
+    }
+
+    /// True if the entry expires at or before now
+    pub fn is_expired(&self, now: DateTime<Local>) -> bool {
+        self.expiry().map_or(false, |expiry| expiry <= now)
+    }
+
+    /// Set the comment from Markdown notes. Sections between two
+    /// "::secret::" lines are moved to protected_notes, so only they are
+    /// encrypted in memory while the rest stays searchable plain text.
+    /// notes is zeroed out.
+    pub fn set_markdown_notes(&mut self, notes: String) -> Result<(), V1KpdbError> {
+        let len = notes.len();
+        let (comment, secrets) = split_secrets(notes);
+        try!(check(len, FieldLimits::v1().comment));
+        self.comment = Some(comment);
+        self.protected_notes = secrets;
+        self.set_dirty(true);
+        Ok(())
+    }
+
+    /// The comment with the protected sections filled in, as it's saved.
+    /// The result holds the secrets in plain text, so drop it soon.
+    pub fn markdown_notes(&mut self) -> Option<String> {
+        match self.comment {
+            Some(ref comment) => Some(join_secrets(comment, &mut self.protected_notes)),
+            None if !self.protected_notes.is_empty() => {
+                Some(join_secrets("", &mut self.protected_notes))
+            }
+            None => None,
+        }
+    }
+
+    /// The recovery codes of the entry in the order they were set. They
+    /// are kept in a protected section of the notes.
+    pub fn recovery_codes(&mut self) -> Vec<RecoveryCode> {
+        self.protected_notes
+            .iter_mut()
+            .filter_map(decode_codes)
+            .next()
+            .unwrap_or(vec![])
+    }
+
+    /// Replace the recovery codes by new unused ones. Sets last_mod.
+    pub fn set_recovery_codes(&mut self, codes: Vec<SecureString>) {
+        let mut codes: Vec<RecoveryCode> = codes.into_iter().map(RecoveryCode::new).collect();
+        self.store_recovery_codes(&mut codes);
+    }
+
+    /// Take the next unused recovery code and mark it as used now. Sets
+    /// last_mod. None if all codes are used.
+    pub fn consume_recovery_code(&mut self) -> Option<SecureString> {
+        let mut codes = self.recovery_codes();
+        let code = match codes.iter_mut().find(|c| !c.is_used()) {
+            Some(code) => {
+                code.used = Some(Local::now());
+                SecureString::new(code.code.unlocked().to_string())
+            }
+            None => return None,
+        };
+        self.store_recovery_codes(&mut codes);
+        Some(code)
+    }
+
+    /// Number of unused recovery codes
+    pub fn remaining_recovery_codes(&mut self) -> usize {
+        self.recovery_codes().iter().filter(|c| !c.is_used()).count()
+    }
+
+    fn store_recovery_codes(&mut self, codes: &mut [RecoveryCode]) {
+        let section = encode_codes(codes);
+        self.store_section(section, |s| decode_codes(s).is_some());
+        self.last_mod = Local::now();
+        self.set_dirty(true);
+    }
+
+    // Replace the protected section is_section finds by section or add
+    // it to the end of the notes
+    fn store_section<F>(&mut self, section: SecureString, is_section: F)
+        where F: FnMut(&mut SecureString) -> bool
+    {
+        match self.protected_notes.iter_mut().position(is_section) {
+            Some(index) => self.protected_notes[index] = section,
+            None => {
+                // Placeholder for the new section, see kpdb::notes
+                let placeholder = format!("{}\n{}", SECRET_FENCE, SECRET_FENCE);
+                self.comment = Some(match self.comment.take() {
+                    Some(ref comment) if !comment.is_empty() => {
+                        format!("{}\n{}", comment, placeholder)
+                    }
+                    _ => placeholder,
+                });
+                self.protected_notes.push(section);
+            }
+        }
+    }
+
+    /// Keep the previous password in the notes whenever set_password
+    /// changes it, up to limit passwords, 0 for all of them. The history
+    /// is a protected section of the notes, see PasswordHistory, so
+    /// other clients show and keep it like any other text. Changes the
+    /// limit if the history is kept already.
+    pub fn enable_password_history(&mut self, limit: usize) {
+        let mut history = self.stored_history().unwrap_or(PasswordHistory::new(limit));
+        history.set_limit(limit);
+        self.store_section(encode_history(&mut history), |s| decode_history(s).is_some());
+        self.set_dirty(true);
+    }
+
+    /// Drop the password history, set_password doesn't keep previous
+    /// passwords any more
+    pub fn disable_password_history(&mut self) {
+        let index = match self.protected_notes
+                              .iter_mut()
+                              .position(|s| decode_history(s).is_some()) {
+            Some(index) => index,
+            None => return,
+        };
+        self.protected_notes.remove(index);
+        self.comment = match self.comment.take().map(|c| remove_placeholder(&c, index)) {
+            Some(ref comment) if comment.is_empty() => None,
+            comment => comment,
+        };
+        self.set_dirty(true);
+    }
+
+    /// True if set_password keeps the previous passwords
+    pub fn has_password_history(&mut self) -> bool {
+        self.stored_history().is_some()
+    }
+
+    /// The previous passwords, the oldest first. Empty if no history is
+    /// kept. Like password it reports the read if there are any.
+    pub fn password_history(&mut self) -> Vec<OldPassword> {
+        let passwords = self.stored_history().map_or(vec![], |h| h.passwords);
+        if !passwords.is_empty() {
+            self.report_secret_access();
+        }
+        passwords
+    }
+
+    /// Set the password back to the previous one at index of
+    /// password_history. It leaves the history and the current password
+    /// goes into it like with set_password. IndexErr if there's no such
+    /// password.
+    pub fn restore_password(&mut self, index: usize) -> Result<(), V1KpdbError> {
+        let mut history = match self.stored_history() {
+            Some(ref history) if index >= history.passwords.len() => {
+                return Err(V1KpdbError::IndexErr)
+            }
+            Some(history) => history,
+            None => return Err(V1KpdbError::IndexErr),
+        };
+        let old = history.passwords.remove(index);
+        self.store_section(encode_history(&mut history), |s| decode_history(s).is_some());
+        self.set_password(Some(old.password))
+    }
+
+    fn stored_history(&mut self) -> Option<PasswordHistory> {
+        self.protected_notes.iter_mut().filter_map(decode_history).next()
+    }
+
+    /// Attach data as a file called name, replacing the previous
+    /// attachment. KeePass 1.x allows one attachment per entry. The data
+    /// is locked in memory and zeroed out when it's replaced or the
+    /// entry is dropped, even if FieldLengthErr is returned.
+    pub fn set_attachment(&mut self, name: String, data: Vec<u8>) -> Result<(), V1KpdbError> {
+        let data = SecureBytes::new(data);
+        try!(check(name.len(), FieldLimits::v1().binary_desc));
+        try!(check(data.len(), FieldLimits::v1().binary));
+        self.binary_desc = Some(name);
+        self.binary = Some(data);
+        self.set_dirty(true);
+        Ok(())
+    }
+
+    /// Name and content of the attachment. KeePass saves empty fields
+    /// for entries without one, these give None as well.
+    pub fn attachment(&self) -> Option<(&str, &[u8])> {
+        match (&self.binary_desc, &self.binary) {
+            (&Some(ref name), &Some(ref data)) if !name.is_empty() || data.len() > 0 => {
+                Some((name, data.bytes()))
+            }
+            _ => None,
+        }
+    }
+
+    /// Remove the attachment and return its name and content
+    pub fn remove_attachment(&mut self) -> Option<(String, SecureBytes)> {
+        if self.attachment().is_none() {
+            return None;
+        }
+        self.set_dirty(true);
+        match (self.binary_desc.take(), self.binary.take()) {
+            (Some(name), Some(data)) => Some((name, data)),
+            _ => None,
+        }
+    }
+
+    /// Decrypted password of the entry. The password stays encrypted in
+    /// memory, the plain text only exists until the returned guard is
+    /// dropped. The read is reported to the callback of
+    /// V1Kpdb::on_secret_access if the entry has a password.
+    pub fn password<'a>(&'a mut self) -> Option<Unlocked<'a>> {
+        if self.password.is_some() {
+            self.report_secret_access();
+        }
+        self.password.as_mut().map(|p| p.unlocked())
+    }
+
+    /// True if the entry has a password, which may be empty. Doesn't
+    /// count as a read.
+    pub fn has_password(&self) -> bool {
+        self.password.is_some()
+    }
+
+    /// The password without reporting the read, for saving, copying and
+    /// checking entries within the crate
+    pub(crate) fn raw_password(&self) -> Option<&SecureString> {
+        self.password.as_ref()
+    }
+
+    /// Like raw_password but mutable, e.g. to unlock it
+    pub(crate) fn raw_password_mut(&mut self) -> Option<&mut SecureString> {
+        self.password.as_mut()
+    }
+
+    /// Replace the password without checks, history or dirty flag, e.g.
+    /// while parsing. Returns the previous one.
+    pub(crate) fn replace_raw_password(&mut self,
+                                       password: Option<SecureString>)
+                                       -> Option<SecureString> {
+        mem::replace(&mut self.password, password)
+    }
+
+    /// Report password reads to secret_access, see
+    /// V1Kpdb::on_secret_access. The database sets it for the entries it
+    /// holds.
+    pub(crate) fn set_secret_access(&mut self, secret_access: Option<Rc<SecretAccess>>) {
+        self.secret_access = secret_access;
+    }
+
+    fn report_secret_access(&self) {
+        if let Some(ref secret_access) = self.secret_access {
+            secret_access.notify(&self.uuid);
+        }
+    }
+
+    /// Decrypted username of the entry, see password
+    pub fn username<'a>(&'a mut self) -> Option<Unlocked<'a>> {
+        self.username.as_mut().map(|u| u.unlocked())
+    }
+
+    /// A copy of the plain text of field which is wiped after ttl or when
+    /// the guard is dropped, see Revealed. None if the field isn't set.
+    /// Use V1Kpdb::reveal to have the guard tracked by the database.
+    pub fn reveal(&mut self, field: ProtectedField, ttl: Duration) -> Option<Revealed> {
+        if field == ProtectedField::Password && self.password.is_some() {
+            self.report_secret_access();
+        }
+        let secret = match field {
+            ProtectedField::Username => self.username.as_mut(),
+            ProtectedField::Password => self.password.as_mut(),
+            ProtectedField::Notes(index) => self.protected_notes.get_mut(index),
+        };
+        secret.map(|secret| Revealed::new(secret.unlocked().to_string(), ttl))
+    }
+
+    /// All URLs of the entry: the URL field first, followed by the
+    /// additional URLs
+    pub fn urls(&self) -> Vec<String> {
+        let mut urls = vec![];
+        if let Some(ref url) = self.url {
+            if !url.is_empty() {
+                urls.push(url.clone());
+            }
+        }
+        urls.extend(self.additional_urls());
+        urls
+    }
+
+    /// The additional URLs of the entry (KP2A_URL lines in the comment)
+    pub fn additional_urls(&self) -> Vec<String> {
+        match self.comment {
+            Some(ref comment) => {
+                comment.lines()
+                       .filter_map(|line| V1Entry::parse_additional_url(line))
+                       .collect()
+            }
+            None => vec![],
+        }
+    }
+
+    /// Add an URL to the entry. If the URL field is empty the URL is
+    /// stored there, otherwise it becomes an additional URL.
+    pub fn add_url(&mut self, url: String) {
+        self.set_dirty(true);
+        let url_is_empty = match self.url {
+            Some(ref u) => u.is_empty(),
+            None => true,
+        };
+        if url_is_empty {
+            self.url = Some(url);
+            return;
+        }
+
+        let line = format!("{}_{}: {}",
+                           ADDITIONAL_URL_PREFIX,
+                           self.additional_urls().len() + 1,
+                           url);
+        self.comment = Some(match self.comment.take() {
+            Some(ref comment) if !comment.is_empty() => format!("{}\n{}", comment, line),
+            _ => line,
+        });
+    }
+
+    /// Remove an URL from the entry. If the URL field is removed the
+    /// first additional URL takes its place. Returns false if the
+    /// entry doesn't have the URL.
+    pub fn remove_url(&mut self, url: &str) -> bool {
+        let mut urls = self.urls();
+        let index = match urls.iter().position(|u| u == url) {
+            Some(i) => i,
+            None => return false,
+        };
+        urls.remove(index);
+        self.set_dirty(true);
+
+        // Rewrite the comment without the KP2A_URL lines...
+        let comment = match self.comment.take() {
+            Some(comment) => {
+                comment.lines()
+                       .filter(|line| V1Entry::parse_additional_url(line).is_none())
+                       .collect::<Vec<&str>>()
+                       .join("\n")
+            }
+            None => "".to_string(),
+        };
+        self.comment = if comment.is_empty() {
+            None
+        } else {
+            Some(comment)
+        };
+        self.url = None;
+
+        // ...and add the remaining URLs again to renumber them
+        for u in urls {
+            self.add_url(u);
+        }
+        true
+    }
+
+    /// The tags of the entry (Tags line in the comment)
+    pub fn tags(&self) -> Vec<String> {
+        let comment = match self.comment {
+            Some(ref comment) => comment,
+            None => return vec![],
+        };
+        match comment.lines().find(|line| line.starts_with(TAGS_PREFIX)) {
+            Some(line) => {
+                line[TAGS_PREFIX.len()..]
+                    .split(',')
+                    .map(|tag| tag.trim())
+                    .filter(|tag| !tag.is_empty())
+                    .map(|tag| tag.to_string())
+                    .collect()
+            }
+            None => vec![],
+        }
+    }
+
+    /// Add a tag to the entry. Returns false if the entry already has
+    /// the tag. Tags can't contain commas or line breaks.
+    pub fn add_tag(&mut self, tag: &str) -> bool {
+        let mut tags = self.tags();
+        if tags.iter().any(|t| t == tag) {
+            return false;
+        }
+        tags.push(tag.to_string());
+        let line = format!("{} {}", TAGS_PREFIX, tags.join(", "));
+
+        // Replace the existing Tags line or append a new one
+        let mut replaced = false;
+        let mut lines = vec![];
+        if let Some(ref comment) = self.comment {
+            for l in comment.lines() {
+                if !replaced && l.starts_with(TAGS_PREFIX) {
+                    lines.push(line.clone());
+                    replaced = true;
+                } else {
+                    lines.push(l.to_string());
+                }
+            }
+        }
+        if !replaced {
+            lines.push(line);
+        }
+        self.comment = Some(lines.join("\n"));
+        self.set_dirty(true);
+        true
+    }
+
+    /// The custom fields of the entry as pairs of name and value, in
+    /// the order they were added. See custom_fields for how they're
+    /// stored.
+    pub fn custom_fields(&self) -> Vec<(String, String)> {
+        match self.comment {
+            Some(ref comment) => split_fields(comment).1,
+            None => vec![],
+        }
+    }
+
+    /// The value of the custom field name
+    pub fn custom_field(&self, name: &str) -> Option<String> {
+        self.custom_fields().into_iter().find(|&(ref n, _)| n == name).map(|(_, v)| v)
+    }
+
+    /// Set the custom field name to value, adding it if the entry
+    /// doesn't have it yet. CustomFieldErr if name is invalid (see
+    /// custom_fields::is_valid_name), FieldLengthErr if the comment
+    /// would get too long.
+    pub fn set_custom_field(&mut self, name: &str, value: &str) -> Result<(), V1KpdbError> {
+        if !is_valid_name(name) {
+            return Err(V1KpdbError::CustomFieldErr);
+        }
+        let (text, mut fields) = split_fields(self.comment.as_ref().map_or("", |c| c));
+        match fields.iter().position(|&(ref n, _)| n == name) {
+            Some(index) => fields[index].1 = value.to_string(),
+            None => fields.push((name.to_string(), value.to_string())),
+        }
+        self.set_comment(Some(join_fields(&text, &fields)))
+    }
+
+    /// Remove the custom field name. Returns false if the entry doesn't
+    /// have it.
+    pub fn remove_custom_field(&mut self, name: &str) -> bool {
+        let (text, mut fields) = split_fields(self.comment.as_ref().map_or("", |c| c));
+        let index = match fields.iter().position(|&(ref n, _)| n == name) {
+            Some(index) => index,
+            None => return false,
+        };
+        fields.remove(index);
+        let comment = join_fields(&text, &fields);
+        // It only gets shorter, so the length limit still holds
+        self.comment = if comment.is_empty() {
+            None
+        } else {
+            Some(comment)
+        };
+        self.set_dirty(true);
+        true
+    }
+
+    /// The auto-type settings of the entry (Auto-Type lines in the
+    /// comment)
+    pub fn auto_type(&self) -> AutoTypeConfig {
+        match self.comment {
+            Some(ref comment) => AutoTypeConfig::parse(comment),
+            None => AutoTypeConfig::new(),
+        }
+    }
+
+    /// Replace the Auto-Type lines of the comment by the lines of
+    /// config. They are appended after the other lines.
+    pub fn set_auto_type(&mut self, config: &AutoTypeConfig) {
+        let mut lines: Vec<String> = match self.comment {
+            Some(ref comment) => {
+                comment.lines()
+                       .filter(|line| !AutoTypeConfig::is_auto_type_line(line))
+                       .map(|line| line.to_string())
+                       .collect()
+            }
+            None => vec![],
+        };
+        lines.extend(config.to_lines());
+        self.comment = if lines.is_empty() {
+            None
+        } else {
+            Some(lines.join("\n"))
+        };
+        self.set_dirty(true);
+    }
+
+    /// The expanded auto-type sequence of the entry for the window with
+    /// title window, see AutoTypeConfig::sequence_for and
+    /// autotype::expand
+    pub fn auto_type_for(&mut self, window: &str) -> Result<Vec<AutoTypeAction>, V1KpdbError> {
+        let config = self.auto_type();
+        expand(config.sequence_for(window), self)
+    }
+
+    /// Apply a JSON patch to the entry, see PatchOp for the format. The
+    /// patch is validated completely before anything is changed, so on
+    /// PatchErr or FieldLengthErr the entry stays untouched. Sets
+    /// last_mod.
+    pub fn apply_patch(&mut self, patch: &str) -> Result<(), V1KpdbError> {
+        let ops = try!(parse_patch(patch));
+        apply_ops(self, ops);
+        self.set_dirty(true);
+        Ok(())
+    }
+
+    /// True if both entries hold the same data. UUID, group and the
+    /// creation, modification and access times aren't compared.
+    pub fn same_content(&mut self, other: &mut V1Entry) -> bool {
+        self.changed_fields(other).is_empty()
+    }
+
+    /// The fields which differ between the entries, compared like in
+    /// same_content
+    pub fn changed_fields(&mut self, other: &mut V1Entry) -> Vec<EntryField> {
+        let mut fields = vec![];
+        if self.title != other.title {
+            fields.push(EntryField::Title);
+        }
+        if self.url != other.url {
+            fields.push(EntryField::Url);
+        }
+        if !same_secret(self.username.as_mut(), other.username.as_mut()) {
+            fields.push(EntryField::Username);
+        }
+        if !same_secret(self.password.as_mut(), other.password.as_mut()) {
+            fields.push(EntryField::Password);
+        }
+        if self.comment != other.comment {
+            fields.push(EntryField::Comment);
+        }
+        if self.protected_notes.len() != other.protected_notes.len() ||
+           !self.protected_notes
+                .iter_mut()
+                .zip(other.protected_notes.iter_mut())
+                .all(|(a, b)| same_secret(Some(a), Some(b))) {
+            fields.push(EntryField::ProtectedNotes);
+        }
+        if self.image != other.image {
+            fields.push(EntryField::Image);
+        }
+        if self.expire != other.expire {
+            fields.push(EntryField::Expire);
+        }
+        if self.binary_desc != other.binary_desc ||
+           self.binary.as_ref().map(|b| b.bytes()) != other.binary.as_ref().map(|b| b.bytes()) {
+            fields.push(EntryField::Attachment);
+        }
+        fields
+    }
+
+    /// Copy all fields of other but UUID and group into the entry, e.g.
+    /// to take over the version of another database. The secrets are
+    /// copied into new SecureStrings.
+    pub fn copy_from(&mut self, other: &mut V1Entry) {
+        self.image = other.image;
+        self.title = other.title.clone();
+        self.url = other.url.clone();
+        self.username = other.username.as_mut().map(copy_secret);
+        self.password = other.password.as_mut().map(copy_secret);
+        self.comment = other.comment.clone();
+        self.protected_notes = other.protected_notes.iter_mut().map(copy_secret).collect();
+        self.binary_desc = other.binary_desc.clone();
+        self.binary = other.binary.as_ref().map(|b| SecureBytes::new(b.bytes().to_vec()));
+        self.creation = other.creation;
+        self.last_mod = other.last_mod;
+        self.last_access = other.last_access;
+        self.expire = other.expire;
+        self.set_dirty(true);
+    }
+
+    /// The passkey stored in the entry, None if it isn't a passkey
+    /// entry. The private key is the password.
+    pub fn passkey(&self) -> Option<Passkey> {
+        Passkey::from_entry(self)
+    }
+
+    pub fn is_passkey(&self) -> bool {
+        self.passkey().is_some()
+    }
+
+    /// Turn the entry into a passkey entry the way KeePassXC creates
+    /// them: the username and the URL of the relying party are set as
+    /// well and private_key_pem becomes the password. Both are validated
+    /// first, on PasskeyErr or FieldLengthErr the entry stays untouched.
+    /// Sets last_mod.
+    pub fn set_passkey(&mut self,
+                       passkey: &Passkey,
+                       mut private_key_pem: SecureString)
+                       -> Result<(), V1KpdbError> {
+        try!(passkey.validate());
+        try!(Passkey::validate_private_key(&mut private_key_pem));
+        let limits = FieldLimits::v1();
+        try!(check(passkey.username.len(), limits.username));
+        try!(check(private_key_pem.string.len(), limits.password));
+
+        if self.title.is_empty() {
+            self.title = passkey.relying_party.clone();
+        }
+        self.url = Some(format!("https://{}", passkey.relying_party));
+        self.username = Some(SecureString::new(passkey.username.clone()));
+        self.password = Some(private_key_pem);
+        passkey.apply_to(self);
+        self.last_mod = Local::now();
+        self.set_dirty(true);
+        Ok(())
+    }
+
+    /// The one-time password secret of the entry. It's looked for in
+    /// this order: an otpauth:// URI as URL, an otpauth:// line or the
+    /// "TOTP Seed:" line of KeeTrayTOTP in the notes and then in the
+    /// protected sections of the notes. Ok(None) if there's none, OtpErr
+    /// if the first one found is malformed.
+    pub fn otp(&mut self) -> Result<Option<Otp>, V1KpdbError> {
+        if let Some(ref url) = self.url {
+            if let Some(otp) = try!(Otp::find(url)) {
+                return Ok(Some(otp));
+            }
+        }
+        if let Some(ref comment) = self.comment {
+            if let Some(otp) = try!(Otp::find(comment)) {
+                return Ok(Some(otp));
+            }
+        }
+        for section in self.protected_notes.iter_mut() {
+            if let Some(otp) = try!(Otp::find(&section.unlocked())) {
+                return Ok(Some(otp));
+            }
+        }
+        Ok(None)
+    }
+
+    /// The current TOTP code of the entry, see otp. Ok(None) if the
+    /// entry has no secret, OtpErr if it's an HOTP secret.
+    pub fn totp_code(&mut self) -> Result<Option<SecureString>, V1KpdbError> {
+        match try!(self.otp()) {
+            Some(ref otp) if otp.kind == OtpKind::Hotp => Err(V1KpdbError::OtpErr),
+            Some(mut otp) => otp.totp(Local::now()).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// The events of the entry's own timestamps sorted by time:
+    /// creation, last modification, last access and expiry. An entry
+    /// which never expires has no Expires event. Use V1Kpdb::timeline
+    /// to add the earlier modifications kept in the Backup group.
+    pub fn timeline(&self) -> Vec<TimelineEvent> {
+        let mut events = vec![TimelineEvent::new(self.creation, TimelineKind::Created)];
+        if self.last_mod != self.creation {
+            events.push(TimelineEvent::new(self.last_mod, TimelineKind::Modified));
+        }
+        events.push(TimelineEvent::new(self.last_access, TimelineKind::Accessed));
+        if self.expire != never_expires() {
+            events.push(TimelineEvent::new(self.expire, TimelineKind::Expires));
+        }
+        sort_events(&mut events);
+        events
+    }
+
+    /// Check if one of the URLs of the entry points to the same host
+    /// as url
+    pub fn matches_url(&self, url: &str) -> bool {
+        let host = match url_host(url) {
+            Some(h) => h,
+            None => return false,
+        };
+        self.urls().iter().any(|u| url_host(u) == Some(host.clone()))
+    }
+
+    fn parse_additional_url(line: &str) -> Option<String> {
+        if !line.starts_with(ADDITIONAL_URL_PREFIX) {
+            return None;
+        }
+        let rest = &line[ADDITIONAL_URL_PREFIX.len()..];
+        let colon = match rest.find(':') {
+            Some(i) => i,
+            None => return None,
+        };
+        // Only KP2A_URL or KP2A_URL_<n> are valid names
+        let suffix = &rest[..colon];
+        if !suffix.is_empty() &&
+           !(suffix.starts_with('_') && suffix.len() > 1 &&
+             suffix[1..].chars().all(|c| c.is_digit(10))) {
+            return None;
+        }
+        let url = rest[colon + 1..].trim();
+        if url.is_empty() {
+            None
+        } else {
+            Some(url.to_string())
+        }
+    }
+}
+
+fn same_secret(a: Option<&mut SecureString>, b: Option<&mut SecureString>) -> bool {
+    match (a, b) {
+        (Some(a), Some(b)) => *a.unlocked() == *b.unlocked(),
+        (None, None) => true,
+        _ => false,
+    }
+}
+
+fn copy_secret(secret: &mut SecureString) -> SecureString {
+    SecureString::new(secret.unlocked().to_string())
+}
+
+// username, password and binary are zeroed out by SecureString and
+// SecureBytes themselves, the other
+// fields which may hold sensitive data are overwritten here
+impl Drop for V1Entry {
+    fn drop(&mut self) {
+        unsafe {
+            mem_protect::zero(&self.title);
+            if let Some(ref url) = self.url {
+                mem_protect::zero(url);
+            }
+            if let Some(ref comment) = self.comment {
+                mem_protect::zero(comment);
+            }
+            if let Some(ref binary_desc) = self.binary_desc {
+                mem_protect::zero(binary_desc);
+            }
         }
     }
 }
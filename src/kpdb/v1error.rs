@@ -23,7 +23,9 @@ pub enum V1KpdbError {
     EncFlagErr,
     /// Wrong database version
     VersionErr,
-    /// Some error in decryption
+    /// Some error in decryption, e.g. invalid padding.
+    /// Like HashErr this usually means that the wrong
+    /// password and/or keyfile was used
     DecryptErr,
     /// Hash of decrypted content is wrong.
     /// Probably the wrong password and/or keyfile
@@ -41,6 +43,74 @@ pub enum V1KpdbError {
     IndexErr,
     /// Tried upgrade of weak reference without strong one
     WeakErr,
+    /// The regular expression of a search query is invalid
+    RegexErr,
+    /// Group still holds subgroups or entries
+    NotEmptyErr,
+    /// Lock file of the database is invalid
+    LockErr,
+    /// Invalid number of key transformation rounds
+    RoundsErr,
+    /// The additional key provider (e.g. a hardware token) failed
+    KeyProviderErr,
+    /// The key transformation was cancelled through a CancelToken
+    CancelledErr,
+    /// A meta entry couldn't be read
+    MetaErr,
+    /// The keyfile looks like a KeePass 2 XML keyfile but is invalid
+    KeyfileErr,
+    /// An entry patch is malformed or contains an invalid operation
+    PatchErr,
+    /// A field is longer than the format or FieldLimits allow
+    FieldLengthErr,
+    /// The key doesn't satisfy the unlock policy or the policy can't be
+    /// enforced
+    PolicyErr,
+    /// Passkey attributes or private key are missing or malformed
+    PasskeyErr,
+    /// A custom icon isn't a PNG image
+    IconErr,
+    /// The settings of a PasswordGenerator allow no password
+    GeneratorErr,
+    /// CSV data is malformed or the CsvMapping is invalid
+    CsvErr,
+    /// An export of another password manager is malformed, encrypted
+    /// or of an unknown version
+    ImportErr,
+    /// An XML export of KeePass 1.x is malformed
+    XmlErr,
+    /// The entry or group of a handle was removed
+    HandleErr,
+    /// The entry or group of a handle is borrowed elsewhere
+    BorrowErr,
+    /// An OTP secret or otpauth:// URI is malformed, or the entry holds
+    /// an HOTP secret where a TOTP is needed
+    OtpErr,
+    /// An auto-type sequence holds an unknown or unclosed placeholder
+    AutoTypeErr,
+    /// The thread holding a SharedKpdb is gone, e.g. after a panic
+    ThreadErr,
+    /// The database is locked, see AutoLock
+    LockedErr,
+    /// Another process holds the lock file of the database, see
+    /// lockfile::inspect
+    FileLockedErr,
+    /// The random number generator of the OS failed
+    RngErr,
+    /// Memory holding secrets couldn't be locked under
+    /// MemLockPolicy::Require
+    MemLockErr,
+    /// The credential store of a KeyCache failed, see kpdb::credentials
+    KeyringErr,
+    /// A path of a group or an entry is malformed, see kpdb::path
+    PathErr,
+    /// The name of a custom field is invalid, see
+    /// custom_fields::is_valid_name
+    CustomFieldErr,
+    /// The agent doesn't hold a database at the path, see kpdb::agent
+    NotOpenErr,
+    /// The database has unsaved changes, see V1Kpdb::dirty
+    UnsavedErr,
 }
 
 impl fmt::Display for V1KpdbError {
@@ -58,7 +128,7 @@ impl error::Error for V1KpdbError {
             SignatureErr => "File signature in header is wrong",
             EncFlagErr => "Encryption algorithm not supported",
             VersionErr => "Wrong database version",
-            DecryptErr => "Something went wrong during decryption",
+            DecryptErr => "Something went wrong during decryption, probably wrong password",
             HashErr => "Content's hash is wrong, probably wrong password",
             ConvertErr => "Some error while parsing the database",
             OffsetErr => "Some error while parsing the database. Probably a corrupted file",
@@ -66,6 +136,37 @@ impl error::Error for V1KpdbError {
             PassErr => "Password and/or keyfile needed but at least one of both",
             IndexErr => "Can't find item in Vec",
             WeakErr => "Tried upgrade of weak reference without strong one",
+            RegexErr => "Invalid regular expression in search query",
+            NotEmptyErr => "Group still holds subgroups or entries",
+            LockErr => "Lock file of the database is invalid",
+            RoundsErr => "Invalid number of key transformation rounds",
+            KeyProviderErr => "Couldn't get key from additional key provider",
+            CancelledErr => "Key transformation was cancelled",
+            MetaErr => "Invalid data in meta entry",
+            KeyfileErr => "Invalid XML keyfile",
+            PatchErr => "Invalid entry patch",
+            FieldLengthErr => "Field exceeds the length limit",
+            PolicyErr => "Key doesn't satisfy the unlock policy",
+            PasskeyErr => "Invalid passkey",
+            IconErr => "Custom icon is no PNG image",
+            GeneratorErr => "Invalid password generator settings",
+            CsvErr => "Invalid CSV data or mapping",
+            ImportErr => "Unsupported or malformed export",
+            XmlErr => "Invalid KeePass XML export",
+            HandleErr => "Entry or group of the handle was removed",
+            BorrowErr => "Entry or group is already borrowed",
+            OtpErr => "Invalid OTP secret",
+            AutoTypeErr => "Invalid auto-type sequence",
+            ThreadErr => "Database thread isn't running",
+            LockedErr => "Database is locked",
+            FileLockedErr => "Database is in use by another process",
+            RngErr => "Random number generator of the OS failed",
+            MemLockErr => "Memory of secrets couldn't be locked",
+            KeyringErr => "Couldn't access the credential store",
+            PathErr => "Invalid path of a group or an entry",
+            CustomFieldErr => "Invalid name of a custom field",
+            NotOpenErr => "Database isn't open in the agent",
+            UnsavedErr => "Database has unsaved changes",
         }
     }
 }
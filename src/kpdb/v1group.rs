@@ -1,11 +1,14 @@
 use std::cell::RefCell;
+use std::collections::BTreeMap;
 use std::rc::{Rc, Weak};
 
-use chrono::{DateTime, Local, TimeZone};
+use chrono::{DateTime, Local};
 
 use kpdb::GetIndex;
-use kpdb::v1entry::V1Entry;
+use kpdb::limits::{check, FieldLimits};
+use kpdb::v1entry::{never_expires, V1Entry};
 use kpdb::v1error::V1KpdbError;
+use super::super::mem_protect;
 
 #[doc = "
 Implements a group of a KeePass v1.x database
@@ -29,12 +32,21 @@ pub struct V1Group {
     pub expire: DateTime<Local>,
     /// ??
     pub flags: u32,
+    /// Notes of the group. KeePass 1.x has no such field, hence
+    /// they're saved in a meta entry
+    pub notes: Option<String>,
+    /// Additional key value pairs, saved like notes
+    pub custom_data: BTreeMap<String, String>,
     /// Pointer to the parent group
     pub parent: Option<Rc<RefCell<V1Group>>>,
     /// Array of weak references to the children
     pub children: Vec<Weak<RefCell<V1Group>>>,
     /// Array of weak references to the entries
     pub entries: Vec<Weak<RefCell<V1Entry>>>, // db: Box<Option<V1Kpdb>>,
+    // Changed since the last load or save, see dirty
+    dirty: bool,
+    // Counts the changes, see V1Kpdb::batch
+    revision: u64,
 }
 
 impl V1Group {
@@ -50,14 +62,63 @@ impl V1Group {
             creation: Local::now(),
             last_mod: Local::now(),
             last_access: Local::now(),
-            expire: Local.ymd(2999, 12, 28).and_hms(23, 59, 59),
+            expire: never_expires(),
             flags: 0,
+            notes: None,
+            custom_data: BTreeMap::new(),
             parent: None,
             children: vec![],
             entries: vec![], // db: box None,
+            dirty: false,
+            revision: 0,
         }
     }
 
+    /// True if the group was changed since the database was loaded or
+    /// saved. The methods of V1Kpdb set it, writing to a field directly
+    /// doesn't, use set_dirty then.
+    pub fn dirty(&self) -> bool {
+        self.dirty
+    }
+
+    /// Set the title. Returns FieldLengthErr if it's longer than
+    /// FieldLimits::v1 allows, the group stays unchanged then.
+    pub fn set_title(&mut self, title: String) -> Result<(), V1KpdbError> {
+        try!(check(title.len(), FieldLimits::v1().title));
+        self.title = title;
+        Ok(())
+    }
+
+    /// Mark the group as changed or unchanged
+    pub fn set_dirty(&mut self, dirty: bool) {
+        self.dirty = dirty;
+        if dirty {
+            self.revision = self.revision.wrapping_add(1);
+        }
+    }
+
+    // Number of changes so far, set_dirty(true) counts
+    pub(crate) fn revision(&self) -> u64 {
+        self.revision
+    }
+
+    /// The subgroups in their order. Subgroups which were dropped
+    /// already are skipped.
+    pub fn child_groups(&self) -> Vec<Rc<RefCell<V1Group>>> {
+        self.children.iter().filter_map(|c| c.upgrade()).collect()
+    }
+
+    /// The entries of the group in their order, without entries which
+    /// were dropped already
+    pub fn child_entries(&self) -> Vec<Rc<RefCell<V1Entry>>> {
+        self.entries.iter().filter_map(|e| e.upgrade()).collect()
+    }
+
+    /// Id of the parent group, None for the root group
+    pub fn parent_id(&self) -> Option<u32> {
+        self.parent.as_ref().map(|p| p.borrow().id)
+    }
+
     pub fn drop_weak_child_reference(&mut self,
                                      child: &Rc<RefCell<V1Group>>)
                                      -> Result<(), V1KpdbError> {
@@ -77,6 +138,22 @@ impl V1Group {
     }
 }
 
+// Overwrite title, notes and custom data
+impl Drop for V1Group {
+    fn drop(&mut self) {
+        unsafe {
+            mem_protect::zero(&self.title);
+            if let Some(ref notes) = self.notes {
+                mem_protect::zero(notes);
+            }
+            for (key, value) in self.custom_data.iter() {
+                mem_protect::zero(key);
+                mem_protect::zero(value);
+            }
+        }
+    }
+}
+
 impl PartialEq for V1Group {
     fn eq(&self, other: &V1Group) -> bool {
         self.id == other.id
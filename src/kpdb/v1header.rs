@@ -1,3 +1,6 @@
+use rand::{OsRng, Rng};
+
+use kpdb::parser::HeaderLoadParser;
 use kpdb::v1error::V1KpdbError;
 
 // Todo:
@@ -56,6 +59,42 @@ impl V1Header {
         }
     }
 
+    /// Parse the header at the start of data, e.g. a whole file. FileErr
+    /// if data is shorter than the 124 bytes of a header. The fields
+    /// aren't checked, see check_signatures, check_enc_flag and
+    /// check_version. Never panics, see v1parser for the content.
+    pub fn parse(data: &[u8]) -> Result<V1Header, V1KpdbError> {
+        if data.len() < 124 {
+            return Err(V1KpdbError::FileErr);
+        }
+        HeaderLoadParser::new(data[..124].to_vec()).parse_header()
+    }
+
+    /// Set the number of rounds of the key transformation. More rounds
+    /// make brute forcing harder but opening slower. See
+    /// Crypter::benchmark_rounds to choose a value. At least one
+    /// round is needed.
+    pub fn set_key_transf_rounds(&mut self, rounds: u32) -> Result<(), V1KpdbError> {
+        if rounds == 0 {
+            return Err(V1KpdbError::RoundsErr);
+        }
+        self.key_transf_rounds = rounds;
+        Ok(())
+    }
+
+    /// Replace final_randomseed, iv and transf_randomseed with fresh
+    /// values from the random number generator of the OS, as KeePass
+    /// does on every save. Re-using the IV with the same key would leak
+    /// whether the start of the content changed. V1Kpdb::save calls
+    /// this, RngErr if the OS can't provide random numbers.
+    pub fn regenerate_seeds(&mut self) -> Result<(), V1KpdbError> {
+        let mut rng = try!(OsRng::new().map_err(|_| V1KpdbError::RngErr));
+        self.final_randomseed = random_bytes(&mut rng, 16);
+        self.iv = random_bytes(&mut rng, 16);
+        self.transf_randomseed = random_bytes(&mut rng, 32);
+        Ok(())
+    }
+
     // Checks file signatures
     pub fn check_signatures(&self) -> Result<(), V1KpdbError> {
         if self.signature1 != 0x9AA2D903u32 || self.signature2 != 0xB54BFB65u32 {
@@ -81,3 +120,9 @@ impl V1Header {
     }
 }
 
+
+fn random_bytes(rng: &mut OsRng, len: usize) -> Vec<u8> {
+    let mut bytes = vec![0u8; len];
+    rng.fill_bytes(&mut bytes);
+    bytes
+}
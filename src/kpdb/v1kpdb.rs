@@ -1,19 +1,64 @@
 use std::cell::RefCell;
-use std::rc::Rc;
-use std::io::{Read, Write};
-use std::fs::File;
+use std::cmp;
+use std::collections::{HashMap, HashSet};
+use std::rc::{Rc, Weak};
+use std::io::{self, Read, Write};
+use std::fs::{self, File, OpenOptions};
+use std::mem;
+use std::path::Path;
+use std::time::{Duration, Instant, SystemTime};
 
-use chrono::{DateTime, Local};
+use chrono::{self, DateTime, Local};
+use openssl::crypto::hash::{Hasher, Type};
 use rand;
+use uuid::Uuid;
 
 use kpdb::GetIndex;
-use kpdb::crypter::Crypter;
+use kpdb::advisor::{password_strength, Advice, Priority, Recommendation, WEAK_PASSWORD_BITS};
+use kpdb::audit::AuditReport;
+use kpdb::autolock::AutoLock;
+use kpdb::backup::SaveOptions;
+use kpdb::breach::BreachList;
+use kpdb::builder::{Cipher, ReencryptOptions};
+use kpdb::conformance::{check_tree, Violation};
+use kpdb::crypter::{CancelToken, CompositeKey, Crypter, KeyJob, KeyProvider};
+use kpdb::domains::{EquivalentDomains, EQUIVALENT_DOMAINS_STREAM};
+use kpdb::duplicates::{duplicate_key, DuplicateCriteria};
+#[cfg(feature = "serde")]
+use kpdb::dump::FlatTree;
+use kpdb::error::KpdbError;
+use kpdb::export::archive;
+use kpdb::handle::{EntryHandle, GroupHandle, HandleTable};
+use kpdb::import::{create_groups, find_group};
+use kpdb::iter::{EntryIter, GroupIter, Traversal};
+use kpdb::limits::{check, FieldLimits};
+use kpdb::lockfile::{inspect, FileLock};
+use kpdb::mmap::MappedFile;
+use kpdb::merge::{ConflictResolver, Decision, MergeConflict, MergeReport, Resolution};
+use kpdb::passkey::Passkey;
+use kpdb::path;
+use kpdb::order::{entry_order, group_order, SortKey};
+use kpdb::meta::{decode_group_meta, encode_group_meta, is_meta_entry, new_meta_entry,
+                 MetaInfo, GROUP_META_STREAM};
+use kpdb::policy::{KeyFactor, UnlockPolicy, UNLOCK_POLICY_STREAM};
+use kpdb::reveal::{ProtectedField, RevealTracker, Revealed};
+use kpdb::recovery::{RecoveryReport, RECOVERED_GROUP_TITLE};
 use kpdb::parser::{HeaderLoadParser, HeaderSaveParser, LoadParser, SaveParser};
+use kpdb::search::{is_backup_group, is_in_backup_group, is_in_excluded_group, SearchQuery,
+                   ARCHIVE_GROUP_TITLE, BACKUP_GROUP_TITLE, EXCLUDE_FROM_SEARCH};
+use kpdb::secret_access::SecretAccess;
+use kpdb::stats::DbStats;
+use kpdb::timeline::{sort_events, TimelineEvent, TimelineKind};
+use kpdb::undo::{Snapshot, UndoLog};
+use kpdb::usage::{UsageEvent, UsageKind, UsageSink};
 use kpdb::v1error::V1KpdbError;
 use kpdb::v1group::V1Group;
 use kpdb::v1entry::V1Entry;
 use kpdb::v1header::V1Header;
-use super::super::sec_str::SecureString;
+use kpdb::v1parser::parse_decrypted;
+use kpdb::xml;
+use super::super::mem_protect::{self, MemLockPolicy, MemLockScope};
+use super::super::sec_str::{SecureBytes, SecureString};
 
 #[doc = "
 V1Kpdb implements a KeePass v1.x database. Some notes on the file format:
@@ -44,17 +89,95 @@ pub struct V1Kpdb {
     pub groups: Vec<Rc<RefCell<V1Group>>>,
     /// The entries of the whole database
     pub entries: Vec<Rc<RefCell<V1Entry>>>,
+    /// Meta entries, i.e. data of KeePass or other clients stored as
+    /// entries. They aren't part of the group tree and are saved as
+    /// they are. Group notes and custom data are kept in the groups.
+    pub meta_entries: Vec<Rc<RefCell<V1Entry>>>,
+    /// Data of the meta streams of KeePassX and KeePass, e.g. custom
+    /// icons. It's read on load and written back on save.
+    pub meta_info: MetaInfo,
     /// A group which holds all groups of level 0
     /// as a subgroup (all groups which are not a
     /// subgroup of another group )
     pub root_group: Rc<RefCell<V1Group>>,
+    /// If true, save keeps the previous database file as
+    /// <path>.bak before replacing it
+    pub keep_backup: bool,
+    /// Numbered copies of the previous file save keeps, see
+    /// SaveOptions. None are kept by default
+    pub save_options: SaveOptions,
+    /// What load and save do if memory holding secrets can't be locked.
+    /// Under Require they fail with MemLockErr, load drops what was
+    /// loaded then. Unless it's Disabled, load first raises the soft
+    /// RLIMIT_MEMLOCK up to the hard one to fit the file, see
+    /// MemLockScope. Defaults to BestEffort
+    pub mem_lock_policy: MemLockPolicy,
+    /// If true, load and load_with_recovery map the file into memory
+    /// instead of reading it, so the ciphertext isn't copied into the
+    /// heap, see MappedFile. Where mapping fails the file is read as
+    /// before. False by default.
+    ///
+    /// Warning: only set it for files which no other process writes to
+    /// in place. A write while the file is loaded makes the load fail
+    /// with HashErr, truncating it kills the process with SIGBUS.
+    /// Replacing the file, as save does, is fine.
+    pub use_mmap: bool,
+    /// If true, the load functions call mem_protect::harden_process
+    /// first, so a crash doesn't write decrypted passwords to a core
+    /// file. It affects the whole process for good. False by default
+    pub harden_process: bool,
+    /// Sets of domains which find_entries_for_url treats as the same
+    /// service. Saved in a meta stream, see EQUIVALENT_DOMAINS_STREAM.
+    pub equivalent_domains: EquivalentDomains,
+    /// Receives a UsageEvent after every load, save and merge. None
+    /// (the default) disables reporting
+    pub usage_sink: Option<Box<UsageSink>>,
+    /// If set, rotate_protection_keys_if_due re-encrypts all protected
+    /// values once this much time has passed since the last rotation.
+    /// None (the default) disables the rotation
+    pub key_rotation_interval: Option<Duration>,
+    /// Maximal field lengths, checked by save before anything is
+    /// written, see check_field_limits, and by create_group. Defaults
+    /// to the limits of the KeePass 1.x format
+    pub field_limits: FieldLimits,
+    /// Upper bound for the time to live of the guards handed out by
+    /// reveal. None (the default) leaves it to the caller
+    pub max_reveal_ttl: Option<Duration>,
+    /// Number of changes undo can revert. Every change made through the
+    /// methods of V1Kpdb keeps a copy of the database, so this costs
+    /// memory. 0 (the default) disables undo
+    pub undo_limit: usize,
+    // Factors needed to open the database, saved as meta entry
+    unlock_policy: Option<UnlockPolicy>,
+    // Entries by UUID for find_by_uuid
+    uuid_index: HashMap<Uuid, Weak<RefCell<V1Entry>>>,
+    // Shared with all entries, see on_secret_access
+    secret_access: Rc<SecretAccess>,
+    // Guards handed out by reveal
+    reveals: RevealTracker,
+    // States before the last changes, see undo
+    undo_log: UndoLog,
+    // Changed since the last load or save in a way the groups and
+    // entries don't tell, e.g. by removing one, see dirty
+    modified: bool,
+    // Counts the changes which set modified, see touch
+    changes: u64,
+    // Generations behind EntryHandle and GroupHandle
+    handles: HandleTable,
+    // Time of the last rotation of the in-memory keys
+    last_key_rotation: Instant,
+    // Lock file taken by acquire_file_lock
+    file_lock: Option<FileLock>,
+    // The file as it was last loaded or saved, for has_changed_on_disk
+    disk_state: Option<DiskState>,
     // Used to de- and encrypt the database
     crypter: Crypter,
 }
 
 impl V1Kpdb {
     /// Call this to create a new database instance. You have to call load
-    /// to start decrypting and parsing of an existing database!
+    /// to start decrypting and parsing of an existing database! Use
+    /// builder::V1KpdbBuilder to create a new database.
     /// path is the filepath of the database, password is the database password
     /// and keyfile is the filepath to the keyfile.
     /// password should already lie on the heap as a String type and not &str
@@ -77,287 +200,3417 @@ impl V1Kpdb {
             None => None,
         };
 
-        Ok(V1Kpdb {
+        V1Kpdb::with_key(path, CompositeKey::from_credentials(sec_password, sec_keyfile))
+    }
+
+    /// Like new but with a CompositeKey, e.g. to add a challenge-response
+    /// component to password and/or keyfile.
+    pub fn with_key(path: String, key: CompositeKey) -> Result<V1Kpdb, V1KpdbError> {
+        if !key.is_valid() {
+            return Err(V1KpdbError::PassErr);
+        }
+
+        Ok(V1Kpdb::with_crypter(path, Crypter::with_key(key)))
+    }
+
+    fn with_crypter(path: String, crypter: Crypter) -> V1Kpdb {
+        V1Kpdb {
             path: path,
             header: V1Header::new(),
             groups: vec![],
             entries: vec![],
+            meta_entries: vec![],
+            meta_info: MetaInfo::new(),
             root_group: Rc::new(RefCell::new(V1Group::new())),
-            crypter: Crypter::new(sec_password, sec_keyfile),
-        })
+            keep_backup: false,
+            save_options: SaveOptions::new(),
+            mem_lock_policy: MemLockPolicy::BestEffort,
+            use_mmap: false,
+            harden_process: false,
+            equivalent_domains: EquivalentDomains::new(),
+            usage_sink: None,
+            key_rotation_interval: None,
+            field_limits: FieldLimits::v1(),
+            max_reveal_ttl: None,
+            undo_limit: 0,
+            unlock_policy: None,
+            uuid_index: HashMap::new(),
+            secret_access: Rc::new(SecretAccess::new()),
+            reveals: RevealTracker::new(),
+            handles: HandleTable::new(),
+            undo_log: UndoLog::new(),
+            modified: false,
+            changes: 0,
+            last_key_rotation: Instant::now(),
+            file_lock: None,
+            disk_state: None,
+            crypter: crypter,
+        }
     }
 
-    /// Decrypt and parse the database.
+    /// Decrypt and parse the database. A wrong key gives DecryptErr, use
+    /// load_detailed for the details of an error.
     pub fn load(&mut self) -> Result<(), V1KpdbError> {
-        let (header, encrypted_database) = try!(self.read_in_file());
+        self.load_detailed().map_err(|e| e.coarse())
+    }
 
-        // First read header and decrypt the database
-        let header_parser = HeaderLoadParser::new(header);
-        self.header = try!(header_parser.parse_header());
-        try!(self.check_header());
-        let decrypted_database = try!(self.crypter
-                                      .decrypt_database(&self.header, encrypted_database));
+    /// Like load but the error carries its context, e.g. whether the key
+    /// is wrong or the file is damaged. The other load functions only
+    /// return the coarse V1KpdbError.
+    pub fn load_detailed(&mut self) -> Result<(), KpdbError> {
+        let start = Instant::now();
+        self.harden();
+        let scope = MemLockScope::enter(self.mem_lock_policy, self.load_budget());
+        let result = self.load_database();
+        let lock_failures = scope.leave();
+        let result = result.and_then(|_| {
+            self.check_mem_locks(lock_failures).map_err(KpdbError::from)
+        });
+        self.report_usage(UsageKind::Open, start, result.is_ok(), lock_failures);
+        result
+    }
+
+    fn load_database(&mut self) -> Result<(), KpdbError> {
+        // First read header and decrypt the database. If the content
+        // fails the checks, it tells whether the key is wrong or the file
+        // is damaged.
+        let (disk_state, decrypted_database, intact) = try!(self.read_and_decrypt());
+        try!(self.parse_database(decrypted_database, intact));
+        self.disk_state = Some(disk_state);
+        Ok(())
+    }
 
+    /// Like load but the database is read from data instead of path,
+    /// e.g. a file received over the network or picked by the user.
+    /// path is only used by save then, and has_changed_on_disk and
+    /// reload don't know the file. WebAssembly (wasm32) isn't supported
+    /// yet: the crypto links against native OpenSSL and the file
+    /// system, memory mapping and lock files aren't left out there.
+    pub fn load_from_bytes(&mut self, data: &[u8]) -> Result<(), V1KpdbError> {
+        let start = Instant::now();
+        self.harden();
+        let budget = mem_protect::lock_budget(data.len() as u64);
+        let scope = MemLockScope::enter(self.mem_lock_policy, budget);
+        let result = self.load_bytes(data).map_err(|e| e.coarse());
+        let lock_failures = scope.leave();
+        let result = result.and_then(|_| self.check_mem_locks(lock_failures));
+        self.report_usage(UsageKind::Open, start, result.is_ok(), lock_failures);
+        result
+    }
+
+    /// load_from_bytes with everything reader returns
+    pub fn load_from_reader<R: Read>(&mut self, mut reader: R) -> Result<(), V1KpdbError> {
+        let mut data = vec![];
+        try!(reader.read_to_end(&mut data).map_err(|_| V1KpdbError::ReadErr));
+        self.load_from_bytes(&data)
+    }
+
+    fn load_bytes(&mut self, data: &[u8]) -> Result<(), KpdbError> {
+        let (decrypted_database, intact) = try!(self.decrypt_bytes(data));
+        try!(self.parse_database(decrypted_database, intact));
+        self.disk_state = None;
+        Ok(())
+    }
+
+    fn parse_database(&mut self,
+                      decrypted_database: Vec<u8>,
+                      intact: bool)
+                      -> Result<(), KpdbError> {
         // Next parse groups and entries.
         // pos is needed to remember position after group parsing
         let mut parser = LoadParser::new(decrypted_database,
                                          self.header.num_groups,
                                          self.header.num_entries);
-        let (groups, levels) = try!(parser.parse_groups());
+        if !intact {
+            return Err(parser.diagnose());
+        }
+        let (groups, levels) = match parser.parse_groups() {
+            Ok(parsed) => parsed,
+            Err(e) => return Err(parser.corrupt(e)),
+        };
+        log_trace!("parsed {} groups", groups.len());
         self.groups = groups;
-        self.entries = try!(parser.parse_entries());
+        let entries = match parser.parse_entries() {
+            Ok(entries) => entries,
+            Err(e) => return Err(parser.corrupt(e)),
+        };
+        log_trace!("parsed {} entries, meta entries included", entries.len());
         parser.delete_decrypted_content();
+        self.forget_changes();
+        try!(self.split_meta_entries(entries));
 
         // Now create the group tree and sort the entries to their groups
+        self.root_group = Rc::new(RefCell::new(V1Group::new()));
         try!(LoadParser::create_group_tree(self, levels));
         Ok(())
     }
 
-    fn read_in_file(&self) -> Result<(Vec<u8>, Vec<u8>), V1KpdbError> {
-        let mut file = try!(File::open(&self.path).map_err(|_| V1KpdbError::FileErr));
-        let mut raw: Vec<u8> = vec![];
-        try!(file.read_to_end(&mut raw).map_err(|_| V1KpdbError::ReadErr));
-        let encrypted_database = raw.split_off(124);
-        Ok((raw, encrypted_database))
+    /// Like load but the content is decrypted and parsed chunk by chunk
+    /// while it's read, see DecryptReader. There's never a decrypted copy
+    /// of the whole database in memory besides the groups and entries,
+    /// so large databases, e.g. with big attachments, need about half
+    /// the locked memory. As the content hash is only known at the end,
+    /// a wrong key or a damaged file give DecryptErr or HashErr without
+    /// the details of load_detailed. Nothing is changed on error.
+    pub fn load_streaming(&mut self) -> Result<(), V1KpdbError> {
+        let start = Instant::now();
+        self.harden();
+        let scope = MemLockScope::enter(self.mem_lock_policy, self.load_budget());
+        let result = self.load_database_streaming();
+        let lock_failures = scope.leave();
+        let result = result.and_then(|_| self.check_mem_locks(lock_failures));
+        self.report_usage(UsageKind::Open, start, result.is_ok(), lock_failures);
+        result
     }
 
-    fn check_header(&self) -> Result<(), V1KpdbError> {
-        try!(self.header.check_signatures());
-        try!(self.header.check_enc_flag());
-        try!(self.header.check_version());
-        Ok(())
-    }
-    
-    pub fn save(&mut self,
-                path: Option<String>,
-                password: Option<String>,
-                keyfile: Option<String>) -> Result<(), V1KpdbError> {
-        let mut parser = SaveParser::new();
-        parser.prepare(self);
-        
-        let mut header = self.header.clone();
-        header.final_randomseed = (0..16).map(|_| rand::random::<u8>()).collect();
-        header.iv = (0..16).map(|_| rand::random::<u8>()).collect();
-        header.content_hash = try!(Crypter::get_content_hash(&parser.database));
-        let encrypted_database = try!(self.crypter.encrypt_database(&header, parser.database));
+    fn load_database_streaming(&mut self) -> Result<(), V1KpdbError> {
+        let mut file = try!(File::open(&self.path).map_err(|_| V1KpdbError::FileErr));
+        let len = try!(file.metadata().map_err(|_| V1KpdbError::ReadErr)).len();
+        if len < 124 {
+            return Err(V1KpdbError::FileErr);
+        }
+        let mut header = vec![0u8; 124];
+        try!(file.read_exact(&mut header).map_err(|_| V1KpdbError::ReadErr));
+        let disk_state = DiskState::new(&self.path, &header);
+        let header = try!(HeaderLoadParser::new(header).parse_header());
+        try!(V1Kpdb::check_header_of(&header));
 
-        let mut header_parser = HeaderSaveParser::new(header);
-        let header_raw = header_parser.parse_header();
+        let len = len - 124;
+        let mut reader = try!(self.crypter.decrypt_stream(&header, file, len));
+        let parsed = LoadParser::parse_stream(&mut reader,
+                                              header.num_groups,
+                                              header.num_entries,
+                                              len);
+        // Whatever was parsed is only trusted if the content hash matches.
+        // Garbage from a wrong key doesn't parse, content which parses
+        // but fails the hash is damaged.
+        let (groups, levels, entries) = match (parsed, reader.finish()) {
+            (Ok(parsed), Ok(())) => parsed,
+            (Ok(_), Err(e)) => return Err(e),
+            (Err(_), Err(V1KpdbError::HashErr)) |
+            (Err(_), Err(V1KpdbError::DecryptErr)) => return Err(V1KpdbError::DecryptErr),
+            (Err(e), _) => return Err(e),
+        };
+        log_trace!("parsed {} groups and {} entries, meta entries included",
+                   groups.len(),
+                   entries.len());
 
-        let mut file = try!(File::create(&self.path).map_err(|_| V1KpdbError::FileErr));
-        try!(file.write_all(&header_raw).map_err(|_| V1KpdbError::WriteErr));
-        try!(file.write_all(&encrypted_database).map_err(|_| V1KpdbError::WriteErr));
-        try!(file.flush().map_err(|_| V1KpdbError::WriteErr));
+        // Build the tree aside so a failure doesn't leave a half loaded
+        // database behind
+        let mut disk = V1Kpdb::with_crypter(self.path.clone(),
+                                            Crypter::with_key(CompositeKey::new()));
+        disk.header = header;
+        disk.groups = groups;
+        try!(disk.split_meta_entries(entries));
+        try!(LoadParser::create_group_tree(&mut disk, levels));
+        disk.disk_state = Some(disk_state);
+        self.replace_with(disk);
         Ok(())
     }
-    
-    /// Create a new group
-    ///
-    /// * title: title of the new group
-    ///
-    /// * expire: expiration date of the group
-    ///           None means that the group expires never which itself
-    ///           corresponds to the date 28-12-2999 23:59:59
-    ///
-    /// * image: an image number, used in KeePass and KeePassX for the group
-    ///          icon. None means 0
+
+    /// Like load but for damaged databases, similar to the repair mode
+    /// of KeePass. A wrong content hash or a truncated file aren't errors,
+    /// groups and entries which can't be read are skipped instead. The
+    /// returned report lists what was dropped or changed.
     ///
-    /// * parent: a group inside the groups vector which should be the parent in
-    ///           the group tree. None means that the root group is the parent
-    pub fn create_group(&mut self,
-                        title: String,
-                        expire: Option<DateTime<Local>>,
-                        image: Option<u32>,
-                        parent: Option<Rc<RefCell<V1Group>>>)
-                        -> Result<(), V1KpdbError> {
-        let mut new_id: u32 = 1;
-        for group in self.groups.iter() {
-            let id = group.borrow().id;
-            if id >= new_id {
-                new_id = id + 1;
+    /// As the content hash can't tell a wrong key from a damaged file,
+    /// DecryptErr is returned if nothing at all could be salvaged.
+    pub fn load_with_recovery(&mut self) -> Result<RecoveryReport, V1KpdbError> {
+        let start = Instant::now();
+        self.harden();
+        let scope = MemLockScope::enter(self.mem_lock_policy, self.load_budget());
+        let result = self.load_damaged_database();
+        let lock_failures = scope.leave();
+        let result = result.and_then(|report| {
+            self.check_mem_locks(lock_failures).map(|_| report)
+        });
+        self.report_usage(UsageKind::Open, start, result.is_ok(), lock_failures);
+        result
+    }
+
+    fn load_damaged_database(&mut self) -> Result<RecoveryReport, V1KpdbError> {
+        let (disk_state, decrypted_database, intact) = try!(self.read_and_decrypt()
+                                                                .map_err(|e| e.coarse()));
+
+        let mut report = RecoveryReport::new();
+        report.content_intact = intact;
+        let mut parser = LoadParser::new(decrypted_database,
+                                         self.header.num_groups,
+                                         self.header.num_entries);
+        let (groups, mut levels) = parser.salvage_groups(&mut report);
+        let entries = parser.salvage_entries(&mut report);
+        parser.delete_decrypted_content();
+        log_trace!("salvaged {} groups and {} entries, meta entries included",
+                   groups.len(),
+                   entries.len());
+        if !intact && groups.is_empty() && entries.is_empty() {
+            return Err(V1KpdbError::DecryptErr);
+        }
+        report.repaired_levels = LoadParser::repair_levels(&groups, &mut levels);
+        self.groups = groups;
+        self.forget_changes();
+
+        // Broken meta streams are dropped, the database works without them
+        self.entries = vec![];
+        self.meta_entries = vec![];
+        self.meta_info = MetaInfo::new();
+        self.unlock_policy = None;
+        self.equivalent_domains = EquivalentDomains::new();
+        for entry in entries {
+            if !is_meta_entry(&mut entry.borrow_mut()) {
+                self.entries.push(entry);
+            } else if self.read_meta_entry(&entry).is_ok() {
+                self.meta_entries.push(entry);
+            } else {
+                report.dropped_meta_entries += 1;
             }
         }
 
-        let new_group = Rc::new(RefCell::new(V1Group::new()));
-        new_group.borrow_mut().id = new_id;
-        new_group.borrow_mut().title = title;
-        new_group.borrow_mut().creation = Local::now();
-        new_group.borrow_mut().last_mod = Local::now();
-        new_group.borrow_mut().last_access = Local::now();
-        match expire {
-            Some(s) => new_group.borrow_mut().expire = s,
-            None => {} // is 12-28-2999 23:59:59 through V1Group::new
+        self.index_entries();
+
+        self.root_group = Rc::new(RefCell::new(V1Group::new()));
+        try!(LoadParser::create_group_tree(self, levels));
+        self.header.num_groups = self.groups.len() as u32;
+        self.header.num_entries = (self.entries.len() + self.meta_entries.len()) as u32;
+        try!(self.adopt_orphaned_entries(&mut report));
+        self.disk_state = Some(disk_state);
+        self.modified = !report.is_clean();
+        Ok(report)
+    }
+
+    // Move entries whose group was dropped into a new group
+    fn adopt_orphaned_entries(&mut self, report: &mut RecoveryReport) -> Result<(), V1KpdbError> {
+        let orphans: Vec<Rc<RefCell<V1Entry>>> = self.entries
+                                                     .iter()
+                                                     .filter(|e| e.borrow().group.is_none())
+                                                     .cloned()
+                                                     .collect();
+        if orphans.is_empty() {
+            return Ok(());
         }
-        match image {
-            Some(s) => new_group.borrow_mut().image = s,
-            None => {} // is 0 through V1Group::new
+        report.orphaned_entries = orphans.len();
+        try!(self.adopt_entries(orphans));
+        // Meta entries need a group as well
+        let group_id = self.groups[0].borrow().id;
+        for entry in &self.meta_entries {
+            entry.borrow_mut().group_id = group_id;
         }
-        match parent {
-            Some(s) => {
-                let index = try!(self.groups.get_index(&s));
-                new_group.borrow_mut().parent = Some(s.clone());
-                s.borrow_mut().children.push(Rc::downgrade(&new_group.clone()));
-                self.groups.insert(index + 1, new_group);
+        Ok(())
+    }
+
+    // Move entries into a new group titled RECOVERED_GROUP_TITLE
+    fn adopt_entries(&mut self, orphans: Vec<Rc<RefCell<V1Entry>>>) -> Result<(), V1KpdbError> {
+        let title = RECOVERED_GROUP_TITLE.to_string();
+        let group = try!(self.create_group_unrecorded(title, None, None, None));
+        for entry in orphans {
+            entry.borrow_mut().group_id = group.borrow().id;
+            entry.borrow_mut().group = Some(group.clone());
+            group.borrow_mut().entries.push(Rc::downgrade(&entry));
+        }
+        Ok(())
+    }
 
+    // Separate the meta entries from the real ones and read the group
+    // metadata
+    fn split_meta_entries(&mut self,
+                          entries: Vec<Rc<RefCell<V1Entry>>>)
+                          -> Result<(), V1KpdbError> {
+        self.entries = vec![];
+        self.meta_entries = vec![];
+        self.meta_info = MetaInfo::new();
+        self.unlock_policy = None;
+        self.equivalent_domains = EquivalentDomains::new();
+        for entry in entries {
+            if !is_meta_entry(&mut entry.borrow_mut()) {
+                self.entries.push(entry);
+                continue;
             }
-            None => {
-                new_group.borrow_mut().parent = Some(self.root_group
-                                                         .clone());
-                self.root_group.borrow_mut().children.push(Rc::downgrade(&new_group.clone()));
-                self.groups.push(new_group);
+            try!(self.read_meta_entry(&entry));
+            self.meta_entries.push(entry);
+        }
+        self.index_entries();
+        Ok(())
+    }
+
+    // Forget the changes made to the previous groups and entries, e.g.
+    // because others were loaded
+    fn forget_changes(&mut self) {
+        self.undo_log.clear();
+        self.modified = false;
+    }
+
+    // Rebuild the UUID index from entries. If UUIDs are duplicated, the
+    // first entry wins as in a linear search. The groups may have been
+    // replaced as well, so the cached group lookups are dropped.
+    fn index_entries(&mut self) {
+        self.uuid_index.clear();
+        self.handles.clear_cache();
+        for entry in self.entries.iter() {
+            entry.borrow_mut().set_secret_access(Some(self.secret_access.clone()));
+            let uuid = entry.borrow().uuid;
+            if !self.uuid_index.contains_key(&uuid) {
+                self.uuid_index.insert(uuid, Rc::downgrade(entry));
             }
         }
+    }
 
-        self.header.num_groups += 1;
+    fn read_meta_entry(&mut self, entry: &Rc<RefCell<V1Entry>>) -> Result<(), V1KpdbError> {
+        let entry = entry.borrow();
+        if entry.comment.as_ref().map(|c| &c[..]) == Some(GROUP_META_STREAM) {
+            try!(decode_group_meta(entry.binary.as_ref().unwrap().bytes(), &self.groups));
+        }
+        if entry.comment.as_ref().map(|c| &c[..]) == Some(UNLOCK_POLICY_STREAM) {
+            self.unlock_policy = Some(try!(UnlockPolicy::decode(entry.binary.as_ref().unwrap().bytes())));
+        }
+        if entry.comment.as_ref().map(|c| &c[..]) == Some(EQUIVALENT_DOMAINS_STREAM) {
+            let data = entry.binary.as_ref().unwrap().bytes();
+            self.equivalent_domains = try!(EquivalentDomains::decode(data));
+        }
+        if let Some(ref name) = entry.comment {
+            try!(self.meta_info.decode_stream(name, entry.binary.as_ref().unwrap().bytes()));
+        }
         Ok(())
     }
 
-    /// Create a new entry
-    ///
-    /// * group: group which should hold the entry
-    ///
-    /// * title: title of the new entry
-    ///
-    /// * expire: expiration date of the group
-    ///           None means that the group expires never which itself
-    ///           corresponds to the date 28-12-2999 23:59:59
-    ///
-    /// * image: an image number, used in KeePass and KeePassX for the group
-    ///          icon. None means 0
-    ///
-    /// * url: URL from where the credentials are
-    ///
-    /// * comment: some free-text-comment about the entry
-    ///
-    /// * username: username for the URL
-    ///
-    /// * password: password for the URL
-    ///
-    /// Note: username and password should be of type String at creation. If you have a
-    /// &str which you convert into a String with to_string() the plaintext will remain
-    /// in memory as the new created String is a copy of the original &str. If you use
-    /// String this function call is a move so that the String remains where it was
-    /// created.
-    ///
-    pub fn create_entry(&mut self,
-                        group: Rc<RefCell<V1Group>>,
-                        title: String,
-                        expire: Option<DateTime<Local>>,
-                        image: Option<u32>,
-                        url: Option<String>,
-                        comment: Option<String>,
-                        username: Option<String>,
-                        password: Option<String>) {
-        // Automatically creates a UUID for the entry
-        let new_entry = Rc::new(RefCell::new(V1Entry::new()));
-        new_entry.borrow_mut().title = title;
-        new_entry.borrow_mut().group = Some(group.clone());
-        group.borrow_mut().entries.push(Rc::downgrade(&new_entry.clone()));
-        new_entry.borrow_mut().group_id = group.borrow().id;
-        new_entry.borrow_mut().creation = Local::now();
-        new_entry.borrow_mut().last_mod = Local::now();
-        new_entry.borrow_mut().last_access = Local::now();
-        match expire {
-            Some(s) => new_entry.borrow_mut().expire = s,
-            None => {} // is 12-28-2999 23:59:59 through V1Entry::new()
+    // Replace the meta entries with the group metadata, the unlock
+    // policy, the equivalent domains and meta_info by current ones. Meta
+    // entries must belong to an existing group, KeePass itself uses the
+    // first one.
+    fn update_meta_entries(&mut self) {
+        self.meta_entries.retain(|e| {
+            let name = e.borrow().comment.clone();
+            name.as_ref().map(|c| &c[..]) != Some(GROUP_META_STREAM) &&
+            name.as_ref().map(|c| &c[..]) != Some(UNLOCK_POLICY_STREAM) &&
+            name.as_ref().map(|c| &c[..]) != Some(EQUIVALENT_DOMAINS_STREAM) &&
+            !name.as_ref().map_or(false, |c| MetaInfo::is_known_stream(c))
+        });
+        let group_id = match self.groups.first() {
+            Some(group) => group.borrow().id,
+            None => {
+                self.meta_entries.clear();
+                return;
+            }
         };
-        match image {
-            Some(s) => new_entry.borrow_mut().image = s,
-            None => {} // is 0 through V1Entry::new()
+        let data = encode_group_meta(&self.groups);
+        if !data.is_empty() {
+            self.meta_entries.push(Rc::new(RefCell::new(new_meta_entry(GROUP_META_STREAM,
+                                                                       data,
+                                                                       group_id))));
         }
-        new_entry.borrow_mut().url = url;
-        new_entry.borrow_mut().comment = comment;
-        match username {
-            Some(s) => new_entry.borrow_mut().username = Some(SecureString::new(s)),
-            None => {}
-        };
-        match password {
-            Some(s) => new_entry.borrow_mut().password = Some(SecureString::new(s)),
-            None => {}
-        };
+        if let Some(ref policy) = self.unlock_policy {
+            self.meta_entries.push(Rc::new(RefCell::new(new_meta_entry(UNLOCK_POLICY_STREAM,
+                                                                       policy.encode(),
+                                                                       group_id))));
+        }
+        if !self.equivalent_domains.sets().is_empty() {
+            let data = self.equivalent_domains.encode();
+            self.meta_entries.push(Rc::new(RefCell::new(new_meta_entry(EQUIVALENT_DOMAINS_STREAM,
+                                                                       data,
+                                                                       group_id))));
+        }
+        for (name, data) in self.meta_info.encode() {
+            self.meta_entries.push(Rc::new(RefCell::new(new_meta_entry(name, data, group_id))));
+        }
+        for entry in &self.meta_entries {
+            entry.borrow_mut().group_id = group_id;
+        }
+    }
 
-        self.entries.push(new_entry);
-        self.header.num_entries += 1;
+    fn read_in_file(path: &str) -> Result<(Vec<u8>, Vec<u8>), V1KpdbError> {
+        V1Kpdb::read_file(path).map_err(|e| e.coarse())
     }
 
-    /// Remove a group
-    ///
-    /// * group: The group to remove
-    ///
-    /// Note: Entries and children of the group are deleted, too.
-    ///
-    /// The group should be given to the function as a move. If this is done, the rc counter
-    /// is 0 at the end of the function and therefore sensitive data is deleted correctly.
-    pub fn remove_group(&mut self, group: Rc<RefCell<V1Group>>) -> Result<(), V1KpdbError> {
-        // Sensitive data (e.g. SecureString) is automatically dropped at the end of this
-        // function as Rc is 0 then
-        try!(self.remove_group_from_db(&group));
-        try!(self.remove_entries(&group));
-        if let Some(ref parent) = group.borrow().parent {
-            try!(parent.borrow_mut().drop_weak_child_reference(&group));
-            drop(parent);
+    // Read the header into self.header, check it and decrypt the content,
+    // from a mapping if use_mmap is set. The content is returned even if
+    // it fails the checks, together with whether it passed them.
+    fn read_and_decrypt(&mut self) -> Result<(DiskState, Vec<u8>, bool), KpdbError> {
+        let mapped = if self.use_mmap {
+            MappedFile::open(&self.path).ok()
+        } else {
+            None
+        };
+        if let Some(mapped) = mapped {
+            let (decrypted_database, intact) = try!(self.decrypt_bytes(&mapped));
+            let disk_state = DiskState::new(&self.path, &mapped[..124]);
+            return Ok((disk_state, decrypted_database, intact));
         }
-        try!(self.remove_children(&group));
-        Ok(())
+
+        let (header, encrypted_database) = try!(V1Kpdb::read_file(&self.path));
+        let disk_state = DiskState::new(&self.path, &header);
+        self.header = try!(HeaderLoadParser::new(header).parse_header());
+        try!(self.check_header());
+        let (decrypted_database, intact) =
+            try!(self.crypter.decrypt_damaged_database(&self.header, encrypted_database));
+        Ok((disk_state, decrypted_database, intact))
     }
 
-    fn remove_group_from_db(&mut self, group: &Rc<RefCell<V1Group>>) -> Result<(), V1KpdbError> {
-        let index = try!(self.groups.get_index(group));
-        let db_reference = self.groups.remove(index);
-        drop(db_reference);
-        self.header.num_groups -= 1;
-        Ok(())
+    // Parse and check the header of data and derive the key up to the
+    // transformation, which the returned job runs. Public for
+    // load_async.
+    #[doc(hidden)]
+    pub fn load_job(&mut self, data: Vec<u8>) -> Result<LoadJob, V1KpdbError> {
+        if data.len() < 124 {
+            return Err(V1KpdbError::FileErr);
+        }
+        self.header = try!(V1Header::parse(&data));
+        try!(self.check_header());
+        let key = try!(self.crypter.key_job(&self.header));
+        Ok(LoadJob {
+            data: data,
+            header: self.header.clone(),
+            key: Some(key),
+            decrypted: None,
+        })
     }
 
-    fn remove_entry_from_db(&mut self, entry: &Rc<RefCell<V1Entry>>) -> Result<(), V1KpdbError> {
-        let index = try!(self.entries.get_index(entry));
-        let db_reference = self.entries.remove(index);
-        drop(db_reference);
-        self.header.num_entries -= 1;
+    // Parse what job decrypted. Public for load_async.
+    #[doc(hidden)]
+    pub fn finish_load(&mut self, mut job: LoadJob) -> Result<(), V1KpdbError> {
+        let (decrypted_database, intact) = try!(job.decrypted
+                                                    .take()
+                                                    .ok_or(V1KpdbError::DecryptErr));
+        try!(self.parse_database(decrypted_database, intact).map_err(|e| e.coarse()));
+        self.disk_state = Some(DiskState::new(&self.path, &job.data[..124]));
         Ok(())
     }
 
-    fn remove_entries(&mut self, group: &Rc<RefCell<V1Group>>) -> Result<(), V1KpdbError> {
-        // Clone needed to prevent thread panning through borrowing
-        let entries = group.borrow().entries.clone();
-        for entry in entries {
-            if let Some(entry_strong) = entry.upgrade() {
-                try!(self.remove_entry(entry_strong));
-            } else {
-                return Err(V1KpdbError::WeakErr);
-            }
+    // read_and_decrypt of a whole file in memory, e.g. a MappedFile
+    fn decrypt_bytes(&mut self, data: &[u8]) -> Result<(Vec<u8>, bool), KpdbError> {
+        if data.len() < 124 {
+            return Err(KpdbError::TooSmall);
         }
-        Ok(())
+        let (header, encrypted_database) = data.split_at(124);
+        self.header = try!(V1Header::parse(header));
+        try!(self.check_header());
+        let decrypted = try!(self.crypter
+                                 .decrypt_mapped_database(&self.header, encrypted_database));
+        Ok(decrypted)
     }
 
-    fn remove_children(&mut self, group: &Rc<RefCell<V1Group>>) -> Result<(), V1KpdbError> {
-        // Clone needed to prevent thread panning through borrowing
-        let children = group.borrow().children.clone();
-        for child in children {
-            if let Some(child_strong) = child.upgrade() {
-                try!(self.remove_group(child_strong));
-            } else {
-                return Err(V1KpdbError::WeakErr);
+    fn read_file(path: &str) -> Result<(Vec<u8>, Vec<u8>), KpdbError> {
+        let mut file = try!(File::open(path).map_err(|e| {
+            KpdbError::Io {
+                error: V1KpdbError::FileErr,
+                source: e,
             }
+        }));
+        let read_err = |e| {
+            KpdbError::Io {
+                error: V1KpdbError::ReadErr,
+                source: e,
+            }
+        };
+        let len = try!(file.metadata().map_err(&read_err)).len() as usize;
+        // Too small for the header
+        if len < 124 {
+            return Err(KpdbError::TooSmall);
         }
+        // Header and content are read into buffers of their own, so the
+        // content isn't copied
+        let mut header = vec![0u8; 124];
+        try!(file.read_exact(&mut header).map_err(&read_err));
+        let mut encrypted_database: Vec<u8> = Vec::with_capacity(len - 124);
+        try!(file.read_to_end(&mut encrypted_database).map_err(&read_err));
+        Ok((header, encrypted_database))
+    }
+
+    fn check_header(&self) -> Result<(), V1KpdbError> {
+        V1Kpdb::check_header_of(&self.header)
+    }
+
+    fn check_header_of(header: &V1Header) -> Result<(), V1KpdbError> {
+        try!(header.check_signatures());
+        try!(header.check_enc_flag());
+        try!(header.check_version());
         Ok(())
     }
 
-    /// Remove a group
-    ///
-    /// * entry: The entry to remove.
-    ///
-    /// Note: The entry should be given to the function as a move. If this is done, the rc counter
+    /// Check whether password and/or keyfile open the database at path
+    /// without parsing any groups or entries, e.g. for a login prompt.
+    /// Wrong credentials give Ok(false), errors are only returned if the
+    /// file couldn't be read or isn't a supported database.
+    pub fn verify_credentials(path: &str,
+                              password: Option<String>,
+                              keyfile: Option<String>)
+                              -> Result<bool, V1KpdbError> {
+        if password.is_none() && keyfile.is_none() {
+            return Err(V1KpdbError::PassErr);
+        }
+        let (header, encrypted_database) = try!(V1Kpdb::read_in_file(path));
+        let header = try!(HeaderLoadParser::new(header).parse_header());
+        try!(V1Kpdb::check_header_of(&header));
+
+        let mut crypter = Crypter::new(password.map(SecureString::new),
+                                       keyfile.map(SecureString::new));
+        crypter.verify_key(&header, encrypted_database)
+    }
+    
+    /// Encrypt and save the database.
+    ///
+    /// * path: where to save the database. None means the path the
+    ///         database was created with. On success path becomes the
+    ///         new path of the database
+    ///
+    /// The database is first written to a temporary file in the same
+    /// directory, synced to disk and then renamed over the target. Hence
+    /// a crash while saving never leaves a half-written database behind.
+    /// If path is a symlink, the file it points to is replaced. A read-only
+    /// file isn't replaced but gives FileErr. If keep_backup is set the
+    /// previous file is kept as <path>.bak, save_options sets how many
+    /// numbered copies are kept.
+    /// Nothing is written if a field exceeds field_limits, see
+    /// check_field_limits, nor if another process holds the lock file of
+    /// path (FileLockedErr). If the database holds its lock file, see
+    /// acquire_file_lock, the lock moves to the new path.
+    pub fn save(&mut self,
+                path: Option<String>,
+                password: Option<String>,
+                keyfile: Option<String>) -> Result<(), V1KpdbError> {
+        let start = Instant::now();
+        let scope = MemLockScope::enter(self.mem_lock_policy, 0);
+        let result = self.save_database(path);
+        let lock_failures = scope.leave();
+        self.report_usage(UsageKind::Save, start, result.is_ok(), lock_failures);
+        result
+    }
+
+    fn save_database(&mut self, path: Option<String>) -> Result<(), V1KpdbError> {
+        let lock_failures = mem_protect::lock_failures();
+        let (mut job, new_lock) = try!(self.save_job(path));
+        try!(job.encrypt());
+        if self.mem_lock_policy == MemLockPolicy::Require &&
+           mem_protect::lock_failures() != lock_failures {
+            return Err(V1KpdbError::MemLockErr);
+        }
+        try!(job.write());
+        self.finish_save(job, new_lock);
+        Ok(())
+    }
+
+    // Everything of a save before the key transformation, which the
+    // returned job runs. new_lock is the lock file of a new path, see
+    // check_file_lock. Public for save_async.
+    #[doc(hidden)]
+    pub fn save_job(&mut self,
+                    path: Option<String>)
+                    -> Result<(SaveJob, Option<FileLock>), V1KpdbError> {
+        // Seeds and IV are never re-used, every save gets fresh ones
+        let mut header = self.header.clone();
+        try!(header.regenerate_seeds());
+        let path = path.unwrap_or(self.path.clone());
+        // Before anything is changed, e.g. the meta entries
+        let new_lock = try!(self.check_file_lock(&path));
+        let job = try!(self.save_job_with(path, header, |_| ()));
+        Ok((job, new_lock))
+    }
+
+    // The file save writes, but with the seeds and IV of header instead
+    // of fresh ones. stamp is called on every meta entry, as the ones
+    // save generates get a random UUID and the current time. Public for
+    // fixtures, which need the same bytes every time.
+    #[doc(hidden)]
+    pub fn encode_with<F>(&mut self, header: V1Header, stamp: F) -> Result<Vec<u8>, V1KpdbError>
+        where F: FnMut(&mut V1Entry)
+    {
+        let path = self.path.clone();
+        let mut job = try!(self.save_job_with(path, header, stamp));
+        try!(job.encrypt());
+        let mut data = job.header_raw.clone();
+        data.extend(&job.encrypted);
+        Ok(data)
+    }
+
+    fn save_job_with<F>(&mut self,
+                        path: String,
+                        header: V1Header,
+                        mut stamp: F)
+                        -> Result<SaveJob, V1KpdbError>
+        where F: FnMut(&mut V1Entry)
+    {
+        try!(self.check_field_limits());
+        self.update_meta_entries();
+        for entry in self.meta_entries.iter() {
+            stamp(&mut entry.borrow_mut());
+        }
+        let mut parser = SaveParser::new();
+        parser.prepare(self);
+        log_trace!("serialized {} groups and {} entries into {} bytes",
+                   self.groups.len(),
+                   self.entries.len() + self.meta_entries.len(),
+                   parser.database.len());
+        let mut job = SaveJob {
+            path: path,
+            header: header,
+            header_raw: vec![],
+            plaintext: parser.database,
+            encrypted: vec![],
+            key: None,
+            keep_backup: self.keep_backup,
+            save_options: self.save_options.clone(),
+        };
+
+        job.header.num_groups = self.groups.len() as u32;
+        job.header.num_entries = (self.entries.len() + self.meta_entries.len()) as u32;
+        job.header.content_hash = try!(Crypter::get_content_hash(&job.plaintext));
+        job.key = Some(try!(self.crypter.key_job(&job.header)));
+        let mut header_parser = HeaderSaveParser::new(job.header.clone());
+        job.header_raw = header_parser.parse_header();
+        Ok(job)
+    }
+
+    // Take over the state of the database written by job. Public for
+    // save_async.
+    #[doc(hidden)]
+    pub fn finish_save(&mut self, job: SaveJob, new_lock: Option<FileLock>) {
+        if new_lock.is_some() {
+            self.file_lock = new_lock;
+        }
+        self.disk_state = Some(DiskState::new(&job.path, &job.header_raw));
+        self.header = job.header.clone();
+        self.path = job.path.clone();
+        self.modified = false;
+        for group in self.groups.iter() {
+            group.borrow_mut().set_dirty(false);
+        }
+        for entry in self.entries.iter() {
+            entry.borrow_mut().set_dirty(false);
+        }
+    }
+
+    /// Check if the file of the database was changed since it was loaded
+    /// or saved, e.g. by a sync client like Dropbox or Syncthing. If the
+    /// modification time differs, the header tells if the content really
+    /// changed, as it gets a new random seed on every save. If the
+    /// database was neither loaded nor saved, an existing file counts as
+    /// changed.
+    pub fn has_changed_on_disk(&self) -> Result<bool, V1KpdbError> {
+        let state = match self.disk_state {
+            Some(ref state) => state,
+            None => return Ok(fs::metadata(&self.path).is_ok()),
+        };
+        let metadata = try!(fs::metadata(&self.path).map_err(|_| V1KpdbError::FileErr));
+        let modified = metadata.modified().ok();
+        if modified.is_some() && modified == state.modified {
+            return Ok(false);
+        }
+        let mut header = vec![0u8; 124];
+        let mut file = try!(File::open(&self.path).map_err(|_| V1KpdbError::FileErr));
+        if file.read_exact(&mut header).is_err() {
+            // Too small for a database, so it was truncated
+            return Ok(true);
+        }
+        Ok(header_hash(&header) != state.header_hash)
+    }
+
+    /// Read the database from disk again, e.g. after has_changed_on_disk.
+    /// Unsaved changes are lost. key replaces the key first, e.g. if the
+    /// password was changed on another machine. On error the database
+    /// and its key stay as they were.
+    pub fn reload(&mut self, key: Option<CompositeKey>) -> Result<(), V1KpdbError> {
+        let disk = try!(self.load_copy(key));
+        self.replace_with(disk);
+        Ok(())
+    }
+
+    /// Like reload but the unsaved changes are merged into the database
+    /// read from disk, see merge. Entries which were only changed in
+    /// memory since the last load or save are kept without asking
+    /// strategy. Groups and entries deleted in memory come back if
+    /// they're still on disk. Save afterwards to write the merged
+    /// database.
+    pub fn reload_merged<R: ConflictResolver>(&mut self,
+                                              key: Option<CompositeKey>,
+                                              strategy: &mut R)
+                                              -> Result<MergeReport, V1KpdbError> {
+        let start = Instant::now();
+        let mut disk = try!(self.load_copy(key));
+        let last_sync = self.disk_state.as_ref().map(|state| state.synced);
+        let result = disk.merge(self, strategy, last_sync);
+        if result.is_ok() {
+            self.replace_with(disk);
+        }
+        self.report_usage(UsageKind::Merge, start, result.is_ok(), 0);
+        result
+    }
+
+    // Load the file into a new database with the key of this one. The
+    // crypter is lent, so settings like the key provider apply.
+    fn load_copy(&mut self, key: Option<CompositeKey>) -> Result<V1Kpdb, V1KpdbError> {
+        let old_key = match key {
+            Some(ref key) if !key.is_valid() => return Err(V1KpdbError::PassErr),
+            Some(key) => Some(self.crypter.replace_key(key)),
+            None => None,
+        };
+        let mut disk = V1Kpdb::with_crypter(self.path.clone(),
+                                            Crypter::with_key(CompositeKey::new()));
+        mem::swap(&mut disk.crypter, &mut self.crypter);
+        let result = disk.load();
+        mem::swap(&mut disk.crypter, &mut self.crypter);
+        if let Err(e) = result {
+            if let Some(old_key) = old_key {
+                self.crypter.set_key(old_key);
+            }
+            return Err(e);
+        }
+        Ok(disk)
+    }
+
+    // Take over the data read from disk. Settings like keep_backup and
+    // the lock file are kept.
+    // The previous data ends up in disk and is wiped when it's dropped.
+    fn replace_with(&mut self, mut disk: V1Kpdb) {
+        self.reveals.wipe_all();
+        mem::swap(&mut self.header, &mut disk.header);
+        mem::swap(&mut self.groups, &mut disk.groups);
+        mem::swap(&mut self.entries, &mut disk.entries);
+        mem::swap(&mut self.meta_entries, &mut disk.meta_entries);
+        mem::swap(&mut self.meta_info, &mut disk.meta_info);
+        mem::swap(&mut self.root_group, &mut disk.root_group);
+        mem::swap(&mut self.unlock_policy, &mut disk.unlock_policy);
+        mem::swap(&mut self.equivalent_domains, &mut disk.equivalent_domains);
+        mem::swap(&mut self.disk_state, &mut disk.disk_state);
+        self.forget_changes();
+        self.index_entries();
+    }
+
+    // FileLockedErr if someone else holds the lock file of path. If the
+    // database holds a lock of another path, the lock of path is taken
+    // and returned.
+    fn check_file_lock(&self, path: &str) -> Result<Option<FileLock>, V1KpdbError> {
+        match self.file_lock {
+            Some(ref lock) if lock.path() == path => {
+                if lock.is_held() {
+                    Ok(None)
+                } else {
+                    Err(V1KpdbError::FileLockedErr)
+                }
+            }
+            Some(_) => FileLock::acquire(path).map(Some),
+            None => {
+                match inspect(path) {
+                    Ok(None) => Ok(None),
+                    Ok(Some(_)) | Err(V1KpdbError::LockErr) => Err(V1KpdbError::FileLockedErr),
+                    Err(e) => Err(e),
+                }
+            }
+        }
+    }
+
+    /// Take the lock file of the database, see FileLock, until
+    /// release_file_lock or the database is dropped. FileLockedErr if
+    /// another process holds it, lockfile::inspect tells who. With force
+    /// the lock is taken over anyway.
+    pub fn acquire_file_lock(&mut self, force: bool) -> Result<(), V1KpdbError> {
+        if let Some(ref lock) = self.file_lock {
+            if lock.path() == self.path && lock.is_held() {
+                return Ok(());
+            }
+        }
+        // Drop a lock of an old path first
+        self.file_lock = None;
+        let lock = if force {
+            try!(FileLock::force(&self.path))
+        } else {
+            try!(FileLock::acquire(&self.path))
+        };
+        self.file_lock = Some(lock);
+        Ok(())
+    }
+
+    /// Remove the lock file taken by acquire_file_lock
+    pub fn release_file_lock(&mut self) {
+        self.file_lock = None;
+    }
+
+    /// The lock file held by the database, if any
+    pub fn file_lock(&self) -> Option<&FileLock> {
+        self.file_lock.as_ref()
+    }
+
+    /// Check all groups and entries against field_limits. Returns
+    /// FieldLengthErr if a field is too long. Titles and usernames are
+    /// checked by the setters, here they only need to fit into the
+    /// format, see FieldLimits::for_save. Longer ones than field_limits
+    /// allows are logged.
+    pub fn check_field_limits(&self) -> Result<(), V1KpdbError> {
+        let limits = self.field_limits.for_save();
+        let mut too_long = 0;
+        for group in self.groups.iter() {
+            let group = group.borrow();
+            try!(limits.check_group(&group));
+            if group.title.len() > self.field_limits.title {
+                too_long += 1;
+            }
+        }
+        for entry in self.entries.iter() {
+            let entry = entry.borrow();
+            try!(limits.check_entry(&entry));
+            if entry.title.len() > self.field_limits.title {
+                too_long += 1;
+            }
+            if entry.username.as_ref().map_or(0, |u| u.string.len()) > self.field_limits.username {
+                too_long += 1;
+            }
+        }
+        if too_long > 0 {
+            log_warn!("{} titles and usernames are longer than the field limits", too_long);
+        }
+        Ok(())
+    }
+
+    /// Check the invariants of the group tree, e.g. after a lenient load
+    /// or a merge, see conformance::check_tree. An empty result means the
+    /// database can be saved and loaded again as it is.
+    pub fn validate(&self) -> Vec<Violation> {
+        check_tree(self)
+    }
+
+    /// Fix what validate finds and return what was fixed:
+    ///
+    /// * groups sharing an id and entries sharing a UUID get new ones,
+    ///   except for the first of them
+    ///
+    /// * references to dropped or foreign children and entries are
+    ///   removed and groups without a parent are moved to the top level
+    ///
+    /// * levels and the order of the groups are rebuilt from the parent
+    ///   links
+    ///
+    /// * entries without a group are moved into a new group titled
+    ///   recovery::RECOVERED_GROUP_TITLE
+    pub fn repair(&mut self) -> Result<Vec<Violation>, V1KpdbError> {
+        self.batch(move |db| db.repair_unrecorded())
+    }
+
+    fn repair_unrecorded(&mut self) -> Result<Vec<Violation>, V1KpdbError> {
+        let violations = self.validate();
+        if violations.is_empty() {
+            return Ok(violations);
+        }
+
+        let mut ids = HashSet::new();
+        for group in self.groups.clone() {
+            let id = group.borrow().id;
+            if ids.insert(id) {
+                continue;
+            }
+            let new_id = self.groups.iter().map(|g| g.borrow().id).max().unwrap_or(0) + 1;
+            ids.insert(new_id);
+            group.borrow_mut().id = new_id;
+            for entry in group.borrow().entries.iter().filter_map(|e| e.upgrade()) {
+                if entry.borrow().group.as_ref().map_or(false, |g| Rc::ptr_eq(g, &group)) {
+                    entry.borrow_mut().group_id = new_id;
+                }
+            }
+        }
+        let mut uuids = HashSet::new();
+        for entry in self.entries.iter() {
+            let uuid = entry.borrow().uuid;
+            if !uuids.insert(uuid) {
+                entry.borrow_mut().uuid = Uuid::new_v4();
+            }
+        }
+
+        // Drop the references which don't point back, then link every
+        // group and entry to its parent and group respectively
+        let groups: Vec<Rc<RefCell<V1Group>>> =
+            Some(self.root_group.clone()).into_iter().chain(self.groups.iter().cloned()).collect();
+        for group in groups.iter() {
+            let mut g = group.borrow_mut();
+            g.children.retain(|child| {
+                child.upgrade().map_or(false, |child| {
+                    child.borrow().parent.as_ref().map_or(false, |p| Rc::ptr_eq(p, group))
+                })
+            });
+            g.entries.retain(|entry| {
+                entry.upgrade().map_or(false, |entry| {
+                    entry.borrow().group.as_ref().map_or(false, |e| Rc::ptr_eq(e, group))
+                })
+            });
+        }
+        for group in self.groups.iter() {
+            let parent = match group.borrow().parent {
+                Some(ref parent) if groups.iter().any(|g| Rc::ptr_eq(g, parent)) => {
+                    parent.clone()
+                }
+                _ => self.root_group.clone(),
+            };
+            group.borrow_mut().parent = Some(parent.clone());
+            let linked = parent.borrow()
+                               .children
+                               .iter()
+                               .any(|c| c.upgrade().map_or(false, |c| Rc::ptr_eq(&c, group)));
+            if !linked {
+                parent.borrow_mut().children.push(Rc::downgrade(group));
+            }
+        }
+        try!(self.rebuild_group_order());
+
+        let mut orphans = vec![];
+        for entry in self.entries.iter() {
+            let group = {
+                let e = entry.borrow();
+                let linked = e.group.as_ref().and_then(|g| {
+                    self.groups.iter().find(|other| Rc::ptr_eq(other, g)).cloned()
+                });
+                linked.or_else(|| {
+                    self.groups.iter().find(|g| g.borrow().id == e.group_id).cloned()
+                })
+            };
+            let group = match group {
+                Some(group) => group,
+                None => {
+                    entry.borrow_mut().group = None;
+                    orphans.push(entry.clone());
+                    continue;
+                }
+            };
+            entry.borrow_mut().group_id = group.borrow().id;
+            entry.borrow_mut().group = Some(group.clone());
+            let linked = group.borrow()
+                              .entries
+                              .iter()
+                              .any(|e| e.upgrade().map_or(false, |e| Rc::ptr_eq(&e, entry)));
+            if !linked {
+                group.borrow_mut().entries.push(Rc::downgrade(entry));
+            }
+        }
+        if !orphans.is_empty() {
+            try!(self.adopt_entries(orphans));
+        }
+
+        self.header.num_groups = self.groups.len() as u32;
+        self.index_entries();
+        self.touch();
+        Ok(violations)
+    }
+
+    // Order the groups depth-first from the root and set their levels
+    // accordingly. Groups which aren't reached from the root, i.e. are
+    // part of a cycle, are moved to the top level.
+    fn rebuild_group_order(&mut self) -> Result<(), V1KpdbError> {
+        let mut ordered: Vec<Rc<RefCell<V1Group>>> = vec![];
+        loop {
+            let mut pending: Vec<(Rc<RefCell<V1Group>>, u16)> =
+                self.root_group
+                    .borrow()
+                    .children
+                    .iter()
+                    .rev()
+                    .filter_map(|c| c.upgrade())
+                    .map(|c| (c, 0))
+                    .collect();
+            ordered.clear();
+            while let Some((group, level)) = pending.pop() {
+                // Skip groups seen before and groups which were removed
+                if ordered.iter().any(|g| Rc::ptr_eq(g, &group)) ||
+                   !self.groups.iter().any(|g| Rc::ptr_eq(g, &group)) {
+                    continue;
+                }
+                group.borrow_mut().level = level;
+                for child in group.borrow().children.iter().rev().filter_map(|c| c.upgrade()) {
+                    pending.push((child, level + 1));
+                }
+                ordered.push(group);
+            }
+            let unreached = self.groups
+                                .iter()
+                                .find(|g| !ordered.iter().any(|o| Rc::ptr_eq(o, g)))
+                                .cloned();
+            match unreached {
+                Some(group) => {
+                    let parent = group.borrow_mut().parent.take();
+                    if let Some(parent) = parent {
+                        try!(parent.borrow_mut().drop_weak_child_reference(&group));
+                    }
+                    group.borrow_mut().parent = Some(self.root_group.clone());
+                    self.root_group.borrow_mut().children.push(Rc::downgrade(&group));
+                }
+                None => break,
+            }
+        }
+        self.groups = ordered;
+        Ok(())
+    }
+
+    // Public for load_async and save_async
+    #[doc(hidden)]
+    pub fn report_usage(&mut self,
+                        kind: UsageKind,
+                        start: Instant,
+                        success: bool,
+                        lock_failures: usize) {
+        log_debug!("{:?} {} after {:?}: {} groups, {} entries, {} buffers not locked",
+                   kind,
+                   if success { "succeeded" } else { "failed" },
+                   start.elapsed(),
+                   self.groups.len(),
+                   self.entries.len(),
+                   lock_failures);
+        if let Some(ref mut sink) = self.usage_sink {
+            sink.record(&UsageEvent {
+                kind: kind,
+                duration: start.elapsed(),
+                success: success,
+                num_groups: self.groups.len(),
+                num_entries: self.entries.len(),
+                lock_failures: lock_failures,
+            });
+        }
+    }
+
+    // Best effort, the platform may not support every step
+    fn harden(&self) {
+        if self.harden_process {
+            mem_protect::harden_process();
+        }
+    }
+
+    // Under MemLockPolicy::Require a load which left secrets unlocked
+    // fails and drops them again
+    // See mem_protect::lock_budget, 0 if path can't be read
+    fn load_budget(&self) -> u64 {
+        fs::metadata(&self.path).map(|m| mem_protect::lock_budget(m.len())).unwrap_or(0)
+    }
+
+    fn check_mem_locks(&mut self, lock_failures: usize) -> Result<(), V1KpdbError> {
+        if lock_failures > 0 && self.mem_lock_policy == MemLockPolicy::Require {
+            log_debug!("{} buffers couldn't be locked, closing the database", lock_failures);
+            self.close(false);
+            return Err(V1KpdbError::MemLockErr);
+        }
+        Ok(())
+    }
+
+    // Write header and content to a new temporary file next to the file
+    // path points to, sync it and rename it over that file, so a symlink
+    // stays a symlink. The temporary file gets the permissions of the
+    // file. FileErr if it's read-only, rename would replace it anyway. On
+    // error the temporary file is removed and path is untouched.
+    fn write_atomically(path: &str,
+                        header_raw: &[u8],
+                        encrypted_database: &[u8],
+                        keep_backup: bool,
+                        save_options: &SaveOptions)
+                        -> Result<(), V1KpdbError> {
+        let target = match fs::canonicalize(path) {
+            Ok(target) => try!(target.to_str().map(|t| t.to_string()).ok_or(V1KpdbError::FileErr)),
+            Err(_) => path.to_string(),
+        };
+        let metadata = fs::metadata(&target).ok();
+        if metadata.as_ref().map_or(false, |m| m.permissions().readonly()) {
+            return Err(V1KpdbError::FileErr);
+        }
+        let (tmp_path, file) = try!(V1Kpdb::create_tmp_file(&target));
+        let mut result = V1Kpdb::write_synced(file, header_raw, encrypted_database);
+        if result.is_ok() {
+            if let Some(metadata) = metadata {
+                result = fs::set_permissions(&tmp_path, metadata.permissions())
+                             .map_err(|_| V1KpdbError::WriteErr);
+            }
+        }
+        if result.is_err() {
+            let _ = fs::remove_file(&tmp_path);
+            return result;
+        }
+
+        if keep_backup && fs::metadata(path).is_ok() {
+            let backup_path = format!("{}.bak", path);
+            if fs::copy(path, &backup_path).is_err() {
+                let _ = fs::remove_file(&tmp_path);
+                return Err(V1KpdbError::WriteErr);
+            }
+        }
+        if let Err(e) = save_options.rotate(path) {
+            let _ = fs::remove_file(&tmp_path);
+            return Err(e);
+        }
+
+        if fs::rename(&tmp_path, &target).is_err() {
+            let _ = fs::remove_file(&tmp_path);
+            return Err(V1KpdbError::WriteErr);
+        }
+
+        // Sync the directory, too, so that the rename itself is durable.
+        // Not every platform allows opening a directory, hence ignore errors
+        let dir = match Path::new(&target).parent() {
+            Some(d) if d.as_os_str().len() > 0 => d.to_path_buf(),
+            _ => Path::new(".").to_path_buf(),
+        };
+        if let Ok(dir_file) = File::open(&dir) {
+            let _ = dir_file.sync_all();
+        }
+        Ok(())
+    }
+
+    // Create <path>.<random>.tmp, which mustn't exist yet, so concurrent
+    // saves and other files are never overwritten. On Unix only the owner
+    // may read it until write_atomically copies the permissions of path.
+    fn create_tmp_file(path: &str) -> Result<(String, File), V1KpdbError> {
+        let mut options = OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::OpenOptionsExt;
+            options.mode(0o600);
+        }
+        for _ in 0..16 {
+            let tmp_path = format!("{}.{:08x}.tmp", path, rand::random::<u32>());
+            match options.open(&tmp_path) {
+                Ok(file) => return Ok((tmp_path, file)),
+                Err(ref e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+                Err(_) => return Err(V1KpdbError::FileErr),
+            }
+        }
+        Err(V1KpdbError::FileErr)
+    }
+
+    fn write_synced(mut file: File,
+                    header_raw: &[u8],
+                    encrypted_database: &[u8])
+                    -> Result<(), V1KpdbError> {
+        try!(file.write_all(header_raw).map_err(|_| V1KpdbError::WriteErr));
+        try!(file.write_all(encrypted_database).map_err(|_| V1KpdbError::WriteErr));
+        try!(file.flush().map_err(|_| V1KpdbError::WriteErr));
+        try!(file.sync_all().map_err(|_| V1KpdbError::WriteErr));
+        Ok(())
+    }
+
+    /// Change the master key of the database
+    ///
+    /// * new_password: the new password. None means that no password is used
+    ///
+    /// * new_keyfile: path of the new keyfile. None means that no keyfile is used
+    ///
+    /// At least one of both is needed. The seed for the key transformation is
+    /// regenerated. The database is re-encrypted with the new key on the next
+    /// save which also creates a fresh IV, final seed and content hash.
+    pub fn set_credentials(&mut self,
+                           new_password: Option<SecureString>,
+                           new_keyfile: Option<SecureString>)
+                           -> Result<(), V1KpdbError> {
+        if new_password.is_none() && new_keyfile.is_none() {
+            return Err(V1KpdbError::PassErr);
+        }
+        if let Some(ref policy) = self.unlock_policy {
+            let mut factors = vec![];
+            if new_password.is_some() {
+                factors.push(KeyFactor::Password);
+            }
+            if new_keyfile.is_some() {
+                factors.push(KeyFactor::Keyfile);
+            }
+            try!(policy.check(&factors));
+        }
+        self.crypter.set_credentials(new_password, new_keyfile);
+        self.header.transf_randomseed = (0..32).map(|_| rand::random::<u8>()).collect();
+        self.touch();
+        Ok(())
+    }
+
+    /// Replace the whole master key, see set_credentials
+    pub fn set_key(&mut self, key: CompositeKey) -> Result<(), V1KpdbError> {
+        if !key.is_valid() {
+            return Err(V1KpdbError::PassErr);
+        }
+        if let Some(ref policy) = self.unlock_policy {
+            try!(policy.check(&key.factors()));
+        }
+        self.crypter.set_key(key);
+        self.header.transf_randomseed = (0..32).map(|_| rand::random::<u8>()).collect();
+        self.touch();
+        Ok(())
+    }
+
+    /// Change the cipher and/or the rounds of the key transformation as
+    /// options says and save the database to its path right away, with
+    /// fresh seeds and IV like every save. Nothing is changed if the
+    /// save fails. EncFlagErr for Twofish, which isn't supported yet,
+    /// RoundsErr for 0 rounds.
+    pub fn reencrypt(&mut self, options: ReencryptOptions) -> Result<(), V1KpdbError> {
+        if options.cipher.map_or(false, |c| c != Cipher::Aes) {
+            return Err(V1KpdbError::EncFlagErr);
+        }
+        let previous = self.header.clone();
+        if let Some(cipher) = options.cipher {
+            self.header.enc_flag = cipher.enc_flag();
+        }
+        let rounds = match (options.rounds, options.calibrate) {
+            (Some(rounds), _) => Some(rounds),
+            (None, Some(target)) => Some(Crypter::benchmark_rounds(target)),
+            (None, None) => None,
+        };
+        if let Some(rounds) = rounds {
+            if let Err(e) = self.header.set_key_transf_rounds(rounds) {
+                self.header = previous;
+                return Err(e);
+            }
+        }
+        let result = self.save(None, None, None);
+        if result.is_err() {
+            self.header = previous;
+        }
+        result
+    }
+
+    // The current key as kpdb::credentials stores it. Public for
+    // KeyCache.
+    #[doc(hidden)]
+    pub fn cache_key(&mut self, master_key: bool) -> Result<SecureBytes, V1KpdbError> {
+        self.crypter.cache_key(master_key)
+    }
+
+    /// Record which factors are needed to open the database. The
+    /// current key has to satisfy the policy, otherwise and for
+    /// policies which can't be enforced PolicyErr is returned. Later
+    /// calls of set_key and set_credentials are checked against it. The
+    /// policy is saved with the database, None removes it.
+    pub fn set_unlock_policy(&mut self, policy: Option<UnlockPolicy>) -> Result<(), V1KpdbError> {
+        if let Some(ref policy) = policy {
+            try!(policy.check(&self.crypter.key_factors()));
+        }
+        self.unlock_policy = policy;
+        self.touch();
+        Ok(())
+    }
+
+    /// The unlock policy of the database, available after load
+    pub fn unlock_policy(&self) -> Option<&UnlockPolicy> {
+        self.unlock_policy.as_ref()
+    }
+
+    /// The custom icons of the database as PNG images. Groups and
+    /// entries refer to them by their index.
+    pub fn custom_icons(&self) -> &[Vec<u8>] {
+        &self.meta_info.custom_icons
+    }
+
+    /// Add a PNG image as custom icon and return its index. An icon
+    /// which is there already isn't added twice. IconErr if png isn't a
+    /// PNG image.
+    pub fn add_custom_icon(&mut self, png: Vec<u8>) -> Result<u32, V1KpdbError> {
+        self.batch(move |db| db.add_custom_icon_unrecorded(png))
+    }
+
+    fn add_custom_icon_unrecorded(&mut self, png: Vec<u8>) -> Result<u32, V1KpdbError> {
+        let index = try!(self.meta_info.add_icon(png));
+        self.touch();
+        Ok(index)
+    }
+
+    /// Remove the custom icon at index and return it. Groups and entries
+    /// which used it show their standard image again, the indices of the
+    /// following icons shift down by one.
+    pub fn remove_custom_icon(&mut self, index: u32) -> Result<Vec<u8>, V1KpdbError> {
+        self.batch(move |db| db.remove_custom_icon_unrecorded(index))
+    }
+
+    fn remove_custom_icon_unrecorded(&mut self, index: u32) -> Result<Vec<u8>, V1KpdbError> {
+        let png = try!(self.meta_info.remove_icon(index));
+        self.touch();
+        Ok(png)
+    }
+
+    /// Show the custom icon at index for group instead of its image. None
+    /// removes the custom icon. IndexErr if there's no such icon.
+    pub fn set_group_custom_icon(&mut self,
+                                 group: &V1Group,
+                                 icon: Option<u32>)
+                                 -> Result<(), V1KpdbError> {
+        self.batch(move |db| db.set_group_custom_icon_unrecorded(group, icon))
+    }
+
+    fn set_group_custom_icon_unrecorded(&mut self,
+                                        group: &V1Group,
+                                        icon: Option<u32>)
+                                        -> Result<(), V1KpdbError> {
+        match icon {
+            Some(index) => {
+                try!(self.check_icon_index(index));
+                self.meta_info.group_icons.insert(group.id, index);
+            }
+            None => {
+                self.meta_info.group_icons.remove(&group.id);
+            }
+        }
+        self.touch();
+        Ok(())
+    }
+
+    /// Index of the custom icon of group
+    pub fn group_custom_icon(&self, group: &V1Group) -> Option<u32> {
+        self.meta_info.group_icons.get(&group.id).cloned()
+    }
+
+    /// Show the custom icon at index for entry instead of its image. None
+    /// removes the custom icon. IndexErr if there's no such icon.
+    pub fn set_entry_custom_icon(&mut self,
+                                 entry: &V1Entry,
+                                 icon: Option<u32>)
+                                 -> Result<(), V1KpdbError> {
+        self.batch(move |db| db.set_entry_custom_icon_unrecorded(entry, icon))
+    }
+
+    fn set_entry_custom_icon_unrecorded(&mut self,
+                                        entry: &V1Entry,
+                                        icon: Option<u32>)
+                                        -> Result<(), V1KpdbError> {
+        match icon {
+            Some(index) => {
+                try!(self.check_icon_index(index));
+                self.meta_info.entry_icons.insert(entry.uuid, index);
+            }
+            None => {
+                self.meta_info.entry_icons.remove(&entry.uuid);
+            }
+        }
+        self.touch();
+        Ok(())
+    }
+
+    /// Index of the custom icon of entry
+    pub fn entry_custom_icon(&self, entry: &V1Entry) -> Option<u32> {
+        self.meta_info.entry_icons.get(&entry.uuid).cloned()
+    }
+
+    fn check_icon_index(&self, index: u32) -> Result<(), V1KpdbError> {
+        if index as usize >= self.meta_info.custom_icons.len() {
+            return Err(V1KpdbError::IndexErr);
+        }
+        Ok(())
+    }
+
+    /// Add another factor to the master key, e.g. a Fido2KeyProvider.
+    /// This replaces all challenge-response components of the key, None
+    /// removes them. The provider is asked for its key on every load and
+    /// save.
+    pub fn set_key_provider(&mut self, key_provider: Option<Box<KeyProvider>>) {
+        self.crypter.set_key_provider(key_provider);
+    }
+
+    /// Set a callback which is called with the done and total number of
+    /// key transformation rounds while the database is loaded or saved,
+    /// e.g. to drive a progress bar. None removes it again.
+    pub fn set_progress_callback(&mut self, progress: Option<Box<FnMut(u32, u32)>>) {
+        self.crypter.set_progress(progress);
+    }
+
+    /// Set a token to abort a pending load or save during the key
+    /// transformation. The aborted call returns CancelledErr. Note that
+    /// the token stays cancelled, use a new one for the next attempt.
+    pub fn set_cancel_token(&mut self, cancel_token: Option<CancelToken>) {
+        self.crypter.set_cancel_token(cancel_token);
+    }
+
+    /// Re-encrypt all protected values in memory, i.e. usernames and
+    /// passwords of the entries and the master password, with fresh random
+    /// keys and overwrite the old keys. This limits the use of a memory
+    /// snapshot to the time until the next rotation. The database file
+    /// isn't touched.
+    pub fn rotate_protection_keys(&mut self) {
+        for entry in self.entries.iter().chain(self.meta_entries.iter()) {
+            let mut entry = entry.borrow_mut();
+            if let Some(ref mut username) = entry.username {
+                username.rekey();
+            }
+            if let Some(password) = entry.raw_password_mut() {
+                password.rekey();
+            }
+        }
+        self.crypter.rekey();
+        self.last_key_rotation = Instant::now();
+    }
+
+    /// Call rotate_protection_keys if key_rotation_interval has passed
+    /// since the last rotation. Long-running applications should call
+    /// this regularly, e.g. from their event loop. Returns true if the
+    /// keys were rotated.
+    pub fn rotate_protection_keys_if_due(&mut self) -> bool {
+        match self.key_rotation_interval {
+            Some(interval) if self.last_key_rotation.elapsed() >= interval => {
+                self.rotate_protection_keys();
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Call callback with the UUID of the entry and the time whenever the
+    /// password of an entry is read through V1Entry::password, reveal or
+    /// V1Entry::password_history, e.g. to log access to secrets or to
+    /// throttle bulk reads. Exports, auto-type and placeholders read
+    /// passwords that way as well. Reads which don't hand the password
+    /// out, like saving, audits and duplicate detection, aren't reported.
+    /// A later call replaces callback.
+    ///
+    /// The database hands the hook to the entries it loads or creates.
+    /// Entries pushed to entries directly get it with the next load or
+    /// undo.
+    pub fn on_secret_access<F>(&mut self, callback: F)
+        where F: FnMut(&Uuid, DateTime<Local>) + 'static
+    {
+        self.secret_access.set_callback(Some(Box::new(callback)));
+    }
+
+    /// Stop reporting password reads, see on_secret_access
+    pub fn clear_secret_access(&mut self) {
+        self.secret_access.set_callback(None);
+    }
+
+    /// Like V1Entry::reveal but the guard is tracked, so it can be wiped
+    /// with wipe_reveals. ttl is capped to max_reveal_ttl.
+    pub fn reveal(&mut self,
+                  entry: &Rc<RefCell<V1Entry>>,
+                  field: ProtectedField,
+                  ttl: Duration)
+                  -> Option<Revealed> {
+        let ttl = match self.max_reveal_ttl {
+            Some(max) if max < ttl => max,
+            _ => ttl,
+        };
+        let revealed = entry.borrow_mut().reveal(field, ttl);
+        if let Some(ref revealed) = revealed {
+            self.reveals.track(revealed);
+        }
+        revealed
+    }
+
+    /// Number of guards handed out by reveal which still hold plain text
+    pub fn outstanding_reveals(&mut self) -> usize {
+        self.reveals.outstanding()
+    }
+
+    /// Wipe the plain text of the guards whose time to live has passed.
+    /// Call this regularly, e.g. from the event loop, to bound the time
+    /// plain text stays in memory. Returns the number of wiped guards.
+    pub fn wipe_expired_reveals(&mut self) -> usize {
+        self.reveals.wipe_expired()
+    }
+
+    /// Wipe the plain text of all guards handed out by reveal, e.g. when
+    /// the application locks. Returns the number of wiped guards.
+    pub fn wipe_reveals(&mut self) -> usize {
+        self.reveals.wipe_all()
+    }
+
+    /// Drop all groups and entries and wipe the guards handed out by
+    /// reveal, e.g. to lock the application. Entries which aren't
+    /// referenced elsewhere are zeroed out. Path, header and settings
+    /// are kept, so load opens the database again. The key is kept
+    /// re-encrypted with fresh keys, with forget_key it's dropped and
+    /// set_key is needed before load. Unsaved changes are lost. See
+    /// LockedKpdb for a type which enforces this state.
+    pub fn close(&mut self, forget_key: bool) {
+        self.reveals.wipe_all();
+        self.groups.clear();
+        self.entries.clear();
+        self.meta_entries.clear();
+        self.meta_info = MetaInfo::new();
+        self.root_group = Rc::new(RefCell::new(V1Group::new()));
+        self.unlock_policy = None;
+        self.equivalent_domains = EquivalentDomains::new();
+        self.forget_changes();
+        self.index_entries();
+        if forget_key {
+            self.crypter.set_key(CompositeKey::new());
+        } else {
+            self.crypter.rekey();
+        }
+        self.last_key_rotation = Instant::now();
+    }
+
+    /// Wrap the database in an AutoLock which locks it after timeout
+    /// without use
+    pub fn auto_lock_after(self, timeout: Duration) -> AutoLock {
+        AutoLock::new(self, Some(timeout))
+    }
+
+    /// Replace the groups and entries with the ones of tree, e.g. a
+    /// fixture deserialized from JSON. Meta entries are kept. TreeErr if
+    /// the levels of the groups don't form a tree, nothing is changed
+    /// then.
+    #[cfg(feature = "serde")]
+    pub fn set_tree(&mut self, tree: FlatTree) -> Result<(), V1KpdbError> {
+        self.transaction(move |db| db.set_tree_unrecorded(tree))
+    }
+
+    #[cfg(feature = "serde")]
+    fn set_tree_unrecorded(&mut self, tree: FlatTree) -> Result<(), V1KpdbError> {
+        let levels = tree.groups.iter().map(|g| g.level).collect();
+        self.groups = tree.groups.into_iter().map(|g| Rc::new(RefCell::new(g))).collect();
+        self.entries = tree.entries.into_iter().map(|e| Rc::new(RefCell::new(e))).collect();
+        self.index_entries();
+        self.root_group = Rc::new(RefCell::new(V1Group::new()));
+        try!(LoadParser::create_group_tree(self, levels));
+        self.header.num_groups = self.groups.len() as u32;
+        self.header.num_entries = (self.entries.len() + self.meta_entries.len()) as u32;
+        self.touch();
+        Ok(())
+    }
+
+    /// Write all entries in the XML format of KeePass 1.x, which many
+    /// conversion tools read. Groups without entries aren't part of the
+    /// format. Returns the number of entries written.
+    ///
+    /// Note that the output holds the passwords in plain text.
+    pub fn export_xml<W: Write>(&self, writer: &mut W) -> Result<usize, V1KpdbError> {
+        xml::write_entries(self, writer).map_err(|_| V1KpdbError::WriteErr)
+    }
+
+    /// Add the entries of an XML export of KeePass 1.x, creating their
+    /// groups on the way. UUIDs, icons and times are kept, an entry gets
+    /// a new UUID if the database already holds its UUID. Returns the
+    /// number of imported entries. The data read is overwritten with
+    /// zeroes afterwards. Nothing is imported on error.
+    pub fn import_xml<R: Read>(&mut self, reader: R) -> Result<usize, V1KpdbError> {
+        self.transaction(move |db| db.import_xml_unrecorded(reader))
+    }
+
+    fn import_xml_unrecorded<R: Read>(&mut self, mut reader: R) -> Result<usize, V1KpdbError> {
+        let mut text = String::new();
+        let result = match reader.read_to_string(&mut text) {
+            Ok(_) => xml::parse(&text),
+            Err(_) => Err(V1KpdbError::ReadErr),
+        };
+        unsafe {
+            mem_protect::zero(&text);
+        }
+        let mut imported = try!(result);
+
+        for xml_entry in imported.iter_mut() {
+            if xml_entry.group_path.is_empty() {
+                xml_entry.group_path.push("Import".to_string());
+            }
+            let group = try!(create_groups(self, &xml_entry.group_path));
+            let entry = self.create_entry(group,
+                                          xml_entry.title.clone(),
+                                          xml_entry.expire,
+                                          Some(xml_entry.image),
+                                          xml_entry.url.take(),
+                                          None,
+                                          xml_entry.username.take(),
+                                          xml_entry.password.take());
+            let mut entry = entry.borrow_mut();
+            if let Some(notes) = xml_entry.notes.take() {
+                try!(entry.set_markdown_notes(notes));
+            }
+            if let Some((name, data)) = xml_entry.attachment.take() {
+                try!(entry.set_attachment(name, data));
+            }
+            if let Some(creation) = xml_entry.creation {
+                entry.creation = creation;
+            }
+            if let Some(last_mod) = xml_entry.last_mod {
+                entry.last_mod = last_mod;
+            }
+            if let Some(last_access) = xml_entry.last_access {
+                entry.last_access = last_access;
+            }
+            if let Some(uuid) = xml_entry.uuid {
+                if !self.uuid_index.contains_key(&uuid) {
+                    let weak = self.uuid_index.remove(&entry.uuid);
+                    entry.uuid = uuid;
+                    if let Some(weak) = weak {
+                        self.uuid_index.insert(uuid, weak);
+                    }
+                }
+            }
+        }
+        Ok(imported.len())
+    }
+
+    /// Write all groups and entries, attachments and meta streams
+    /// included, to writer as an archive encrypted with passphrase alone,
+    /// see export::archive. The archive doesn't depend on the key or the
+    /// header of the database, e.g. for backups which can be restored
+    /// with import_encrypted into a database with another key.
+    pub fn export_encrypted<W: Write>(&mut self,
+                                      writer: W,
+                                      passphrase: SecureString)
+                                      -> Result<(), V1KpdbError> {
+        self.export_encrypted_with(writer, passphrase, archive::DEFAULT_ITERATIONS)
+    }
+
+    /// Like export_encrypted with iterations of PBKDF2 instead of
+    /// archive::DEFAULT_ITERATIONS
+    pub fn export_encrypted_with<W: Write>(&mut self,
+                                           writer: W,
+                                           mut passphrase: SecureString,
+                                           iterations: u32)
+                                           -> Result<(), V1KpdbError> {
+        self.update_meta_entries();
+        let passphrase = passphrase.unlocked();
+        let db = &*self;
+        archive::write(writer,
+                       passphrase.as_bytes(),
+                       iterations,
+                       |sink| SaveParser::stream(db, sink))
+    }
+
+    /// Replace all groups and entries with the ones of an archive
+    /// written by export_encrypted. The key and the header of the
+    /// database stay as they are. Nothing is changed on error, see
+    /// archive::decrypt for the errors. The import can be undone.
+    pub fn import_encrypted<R: Read>(&mut self,
+                                     reader: R,
+                                     mut passphrase: SecureString)
+                                     -> Result<(), V1KpdbError> {
+        let content = {
+            let passphrase = passphrase.unlocked();
+            try!(archive::decrypt(reader, passphrase.as_bytes()))
+        };
+        // Parse once before anything is replaced, restore would leave
+        // the database half loaded on malformed content
+        let (num_groups, num_entries) = {
+            let tree = try!(parse_decrypted(content.bytes()));
+            (tree.groups.len() as u32, (tree.entries.len() + tree.meta_entries.len()) as u32)
+        };
+        let snapshot = Snapshot::new(content, num_groups, num_entries);
+        self.batch(move |db| db.restore(&snapshot))
+    }
+
+    /// Search for entries
+    ///
+    /// * query: which fields to search and how to match them.
+    ///          See SearchQuery for details
+    ///
+    /// Returns the matching entries in the order of the entries vector.
+    pub fn search(&self, query: &SearchQuery) -> Result<Vec<Rc<RefCell<V1Entry>>>, V1KpdbError> {
+        let matcher = try!(query.matcher());
+        let mut result = vec![];
+        for entry in self.entries.iter() {
+            if query.matches_entry(&matcher, &mut entry.borrow_mut()) {
+                result.push(entry.clone());
+            }
+        }
+        Ok(result)
+    }
+
+    /// Find the entries which hold credentials for url
+    ///
+    /// An entry matches if one of its URLs has the same registrable
+    /// domain as url (e.g. mail.example.co.uk and www.example.co.uk)
+    /// or if both domains are declared equivalent in equivalent_domains.
+    /// Entries in the Backup group are never returned.
+    pub fn find_entries_for_url(&self, url: &str) -> Vec<Rc<RefCell<V1Entry>>> {
+        self.entries
+            .iter()
+            .filter(|entry| {
+                let entry = entry.borrow();
+                !is_in_backup_group(&entry) && !is_in_excluded_group(&entry) &&
+                entry.urls().iter().any(|u| self.equivalent_domains.urls_match(u, url))
+            })
+            .cloned()
+            .collect()
+    }
+
+    /// Check the passwords of all entries against a local breach list.
+    /// Returns the entries whose password appears in the list together
+    /// with the number of appearances. Entries without password and
+    /// entries in the Backup group are skipped.
+    pub fn breached_entries(&self,
+                            list: &mut BreachList)
+                            -> Result<Vec<(Rc<RefCell<V1Entry>>, u64)>, V1KpdbError> {
+        let mut breached = vec![];
+        for entry in self.entries.iter() {
+            let mut entry_ref = entry.borrow_mut();
+            if is_in_backup_group(&entry_ref) {
+                continue;
+            }
+            let count = match entry_ref.raw_password_mut() {
+                Some(password) => try!(list.lookup(password)),
+                None => 0,
+            };
+            if count > 0 {
+                breached.push((entry.clone(), count));
+            }
+        }
+        Ok(breached)
+    }
+
+    /// The entries which expired at or before now, the earliest
+    /// first. Entries in the Backup group are skipped.
+    pub fn expired_entries(&self, now: DateTime<Local>) -> Vec<Rc<RefCell<V1Entry>>> {
+        self.entries_expiring(|expiry| expiry <= now)
+    }
+
+    /// The entries which haven't expired yet but will within duration,
+    /// e.g. to remind the user, the earliest first. Entries in the
+    /// Backup group are skipped.
+    pub fn expiring_within(&self, duration: chrono::Duration) -> Vec<Rc<RefCell<V1Entry>>> {
+        let now = Local::now();
+        let end = now + duration;
+        self.entries_expiring(|expiry| expiry > now && expiry <= end)
+    }
+
+    fn entries_expiring<F>(&self, in_range: F) -> Vec<Rc<RefCell<V1Entry>>>
+        where F: Fn(DateTime<Local>) -> bool
+    {
+        let mut entries: Vec<(DateTime<Local>, Rc<RefCell<V1Entry>>)> =
+            self.entries
+                .iter()
+                .filter_map(|entry| {
+                    let e = entry.borrow();
+                    match e.expiry() {
+                        Some(expiry) if in_range(expiry) && !is_in_backup_group(&e) => {
+                            Some((expiry, entry.clone()))
+                        }
+                        _ => None,
+                    }
+                })
+                .collect();
+        // The sort is stable, entries expiring at the same time keep
+        // their order
+        entries.sort_by(|a, b| a.0.cmp(&b.0));
+        entries.into_iter().map(|(_, entry)| entry).collect()
+    }
+
+    /// Counts, tree depth, attachment size and modification times of
+    /// the groups and entries, computed in one pass over them
+    pub fn stats(&self) -> DbStats {
+        let mut stats = DbStats::new();
+        stats.num_groups = self.groups.len();
+        stats.num_entries = self.entries.len();
+        for group in self.groups.iter() {
+            let group = group.borrow();
+            stats.depth = cmp::max(stats.depth, group.level as usize + 1);
+            stats.add_mod(group.last_mod);
+        }
+        let now = Local::now();
+        for entry in self.entries.iter() {
+            let entry = entry.borrow();
+            if let Some((_, data)) = entry.attachment() {
+                stats.num_attachments += 1;
+                stats.attachment_size += data.len();
+            }
+            stats.add_mod(entry.last_mod);
+            if entry.is_expired(now) && !is_in_backup_group(&entry) {
+                stats.num_expired += 1;
+            }
+        }
+        stats
+    }
+
+    /// Check the security settings of the database and return
+    /// recommendations, most urgent first:
+    ///
+    /// * target: time the key transformation should take, e.g. one second.
+    ///           The rounds this machine manages in it are measured, so
+    ///           this call takes at least target.
+    ///
+    /// * breach_list: if given, passwords are looked up in it as in
+    ///                breached_entries
+    ///
+    /// Entries in the backup group are skipped. Only AES is supported, so
+    /// there's no advice on the cipher.
+    pub fn security_advisor(&self,
+                            target: Duration,
+                            breach_list: Option<&mut BreachList>)
+                            -> Result<Vec<Recommendation>, V1KpdbError> {
+        let mut recommendations = vec![];
+
+        let current = self.header.key_transf_rounds;
+        let suggested = Crypter::benchmark_rounds(target);
+        // Machines differ, so only a clear shortfall is reported
+        if current < suggested / 2 {
+            let priority = if current < suggested / 10 {
+                Priority::High
+            } else {
+                Priority::Medium
+            };
+            recommendations.push(Recommendation::new(priority,
+                                                     Advice::IncreaseRounds {
+                                                         current: current,
+                                                         suggested: suggested,
+                                                     }));
+        }
+
+        if let Some(list) = breach_list {
+            let breached: Vec<Uuid> = try!(self.breached_entries(list))
+                                          .iter()
+                                          .map(|&(ref e, _)| e.borrow().uuid)
+                                          .collect();
+            if !breached.is_empty() {
+                recommendations.push(Recommendation::new(Priority::High,
+                                                         Advice::ChangeBreachedPasswords(breached)));
+            }
+        }
+
+        let mut weak = vec![];
+        for entry in self.entries.iter() {
+            let mut entry = entry.borrow_mut();
+            if is_in_backup_group(&entry) {
+                continue;
+            }
+            let is_weak = match entry.raw_password_mut().map(|p| p.unlocked()) {
+                Some(ref password) => {
+                    !password.is_empty() && password_strength(password) < WEAK_PASSWORD_BITS
+                }
+                None => false,
+            };
+            if is_weak {
+                weak.push(entry.uuid);
+            }
+        }
+        if !weak.is_empty() {
+            recommendations.push(Recommendation::new(Priority::Medium,
+                                                     Advice::ChangeWeakPasswords(weak)));
+        }
+
+        if self.crypter.key_factors().len() < 2 {
+            recommendations.push(Recommendation::new(Priority::Low, Advice::AddKeyFactor));
+        }
+        recommendations.push(Recommendation::new(Priority::Low, Advice::MigrateToKdbx4));
+
+        // The sort is stable, so the order above breaks ties
+        recommendations.sort_by(|a, b| a.priority.cmp(&b.priority));
+        Ok(recommendations)
+    }
+
+    /// Check the passwords and ages of all entries, see AuditReport.
+    /// Entries which weren't modified for longer than max_age are stale.
+    ///
+    /// Reused passwords are found by their SHA-256 hashes, so no
+    /// plaintext copies are kept while walking the entries. Entries in
+    /// the backup group and passkey entries, whose password is a private
+    /// key, are skipped.
+    pub fn audit(&self, max_age: chrono::Duration) -> AuditReport {
+        let mut report = AuditReport::new();
+        let now = Local::now();
+        let mut hashes: Vec<(Vec<u8>, Uuid)> = vec![];
+        for entry in self.entries.iter() {
+            let mut entry = entry.borrow_mut();
+            if is_in_backup_group(&entry) || entry.is_passkey() {
+                continue;
+            }
+            report.num_entries += 1;
+            let uuid = entry.uuid;
+            if now - entry.last_mod > max_age {
+                report.stale.push(uuid);
+            }
+            let hash = match entry.raw_password_mut().map(|p| p.unlocked()) {
+                Some(ref password) if !password.is_empty() => {
+                    let bits = password_strength(password);
+                    if bits < WEAK_PASSWORD_BITS {
+                        report.weak.push((uuid, bits));
+                    }
+                    let mut hasher = Hasher::new(Type::SHA256);
+                    let _ = hasher.write_all(password.as_bytes());
+                    hasher.finish()
+                }
+                _ => {
+                    report.empty.push(uuid);
+                    continue;
+                }
+            };
+            hashes.push((hash, uuid));
+        }
+
+        // Group by hash while keeping the order of the first entries
+        let mut first_seen: HashMap<Vec<u8>, usize> = HashMap::new();
+        let mut groups: Vec<Vec<Uuid>> = vec![];
+        for (hash, uuid) in hashes {
+            match first_seen.get(&hash).cloned() {
+                Some(i) => groups[i].push(uuid),
+                None => {
+                    first_seen.insert(hash, groups.len());
+                    groups.push(vec![uuid]);
+                }
+            }
+        }
+        report.reused = groups.into_iter().filter(|g| g.len() > 1).collect();
+        report
+    }
+
+    /// The timeline of entry (see V1Entry::timeline) together with its
+    /// earlier modifications. KeePass 1.x keeps old revisions of an entry
+    /// in the Backup group, they are found by title and username. Each
+    /// gives a Modified event at its modification time which refers to
+    /// the revision.
+    pub fn timeline(&self, entry: &Rc<RefCell<V1Entry>>) -> Vec<TimelineEvent> {
+        let mut entry = entry.borrow_mut();
+        let mut events = entry.timeline();
+        for revision in self.entries.iter() {
+            let mut revision = match revision.try_borrow_mut() {
+                Ok(revision) => revision,
+                // entry itself
+                Err(_) => continue,
+            };
+            if !is_in_backup_group(&revision) || revision.uuid == entry.uuid ||
+               revision.title != entry.title {
+                continue;
+            }
+            let same_username = match (entry.username.as_mut(), revision.username.as_mut()) {
+                (Some(a), Some(b)) => *a.unlocked() == *b.unlocked(),
+                (None, None) => true,
+                _ => false,
+            };
+            if same_username {
+                events.push(TimelineEvent {
+                    time: revision.last_mod,
+                    kind: TimelineKind::Modified,
+                    revision: Some(revision.uuid),
+                });
+            }
+        }
+        sort_events(&mut events);
+        events
+    }
+
+    /// All passkey entries of relying_party, e.g. for a browser bridge
+    /// which answers a WebAuthn request. Entries in the backup group are
+    /// skipped.
+    pub fn passkeys_for(&self, relying_party: &str) -> Vec<Rc<RefCell<V1Entry>>> {
+        self.entries
+            .iter()
+            .filter(|e| {
+                let entry = e.borrow();
+                !is_in_backup_group(&entry) &&
+                entry.passkey().map_or(false, |p| p.relying_party == relying_party)
+            })
+            .cloned()
+            .collect()
+    }
+
+    /// Create a new passkey entry in group, see V1Entry::set_passkey.
+    /// Returns PasskeyErr if passkey or private_key_pem is invalid, no
+    /// entry is created then.
+    pub fn create_passkey_entry(&mut self,
+                                group: Rc<RefCell<V1Group>>,
+                                passkey: &Passkey,
+                                private_key_pem: SecureString)
+                                -> Result<Rc<RefCell<V1Entry>>, V1KpdbError> {
+        self.batch(move |db| db.create_passkey_entry_unrecorded(group, passkey, private_key_pem))
+    }
+
+    fn create_passkey_entry_unrecorded(&mut self,
+                                       group: Rc<RefCell<V1Group>>,
+                                       passkey: &Passkey,
+                                       private_key_pem: SecureString)
+                                       -> Result<Rc<RefCell<V1Entry>>, V1KpdbError> {
+        let mut entry = V1Entry::new();
+        try!(entry.set_passkey(passkey, private_key_pem));
+        let new_entry = self.create_entry(group, passkey.relying_party.clone(),
+                                          None, None, None, None, None, None);
+        {
+            let mut new_ref = new_entry.borrow_mut();
+            new_ref.url = entry.url.take();
+            new_ref.username = entry.username.take();
+            new_ref.replace_raw_password(entry.replace_raw_password(None));
+            new_ref.comment = entry.comment.take();
+        }
+        Ok(new_entry)
+    }
+
+    /// Create a new group
+    ///
+    /// * title: title of the new group
+    ///
+    /// * expire: expiration date of the group
+    ///           None means that the group expires never which itself
+    ///           corresponds to the date 28-12-2999 23:59:59
+    ///
+    /// * image: an image number, used in KeePass and KeePassX for the group
+    ///          icon. None means 0
+    ///
+    /// * parent: a group inside the groups vector which should be the parent in
+    ///           the group tree. None means that the root group is the parent
+    ///
+    /// Returns the new group or FieldLengthErr if the title is longer
+    /// than field_limits allows.
+    pub fn create_group(&mut self,
+                        title: String,
+                        expire: Option<DateTime<Local>>,
+                        image: Option<u32>,
+                        parent: Option<Rc<RefCell<V1Group>>>)
+                        -> Result<Rc<RefCell<V1Group>>, V1KpdbError> {
+        self.batch(move |db| db.create_group_unrecorded(title, expire, image, parent))
+    }
+
+    fn create_group_unrecorded(&mut self,
+                               title: String,
+                               expire: Option<DateTime<Local>>,
+                               image: Option<u32>,
+                               parent: Option<Rc<RefCell<V1Group>>>)
+                               -> Result<Rc<RefCell<V1Group>>, V1KpdbError> {
+        try!(check(title.len(), self.field_limits.title));
+        let mut new_id: u32 = 1;
+        for group in self.groups.iter() {
+            let id = group.borrow().id;
+            if id >= new_id {
+                new_id = id + 1;
+            }
+        }
+
+        let new_group = Rc::new(RefCell::new(V1Group::new()));
+        new_group.borrow_mut().id = new_id;
+        new_group.borrow_mut().title = title;
+        new_group.borrow_mut().creation = Local::now();
+        new_group.borrow_mut().last_mod = Local::now();
+        new_group.borrow_mut().last_access = Local::now();
+        match expire {
+            Some(s) => new_group.borrow_mut().expire = s,
+            None => {} // is 12-28-2999 23:59:59 through V1Group::new
+        }
+        match image {
+            Some(s) => new_group.borrow_mut().image = s,
+            None => {} // is 0 through V1Group::new
+        }
+        match parent {
+            Some(s) => {
+                let index = try!(self.groups.get_index(&s));
+                new_group.borrow_mut().level = s.borrow().level + 1;
+                new_group.borrow_mut().parent = Some(s.clone());
+                s.borrow_mut().children.push(Rc::downgrade(&new_group.clone()));
+                self.groups.insert(index + 1, new_group.clone());
+
+            }
+            None => {
+                new_group.borrow_mut().parent = Some(self.root_group
+                                                         .clone());
+                self.root_group.borrow_mut().children.push(Rc::downgrade(&new_group.clone()));
+                self.groups.push(new_group.clone());
+            }
+        }
+
+        new_group.borrow_mut().set_dirty(true);
+        self.header.num_groups += 1;
+        Ok(new_group)
+    }
+
+    /// Create a new entry
+    ///
+    /// * group: group which should hold the entry
+    ///
+    /// * title: title of the new entry
+    ///
+    /// * expire: expiration date of the group
+    ///           None means that the group expires never which itself
+    ///           corresponds to the date 28-12-2999 23:59:59
+    ///
+    /// * image: an image number, used in KeePass and KeePassX for the group
+    ///          icon. None means 0
+    ///
+    /// * url: URL from where the credentials are
+    ///
+    /// * comment: some free-text-comment about the entry
+    ///
+    /// * username: username for the URL
+    ///
+    /// * password: password for the URL
+    ///
+    /// Note: username and password should be of type String at creation. If you have a
+    /// &str which you convert into a String with to_string() the plaintext will remain
+    /// in memory as the new created String is a copy of the original &str. If you use
+    /// String this function call is a move so that the String remains where it was
+    /// created.
+    ///
+    /// Returns the new entry.
+    pub fn create_entry(&mut self,
+                        group: Rc<RefCell<V1Group>>,
+                        title: String,
+                        expire: Option<DateTime<Local>>,
+                        image: Option<u32>,
+                        url: Option<String>,
+                        comment: Option<String>,
+                        username: Option<String>,
+                        password: Option<String>)
+                        -> Rc<RefCell<V1Entry>> {
+        self.batch(move |db| db.create_entry_unrecorded(group,
+                                                        title,
+                                                        expire,
+                                                        image,
+                                                        url,
+                                                        comment,
+                                                        username,
+                                                        password))
+    }
+
+    fn create_entry_unrecorded(&mut self,
+                               group: Rc<RefCell<V1Group>>,
+                               title: String,
+                               expire: Option<DateTime<Local>>,
+                               image: Option<u32>,
+                               url: Option<String>,
+                               comment: Option<String>,
+                               username: Option<String>,
+                               password: Option<String>)
+                               -> Rc<RefCell<V1Entry>> {
+        // Automatically creates a UUID for the entry
+        let new_entry = Rc::new(RefCell::new(V1Entry::new()));
+        new_entry.borrow_mut().title = title;
+        new_entry.borrow_mut().group = Some(group.clone());
+        group.borrow_mut().entries.push(Rc::downgrade(&new_entry.clone()));
+        new_entry.borrow_mut().group_id = group.borrow().id;
+        new_entry.borrow_mut().creation = Local::now();
+        new_entry.borrow_mut().last_mod = Local::now();
+        new_entry.borrow_mut().last_access = Local::now();
+        match expire {
+            Some(s) => new_entry.borrow_mut().expire = s,
+            None => {} // is 12-28-2999 23:59:59 through V1Entry::new()
+        };
+        match image {
+            Some(s) => new_entry.borrow_mut().image = s,
+            None => {} // is 0 through V1Entry::new()
+        }
+        new_entry.borrow_mut().url = url;
+        new_entry.borrow_mut().comment = comment;
+        match username {
+            Some(s) => new_entry.borrow_mut().username = Some(SecureString::new(s)),
+            None => {}
+        };
+        match password {
+            Some(s) => {
+                new_entry.borrow_mut().replace_raw_password(Some(SecureString::new(s)));
+            }
+            None => {}
+        };
+
+        new_entry.borrow_mut().set_dirty(true);
+        new_entry.borrow_mut().set_secret_access(Some(self.secret_access.clone()));
+        self.entries.push(new_entry.clone());
+        self.uuid_index.insert(new_entry.borrow().uuid, Rc::downgrade(&new_entry));
+        self.header.num_entries += 1;
+        new_entry
+    }
+
+    /// Remove a group
+    ///
+    /// * group: The group to remove
+    ///
+    /// Note: Entries and children of the group are deleted, too.
+    ///
+    /// The group should be given to the function as a move. If this is done, the rc counter
+    /// is 0 at the end of the function and therefore sensitive data is deleted correctly.
+    pub fn remove_group(&mut self, group: Rc<RefCell<V1Group>>) -> Result<(), V1KpdbError> {
+        self.batch(move |db| db.remove_group_unrecorded(group))
+    }
+
+    fn remove_group_unrecorded(&mut self, group: Rc<RefCell<V1Group>>) -> Result<(), V1KpdbError> {
+        // Sensitive data (e.g. SecureString) is automatically dropped at the end of this
+        // function as Rc is 0 then
+        try!(self.remove_group_from_db(&group));
+        try!(self.remove_entries(&group));
+        if let Some(ref parent) = group.borrow().parent {
+            try!(parent.borrow_mut().drop_weak_child_reference(&group));
+            drop(parent);
+        }
+        try!(self.remove_children(&group));
+        self.touch();
+        Ok(())
+    }
+
+    fn remove_group_from_db(&mut self, group: &Rc<RefCell<V1Group>>) -> Result<(), V1KpdbError> {
+        let index = try!(self.groups.get_index(group));
+        let db_reference = self.groups.remove(index);
+        self.meta_info.group_icons.remove(&db_reference.borrow().id);
+        self.handles.group_removed(db_reference.borrow().id);
+        drop(db_reference);
+        self.header.num_groups -= 1;
+        Ok(())
+    }
+
+    fn remove_entry_from_db(&mut self, entry: &Rc<RefCell<V1Entry>>) -> Result<(), V1KpdbError> {
+        let index = try!(self.entries.get_index(entry));
+        let db_reference = self.entries.remove(index);
+        self.meta_info.entry_icons.remove(&db_reference.borrow().uuid);
+        self.uuid_index.remove(&db_reference.borrow().uuid);
+        self.handles.entry_removed(db_reference.borrow().uuid);
+        drop(db_reference);
+        self.header.num_entries -= 1;
+        Ok(())
+    }
+
+    fn remove_entries(&mut self, group: &Rc<RefCell<V1Group>>) -> Result<(), V1KpdbError> {
+        // Clone needed to prevent thread panning through borrowing
+        let entries = group.borrow().entries.clone();
+        for entry in entries {
+            if let Some(entry_strong) = entry.upgrade() {
+                try!(self.remove_entry(entry_strong));
+            } else {
+                return Err(V1KpdbError::WeakErr);
+            }
+        }
+        Ok(())
+    }
+
+    fn remove_children(&mut self, group: &Rc<RefCell<V1Group>>) -> Result<(), V1KpdbError> {
+        // Clone needed to prevent thread panning through borrowing
+        let children = group.borrow().children.clone();
+        for child in children {
+            if let Some(child_strong) = child.upgrade() {
+                try!(self.remove_group(child_strong));
+            } else {
+                return Err(V1KpdbError::WeakErr);
+            }
+        }
+        Ok(())
+    }
+
+    /// Remove a group
+    ///
+    /// * entry: The entry to remove.
+    ///
+    /// Note: The entry should be given to the function as a move. If this is done, the rc counter
     /// is 0 at the end of the function and therefore sensitive data is deleted correctly.
     pub fn remove_entry(&mut self, entry: Rc<RefCell<V1Entry>>) -> Result<(), V1KpdbError> {
+        self.batch(move |db| db.remove_entry_unrecorded(entry))
+    }
+
+    fn remove_entry_unrecorded(&mut self, entry: Rc<RefCell<V1Entry>>) -> Result<(), V1KpdbError> {
         // Sensitive data (e.g. SecureString) is automatically dropped at the end of this
         // function as Rc is 0 then
         try!(self.remove_entry_from_db(&entry));
 
-        if let Some(ref group) = entry.borrow().group {
-            try!(group.borrow_mut().drop_weak_entry_reference(&entry));
-            drop(group);
+        if let Some(ref group) = entry.borrow().group {
+            try!(group.borrow_mut().drop_weak_entry_reference(&entry));
+            drop(group);
+        }
+        self.touch();
+        Ok(())
+    }
+
+    /// Iterate over all groups, depth-first, i.e. in the order in which
+    /// KeePass shows the group tree
+    pub fn iter_groups(&self) -> GroupIter {
+        GroupIter::new(&self.root_group, Traversal::DepthFirst)
+    }
+
+    /// Iterate over all groups level by level
+    pub fn iter_groups_breadth_first(&self) -> GroupIter {
+        GroupIter::new(&self.root_group, Traversal::BreadthFirst)
+    }
+
+    /// Iterate over all entries sorted by their groups depth-first
+    pub fn iter_entries(&self) -> EntryIter {
+        EntryIter::new(&self.root_group, Traversal::DepthFirst)
+    }
+
+    /// Iterate over all entries sorted by their groups level by level
+    pub fn iter_entries_breadth_first(&self) -> EntryIter {
+        EntryIter::new(&self.root_group, Traversal::BreadthFirst)
+    }
+
+    /// Find a group by its id
+    pub fn group_by_id(&self, id: u32) -> Option<Rc<RefCell<V1Group>>> {
+        self.groups.iter().find(|g| g.borrow().id == id).cloned()
+    }
+
+    /// Find a group by its path, e.g. "Internet/Email", see kpdb::path.
+    /// If groups on the way share a title, the first one is taken. None
+    /// if there's no such group or the path is malformed.
+    pub fn group_by_path(&self, path: &str) -> Option<Rc<RefCell<V1Group>>> {
+        path::split(path).ok().and_then(|titles| find_group(self, &titles))
+    }
+
+    /// Find an entry by the path of its group and its title, e.g.
+    /// "Internet/Email/Gmail". If entries of the group share the title,
+    /// the first one is taken.
+    pub fn entry_by_path(&self, path: &str) -> Option<Rc<RefCell<V1Entry>>> {
+        let mut titles = match path::split(path) {
+            Ok(titles) => titles,
+            Err(_) => return None,
+        };
+        // split never returns an empty path
+        let title = titles.pop().unwrap();
+        find_group(self, &titles).and_then(|group| {
+            group.borrow().child_entries().into_iter().find(|e| e.borrow().title == title)
+        })
+    }
+
+    /// Find the group at path or create it and the groups on the way,
+    /// e.g. for scripts which put entries into "Internet/Email". PathErr
+    /// if the path is malformed. Returns the group.
+    pub fn create_path(&mut self, path: &str) -> Result<Rc<RefCell<V1Group>>, V1KpdbError> {
+        let titles = try!(path::split(path));
+        self.batch(move |db| create_groups(db, &titles))
+    }
+
+    /// Find an entry by its UUID, see find_by_uuid
+    pub fn entry_by_uuid(&self, uuid: &Uuid) -> Option<Rc<RefCell<V1Entry>>> {
+        self.find_by_uuid(uuid)
+    }
+
+    /// Find an entry by its UUID. Entries which were loaded or created
+    /// and removed through V1Kpdb are looked up in an index. Entries
+    /// which were pushed to entries directly or whose UUID was changed
+    /// are still found, but through a linear search. Remove entries
+    /// with remove_entry or delete_entry, otherwise the index may still
+    /// return them.
+    pub fn find_by_uuid(&self, uuid: &Uuid) -> Option<Rc<RefCell<V1Entry>>> {
+        if let Some(entry) = self.uuid_index.get(uuid).and_then(|e| e.upgrade()) {
+            if entry.borrow().uuid == *uuid {
+                return Some(entry);
+            }
+        }
+        self.entries.iter().find(|e| e.borrow().uuid == *uuid).cloned()
+    }
+
+    /// Handle of entry, see EntryHandle
+    pub fn entry_handle(&self, entry: &V1Entry) -> EntryHandle {
+        self.handles.entry_handle(entry.uuid)
+    }
+
+    /// Handle of group, see GroupHandle
+    pub fn group_handle(&self, group: &V1Group) -> GroupHandle {
+        self.handles.group_handle(group.id)
+    }
+
+    /// Handles of all entries in the order of iter_entries
+    pub fn entry_handles(&self) -> Vec<EntryHandle> {
+        self.iter_entries().map(|e| self.handles.entry_handle(e.borrow().uuid)).collect()
+    }
+
+    /// Handles of all groups in the order of iter_groups
+    pub fn group_handles(&self) -> Vec<GroupHandle> {
+        self.iter_groups().map(|g| self.handles.group_handle(g.borrow().id)).collect()
+    }
+
+    /// Like search but returns handles
+    pub fn search_handles(&self, query: &SearchQuery) -> Result<Vec<EntryHandle>, V1KpdbError> {
+        let entries = try!(self.search(query));
+        Ok(entries.iter().map(|e| self.handles.entry_handle(e.borrow().uuid)).collect())
+    }
+
+    /// The entry of handle. None if it was removed.
+    pub fn resolve_entry(&self, handle: &EntryHandle) -> Option<Rc<RefCell<V1Entry>>> {
+        if !self.handles.is_current_entry(handle) {
+            return None;
+        }
+        // Like find_by_uuid but without panicking on borrowed entries
+        let uuid = handle.uuid();
+        if let Some(entry) = self.uuid_index.get(&uuid).and_then(|e| e.upgrade()) {
+            // A borrowed entry can't be checked. The index is right unless
+            // the UUID was changed by hand.
+            let is_indexed = entry.try_borrow().map(|e| e.uuid == uuid).unwrap_or(true);
+            if is_indexed {
+                return Some(entry);
+            }
+        }
+        self.entries
+            .iter()
+            .find(|e| e.try_borrow().map(|e| e.uuid == uuid).unwrap_or(false))
+            .cloned()
+    }
+
+    /// The group of handle. None if it was removed. Groups are looked up
+    /// once and then kept in a cache until the groups are replaced, e.g.
+    /// by load.
+    pub fn resolve_group(&self, handle: &GroupHandle) -> Option<Rc<RefCell<V1Group>>> {
+        if !self.handles.is_current_group(handle) {
+            return None;
+        }
+        if let Some(group) = self.handles.cached_group(handle.id()) {
+            return Some(group);
+        }
+        let group = self.groups
+                        .iter()
+                        .find(|g| g.try_borrow().map(|g| g.id == handle.id()).unwrap_or(false))
+                        .cloned();
+        if let Some(ref group) = group {
+            self.handles.cache_group(handle.id(), group);
+        }
+        group
+    }
+
+    /// Call f with the entry of handle. HandleErr if the entry was
+    /// removed, BorrowErr if it's borrowed elsewhere at the moment, e.g.
+    /// by an enclosing with_entry. Unlike borrowing the result of
+    /// find_by_uuid, this never panics.
+    pub fn with_entry<T, F>(&self, handle: &EntryHandle, f: F) -> Result<T, V1KpdbError>
+        where F: FnOnce(&mut V1Entry) -> T
+    {
+        let entry = try!(self.resolve_entry(handle).ok_or(V1KpdbError::HandleErr));
+        let mut entry = try!(entry.try_borrow_mut().map_err(|_| V1KpdbError::BorrowErr));
+        Ok(f(&mut entry))
+    }
+
+    /// Call f with the group of handle, see with_entry
+    pub fn with_group<T, F>(&self, handle: &GroupHandle, f: F) -> Result<T, V1KpdbError>
+        where F: FnOnce(&mut V1Group) -> T
+    {
+        let group = try!(self.resolve_group(handle).ok_or(V1KpdbError::HandleErr));
+        let mut group = try!(group.try_borrow_mut().map_err(|_| V1KpdbError::BorrowErr));
+        Ok(f(&mut group))
+    }
+
+    /// Like with_entry but the change f makes can be undone
+    pub fn edit_entry<T, F>(&mut self, handle: &EntryHandle, f: F) -> Result<T, V1KpdbError>
+        where F: FnOnce(&mut V1Entry) -> T
+    {
+        self.batch(move |db| {
+            db.with_entry(handle, |entry| {
+                entry.set_dirty(true);
+                f(entry)
+            })
+        })
+    }
+
+    /// Like with_group but the change f makes can be undone
+    pub fn edit_group<T, F>(&mut self, handle: &GroupHandle, f: F) -> Result<T, V1KpdbError>
+        where F: FnOnce(&mut V1Group) -> T
+    {
+        self.batch(move |db| {
+            db.with_group(handle, |group| {
+                group.set_dirty(true);
+                f(group)
+            })
+        })
+    }
+
+    /// Run f as a single change for undo, e.g. to revert a whole import
+    /// at once. Changes to groups and entries which don't go through the
+    /// methods of the database, e.g. setting a field of a borrowed
+    /// entry, can be undone if they're made inside f. A change is only
+    /// recorded if the database, a setter or set_dirty tells about it,
+    /// so call set_dirty after writing a field directly. Nested calls
+    /// belong to the outermost one.
+    pub fn batch<T, F>(&mut self, f: F) -> T
+        where F: FnOnce(&mut V1Kpdb) -> T
+    {
+        let before = if self.undo_log.begin() && self.undo_limit > 0 {
+            Some((self.snapshot(), self.revision()))
+        } else {
+            None
+        };
+        let result = f(self);
+        self.undo_log.end();
+        if let Some((before, revision)) = before {
+            if revision != self.revision() {
+                self.undo_log.record(before, self.undo_limit);
+            }
+        }
+        result
+    }
+
+    /// Run f as a single change like batch, but keep its changes only if
+    /// it succeeds. If f returns an error, the groups and entries are
+    /// restored as they were before and the error is returned. The copy
+    /// they're restored from is locked in memory and zeroed afterwards.
+    /// Groups and entries are restored from a copy, so references to
+    /// them become stale like after undo.
+    pub fn transaction<T, F>(&mut self, f: F) -> Result<T, V1KpdbError>
+        where F: FnOnce(&mut V1Kpdb) -> Result<T, V1KpdbError>
+    {
+        // The copy to roll back to is the state recorded for undo, too
+        let before = self.snapshot();
+        let revision = self.revision();
+        let was_dirty = self.dirty();
+        let outermost = self.undo_log.begin();
+        let result = match f(self) {
+            Ok(result) => Ok(result),
+            Err(e) => {
+                log_debug!("rolling back a transaction after {:?}", e);
+                // The copy was written by snapshot, so restoring it only
+                // fails if the memory is corrupted
+                let restored = self.restore(&before);
+                self.modified = was_dirty;
+                restored.and(Err(e))
+            }
+        };
+        self.undo_log.end();
+        // Nothing is left to undo after a rollback
+        if outermost && self.undo_limit > 0 && result.is_ok() && revision != self.revision() {
+            self.undo_log.record(before, self.undo_limit);
+        }
+        result
+    }
+
+    /// Revert the last change, see undo_limit. Groups and entries are
+    /// restored from a copy, so references to them become stale while
+    /// handles stay valid, unless they refer to an entry or group which
+    /// was removed by the change. Returns false if there's nothing to
+    /// undo.
+    pub fn undo(&mut self) -> Result<bool, V1KpdbError> {
+        let current = self.snapshot();
+        match self.undo_log.undo(current) {
+            Some(before) => self.restore(&before).map(|_| true),
+            None => Ok(false),
+        }
+    }
+
+    /// Apply the last change undone again. Any other change since the
+    /// undo discards what could be redone. Returns false if there's
+    /// nothing to redo.
+    pub fn redo(&mut self) -> Result<bool, V1KpdbError> {
+        let current = self.snapshot();
+        match self.undo_log.redo(current) {
+            Some(after) => self.restore(&after).map(|_| true),
+            None => Ok(false),
+        }
+    }
+
+    /// Number of changes undo can revert
+    pub fn undo_steps(&self) -> usize {
+        self.undo_log.undo_steps()
+    }
+
+    /// Number of changes redo can apply again
+    pub fn redo_steps(&self) -> usize {
+        self.undo_log.redo_steps()
+    }
+
+    /// True if the database was changed since it was loaded or saved,
+    /// e.g. to ask the user whether to save before closing. Changes made
+    /// through the methods of the database and the setters of groups and
+    /// entries count, see V1Entry::dirty. Undoing every change still
+    /// leaves the database dirty.
+    pub fn dirty(&self) -> bool {
+        self.modified || self.groups.iter().any(|g| g.borrow().dirty()) ||
+        self.entries.iter().any(|e| e.borrow().dirty())
+    }
+
+    /// The entries which were added or changed since the database was
+    /// loaded or saved, in the order of the entries. Removed entries
+    /// aren't there any more, they only make the database dirty.
+    pub fn changed_entries(&self) -> Vec<Rc<RefCell<V1Entry>>> {
+        self.entries.iter().filter(|e| e.borrow().dirty()).cloned().collect()
+    }
+
+    // Mark the database as changed, see dirty and batch
+    fn touch(&mut self) {
+        self.modified = true;
+        self.changes = self.changes.wrapping_add(1);
+    }
+
+    // Changes whenever the groups or entries are changed through the
+    // methods of the database, their setters or set_dirty, so batch
+    // doesn't have to compare snapshots
+    fn revision(&self) -> (u64, usize, usize, u64) {
+        let groups = self.groups.iter().map(|g| g.borrow().revision());
+        let entries = self.entries.iter().map(|e| e.borrow().revision());
+        let sum = groups.chain(entries).fold(0u64, |sum, r| sum.wrapping_add(r));
+        (self.changes, self.groups.len(), self.entries.len(), sum)
+    }
+
+    // The groups and entries as save writes them
+    fn snapshot(&mut self) -> Snapshot {
+        self.update_meta_entries();
+        let mut parser = SaveParser::new();
+        parser.prepare(self);
+        let content = SecureBytes::new(mem::replace(&mut parser.database, vec![]));
+        Snapshot::new(content,
+                      self.groups.len() as u32,
+                      (self.entries.len() + self.meta_entries.len()) as u32)
+    }
+
+    // Replace the groups and entries with the ones of snapshot
+    fn restore(&mut self, snapshot: &Snapshot) -> Result<(), V1KpdbError> {
+        self.header.num_groups = snapshot.num_groups();
+        self.header.num_entries = snapshot.num_entries();
+        // Parsing a database drops the undo log of the previous one
+        let log = mem::replace(&mut self.undo_log, UndoLog::new());
+        // Locked like decrypted content, the parser zeroes it out
+        let content = try!(snapshot.decrypt());
+        let result = self.parse_database(content, true);
+        self.undo_log = log;
+        try!(result.map_err(|e| e.coarse()));
+        self.index_entries();
+        self.touch();
+        Ok(())
+    }
+
+    /// Delete a group by its id
+    ///
+    /// * id: id of the group to delete
+    ///
+    /// * recursive: if true subgroups and entries of the group are deleted,
+    ///              too. Otherwise deleting a group which isn't empty fails
+    ///              with NotEmptyErr
+    pub fn delete_group(&mut self, id: u32, recursive: bool) -> Result<(), V1KpdbError> {
+        self.batch(move |db| db.delete_group_unrecorded(id, recursive))
+    }
+
+    fn delete_group_unrecorded(&mut self, id: u32, recursive: bool) -> Result<(), V1KpdbError> {
+        let group = match self.group_by_id(id) {
+            Some(g) => g,
+            None => return Err(V1KpdbError::IndexErr),
+        };
+        if !recursive &&
+           (group.borrow().children.len() > 0 || group.borrow().entries.len() > 0) {
+            return Err(V1KpdbError::NotEmptyErr);
+        }
+        self.remove_group(group)
+    }
+
+    /// Delete an entry by its UUID
+    pub fn delete_entry(&mut self, uuid: &Uuid) -> Result<(), V1KpdbError> {
+        self.batch(move |db| db.delete_entry_unrecorded(uuid))
+    }
+
+    fn delete_entry_unrecorded(&mut self, uuid: &Uuid) -> Result<(), V1KpdbError> {
+        match self.entry_by_uuid(uuid) {
+            Some(entry) => self.remove_entry(entry),
+            None => Err(V1KpdbError::IndexErr),
+        }
+    }
+
+    /// Move an entry into another group
+    ///
+    /// * entry: the entry to move
+    ///
+    /// * new_group: a group inside the groups vector which should hold the entry
+    pub fn move_entry(&mut self,
+                      entry: Rc<RefCell<V1Entry>>,
+                      new_group: Rc<RefCell<V1Group>>)
+                      -> Result<(), V1KpdbError> {
+        self.batch(move |db| db.move_entry_unrecorded(entry, new_group))
+    }
+
+    fn move_entry_unrecorded(&mut self,
+                             entry: Rc<RefCell<V1Entry>>,
+                             new_group: Rc<RefCell<V1Group>>)
+                             -> Result<(), V1KpdbError> {
+        try!(self.entries.get_index(&entry));
+        try!(self.groups.get_index(&new_group));
+
+        let old_group = entry.borrow().group.clone();
+        if let Some(old_group) = old_group {
+            try!(old_group.borrow_mut().drop_weak_entry_reference(&entry));
+        }
+        new_group.borrow_mut().entries.push(Rc::downgrade(&entry));
+        let mut entry = entry.borrow_mut();
+        entry.group_id = new_group.borrow().id;
+        entry.group = Some(new_group);
+        entry.last_mod = Local::now();
+        entry.set_dirty(true);
+        Ok(())
+    }
+
+    /// Move entry into the Archive group instead of deleting it. The
+    /// group is created at the top level if needed and is excluded
+    /// from search, see SearchQuery::include_excluded.
+    pub fn archive_entry(&mut self, entry: Rc<RefCell<V1Entry>>) -> Result<(), V1KpdbError> {
+        self.batch(move |db| db.archive_entry_unrecorded(entry))
+    }
+
+    fn archive_entry_unrecorded(&mut self, entry: Rc<RefCell<V1Entry>>) -> Result<(), V1KpdbError> {
+        let archive = match self.archive_group() {
+            Some(group) => group,
+            None => {
+                let group = try!(self.create_group(ARCHIVE_GROUP_TITLE.to_string(),
+                                                   None,
+                                                   None,
+                                                   None));
+                group.borrow_mut()
+                     .custom_data
+                     .insert(EXCLUDE_FROM_SEARCH.to_string(), "true".to_string());
+                group
+            }
+        };
+        self.move_entry(entry, archive)
+    }
+
+    /// The top level Archive group if there is one
+    pub fn archive_group(&self) -> Option<Rc<RefCell<V1Group>>> {
+        self.groups
+            .iter()
+            .find(|g| g.borrow().level == 0 && g.borrow().title == ARCHIVE_GROUP_TITLE)
+            .cloned()
+    }
+
+    /// All entries in the Archive group and its subgroups
+    pub fn archived(&self) -> Vec<Rc<RefCell<V1Entry>>> {
+        let archive_id = match self.archive_group() {
+            Some(group) => group.borrow().id,
+            None => return vec![],
+        };
+        self.entries
+            .iter()
+            .filter(|entry| {
+                let mut current = entry.borrow().group.clone();
+                while let Some(group) = current {
+                    if group.borrow().id == archive_id {
+                        return true;
+                    }
+                    current = group.borrow().parent.clone();
+                }
+                false
+            })
+            .cloned()
+            .collect()
+    }
+
+    /// Move entry into the Backup group, which KeePass 1.x uses as a
+    /// recycle bin, instead of deleting it. The group is created at the
+    /// top level if needed. Entries in the Backup group are left out of
+    /// searches and audits, moving them out again restores them. Entries
+    /// which already are in the Backup group are removed for good.
+    pub fn recycle_entry(&mut self, entry: Rc<RefCell<V1Entry>>) -> Result<(), V1KpdbError> {
+        self.batch(move |db| db.recycle_entry_unrecorded(entry))
+    }
+
+    fn recycle_entry_unrecorded(&mut self, entry: Rc<RefCell<V1Entry>>) -> Result<(), V1KpdbError> {
+        if is_in_backup_group(&entry.borrow()) {
+            return self.remove_entry(entry);
+        }
+        let bin = try!(self.recycle_bin());
+        self.move_entry(entry, bin)
+    }
+
+    /// Move group with its subgroups and entries into the Backup group,
+    /// see recycle_entry. The Backup group itself and groups inside it
+    /// are removed for good.
+    pub fn recycle_group(&mut self, group: Rc<RefCell<V1Group>>) -> Result<(), V1KpdbError> {
+        self.batch(move |db| db.recycle_group_unrecorded(group))
+    }
+
+    fn recycle_group_unrecorded(&mut self, group: Rc<RefCell<V1Group>>) -> Result<(), V1KpdbError> {
+        if is_backup_group(&group) {
+            return self.remove_group(group);
+        }
+        let bin = try!(self.recycle_bin());
+        self.move_group(group, Some(bin))
+    }
+
+    /// Remove all entries and subgroups of the Backup group for good.
+    /// The group itself is kept.
+    pub fn empty_recycle_bin(&mut self) -> Result<(), V1KpdbError> {
+        self.batch(move |db| db.empty_recycle_bin_unrecorded())
+    }
+
+    fn empty_recycle_bin_unrecorded(&mut self) -> Result<(), V1KpdbError> {
+        match self.backup_group() {
+            Some(bin) => {
+                try!(self.remove_entries(&bin));
+                self.remove_children(&bin)
+            }
+            None => Ok(()),
+        }
+    }
+
+    /// The top level Backup group if there is one
+    pub fn backup_group(&self) -> Option<Rc<RefCell<V1Group>>> {
+        self.groups
+            .iter()
+            .find(|g| g.borrow().level == 0 && g.borrow().title == BACKUP_GROUP_TITLE)
+            .cloned()
+    }
+
+    /// All entries in the Backup group and its subgroups
+    pub fn recycled(&self) -> Vec<Rc<RefCell<V1Entry>>> {
+        self.entries
+            .iter()
+            .filter(|entry| is_in_backup_group(&entry.borrow()))
+            .cloned()
+            .collect()
+    }
+
+    /// Sets of entries which are duplicates of each other by criteria,
+    /// ordered by their first entry. Entries in the Backup group and
+    /// passkey entries are skipped.
+    pub fn find_duplicates(&self, criteria: DuplicateCriteria) -> Vec<Vec<EntryHandle>> {
+        self.duplicate_clusters(criteria)
+            .iter()
+            .map(|cluster| cluster.iter().map(|e| self.entry_handle(&e.borrow())).collect())
+            .collect()
+    }
+
+    /// Recycle all but the most recently modified entry of each set of
+    /// duplicates found by find_duplicates. If several were modified
+    /// last the first one is kept. Returns the number of recycled
+    /// entries. Nothing is recycled on error.
+    pub fn deduplicate(&mut self, criteria: DuplicateCriteria) -> Result<usize, V1KpdbError> {
+        self.transaction(move |db| db.deduplicate_unrecorded(criteria))
+    }
+
+    fn deduplicate_unrecorded(&mut self,
+                              criteria: DuplicateCriteria)
+                              -> Result<usize, V1KpdbError> {
+        let mut num_recycled = 0;
+        for mut cluster in self.duplicate_clusters(criteria) {
+            let mut newest = 0;
+            for (i, entry) in cluster.iter().enumerate() {
+                if entry.borrow().last_mod > cluster[newest].borrow().last_mod {
+                    newest = i;
+                }
+            }
+            cluster.remove(newest);
+            for entry in cluster {
+                try!(self.recycle_entry(entry));
+                num_recycled += 1;
+            }
+        }
+        Ok(num_recycled)
+    }
+
+    fn duplicate_clusters(&self, criteria: DuplicateCriteria) -> Vec<Vec<Rc<RefCell<V1Entry>>>> {
+        // Group by key while keeping the order of the first entries
+        let mut first_seen: HashMap<Vec<u8>, usize> = HashMap::new();
+        let mut clusters: Vec<Vec<Rc<RefCell<V1Entry>>>> = vec![];
+        for entry in self.entries.iter() {
+            let key = {
+                let mut e = entry.borrow_mut();
+                if is_in_backup_group(&e) || e.is_passkey() {
+                    continue;
+                }
+                match duplicate_key(&mut e, criteria) {
+                    Some(key) => key,
+                    None => continue,
+                }
+            };
+            match first_seen.get(&key).cloned() {
+                Some(i) => clusters[i].push(entry.clone()),
+                None => {
+                    first_seen.insert(key, clusters.len());
+                    clusters.push(vec![entry.clone()]);
+                }
+            }
+        }
+        clusters.into_iter().filter(|c| c.len() > 1).collect()
+    }
+
+    fn recycle_bin(&mut self) -> Result<Rc<RefCell<V1Group>>, V1KpdbError> {
+        match self.backup_group() {
+            Some(group) => Ok(group),
+            None => self.create_group(BACKUP_GROUP_TITLE.to_string(), None, None, None),
+        }
+    }
+
+    /// Move a group with all its subgroups and entries
+    ///
+    /// * group: the group to move
+    ///
+    /// * new_parent: a group inside the groups vector which should be the new parent.
+    ///               None means that the root group is the parent. Moving a group
+    ///               into itself or one of its subgroups fails with TreeErr
+    pub fn move_group(&mut self,
+                      group: Rc<RefCell<V1Group>>,
+                      new_parent: Option<Rc<RefCell<V1Group>>>)
+                      -> Result<(), V1KpdbError> {
+        self.batch(move |db| db.move_group_unrecorded(group, new_parent))
+    }
+
+    fn move_group_unrecorded(&mut self,
+                             group: Rc<RefCell<V1Group>>,
+                             new_parent: Option<Rc<RefCell<V1Group>>>)
+                             -> Result<(), V1KpdbError> {
+        let index = try!(self.groups.get_index(&group));
+        let subtree_end = self.subtree_end(index);
+        if let Some(ref p) = new_parent {
+            let parent_index = try!(self.groups.get_index(p));
+            if parent_index >= index && parent_index < subtree_end {
+                return Err(V1KpdbError::TreeErr);
+            }
+        }
+
+        // Unlink from old parent...
+        let old_parent = group.borrow().parent.clone();
+        if let Some(old_parent) = old_parent {
+            try!(old_parent.borrow_mut().drop_weak_child_reference(&group));
+        }
+
+        // ...take the whole subtree out of the groups vector...
+        let subtree: Vec<Rc<RefCell<V1Group>>> = self.groups.drain(index..subtree_end).collect();
+        let old_level = group.borrow().level as i32;
+        let (new_parent, new_level, insert_at) = match new_parent {
+            Some(p) => {
+                let parent_index = try!(self.groups.get_index(&p));
+                let level = p.borrow().level as i32 + 1;
+                let end = self.subtree_end(parent_index);
+                (p, level, end)
+            }
+            None => (self.root_group.clone(), 0, self.groups.len()),
+        };
+
+        // ...fix the levels and insert it after the subtree of the new parent
+        for (offset, g) in subtree.into_iter().enumerate() {
+            let level = g.borrow().level as i32 - old_level + new_level;
+            g.borrow_mut().level = level as u16;
+            self.groups.insert(insert_at + offset, g);
+        }
+
+        new_parent.borrow_mut().children.push(Rc::downgrade(&group));
+        let mut group = group.borrow_mut();
+        group.parent = Some(new_parent);
+        group.last_mod = Local::now();
+        group.set_dirty(true);
+        Ok(())
+    }
+
+    /// Move group with its subgroups before the previous group of its
+    /// parent. The order of groups and entries is the one of the groups
+    /// and entries vectors, which save writes as it is, the children and
+    /// entries of the groups follow it. Returns false if group is the
+    /// first one already.
+    pub fn move_group_up(&mut self, group: Rc<RefCell<V1Group>>) -> Result<bool, V1KpdbError> {
+        self.batch(move |db| db.shift_group(group, true))
+    }
+
+    /// Move group with its subgroups behind the next group of its
+    /// parent, see move_group_up. Returns false if group is the last one
+    /// already.
+    pub fn move_group_down(&mut self, group: Rc<RefCell<V1Group>>) -> Result<bool, V1KpdbError> {
+        self.batch(move |db| db.shift_group(group, false))
+    }
+
+    /// Move entry before the previous entry of its group, see
+    /// move_group_up. Returns false if entry is the first one already.
+    pub fn move_entry_up(&mut self, entry: Rc<RefCell<V1Entry>>) -> Result<bool, V1KpdbError> {
+        self.batch(move |db| db.shift_entry(entry, true))
+    }
+
+    /// Move entry behind the next entry of its group, see move_group_up.
+    /// Returns false if entry is the last one already.
+    pub fn move_entry_down(&mut self, entry: Rc<RefCell<V1Entry>>) -> Result<bool, V1KpdbError> {
+        self.batch(move |db| db.shift_entry(entry, false))
+    }
+
+    /// Sort the subgroups of parent by key, each with its own subgroups.
+    /// None sorts the top-level groups. Groups with the same value keep
+    /// their order.
+    pub fn sort_groups(&mut self,
+                       parent: Option<Rc<RefCell<V1Group>>>,
+                       key: SortKey)
+                       -> Result<(), V1KpdbError> {
+        self.batch(move |db| db.sort_groups_unrecorded(parent, key))
+    }
+
+    fn sort_groups_unrecorded(&mut self,
+                              parent: Option<Rc<RefCell<V1Group>>>,
+                              key: SortKey)
+                              -> Result<(), V1KpdbError> {
+        let blocks = try!(self.child_blocks(&parent));
+        let children = blocks.iter()
+                             .map(|&(start, _)| self.groups[start].clone())
+                             .collect::<Vec<_>>();
+        let order = group_order(&children, key);
+        self.arrange_groups(parent, blocks, order);
+        Ok(())
+    }
+
+    /// Sort the entries of group by key. Entries with the same value keep
+    /// their order.
+    pub fn sort_entries(&mut self,
+                        group: Rc<RefCell<V1Group>>,
+                        key: SortKey)
+                        -> Result<(), V1KpdbError> {
+        self.batch(move |db| db.sort_entries_unrecorded(group, key))
+    }
+
+    fn sort_entries_unrecorded(&mut self,
+                               group: Rc<RefCell<V1Group>>,
+                               key: SortKey)
+                               -> Result<(), V1KpdbError> {
+        try!(self.groups.get_index(&group));
+        let slots = self.entry_slots(&group);
+        let entries = slots.iter().map(|i| self.entries[*i].clone()).collect::<Vec<_>>();
+        let order = entry_order(&entries, key);
+        self.arrange_entries(&group, slots, order);
+        Ok(())
+    }
+
+    fn shift_group(&mut self, group: Rc<RefCell<V1Group>>, up: bool) -> Result<bool, V1KpdbError> {
+        let index = try!(self.groups.get_index(&group));
+        let parent = group.borrow().parent.clone().and_then(|p| {
+            if Rc::ptr_eq(&p, &self.root_group) {
+                None
+            } else {
+                Some(p)
+            }
+        });
+        let blocks = try!(self.child_blocks(&parent));
+        let position = try!(blocks.iter()
+                                  .position(|&(start, _)| start == index)
+                                  .ok_or(V1KpdbError::TreeErr));
+        let other = match (up, position) {
+            (true, 0) => return Ok(false),
+            (true, _) => position - 1,
+            (false, _) if position + 1 == blocks.len() => return Ok(false),
+            (false, _) => position + 1,
+        };
+        let mut order = (0..blocks.len()).collect::<Vec<_>>();
+        order.swap(position, other);
+        self.arrange_groups(parent, blocks, order);
+        Ok(true)
+    }
+
+    fn shift_entry(&mut self, entry: Rc<RefCell<V1Entry>>, up: bool) -> Result<bool, V1KpdbError> {
+        let index = try!(self.entries.get_index(&entry));
+        let group = try!(entry.borrow().group.clone().ok_or(V1KpdbError::TreeErr));
+        let slots = self.entry_slots(&group);
+        let position = try!(slots.iter().position(|i| *i == index).ok_or(V1KpdbError::TreeErr));
+        let other = match (up, position) {
+            (true, 0) => return Ok(false),
+            (true, _) => position - 1,
+            (false, _) if position + 1 == slots.len() => return Ok(false),
+            (false, _) => position + 1,
+        };
+        let mut order = (0..slots.len()).collect::<Vec<_>>();
+        order.swap(position, other);
+        self.arrange_entries(&group, slots, order);
+        Ok(true)
+    }
+
+    /// Merge other, e.g. a copy edited on another machine, into this
+    /// database like KeePass' synchronize. Groups are matched by id and
+    /// creation time or title, entries by UUID. Missing groups and
+    /// entries are added, groups take over newer remote changes.
+    ///
+    /// * strategy: decides about entries which were changed in both
+    ///             databases, e.g. NewestWins
+    ///
+    /// * last_sync: time of the last merge of both copies. Entries which
+    ///              were only changed remotely since then are updated
+    ///              without asking strategy, local changes are kept. None
+    ///              means every differing entry is a conflict.
+    ///
+    /// Meta entries, e.g. custom icons, aren't merged. Nothing is
+    /// changed on error.
+    pub fn merge<R: ConflictResolver>(&mut self,
+                                      other: &V1Kpdb,
+                                      strategy: &mut R,
+                                      last_sync: Option<DateTime<Local>>)
+                                      -> Result<MergeReport, V1KpdbError> {
+        let start = Instant::now();
+        let result = self.transaction(move |db| db.merge_unrecorded(other, strategy, last_sync));
+        self.report_usage(UsageKind::Merge, start, result.is_ok(), 0);
+        result
+    }
+
+    fn merge_unrecorded<R: ConflictResolver>(&mut self,
+                                             other: &V1Kpdb,
+                                             strategy: &mut R,
+                                             last_sync: Option<DateTime<Local>>)
+                                             -> Result<MergeReport, V1KpdbError> {
+        let mut report = MergeReport::new();
+        let groups = try!(self.merge_groups(other, &mut report));
+
+        for remote in other.entries.iter() {
+            let uuid = remote.borrow().uuid;
+            let group = match groups.get(&remote.borrow().group_id) {
+                Some(group) => group.clone(),
+                None => continue,
+            };
+            let local = match self.find_by_uuid(&uuid) {
+                Some(local) => local,
+                None => {
+                    try!(self.add_copy(&mut remote.borrow_mut(), group, Some(uuid)));
+                    report.added.push(uuid);
+                    continue;
+                }
+            };
+
+            let same_group = local.borrow().group_id == group.borrow().id;
+            if same_group && local.borrow_mut().same_content(&mut remote.borrow_mut()) {
+                report.skipped.push(uuid);
+                continue;
+            }
+            let changed_locally = last_sync.map_or(true, |t| local.borrow().last_mod > t);
+            let changed_remotely = last_sync.map_or(true, |t| remote.borrow().last_mod > t);
+            if !changed_remotely {
+                report.skipped.push(uuid);
+                continue;
+            }
+            if !changed_locally {
+                try!(self.take_remote(&local, &mut remote.borrow_mut(), group));
+                report.updated.push(uuid);
+                continue;
+            }
+
+            let resolution = strategy.resolve(&local.borrow(), &remote.borrow());
+            let decision = Decision::from(&resolution);
+            match resolution {
+                Resolution::Local => {}
+                Resolution::Remote => try!(self.take_remote(&local, &mut remote.borrow_mut(), group)),
+                Resolution::Both => {
+                    try!(self.add_copy(&mut remote.borrow_mut(), group, None));
+                }
+                Resolution::Custom(mut entry) => local.borrow_mut().copy_from(&mut entry),
+            }
+            report.conflicts.push(MergeConflict {
+                uuid: uuid,
+                title: local.borrow().title.clone(),
+                decision: decision,
+            });
+        }
+        Ok(report)
+    }
+
+    // Add the groups of other which are missing here and update the
+    // others if they were changed remotely. Returns the local group for
+    // each remote group id.
+    fn merge_groups(&mut self,
+                    other: &V1Kpdb,
+                    report: &mut MergeReport)
+                    -> Result<HashMap<u32, Rc<RefCell<V1Group>>>, V1KpdbError> {
+        let mut groups = HashMap::new();
+        // Parents come before their subgroups in the groups vector
+        for remote in other.groups.iter() {
+            let remote = remote.borrow();
+            // Both copies may have created a group with the same id
+            let local = self.groups
+                            .iter()
+                            .find(|g| {
+                                let g = g.borrow();
+                                g.id == remote.id &&
+                                (g.creation == remote.creation || g.title == remote.title)
+                            })
+                            .cloned();
+            let local = match local {
+                Some(local) => {
+                    if remote.last_mod > local.borrow().last_mod {
+                        copy_group_fields(&mut local.borrow_mut(), &remote);
+                    }
+                    local
+                }
+                None => {
+                    let parent = remote.parent
+                                       .as_ref()
+                                       .and_then(|p| groups.get(&p.borrow().id))
+                                       .cloned();
+                    let group = try!(self.create_group(remote.title.clone(), None, None, parent));
+                    copy_group_fields(&mut group.borrow_mut(), &remote);
+                    if self.group_by_id(remote.id).is_none() {
+                        group.borrow_mut().id = remote.id;
+                    }
+                    report.added_groups.push(group.borrow().id);
+                    group
+                }
+            };
+            groups.insert(remote.id, local);
+        }
+        Ok(groups)
+    }
+
+    // Add a copy of remote to group. uuid None gives the copy a new UUID.
+    fn add_copy(&mut self,
+                remote: &mut V1Entry,
+                group: Rc<RefCell<V1Group>>,
+                uuid: Option<Uuid>)
+                -> Result<(), V1KpdbError> {
+        let entry = self.create_entry(group, String::new(), None, None, None, None, None, None);
+        entry.borrow_mut().copy_from(remote);
+        if let Some(uuid) = uuid {
+            self.uuid_index.remove(&entry.borrow().uuid);
+            entry.borrow_mut().uuid = uuid;
+            self.uuid_index.insert(uuid, Rc::downgrade(&entry));
+        }
+        Ok(())
+    }
+
+    // Replace local by remote, including its group
+    fn take_remote(&mut self,
+                   local: &Rc<RefCell<V1Entry>>,
+                   remote: &mut V1Entry,
+                   group: Rc<RefCell<V1Group>>)
+                   -> Result<(), V1KpdbError> {
+        if local.borrow().group_id != group.borrow().id {
+            try!(self.move_entry(local.clone(), group));
+        }
+        local.borrow_mut().copy_from(remote);
+        Ok(())
+    }
+
+    // Index after the last subgroup of the group at index. As the groups vector
+    // is in tree order all subgroups directly follow the group with a higher level.
+    fn subtree_end(&self, index: usize) -> usize {
+        let level = self.groups[index].borrow().level;
+        let mut end = index + 1;
+        while end < self.groups.len() && self.groups[end].borrow().level > level {
+            end += 1;
+        }
+        end
+    }
+
+    // The ranges in groups of the subgroups of parent, each with its own
+    // subgroups, in their order. None stands for the root group.
+    fn child_blocks(&self,
+                    parent: &Option<Rc<RefCell<V1Group>>>)
+                    -> Result<Vec<(usize, usize)>, V1KpdbError> {
+        let (mut start, end) = match *parent {
+            Some(ref p) => {
+                let index = try!(self.groups.get_index(p));
+                (index + 1, self.subtree_end(index))
+            }
+            None => (0, self.groups.len()),
+        };
+        let mut blocks = vec![];
+        while start < end {
+            let block_end = self.subtree_end(start);
+            blocks.push((start, block_end));
+            start = block_end;
+        }
+        Ok(blocks)
+    }
+
+    // Put the blocks of child_blocks into order, order[i] is the block
+    // which goes to place i, and let the children of parent follow
+    fn arrange_groups(&mut self,
+                      parent: Option<Rc<RefCell<V1Group>>>,
+                      blocks: Vec<(usize, usize)>,
+                      order: Vec<usize>) {
+        if blocks.is_empty() || order.iter().enumerate().all(|(i, o)| i == *o) {
+            return;
+        }
+        let start = blocks[0].0;
+        let arranged = order.iter()
+                            .flat_map(|o| self.groups[blocks[*o].0..blocks[*o].1].to_vec())
+                            .collect::<Vec<_>>();
+        let children = order.iter().map(|o| Rc::downgrade(&self.groups[blocks[*o].0])).collect();
+        for (offset, group) in arranged.into_iter().enumerate() {
+            self.groups[start + offset] = group;
+        }
+        let parent = parent.unwrap_or(self.root_group.clone());
+        parent.borrow_mut().children = children;
+        self.touch();
+    }
+
+    // Indices in entries of the entries of group in their order
+    fn entry_slots(&self, group: &Rc<RefCell<V1Group>>) -> Vec<usize> {
+        self.entries
+            .iter()
+            .enumerate()
+            .filter(|&(_, e)| e.borrow().group.as_ref().map_or(false, |g| Rc::ptr_eq(g, group)))
+            .map(|(i, _)| i)
+            .collect()
+    }
+
+    // Put the entries at slots into order, order[i] is the entry which
+    // goes to slots[i], and let the entries of group follow. Entries of
+    // other groups keep their places.
+    fn arrange_entries(&mut self,
+                       group: &Rc<RefCell<V1Group>>,
+                       slots: Vec<usize>,
+                       order: Vec<usize>) {
+        if order.iter().enumerate().all(|(i, o)| i == *o) {
+            return;
+        }
+        let arranged = order.iter().map(|o| self.entries[slots[*o]].clone()).collect::<Vec<_>>();
+        group.borrow_mut().entries = arranged.iter().map(|e| Rc::downgrade(e)).collect();
+        for (slot, entry) in slots.into_iter().zip(arranged.into_iter()) {
+            self.entries[slot] = entry;
+        }
+        self.touch();
+    }
+}
+
+// The key is zeroed out by SecureString and SecureBytes in the Crypter,
+// groups and entries by their own Drop. The seeds and the IV of the
+// header are overwritten here.
+impl Drop for V1Kpdb {
+    fn drop(&mut self) {
+        unsafe {
+            mem_protect::zero(&self.header.final_randomseed);
+            mem_protect::zero(&self.header.iv);
+            mem_protect::zero(&self.header.content_hash);
+            mem_protect::zero(&self.header.transf_randomseed);
+            if let Some(ref username) = self.meta_info.default_username {
+                mem_protect::zero(username);
+            }
         }
+    }
+}
+
+// What has_changed_on_disk compares the file with
+struct DiskState {
+    modified: Option<SystemTime>,
+    header_hash: Vec<u8>,
+    // Time of the load or save, the last sync for reload_merged
+    synced: DateTime<Local>,
+}
+
+impl DiskState {
+    fn new(path: &str, header: &[u8]) -> DiskState {
+        DiskState {
+            modified: fs::metadata(path).and_then(|m| m.modified()).ok(),
+            header_hash: header_hash(header),
+            synced: Local::now(),
+        }
+    }
+}
+
+// The part of a load which needs the final key: the key transformation
+// and the decryption. It's Send, so load_async runs it on a blocking
+// thread.
+#[doc(hidden)]
+pub struct LoadJob {
+    data: Vec<u8>,
+    header: V1Header,
+    key: Option<KeyJob>,
+    // Locked, taken by finish_load
+    decrypted: Option<(Vec<u8>, bool)>,
+}
+
+impl LoadJob {
+    pub fn decrypt(&mut self) -> Result<(), V1KpdbError> {
+        let key = try!(self.key.take().ok_or(V1KpdbError::DecryptErr));
+        let finalkey = try!(key.finalkey());
+        self.decrypted = Some(Crypter::decrypt_damaged_slice(&self.header,
+                                                             &self.data[124..],
+                                                             finalkey));
+        Ok(())
+    }
+}
+
+impl Drop for LoadJob {
+    fn drop(&mut self) {
+        if let Some((ref decrypted, _)) = self.decrypted {
+            unsafe {
+                mem_protect::zero(decrypted);
+            }
+            mem_protect::unlock(decrypted);
+        }
+    }
+}
+
+// The part of a save after the tree was serialized: the key
+// transformation, the encryption and the write. It's Send, so save_async
+// runs it on a blocking thread.
+#[doc(hidden)]
+pub struct SaveJob {
+    path: String,
+    header: V1Header,
+    header_raw: Vec<u8>,
+    // Moved to encrypt_raw, which zeroes it out
+    plaintext: Vec<u8>,
+    encrypted: Vec<u8>,
+    key: Option<KeyJob>,
+    keep_backup: bool,
+    save_options: SaveOptions,
+}
+
+impl SaveJob {
+    pub fn encrypt(&mut self) -> Result<(), V1KpdbError> {
+        let key = try!(self.key.take().ok_or(V1KpdbError::DecryptErr));
+        let finalkey = try!(key.finalkey());
+        let plaintext = mem::replace(&mut self.plaintext, vec![]);
+        self.encrypted = Crypter::encrypt_raw(&self.header, plaintext, finalkey);
         Ok(())
     }
+
+    pub fn write(&self) -> Result<(), V1KpdbError> {
+        V1Kpdb::write_atomically(&self.path,
+                                 &self.header_raw,
+                                 &self.encrypted,
+                                 self.keep_backup,
+                                 &self.save_options)
+    }
+}
+
+impl Drop for SaveJob {
+    fn drop(&mut self) {
+        unsafe {
+            mem_protect::zero(&self.plaintext);
+        }
+    }
+}
+
+fn header_hash(header: &[u8]) -> Vec<u8> {
+    let mut hasher = Hasher::new(Type::SHA256);
+    let _ = hasher.write_all(header);
+    hasher.finish()
+}
+
+// The data of a group without id and tree position
+fn copy_group_fields(group: &mut V1Group, other: &V1Group) {
+    group.title = other.title.clone();
+    group.image = other.image;
+    group.creation = other.creation;
+    group.last_mod = other.last_mod;
+    group.last_access = other.last_access;
+    group.expire = other.expire;
+    group.flags = other.flags;
+    group.notes = other.notes.clone();
+    group.custom_data = other.custom_data.clone();
 }
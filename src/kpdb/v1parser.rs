@@ -0,0 +1,66 @@
+//! Parsing of the decrypted content without a file or a key
+//!
+//! For callers which hold the plaintext already, and for fuzzers and
+//! property tests which want to reach the parser directly. Malformed
+//! input gives an error, never a panic, and memory is bounded by the
+//! size of the input: counts and field sizes which don't fit into the
+//! data are rejected before anything is allocated for them. See
+//! V1Header::parse for the header.
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use kpdb::meta::is_meta_entry;
+use kpdb::parser::LoadParser;
+use kpdb::v1entry::V1Entry;
+use kpdb::v1error::V1KpdbError;
+use kpdb::v1group::V1Group;
+use mem_protect;
+
+#[doc = "
+Tree is the decrypted content of a database: the groups in file order,
+linked below root_group like in V1Kpdb, and the entries sorted into
+their groups. Meta streams are kept apart in meta_entries as they are,
+they aren't read into MetaInfo.
+"]
+pub struct Tree {
+    /// Invisible parent of the top-level groups
+    pub root_group: Rc<RefCell<V1Group>>,
+    pub groups: Vec<Rc<RefCell<V1Group>>>,
+    pub entries: Vec<Rc<RefCell<V1Entry>>>,
+    pub meta_entries: Vec<Rc<RefCell<V1Entry>>>,
+}
+
+/// Parse data, the decrypted content of a database without its padding.
+/// The header's counts of groups and entries aren't needed: a record
+/// is a group if its id field has 4 bytes and an entry if it has 16,
+/// the UUID. OffsetErr if a field doesn't fit into data, ConvertErr for
+/// a field which can't be read or a record which is neither, TreeErr if
+/// the levels of the groups don't form a tree.
+pub fn parse_decrypted(data: &[u8]) -> Result<Tree, V1KpdbError> {
+    // The parser zeroes out and unlocks its copy when it's dropped
+    let content = data.to_vec();
+    mem_protect::lock(&content, "decrypted_database");
+    let mut parser = LoadParser::new(content, 0, 0);
+    try!(parser.infer_counts());
+    let (groups, levels) = try!(parser.parse_groups());
+    let parsed = try!(parser.parse_entries());
+
+    let mut entries = vec![];
+    let mut meta_entries = vec![];
+    for entry in parsed {
+        if is_meta_entry(&mut entry.borrow_mut()) {
+            meta_entries.push(entry);
+        } else {
+            entries.push(entry);
+        }
+    }
+    let root_group = Rc::new(RefCell::new(V1Group::new()));
+    try!(LoadParser::link_group_tree(&root_group, &groups, &entries, levels));
+    Ok(Tree {
+        root_group: root_group,
+        groups: groups,
+        entries: entries,
+        meta_entries: meta_entries,
+    })
+}
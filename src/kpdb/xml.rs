@@ -0,0 +1,346 @@
+use std::char;
+use std::io::{self, Write};
+
+use chrono::{DateTime, Local, TimeZone};
+use rustc_serialize::base64::{FromBase64, ToBase64, STANDARD};
+use rustc_serialize::hex::FromHex;
+use uuid::Uuid;
+
+use kpdb::export::group_titles;
+use kpdb::v1entry::{never_expires, V1Entry};
+use kpdb::v1error::V1KpdbError;
+use kpdb::v1kpdb::V1Kpdb;
+use mem_protect;
+
+// The XML export of KeePass 1.x looks like
+//
+//   <pwlist>
+//   <pwentry>
+//       <group tree="Internet\Mail">Work</group>
+//       <title>Mailbox</title>
+//       <username>alice</username>
+//       <url>https://mail.example.com</url>
+//       <password>secret</password>
+//       <notes>...</notes>
+//       <uuid>3b1c0b0e5a1e4f5d9c2a7b6e8f0d1c2a</uuid>
+//       <image>1</image>
+//       <creationtime>2016-01-31T12:00:00</creationtime>
+//       <lastmodtime>2016-01-31T12:00:00</lastmodtime>
+//       <lastaccesstime>2016-01-31T12:00:00</lastaccesstime>
+//       <expiretime expires="false">2999-12-28T23:59:59</expiretime>
+//       <attachdesc>file.txt</attachdesc>
+//       <attachment>base64 data</attachment>
+//   </pwentry>
+//   </pwlist>
+//
+// group holds the title of the group of the entry, tree the titles of
+// its parents separated by backslashes. Times are local times.
+
+const TIME_FORMAT: &'static str = "%Y-%m-%dT%H:%M:%S";
+
+/// An entry of an XML export
+pub struct XmlEntry {
+    /// Titles of the groups from the top level down
+    pub group_path: Vec<String>,
+    pub title: String,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    pub url: Option<String>,
+    pub notes: Option<String>,
+    pub uuid: Option<Uuid>,
+    pub image: u32,
+    pub creation: Option<DateTime<Local>>,
+    pub last_mod: Option<DateTime<Local>>,
+    pub last_access: Option<DateTime<Local>>,
+    /// None if the entry never expires
+    pub expire: Option<DateTime<Local>>,
+    /// Name and content
+    pub attachment: Option<(String, Vec<u8>)>,
+}
+
+// The fields which weren't moved into an entry are overwritten
+impl Drop for XmlEntry {
+    fn drop(&mut self) {
+        for field in [&self.username, &self.password, &self.notes].iter() {
+            if let Some(ref s) = **field {
+                unsafe {
+                    mem_protect::zero(s);
+                }
+            }
+        }
+        if let Some((_, ref data)) = self.attachment {
+            unsafe {
+                mem_protect::zero(data);
+            }
+        }
+    }
+}
+
+/// Write the entries of db as XML export. Returns the number of entries.
+pub fn write_entries<W: Write>(db: &V1Kpdb, writer: &mut W) -> io::Result<usize> {
+    try!(writer.write_all(b"<?xml version=\"1.0\" encoding=\"UTF-8\" standalone=\"yes\"?>\n\
+                            <pwlist>\n"));
+    for entry in db.entries.iter() {
+        try!(write_entry(&mut entry.borrow_mut(), writer));
+    }
+    try!(writer.write_all(b"</pwlist>\n"));
+    try!(writer.flush());
+    Ok(db.entries.len())
+}
+
+fn write_entry<W: Write>(entry: &mut V1Entry, writer: &mut W) -> io::Result<()> {
+    let mut path = group_titles(&entry.group);
+    let group = path.pop().unwrap_or(String::new());
+    try!(writer.write_all(b"<pwentry>\n\t<group"));
+    if !path.is_empty() {
+        try!(writer.write_all(b" tree=\""));
+        try!(write_escaped(writer, &path.join("\\")));
+        try!(writer.write_all(b"\""));
+    }
+    try!(writer.write_all(b">"));
+    try!(write_escaped(writer, &group));
+    try!(writer.write_all(b"</group>\n"));
+
+    try!(write_element(writer, "title", &entry.title));
+    if let Some(username) = entry.username() {
+        try!(write_element(writer, "username", &username));
+    }
+    if let Some(ref url) = entry.url {
+        try!(write_element(writer, "url", url));
+    }
+    if let Some(password) = entry.password() {
+        try!(write_element(writer, "password", &password));
+    }
+    if let Some(notes) = entry.markdown_notes() {
+        let result = write_element(writer, "notes", &notes);
+        unsafe {
+            mem_protect::zero(&notes);
+        }
+        try!(result);
+    }
+    try!(write_element(writer, "uuid", &entry.uuid.to_simple_string()));
+    try!(write_element(writer, "image", &entry.image.to_string()));
+    try!(write_element(writer, "creationtime", &entry.creation.format(TIME_FORMAT).to_string()));
+    try!(write_element(writer, "lastmodtime", &entry.last_mod.format(TIME_FORMAT).to_string()));
+    try!(write_element(writer,
+                       "lastaccesstime",
+                       &entry.last_access.format(TIME_FORMAT).to_string()));
+    // V1Entry::new sets this for entries which never expire
+    let expires = entry.expire != never_expires();
+    try!(write!(writer,
+                "\t<expiretime expires=\"{}\">{}</expiretime>\n",
+                expires,
+                entry.expire.format(TIME_FORMAT)));
+    if let Some((name, data)) = entry.attachment() {
+        try!(write_element(writer, "attachdesc", name));
+        try!(write_element(writer, "attachment", &data.to_base64(STANDARD)));
+    }
+    writer.write_all(b"</pwentry>\n")
+}
+
+fn write_element<W: Write>(writer: &mut W, name: &str, value: &str) -> io::Result<()> {
+    try!(write!(writer, "\t<{}>", name));
+    try!(write_escaped(writer, value));
+    write!(writer, "</{}>\n", name)
+}
+
+// Write text with the markup characters replaced by entities. The text
+// is written in pieces, so no escaped copy of it is made.
+fn write_escaped<W: Write>(writer: &mut W, text: &str) -> io::Result<()> {
+    let mut start = 0;
+    for (i, c) in text.char_indices() {
+        let entity: &[u8] = match c {
+            '&' => b"&amp;",
+            '<' => b"&lt;",
+            '>' => b"&gt;",
+            '"' => b"&quot;",
+            '\'' => b"&apos;",
+            // XML parsers turn CR LF into LF
+            '\r' => b"&#13;",
+            _ => continue,
+        };
+        try!(writer.write_all(text[start..i].as_bytes()));
+        try!(writer.write_all(entity));
+        start = i + 1;
+    }
+    writer.write_all(text[start..].as_bytes())
+}
+
+/// Parse an XML export. XmlErr if it's no KeePass 1.x export or an
+/// element is malformed.
+pub fn parse(text: &str) -> Result<Vec<XmlEntry>, V1KpdbError> {
+    if !text.contains("<pwlist") {
+        return Err(V1KpdbError::XmlErr);
+    }
+    let mut entries = vec![];
+    let mut rest = text;
+    while let Some(start) = rest.find("<pwentry>") {
+        let content = &rest[start + "<pwentry>".len()..];
+        let end = try!(content.find("</pwentry>").ok_or(V1KpdbError::XmlErr));
+        entries.push(try!(parse_entry(&content[..end])));
+        rest = &content[end + "</pwentry>".len()..];
+    }
+    Ok(entries)
+}
+
+fn parse_entry(text: &str) -> Result<XmlEntry, V1KpdbError> {
+    let mut entry = XmlEntry {
+        group_path: vec![],
+        title: String::new(),
+        username: None,
+        password: None,
+        url: None,
+        notes: None,
+        uuid: None,
+        image: 0,
+        creation: None,
+        last_mod: None,
+        last_access: None,
+        expire: None,
+        attachment: None,
+    };
+
+    if let Some((attributes, group)) = element(text, "group") {
+        if let Some(tree) = attribute(attributes, "tree") {
+            for title in try!(unescape(tree)).split('\\').filter(|t| !t.is_empty()) {
+                entry.group_path.push(title.to_string());
+            }
+        }
+        entry.group_path.push(try!(unescape(group)));
+    }
+    entry.title = try!(text_of(text, "title")).unwrap_or(String::new());
+    entry.username = try!(text_of(text, "username"));
+    entry.password = try!(text_of(text, "password"));
+    entry.url = try!(text_of(text, "url"));
+    entry.notes = try!(text_of(text, "notes"));
+    if let Some(uuid) = try!(text_of(text, "uuid")) {
+        let bytes = try!(uuid.trim().from_hex().map_err(|_| V1KpdbError::XmlErr));
+        entry.uuid = Some(try!(Uuid::from_bytes(&bytes).ok_or(V1KpdbError::XmlErr)));
+    }
+    if let Some(image) = try!(text_of(text, "image")) {
+        entry.image = try!(image.trim().parse().map_err(|_| V1KpdbError::XmlErr));
+    }
+    entry.creation = try!(time_of(text, "creationtime"));
+    entry.last_mod = try!(time_of(text, "lastmodtime"));
+    entry.last_access = try!(time_of(text, "lastaccesstime"));
+    if let Some((attributes, _)) = element(text, "expiretime") {
+        if attribute(attributes, "expires") != Some("false") {
+            entry.expire = try!(time_of(text, "expiretime"));
+        }
+    }
+    if let Some(data) = try!(text_of(text, "attachment")) {
+        let data = try!(data.trim().from_base64().map_err(|_| V1KpdbError::XmlErr));
+        let name = try!(text_of(text, "attachdesc")).unwrap_or(String::new());
+        entry.attachment = Some((name, data));
+    }
+    Ok(entry)
+}
+
+fn text_of(text: &str, name: &str) -> Result<Option<String>, V1KpdbError> {
+    match element(text, name) {
+        Some((_, content)) if !content.is_empty() => unescape(content).map(Some),
+        _ => Ok(None),
+    }
+}
+
+fn time_of(text: &str, name: &str) -> Result<Option<DateTime<Local>>, V1KpdbError> {
+    match try!(text_of(text, name)) {
+        Some(time) => {
+            Local.datetime_from_str(time.trim(), TIME_FORMAT)
+                 .map(Some)
+                 .map_err(|_| V1KpdbError::XmlErr)
+        }
+        None => Ok(None),
+    }
+}
+
+// Attributes and content of the first element name in text
+fn element<'a>(text: &'a str, name: &str) -> Option<(&'a str, &'a str)> {
+    let open = format!("<{}", name);
+    let close = format!("</{}>", name);
+    let mut from = 0;
+    loop {
+        let start = match text[from..].find(&open) {
+            Some(start) => from + start + open.len(),
+            None => return None,
+        };
+        // <url doesn't open <urlfoo
+        match text[start..].chars().next() {
+            Some('>') | Some('/') => {}
+            Some(c) if c.is_whitespace() => {}
+            _ => {
+                from = start;
+                continue;
+            }
+        }
+        let tag_end = match text[start..].find('>') {
+            Some(end) => start + end,
+            None => return None,
+        };
+        let attributes = &text[start..tag_end];
+        if attributes.ends_with('/') {
+            return Some((&attributes[..attributes.len() - 1], ""));
+        }
+        let content = &text[tag_end + 1..];
+        return content.find(&close).map(|end| (attributes, &content[..end]));
+    }
+}
+
+fn attribute<'a>(attributes: &'a str, name: &str) -> Option<&'a str> {
+    let pattern = format!("{}=\"", name);
+    let mut from = 0;
+    while let Some(start) = attributes[from..].find(&pattern) {
+        let start = from + start;
+        let value = &attributes[start + pattern.len()..];
+        let preceded_by_space = attributes[..start].chars().last().map_or(true, |c| c.is_whitespace());
+        if preceded_by_space {
+            return value.find('"').map(|end| &value[..end]);
+        }
+        from = start + pattern.len();
+    }
+    None
+}
+
+// Replace entities and CDATA sections. The result is allocated once.
+fn unescape(text: &str) -> Result<String, V1KpdbError> {
+    if text.starts_with("<![CDATA[") && text.ends_with("]]>") {
+        return Ok(text["<![CDATA[".len()..text.len() - "]]>".len()].to_string());
+    }
+    let mut result = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find('&') {
+        result.push_str(&rest[..start]);
+        let end = match rest[start..].find(';') {
+            Some(end) => start + end,
+            None => return Err(wipe_err(result)),
+        };
+        let c = match &rest[start + 1..end] {
+            "amp" => Some('&'),
+            "lt" => Some('<'),
+            "gt" => Some('>'),
+            "quot" => Some('"'),
+            "apos" => Some('\''),
+            entity if entity.starts_with("#x") => {
+                u32::from_str_radix(&entity[2..], 16).ok().and_then(char::from_u32)
+            }
+            entity if entity.starts_with('#') => {
+                entity[1..].parse().ok().and_then(char::from_u32)
+            }
+            _ => None,
+        };
+        match c {
+            Some(c) => result.push(c),
+            None => return Err(wipe_err(result)),
+        }
+        rest = &rest[end + 1..];
+    }
+    result.push_str(rest);
+    Ok(result)
+}
+
+fn wipe_err(partial: String) -> V1KpdbError {
+    unsafe {
+        mem_protect::zero(&partial);
+    }
+    V1KpdbError::XmlErr
+}
@@ -9,6 +9,30 @@ extern crate rustc_serialize;
 extern crate chrono;
 extern crate rand;
 extern crate uuid;
+extern crate regex;
+#[cfg(feature = "secrecy")]
+extern crate secrecy;
+#[cfg(feature = "zeroize")]
+extern crate zeroize;
+#[cfg(feature = "serde")]
+extern crate serde;
+#[cfg(feature = "tokio")]
+extern crate tokio;
+#[cfg(feature = "keyring")]
+extern crate keyring;
+#[cfg(all(test, feature = "serde"))]
+extern crate serde_json;
+#[cfg(feature = "log")]
+#[macro_use]
+extern crate log;
 
+#[macro_use]
+mod logging;
+
+pub mod mem_protect;
 pub mod sec_str;
 pub mod kpdb;
+#[cfg(feature = "secret-audit")]
+pub mod security;
+#[cfg(feature = "ffi")]
+pub mod ffi;
@@ -0,0 +1,52 @@
+//! Logging of opening and saving through the log crate
+//!
+//! With the log feature log_warn, log_debug and log_trace forward to
+//! warn!, debug! and trace! of the log crate, without it they expand to
+//! nothing, the
+//! arguments are still type checked. Only what the header and the
+//! counters of the database tell is logged: header fields but not the
+//! seeds, IV and content hash, numbers of rounds, groups, entries and
+//! bytes, milestones and errors. Never pass a key, a password, a field
+//! of a group or an entry or the path of a file to these macros.
+
+#[cfg(feature = "log")]
+macro_rules! log_warn {
+    ($($arg:tt)+) => (warn!($($arg)+))
+}
+
+#[cfg(not(feature = "log"))]
+macro_rules! log_warn {
+    ($($arg:tt)+) => ({
+        if false {
+            let _ = format!($($arg)+);
+        }
+    })
+}
+
+#[cfg(feature = "log")]
+macro_rules! log_debug {
+    ($($arg:tt)+) => (debug!($($arg)+))
+}
+
+#[cfg(not(feature = "log"))]
+macro_rules! log_debug {
+    ($($arg:tt)+) => ({
+        if false {
+            let _ = format!($($arg)+);
+        }
+    })
+}
+
+#[cfg(feature = "log")]
+macro_rules! log_trace {
+    ($($arg:tt)+) => (trace!($($arg)+))
+}
+
+#[cfg(not(feature = "log"))]
+macro_rules! log_trace {
+    ($($arg:tt)+) => ({
+        if false {
+            let _ = format!($($arg)+);
+        }
+    })
+}
@@ -0,0 +1,381 @@
+//! Locking and zeroing of memory which holds sensitive data
+//!
+//! Locked memory is never swapped to disk. On Unix this uses
+//! mlock/munlock, on Windows VirtualLock/VirtualUnlock. Other platforms
+//! have nothing to lock against, locking always succeeds there. The
+//! crate doesn't build for WebAssembly (wasm32) yet, though. Zeroing uses
+//! volatile writes on every platform which can't be optimized away, the
+//! same as explicit_bzero and SecureZeroMemory do. If memory can't be
+//! locked, e.g. when RLIMIT_MEMLOCK is exceeded, MemLockPolicy decides
+//! what happens.
+//!
+//! lock and unlock also register the memory with sec_str::shadow in
+//! debug builds.
+//!
+//! harden_process keeps a crash from writing secrets to a core file.
+
+use libc::{c_void, size_t};
+use std::cell::Cell;
+use std::intrinsics;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+use sec_str::shadow;
+
+/// What happens if memory can't be locked, e.g. because RLIMIT_MEMLOCK
+/// is exceeded. It's set per thread, see set_policy,
+/// V1Kpdb::mem_lock_policy and Crypter::set_mem_lock_policy.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MemLockPolicy {
+    /// Loading and saving fail with MemLockErr if a buffer couldn't be
+    /// locked
+    Require,
+    /// The memory is used unlocked and the failure is counted, see
+    /// lock_failures. The default
+    BestEffort,
+    /// Nothing is locked, e.g. where mlock is slow or forbidden. Memory
+    /// is still zeroed out
+    Disabled,
+}
+
+thread_local!(static POLICY: Cell<MemLockPolicy> = Cell::new(MemLockPolicy::BestEffort));
+thread_local!(static FAILURES: Cell<usize> = Cell::new(0));
+
+// Bytes passed to lock minus the ones passed to unlock, process-wide
+// like the limit
+static LOCKED_BYTES: AtomicUsize = AtomicUsize::new(0);
+
+// Set by harden_process, lock excludes memory from core dumps then
+static HARDENED: AtomicBool = AtomicBool::new(false);
+
+#[cfg(unix)]
+mod sys {
+    use libc::{self, c_void, size_t};
+    use libc::funcs::posix88::mman;
+
+    pub unsafe fn lock(ptr: *const c_void, len: size_t) -> bool {
+        mman::mlock(ptr, len) == 0
+    }
+
+    pub unsafe fn unlock(ptr: *const c_void, len: size_t) {
+        mman::munlock(ptr, len);
+    }
+
+    // Soft and hard RLIMIT_MEMLOCK
+    pub fn lock_limit() -> Option<(u64, u64)> {
+        let mut limit = libc::rlimit {
+            rlim_cur: 0,
+            rlim_max: 0,
+        };
+        match unsafe { libc::getrlimit(libc::RLIMIT_MEMLOCK, &mut limit) } {
+            0 => Some((limit.rlim_cur as u64, limit.rlim_max as u64)),
+            _ => None,
+        }
+    }
+
+    pub fn set_lock_limit(soft: u64, hard: u64) -> bool {
+        let limit = libc::rlimit {
+            rlim_cur: soft as libc::rlim_t,
+            rlim_max: hard as libc::rlim_t,
+        };
+        unsafe { libc::setrlimit(libc::RLIMIT_MEMLOCK, &limit) == 0 }
+    }
+
+    pub fn disable_core_dumps() -> bool {
+        let limit = libc::rlimit {
+            rlim_cur: 0,
+            rlim_max: 0,
+        };
+        let rlimit = unsafe { libc::setrlimit(libc::RLIMIT_CORE, &limit) == 0 };
+        rlimit && set_not_dumpable()
+    }
+
+    // Also keeps other processes of the user from attaching with ptrace
+    #[cfg(target_os = "linux")]
+    fn set_not_dumpable() -> bool {
+        use libc::c_int;
+        // Neither is in this version of libc
+        const PR_SET_DUMPABLE: c_int = 4;
+        extern "C" {
+            fn prctl(option: c_int, ...) -> c_int;
+        }
+        unsafe { prctl(PR_SET_DUMPABLE, 0 as libc::c_ulong) == 0 }
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    fn set_not_dumpable() -> bool {
+        true
+    }
+
+    // madvise needs whole pages, so the pages around the buffer are
+    // excluded as well, errors are ignored
+    #[cfg(target_os = "linux")]
+    pub unsafe fn exclude_from_dumps(ptr: *const c_void, len: size_t) {
+        use libc::c_int;
+        use libc::funcs::bsd44::madvise;
+        const MADV_DONTDUMP: c_int = 16;
+        let page = libc::sysconf(libc::_SC_PAGESIZE) as usize;
+        let start = ptr as usize / page * page;
+        let end = (ptr as usize + len as usize + page - 1) / page * page;
+        madvise(start as *mut c_void, (end - start) as size_t, MADV_DONTDUMP);
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    pub unsafe fn exclude_from_dumps(_: *const c_void, _: size_t) {}
+}
+
+#[cfg(windows)]
+mod sys {
+    use libc::{c_void, size_t};
+
+    #[link(name = "kernel32")]
+    extern "system" {
+        fn VirtualLock(address: *mut c_void, size: size_t) -> i32;
+        fn VirtualUnlock(address: *mut c_void, size: size_t) -> i32;
+    }
+
+    pub unsafe fn lock(ptr: *const c_void, len: size_t) -> bool {
+        VirtualLock(ptr as *mut c_void, len) != 0
+    }
+
+    pub unsafe fn unlock(ptr: *const c_void, len: size_t) {
+        VirtualUnlock(ptr as *mut c_void, len);
+    }
+
+    // The working set size limits locking on Windows instead
+    pub fn lock_limit() -> Option<(u64, u64)> {
+        None
+    }
+
+    pub fn set_lock_limit(_: u64, _: u64) -> bool {
+        false
+    }
+
+    // There are no core dumps, but Windows Error Reporting writes crash
+    // dumps unless the process handles faults itself
+    pub fn disable_core_dumps() -> bool {
+        const SEM_FAILCRITICALERRORS: u32 = 0x0001;
+        const SEM_NOGPFAULTERRORBOX: u32 = 0x0002;
+        #[link(name = "kernel32")]
+        extern "system" {
+            fn SetErrorMode(mode: u32) -> u32;
+        }
+        unsafe {
+            SetErrorMode(SEM_FAILCRITICALERRORS | SEM_NOGPFAULTERRORBOX);
+        }
+        true
+    }
+
+    pub unsafe fn exclude_from_dumps(_: *const c_void, _: size_t) {}
+}
+
+#[cfg(not(any(unix, windows)))]
+mod sys {
+    use libc::{c_void, size_t};
+
+    pub unsafe fn lock(_: *const c_void, _: size_t) -> bool {
+        true
+    }
+
+    pub unsafe fn unlock(_: *const c_void, _: size_t) {}
+
+    pub fn lock_limit() -> Option<(u64, u64)> {
+        None
+    }
+
+    pub fn set_lock_limit(_: u64, _: u64) -> bool {
+        false
+    }
+
+    pub fn disable_core_dumps() -> bool {
+        true
+    }
+
+    pub unsafe fn exclude_from_dumps(_: *const c_void, _: size_t) {}
+}
+
+/// Keep crashes from writing the memory of the process to disk. On
+/// Linux the process is marked as not dumpable, which also keeps other
+/// processes of the user from attaching a debugger, and RLIMIT_CORE is
+/// set to 0 on every Unix, for good as the hard limit is lowered too.
+/// On Windows the crash dumps of Windows Error Reporting are disabled.
+/// Memory locked from now on is excluded from core dumps on Linux as
+/// well, in case a dump is written anyway, e.g. by a debugger. Returns
+/// false if a step failed. See V1Kpdb::harden_process.
+pub fn harden_process() -> bool {
+    HARDENED.store(true, Ordering::SeqCst);
+    sys::disable_core_dumps()
+}
+
+/// True once harden_process was called
+pub fn is_hardened() -> bool {
+    HARDENED.load(Ordering::SeqCst)
+}
+
+/// Set the MemLockPolicy of the current thread
+pub fn set_policy(policy: MemLockPolicy) {
+    POLICY.with(|p| p.set(policy));
+}
+
+/// The MemLockPolicy of the current thread
+pub fn policy() -> MemLockPolicy {
+    POLICY.with(|p| p.get())
+}
+
+/// Number of buffers the current thread failed to lock. Compare it
+/// before and after an operation to see if secrets were left unlocked.
+pub fn lock_failures() -> usize {
+    FAILURES.with(|f| f.get())
+}
+
+/// Bytes locked by the process. It's approximate, buffers which failed
+/// to lock are subtracted when they're unlocked as well.
+pub fn locked_bytes() -> usize {
+    LOCKED_BYTES.load(Ordering::SeqCst)
+}
+
+/// The soft limit of locked memory in bytes, None if it can't be read
+/// or there's none like on Windows
+pub fn lock_limit() -> Option<u64> {
+    sys::lock_limit().map(|(soft, _)| soft)
+}
+
+/// Raise the soft limit of locked memory to bytes, at most up to the
+/// hard limit, which needs no privileges. Returns the new soft limit,
+/// None if it can't be changed.
+pub fn raise_lock_limit(bytes: u64) -> Option<u64> {
+    let (soft, hard) = match sys::lock_limit() {
+        Some(limit) => limit,
+        None => return None,
+    };
+    if soft >= bytes {
+        return Some(soft);
+    }
+    let soft = if bytes < hard {
+        bytes
+    } else {
+        hard
+    };
+    if sys::set_lock_limit(soft, hard) {
+        Some(soft)
+    } else {
+        None
+    }
+}
+
+/// Bytes which loading a file of len bytes locks at most: the encrypted
+/// and the decrypted content and the fields parsed from it
+pub fn lock_budget(len: u64) -> u64 {
+    len.saturating_mul(3)
+}
+
+#[doc = "
+MemLockScope sets the MemLockPolicy of the thread for a load, save or
+decryption and counts the buffers which failed to lock meanwhile. The
+previous policy is restored on drop, also if the operation panics.
+"]
+pub struct MemLockScope {
+    previous: MemLockPolicy,
+    failures: usize,
+}
+
+impl MemLockScope {
+    /// Set scope_policy until the scope is left. Unless it's Disabled,
+    /// the soft limit is raised so that budget more bytes can be locked,
+    /// see raise_lock_limit and lock_budget. 0 leaves the limit alone.
+    pub fn enter(scope_policy: MemLockPolicy, budget: u64) -> MemLockScope {
+        if scope_policy != MemLockPolicy::Disabled && budget > 0 {
+            let needed = (locked_bytes() as u64).saturating_add(budget);
+            if let Some(soft) = raise_lock_limit(needed) {
+                log_trace!("lock limit {} bytes, {} needed", soft, needed);
+            }
+        }
+        let previous = policy();
+        set_policy(scope_policy);
+        MemLockScope {
+            previous: previous,
+            failures: lock_failures(),
+        }
+    }
+
+    /// Restore the previous policy. Returns the number of buffers which
+    /// failed to lock within the scope.
+    pub fn leave(self) -> usize {
+        lock_failures() - self.failures
+    }
+}
+
+impl Drop for MemLockScope {
+    fn drop(&mut self) {
+        set_policy(self.previous);
+    }
+}
+
+/// Lock data against swapping. what names it in shadow's reports.
+pub fn lock<T: AsRef<[u8]> + ?Sized>(data: &T, what: &'static str) {
+    let data = data.as_ref();
+    if data.is_empty() {
+        return;
+    }
+    if is_hardened() {
+        unsafe {
+            sys::exclude_from_dumps(data.as_ptr() as *const c_void, data.len() as size_t);
+        }
+    }
+    if policy() != MemLockPolicy::Disabled {
+        if unsafe { sys::lock(data.as_ptr() as *const c_void, data.len() as size_t) } {
+            LOCKED_BYTES.fetch_add(data.len(), Ordering::SeqCst);
+        } else {
+            log_debug!("failed to lock {} bytes of {}", data.len(), what);
+            FAILURES.with(|f| f.set(f.get() + 1));
+        }
+    }
+    shadow::protect(data.as_ptr(), data.len(), what);
+}
+
+/// Unlock data again. It has to be zeroed out before, which is checked
+/// in debug builds.
+pub fn unlock<T: AsRef<[u8]> + ?Sized>(data: &T) {
+    let data = data.as_ref();
+    if data.is_empty() {
+        return;
+    }
+    shadow::release(data.as_ptr());
+    unlock_memory(data);
+}
+
+/// Unlock data which still holds plaintext because it moves to an owner
+/// who zeroes it out on its own, e.g. zeroize::Zeroizing. Not checked by
+/// shadow.
+pub fn disown<T: AsRef<[u8]> + ?Sized>(data: &T) {
+    let data = data.as_ref();
+    if data.is_empty() {
+        return;
+    }
+    shadow::forget(data.as_ptr());
+    unlock_memory(data);
+}
+
+fn unlock_memory(data: &[u8]) {
+    if policy() == MemLockPolicy::Disabled {
+        return;
+    }
+    unsafe {
+        sys::unlock(data.as_ptr() as *const c_void, data.len() as size_t);
+    }
+    // Saturating, see locked_bytes
+    let mut current = LOCKED_BYTES.load(Ordering::SeqCst);
+    loop {
+        let new = current.saturating_sub(data.len());
+        match LOCKED_BYTES.compare_exchange(current, new, Ordering::SeqCst, Ordering::SeqCst) {
+            Ok(_) => break,
+            Err(previous) => current = previous,
+        }
+    }
+}
+
+/// Overwrite data with zeroes. This writes through a shared reference,
+/// so nothing else may access data meanwhile.
+pub unsafe fn zero<T: AsRef<[u8]> + ?Sized>(data: &T) {
+    let data = data.as_ref();
+    intrinsics::volatile_set_memory(data.as_ptr() as *mut c_void, 0u8, data.len());
+}
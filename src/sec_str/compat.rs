@@ -0,0 +1,220 @@
+//! Conversions between SecureString/SecureBytes and the types of the
+//! secrecy and zeroize crates, and serde support
+//!
+//! Enabled by the features of the same name. Wherever possible the
+//! plaintext buffer is moved instead of copied. Only secrecy::Secret
+//! doesn't give up its value, so converting from it copies the plaintext
+//! directly into the SecureString or SecureBytes.
+//!
+//! With serde, SecureString and SecureBytes serialize to their plain
+//! text, e.g. to dump a database for an audit. The serialized form
+//! isn't protected, neither are the buffers of the serializer or
+//! deserializer.
+
+#[cfg(any(feature = "secrecy", feature = "zeroize"))]
+use std::mem;
+
+#[cfg(any(feature = "secrecy", feature = "zeroize"))]
+use mem_protect;
+#[cfg(any(feature = "secrecy", feature = "zeroize"))]
+use super::{SecureBytes, SecureString};
+
+#[cfg(any(feature = "secrecy", feature = "zeroize"))]
+impl SecureString {
+    // Decrypt and hand out the plaintext. The caller has to zero it out.
+    fn take_string(mut self) -> String {
+        self.unlock();
+        mem_protect::disown(&self.string);
+        mem::replace(&mut self.string, String::new())
+    }
+}
+
+#[cfg(any(feature = "secrecy", feature = "zeroize"))]
+impl SecureBytes {
+    // Hand out the bytes. The caller has to zero them out.
+    fn take_bytes(mut self) -> Vec<u8> {
+        mem_protect::disown(&self.bytes);
+        mem::replace(&mut self.bytes, vec![])
+    }
+}
+
+#[cfg(feature = "zeroize")]
+mod zeroize_compat {
+    use std::mem;
+
+    use zeroize::{Zeroize, Zeroizing};
+
+    use mem_protect;
+    use super::super::{SecureBytes, SecureString};
+
+    impl From<Zeroizing<String>> for SecureString {
+        fn from(mut string: Zeroizing<String>) -> SecureString {
+            // The empty string left behind is zeroed out on drop
+            SecureString::new(mem::replace(&mut *string, String::new()))
+        }
+    }
+
+    impl From<SecureString> for Zeroizing<String> {
+        fn from(sec_str: SecureString) -> Zeroizing<String> {
+            Zeroizing::new(sec_str.take_string())
+        }
+    }
+
+    impl From<Zeroizing<Vec<u8>>> for SecureBytes {
+        fn from(mut bytes: Zeroizing<Vec<u8>>) -> SecureBytes {
+            SecureBytes::new(mem::replace(&mut *bytes, vec![]))
+        }
+    }
+
+    impl From<SecureBytes> for Zeroizing<Vec<u8>> {
+        fn from(sec_bytes: SecureBytes) -> Zeroizing<Vec<u8>> {
+            Zeroizing::new(sec_bytes.take_bytes())
+        }
+    }
+
+    /// Overwrites the decrypted string, same as delete
+    impl Zeroize for SecureString {
+        fn zeroize(&mut self) {
+            self.delete();
+        }
+    }
+
+    /// Overwrites the bytes with zeroes. The length stays the same.
+    impl Zeroize for SecureBytes {
+        fn zeroize(&mut self) {
+            unsafe {
+                mem_protect::zero(&self.bytes);
+            }
+        }
+    }
+}
+
+#[cfg(feature = "secrecy")]
+mod secrecy_compat {
+    use secrecy::{ExposeSecret, Secret, SecretString, SecretVec};
+
+    use super::super::{SecureBytes, SecureString};
+
+    impl<'a> From<&'a SecretString> for SecureString {
+        fn from(secret: &'a SecretString) -> SecureString {
+            SecureString::new(secret.expose_secret().clone())
+        }
+    }
+
+    impl From<SecureString> for SecretString {
+        fn from(sec_str: SecureString) -> SecretString {
+            Secret::new(sec_str.take_string())
+        }
+    }
+
+    impl<'a> From<&'a SecretVec<u8>> for SecureBytes {
+        fn from(secret: &'a SecretVec<u8>) -> SecureBytes {
+            SecureBytes::new(secret.expose_secret().clone())
+        }
+    }
+
+    impl From<SecureBytes> for SecretVec<u8> {
+        fn from(sec_bytes: SecureBytes) -> SecretVec<u8> {
+            Secret::new(sec_bytes.take_bytes())
+        }
+    }
+}
+
+#[cfg(feature = "serde")]
+mod serde_compat {
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    use mem_protect;
+    use super::super::{SecureBytes, SecureString};
+
+    impl Serialize for SecureString {
+        fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+            let string = self.decrypt();
+            let result = serializer.serialize_str(&string);
+            unsafe {
+                mem_protect::zero(&string);
+            }
+            mem_protect::unlock(&string);
+            result
+        }
+    }
+
+    impl<'de> Deserialize<'de> for SecureString {
+        fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<SecureString, D::Error> {
+            String::deserialize(deserializer).map(SecureString::new)
+        }
+    }
+
+    impl Serialize for SecureBytes {
+        fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+            serializer.serialize_bytes(&self.bytes)
+        }
+    }
+
+    impl<'de> Deserialize<'de> for SecureBytes {
+        fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<SecureBytes, D::Error> {
+            Vec::<u8>::deserialize(deserializer).map(SecureBytes::new)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{SecureBytes, SecureString};
+
+    #[test]
+    #[cfg(feature = "zeroize")]
+    fn test_zeroize() {
+        use zeroize::{Zeroize, Zeroizing};
+
+        let sec_str = SecureString::from(Zeroizing::new("zeroize".to_string()));
+        assert_eq!(sec_str.string, "\0\0\0\0\0\0\0");
+        let string: Zeroizing<String> = sec_str.into();
+        assert_eq!(*string, "zeroize");
+
+        let mut sec_bytes = SecureBytes::from(Zeroizing::new(vec![1u8, 2, 3]));
+        assert_eq!(sec_bytes.bytes(), &[1u8, 2, 3]);
+        sec_bytes.zeroize();
+        assert_eq!(sec_bytes.bytes(), &[0u8, 0, 0]);
+        let bytes: Zeroizing<Vec<u8>> = sec_bytes.into();
+        assert_eq!(*bytes, vec![0u8, 0, 0]);
+    }
+
+    #[test]
+    #[cfg(feature = "secrecy")]
+    fn test_secrecy() {
+        use secrecy::{ExposeSecret, Secret, SecretString, SecretVec};
+
+        let secret = SecretString::new("secrecy".to_string());
+        let mut sec_str = SecureString::from(&secret);
+        sec_str.unlock();
+        assert_eq!(sec_str.string, "secrecy");
+        let secret: SecretString = sec_str.into();
+        assert_eq!(secret.expose_secret(), "secrecy");
+
+        let secret: SecretVec<u8> = Secret::new(vec![1u8, 2, 3]);
+        let sec_bytes = SecureBytes::from(&secret);
+        let secret: SecretVec<u8> = sec_bytes.into();
+        assert_eq!(secret.expose_secret(), &vec![1u8, 2, 3]);
+    }
+
+    #[test]
+    #[cfg(feature = "serde")]
+    fn test_serde() {
+        use serde_json;
+
+        let sec_str = SecureString::new("serde \"json\"".to_string());
+        let json = serde_json::to_string(&sec_str).unwrap();
+        assert_eq!(json, "\"serde \\\"json\\\"\"");
+        assert_eq!(sec_str.string, "\0\0\0\0\0\0\0\0\0\0\0\0");
+        let mut sec_str: SecureString = serde_json::from_str(&json).unwrap();
+        sec_str.unlock();
+        assert_eq!(sec_str.string, "serde \"json\"");
+
+        let sec_bytes = SecureBytes::new(vec![1u8, 2, 3]);
+        let json = serde_json::to_string(&sec_bytes).unwrap();
+        assert_eq!(json, "[1,2,3]");
+        let sec_bytes: SecureBytes = serde_json::from_str(&json).unwrap();
+        assert_eq!(sec_bytes.bytes(), &[1u8, 2, 3]);
+    }
+}
@@ -1,8 +1,12 @@
-use libc::{c_void, size_t};
-use libc::funcs::posix88::mman;
 use openssl::crypto::symm;
 use rand;
-use std::intrinsics;
+use std::ops::Deref;
+
+use mem_protect;
+
+pub mod shadow;
+#[cfg(any(feature = "secrecy", feature = "zeroize", feature = "serde"))]
+pub mod compat;
 
 #[doc = "
 SecureString implements a secure string. This means in particular:
@@ -39,19 +43,14 @@ impl SecureString {
     /// lie in memory. The string will be automatically encrypted and deleted.
     pub fn new(string: String) -> SecureString {
         // Lock the string against swapping
-        unsafe {
-            mman::mlock(string.as_ptr() as *const c_void, string.len() as size_t);
-        }
+        mem_protect::lock(&string, "SecureString");
         let mut sec_str = SecureString {
             string: string,
             encrypted_string: vec![],
             password: (0..32).map(|_| rand::random::<u8>()).collect(),
             iv: (0..32).map(|_| rand::random::<u8>()).collect(),
         };
-        unsafe {
-            mman::mlock(sec_str.encrypted_string.as_ptr() as *const c_void,
-                        sec_str.encrypted_string.len() as size_t);
-        }
+        mem_protect::lock(&sec_str.encrypted_string, "encrypted_string");
         sec_str.lock();
         sec_str.delete();
         sec_str
@@ -60,11 +59,9 @@ impl SecureString {
     /// Overwrite the string with zeroes. Call this everytime after unlock() if you don't
     /// need the string anymore.
     pub fn delete(&self) {
-        // Use volatile_set_memory to make sure that the operation is executed.
+        // zero uses volatile writes to make sure that the operation is executed.
         unsafe {
-            intrinsics::volatile_set_memory(self.string.as_ptr() as *mut c_void,
-                                            0u8,
-                                            self.string.len())
+            mem_protect::zero(&self.string)
         };
     }
 
@@ -78,11 +75,49 @@ impl SecureString {
     /// Unlock the string, i.e. decrypt it and make it available via the string value.
     /// Don't forget to call delete() if you don't need the plain text anymore.
     pub fn unlock(&mut self) {
-        self.string = String::from_utf8(symm::decrypt(symm::Type::AES_256_CBC,
-                                                      &self.password,
-                                                      self.iv.clone(),
-                                                      &self.encrypted_string))
-                          .unwrap();
+        // The old string is dropped below, make sure it holds no plaintext
+        self.delete();
+        mem_protect::unlock(&self.string);
+        self.string = self.decrypt();
+    }
+
+    // A locked copy of the plain text. The caller has to zero it out.
+    fn decrypt(&self) -> String {
+        let string = String::from_utf8(symm::decrypt(symm::Type::AES_256_CBC,
+                                                     &self.password,
+                                                     self.iv.clone(),
+                                                     &self.encrypted_string))
+                         .unwrap();
+        mem_protect::lock(&string, "SecureString");
+        string
+    }
+
+    /// Unlock the string for as long as the returned guard lives. The
+    /// plain text is deleted again when the guard is dropped, so there's
+    /// no need to call delete.
+    pub fn unlocked<'a>(&'a mut self) -> Unlocked<'a> {
+        self.unlock();
+        Unlocked { sec_str: self }
+    }
+
+    /// Re-encrypt the string with a fresh random password and IV. The
+    /// old ones are overwritten, so a memory dump taken before is useless
+    /// afterwards. If the string was deleted it's deleted again, otherwise
+    /// it stays unlocked.
+    pub fn rekey(&mut self) {
+        let was_deleted = self.string.bytes().all(|b| b == 0);
+        self.unlock();
+        // Overwrite in place so no copy of the old key is left behind
+        for b in self.password.iter_mut().chain(self.iv.iter_mut()) {
+            *b = rand::random::<u8>();
+        }
+        unsafe {
+            mem_protect::zero(&self.encrypted_string);
+        }
+        self.lock();
+        if was_deleted {
+            self.delete();
+        }
     }
 }
 
@@ -90,21 +125,83 @@ impl SecureString {
 impl Drop for SecureString {
     fn drop(&mut self) {
         self.delete();
+        mem_protect::unlock(&self.string);
+        unsafe {
+            mem_protect::zero(&self.encrypted_string);
+        }
+        mem_protect::unlock(&self.encrypted_string);
+    }
+}
+
+/// Access to the plain text of a SecureString, see SecureString::unlocked
+pub struct Unlocked<'a> {
+    sec_str: &'a mut SecureString,
+}
+
+impl<'a> Deref for Unlocked<'a> {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        &self.sec_str.string
+    }
+}
+
+impl<'a> Drop for Unlocked<'a> {
+    fn drop(&mut self) {
+        self.sec_str.delete();
+    }
+}
+
+#[doc = "
+SecureBytes holds binary secrets, e.g. the content of a keyfile. The
+bytes are locked against swapping and overwritten with zeroes on drop.
+Unlike SecureString they aren't encrypted in memory.
+"]
+pub struct SecureBytes {
+    bytes: Vec<u8>,
+}
+
+impl SecureBytes {
+    /// Take ownership of bytes and lock them. Like for SecureString the
+    /// input should not be a copy of other unprotected data.
+    pub fn new(bytes: Vec<u8>) -> SecureBytes {
+        mem_protect::lock(&bytes, "SecureBytes");
+        SecureBytes { bytes: bytes }
+    }
+
+    /// Take ownership of bytes which are locked already, e.g. decrypted
+    /// into a locked buffer, so they're never unlocked in between
+    pub fn from_locked(bytes: Vec<u8>) -> SecureBytes {
+        SecureBytes { bytes: bytes }
+    }
+
+    pub fn bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    /// Write access, e.g. to fill a buffer which was locked before
+    pub fn bytes_mut(&mut self) -> &mut [u8] {
+        &mut self.bytes
+    }
+
+    pub fn len(&self) -> usize {
+        self.bytes.len()
+    }
+}
+
+impl Drop for SecureBytes {
+    fn drop(&mut self) {
         unsafe {
-            mman::munlock(self.string.as_ptr() as *const c_void,
-                          self.string.len() as size_t);
-            intrinsics::volatile_set_memory(self.encrypted_string.as_ptr() as *mut c_void,
-                                            0u8,
-                                            self.encrypted_string.len());
-            mman::munlock(self.encrypted_string.as_ptr() as *const c_void,
-                          self.encrypted_string.len() as size_t);
+            mem_protect::zero(&self.bytes);
         }
+        mem_protect::unlock(&self.bytes);
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::SecureString;
+    use super::shadow;
     use std::str;
     use std::ptr::copy;
 
@@ -173,4 +270,38 @@ mod tests {
         sec_str2.lock();
         assert_eq!(sec_str.encrypted_string, sec_str2.encrypted_string);
     }
+
+    #[test]
+    fn test_unlocked() {
+        let mut sec_str = SecureString::new("guard".to_string());
+        {
+            let plain = sec_str.unlocked();
+            assert_eq!(&*plain, "guard");
+        }
+        assert_eq!(sec_str.string, "\0\0\0\0\0");
+    }
+
+    #[test]
+    fn test_rekey() {
+        let mut sec_str = SecureString::new("rekey".to_string());
+        let password = sec_str.password.clone();
+        let encrypted_string = sec_str.encrypted_string.clone();
+        sec_str.rekey();
+        assert!(sec_str.password != password);
+        assert!(sec_str.encrypted_string != encrypted_string);
+        assert_eq!(sec_str.string, "\0\0\0\0\0");
+
+        sec_str.unlock();
+        sec_str.rekey();
+        assert_eq!(sec_str.string, "rekey");
+    }
+
+    #[test]
+    #[should_panic]
+    #[cfg(debug_assertions)]
+    fn test_shadow_release_without_zeroing() {
+        let key = vec![1u8; 32];
+        shadow::protect(key.as_ptr(), key.len(), "key");
+        shadow::release(key.as_ptr());
+    }
 }
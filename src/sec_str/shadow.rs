@@ -0,0 +1,78 @@
+//! Shadow tracking of protected memory in debug builds
+//!
+//! Every buffer which is mlocked because it holds sensitive data is
+//! recorded with protect and checked for zeroes when release is called
+//! right before munlock. Hence a code path which gives up plaintext
+//! without overwriting it panics in debug builds and therefore in the
+//! tests. outstanding lists the buffers of the current thread which were
+//! never released at all. In release builds all of this compiles to
+//! nothing.
+
+#[cfg(debug_assertions)]
+use std::cell::RefCell;
+#[cfg(debug_assertions)]
+use std::collections::HashMap;
+#[cfg(debug_assertions)]
+use std::slice;
+
+// Maps the address of a protected buffer to its length and a label
+#[cfg(debug_assertions)]
+thread_local!(static PROTECTED: RefCell<HashMap<usize, (usize, &'static str)>> =
+                  RefCell::new(HashMap::new()));
+
+/// Record the buffer at ptr as holding sensitive data
+#[cfg(debug_assertions)]
+pub fn protect(ptr: *const u8, len: usize, what: &'static str) {
+    if len == 0 {
+        return;
+    }
+    PROTECTED.with(|p| {
+        p.borrow_mut().insert(ptr as usize, (len, what));
+    });
+}
+
+/// Check that the buffer at ptr is zeroed out and stop tracking it.
+/// Panics if it still holds data. Unknown buffers are ignored.
+#[cfg(debug_assertions)]
+pub fn release(ptr: *const u8) {
+    let tracked = PROTECTED.with(|p| p.borrow_mut().remove(&(ptr as usize)));
+    if let Some((len, what)) = tracked {
+        // The buffer is still allocated as release is called before it
+        // is dropped
+        let data = unsafe { slice::from_raw_parts(ptr, len) };
+        if data.iter().any(|b| *b != 0) {
+            panic!("{} was released without being zeroed out", what);
+        }
+    }
+}
+
+/// Stop tracking the buffer at ptr without any check, e.g. because it's
+/// handed over to another owner who zeroes it out
+#[cfg(debug_assertions)]
+pub fn forget(ptr: *const u8) {
+    PROTECTED.with(|p| p.borrow_mut().remove(&(ptr as usize)));
+}
+
+/// Labels of all buffers of this thread which were protected but not
+/// released yet
+#[cfg(debug_assertions)]
+pub fn outstanding() -> Vec<&'static str> {
+    PROTECTED.with(|p| p.borrow().values().map(|&(_, what)| what).collect())
+}
+
+#[cfg(not(debug_assertions))]
+#[inline(always)]
+pub fn protect(_: *const u8, _: usize, _: &'static str) {}
+
+#[cfg(not(debug_assertions))]
+#[inline(always)]
+pub fn release(_: *const u8) {}
+
+#[cfg(not(debug_assertions))]
+#[inline(always)]
+pub fn forget(_: *const u8) {}
+
+#[cfg(not(debug_assertions))]
+pub fn outstanding() -> Vec<&'static str> {
+    vec![]
+}
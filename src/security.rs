@@ -0,0 +1,50 @@
+//! Audit of the cleanup of secrets, enabled by the secret-audit feature
+//!
+//! Every buffer which holds plaintext is tracked by sec_str::shadow from
+//! the moment it's locked until it's zeroed out and unlocked. Embedders
+//! can use this in their own test suites to check that the crate cleans
+//! up on paths which end early, e.g. an aborted open (CancelledErr), a
+//! failed save or a panic caught with catch_unwind at an FFI boundary:
+//! drop the databases and secrets of the path, then call
+//! assert_no_residual_secrets.
+//!
+//! The tracking only runs in builds with debug assertions, in release
+//! builds every check passes, see is_tracking. It's per thread, so the
+//! checks cover the secrets of the calling thread only.
+
+use sec_str::shadow;
+
+/// True if secrets are tracked, i.e. the checks of this module mean
+/// something
+pub fn is_tracking() -> bool {
+    cfg!(debug_assertions)
+}
+
+/// Labels of the buffers of this thread which were locked for a secret
+/// but never zeroed out and unlocked, e.g. "SecureString" or "key"
+pub fn residual_secrets() -> Vec<&'static str> {
+    let mut residual = shadow::outstanding();
+    residual.sort();
+    residual
+}
+
+/// Err with the labels of residual_secrets if there are any
+pub fn check_no_residual_secrets() -> Result<(), Vec<&'static str>> {
+    let residual = residual_secrets();
+    if residual.is_empty() {
+        Ok(())
+    } else {
+        Err(residual)
+    }
+}
+
+/// Panic if this thread holds secrets which weren't cleaned up, see
+/// residual_secrets. Note that secrets which are still owned, e.g. by a
+/// database which wasn't dropped yet, count as residual as well.
+pub fn assert_no_residual_secrets() {
+    if let Err(residual) = check_no_residual_secrets() {
+        panic!("{} secrets weren't cleaned up: {}",
+               residual.len(),
+               residual.join(", "));
+    }
+}
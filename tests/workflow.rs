@@ -0,0 +1,193 @@
+// End-to-end workflows over the public API: the steps an application goes
+// through, from creating a database to auditing it.
+
+extern crate chrono;
+extern crate keepass;
+
+use std::cell::RefCell;
+use std::env;
+use std::fs::{self, File};
+use std::io::Write;
+use std::rc::Rc;
+
+use chrono::{Duration, Local};
+
+use keepass::kpdb::breach::{BreachHash, BreachList};
+use keepass::kpdb::merge::{Decision, NewestWins};
+use keepass::kpdb::search::SearchQuery;
+use keepass::kpdb::v1entry::V1Entry;
+use keepass::kpdb::v1kpdb::V1Kpdb;
+use keepass::sec_str::SecureString;
+
+const PASSWORD: &'static str = "test";
+const NUM_ENTRIES: usize = 1000;
+// Fewer rounds than the test database has, to keep the tests fast
+const ROUNDS: u32 = 2000;
+
+fn tmp_path(name: &str) -> String {
+    let mut path = env::temp_dir();
+    path.push(name);
+    path.to_str().unwrap().to_string()
+}
+
+// KeePass 1.x databases can't be created from scratch yet, so the test
+// database serves as template
+fn create_database(name: &str) -> V1Kpdb {
+    let path = tmp_path(name);
+    assert!(fs::copy("test/test_password.kdb", &path).is_ok());
+    let mut db = V1Kpdb::new(path, Some(PASSWORD.to_string()), None).unwrap();
+    assert!(db.load().is_ok());
+    assert!(db.header.set_key_transf_rounds(ROUNDS).is_ok());
+    db
+}
+
+fn open(path: &str) -> V1Kpdb {
+    let mut db = V1Kpdb::new(path.to_string(), Some(PASSWORD.to_string()), None).unwrap();
+    assert!(db.load().is_ok());
+    db
+}
+
+fn fill(db: &mut V1Kpdb) {
+    let work = db.create_group("Work".to_string(), None, None, None).unwrap();
+    let mail = db.create_group("Mail".to_string(), None, None, Some(work.clone())).unwrap();
+    for i in 0..NUM_ENTRIES {
+        let group = if i % 2 == 0 {
+            work.clone()
+        } else {
+            mail.clone()
+        };
+        // Every 100th entry has a weak password, every 250th is expired
+        let password = if i % 100 == 0 {
+            "password".to_string()
+        } else {
+            format!("Xk9#mP2${}", i)
+        };
+        let expire = if i % 250 == 0 {
+            Some(Local::now() - Duration::days(1))
+        } else {
+            None
+        };
+        db.create_entry(group,
+                        format!("Entry {}", i),
+                        expire,
+                        None,
+                        Some(format!("https://example{}.com", i)),
+                        None,
+                        Some(format!("user{}", i)),
+                        Some(password));
+    }
+}
+
+fn find(db: &V1Kpdb, title: &str) -> Rc<RefCell<V1Entry>> {
+    db.entries
+      .iter()
+      .find(|e| e.borrow().title == title)
+      .unwrap()
+      .clone()
+}
+
+fn password_of(entry: &Rc<RefCell<V1Entry>>) -> String {
+    let mut entry = entry.borrow_mut();
+    let password = entry.password().unwrap();
+    password.to_string()
+}
+
+#[test]
+fn test_create_save_reopen() {
+    let mut db = create_database("rust_keepass_workflow_reopen.kdb");
+    let num_entries = db.entries.len();
+    fill(&mut db);
+    assert!(db.save(None, None, None).is_ok());
+
+    let db = open(&db.path);
+    assert_eq!(db.entries.len(), num_entries + NUM_ENTRIES);
+    let mail = db.groups.iter().find(|g| g.borrow().title == "Mail").unwrap().clone();
+    assert_eq!(mail.borrow().parent.as_ref().unwrap().borrow().title, "Work");
+    assert_eq!(mail.borrow().entries.len(), NUM_ENTRIES / 2);
+
+    let entry = find(&db, "Entry 42");
+    assert_eq!(password_of(&entry), "Xk9#mP2$42");
+    assert!(entry.borrow().matches_url("https://example42.com/login"));
+    assert_eq!(db.find_entries_for_url("https://example42.com").len(), 1);
+    assert_eq!(db.search(&SearchQuery::new("user99".to_string())).unwrap().len(), 11);
+    let _ = fs::remove_file(&db.path);
+}
+
+#[test]
+fn test_merge_modified_copy() {
+    let mut db = create_database("rust_keepass_workflow_merge.kdb");
+    fill(&mut db);
+    assert!(db.save(None, None, None).is_ok());
+
+    // Somebody else changes a copy: one entry gets a new password and a
+    // new entry is added
+    let copy_path = tmp_path("rust_keepass_workflow_merge_copy.kdb");
+    assert!(fs::copy(&db.path, &copy_path).is_ok());
+    let mut copy = open(&copy_path);
+    {
+        let entry = find(&copy, "Entry 7");
+        let mut entry = entry.borrow_mut();
+        entry.set_password(Some(SecureString::new("changed".to_string()))).unwrap();
+        entry.last_mod = Local::now() + Duration::seconds(10);
+    }
+    let mail = copy.groups.iter().find(|g| g.borrow().title == "Mail").unwrap().clone();
+    copy.create_entry(mail,
+                      "Added remotely".to_string(),
+                      None,
+                      None,
+                      None,
+                      None,
+                      Some("bob".to_string()),
+                      Some("hunter2".to_string()));
+    assert!(copy.save(None, None, None).is_ok());
+
+    let mut local = open(&db.path);
+    let remote = open(&copy_path);
+    let num_entries = local.entries.len();
+    let report = local.merge(&remote, &mut NewestWins, None).unwrap();
+    assert_eq!(report.added.len(), 1);
+    assert_eq!(report.conflicts.len(), 1);
+    assert_eq!(report.conflicts[0].decision, Decision::TookRemote);
+    assert_eq!(local.entries.len(), num_entries + 1);
+    assert_eq!(password_of(&find(&local, "Entry 7")), "changed");
+    assert!(local.save(None, None, None).is_ok());
+
+    // Merging again changes nothing
+    let mut local = open(&db.path);
+    assert!(local.merge(&remote, &mut NewestWins, None).unwrap().is_unchanged());
+    assert_eq!(password_of(&find(&local, "Added remotely")), "hunter2");
+    let _ = fs::remove_file(&db.path);
+    let _ = fs::remove_file(&copy_path);
+}
+
+#[test]
+fn test_audit() {
+    let mut db = create_database("rust_keepass_workflow_audit.kdb");
+    fill(&mut db);
+    assert!(db.save(None, None, None).is_ok());
+    let db = open(&db.path);
+
+    // SHA-1 of "password", as in the Have I Been Pwned dumps
+    let list_path = tmp_path("rust_keepass_workflow_audit.txt");
+    {
+        let mut file = File::create(&list_path).unwrap();
+        let _ = write!(file,
+                       "5BAA61E4C9B93F3F0682250B6CF8331B7EE68FD8:9545824\r\n\
+                        7C4A8D09CA3762AF61E59520943DC26494F8941B:123\r\n");
+    }
+    let mut list = BreachList::open(&list_path, BreachHash::Sha1).unwrap();
+    let breached = db.breached_entries(&mut list).unwrap();
+    assert_eq!(breached.len(), NUM_ENTRIES / 100);
+    assert!(breached.iter().all(|&(_, count)| count == 9545824));
+
+    let now = Local::now();
+    let expired = db.entries
+                    .iter()
+                    .filter(|e| e.borrow().title.starts_with("Entry ") && e.borrow().expire < now)
+                    .count();
+    assert_eq!(expired, NUM_ENTRIES / 250);
+
+    assert!(db.check_field_limits().is_ok());
+    let _ = fs::remove_file(&db.path);
+    let _ = fs::remove_file(&list_path);
+}